@@ -0,0 +1,20 @@
+fn main() {
+    // Generates the C header into `OUT_DIR`, such that building never writes to the source tree.
+    // `include/dyon.h` is the published copy, which `test_capi_header` checks is up to date.
+    #[cfg(feature = "capi")]
+    {
+        use std::env;
+        use std::path::Path;
+
+        println!("cargo:rerun-if-changed=src/capi.rs");
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+        let config = cbindgen::Config::from_file("cbindgen.toml").unwrap();
+        let out_dir = env::var("OUT_DIR").unwrap();
+        cbindgen::Builder::new()
+            .with_config(config)
+            .with_src("src/capi.rs")
+            .generate()
+            .expect("Could not generate `dyon.h`")
+            .write_to_file(Path::new(&out_dir).join("dyon.h"));
+    }
+}
@@ -0,0 +1,144 @@
+//! Derive macros for Dyon interop.
+//!
+//! Use through the `derive` feature of the `dyon` crate.
+
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use syn::{Data, DeriveInput, Fields, LitStr};
+
+/// Implements `dyon::embed::PopVariable` and `dyon::embed::PushVariable` for a struct,
+/// converting it from and to a Dyon object with a key per field.
+///
+/// Fields can be renamed with `#[dyon(rename = "key")]`,
+/// or left out with `#[dyon(skip)]`, which uses `Default` when converting from an object.
+#[proc_macro_derive(DyonObject, attributes(dyon))]
+pub fn derive_dyon_object(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    match dyon_object(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+/// A field mapped to an object key.
+struct Field {
+    ident: syn::Ident,
+    key: Option<String>,
+}
+
+fn dyon_object(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match input.data {
+        Data::Struct(ref data) => match data.fields {
+            Fields::Named(ref fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    &input.ident,
+                    "`DyonObject` requires a struct with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "`DyonObject` can only be derived for structs",
+            ))
+        }
+    };
+    let mut mapped = vec![];
+    for field in fields {
+        let ident = field.ident.clone().unwrap();
+        let mut key = Some(ident.to_string().trim_start_matches("r#").to_string());
+        for attr in &field.attrs {
+            if !attr.path().is_ident("dyon") {
+                continue;
+            }
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    let name: LitStr = meta.value()?.parse()?;
+                    key = Some(name.value());
+                    Ok(())
+                } else if meta.path.is_ident("skip") {
+                    key = None;
+                    Ok(())
+                } else {
+                    Err(meta.error("expected `rename` or `skip`"))
+                }
+            })?;
+        }
+        mapped.push(Field { ident, key });
+    }
+
+    let name = &input.ident;
+    let type_name = name.to_string();
+    let mut pop_generics = input.generics.clone();
+    let mut push_generics = input.generics.clone();
+    for param in pop_generics.type_params_mut() {
+        param
+            .bounds
+            .push(syn::parse_quote!(::dyon::embed::PopVariable));
+    }
+    for param in push_generics.type_params_mut() {
+        param
+            .bounds
+            .push(syn::parse_quote!(::dyon::embed::PushVariable));
+    }
+    let (pop_impl, ty_generics, pop_where) = pop_generics.split_for_impl();
+    let (push_impl, _, push_where) = push_generics.split_for_impl();
+
+    let pop_fields = mapped.iter().map(|f| {
+        let ident = &f.ident;
+        match f.key {
+            Some(ref key) => quote! {
+                #ident: ::dyon::embed::obj_field(rt, obj, #key)?
+            },
+            None => quote! {
+                #ident: ::std::default::Default::default()
+            },
+        }
+    });
+    let push_fields = mapped.iter().filter_map(|f| {
+        let ident = &f.ident;
+        f.key.as_ref().map(|key| {
+            quote! {
+                obj.insert(
+                    ::std::sync::Arc::new(#key.into()),
+                    ::dyon::embed::PushVariable::push_var(&self.#ident),
+                );
+            }
+        })
+    });
+
+    Ok(quote! {
+        impl #pop_impl ::dyon::embed::PopVariable for #name #ty_generics #pop_where {
+            fn pop_var(
+                rt: &::dyon::Runtime,
+                var: &::dyon::Variable,
+            ) -> ::std::result::Result<Self, ::dyon::RuntimeError> {
+                let var = rt.resolve(var);
+                if let &::dyon::Variable::Object(ref obj) = var {
+                    Ok(#name {
+                        #(#pop_fields),*
+                    })
+                } else {
+                    Err(rt.expected(var, #type_name))
+                }
+            }
+        }
+
+        impl #push_impl ::dyon::embed::PushVariable for #name #ty_generics #push_where {
+            fn push_var(&self) -> ::dyon::Variable {
+                #[allow(unused_mut)]
+                let mut obj: ::std::collections::HashMap<_, ::dyon::Variable> =
+                    ::std::collections::HashMap::new();
+                #(#push_fields)*
+                ::dyon::Variable::Object(::std::sync::Arc::new(obj))
+            }
+        }
+    })
+}
@@ -1,5 +1,7 @@
 extern crate dyon;
 
+#[cfg(feature = "file")]
+use dyon::literate;
 use dyon::{error, run};
 
 fn main() {
@@ -7,8 +9,15 @@ fn main() {
         .nth(1)
         .and_then(|s| s.into_string().ok());
     if let Some(file) = file {
+        #[cfg(feature = "file")]
+        {
+            if file.ends_with(".md") {
+                error(literate::run(&file));
+                return;
+            }
+        }
         error(run(&file));
     } else {
-        eprintln!("dyonrun <file.dyon>");
+        eprintln!("dyonrun <file.dyon|file.md>");
     }
 }
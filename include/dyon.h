@@ -0,0 +1,256 @@
+/* C API for embedding Dyon. Requires the `capi` feature. */
+
+#ifndef DYON_H
+#define DYON_H
+
+/* Generated from `src/capi.rs` by cbindgen, do not edit. */
+
+#include <stdbool.h>
+#include <stddef.h>
+
+/**
+ * The type of a variable, as returned by `dyon_var_type`.
+ */
+typedef enum {
+  /**
+   * Any type not listed below.
+   */
+  DYON_TYPE_OTHER = 0,
+  /**
+   * `bool`.
+   */
+  DYON_TYPE_BOOL = 1,
+  /**
+   * `f64`.
+   */
+  DYON_TYPE_F64 = 2,
+  /**
+   * `vec4`.
+   */
+  DYON_TYPE_VEC4 = 3,
+  /**
+   * `mat4`.
+   */
+  DYON_TYPE_MAT4 = 4,
+  /**
+   * `str`.
+   */
+  DYON_TYPE_STR = 5,
+  /**
+   * `[]`.
+   */
+  DYON_TYPE_ARRAY = 6,
+  /**
+   * `{}`.
+   */
+  DYON_TYPE_OBJECT = 7,
+  /**
+   * `opt`.
+   */
+  DYON_TYPE_OPTION = 8,
+  /**
+   * `res`.
+   */
+  DYON_TYPE_RESULT = 9,
+} DyonType;
+
+/**
+ * Opaque module handle.
+ */
+typedef struct DyonModule DyonModule;
+
+/**
+ * Opaque runtime handle.
+ */
+typedef struct DyonRuntime DyonRuntime;
+
+/**
+ * Opaque variable handle.
+ */
+typedef struct DyonVariable DyonVariable;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Returns the last error message on this thread, or null if there is none.
+ *
+ * The string is valid until the next call that fails on this thread.
+ */
+const char *dyon_last_error(void);
+
+/**
+ * Clears the last error message on this thread.
+ */
+void dyon_clear_error(void);
+
+/**
+ * Creates a new module with the standard external functions.
+ */
+DyonModule *dyon_module_new(void);
+
+/**
+ * Frees a module.
+ */
+void dyon_module_free(DyonModule *module);
+
+/**
+ * Loads source into a module, using `name` as file name in error messages.
+ *
+ * Returns `0` on success and `-1` on failure.
+ */
+int dyon_load_str(DyonModule *module, const char *name, const char *source);
+
+/**
+ * Creates a new runtime.
+ */
+DyonRuntime *dyon_runtime_new(void);
+
+/**
+ * Frees a runtime.
+ */
+void dyon_runtime_free(DyonRuntime *runtime);
+
+/**
+ * Runs the `main` function of a module.
+ *
+ * Returns `0` on success and `-1` on failure.
+ */
+int dyon_runtime_run(DyonRuntime *runtime, const DyonModule *module);
+
+/**
+ * Calls a function without a return value.
+ *
+ * `args` points to `n` variables, which are cloned.
+ * Returns `0` on success and `-1` on failure.
+ */
+int dyon_runtime_call(DyonRuntime *runtime,
+                      const DyonModule *module,
+                      const char *function,
+                      const DyonVariable *const *args_ptr,
+                      size_t n);
+
+/**
+ * Calls a function with a return value.
+ *
+ * `args` points to `n` variables, which are cloned.
+ * Returns null on failure.
+ */
+DyonVariable *dyon_runtime_call_ret(DyonRuntime *runtime,
+                                    const DyonModule *module,
+                                    const char *function,
+                                    const DyonVariable *const *args_ptr,
+                                    size_t n);
+
+/**
+ * Frees a variable.
+ */
+void dyon_var_free(DyonVariable *var);
+
+/**
+ * Creates a `f64` variable.
+ */
+DyonVariable *dyon_var_f64(double val);
+
+/**
+ * Creates a `bool` variable.
+ */
+DyonVariable *dyon_var_bool(bool val);
+
+/**
+ * Creates a `vec4` variable.
+ */
+DyonVariable *dyon_var_vec4(float x, float y, float z, float w);
+
+/**
+ * Creates a `str` variable. Returns null if the string is not UTF-8.
+ */
+DyonVariable *dyon_var_str(const char *val);
+
+/**
+ * Creates an empty array.
+ */
+DyonVariable *dyon_var_array(void);
+
+/**
+ * Creates an empty object.
+ */
+DyonVariable *dyon_var_object(void);
+
+/**
+ * Pushes a clone of an item to an array.
+ *
+ * Returns `0` on success and `-1` if the variable is not an array.
+ */
+int dyon_var_array_push(DyonVariable *arr, const DyonVariable *item);
+
+/**
+ * Sets a key of an object to a clone of a value.
+ *
+ * Returns `0` on success and `-1` if the variable is not an object.
+ */
+int dyon_var_object_insert(DyonVariable *obj, const char *key, const DyonVariable *val);
+
+/**
+ * Returns the type of a variable.
+ */
+DyonType dyon_var_type(const DyonVariable *var);
+
+/**
+ * Reads a `f64` variable.
+ *
+ * Returns `0` on success and `-1` if the variable has another type.
+ */
+int dyon_var_as_f64(const DyonVariable *var, double *out);
+
+/**
+ * Reads a `bool` variable.
+ *
+ * Returns `0` on success and `-1` if the variable has another type.
+ */
+int dyon_var_as_bool(const DyonVariable *var, bool *out);
+
+/**
+ * Reads a `vec4` variable into an array of 4 floats.
+ *
+ * Returns `0` on success and `-1` if the variable has another type.
+ */
+int dyon_var_as_vec4(const DyonVariable *var, float *out);
+
+/**
+ * Returns a copy of a `str` variable, which must be freed with `dyon_str_free`.
+ *
+ * Returns null if the variable has another type.
+ */
+char *dyon_var_as_str(const DyonVariable *var);
+
+/**
+ * Frees a string returned by `dyon_var_as_str`.
+ */
+void dyon_str_free(char *s);
+
+/**
+ * Returns the length of an array, or `-1` if the variable is not an array.
+ */
+ptrdiff_t dyon_var_array_len(const DyonVariable *arr);
+
+/**
+ * Returns a copy of an array item.
+ *
+ * Returns null if the variable is not an array or the index is out of bounds.
+ */
+DyonVariable *dyon_var_array_get(const DyonVariable *arr, size_t index);
+
+/**
+ * Returns a copy of an object field.
+ *
+ * Returns null if the variable is not an object or the key does not exist.
+ */
+DyonVariable *dyon_var_object_get(const DyonVariable *obj, const char *key);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* DYON_H */
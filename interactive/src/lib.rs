@@ -133,25 +133,25 @@ pub fn add_functions<W, F, C>(module: &mut Module)
     );
 }
 
-pub fn window_size<W: Any + Window>(_rt: &mut Runtime) -> Result<Variable, String> {
+pub fn window_size<W: Any + Window>(_rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     let size = unsafe { Current::<W>::new() }.size();
     Ok(Variable::Vec4([size.width as f32, size.height as f32, 0.0, 0.0]))
 }
 
-pub fn window_draw_size<W: Any + Window>(_rt: &mut Runtime) -> Result<Variable, String> {
+pub fn window_draw_size<W: Any + Window>(_rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     let draw_size = unsafe { Current::<W>::new() }.draw_size();
     Ok(Variable::Vec4([draw_size.width as f32, draw_size.height as f32, 0.0, 0.0]))
 }
 
 #[allow(non_snake_case)]
-pub fn set_window__size<W: Any + AdvancedWindow>(rt: &mut Runtime) -> Result<(), String> {
+pub fn set_window__size<W: Any + AdvancedWindow>(rt: &mut Runtime) -> Result<(), RuntimeError> {
     let size: [f32; 2] = rt.pop_vec4()?;
     let size: [u32; 2] = [size[0] as u32, size[1] as u32];
     unsafe { Current::<W>::new() }.set_size(size);
     Ok(())
 }
 
-pub fn window_position<W: Any + AdvancedWindow>(_rt: &mut Runtime) -> Result<Variable, String> {
+pub fn window_position<W: Any + AdvancedWindow>(_rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     Ok(Variable::Vec4(if let Some(pos) = unsafe { Current::<W>::new() }.get_position() {
         [pos.x as f32, pos.y as f32, 0.0, 0.0]
     } else {
@@ -160,7 +160,7 @@ pub fn window_position<W: Any + AdvancedWindow>(_rt: &mut Runtime) -> Result<Var
 }
 
 #[allow(non_snake_case)]
-pub fn set_window__position<W: Any + AdvancedWindow>(rt: &mut Runtime) -> Result<(), String> {
+pub fn set_window__position<W: Any + AdvancedWindow>(rt: &mut Runtime) -> Result<(), RuntimeError> {
     let pos: [f32; 2] = rt.pop_vec4()?;
     let pos: [i32; 2] = [pos[0] as i32, pos[1] as i32];
     unsafe { Current::<W>::new() }.set_position(pos);
@@ -215,7 +215,7 @@ dyon_fn!{fn set_event_loop__swapbuffers(swap_buffers: bool) {
     unsafe { Current::<Events>::new() }.set_swap_buffers(swap_buffers);
 }}
 
-pub fn swap_buffers<W: Any + Window>(_rt: &mut Runtime) -> Result<(), String> {
+pub fn swap_buffers<W: Any + Window>(_rt: &mut Runtime) -> Result<(), RuntimeError> {
     unsafe { Current::<W>::new() }.swap_buffers();
     Ok(())
 }
@@ -329,7 +329,7 @@ dyon_fn!{fn mouse_cursor_pos() -> Option<Vec4> {
         .as_ref().expect(NO_EVENT).mouse_cursor_args().map(|pos| pos.into()) }
 }}
 
-pub fn press_keyboard_key(_rt: &mut Runtime) -> Result<Variable, String> {
+pub fn press_keyboard_key(_rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     use dyon::embed::PushVariable;
 
     let e = unsafe { &*Current::<Option<Event>>::new() };
@@ -344,7 +344,7 @@ pub fn press_keyboard_key(_rt: &mut Runtime) -> Result<Variable, String> {
     }
 }
 
-pub fn release_keyboard_key(_rt: &mut Runtime) -> Result<Variable, String> {
+pub fn release_keyboard_key(_rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     use dyon::embed::PushVariable;
 
     let e = unsafe { &*Current::<Option<Event>>::new() };
@@ -359,7 +359,7 @@ pub fn release_keyboard_key(_rt: &mut Runtime) -> Result<Variable, String> {
     }
 }
 
-pub fn press_mouse_button(_rt: &mut Runtime) -> Result<Variable, String> {
+pub fn press_mouse_button(_rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     use dyon::embed::PushVariable;
 
     let e = unsafe { &*Current::<Option<Event>>::new() };
@@ -374,7 +374,7 @@ pub fn press_mouse_button(_rt: &mut Runtime) -> Result<Variable, String> {
     }
 }
 
-pub fn release_mouse_button(_rt: &mut Runtime) -> Result<Variable, String> {
+pub fn release_mouse_button(_rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     use dyon::embed::PushVariable;
 
     let e = unsafe { &*Current::<Option<Event>>::new() };
@@ -389,7 +389,7 @@ pub fn release_mouse_button(_rt: &mut Runtime) -> Result<Variable, String> {
     }
 }
 
-pub fn text_arg(_rt: &mut Runtime) -> Result<Variable, String> {
+pub fn text_arg(_rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     use dyon::embed::PushVariable;
 
     let e = unsafe { &*Current::<Option<Event>>::new() };
@@ -404,13 +404,13 @@ pub fn text_arg(_rt: &mut Runtime) -> Result<Variable, String> {
     }
 }
 
-pub fn window_title<W: Any + AdvancedWindow>(_rt: &mut Runtime) -> Result<Variable, String> {
+pub fn window_title<W: Any + AdvancedWindow>(_rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     let window = unsafe { &mut *Current::<W>::new() };
     Ok(Variable::Str(Arc::new(window.get_title())))
 }
 
 #[allow(non_snake_case)]
-pub fn set_window__title<W: Any + AdvancedWindow>(rt: &mut Runtime) -> Result<(), String> {
+pub fn set_window__title<W: Any + AdvancedWindow>(rt: &mut Runtime) -> Result<(), RuntimeError> {
     let window = unsafe { &mut *Current::<W>::new() };
     let title: Arc<String> = rt.pop()?;
     window.set_title((*title).clone());
@@ -418,7 +418,7 @@ pub fn set_window__title<W: Any + AdvancedWindow>(rt: &mut Runtime) -> Result<()
 }
 
 #[allow(non_snake_case)]
-pub fn width__font_size_string<C: Any + CharacterCache>(rt: &mut Runtime) -> Result<Variable, String> {
+pub fn width__font_size_string<C: Any + CharacterCache>(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     let glyphs = unsafe { &mut *Current::<Vec<C>>::new() };
     let s: Arc<String> = rt.pop()?;
     let size: u32 = rt.pop()?;
@@ -439,7 +439,7 @@ dyon_fn!{fn font_names() -> Vec<Arc<String>> {
 }}
 
 /// Helper method for loading fonts.
-pub fn load_font<F, T>(rt: &mut Runtime) -> Result<Variable, String>
+pub fn load_font<F, T>(rt: &mut Runtime) -> Result<Variable, RuntimeError>
     where F: 'static + Clone, T: 'static +
           CreateTexture<F> + UpdateTexture<F> +
           graphics::ImageSize
@@ -520,7 +520,7 @@ dyon_fn!{fn image_size(id: usize) -> Vec4 {
 }}
 
 #[allow(non_snake_case)]
-pub fn pxl__image_pos_color(rt: &mut Runtime) -> Result<(), String> {
+pub fn pxl__image_pos_color(rt: &mut Runtime) -> Result<(), RuntimeError> {
     use image::{Rgba, RgbaImage};
 
     let images = unsafe { &mut *Current::<Vec<RgbaImage>>::new() };
@@ -548,7 +548,7 @@ pub fn pxl__image_pos_color(rt: &mut Runtime) -> Result<(), String> {
 }
 
 #[allow(non_snake_case)]
-pub fn pxl__image_pos(rt: &mut Runtime) -> Result<Variable, String> {
+pub fn pxl__image_pos(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     use image::RgbaImage;
 
     let images = unsafe { &*Current::<Vec<RgbaImage>>::new() };
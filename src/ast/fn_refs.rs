@@ -0,0 +1,219 @@
+//! Checks functions referenced by name in string literals.
+//!
+//! Functions called by name, e.g. `call(m, "foo", [])`, are looked up at run time,
+//! so a misspelled name is not found until the call happens.
+//! This pass finds the names known when loading and checks them up front.
+
+use range::Range;
+use std::sync::Arc;
+
+use super::{Block, Expression, ForIn, ForN, Id};
+use FnIndex;
+use Module;
+use Variable;
+
+/// Checks the function references of `module`.
+///
+/// Names given to `call` and `call_ret` are looked up among the loaded functions
+/// of `target`, and the number of arguments is checked when given as an array literal.
+/// Names given to `glsl_of` are looked up in `module`.
+pub(crate) fn check(module: &Module, target: &Module) -> Result<(), String> {
+    let mut errors = vec![];
+    for f in &module.functions {
+        let mut checker = Checker {
+            module,
+            target,
+            source: f.source.clone(),
+            errors: vec![],
+        };
+        checker.block(&f.block);
+        for (range, msg, source) in checker.errors {
+            errors.push(format!(
+                "In `{}`:\n{}",
+                f.file,
+                module.error_source(range, &msg, &source)
+            ));
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("\n"))
+    }
+}
+
+struct Checker<'a> {
+    module: &'a Module,
+    target: &'a Module,
+    source: Arc<String>,
+    errors: Vec<(Range, String, Arc<String>)>,
+}
+
+impl<'a> Checker<'a> {
+    fn error(&mut self, range: Range, msg: String) {
+        self.errors.push((range, msg, self.source.clone()));
+    }
+
+    fn call(&mut self, name: &str, args: &[Expression]) {
+        match name {
+            "call" | "call_ret" if args.len() == 3 => {
+                if let Some((range, fn_name)) = str_literal(&args[1]) {
+                    match self.target.find_function(&fn_name, 0) {
+                        FnIndex::Loaded(i) => {
+                            let n = self.target.functions[i as usize].args.len();
+                            if let Expression::Array(ref arr) = args[2] {
+                                if arr.items.len() != n {
+                                    self.error(
+                                        arr.source_range,
+                                        format!(
+                                            "Expected {} arguments to `{}`, found {}",
+                                            n,
+                                            fn_name,
+                                            arr.items.len()
+                                        ),
+                                    );
+                                }
+                            }
+                        }
+                        _ => self.error(range, format!("Could not find function `{}`", fn_name)),
+                    }
+                }
+            }
+            "glsl_of" if args.len() == 1 => {
+                if let Some((range, fn_name)) = str_literal(&args[0]) {
+                    if let FnIndex::Loaded(_) = self.module.find_function(&fn_name, 0) {
+                    } else {
+                        self.error(range, format!("Could not find function `{}`", fn_name));
+                    }
+                }
+            }
+            _ => {}
+        }
+        self.exprs(args);
+    }
+
+    fn exprs(&mut self, exprs: &[Expression]) {
+        for expr in exprs {
+            self.expr(expr);
+        }
+    }
+
+    fn block(&mut self, block: &Block) {
+        self.exprs(&block.expressions);
+    }
+
+    fn for_n(&mut self, for_n: &ForN) {
+        if let Some(ref start) = for_n.start {
+            self.expr(start);
+        }
+        self.expr(&for_n.end);
+        self.block(&for_n.block);
+    }
+
+    fn for_in(&mut self, for_in: &ForIn) {
+        self.expr(&for_in.iter);
+        self.block(&for_in.block);
+    }
+
+    fn expr(&mut self, expr: &Expression) {
+        use super::Expression as E;
+
+        match *expr {
+            E::Link(ref link) => self.exprs(&link.items),
+            E::Object(ref obj) => {
+                for &(_, ref expr) in &obj.key_values {
+                    self.expr(expr);
+                }
+            }
+            E::Array(ref arr) => self.exprs(&arr.items),
+            E::ArrayFill(ref fill) => {
+                self.expr(&fill.fill);
+                self.expr(&fill.n);
+            }
+            E::Return(ref expr) | E::Try(ref expr) => self.expr(expr),
+            E::ReturnVoid(_) | E::Break(_) | E::Continue(_) | E::Variable(_) | E::In(_) => {}
+            E::Block(ref block) => self.block(block),
+            #[cfg(all(not(target_family = "wasm"), feature = "threading"))]
+            E::Go(ref go) => self.call(&go.call.info.name, &go.call.args),
+            E::Call(ref call) => self.call(&call.info.name, &call.args),
+            E::CallVoid(ref call) => self.call(&call.info.name, &call.args),
+            E::CallReturn(ref call) => self.call(&call.info.name, &call.args),
+            E::CallLazy(ref call) => self.call(&call.info.name, &call.args),
+            E::CallLoaded(ref call) => self.call(&call.info.name, &call.args),
+            E::CallBinOp(ref call) => {
+                self.expr(&call.left);
+                self.expr(&call.right);
+            }
+            E::CallUnOp(ref call) => self.expr(&call.arg),
+            E::Item(ref item) => {
+                for id in &item.ids {
+                    if let Id::Expression(ref expr) = *id {
+                        self.expr(expr);
+                    }
+                }
+            }
+            E::Assign(ref assign) => {
+                self.expr(&assign.left);
+                self.expr(&assign.right);
+            }
+            E::Vec4(ref vec4) => self.exprs(&vec4.args),
+            E::Mat4(ref mat4) => self.exprs(&mat4.args),
+            E::For(ref for_expr) => {
+                self.expr(&for_expr.init);
+                self.expr(&for_expr.cond);
+                self.expr(&for_expr.step);
+                self.block(&for_expr.block);
+            }
+            E::ForN(ref for_n)
+            | E::Sum(ref for_n)
+            | E::SumVec4(ref for_n)
+            | E::Prod(ref for_n)
+            | E::ProdVec4(ref for_n)
+            | E::Min(ref for_n)
+            | E::Max(ref for_n)
+            | E::Sift(ref for_n)
+            | E::Any(ref for_n)
+            | E::All(ref for_n)
+            | E::LinkFor(ref for_n) => self.for_n(for_n),
+            E::ForIn(ref for_in)
+            | E::SumIn(ref for_in)
+            | E::ProdIn(ref for_in)
+            | E::MinIn(ref for_in)
+            | E::MaxIn(ref for_in)
+            | E::SiftIn(ref for_in)
+            | E::AnyIn(ref for_in)
+            | E::AllIn(ref for_in)
+            | E::LinkIn(ref for_in) => self.for_in(for_in),
+            E::If(ref if_expr) => {
+                self.expr(&if_expr.cond);
+                self.block(&if_expr.true_block);
+                self.exprs(&if_expr.else_if_conds);
+                for block in &if_expr.else_if_blocks {
+                    self.block(block);
+                }
+                if let Some(ref block) = if_expr.else_block {
+                    self.block(block);
+                }
+            }
+            E::Swizzle(ref swizzle) => self.expr(&swizzle.expr),
+            E::Closure(ref closure) => {
+                // Errors in closures are reported with the closure's source.
+                let source = ::std::mem::replace(&mut self.source, closure.source.clone());
+                self.expr(&closure.expr);
+                self.source = source;
+            }
+            E::CallClosure(ref call) => self.exprs(&call.args),
+            E::Grab(ref grab) => self.expr(&grab.expr),
+            E::TryExpr(ref try_expr) => self.expr(&try_expr.expr),
+        }
+    }
+}
+
+fn str_literal(expr: &Expression) -> Option<(Range, Arc<String>)> {
+    if let Expression::Variable(ref range_var) = *expr {
+        if let (range, Variable::Str(ref s)) = **range_var {
+            return Some((range, s.clone()));
+        }
+    }
+    None
+}
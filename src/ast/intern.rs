@@ -0,0 +1,173 @@
+//! Interning of string literals and object keys.
+//!
+//! Loading the same literal twice, or the same key in many objects,
+//! shares one allocation for all of them.
+//! Since `Arc<String>` compares pointers before contents,
+//! looking up a key with the interned string only compares pointers.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use super::{Block, Call, CallClosure, Expression, ForIn, ForN, Function, Id, Item};
+use Variable;
+
+/// Stores the strings shared by functions in a module.
+#[derive(Clone, Default)]
+pub(crate) struct Interner {
+    strings: HashSet<Arc<String>>,
+}
+
+impl Interner {
+    /// Returns the shared string equal to `s`, adding it when missing.
+    pub fn intern(&mut self, s: &Arc<String>) -> Arc<String> {
+        if let Some(x) = self.strings.get(s) {
+            return x.clone();
+        }
+        self.strings.insert(s.clone());
+        s.clone()
+    }
+
+    /// Adds the strings of another interner.
+    pub fn extend(&mut self, other: &Interner) {
+        for s in &other.strings {
+            if !self.strings.contains(s) {
+                self.strings.insert(s.clone());
+            }
+        }
+    }
+
+    /// Replaces string literals and object keys in a function by shared strings.
+    pub fn function(&mut self, f: &mut Function) {
+        self.block(&mut f.block);
+    }
+
+    fn block(&mut self, block: &mut Block) {
+        self.exprs(&mut block.expressions);
+    }
+
+    fn exprs(&mut self, exprs: &mut [Expression]) {
+        for expr in exprs {
+            self.expr(expr);
+        }
+    }
+
+    fn call(&mut self, call: &mut Call) {
+        self.exprs(&mut call.args);
+    }
+
+    fn item(&mut self, item: &mut Item) {
+        for id in &mut item.ids {
+            match *id {
+                Id::String(_, ref mut s) => *s = self.intern(s),
+                Id::Expression(ref mut expr) => self.expr(expr),
+                Id::F64(..) => {}
+            }
+        }
+    }
+
+    fn for_n(&mut self, for_n: &mut ForN) {
+        if let Some(ref mut start) = for_n.start {
+            self.expr(start);
+        }
+        self.expr(&mut for_n.end);
+        self.block(&mut for_n.block);
+    }
+
+    fn for_in(&mut self, for_in: &mut ForIn) {
+        self.expr(&mut for_in.iter);
+        self.block(&mut for_in.block);
+    }
+
+    fn call_closure(&mut self, call: &mut CallClosure) {
+        self.item(&mut call.item);
+        self.exprs(&mut call.args);
+    }
+
+    fn expr(&mut self, expr: &mut Expression) {
+        use super::Expression as E;
+
+        match *expr {
+            E::Link(ref mut link) => self.exprs(&mut link.items),
+            E::Object(ref mut obj) => {
+                for &mut (ref mut key, ref mut expr) in &mut obj.key_values {
+                    *key = self.intern(key);
+                    self.expr(expr);
+                }
+            }
+            E::Array(ref mut arr) => self.exprs(&mut arr.items),
+            E::ArrayFill(ref mut fill) => {
+                self.expr(&mut fill.fill);
+                self.expr(&mut fill.n);
+            }
+            E::Return(ref mut expr) | E::Try(ref mut expr) => self.expr(expr),
+            E::ReturnVoid(_) | E::Break(_) | E::Continue(_) | E::In(_) => {}
+            E::Block(ref mut block) => self.block(block),
+            #[cfg(all(not(target_family = "wasm"), feature = "threading"))]
+            E::Go(ref mut go) => self.call(&mut go.call),
+            E::Call(ref mut call) => self.call(call),
+            E::CallVoid(ref mut call) => self.exprs(&mut call.args),
+            E::CallReturn(ref mut call) => self.exprs(&mut call.args),
+            E::CallLazy(ref mut call) => self.exprs(&mut call.args),
+            E::CallLoaded(ref mut call) => self.exprs(&mut call.args),
+            E::CallBinOp(ref mut call) => {
+                self.expr(&mut call.left);
+                self.expr(&mut call.right);
+            }
+            E::CallUnOp(ref mut call) => self.expr(&mut call.arg),
+            E::Item(ref mut item) => self.item(item),
+            E::Assign(ref mut assign) => {
+                self.expr(&mut assign.left);
+                self.expr(&mut assign.right);
+            }
+            E::Vec4(ref mut vec4) => self.exprs(&mut vec4.args),
+            E::Mat4(ref mut mat4) => self.exprs(&mut mat4.args),
+            E::For(ref mut for_expr) => {
+                self.expr(&mut for_expr.init);
+                self.expr(&mut for_expr.cond);
+                self.expr(&mut for_expr.step);
+                self.block(&mut for_expr.block);
+            }
+            E::ForN(ref mut for_n)
+            | E::Sum(ref mut for_n)
+            | E::SumVec4(ref mut for_n)
+            | E::Prod(ref mut for_n)
+            | E::ProdVec4(ref mut for_n)
+            | E::Min(ref mut for_n)
+            | E::Max(ref mut for_n)
+            | E::Sift(ref mut for_n)
+            | E::Any(ref mut for_n)
+            | E::All(ref mut for_n)
+            | E::LinkFor(ref mut for_n) => self.for_n(for_n),
+            E::ForIn(ref mut for_in)
+            | E::SumIn(ref mut for_in)
+            | E::ProdIn(ref mut for_in)
+            | E::MinIn(ref mut for_in)
+            | E::MaxIn(ref mut for_in)
+            | E::SiftIn(ref mut for_in)
+            | E::AnyIn(ref mut for_in)
+            | E::AllIn(ref mut for_in)
+            | E::LinkIn(ref mut for_in) => self.for_in(for_in),
+            E::If(ref mut if_expr) => {
+                self.expr(&mut if_expr.cond);
+                self.block(&mut if_expr.true_block);
+                self.exprs(&mut if_expr.else_if_conds);
+                for block in &mut if_expr.else_if_blocks {
+                    self.block(block);
+                }
+                if let Some(ref mut block) = if_expr.else_block {
+                    self.block(block);
+                }
+            }
+            E::Variable(ref mut range_var) => {
+                if let (_, Variable::Str(ref mut s)) = **range_var {
+                    *s = self.intern(s);
+                }
+            }
+            E::Swizzle(ref mut swizzle) => self.expr(&mut swizzle.expr),
+            E::Closure(ref mut closure) => self.expr(&mut Arc::make_mut(closure).expr),
+            E::CallClosure(ref mut call) => self.call_closure(call),
+            E::Grab(ref mut grab) => self.expr(&mut grab.expr),
+            E::TryExpr(ref mut try_expr) => self.expr(&mut try_expr.expr),
+        }
+    }
+}
@@ -10,12 +10,20 @@ use std::sync::{self, Arc};
 use FnIndex;
 use Module;
 use Prelude;
+use Scalar;
 use Type;
 use Variable;
 
+mod fn_refs;
 mod infer_len;
+mod intern;
+mod optimize;
 mod replace;
 
+pub(crate) use self::fn_refs::check as check_fn_refs;
+pub(crate) use self::intern::Interner;
+pub(crate) use self::optimize::optimize;
+
 /// Convert meta data and load it into a module.
 pub fn convert(
     file: Arc<String>,
@@ -1268,10 +1276,10 @@ impl Expression {
                 convert.update(range);
                 if let Some((rgb, a)) = read_color::rgb_maybe_a(&mut val.chars()) {
                     let v = [
-                        f32::from(rgb[0]) / 255.0,
-                        f32::from(rgb[1]) / 255.0,
-                        f32::from(rgb[2]) / 255.0,
-                        f32::from(a.unwrap_or(255)) / 255.0,
+                        Scalar::from(rgb[0]) / 255.0,
+                        Scalar::from(rgb[1]) / 255.0,
+                        Scalar::from(rgb[2]) / 255.0,
+                        Scalar::from(a.unwrap_or(255)) / 255.0,
                     ];
                     result = Some(Expression::Variable(Box::new((range, Variable::Vec4(v)))));
                 } else {
@@ -3632,11 +3640,11 @@ impl Vec4 {
     }
 
     fn precompute(&self) -> Option<Variable> {
-        let mut v: [f32; 4] = [0.0; 4];
+        let mut v: [Scalar; 4] = [0.0; 4];
         for i in 0..self.args.len().min(4) {
             if let Some(val) = self.args[i].precompute() {
                 if let Variable::F64(val, _) = val {
-                    v[i] = val as f32;
+                    v[i] = val as Scalar;
                 } else {
                     return None;
                 }
@@ -0,0 +1,283 @@
+//! Constant folding and removal of dead branches.
+//!
+//! Expressions are simplified bottom-up, so folding the condition of an `if`
+//! to a constant also removes the branches that are never taken.
+
+use std::mem;
+use std::sync::Arc;
+
+use super::{Block, Call, CallBinOp, CallClosure, CallUnOp, Expression, ForIn, ForN, Id, If, Item};
+use Scalar;
+use Variable;
+
+/// Optimizes loaded functions, returning the number of simplified expressions.
+pub(crate) fn optimize(functions: &mut [super::Function]) -> usize {
+    let mut opt = Optimizer { simplified: 0 };
+    for f in functions {
+        opt.block(&mut f.block);
+    }
+    opt.simplified
+}
+
+struct Optimizer {
+    simplified: usize,
+}
+
+impl Optimizer {
+    fn block(&mut self, block: &mut Block) {
+        self.exprs(&mut block.expressions);
+    }
+
+    fn exprs(&mut self, exprs: &mut [Expression]) {
+        for expr in exprs {
+            self.expr(expr);
+        }
+    }
+
+    fn call(&mut self, call: &mut Call) {
+        self.exprs(&mut call.args);
+    }
+
+    fn item(&mut self, item: &mut Item) {
+        for id in &mut item.ids {
+            if let Id::Expression(ref mut expr) = *id {
+                self.expr(expr);
+            }
+        }
+    }
+
+    fn for_n(&mut self, for_n: &mut ForN) {
+        if let Some(ref mut start) = for_n.start {
+            self.expr(start);
+        }
+        self.expr(&mut for_n.end);
+        self.block(&mut for_n.block);
+    }
+
+    fn for_in(&mut self, for_in: &mut ForIn) {
+        self.expr(&mut for_in.iter);
+        self.block(&mut for_in.block);
+    }
+
+    fn call_closure(&mut self, call: &mut CallClosure) {
+        self.item(&mut call.item);
+        self.exprs(&mut call.args);
+    }
+
+    fn if_expr(&mut self, if_expr: &mut If) {
+        self.expr(&mut if_expr.cond);
+        self.block(&mut if_expr.true_block);
+        self.exprs(&mut if_expr.else_if_conds);
+        for block in &mut if_expr.else_if_blocks {
+            self.block(block);
+        }
+        if let Some(ref mut block) = if_expr.else_block {
+            self.block(block);
+        }
+    }
+
+    fn expr(&mut self, expr: &mut Expression) {
+        use super::Expression as E;
+
+        // Simplify sub-expressions first.
+        match *expr {
+            E::Link(ref mut link) => self.exprs(&mut link.items),
+            E::Object(ref mut obj) => {
+                for &mut (_, ref mut expr) in &mut obj.key_values {
+                    self.expr(expr);
+                }
+            }
+            E::Array(ref mut arr) => self.exprs(&mut arr.items),
+            E::ArrayFill(ref mut fill) => {
+                self.expr(&mut fill.fill);
+                self.expr(&mut fill.n);
+            }
+            E::Return(ref mut expr) | E::Try(ref mut expr) => self.expr(expr),
+            E::ReturnVoid(_) | E::Break(_) | E::Continue(_) | E::Variable(_) | E::In(_) => {}
+            E::Block(ref mut block) => self.block(block),
+            #[cfg(all(not(target_family = "wasm"), feature = "threading"))]
+            E::Go(ref mut go) => self.call(&mut go.call),
+            E::Call(ref mut call) => self.call(call),
+            E::CallVoid(ref mut call) => self.exprs(&mut call.args),
+            E::CallReturn(ref mut call) => self.exprs(&mut call.args),
+            E::CallLazy(ref mut call) => self.exprs(&mut call.args),
+            E::CallLoaded(ref mut call) => self.exprs(&mut call.args),
+            E::CallBinOp(ref mut call) => {
+                self.expr(&mut call.left);
+                self.expr(&mut call.right);
+            }
+            E::CallUnOp(ref mut call) => self.expr(&mut call.arg),
+            E::Item(ref mut item) => self.item(item),
+            E::Assign(ref mut assign) => {
+                self.expr(&mut assign.left);
+                self.expr(&mut assign.right);
+            }
+            E::Vec4(ref mut vec4) => self.exprs(&mut vec4.args),
+            E::Mat4(ref mut mat4) => self.exprs(&mut mat4.args),
+            E::For(ref mut for_expr) => {
+                self.expr(&mut for_expr.init);
+                self.expr(&mut for_expr.cond);
+                self.expr(&mut for_expr.step);
+                self.block(&mut for_expr.block);
+            }
+            E::ForN(ref mut for_n)
+            | E::Sum(ref mut for_n)
+            | E::SumVec4(ref mut for_n)
+            | E::Prod(ref mut for_n)
+            | E::ProdVec4(ref mut for_n)
+            | E::Min(ref mut for_n)
+            | E::Max(ref mut for_n)
+            | E::Sift(ref mut for_n)
+            | E::Any(ref mut for_n)
+            | E::All(ref mut for_n)
+            | E::LinkFor(ref mut for_n) => self.for_n(for_n),
+            E::ForIn(ref mut for_in)
+            | E::SumIn(ref mut for_in)
+            | E::ProdIn(ref mut for_in)
+            | E::MinIn(ref mut for_in)
+            | E::MaxIn(ref mut for_in)
+            | E::SiftIn(ref mut for_in)
+            | E::AnyIn(ref mut for_in)
+            | E::AllIn(ref mut for_in)
+            | E::LinkIn(ref mut for_in) => self.for_in(for_in),
+            E::If(ref mut if_expr) => self.if_expr(if_expr),
+            E::Swizzle(ref mut swizzle) => self.expr(&mut swizzle.expr),
+            E::Closure(ref mut closure) => self.expr(&mut Arc::make_mut(closure).expr),
+            E::CallClosure(ref mut call) => self.call_closure(call),
+            E::Grab(ref mut grab) => self.expr(&mut grab.expr),
+            E::TryExpr(ref mut try_expr) => self.expr(&mut try_expr.expr),
+        }
+
+        let new_expr = match *expr {
+            E::CallBinOp(ref call) => fold_binop(call),
+            E::CallUnOp(ref call) => fold_unop(call),
+            E::Vec4(ref vec4) => {
+                let mut v = [0.0; 4];
+                for (x, arg) in v.iter_mut().zip(&vec4.args) {
+                    match constant(arg) {
+                        Some(&Variable::F64(a, None)) => *x = a as Scalar,
+                        _ => return,
+                    }
+                }
+                Some(literal(vec4.source_range, Variable::Vec4(v)))
+            }
+            E::Mat4(ref mat4) => {
+                let mut rows = [[0.0; 4]; 4];
+                for (row, arg) in rows.iter_mut().zip(&mat4.args) {
+                    match constant(arg) {
+                        Some(&Variable::Vec4(v)) => *row = v,
+                        _ => return,
+                    }
+                }
+                // Matrices are stored by columns.
+                let mut m = [[0.0; 4]; 4];
+                for (i, col) in m.iter_mut().enumerate() {
+                    for (j, x) in col.iter_mut().enumerate() {
+                        *x = rows[j][i];
+                    }
+                }
+                Some(literal(mat4.source_range, Variable::Mat4(Box::new(m))))
+            }
+            E::If(ref mut if_expr) => self.fold_if(if_expr),
+            _ => None,
+        };
+        if let Some(new_expr) = new_expr {
+            *expr = new_expr;
+            self.simplified += 1;
+        }
+    }
+
+    /// Removes branches that are never taken.
+    ///
+    /// Returns the block to use instead when the branch taken is known.
+    fn fold_if(&mut self, if_expr: &mut If) -> Option<Expression> {
+        let mut i = 0;
+        while i < if_expr.else_if_conds.len() {
+            match constant(&if_expr.else_if_conds[i]) {
+                Some(&Variable::Bool(false, None)) => {
+                    if_expr.else_if_conds.remove(i);
+                    if_expr.else_if_blocks.remove(i);
+                    self.simplified += 1;
+                }
+                Some(&Variable::Bool(true, None)) => {
+                    // The following branches are never taken.
+                    if_expr.else_block = Some(if_expr.else_if_blocks.remove(i));
+                    if_expr.else_if_conds.truncate(i);
+                    if_expr.else_if_blocks.truncate(i);
+                    self.simplified += 1;
+                }
+                _ => i += 1,
+            }
+        }
+        match constant(&if_expr.cond) {
+            Some(&Variable::Bool(true, None)) => {
+                let empty = empty_block(&if_expr.true_block);
+                let block = mem::replace(&mut if_expr.true_block, empty);
+                Some(Expression::Block(Box::new(block)))
+            }
+            Some(&Variable::Bool(false, None)) => {
+                if !if_expr.else_if_conds.is_empty() {
+                    if_expr.cond = if_expr.else_if_conds.remove(0);
+                    if_expr.true_block = if_expr.else_if_blocks.remove(0);
+                    self.simplified += 1;
+                    None
+                } else if let Some(block) = if_expr.else_block.take() {
+                    Some(Expression::Block(Box::new(block)))
+                } else {
+                    Some(Expression::Block(Box::new(empty_block(
+                        &if_expr.true_block,
+                    ))))
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+fn empty_block(block: &Block) -> Block {
+    Block {
+        expressions: vec![],
+        source_range: block.source_range,
+    }
+}
+
+fn literal(range: ::range::Range, v: Variable) -> Expression {
+    Expression::Variable(Box::new((range, v)))
+}
+
+/// Returns the value of an expression that is a constant without secrets.
+fn constant(expr: &Expression) -> Option<&Variable> {
+    if let Expression::Variable(ref range_var) = *expr {
+        match range_var.1 {
+            Variable::F64(_, None)
+            | Variable::Bool(_, None)
+            | Variable::Vec4(_)
+            | Variable::Mat4(_)
+            | Variable::Str(_) => return Some(&range_var.1),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Computes a binary operator on constants.
+///
+/// Operators do not have access to the runtime, so computing one early gives the same result.
+/// Errors are left to be reported when running.
+fn fold_binop(call: &CallBinOp) -> Option<Expression> {
+    let a = constant(&call.left)?;
+    let b = constant(&call.right)?;
+    let v = (call.fun.0)(a, b).ok()?;
+    let e = literal(call.info.source_range, v);
+    constant(&e)?;
+    Some(e)
+}
+
+/// Computes a unary operator on a constant.
+fn fold_unop(call: &CallUnOp) -> Option<Expression> {
+    let a = constant(&call.arg)?;
+    let v = (call.fun.0)(a).ok()?;
+    let e = literal(call.info.source_range, v);
+    constant(&e)?;
+    Some(e)
+}
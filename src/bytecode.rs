@@ -0,0 +1,966 @@
+//! Bytecode compiler for numeric functions.
+//!
+//! The AST interpreter looks up locals and dispatches on expressions
+//! each time they are evaluated, which is slow for tight loops.
+//! `Module::compile` translates functions into a flat instruction stream
+//! for a small stack machine, which the runtime executes instead of the AST.
+//!
+//! Only functions that work on numbers and booleans are compiled:
+//!
+//! - Arguments must be `f64` or `bool` and not `mut`
+//! - The return type must be `f64`, `bool` or none
+//! - The body can use locals, arithmetic, comparisons, `if`, `for`,
+//!   `for n`, `sum`, `prod`, `break`, `continue` and `return`
+//! - Calls are allowed to other compiled functions
+//!   and to external functions taking only `f64` arguments
+//!
+//! Other functions, and calls with arguments of other types,
+//! e.g. values from `any` arguments carrying secrets, use the AST interpreter.
+
+use range::Range;
+use std::cmp;
+use std::sync::Arc;
+
+use ast;
+use dyon_std;
+use runtime::send_args;
+use FnExt;
+use FnIndex;
+use FnReturnRef;
+use Module;
+use Runtime;
+use RuntimeError;
+use Type;
+use Variable;
+use TINVOTS;
+
+/// The type of a value in compiled code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Ty {
+    F64,
+    Bool,
+}
+
+impl Ty {
+    fn from_type(ty: &Type) -> Option<Ty> {
+        match *ty {
+            Type::F64 => Some(Ty::F64),
+            Type::Bool => Some(Ty::Bool),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn variable(self, v: f64) -> Variable {
+        match self {
+            Ty::F64 => Variable::f64(v),
+            Ty::Bool => Variable::bool(v != 0.0),
+        }
+    }
+}
+
+/// Booleans are stored as `0.0` or `1.0`.
+fn flag(b: bool) -> f64 {
+    f64::from(u8::from(b))
+}
+
+#[derive(Clone, Debug)]
+enum Instr {
+    Const(f64),
+    Load(usize),
+    Store(usize),
+    Pop,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Pow,
+    Neg,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+    NotEqual,
+    And,
+    Or,
+    AndNot,
+    Not,
+    /// Pops a value and adds it to a local.
+    AddTo(usize),
+    /// Pops a value and multiplies a local with it.
+    MulTo(usize),
+    /// Increments a local by one.
+    Inc(usize),
+    Jump(usize),
+    JumpIfFalse(usize),
+    /// Jumps unless the first local is less than the second.
+    JumpIfNotLess(usize, usize, usize),
+    /// Jumps back to the start of a loop, checking for cancellation and limits.
+    Loop(usize, Range),
+    /// Calls a compiled function by index in module.
+    Call(usize, Range),
+    /// Calls an external function by index in chunk.
+    CallExt(usize),
+    Return,
+}
+
+/// External function called from compiled code.
+#[derive(Clone)]
+struct ExtCall {
+    name: Arc<String>,
+    fun: FnReturnRef,
+    args: Vec<Range>,
+    ret: Ty,
+    source_range: Range,
+}
+
+/// A compiled function.
+#[derive(Clone)]
+pub(crate) struct Chunk {
+    code: Vec<Instr>,
+    ext: Vec<ExtCall>,
+    args: Vec<Ty>,
+    locals: usize,
+    pub(crate) ret: Option<Ty>,
+}
+
+impl Chunk {
+    /// Creates locals from arguments on the runtime stack,
+    /// or returns `None` if the arguments have the wrong type.
+    pub(crate) fn frame(&self, rt: &Runtime, st: usize) -> Option<Vec<f64>> {
+        let args = &rt.stack[st..];
+        if args.len() != self.args.len() {
+            return None;
+        }
+        let mut locals = vec![0.0; self.locals];
+        for (i, (arg, &ty)) in args.iter().zip(&self.args).enumerate() {
+            locals[i] = match (rt.resolve(arg), ty) {
+                (&Variable::F64(v, None), Ty::F64) => v,
+                (&Variable::Bool(b, None), Ty::Bool) => flag(b),
+                _ => return None,
+            };
+        }
+        Some(locals)
+    }
+}
+
+/// Compiles the functions of a module.
+///
+/// A function is compiled when its body is supported
+/// and all the loaded functions it calls are compiled.
+pub(crate) fn compile(module: &Module) -> Vec<Option<Chunk>> {
+    let mut candidates: Vec<bool> = module.functions.iter().map(signature).collect();
+    loop {
+        let chunks: Vec<Option<Chunk>> = module
+            .functions
+            .iter()
+            .enumerate()
+            .map(|(i, f)| {
+                if candidates[i] {
+                    Compiler::new(module, i, &candidates).function(f)
+                } else {
+                    None
+                }
+            })
+            .collect();
+        let mut changed = false;
+        for (candidate, chunk) in candidates.iter_mut().zip(&chunks) {
+            if *candidate && chunk.is_none() {
+                *candidate = false;
+                changed = true;
+            }
+        }
+        if !changed {
+            return chunks;
+        }
+    }
+}
+
+/// Returns `true` if the function signature is supported.
+fn signature(f: &ast::Function) -> bool {
+    f.currents.is_empty()
+        && f.lazy_inv.iter().all(|lz| lz.is_empty())
+        && f.args
+            .iter()
+            .all(|arg| !arg.mutable && Ty::from_type(&arg.ty).is_some())
+        && (f.ret == Type::Void || Ty::from_type(&f.ret).is_some())
+}
+
+/// The result of compiling an expression.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Val {
+    /// No value.
+    None,
+    /// A value on the stack.
+    Value(Ty),
+    /// Control flow does not continue after the expression.
+    Never,
+}
+
+impl Val {
+    fn unify(self, other: Val) -> Option<Val> {
+        match (self, other) {
+            (Val::Never, x) | (x, Val::Never) => Some(x),
+            (a, b) if a == b => Some(a),
+            _ => None,
+        }
+    }
+}
+
+struct Loop {
+    /// Stack depth when entering the loop body.
+    depth: usize,
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+#[derive(Clone, Copy)]
+enum Reduce {
+    None,
+    Sum,
+    Prod,
+}
+
+/// Compiles a single function.
+///
+/// Methods return `None` when an expression is not supported.
+struct Compiler<'a> {
+    module: &'a Module,
+    index: usize,
+    candidates: &'a [bool],
+    ret: Option<Ty>,
+    code: Vec<Instr>,
+    ext: Vec<ExtCall>,
+    locals: Vec<(Arc<String>, usize, Ty)>,
+    slots: usize,
+    max_slots: usize,
+    /// Number of values on the stack.
+    depth: usize,
+    loops: Vec<Loop>,
+}
+
+impl<'a> Compiler<'a> {
+    fn new(module: &'a Module, index: usize, candidates: &'a [bool]) -> Compiler<'a> {
+        Compiler {
+            module,
+            index,
+            candidates,
+            ret: Ty::from_type(&module.functions[index].ret),
+            code: vec![],
+            ext: vec![],
+            locals: vec![],
+            slots: 0,
+            max_slots: 0,
+            depth: 0,
+            loops: vec![],
+        }
+    }
+
+    fn function(mut self, f: &ast::Function) -> Option<Chunk> {
+        let mut args = vec![];
+        for arg in &f.args {
+            let ty = Ty::from_type(&arg.ty)?;
+            self.declare(arg.name.clone(), ty);
+            args.push(ty);
+        }
+        match (self.block(&f.block)?, self.ret) {
+            (Val::Value(ty), Some(ret)) if ty == ret => {
+                self.emit(Instr::Return);
+            }
+            (Val::None, None) => {
+                self.emit(Instr::Return);
+            }
+            (Val::Never, _) => {}
+            _ => return None,
+        }
+        Some(Chunk {
+            code: self.code,
+            ext: self.ext,
+            args,
+            locals: self.max_slots,
+            ret: self.ret,
+        })
+    }
+
+    fn emit(&mut self, instr: Instr) -> usize {
+        use self::Instr::*;
+
+        match instr {
+            Const(_) | Load(_) => self.depth += 1,
+            Store(_) | Pop | AddTo(_) | MulTo(_) | JumpIfFalse(_) => self.depth -= 1,
+            Add | Sub | Mul | Div | Rem | Pow | Less | LessOrEqual | Greater | GreaterOrEqual
+            | Equal | NotEqual | And | Or | AndNot => self.depth -= 1,
+            Neg | Not | Inc(_) | Jump(_) | JumpIfNotLess(..) | Loop(..) => {}
+            Call(index, _) => {
+                let f = &self.module.functions[index];
+                self.depth -= f.args.len();
+                if f.ret != Type::Void {
+                    self.depth += 1;
+                }
+            }
+            CallExt(i) => self.depth = self.depth - self.ext[i].args.len() + 1,
+            Return => {
+                if self.ret.is_some() {
+                    self.depth -= 1
+                }
+            }
+        }
+        self.code.push(instr);
+        self.code.len() - 1
+    }
+
+    /// Sets the target of a jump instruction to the next instruction.
+    fn patch(&mut self, pos: usize) {
+        let target = self.code.len();
+        match self.code[pos] {
+            Instr::Jump(ref mut t)
+            | Instr::JumpIfFalse(ref mut t)
+            | Instr::JumpIfNotLess(_, _, ref mut t) => *t = target,
+            _ => panic!("Expected jump instruction"),
+        }
+    }
+
+    fn slot(&mut self) -> usize {
+        let slot = self.slots;
+        self.slots += 1;
+        self.max_slots = self.max_slots.max(self.slots);
+        slot
+    }
+
+    fn declare(&mut self, name: Arc<String>, ty: Ty) -> usize {
+        let slot = self.slot();
+        self.locals.push((name, slot, ty));
+        slot
+    }
+
+    fn lookup(&self, name: &Arc<String>) -> Option<(usize, Ty)> {
+        self.locals
+            .iter()
+            .rev()
+            .find(|l| &l.0 == name)
+            .map(|l| (l.1, l.2))
+    }
+
+    /// Returns the local of an item without ids.
+    fn local(&self, item: &ast::Item) -> Option<(usize, Ty)> {
+        if item.current || item.try || !item.ids.is_empty() {
+            return None;
+        }
+        self.lookup(&item.name)
+    }
+
+    fn value(&mut self, expr: &ast::Expression) -> Option<Ty> {
+        match self.expr(expr)? {
+            Val::Value(ty) => Some(ty),
+            _ => None,
+        }
+    }
+
+    fn expr(&mut self, expr: &ast::Expression) -> Option<Val> {
+        use ast::Expression as E;
+
+        match *expr {
+            E::Variable(ref range_var) => match range_var.1 {
+                Variable::F64(v, None) => {
+                    self.emit(Instr::Const(v));
+                    Some(Val::Value(Ty::F64))
+                }
+                Variable::Bool(b, None) => {
+                    self.emit(Instr::Const(flag(b)));
+                    Some(Val::Value(Ty::Bool))
+                }
+                _ => None,
+            },
+            E::Item(ref item) => {
+                let (slot, ty) = self.local(item)?;
+                self.emit(Instr::Load(slot));
+                Some(Val::Value(ty))
+            }
+            E::Assign(ref assign) => self.assign(assign),
+            E::Block(ref block) => self.block(block),
+            E::If(ref if_expr) => self.if_expr(if_expr),
+            E::For(ref for_expr) => self.for_expr(for_expr),
+            E::ForN(ref for_n) => self.for_n(for_n, Reduce::None),
+            E::Sum(ref for_n) => self.for_n(for_n, Reduce::Sum),
+            E::Prod(ref for_n) => self.for_n(for_n, Reduce::Prod),
+            E::Break(ref b) if b.label.is_none() => self.jump_out(true),
+            E::Continue(ref c) if c.label.is_none() => self.jump_out(false),
+            E::Return(ref ret) => {
+                let ty = self.value(ret)?;
+                if Some(ty) != self.ret {
+                    return None;
+                }
+                self.emit(Instr::Return);
+                Some(Val::Never)
+            }
+            E::ReturnVoid(_) if self.ret.is_none() => {
+                self.emit(Instr::Return);
+                Some(Val::Never)
+            }
+            E::CallBinOp(ref call) => self.binop(&call.left, &call.right, call.fun.0),
+            E::CallUnOp(ref call) => self.unop(&call.arg, call.fun.0),
+            E::CallLazy(ref call) => self.lazy(&call.args, call.fun),
+            E::CallReturn(ref call) => self.call_ext(&call.args, call.fun, &call.info),
+            E::CallLoaded(ref call) => self.call_loaded(&call.args, call.fun, &call.info),
+            E::Call(ref call) => match call.f_index {
+                FnIndex::BinOp(f) if call.args.len() == 2 => {
+                    self.binop(&call.args[0], &call.args[1], f.0)
+                }
+                FnIndex::UnOp(f) if call.args.len() == 1 => self.unop(&call.args[0], f.0),
+                FnIndex::Lazy(f, _) => self.lazy(&call.args, f),
+                FnIndex::Return(f) => self.call_ext(&call.args, f, &call.info),
+                FnIndex::Loaded(f) => self.call_loaded(&call.args, f, &call.info),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn block(&mut self, block: &ast::Block) -> Option<Val> {
+        let locals = self.locals.len();
+        let slots = self.slots;
+        let mut never = false;
+        let mut val = Val::None;
+        for (i, e) in block.expressions.iter().enumerate() {
+            val = self.expr(e)?;
+            if val == Val::Never {
+                never = true;
+            }
+            if i + 1 < block.expressions.len() {
+                if let Val::Value(_) = val {
+                    self.emit(Instr::Pop);
+                }
+            }
+        }
+        self.locals.truncate(locals);
+        self.slots = slots;
+        if never && val == Val::None {
+            val = Val::Never;
+        }
+        Some(val)
+    }
+
+    fn assign(&mut self, assign: &ast::Assign) -> Option<Val> {
+        use ast::AssignOp::*;
+
+        let item = match assign.left {
+            ast::Expression::Item(ref item) => item,
+            _ => return None,
+        };
+        if assign.op == Assign {
+            if item.current || item.try || !item.ids.is_empty() {
+                return None;
+            }
+            let ty = self.value(&assign.right)?;
+            let slot = self.declare(item.name.clone(), ty);
+            self.emit(Instr::Store(slot));
+            return Some(Val::None);
+        }
+        let (slot, ty) = self.local(item)?;
+        let op = match assign.op {
+            Set => None,
+            Add => Some(Instr::Add),
+            Sub => Some(Instr::Sub),
+            Mul => Some(Instr::Mul),
+            Div => Some(Instr::Div),
+            Rem => Some(Instr::Rem),
+            Pow => Some(Instr::Pow),
+            Assign => unreachable!(),
+        };
+        match op {
+            None => {
+                if self.value(&assign.right)? != ty {
+                    return None;
+                }
+            }
+            Some(op) => {
+                if ty != Ty::F64 {
+                    return None;
+                }
+                self.emit(Instr::Load(slot));
+                if self.value(&assign.right)? != Ty::F64 {
+                    return None;
+                }
+                self.emit(op);
+            }
+        }
+        self.emit(Instr::Store(slot));
+        Some(Val::None)
+    }
+
+    fn if_expr(&mut self, if_expr: &ast::If) -> Option<Val> {
+        let depth = self.depth;
+        let mut ends = vec![];
+        let mut val = Val::Never;
+        let conds = Some(&if_expr.cond)
+            .into_iter()
+            .chain(&if_expr.else_if_conds);
+        let blocks = Some(&if_expr.true_block)
+            .into_iter()
+            .chain(&if_expr.else_if_blocks);
+        for (cond, block) in conds.zip(blocks) {
+            self.depth = depth;
+            if self.value(cond)? != Ty::Bool {
+                return None;
+            }
+            let jump = self.emit(Instr::JumpIfFalse(0));
+            val = val.unify(self.block(block)?)?;
+            ends.push(self.emit(Instr::Jump(0)));
+            self.patch(jump);
+        }
+        self.depth = depth;
+        val = match if_expr.else_block {
+            Some(ref block) => val.unify(self.block(block)?)?,
+            None => val.unify(Val::None)?,
+        };
+        for end in ends {
+            self.patch(end);
+        }
+        if let Val::Value(_) = val {
+            self.depth += 1;
+        }
+        Some(val)
+    }
+
+    fn jump_out(&mut self, is_break: bool) -> Option<Val> {
+        let depth = self.loops.last()?.depth;
+        if depth != self.depth {
+            return None;
+        }
+        let jump = self.emit(Instr::Jump(0));
+        let lp = self.loops.last_mut()?;
+        if is_break {
+            lp.breaks.push(jump);
+        } else {
+            lp.continues.push(jump);
+        }
+        Some(Val::Never)
+    }
+
+    /// Compiles a loop body and returns the jumps out of the loop.
+    fn loop_body(&mut self, block: &ast::Block) -> Option<(Val, Loop)> {
+        self.loops.push(Loop {
+            depth: self.depth,
+            breaks: vec![],
+            continues: vec![],
+        });
+        let val = self.block(block);
+        let lp = self.loops.pop()?;
+        Some((val?, lp))
+    }
+
+    fn for_expr(&mut self, for_expr: &ast::For) -> Option<Val> {
+        let locals = self.locals.len();
+        let slots = self.slots;
+        if self.expr(&for_expr.init)? != Val::None {
+            return None;
+        }
+        let start = self.code.len();
+        if self.value(&for_expr.cond)? != Ty::Bool {
+            return None;
+        }
+        let exit = self.emit(Instr::JumpIfFalse(0));
+        let (val, lp) = self.loop_body(&for_expr.block)?;
+        if let Val::Value(_) = val {
+            self.emit(Instr::Pop);
+        }
+        for pos in lp.continues {
+            self.patch(pos);
+        }
+        if self.expr(&for_expr.step)? != Val::None {
+            return None;
+        }
+        self.emit(Instr::Loop(start, for_expr.source_range));
+        self.patch(exit);
+        for pos in lp.breaks {
+            self.patch(pos);
+        }
+        self.locals.truncate(locals);
+        self.slots = slots;
+        Some(Val::None)
+    }
+
+    fn for_n(&mut self, for_n: &ast::ForN, reduce: Reduce) -> Option<Val> {
+        let locals = self.locals.len();
+        let slots = self.slots;
+        match for_n.start {
+            Some(ref start) => {
+                if self.value(start)? != Ty::F64 {
+                    return None;
+                }
+            }
+            None => {
+                self.emit(Instr::Const(0.0));
+            }
+        }
+        if self.value(&for_n.end)? != Ty::F64 {
+            return None;
+        }
+        let end = self.slot();
+        self.emit(Instr::Store(end));
+        let acc = self.slot();
+        match reduce {
+            Reduce::None => {}
+            Reduce::Sum => {
+                self.emit(Instr::Const(0.0));
+                self.emit(Instr::Store(acc));
+            }
+            Reduce::Prod => {
+                self.emit(Instr::Const(1.0));
+                self.emit(Instr::Store(acc));
+            }
+        }
+        let i = self.declare(for_n.name.clone(), Ty::F64);
+        self.emit(Instr::Store(i));
+
+        let start = self.emit(Instr::JumpIfNotLess(i, end, 0));
+        let (val, lp) = self.loop_body(&for_n.block)?;
+        match (reduce, val) {
+            (_, Val::Never) => {}
+            (Reduce::None, Val::Value(_)) => {
+                self.emit(Instr::Pop);
+            }
+            (Reduce::None, Val::None) => {}
+            (Reduce::Sum, Val::Value(Ty::F64)) => {
+                self.emit(Instr::AddTo(acc));
+            }
+            (Reduce::Prod, Val::Value(Ty::F64)) => {
+                self.emit(Instr::MulTo(acc));
+            }
+            _ => return None,
+        }
+        for pos in lp.continues {
+            self.patch(pos);
+        }
+        self.emit(Instr::Inc(i));
+        self.emit(Instr::Loop(start, for_n.source_range));
+        self.patch(start);
+        for pos in lp.breaks {
+            self.patch(pos);
+        }
+        self.locals.truncate(locals);
+        self.slots = slots;
+        match reduce {
+            Reduce::None => Some(Val::None),
+            Reduce::Sum | Reduce::Prod => {
+                self.emit(Instr::Load(acc));
+                Some(Val::Value(Ty::F64))
+            }
+        }
+    }
+
+    fn binop(
+        &mut self,
+        left: &ast::Expression,
+        right: &ast::Expression,
+        f: fn(&Variable, &Variable) -> Result<Variable, String>,
+    ) -> Option<Val> {
+        type BinOp = fn(&Variable, &Variable) -> Result<Variable, String>;
+        // Instructions for numbers and booleans.
+        let ops: [(BinOp, Instr, Option<Instr>); 12] = [
+            (dyon_std::add, Instr::Add, Some(Instr::Or)),
+            (dyon_std::sub, Instr::Sub, Some(Instr::AndNot)),
+            (dyon_std::mul, Instr::Mul, Some(Instr::And)),
+            (dyon_std::div, Instr::Div, None),
+            (dyon_std::rem, Instr::Rem, None),
+            (dyon_std::pow, Instr::Pow, Some(Instr::NotEqual)),
+            (dyon_std::less, Instr::Less, None),
+            (dyon_std::less_or_equal, Instr::LessOrEqual, None),
+            (dyon_std::greater, Instr::Greater, None),
+            (dyon_std::greater_or_equal, Instr::GreaterOrEqual, None),
+            (dyon_std::equal, Instr::Equal, Some(Instr::Equal)),
+            (dyon_std::not_equal, Instr::NotEqual, Some(Instr::NotEqual)),
+        ];
+        let op = ops.iter().find(|op| op.0 as *const () == f as *const ())?;
+        let a = self.value(left)?;
+        if self.value(right)? != a {
+            return None;
+        }
+        let instr = match a {
+            Ty::F64 => op.1.clone(),
+            Ty::Bool => op.2.clone()?,
+        };
+        let ty = match instr {
+            Instr::Add | Instr::Sub | Instr::Mul | Instr::Div | Instr::Rem | Instr::Pow => Ty::F64,
+            _ => Ty::Bool,
+        };
+        self.emit(instr);
+        Some(Val::Value(ty))
+    }
+
+    fn unop(
+        &mut self,
+        arg: &ast::Expression,
+        f: fn(&Variable) -> Result<Variable, String>,
+    ) -> Option<Val> {
+        type UnOp = fn(&Variable) -> Result<Variable, String>;
+        let is = |g: UnOp| g as *const () == f as *const ();
+        let ty = self.value(arg)?;
+        if is(dyon_std::neg) && ty == Ty::F64 {
+            self.emit(Instr::Neg);
+        } else if is(dyon_std::not) && ty == Ty::Bool {
+            self.emit(Instr::Not);
+        } else {
+            return None;
+        }
+        Some(Val::Value(ty))
+    }
+
+    /// Compiles `a && b` or `a || b`, evaluating `b` only when needed.
+    fn lazy(&mut self, args: &[ast::Expression], f: FnReturnRef) -> Option<Val> {
+        type Lazy = fn(&mut Runtime) -> Result<Variable, RuntimeError>;
+        let is = |g: Lazy| g as *const () == f.0 as *const ();
+        let and = if is(dyon_std::and_also) {
+            true
+        } else if is(dyon_std::or_else) {
+            false
+        } else {
+            return None;
+        };
+        if args.len() != 2 || self.value(&args[0])? != Ty::Bool {
+            return None;
+        }
+        let depth = self.depth;
+        let short = self.emit(Instr::JumpIfFalse(0));
+        if and {
+            if self.value(&args[1])? != Ty::Bool {
+                return None;
+            }
+            let end = self.emit(Instr::Jump(0));
+            self.patch(short);
+            self.depth = depth - 1;
+            self.emit(Instr::Const(0.0));
+            self.patch(end);
+        } else {
+            self.emit(Instr::Const(1.0));
+            let end = self.emit(Instr::Jump(0));
+            self.patch(short);
+            self.depth = depth - 1;
+            if self.value(&args[1])? != Ty::Bool {
+                return None;
+            }
+            self.patch(end);
+        }
+        Some(Val::Value(Ty::Bool))
+    }
+
+    fn call_ext(
+        &mut self,
+        args: &[ast::Expression],
+        fun: FnReturnRef,
+        info: &ast::CallInfo,
+    ) -> Option<Val> {
+        let clone: fn(&mut Runtime) -> Result<Variable, RuntimeError> = dyon_std::clone;
+        if clone as *const () == fun.0 as *const () && args.len() == 1 {
+            // Numbers and booleans are copied.
+            return self.value(&args[0]).map(Val::Value);
+        }
+        let ext = self
+            .module
+            .ext_prelude
+            .iter()
+            .rev()
+            .find(|ext| match ext.f {
+                FnExt::Return(f) => f as *const () == fun.0 as *const (),
+                _ => false,
+            })?;
+        if !ext.p.lazy.is_empty()
+            || ext.p.tys.len() != args.len()
+            || ext.p.tys.iter().any(|ty| *ty != Type::F64)
+        {
+            return None;
+        }
+        let ret = Ty::from_type(&ext.p.ret)?;
+        for arg in args {
+            if self.value(arg)? != Ty::F64 {
+                return None;
+            }
+        }
+        self.ext.push(ExtCall {
+            name: info.name.clone(),
+            fun,
+            args: args.iter().map(|arg| arg.source_range()).collect(),
+            ret,
+            source_range: info.source_range,
+        });
+        let i = self.ext.len() - 1;
+        self.emit(Instr::CallExt(i));
+        Some(Val::Value(ret))
+    }
+
+    fn call_loaded(
+        &mut self,
+        args: &[ast::Expression],
+        f_index: isize,
+        info: &ast::CallInfo,
+    ) -> Option<Val> {
+        let index = (f_index + self.index as isize) as usize;
+        if !self.candidates.get(index).cloned().unwrap_or(false) {
+            return None;
+        }
+        let f = &self.module.functions[index];
+        if f.args.len() != args.len() {
+            return None;
+        }
+        for (arg, expr) in f.args.iter().zip(args) {
+            if Some(self.value(expr)?) != Ty::from_type(&arg.ty) {
+                return None;
+            }
+        }
+        self.emit(Instr::Call(index, info.source_range));
+        Some(match Ty::from_type(&f.ret) {
+            Some(ty) => Val::Value(ty),
+            None => Val::None,
+        })
+    }
+}
+
+/// Runs a compiled function.
+///
+/// The function must be on the call stack,
+/// such that errors refer to its source.
+pub(crate) fn run(
+    rt: &mut Runtime,
+    module: &Module,
+    chunk: &Chunk,
+    mut locals: Vec<f64>,
+) -> Result<f64, RuntimeError> {
+    use std::sync::atomic::Ordering;
+
+    macro_rules! binop {
+        ($stack:ident, |$a:ident, $b:ident| $e:expr) => {{
+            let $b = $stack.pop().expect(TINVOTS);
+            let $a = $stack.pop().expect(TINVOTS);
+            $stack.push($e);
+        }};
+    }
+
+    let mut stack: Vec<f64> = Vec::with_capacity(8);
+    let mut pc = 0;
+    loop {
+        match chunk.code[pc] {
+            Instr::Const(v) => stack.push(v),
+            Instr::Load(slot) => stack.push(locals[slot]),
+            Instr::Store(slot) => locals[slot] = stack.pop().expect(TINVOTS),
+            Instr::Pop => {
+                stack.pop();
+            }
+            Instr::Add => binop!(stack, |a, b| a + b),
+            Instr::Sub => binop!(stack, |a, b| a - b),
+            Instr::Mul => binop!(stack, |a, b| a * b),
+            Instr::Div => binop!(stack, |a, b| a / b),
+            Instr::Rem => binop!(stack, |a, b| a % b),
+            Instr::Pow => binop!(stack, |a, b| a.powf(b)),
+            Instr::Neg => {
+                let v = stack.last_mut().expect(TINVOTS);
+                *v = -*v;
+            }
+            Instr::Less => binop!(stack, |a, b| flag(a < b)),
+            Instr::LessOrEqual => binop!(stack, |a, b| flag(a <= b)),
+            Instr::Greater => binop!(stack, |a, b| flag(a > b)),
+            Instr::GreaterOrEqual => binop!(stack, |a, b| flag(a >= b)),
+            Instr::Equal => binop!(stack, |a, b| flag(a == b)),
+            Instr::NotEqual => binop!(stack, |a, b| flag(a != b)),
+            Instr::And => binop!(stack, |a, b| flag(a != 0.0 && b != 0.0)),
+            Instr::Or => binop!(stack, |a, b| flag(a != 0.0 || b != 0.0)),
+            Instr::AndNot => binop!(stack, |a, b| flag(a != 0.0 && b == 0.0)),
+            Instr::Not => {
+                let v = stack.last_mut().expect(TINVOTS);
+                *v = flag(*v == 0.0);
+            }
+            Instr::AddTo(slot) => locals[slot] += stack.pop().expect(TINVOTS),
+            Instr::MulTo(slot) => locals[slot] *= stack.pop().expect(TINVOTS),
+            Instr::Inc(slot) => locals[slot] += 1.0,
+            Instr::Jump(target) => {
+                pc = target;
+                continue;
+            }
+            Instr::JumpIfFalse(target) => {
+                if stack.pop().expect(TINVOTS) == 0.0 {
+                    pc = target;
+                    continue;
+                }
+            }
+            Instr::JumpIfNotLess(a, b, target) => {
+                if locals[a].partial_cmp(&locals[b]) != Some(cmp::Ordering::Less) {
+                    pc = target;
+                    continue;
+                }
+            }
+            Instr::Loop(target, range) => {
+                rt.step(range)?;
+                pc = target;
+                continue;
+            }
+            Instr::Call(index, range) => {
+                let f = &module.functions[index];
+                let callee = module.compiled[index]
+                    .as_ref()
+                    .expect("Expected compiled function");
+                let n = stack.len() - callee.args.len();
+                let mut callee_locals = vec![0.0; callee.locals];
+                callee_locals[..callee.args.len()].copy_from_slice(&stack[n..]);
+                stack.truncate(n);
+                if f.senders.0.load(Ordering::Relaxed) {
+                    let msg = callee
+                        .args
+                        .iter()
+                        .zip(&callee_locals)
+                        .map(|(ty, &v)| ty.variable(v))
+                        .collect();
+                    send_args(f, msg);
+                }
+                let (st, lc, cu) = (rt.stack.len(), rt.local_stack.len(), rt.current_stack.len());
+                rt.push_fn(
+                    f.name.clone(),
+                    index,
+                    (Some(f.file.clone()), f.source.clone()),
+                    Some(range),
+                    (st, lc, cu),
+                )?;
+                let v = run(rt, module, callee, callee_locals)?;
+                rt.pop_fn(f.name.clone());
+                if callee.ret.is_some() {
+                    stack.push(v);
+                }
+            }
+            Instr::CallExt(i) => {
+                let ext = &chunk.ext[i];
+                let n = stack.len() - ext.args.len();
+                for &v in &stack[n..] {
+                    rt.stack.push(Variable::f64(v));
+                }
+                stack.truncate(n);
+                let res = rt
+                    .call_intrinsic(&ext.name, ext.source_range, ext.fun.0)
+                    .map_err(|err| {
+                        let range = if let Some(ind) = rt.arg_err_index.get() {
+                            rt.arg_err_index.set(None);
+                            ext.args[ind]
+                        } else {
+                            ext.source_range
+                        };
+                        rt.intrinsic_err(range, err)
+                    })?;
+                let v = match (rt.resolve(&res), ext.ret) {
+                    (&Variable::F64(v, _), Ty::F64) => v,
+                    (&Variable::Bool(b, _), Ty::Bool) => flag(b),
+                    (x, ty) => {
+                        let name = if ty == Ty::F64 { "number" } else { "bool" };
+                        return Err(rt.intrinsic_err(ext.source_range, rt.expected(x, name)));
+                    }
+                };
+                stack.push(v);
+            }
+            Instr::Return => {
+                return Ok(if chunk.ret.is_some() {
+                    stack.pop().expect(TINVOTS)
+                } else {
+                    0.0
+                });
+            }
+        }
+        pc += 1;
+    }
+}
@@ -0,0 +1,533 @@
+//! C API for embedding Dyon in non-Rust hosts.
+//!
+//! Requires the `capi` feature.
+//! The declarations are in `include/dyon.h`, which is generated from this file
+//! by cbindgen (configured in `cbindgen.toml`) into `OUT_DIR` when building with the feature.
+//! The library can be built with:
+//!
+//! ```text
+//! cargo rustc --release --features capi --crate-type cdylib
+//! ```
+//!
+//! Modules, runtimes and variables are opaque pointers owned by the caller,
+//! which must be released with the matching `_free` function.
+//! Functions that can fail return `-1` or a null pointer,
+//! and the error message is retrieved with `dyon_last_error`.
+//! Panics are caught before they unwind into C and reported the same way.
+//!
+//! All pointer arguments must be valid and strings must be nul-terminated UTF-8.
+
+#![allow(clippy::missing_safety_doc)]
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::sync::Arc;
+
+use embed::ConvertVec4;
+use Module;
+use Runtime;
+use Variable;
+
+/// Opaque module handle.
+pub struct DyonModule(Arc<Module>);
+
+/// Opaque runtime handle.
+pub struct DyonRuntime(Runtime);
+
+/// Opaque variable handle.
+pub struct DyonVariable(Variable);
+
+/// The type of a variable, as returned by `dyon_var_type`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DyonType {
+    /// Any type not listed below.
+    Other = 0,
+    /// `bool`.
+    Bool = 1,
+    /// `f64`.
+    F64 = 2,
+    /// `vec4`.
+    Vec4 = 3,
+    /// `mat4`.
+    Mat4 = 4,
+    /// `str`.
+    Str = 5,
+    /// `[]`.
+    Array = 6,
+    /// `{}`.
+    Object = 7,
+    /// `opt`.
+    Option = 8,
+    /// `res`.
+    Result = 9,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_error(err: String) {
+    let err = CString::new(err.replace('\0', "\\0")).unwrap();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(err));
+}
+
+unsafe fn to_str<'a>(s: *const c_char) -> Result<&'a str, String> {
+    if s.is_null() {
+        return Err("Expected string, found null pointer".into());
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|err| format!("Expected UTF-8 string:\n{}", err))
+}
+
+/// Calls a function, catching any panic such that it does not unwind into C.
+///
+/// Returns `fail` and sets the last error if the function panics.
+fn guard<T, F: FnOnce() -> T>(fail: T, f: F) -> T {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(val) => val,
+        Err(payload) => {
+            let msg = if let Some(msg) = payload.downcast_ref::<&str>() {
+                msg.to_string()
+            } else if let Some(msg) = payload.downcast_ref::<String>() {
+                msg.clone()
+            } else {
+                "Unknown panic".into()
+            };
+            set_error(format!("Dyon panicked:\n{}", msg));
+            fail
+        }
+    }
+}
+
+fn new_var(var: Variable) -> *mut DyonVariable {
+    Box::into_raw(Box::new(DyonVariable(var)))
+}
+
+/// Returns the last error message on this thread, or null if there is none.
+///
+/// The string is valid until the next call that fails on this thread.
+#[no_mangle]
+pub extern "C" fn dyon_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| match *last.borrow() {
+        Some(ref err) => err.as_ptr(),
+        None => ptr::null(),
+    })
+}
+
+/// Clears the last error message on this thread.
+#[no_mangle]
+pub extern "C" fn dyon_clear_error() {
+    LAST_ERROR.with(|last| *last.borrow_mut() = None);
+}
+
+/// Creates a new module with the standard external functions.
+#[no_mangle]
+pub extern "C" fn dyon_module_new() -> *mut DyonModule {
+    guard(ptr::null_mut(), || {
+        Box::into_raw(Box::new(DyonModule(Arc::new(Module::new()))))
+    })
+}
+
+/// Frees a module.
+#[no_mangle]
+pub unsafe extern "C" fn dyon_module_free(module: *mut DyonModule) {
+    guard((), || {
+        if !module.is_null() {
+            drop(Box::from_raw(module));
+        }
+    })
+}
+
+/// Loads source into a module, using `name` as file name in error messages.
+///
+/// Returns `0` on success and `-1` on failure.
+#[no_mangle]
+pub unsafe extern "C" fn dyon_load_str(
+    module: *mut DyonModule,
+    name: *const c_char,
+    source: *const c_char,
+) -> c_int {
+    guard(-1, || {
+        let res = (|| {
+            let name = to_str(name)?;
+            let source = to_str(source)?;
+            let module = Arc::get_mut(&mut (*module).0)
+                .ok_or_else(|| "Can not load into a module that is in use".to_string())?;
+            ::load_str(name, Arc::new(source.into()), module)
+        })();
+        match res {
+            Ok(()) => 0,
+            Err(err) => {
+                set_error(err);
+                -1
+            }
+        }
+    })
+}
+
+/// Creates a new runtime.
+#[no_mangle]
+pub extern "C" fn dyon_runtime_new() -> *mut DyonRuntime {
+    guard(ptr::null_mut(), || {
+        Box::into_raw(Box::new(DyonRuntime(Runtime::new())))
+    })
+}
+
+/// Frees a runtime.
+#[no_mangle]
+pub unsafe extern "C" fn dyon_runtime_free(runtime: *mut DyonRuntime) {
+    guard((), || {
+        if !runtime.is_null() {
+            drop(Box::from_raw(runtime));
+        }
+    })
+}
+
+/// Runs the `main` function of a module.
+///
+/// Returns `0` on success and `-1` on failure.
+#[no_mangle]
+pub unsafe extern "C" fn dyon_runtime_run(
+    runtime: *mut DyonRuntime,
+    module: *const DyonModule,
+) -> c_int {
+    guard(-1, || match (*runtime).0.run(&(*module).0) {
+        Ok(()) => 0,
+        Err(err) => {
+            set_error(err.into());
+            -1
+        }
+    })
+}
+
+unsafe fn args(args: *const *const DyonVariable, n: usize) -> Vec<Variable> {
+    (0..n).map(|i| (**args.add(i)).0.clone()).collect()
+}
+
+/// Calls a function without a return value.
+///
+/// `args` points to `n` variables, which are cloned.
+/// Returns `0` on success and `-1` on failure.
+#[no_mangle]
+pub unsafe extern "C" fn dyon_runtime_call(
+    runtime: *mut DyonRuntime,
+    module: *const DyonModule,
+    function: *const c_char,
+    args_ptr: *const *const DyonVariable,
+    n: usize,
+) -> c_int {
+    guard(-1, || {
+        let res = to_str(function).and_then(|function| {
+            (*runtime)
+                .0
+                .call_str(function, &args(args_ptr, n), &(*module).0)
+                .map_err(String::from)
+        });
+        match res {
+            Ok(()) => 0,
+            Err(err) => {
+                set_error(err);
+                -1
+            }
+        }
+    })
+}
+
+/// Calls a function with a return value.
+///
+/// `args` points to `n` variables, which are cloned.
+/// Returns null on failure.
+#[no_mangle]
+pub unsafe extern "C" fn dyon_runtime_call_ret(
+    runtime: *mut DyonRuntime,
+    module: *const DyonModule,
+    function: *const c_char,
+    args_ptr: *const *const DyonVariable,
+    n: usize,
+) -> *mut DyonVariable {
+    guard(ptr::null_mut(), || {
+        let rt = &mut (*runtime).0;
+        let res = to_str(function).and_then(|function| {
+            rt.call_str_ret(function, &args(args_ptr, n), &(*module).0)
+                .map_err(String::from)
+        });
+        match res {
+            Ok(val) => new_var(rt.resolve(&val).deep_clone(&rt.stack)),
+            Err(err) => {
+                set_error(err);
+                ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Frees a variable.
+#[no_mangle]
+pub unsafe extern "C" fn dyon_var_free(var: *mut DyonVariable) {
+    guard((), || {
+        if !var.is_null() {
+            drop(Box::from_raw(var));
+        }
+    })
+}
+
+/// Creates a `f64` variable.
+#[no_mangle]
+pub extern "C" fn dyon_var_f64(val: f64) -> *mut DyonVariable {
+    guard(ptr::null_mut(), || new_var(Variable::f64(val)))
+}
+
+/// Creates a `bool` variable.
+#[no_mangle]
+pub extern "C" fn dyon_var_bool(val: bool) -> *mut DyonVariable {
+    guard(ptr::null_mut(), || new_var(Variable::bool(val)))
+}
+
+/// Creates a `vec4` variable.
+#[no_mangle]
+pub extern "C" fn dyon_var_vec4(x: f32, y: f32, z: f32, w: f32) -> *mut DyonVariable {
+    guard(ptr::null_mut(), || {
+        new_var(Variable::Vec4([x, y, z, w].to()))
+    })
+}
+
+/// Creates a `str` variable. Returns null if the string is not UTF-8.
+#[no_mangle]
+pub unsafe extern "C" fn dyon_var_str(val: *const c_char) -> *mut DyonVariable {
+    guard(ptr::null_mut(), || match to_str(val) {
+        Ok(val) => new_var(Variable::Str(Arc::new(val.into()))),
+        Err(err) => {
+            set_error(err);
+            ptr::null_mut()
+        }
+    })
+}
+
+/// Creates an empty array.
+#[no_mangle]
+pub extern "C" fn dyon_var_array() -> *mut DyonVariable {
+    guard(ptr::null_mut(), || new_var(Variable::Array(Arc::default())))
+}
+
+/// Creates an empty object.
+#[no_mangle]
+pub extern "C" fn dyon_var_object() -> *mut DyonVariable {
+    guard(ptr::null_mut(), || {
+        new_var(Variable::Object(Arc::default()))
+    })
+}
+
+/// Pushes a clone of an item to an array.
+///
+/// Returns `0` on success and `-1` if the variable is not an array.
+#[no_mangle]
+pub unsafe extern "C" fn dyon_var_array_push(
+    arr: *mut DyonVariable,
+    item: *const DyonVariable,
+) -> c_int {
+    guard(-1, || match (*arr).0 {
+        Variable::Array(ref mut arr) => {
+            Arc::make_mut(arr).push((*item).0.clone());
+            0
+        }
+        ref x => {
+            set_error(format!("Expected `[]`, found `{}`", x.typeof_var()));
+            -1
+        }
+    })
+}
+
+/// Sets a key of an object to a clone of a value.
+///
+/// Returns `0` on success and `-1` if the variable is not an object.
+#[no_mangle]
+pub unsafe extern "C" fn dyon_var_object_insert(
+    obj: *mut DyonVariable,
+    key: *const c_char,
+    val: *const DyonVariable,
+) -> c_int {
+    guard(-1, || {
+        let key = match to_str(key) {
+            Ok(key) => key,
+            Err(err) => {
+                set_error(err);
+                return -1;
+            }
+        };
+        match (*obj).0 {
+            Variable::Object(ref mut obj) => {
+                Arc::make_mut(obj).insert(Arc::new(key.into()), (*val).0.clone());
+                0
+            }
+            ref x => {
+                set_error(format!("Expected `{{}}`, found `{}`", x.typeof_var()));
+                -1
+            }
+        }
+    })
+}
+
+/// Returns the type of a variable.
+#[no_mangle]
+pub unsafe extern "C" fn dyon_var_type(var: *const DyonVariable) -> DyonType {
+    guard(DyonType::Other, || match (*var).0 {
+        Variable::Bool(_, _) => DyonType::Bool,
+        Variable::F64(_, _) => DyonType::F64,
+        Variable::Vec4(_) => DyonType::Vec4,
+        Variable::Mat4(_) => DyonType::Mat4,
+        Variable::Str(_) => DyonType::Str,
+        Variable::Array(_) => DyonType::Array,
+        Variable::Object(_) => DyonType::Object,
+        Variable::Option(_) => DyonType::Option,
+        Variable::Result(_) => DyonType::Result,
+        _ => DyonType::Other,
+    })
+}
+
+/// Reads a `f64` variable.
+///
+/// Returns `0` on success and `-1` if the variable has another type.
+#[no_mangle]
+pub unsafe extern "C" fn dyon_var_as_f64(var: *const DyonVariable, out: *mut f64) -> c_int {
+    guard(-1, || match (*var).0 {
+        Variable::F64(val, _) => {
+            *out = val;
+            0
+        }
+        ref x => {
+            set_error(format!("Expected `f64`, found `{}`", x.typeof_var()));
+            -1
+        }
+    })
+}
+
+/// Reads a `bool` variable.
+///
+/// Returns `0` on success and `-1` if the variable has another type.
+#[no_mangle]
+pub unsafe extern "C" fn dyon_var_as_bool(var: *const DyonVariable, out: *mut bool) -> c_int {
+    guard(-1, || match (*var).0 {
+        Variable::Bool(val, _) => {
+            *out = val;
+            0
+        }
+        ref x => {
+            set_error(format!("Expected `bool`, found `{}`", x.typeof_var()));
+            -1
+        }
+    })
+}
+
+/// Reads a `vec4` variable into an array of 4 floats.
+///
+/// Returns `0` on success and `-1` if the variable has another type.
+#[no_mangle]
+pub unsafe extern "C" fn dyon_var_as_vec4(var: *const DyonVariable, out: *mut f32) -> c_int {
+    guard(-1, || match (*var).0 {
+        Variable::Vec4(val) => {
+            let val: [f32; 4] = ConvertVec4::from(val);
+            ptr::copy_nonoverlapping(val.as_ptr(), out, 4);
+            0
+        }
+        ref x => {
+            set_error(format!("Expected `vec4`, found `{}`", x.typeof_var()));
+            -1
+        }
+    })
+}
+
+/// Returns a copy of a `str` variable, which must be freed with `dyon_str_free`.
+///
+/// Returns null if the variable has another type.
+#[no_mangle]
+pub unsafe extern "C" fn dyon_var_as_str(var: *const DyonVariable) -> *mut c_char {
+    guard(ptr::null_mut(), || match (*var).0 {
+        Variable::Str(ref val) => CString::new(val.replace('\0', "\\0")).unwrap().into_raw(),
+        ref x => {
+            set_error(format!("Expected `str`, found `{}`", x.typeof_var()));
+            ptr::null_mut()
+        }
+    })
+}
+
+/// Frees a string returned by `dyon_var_as_str`.
+#[no_mangle]
+pub unsafe extern "C" fn dyon_str_free(s: *mut c_char) {
+    guard((), || {
+        if !s.is_null() {
+            drop(CString::from_raw(s));
+        }
+    })
+}
+
+/// Returns the length of an array, or `-1` if the variable is not an array.
+#[no_mangle]
+pub unsafe extern "C" fn dyon_var_array_len(arr: *const DyonVariable) -> isize {
+    guard(-1, || match (*arr).0 {
+        Variable::Array(ref arr) => arr.len() as isize,
+        ref x => {
+            set_error(format!("Expected `[]`, found `{}`", x.typeof_var()));
+            -1
+        }
+    })
+}
+
+/// Returns a copy of an array item.
+///
+/// Returns null if the variable is not an array or the index is out of bounds.
+#[no_mangle]
+pub unsafe extern "C" fn dyon_var_array_get(
+    arr: *const DyonVariable,
+    index: usize,
+) -> *mut DyonVariable {
+    guard(ptr::null_mut(), || match (*arr).0 {
+        Variable::Array(ref arr) => match arr.get(index) {
+            Some(item) => new_var(item.clone()),
+            None => {
+                set_error(format!("Index {} is out of bounds", index));
+                ptr::null_mut()
+            }
+        },
+        ref x => {
+            set_error(format!("Expected `[]`, found `{}`", x.typeof_var()));
+            ptr::null_mut()
+        }
+    })
+}
+
+/// Returns a copy of an object field.
+///
+/// Returns null if the variable is not an object or the key does not exist.
+#[no_mangle]
+pub unsafe extern "C" fn dyon_var_object_get(
+    obj: *const DyonVariable,
+    key: *const c_char,
+) -> *mut DyonVariable {
+    guard(ptr::null_mut(), || {
+        let key = match to_str(key) {
+            Ok(key) => key,
+            Err(err) => {
+                set_error(err);
+                return ptr::null_mut();
+            }
+        };
+        match (*obj).0 {
+            Variable::Object(ref obj) => match obj.get(&Arc::new(key.to_string())) {
+                Some(val) => new_var(val.clone()),
+                None => {
+                    set_error(format!("Object has no key `{}`", key));
+                    ptr::null_mut()
+                }
+            },
+            ref x => {
+                set_error(format!("Expected `{{}}`, found `{}`", x.typeof_var()));
+                ptr::null_mut()
+            }
+        }
+    })
+}
@@ -0,0 +1,101 @@
+//! Actors that handle messages one at a time on their own thread.
+//!
+//! An actor has a state and a handler closure `\(state, msg) -> [state, reply]`.
+//! The reply is returned by `ask`, and ignored for messages from `send`.
+//! When the handler fails, the actor is restarted with the initial state.
+
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+
+use Runtime;
+use Variable;
+
+enum Mail {
+    Send(Variable),
+    /// Asks for the reply of the handler.
+    Ask(Variable, Sender<Result<Variable, String>>),
+}
+
+/// The mailbox of an actor, stored in a Rust object.
+///
+/// The actor stops when all references to the mailbox are dropped.
+#[derive(Clone)]
+pub(crate) struct Actor {
+    mailbox: Sender<Mail>,
+}
+
+/// Starts an actor with a handler closure.
+///
+/// The state and the closure must be deep cloned, such that they do not
+/// refer to values on the stack of the caller.
+pub(crate) fn spawn(rt: &Runtime, state: Variable, handler: Variable) -> Actor {
+    let (tx, rx) = channel();
+    let new_rt = rt.thread_runtime(vec![]);
+    thread::spawn(move || run(new_rt, state, handler, rx));
+    Actor { mailbox: tx }
+}
+
+impl Actor {
+    /// Sends a message without waiting for it to be handled.
+    ///
+    /// Returns `false` when the actor is stopped.
+    pub(crate) fn send(&self, msg: Variable) -> bool {
+        self.mailbox.send(Mail::Send(msg)).is_ok()
+    }
+
+    /// Sends a message and waits for the reply of the handler.
+    pub(crate) fn ask(&self, msg: Variable) -> Result<Variable, String> {
+        let (tx, rx) = channel();
+        if self.mailbox.send(Mail::Ask(msg, tx)).is_err() {
+            return Err("Actor is stopped".into());
+        }
+        match rx.recv() {
+            Ok(res) => res,
+            Err(_) => Err("Actor is stopped".into()),
+        }
+    }
+}
+
+fn run(mut rt: Runtime, init: Variable, handler: Variable, mailbox: Receiver<Mail>) {
+    let mut state = init.clone();
+    let mut restarts = 0;
+    for mail in mailbox.iter() {
+        let (msg, reply) = match mail {
+            Mail::Send(msg) => (msg, None),
+            Mail::Ask(msg, reply) => (msg, Some(reply)),
+        };
+        rt.reset_budget();
+        let res = rt
+            .call_closure_ret(&handler, &[state.clone(), msg])
+            .map_err(|err| err.to_string())
+            .and_then(|v| match v.deep_clone(&rt.stack) {
+                Variable::Array(ref arr) if arr.len() == 2 => Ok((arr[0].clone(), arr[1].clone())),
+                x => Err(format!(
+                    "Expected `[state, reply]` from actor handler, found `{}`",
+                    x.typeof_var()
+                )),
+            });
+        let res = match res {
+            Ok((new_state, reply)) => {
+                state = new_state;
+                Ok(reply)
+            }
+            Err(err) => {
+                // Restart with a clean runtime and the initial state.
+                rt.stack.clear();
+                rt.local_stack.clear();
+                rt.current_stack.clear();
+                rt.call_stack.truncate(1);
+                state = init.clone();
+                restarts += 1;
+                Err(format!(
+                    "{}\nActor restarted with initial state ({} restarts)",
+                    err, restarts
+                ))
+            }
+        };
+        if let Some(reply) = reply {
+            let _ = reply.send(res);
+        }
+    }
+}
@@ -0,0 +1,301 @@
+//! Arbitrary precision integers.
+//!
+//! A big integer is stored as a Rust object, such that the operators
+//! `+ - * / %` and comparisons work on it, mixed with integer numbers.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use Variable;
+
+/// Powers of 10 that fit in a digit, used to convert from and to text.
+const DEC_BASE: u32 = 1_000_000_000;
+const DEC_DIGITS: usize = 9;
+
+/// An integer of any size.
+///
+/// The magnitude is stored as base `2^32` digits, least significant first,
+/// without trailing zeros, such that each number has one representation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct BigInt {
+    neg: bool,
+    mag: Vec<u32>,
+}
+
+impl BigInt {
+    fn new(neg: bool, mut mag: Vec<u32>) -> BigInt {
+        while mag.last() == Some(&0) {
+            mag.pop();
+        }
+        BigInt {
+            neg: neg && !mag.is_empty(),
+            mag,
+        }
+    }
+
+    /// Parses an integer in decimal notation, with an optional sign.
+    pub(crate) fn parse(text: &str) -> Option<BigInt> {
+        let (neg, digits) = match text.as_bytes().first() {
+            Some(b'-') => (true, &text[1..]),
+            Some(b'+') => (false, &text[1..]),
+            _ => (false, text),
+        };
+        if digits.is_empty() || !digits.bytes().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let mut mag = vec![];
+        // Handle the shortest chunk first, so the rest is whole chunks.
+        let first = match digits.len() % DEC_DIGITS {
+            0 => DEC_DIGITS,
+            n => n,
+        };
+        let mut start = 0;
+        let mut end = first;
+        while start < digits.len() {
+            let chunk: u32 = digits[start..end].parse().ok()?;
+            let scale = 10u32.pow((end - start) as u32);
+            mul_small_add(&mut mag, scale, chunk);
+            start = end;
+            end += DEC_DIGITS;
+        }
+        Some(BigInt::new(neg, mag))
+    }
+
+    /// Converts from a number, which must be an integer.
+    pub(crate) fn from_f64(x: f64) -> Option<BigInt> {
+        if !x.is_finite() || x.fract() != 0.0 {
+            return None;
+        }
+        // Split into a 53 bit mantissa and a binary exponent.
+        let bits = x.abs().to_bits();
+        let exp = ((bits >> 52) & 0x7ff) as i64;
+        if exp == 0 {
+            return Some(BigInt::new(false, vec![]));
+        }
+        let mantissa = (bits & ((1 << 52) - 1)) | (1 << 52);
+        let shift = exp - 1075;
+        let mag = if shift < 0 {
+            let m = mantissa >> -shift;
+            vec![m as u32, (m >> 32) as u32]
+        } else {
+            shl(&[mantissa as u32, (mantissa >> 32) as u32], shift as usize)
+        };
+        Some(BigInt::new(x < 0.0, mag))
+    }
+
+    pub(crate) fn add(&self, other: &BigInt) -> BigInt {
+        if self.neg == other.neg {
+            return BigInt::new(self.neg, add_mag(&self.mag, &other.mag));
+        }
+        match cmp_mag(&self.mag, &other.mag) {
+            Ordering::Less => BigInt::new(other.neg, sub_mag(&other.mag, &self.mag)),
+            _ => BigInt::new(self.neg, sub_mag(&self.mag, &other.mag)),
+        }
+    }
+
+    pub(crate) fn neg(&self) -> BigInt {
+        BigInt::new(!self.neg, self.mag.clone())
+    }
+
+    pub(crate) fn sub(&self, other: &BigInt) -> BigInt {
+        self.add(&other.neg())
+    }
+
+    pub(crate) fn mul(&self, other: &BigInt) -> BigInt {
+        let mut res = vec![0u32; self.mag.len() + other.mag.len()];
+        for (i, &a) in self.mag.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, &b) in other.mag.iter().enumerate() {
+                let x = u64::from(res[i + j]) + u64::from(a) * u64::from(b) + carry;
+                res[i + j] = x as u32;
+                carry = x >> 32;
+            }
+            res[i + other.mag.len()] = carry as u32;
+        }
+        BigInt::new(self.neg != other.neg, res)
+    }
+
+    /// Divides and rounds toward zero, returning the quotient and remainder.
+    ///
+    /// The remainder has the same sign as `self`, like for `f64`.
+    pub(crate) fn div_rem(&self, other: &BigInt) -> Result<(BigInt, BigInt), String> {
+        if other.mag.is_empty() {
+            return Err("Division by zero".into());
+        }
+        let (q, r) = div_rem_mag(&self.mag, &other.mag);
+        Ok((
+            BigInt::new(self.neg != other.neg, q),
+            BigInt::new(self.neg, r),
+        ))
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &BigInt) -> Ordering {
+        match (self.neg, other.neg) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_mag(&self.mag, &other.mag),
+            (true, true) => cmp_mag(&other.mag, &self.mag),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &BigInt) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.mag.is_empty() {
+            return write!(f, "0");
+        }
+        let mut chunks = vec![];
+        let mut mag = self.mag.clone();
+        while !mag.is_empty() {
+            let (q, r) = div_small(&mag, DEC_BASE);
+            chunks.push(r);
+            mag = q;
+        }
+        if self.neg {
+            write!(f, "-")?;
+        }
+        let mut chunks = chunks.iter().rev();
+        if let Some(first) = chunks.next() {
+            write!(f, "{}", first)?;
+        }
+        for chunk in chunks {
+            write!(f, "{:09}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+fn cmp_mag(a: &[u32], b: &[u32]) -> Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (a, b) = if a.len() < b.len() { (b, a) } else { (a, b) };
+    let mut res = Vec::with_capacity(a.len() + 1);
+    let mut carry = 0u64;
+    for (i, &x) in a.iter().enumerate() {
+        let sum = u64::from(x) + u64::from(b.get(i).cloned().unwrap_or(0)) + carry;
+        res.push(sum as u32);
+        carry = sum >> 32;
+    }
+    res.push(carry as u32);
+    res
+}
+
+/// Subtracts magnitudes, where `a >= b`.
+fn sub_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut res = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, &x) in a.iter().enumerate() {
+        let mut diff = i64::from(x) - i64::from(b.get(i).cloned().unwrap_or(0)) - borrow;
+        borrow = 0;
+        if diff < 0 {
+            diff += 1 << 32;
+            borrow = 1;
+        }
+        res.push(diff as u32);
+    }
+    res
+}
+
+/// Computes `a * scale + add` in place.
+fn mul_small_add(a: &mut Vec<u32>, scale: u32, add: u32) {
+    let mut carry = u64::from(add);
+    for x in a.iter_mut() {
+        let v = u64::from(*x) * u64::from(scale) + carry;
+        *x = v as u32;
+        carry = v >> 32;
+    }
+    if carry > 0 {
+        a.push(carry as u32);
+    }
+}
+
+/// Divides by a single digit, returning the quotient and remainder.
+fn div_small(a: &[u32], d: u32) -> (Vec<u32>, u32) {
+    let mut q = vec![0; a.len()];
+    let mut r = 0u64;
+    for (i, &x) in a.iter().enumerate().rev() {
+        let v = (r << 32) | u64::from(x);
+        q[i] = (v / u64::from(d)) as u32;
+        r = v % u64::from(d);
+    }
+    while q.last() == Some(&0) {
+        q.pop();
+    }
+    (q, r as u32)
+}
+
+/// Shifts a magnitude left by a number of bits.
+fn shl(a: &[u32], bits: usize) -> Vec<u32> {
+    let (words, bits) = (bits / 32, bits % 32);
+    let mut res = vec![0; words];
+    let mut carry = 0;
+    for &x in a {
+        res.push((x << bits) | carry);
+        carry = if bits == 0 { 0 } else { x >> (32 - bits) };
+    }
+    res.push(carry);
+    res
+}
+
+fn div_rem_mag(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if b.len() == 1 {
+        let (q, r) = div_small(a, b[0]);
+        return (q, vec![r]);
+    }
+    // Long division, one bit at a time.
+    let mut q = vec![0u32; a.len()];
+    let mut r: Vec<u32> = vec![];
+    for i in (0..a.len() * 32).rev() {
+        r = shl(&r, 1);
+        r[0] |= (a[i / 32] >> (i % 32)) & 1;
+        while r.last() == Some(&0) {
+            r.pop();
+        }
+        if cmp_mag(&r, b) != Ordering::Less {
+            r = sub_mag(&r, b);
+            while r.last() == Some(&0) {
+                r.pop();
+            }
+            q[i / 32] |= 1 << (i % 32);
+        }
+    }
+    (q, r)
+}
+
+/// Wraps a big integer as a variable.
+pub(crate) fn var(x: BigInt) -> Variable {
+    Variable::RustObject(Arc::new(Mutex::new(x)))
+}
+
+/// Reads a big integer, or an integer number, from an operand.
+pub(crate) fn operand(v: &Variable) -> Option<BigInt> {
+    match *v {
+        Variable::F64(x, _) => BigInt::from_f64(x),
+        Variable::RustObject(ref obj) => obj.lock().unwrap().downcast_ref::<BigInt>().cloned(),
+        _ => None,
+    }
+}
+
+/// Applies a binary operator where one side is a big integer.
+pub(crate) fn binop<F>(a: &Variable, b: &Variable, f: F) -> Result<Variable, String>
+where
+    F: FnOnce(BigInt, BigInt) -> Result<Variable, String>,
+{
+    match (operand(a), operand(b)) {
+        (Some(a), Some(b)) => f(a, b),
+        _ => Err("Expected `bigint` or integer `f64`".into()),
+    }
+}
@@ -0,0 +1,62 @@
+//! Operations on whole arrays of 4D vectors.
+//!
+//! The inner loops work on all 4 components at once with no branches,
+//! such that the compiler turns them into SIMD instructions.
+
+use Scalar;
+
+type V4 = [Scalar; 4];
+
+pub(crate) fn add_all(a: &[V4], b: &[V4]) -> Vec<V4> {
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| {
+            let mut r = [0.0; 4];
+            for ((r, a), b) in r.iter_mut().zip(a).zip(b) {
+                *r = a + b;
+            }
+            r
+        })
+        .collect()
+}
+
+pub(crate) fn scale_all(a: &[V4], s: Scalar) -> Vec<V4> {
+    a.iter()
+        .map(|a| {
+            let mut r = [0.0; 4];
+            for (r, a) in r.iter_mut().zip(a) {
+                *r = a * s;
+            }
+            r
+        })
+        .collect()
+}
+
+pub(crate) fn dot_all(a: &[V4], b: &[V4]) -> Vec<Scalar> {
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| {
+            let mut r = [0.0; 4];
+            for ((r, a), b) in r.iter_mut().zip(a).zip(b) {
+                *r = a * b;
+            }
+            (r[0] + r[1]) + (r[2] + r[3])
+        })
+        .collect()
+}
+
+/// Transforms points by a column major matrix, same as `m * p`.
+pub(crate) fn transform_all(m: &[V4; 4], points: &[V4]) -> Vec<V4> {
+    points
+        .iter()
+        .map(|p| {
+            let mut r = [0.0; 4];
+            for (col, &x) in m.iter().zip(p) {
+                for (r, c) in r.iter_mut().zip(col) {
+                    *r += c * x;
+                }
+            }
+            r
+        })
+        .collect()
+}
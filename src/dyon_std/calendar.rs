@@ -0,0 +1,235 @@
+//! Conversion between Unix timestamps and UTC dates.
+//!
+//! Uses the proleptic Gregorian calendar, without leap seconds.
+
+use std::fmt::Write;
+
+/// The largest number of seconds from Unix Epoch that converts to a date,
+/// which is one hundred billion days.
+const MAX_TIMESTAMP: f64 = 8.64e15;
+
+/// A UTC date and time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct DateTime {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub min: u32,
+    /// Seconds with fraction.
+    pub sec: f64,
+}
+
+/// Returns the number of days since 1970-01-01.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let m = i64::from(month);
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Returns the year, month and day of days since 1970-01-01,
+/// or `None` if the year does not fit in `i64`.
+fn civil_from_days(days: i64) -> Option<(i64, u32, u32)> {
+    let z = days.checked_add(719_468)?;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = era
+        .checked_mul(400)?
+        .checked_add(yoe + if month <= 2 { 1 } else { 0 })?;
+    Some((year, month, day))
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl DateTime {
+    /// Converts from seconds since Unix Epoch.
+    pub fn from_timestamp(ts: f64) -> Result<DateTime, String> {
+        if !ts.is_finite() || ts.abs() > MAX_TIMESTAMP {
+            return Err(format!("Timestamp `{}` is out of range", ts));
+        }
+        let days = (ts / 86_400.0).floor();
+        let secs = ts - days * 86_400.0;
+        let (year, month, day) = civil_from_days(days as i64)
+            .ok_or_else(|| format!("Timestamp `{}` is out of range", ts))?;
+        let hour = (secs / 3600.0).floor().min(23.0);
+        let min = ((secs - hour * 3600.0) / 60.0).floor().min(59.0);
+        Ok(DateTime {
+            year,
+            month,
+            day,
+            hour: hour as u32,
+            min: min as u32,
+            sec: secs - hour * 3600.0 - min * 60.0,
+        })
+    }
+
+    /// Converts to seconds since Unix Epoch.
+    pub fn timestamp(&self) -> f64 {
+        let days = days_from_civil(self.year, self.month, self.day);
+        days as f64 * 86_400.0
+            + f64::from(self.hour) * 3600.0
+            + f64::from(self.min) * 60.0
+            + self.sec
+    }
+
+    /// Returns the day of the year, starting at 1.
+    fn day_of_year(&self) -> i64 {
+        days_from_civil(self.year, self.month, self.day) - days_from_civil(self.year, 1, 1) + 1
+    }
+}
+
+/// Formats a date.
+///
+/// Supports `%Y`, `%m`, `%d`, `%H`, `%M`, `%S`, `%f` (milliseconds),
+/// `%j` (day of year), `%F` (`%Y-%m-%d`), `%T` (`%H:%M:%S`) and `%%`.
+pub(crate) fn format(dt: &DateTime, fmt: &str) -> Result<String, String> {
+    let mut res = String::new();
+    let mut chars = fmt.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '%' {
+            res.push(ch);
+            continue;
+        }
+        let _ = match chars.next() {
+            Some('Y') => write!(res, "{:04}", dt.year),
+            Some('m') => write!(res, "{:02}", dt.month),
+            Some('d') => write!(res, "{:02}", dt.day),
+            Some('H') => write!(res, "{:02}", dt.hour),
+            Some('M') => write!(res, "{:02}", dt.min),
+            Some('S') => write!(res, "{:02}", dt.sec.floor() as u32),
+            Some('f') => write!(res, "{:03}", ((dt.sec.fract() * 1000.0) as u32).min(999)),
+            Some('j') => write!(res, "{:03}", dt.day_of_year()),
+            Some('F') => write!(res, "{:04}-{:02}-{:02}", dt.year, dt.month, dt.day),
+            Some('T') => write!(
+                res,
+                "{:02}:{:02}:{:02}",
+                dt.hour,
+                dt.min,
+                dt.sec.floor() as u32
+            ),
+            Some('%') => write!(res, "%"),
+            Some(x) => return Err(format!("Unknown format specifier `%{}`", x)),
+            None => return Err("Expected format specifier after `%`".into()),
+        };
+    }
+    Ok(res)
+}
+
+/// Reads up to `max` digits, returning the number and digit count.
+fn digits(text: &[char], pos: &mut usize, max: usize) -> Option<(i64, usize)> {
+    let start = *pos;
+    let mut n: i64 = 0;
+    while *pos < text.len() && *pos - start < max {
+        match text[*pos].to_digit(10) {
+            Some(d) => n = n * 10 + i64::from(d),
+            None => break,
+        }
+        *pos += 1;
+    }
+    if *pos == start {
+        None
+    } else {
+        Some((n, *pos - start))
+    }
+}
+
+/// Parses a timestamp, using the same format specifiers as `format`.
+///
+/// Fields that are not in the format default to 1970-01-01 00:00:00.
+pub(crate) fn parse(text: &str, fmt: &str) -> Result<f64, String> {
+    let text: Vec<char> = text.chars().collect();
+    let mut pos = 0;
+    let mut dt = DateTime {
+        year: 1970,
+        month: 1,
+        day: 1,
+        hour: 0,
+        min: 0,
+        sec: 0.0,
+    };
+    let mut millis = 0.0;
+    let expand = fmt.replace("%F", "%Y-%m-%d").replace("%T", "%H:%M:%S");
+    let mut chars = expand.chars();
+    while let Some(ch) = chars.next() {
+        let spec = if ch == '%' {
+            match chars.next() {
+                Some('%') => None,
+                Some(x) => Some(x),
+                None => return Err("Expected format specifier after `%`".into()),
+            }
+        } else {
+            None
+        };
+        let spec = match spec {
+            Some(spec) => spec,
+            None => {
+                if pos < text.len() && text[pos] == ch {
+                    pos += 1;
+                    continue;
+                }
+                return Err(format!("Expected `{}` at position {}", ch, pos));
+            }
+        };
+        let max = match spec {
+            'Y' => 4,
+            'j' | 'f' => 3,
+            'm' | 'd' | 'H' | 'M' | 'S' => 2,
+            x => return Err(format!("Unknown format specifier `%{}`", x)),
+        };
+        let (n, len) = digits(&text, &mut pos, max)
+            .ok_or_else(|| format!("Expected number for `%{}` at position {}", spec, pos))?;
+        match spec {
+            'Y' => dt.year = n,
+            'm' => dt.month = n as u32,
+            'd' => dt.day = n as u32,
+            'H' => dt.hour = n as u32,
+            'M' => dt.min = n as u32,
+            'S' => dt.sec = n as f64,
+            'f' => millis = n as f64 / 10f64.powi(len as i32),
+            _ => {
+                // Day of year, counted from January 1st.
+                if n < 1 || n > if is_leap_year(dt.year) { 366 } else { 365 } {
+                    return Err(format!("Day of year `{}` is out of range", n));
+                }
+                let (_, month, day) = civil_from_days(days_from_civil(dt.year, 1, 1) + n - 1)
+                    .ok_or_else(|| format!("Day of year `{}` is out of range", n))?;
+                dt.month = month;
+                dt.day = day;
+            }
+        }
+    }
+    if pos < text.len() {
+        return Err(format!("Unexpected text at position {}", pos));
+    }
+    if dt.month < 1 || dt.month > 12 {
+        return Err(format!("Month `{}` is out of range", dt.month));
+    }
+    if dt.day < 1 || dt.day > days_in_month(dt.year, dt.month) {
+        return Err(format!("Day `{}` is out of range", dt.day));
+    }
+    if dt.hour > 23 || dt.min > 59 || dt.sec > 59.0 {
+        return Err("Time is out of range".into());
+    }
+    dt.sec += millis;
+    Ok(dt.timestamp())
+}
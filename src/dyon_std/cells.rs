@@ -0,0 +1,143 @@
+//! A spreadsheet-like graph of cells that recompute lazily.
+//!
+//! A cell is a number, a formula or a closure.
+//! Dependencies are recorded while a cell is computed,
+//! such that changing a cell only invalidates the cells that read it.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::formula::Expr;
+use Runtime;
+use RuntimeError;
+use RustObject;
+use Variable;
+
+/// How the value of a cell is computed.
+#[derive(Clone)]
+pub(crate) enum Formula {
+    Value(f64),
+    Expr(Expr),
+    Closure(Variable),
+}
+
+struct Cell {
+    formula: Formula,
+    /// The cached value, `None` when the cell must be recomputed.
+    value: Option<f64>,
+    /// The cells read when the value was computed.
+    deps: Vec<Arc<String>>,
+}
+
+/// Cells created by `cells`, stored in a Rust object.
+#[derive(Default)]
+pub(crate) struct Cells {
+    cells: HashMap<Arc<String>, Cell>,
+    /// The cells being computed, innermost last.
+    computing: Vec<Arc<String>>,
+}
+
+impl Cells {
+    /// Sets the formula of a cell, invalidating the cells that depend on it.
+    pub(crate) fn set(&mut self, name: Arc<String>, formula: Formula) {
+        self.invalidate(&name);
+        self.cells.insert(
+            name,
+            Cell {
+                formula,
+                value: None,
+                deps: vec![],
+            },
+        );
+    }
+
+    fn invalidate(&mut self, name: &Arc<String>) {
+        let mut stack = vec![name.clone()];
+        while let Some(name) = stack.pop() {
+            if let Some(cell) = self.cells.get_mut(&name) {
+                cell.value = None;
+            }
+            for (other, cell) in &self.cells {
+                if cell.value.is_some() && cell.deps.contains(&name) {
+                    stack.push(other.clone());
+                }
+            }
+        }
+    }
+}
+
+/// Returns the value of a cell, computing it if needed.
+///
+/// The lock is not held while computing,
+/// such that closures can read other cells.
+/// Returns `Ok(Err(msg))` when a cell is unknown or depends on itself,
+/// and `Err(msg)` for runtime errors in closures.
+pub(crate) fn get(
+    rt: &mut Runtime,
+    obj: &RustObject,
+    name: &Arc<String>,
+) -> Result<Result<f64, String>, RuntimeError> {
+    let formula = {
+        let mut guard = obj.lock().unwrap();
+        let cells = guard.downcast_mut::<Cells>().ok_or("Expected cells")?;
+        if let Some(parent) = cells.computing.last().cloned() {
+            if let Some(cell) = cells.cells.get_mut(&parent) {
+                if !cell.deps.contains(name) {
+                    cell.deps.push(name.clone());
+                }
+            }
+        }
+        if cells.computing.contains(name) {
+            let mut cycle: Vec<&str> = cells.computing.iter().map(|s| &***s).collect();
+            cycle.push(name);
+            return Ok(Err(format!("Cycle between cells `{}`", cycle.join(" -> "))));
+        }
+        let cell = match cells.cells.get_mut(name) {
+            Some(cell) => cell,
+            None => return Ok(Err(format!("Unknown cell `{}`", name))),
+        };
+        if let Some(value) = cell.value {
+            return Ok(Ok(value));
+        }
+        cell.deps.clear();
+        let formula = cell.formula.clone();
+        cells.computing.push(name.clone());
+        formula
+    };
+    let mut runtime_err = None;
+    let res = match formula {
+        Formula::Value(x) => Ok(x),
+        Formula::Expr(expr) => expr.eval(&mut |var| match get(rt, obj, &Arc::new(var.into())) {
+            Ok(res) => res,
+            Err(err) => {
+                runtime_err = Some(err);
+                Err(String::new())
+            }
+        }),
+        Formula::Closure(closure) => match rt.call_closure_ret(&closure, &[]) {
+            Ok(v) => match *rt.resolve(&v) {
+                Variable::F64(x, _) => Ok(x),
+                ref x => {
+                    runtime_err = Some(rt.expected(x, "f64"));
+                    Err(String::new())
+                }
+            },
+            Err(err) => {
+                runtime_err = Some(err);
+                Err(String::new())
+            }
+        },
+    };
+    let mut guard = obj.lock().unwrap();
+    let cells = guard.downcast_mut::<Cells>().ok_or("Expected cells")?;
+    cells.computing.pop();
+    if let Some(err) = runtime_err {
+        return Err(err);
+    }
+    if let Ok(value) = res {
+        if let Some(cell) = cells.cells.get_mut(name) {
+            cell.value = Some(value);
+        }
+    }
+    Ok(res)
+}
@@ -0,0 +1,125 @@
+//! Clustering of points with any number of dimensions.
+
+use rand::Rng;
+
+fn dist2(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| (a - b) * (a - b)).sum()
+}
+
+/// Returns the index of the nearest centroid.
+fn nearest(p: &[f64], centroids: &[Vec<f64>]) -> usize {
+    let mut best = (0, f64::INFINITY);
+    for (i, c) in centroids.iter().enumerate() {
+        let d = dist2(p, c);
+        if d < best.1 {
+            best = (i, d);
+        }
+    }
+    best.0
+}
+
+/// Groups points into `k` clusters, returning the centroids and a label per point.
+///
+/// Picks initial centroids with k-means++, then runs Lloyd's algorithm
+/// until the labels stop changing or after `iters` iterations.
+/// A centroid without points stays where it is.
+pub(crate) fn kmeans<R: Rng>(
+    rng: &mut R,
+    points: &[Vec<f64>],
+    k: usize,
+    iters: usize,
+) -> (Vec<Vec<f64>>, Vec<usize>) {
+    let k = k.min(points.len());
+    if k == 0 {
+        return (vec![], vec![0; points.len()]);
+    }
+    let mut centroids = vec![points[rng.gen_range(0, points.len())].clone()];
+    // Pick further centroids with probability proportional to squared distance.
+    while centroids.len() < k {
+        let weights: Vec<f64> = points
+            .iter()
+            .map(|p| dist2(p, &centroids[nearest(p, &centroids)]))
+            .collect();
+        let total: f64 = weights.iter().sum();
+        if total <= 0.0 {
+            // The remaining points are duplicates of centroids.
+            break;
+        }
+        let mut r = rng.gen::<f64>() * total;
+        let mut pick = points.len() - 1;
+        for (i, w) in weights.iter().enumerate() {
+            if r < *w {
+                pick = i;
+                break;
+            }
+            r -= w;
+        }
+        centroids.push(points[pick].clone());
+    }
+
+    let mut labels: Vec<usize> = points.iter().map(|p| nearest(p, &centroids)).collect();
+    for _ in 0..iters {
+        let dim = points[0].len();
+        let mut sums = vec![vec![0.0; dim]; centroids.len()];
+        let mut counts = vec![0; centroids.len()];
+        for (p, &label) in points.iter().zip(&labels) {
+            for (s, x) in sums[label].iter_mut().zip(p) {
+                *s += x;
+            }
+            counts[label] += 1;
+        }
+        for ((c, sum), &n) in centroids.iter_mut().zip(sums).zip(&counts) {
+            if n > 0 {
+                *c = sum.into_iter().map(|s| s / n as f64).collect();
+            }
+        }
+        let next: Vec<usize> = points.iter().map(|p| nearest(p, &centroids)).collect();
+        if next == labels {
+            break;
+        }
+        labels = next;
+    }
+    (centroids, labels)
+}
+
+/// Groups points that are densely packed, returning a label per point.
+///
+/// A point with at least `min_pts` points within `eps`, counting itself,
+/// is a core point, and points reachable from core points form a cluster.
+/// Other points are noise, labeled `None`.
+pub(crate) fn dbscan(points: &[Vec<f64>], eps: f64, min_pts: usize) -> Vec<Option<usize>> {
+    let eps2 = eps * eps;
+    let neighbours = |i: usize| -> Vec<usize> {
+        (0..points.len())
+            .filter(|&j| dist2(&points[i], &points[j]) <= eps2)
+            .collect()
+    };
+    let mut labels: Vec<Option<usize>> = vec![None; points.len()];
+    let mut visited = vec![false; points.len()];
+    let mut cluster = 0;
+    for i in 0..points.len() {
+        if visited[i] {
+            continue;
+        }
+        visited[i] = true;
+        let mut queue = neighbours(i);
+        if queue.len() < min_pts {
+            continue;
+        }
+        labels[i] = Some(cluster);
+        while let Some(j) = queue.pop() {
+            if labels[j].is_none() {
+                labels[j] = Some(cluster);
+            }
+            if !visited[j] {
+                visited[j] = true;
+                let more = neighbours(j);
+                if more.len() >= min_pts {
+                    queue.extend(more);
+                }
+            }
+        }
+        cluster += 1;
+    }
+    labels
+}
@@ -0,0 +1,182 @@
+//! Content hashes of values, for cheap change detection.
+//!
+//! Arrays and objects are hashed as Merkle trees, where each array or object
+//! contributes the hash of its items instead of the items themselves.
+//! The hashes of arrays and objects are cached by address while they are alive,
+//! so hashing a large value again after a small change only rehashes
+//! the arrays and objects on the path to the change.
+//!
+//! A cached entry holds a weak reference, which keeps the address from being reused.
+//! Changing an array or object with a weak reference moves it to a new address,
+//! so a cached hash is never stale.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::{Arc, Weak};
+
+use super::sha256::{hex, Digest as _, Sha256};
+use Variable;
+
+type Digest = [u8; 32];
+
+/// The number of cached hashes per thread, before dropping those not in use.
+const MAX_CACHED: usize = 1 << 16;
+
+struct Cache<T> {
+    entries: HashMap<usize, (Weak<T>, Digest)>,
+}
+
+impl<T> Cache<T> {
+    fn new() -> Cache<T> {
+        Cache {
+            entries: HashMap::new(),
+        }
+    }
+
+    fn get(&self, v: &Arc<T>) -> Option<Digest> {
+        match self.entries.get(&(Arc::as_ptr(v) as *const u8 as usize)) {
+            Some(&(ref weak, digest)) if weak.strong_count() > 0 => Some(digest),
+            _ => None,
+        }
+    }
+
+    fn insert(&mut self, v: &Arc<T>, digest: Digest) {
+        if self.entries.len() >= MAX_CACHED {
+            self.entries
+                .retain(|_, &mut (ref weak, _)| weak.strong_count() > 0);
+            if self.entries.len() >= MAX_CACHED {
+                self.entries.clear();
+            }
+        }
+        self.entries.insert(
+            Arc::as_ptr(v) as *const u8 as usize,
+            (Arc::downgrade(v), digest),
+        );
+    }
+}
+
+thread_local! {
+    static ARRAYS: RefCell<Cache<Vec<Variable>>> = RefCell::new(Cache::new());
+    static OBJECTS: RefCell<Cache<HashMap<Arc<String>, Variable>>> = RefCell::new(Cache::new());
+}
+
+/// Computes the content hash of a value, as lowercase hexadecimal digits.
+///
+/// The value must not contain references to the stack.
+pub(crate) fn content_hash(v: &Variable) -> Result<String, String> {
+    let mut h = Sha256::new();
+    hash_var(&mut h, v)?;
+    Ok(hex(&h.finalize()))
+}
+
+fn hash_var(h: &mut Sha256, v: &Variable) -> Result<(), String> {
+    // Each kind of value starts with a tag, so e.g. `[]` and `{}` differ.
+    match *v {
+        Variable::Bool(b, _) => h.update([b'B', b as u8]),
+        Variable::F64(x, _) => {
+            h.update(b"F");
+            hash_f64(h, x);
+        }
+        Variable::Vec4(v) => {
+            h.update(b"V");
+            for &x in &v {
+                hash_f64(h, f64::from(x));
+            }
+        }
+        Variable::Mat4(ref m) => {
+            h.update(b"M");
+            for col in m.iter() {
+                for &x in col {
+                    hash_f64(h, f64::from(x));
+                }
+            }
+        }
+        Variable::Str(ref s) => {
+            h.update(b"S");
+            hash_bytes(h, s.as_bytes());
+        }
+        Variable::Array(ref arr) => {
+            h.update(b"A");
+            h.update(cached(&ARRAYS, arr, array_digest)?);
+        }
+        Variable::Object(ref obj) => {
+            h.update(b"O");
+            h.update(cached(&OBJECTS, obj, object_digest)?);
+        }
+        Variable::Option(None) => h.update(b"N"),
+        Variable::Option(Some(ref v)) => {
+            h.update(b"Y");
+            hash_var(h, v)?;
+        }
+        Variable::Result(Ok(ref v)) => {
+            h.update(b"K");
+            hash_var(h, v)?;
+        }
+        Variable::Result(Err(ref err)) => {
+            h.update(b"E");
+            hash_var(h, &err.message)?;
+        }
+        Variable::Bytes(ref b) => {
+            h.update(b"D");
+            hash_bytes(h, b);
+        }
+        Variable::F64Array(ref arr) => {
+            h.update(b"P");
+            h.update((arr.len() as u64).to_le_bytes());
+            for &x in arr.iter() {
+                hash_f64(h, x);
+            }
+        }
+        _ => return Err(format!("Can not hash `{}`", v.typeof_var())),
+    }
+    Ok(())
+}
+
+fn hash_f64(h: &mut Sha256, x: f64) {
+    // Make `-0` and `0` equal, since they compare equal.
+    h.update((x + 0.0).to_bits().to_le_bytes());
+}
+
+fn hash_bytes(h: &mut Sha256, b: &[u8]) {
+    h.update((b.len() as u64).to_le_bytes());
+    h.update(b);
+}
+
+/// Looks up a digest in the cache, or computes and caches it.
+fn cached<T>(
+    cache: &'static ::std::thread::LocalKey<RefCell<Cache<T>>>,
+    v: &Arc<T>,
+    f: fn(&Arc<T>) -> Result<Digest, String>,
+) -> Result<Digest, String>
+where
+    T: 'static,
+{
+    if let Some(digest) = cache.with(|c| c.borrow().get(v)) {
+        return Ok(digest);
+    }
+    // The cache is not borrowed here, since `f` hashes the items recursively.
+    let digest = f(v)?;
+    cache.with(|c| c.borrow_mut().insert(v, digest));
+    Ok(digest)
+}
+
+fn array_digest(arr: &Arc<Vec<Variable>>) -> Result<Digest, String> {
+    let mut h = Sha256::new();
+    h.update((arr.len() as u64).to_le_bytes());
+    for it in arr.iter() {
+        hash_var(&mut h, it)?;
+    }
+    Ok(h.finalize().into())
+}
+
+fn object_digest(obj: &Arc<HashMap<Arc<String>, Variable>>) -> Result<Digest, String> {
+    let mut h = Sha256::new();
+    h.update((obj.len() as u64).to_le_bytes());
+    let mut keys: Vec<_> = obj.keys().collect();
+    keys.sort();
+    for key in keys {
+        hash_bytes(&mut h, key.as_bytes());
+        hash_var(&mut h, &obj[key])?;
+    }
+    Ok(h.finalize().into())
+}
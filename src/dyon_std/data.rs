@@ -1,8 +1,4 @@
 use std::collections::HashSet;
-#[cfg(all(not(target_family = "wasm"), feature = "file"))]
-use std::fs::File;
-#[cfg(all(not(target_family = "wasm"), feature = "file"))]
-use std::io::Read;
 use std::sync::Arc;
 
 use range::Range;
@@ -11,32 +7,43 @@ use read_token::{NumberSettings, ReadToken};
 #[cfg(all(not(target_family = "wasm"), feature = "file"))]
 use super::io::io_error;
 
+use vfs::Vfs;
+use Scalar;
 use Variable;
 
 type Strings = HashSet<Arc<String>>;
 
-/// Loads data from a file.
+/// Reads a text file.
 #[cfg(all(not(target_family = "wasm"), feature = "file"))]
-pub fn load_file(file: &str) -> Result<Variable, String> {
-    let mut data_file = File::open(file).map_err(|err| io_error("open", file, &err))?;
-    let mut d = String::new();
-    data_file
-        .read_to_string(&mut d)
-        .map_err(|err| io_error("read", file, &err))?;
-    load_data(&d)
+pub(crate) fn read_file(vfs: &dyn Vfs, file: &str) -> Result<String, String> {
+    vfs.read_to_string(file)
+        .map_err(|err| io_error("read", file, &err))
 }
 
 #[cfg(not(all(not(target_family = "wasm"), feature = "file")))]
-pub fn load_file(_: &str) -> Result<Variable, String> {
+pub(crate) fn read_file(_: &dyn Vfs, _: &str) -> Result<String, String> {
     Err(super::FILE_SUPPORT_DISABLED.into())
 }
 
+/// Loads data from a file.
+///
+/// Fails when an array is longer than `max_array_len`.
+pub fn load_file(
+    vfs: &dyn Vfs,
+    file: &str,
+    max_array_len: Option<usize>,
+) -> Result<Variable, String> {
+    load_data(&read_file(vfs, file)?, max_array_len)
+}
+
 /// Loads data from text.
-pub fn load_data(data: &str) -> Result<Variable, String> {
+///
+/// Fails when an array is longer than `max_array_len`.
+pub fn load_data(data: &str, max_array_len: Option<usize>) -> Result<Variable, String> {
     let mut read = ReadToken::new(data, 0);
     let mut strings: Strings = HashSet::new();
     opt_w(&mut read);
-    expr(&mut read, &mut strings, data)
+    expr(&mut read, &mut strings, max_array_len, data)
 }
 
 static NUMBER_SETTINGS: NumberSettings = NumberSettings {
@@ -45,16 +52,21 @@ static NUMBER_SETTINGS: NumberSettings = NumberSettings {
 
 const SEPS: &str = "(){}[],.:;\n\"\\";
 
-fn expr(read: &mut ReadToken, strings: &mut Strings, data: &str) -> Result<Variable, String> {
+fn expr(
+    read: &mut ReadToken,
+    strings: &mut Strings,
+    max_array_len: Option<usize>,
+    data: &str,
+) -> Result<Variable, String> {
     if let Some(range) = read.tag("{") {
         // Object.
         *read = read.consume(range.length);
-        return object(read, strings, data);
+        return object(read, strings, max_array_len, data);
     }
     if let Some(range) = read.tag("[") {
         // Array.
         *read = read.consume(range.length);
-        return array(read, strings, data);
+        return array(read, strings, max_array_len, data);
     }
     if let Some(range) = read.tag("(") {
         // Vec4.
@@ -70,10 +82,10 @@ fn expr(read: &mut ReadToken, strings: &mut Strings, data: &str) -> Result<Varia
         let val = read.raw_string(range.length);
         if let Some((rgb, a)) = rgb_maybe_a(&mut val.chars()) {
             let v = [
-                f32::from(rgb[0]) / 255.0,
-                f32::from(rgb[1]) / 255.0,
-                f32::from(rgb[2]) / 255.0,
-                f32::from(a.unwrap_or(255)) / 255.0,
+                Scalar::from(rgb[0]) / 255.0,
+                Scalar::from(rgb[1]) / 255.0,
+                Scalar::from(rgb[2]) / 255.0,
+                Scalar::from(a.unwrap_or(255)) / 255.0,
             ];
             return Ok(Variable::Vec4(v));
         } else {
@@ -87,7 +99,7 @@ fn expr(read: &mut ReadToken, strings: &mut Strings, data: &str) -> Result<Varia
     if let Some(range) = read.tag("link") {
         // Link.
         *read = read.consume(range.length);
-        return link(read, strings, data);
+        return link(read, strings, max_array_len, data);
     }
     // Text.
     if let Some(range) = read.string() {
@@ -125,6 +137,32 @@ fn expr(read: &mut ReadToken, strings: &mut Strings, data: &str) -> Result<Varia
         *read = read.consume(range.length);
         return Ok(Variable::bool(true));
     }
+    // Binary data.
+    if let Some(range) = read.tag("bytes(") {
+        *read = read.consume(range.length);
+        opt_w(read);
+        let res = bytes(read, data)?;
+        opt_w(read);
+        return if let Some(range) = read.tag(")") {
+            *read = read.consume(range.length);
+            Ok(res)
+        } else {
+            Err(error(read.start(), "Expected `)`", data))
+        };
+    }
+    // Array of numbers.
+    if let Some(range) = read.tag("farr(") {
+        *read = read.consume(range.length);
+        opt_w(read);
+        let res = farr(read, max_array_len, data)?;
+        opt_w(read);
+        return if let Some(range) = read.tag(")") {
+            *read = read.consume(range.length);
+            Ok(res)
+        } else {
+            Err(error(read.start(), "Expected `)`", data))
+        };
+    }
     // Option.
     if let Some(range) = read.tag("none()") {
         *read = read.consume(range.length);
@@ -133,7 +171,7 @@ fn expr(read: &mut ReadToken, strings: &mut Strings, data: &str) -> Result<Varia
     if let Some(range) = read.tag("some(") {
         *read = read.consume(range.length);
         opt_w(read);
-        let res = expr(read, strings, data)?;
+        let res = expr(read, strings, max_array_len, data)?;
         opt_w(read);
         return if let Some(range) = read.tag(")") {
             *read = read.consume(range.length);
@@ -145,7 +183,12 @@ fn expr(read: &mut ReadToken, strings: &mut Strings, data: &str) -> Result<Varia
     Err(error(read.start(), "Reached end of file", data))
 }
 
-fn object(read: &mut ReadToken, strings: &mut Strings, data: &str) -> Result<Variable, String> {
+fn object(
+    read: &mut ReadToken,
+    strings: &mut Strings,
+    max_array_len: Option<usize>,
+    data: &str,
+) -> Result<Variable, String> {
     use std::collections::HashMap;
 
     let mut res: HashMap<Arc<String>, Variable> = HashMap::new();
@@ -205,14 +248,19 @@ fn object(read: &mut ReadToken, strings: &mut Strings, data: &str) -> Result<Var
 
         opt_w(read);
 
-        res.insert(key, expr(read, strings, data)?);
+        res.insert(key, expr(read, strings, max_array_len, data)?);
 
         was_comma = comma(read);
     }
     Ok(Variable::Object(Arc::new(res)))
 }
 
-fn array(read: &mut ReadToken, strings: &mut Strings, data: &str) -> Result<Variable, String> {
+fn array(
+    read: &mut ReadToken,
+    strings: &mut Strings,
+    max_array_len: Option<usize>,
+    data: &str,
+) -> Result<Variable, String> {
     let mut res = vec![];
     let mut was_comma = false;
     loop {
@@ -226,14 +274,20 @@ fn array(read: &mut ReadToken, strings: &mut Strings, data: &str) -> Result<Vari
         if !res.is_empty() && !was_comma {
             return Err(error(read.start(), "Expected `,`", data));
         }
+        check_array_len(read, res.len() + 1, max_array_len, data)?;
 
-        res.push(expr(read, strings, data)?);
+        res.push(expr(read, strings, max_array_len, data)?);
         was_comma = comma(read);
     }
     Ok(Variable::Array(Arc::new(res)))
 }
 
-fn link(read: &mut ReadToken, strings: &mut Strings, data: &str) -> Result<Variable, String> {
+fn link(
+    read: &mut ReadToken,
+    strings: &mut Strings,
+    max_array_len: Option<usize>,
+    data: &str,
+) -> Result<Variable, String> {
     use Link;
 
     opt_w(read);
@@ -256,7 +310,7 @@ fn link(read: &mut ReadToken, strings: &mut Strings, data: &str) -> Result<Varia
             break;
         }
 
-        match link.push(&expr(read, strings, data)?) {
+        match link.push(&expr(read, strings, max_array_len, data)?) {
             Ok(()) => {}
             Err(err) => return Err(err),
         };
@@ -320,10 +374,102 @@ fn vec4(read: &mut ReadToken, data: &str) -> Result<Variable, String> {
     } else {
         return Err(error(read.start(), "Expected `)`", data));
     }
-    Ok(Variable::Vec4([x as f32, y as f32, z as f32, w as f32]))
+    Ok(Variable::Vec4([
+        x as Scalar,
+        y as Scalar,
+        z as Scalar,
+        w as Scalar,
+    ]))
+}
+
+/// Reads binary data as a string of hexadecimal digits.
+fn bytes(read: &mut ReadToken, data: &str) -> Result<Variable, String> {
+    let range = match read.string() {
+        Some(range) => range,
+        None => return Err(error(read.start(), "Expected string of hex digits", data)),
+    };
+    let hex = match read.parse_string(range.length) {
+        Ok(hex) => hex,
+        Err(err_range) => {
+            let (range, err) = err_range.decouple();
+            return Err(error(range, &format!("{}", err), data));
+        }
+    };
+    let digits: Option<Vec<u8>> = hex
+        .chars()
+        .map(|c| c.to_digit(16).map(|d| d as u8))
+        .collect();
+    match digits {
+        Some(ref digits) if digits.len() % 2 == 0 => {
+            *read = read.consume(range.length);
+            Ok(Variable::Bytes(Arc::new(
+                digits.chunks(2).map(|d| d[0] << 4 | d[1]).collect(),
+            )))
+        }
+        _ => Err(error(range, "Expected an even number of hex digits", data)),
+    }
+}
+
+/// Reads an array of numbers.
+fn farr(
+    read: &mut ReadToken,
+    max_array_len: Option<usize>,
+    data: &str,
+) -> Result<Variable, String> {
+    if let Some(range) = read.tag("[") {
+        *read = read.consume(range.length);
+    } else {
+        return Err(error(read.start(), "Expected `[`", data));
+    }
+    let mut res = vec![];
+    let mut was_comma = false;
+    loop {
+        opt_w(read);
+
+        if let Some(range) = read.tag("]") {
+            *read = read.consume(range.length);
+            break;
+        }
+
+        if !res.is_empty() && !was_comma {
+            return Err(error(read.start(), "Expected `,`", data));
+        }
+        check_array_len(read, res.len() + 1, max_array_len, data)?;
+
+        if let Some(range) = read.number(&NUMBER_SETTINGS) {
+            match read.parse_number(&NUMBER_SETTINGS, range.length) {
+                Ok(x) => {
+                    *read = read.consume(range.length);
+                    res.push(x);
+                }
+                Err(err) => return Err(error(range, &format!("{}", err), data)),
+            }
+        } else {
+            return Err(error(read.start(), "Expected number", data));
+        }
+        was_comma = comma(read);
+    }
+    Ok(Variable::F64Array(Arc::new(res)))
 }
 
 /// Reads optional whitespace including comments.
+/// Stops loading arrays longer than the limit, before reading more items.
+fn check_array_len(
+    read: &ReadToken,
+    len: usize,
+    max_array_len: Option<usize>,
+    data: &str,
+) -> Result<(), String> {
+    match max_array_len {
+        Some(max) if len > max => Err(error(
+            read.start(),
+            &format!("Array length limit of `{}` exceeded", max),
+            data,
+        )),
+        _ => Ok(()),
+    }
+}
+
 fn opt_w(read: &mut ReadToken) {
     loop {
         let start = *read;
@@ -0,0 +1,296 @@
+//! Differentiation of closures.
+//!
+//! Closures made of numbers, arguments, arithmetic and math intrinsics
+//! are differentiated symbolically.
+//! Other closures are differentiated numerically, using central differences.
+
+use std::fmt::Write;
+
+use ast;
+use Type;
+use Variable;
+
+/// The name of the function in the generated module.
+pub(crate) const FN_NAME: &str = "derivative_of";
+
+/// Math intrinsics with one argument that can be differentiated.
+const FUNCTIONS: &[&str] = &[
+    "sqrt", "sin", "cos", "tan", "asin", "acos", "atan", "exp", "ln", "log2", "log10", "abs",
+];
+
+/// A math expression in the arguments of a closure.
+#[derive(Clone, Debug, PartialEq)]
+enum Sym {
+    Num(f64),
+    Arg(usize),
+    Add(Box<Sym>, Box<Sym>),
+    Sub(Box<Sym>, Box<Sym>),
+    Mul(Box<Sym>, Box<Sym>),
+    Div(Box<Sym>, Box<Sym>),
+    Pow(Box<Sym>, Box<Sym>),
+    Neg(Box<Sym>),
+    Call(&'static str, Box<Sym>),
+}
+
+use self::Sym::*;
+
+fn apply(name: &str, x: f64) -> f64 {
+    match name {
+        "sqrt" => x.sqrt(),
+        "sin" => x.sin(),
+        "cos" => x.cos(),
+        "tan" => x.tan(),
+        "asin" => x.asin(),
+        "acos" => x.acos(),
+        "atan" => x.atan(),
+        "exp" => x.exp(),
+        "ln" => x.ln(),
+        "log2" => x.log2(),
+        "log10" => x.log10(),
+        _ => x.abs(),
+    }
+}
+
+// The constructors below simplify while building the expression,
+// such that the derivative does not grow with terms that are zero or one.
+
+fn add(a: Sym, b: Sym) -> Sym {
+    match (a, b) {
+        (Num(a), Num(b)) => Num(a + b),
+        (Num(0.0), x) | (x, Num(0.0)) => x,
+        (a, b) => Add(Box::new(a), Box::new(b)),
+    }
+}
+
+fn sub(a: Sym, b: Sym) -> Sym {
+    match (a, b) {
+        (Num(a), Num(b)) => Num(a - b),
+        (x, Num(0.0)) => x,
+        (Num(0.0), x) => neg(x),
+        (a, b) => Sub(Box::new(a), Box::new(b)),
+    }
+}
+
+fn mul(a: Sym, b: Sym) -> Sym {
+    match (a, b) {
+        (Num(a), Num(b)) => Num(a * b),
+        (Num(0.0), _) | (_, Num(0.0)) => Num(0.0),
+        (Num(1.0), x) | (x, Num(1.0)) => x,
+        (Num(-1.0), x) | (x, Num(-1.0)) => neg(x),
+        // Numbers are moved to the left and multiplied together.
+        (x, Num(a)) => mul(Num(a), x),
+        (Num(a), Mul(b, x)) => match *b {
+            Num(b) => mul(Num(a * b), *x),
+            b => Mul(Box::new(Num(a)), Box::new(Mul(Box::new(b), x))),
+        },
+        (a, b) => Mul(Box::new(a), Box::new(b)),
+    }
+}
+
+fn div(a: Sym, b: Sym) -> Sym {
+    match (a, b) {
+        (Num(a), Num(b)) if b != 0.0 => Num(a / b),
+        (x, Num(1.0)) => x,
+        (a, b) => Div(Box::new(a), Box::new(b)),
+    }
+}
+
+fn pow(a: Sym, b: Sym) -> Sym {
+    match (a, b) {
+        (Num(a), Num(b)) => Num(a.powf(b)),
+        (_, Num(0.0)) => Num(1.0),
+        (x, Num(1.0)) => x,
+        (a, b) => Pow(Box::new(a), Box::new(b)),
+    }
+}
+
+fn neg(a: Sym) -> Sym {
+    match a {
+        Num(x) => Num(-x),
+        Neg(x) => *x,
+        x => Neg(Box::new(x)),
+    }
+}
+
+fn call(name: &'static str, a: Sym) -> Sym {
+    match a {
+        Num(x) => Num(apply(name, x)),
+        x => Call(name, Box::new(x)),
+    }
+}
+
+/// Converts the expression of a closure, returning `None` when not supported.
+fn from_expr(expr: &ast::Expression, args: &[ast::Arg]) -> Option<Sym> {
+    use ast::Expression as E;
+
+    Some(match *expr {
+        E::Variable(ref range_var) => match range_var.1 {
+            Variable::F64(x, _) if x.is_finite() => Num(x),
+            _ => return None,
+        },
+        E::Item(ref item) => {
+            if item.current || item.try || !item.ids.is_empty() {
+                return None;
+            }
+            let i = args.iter().position(|arg| arg.name == item.name)?;
+            match args[i].ty {
+                Type::F64 | Type::Any => Arg(i),
+                _ => return None,
+            }
+        }
+        E::Block(ref block) if block.expressions.len() == 1 => {
+            from_expr(&block.expressions[0], args)?
+        }
+        E::CallBinOp(ref call) => {
+            let a = from_expr(&call.left, args)?;
+            let b = from_expr(&call.right, args)?;
+            match &**call.info.name {
+                "add" => Add(Box::new(a), Box::new(b)),
+                "sub" => Sub(Box::new(a), Box::new(b)),
+                "mul" => Mul(Box::new(a), Box::new(b)),
+                "div" => Div(Box::new(a), Box::new(b)),
+                "pow" => Pow(Box::new(a), Box::new(b)),
+                _ => return None,
+            }
+        }
+        E::CallUnOp(ref call) if &**call.info.name == "neg" => {
+            Neg(Box::new(from_expr(&call.arg, args)?))
+        }
+        E::CallReturn(ref c) if c.args.len() == 1 => {
+            let name = FUNCTIONS.iter().find(|&&f| f == &**c.info.name)?;
+            Call(name, Box::new(from_expr(&c.args[0], args)?))
+        }
+        _ => return None,
+    })
+}
+
+impl Sym {
+    /// Returns the derivative with respect to an argument.
+    fn diff(&self, var: usize) -> Sym {
+        match *self {
+            Num(_) => Num(0.0),
+            Arg(i) => Num(if i == var { 1.0 } else { 0.0 }),
+            Add(ref a, ref b) => add(a.diff(var), b.diff(var)),
+            Sub(ref a, ref b) => sub(a.diff(var), b.diff(var)),
+            Mul(ref a, ref b) => add(
+                mul(a.diff(var), (**b).clone()),
+                mul((**a).clone(), b.diff(var)),
+            ),
+            Div(ref a, ref b) => div(
+                sub(
+                    mul(a.diff(var), (**b).clone()),
+                    mul((**a).clone(), b.diff(var)),
+                ),
+                pow((**b).clone(), Num(2.0)),
+            ),
+            Pow(ref a, ref b) => {
+                let (da, db) = (a.diff(var), b.diff(var));
+                if db == Num(0.0) {
+                    let n = (**b).clone();
+                    mul(mul(n.clone(), pow((**a).clone(), sub(n, Num(1.0)))), da)
+                } else {
+                    mul(
+                        self.clone(),
+                        add(
+                            mul(db, call("ln", (**a).clone())),
+                            div(mul((**b).clone(), da), (**a).clone()),
+                        ),
+                    )
+                }
+            }
+            Neg(ref a) => neg(a.diff(var)),
+            Call(name, ref a) => {
+                let x = (**a).clone();
+                let outer = match name {
+                    "sqrt" => div(Num(0.5), call("sqrt", x)),
+                    "sin" => call("cos", x),
+                    "cos" => neg(call("sin", x)),
+                    "tan" => div(Num(1.0), pow(call("cos", x), Num(2.0))),
+                    "asin" => div(Num(1.0), call("sqrt", sub(Num(1.0), pow(x, Num(2.0))))),
+                    "acos" => div(Num(-1.0), call("sqrt", sub(Num(1.0), pow(x, Num(2.0))))),
+                    "atan" => div(Num(1.0), add(Num(1.0), pow(x, Num(2.0)))),
+                    "exp" => call("exp", x),
+                    "ln" => div(Num(1.0), x),
+                    "log2" => div(Num(1.0), mul(x, Num(2f64.ln()))),
+                    "log10" => div(Num(1.0), mul(x, Num(10f64.ln()))),
+                    _ => div(x.clone(), call("abs", x)),
+                };
+                mul(outer, a.diff(var))
+            }
+        }
+    }
+
+    /// Writes the expression as Dyon source.
+    fn write(&self, args: &[ast::Arg], out: &mut String) {
+        let bin = |op: &str, a: &Sym, b: &Sym, out: &mut String| {
+            out.push('(');
+            a.write(args, out);
+            let _ = write!(out, " {} ", op);
+            b.write(args, out);
+            out.push(')');
+        };
+        match *self {
+            Num(x) if x < 0.0 => {
+                let _ = write!(out, "(-{})", -x);
+            }
+            Num(x) => {
+                let _ = write!(out, "{}", x);
+            }
+            Arg(i) => out.push_str(&args[i].name),
+            Add(ref a, ref b) => bin("+", a, b, out),
+            Sub(ref a, ref b) => bin("-", a, b, out),
+            Mul(ref a, ref b) => bin("*", a, b, out),
+            Div(ref a, ref b) => bin("/", a, b, out),
+            Pow(ref a, ref b) => bin("^", a, b, out),
+            Neg(ref a) => {
+                out.push_str("(-");
+                a.write(args, out);
+                out.push(')');
+            }
+            Call(name, ref a) => {
+                out.push_str(name);
+                out.push('(');
+                a.write(args, out);
+                out.push(')');
+            }
+        }
+    }
+}
+
+/// Generates the source of a module with a function that returns the derivative.
+///
+/// The function takes the original closure as argument,
+/// which is only used when differentiating numerically.
+pub(crate) fn module_source(closure: &ast::Closure, var: usize) -> String {
+    let mut out = format!("fn {}(f) -> {{\n    return ", FN_NAME);
+    if let Some(sym) = from_expr(&closure.expr, &closure.args) {
+        let names: Vec<&str> = closure.args.iter().map(|arg| &**arg.name).collect();
+        let _ = write!(out, "\\({}) = ", names.join(", "));
+        sym.diff(var).write(&closure.args, &mut out);
+        out.push('\n');
+    } else {
+        // Arguments are renamed to not shadow `f` and `h`.
+        let names: Vec<String> = (0..closure.args.len()).map(|i| format!("x{}", i)).collect();
+        let nudge = |sign: &str| {
+            let mut args = names.clone();
+            args[var] = format!("{} {} h", names[var], sign);
+            args.join(", ")
+        };
+        // The step is near the cube root of the machine epsilon,
+        // which balances rounding errors with truncation errors.
+        let _ = write!(
+            out,
+            "\\({}) = {{\n        \
+             f := grab f\n        \
+             h := 0.000006 * (abs({}) + 1)\n        \
+             (\\f({}) - \\f({})) / (2 * h)\n    \
+             }}\n",
+            names.join(", "),
+            names[var],
+            nudge("+"),
+            nudge("-")
+        );
+    }
+    out.push_str("}\n");
+    out
+}
@@ -0,0 +1,264 @@
+//! Parses and evaluates formulas typed by users, e.g. in a spreadsheet.
+//!
+//! Formulas only have numbers, variables, arithmetic, comparisons and logic,
+//! such that evaluating them can not call functions or run forever.
+
+use std::sync::Arc;
+
+/// The maximum nesting of parentheses, unary operators and powers.
+const MAX_DEPTH: usize = 64;
+/// The maximum number of tokens, which limits recursion when evaluating.
+const MAX_TOKENS: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Token {
+    Num(f64),
+    Var(usize, usize),
+    Op(&'static str),
+    Open,
+    Close,
+}
+
+const OPS: &[&str] = &[
+    "<=", ">=", "==", "!=", "&&", "||", "+", "-", "*", "/", "%", "^", "<", ">", "!",
+];
+
+fn tokenize(text: &str) -> Result<Vec<(usize, Token)>, String> {
+    let bytes = text.as_bytes();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        if c.is_ascii_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() || c == b'.' {
+            let start = i;
+            while i < bytes.len() && (bytes[i].is_ascii_digit() || bytes[i] == b'.') {
+                i += 1;
+            }
+            if i < bytes.len() && (bytes[i] == b'e' || bytes[i] == b'E') {
+                let mut j = i + 1;
+                if j < bytes.len() && (bytes[j] == b'+' || bytes[j] == b'-') {
+                    j += 1;
+                }
+                if j < bytes.len() && bytes[j].is_ascii_digit() {
+                    i = j;
+                    while i < bytes.len() && bytes[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+            }
+            match text[start..i].parse() {
+                Ok(x) => tokens.push((start, Token::Num(x))),
+                Err(_) => return Err(format!("Invalid number at position {}", start)),
+            }
+        } else if c.is_ascii_alphabetic() || c == b'_' {
+            let start = i;
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                i += 1;
+            }
+            tokens.push((start, Token::Var(start, i)));
+        } else if c == b'(' {
+            tokens.push((i, Token::Open));
+            i += 1;
+        } else if c == b')' {
+            tokens.push((i, Token::Close));
+            i += 1;
+        } else if let Some(op) = OPS.iter().find(|op| text[i..].starts_with(**op)) {
+            tokens.push((i, Token::Op(op)));
+            i += op.len();
+        } else {
+            let ch = text[i..].chars().next().unwrap();
+            return Err(format!("Unexpected `{}` at position {}", ch, i));
+        }
+        if tokens.len() > MAX_TOKENS {
+            return Err(format!("Formula is longer than {} tokens", MAX_TOKENS));
+        }
+    }
+    Ok(tokens)
+}
+
+/// A parsed formula.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Expr {
+    Num(f64),
+    Var(Arc<String>),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Bin(&'static str, Box<Expr>, Box<Expr>),
+}
+
+/// Returns the binding power of a binary operator.
+fn precedence(op: &str) -> usize {
+    match op {
+        "||" => 1,
+        "&&" => 2,
+        "==" | "!=" => 3,
+        "<" | "<=" | ">" | ">=" => 4,
+        "+" | "-" => 5,
+        "*" | "/" | "%" => 6,
+        // `^` binds tighter than unary operators, such that `-2^2` is `-4`.
+        _ => 8,
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<Token> {
+        self.tokens.get(self.pos).map(|&(_, t)| t)
+    }
+
+    fn offset(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .map(|&(i, _)| i)
+            .unwrap_or_else(|| self.text.len())
+    }
+
+    fn nest(&mut self) -> Result<(), String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            Err(format!(
+                "Formula is nested deeper than {} at position {}",
+                MAX_DEPTH,
+                self.offset()
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn expr(&mut self, min: usize) -> Result<Expr, String> {
+        let mut left = self.unary()?;
+        while let Some(Token::Op(op)) = self.peek() {
+            let prec = precedence(op);
+            if prec < min || op == "!" {
+                break;
+            }
+            self.pos += 1;
+            // `^` is right associative, the others are left associative.
+            let right = if op == "^" {
+                self.nest()?;
+                let right = self.expr(prec)?;
+                self.depth -= 1;
+                right
+            } else {
+                self.expr(prec + 1)?
+            };
+            left = Expr::Bin(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.peek() {
+            Some(Token::Op("-")) | Some(Token::Op("!")) => {
+                let neg = self.peek() == Some(Token::Op("-"));
+                self.pos += 1;
+                self.nest()?;
+                let inner = Box::new(self.expr(7)?);
+                self.depth -= 1;
+                Ok(if neg {
+                    Expr::Neg(inner)
+                } else {
+                    Expr::Not(inner)
+                })
+            }
+            _ => self.atom(),
+        }
+    }
+
+    fn atom(&mut self) -> Result<Expr, String> {
+        let offset = self.offset();
+        let token = self.peek();
+        self.pos += 1;
+        match token {
+            Some(Token::Num(x)) => Ok(Expr::Num(x)),
+            Some(Token::Var(start, end)) => Ok(Expr::Var(Arc::new(self.text[start..end].into()))),
+            Some(Token::Open) => {
+                self.nest()?;
+                let inner = self.expr(0)?;
+                self.depth -= 1;
+                if self.peek() == Some(Token::Close) {
+                    self.pos += 1;
+                    Ok(inner)
+                } else {
+                    Err(format!("Expected `)` at position {}", self.offset()))
+                }
+            }
+            Some(Token::Op(op)) => Err(format!("Unexpected `{}` at position {}", op, offset)),
+            Some(Token::Close) => Err(format!("Unexpected `)` at position {}", offset)),
+            None => Err("Unexpected end of formula".into()),
+        }
+    }
+}
+
+/// Parses a formula.
+pub(crate) fn parse(text: &str) -> Result<Expr, String> {
+    let mut parser = Parser {
+        text,
+        tokens: tokenize(text)?,
+        pos: 0,
+        depth: 0,
+    };
+    let expr = parser.expr(0)?;
+    if parser.pos < parser.tokens.len() {
+        return Err(format!("Unexpected token at position {}", parser.offset()));
+    }
+    Ok(expr)
+}
+
+fn truth(x: f64) -> f64 {
+    if x != 0.0 {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+impl Expr {
+    /// Evaluates the formula, looking up variables with `var`.
+    ///
+    /// Comparisons and logic return 1 for true and 0 for false.
+    pub(crate) fn eval(
+        &self,
+        var: &mut dyn FnMut(&str) -> Result<f64, String>,
+    ) -> Result<f64, String> {
+        Ok(match *self {
+            Expr::Num(x) => x,
+            Expr::Var(ref name) => var(name)?,
+            Expr::Neg(ref a) => -a.eval(var)?,
+            Expr::Not(ref a) => 1.0 - truth(a.eval(var)?),
+            Expr::Bin(op, ref a, ref b) => {
+                let a = a.eval(var)?;
+                match op {
+                    "&&" => return Ok(if a == 0.0 { 0.0 } else { truth(b.eval(var)?) }),
+                    "||" => return Ok(if a != 0.0 { 1.0 } else { truth(b.eval(var)?) }),
+                    _ => {}
+                }
+                let b = b.eval(var)?;
+                let cmp = |x: bool| if x { 1.0 } else { 0.0 };
+                match op {
+                    "+" => a + b,
+                    "-" => a - b,
+                    "*" => a * b,
+                    "/" => a / b,
+                    "%" => a % b,
+                    "^" => a.powf(b),
+                    "<" => cmp(a < b),
+                    "<=" => cmp(a <= b),
+                    ">" => cmp(a > b),
+                    ">=" => cmp(a >= b),
+                    "==" => cmp(a == b),
+                    _ => cmp(a != b),
+                }
+            }
+        })
+    }
+}
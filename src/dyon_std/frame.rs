@@ -0,0 +1,408 @@
+//! Frames of named columns for data analysis.
+//!
+//! Each column stores values of one type in a plain vector,
+//! such that sorting and aggregation run without interpreting values.
+
+// Columns and rows convert to arrays and objects,
+// which are shared by `Arc` although variables are not `Sync`.
+#![allow(clippy::arc_with_non_send_sync)]
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use Variable;
+
+#[derive(Clone)]
+pub(crate) enum Column {
+    F64(Vec<f64>),
+    Str(Vec<Arc<String>>),
+    Bool(Vec<bool>),
+}
+
+impl Column {
+    /// Creates a column from values of the same type.
+    fn from_vars(name: &str, vars: &[Variable]) -> Result<Column, String> {
+        let err = || format!("Expected values of the same type in column `{}`", name);
+        Ok(match vars.first() {
+            None | Some(&Variable::F64(_, _)) => Column::F64(
+                vars.iter()
+                    .map(|v| match *v {
+                        Variable::F64(x, _) => Ok(x),
+                        _ => Err(err()),
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            Some(&Variable::Str(_)) => Column::Str(
+                vars.iter()
+                    .map(|v| match *v {
+                        Variable::Str(ref s) => Ok(s.clone()),
+                        _ => Err(err()),
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            Some(&Variable::Bool(_, _)) => Column::Bool(
+                vars.iter()
+                    .map(|v| match *v {
+                        Variable::Bool(b, _) => Ok(b),
+                        _ => Err(err()),
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            Some(_) => {
+                return Err(format!(
+                    "Expected `f64`, `str` or `bool` in column `{}`",
+                    name
+                ))
+            }
+        })
+    }
+
+    /// Creates a column from text, using the most specific type that fits.
+    fn from_text(fields: Vec<String>) -> Column {
+        if let Ok(xs) = fields.iter().map(|s| s.trim().parse()).collect() {
+            Column::F64(xs)
+        } else if fields.iter().all(|s| s == "true" || s == "false") {
+            Column::Bool(fields.iter().map(|s| s == "true").collect())
+        } else {
+            Column::Str(fields.into_iter().map(Arc::new).collect())
+        }
+    }
+
+    fn len(&self) -> usize {
+        match *self {
+            Column::F64(ref xs) => xs.len(),
+            Column::Str(ref xs) => xs.len(),
+            Column::Bool(ref xs) => xs.len(),
+        }
+    }
+
+    fn get(&self, i: usize) -> Variable {
+        match *self {
+            Column::F64(ref xs) => Variable::f64(xs[i]),
+            Column::Str(ref xs) => Variable::Str(xs[i].clone()),
+            Column::Bool(ref xs) => Variable::bool(xs[i]),
+        }
+    }
+
+    fn text(&self, i: usize) -> String {
+        match *self {
+            Column::F64(ref xs) => format!("{}", xs[i]),
+            Column::Str(ref xs) => (*xs[i]).clone(),
+            Column::Bool(ref xs) => format!("{}", xs[i]),
+        }
+    }
+
+    /// Returns the values at the indices, in order.
+    fn take(&self, indices: &[usize]) -> Column {
+        match *self {
+            Column::F64(ref xs) => Column::F64(indices.iter().map(|&i| xs[i]).collect()),
+            Column::Str(ref xs) => Column::Str(indices.iter().map(|&i| xs[i].clone()).collect()),
+            Column::Bool(ref xs) => Column::Bool(indices.iter().map(|&i| xs[i]).collect()),
+        }
+    }
+
+    fn cmp(&self, i: usize, j: usize) -> Ordering {
+        match *self {
+            Column::F64(ref xs) => xs[i].partial_cmp(&xs[j]).unwrap_or(Ordering::Equal),
+            Column::Str(ref xs) => xs[i].cmp(&xs[j]),
+            Column::Bool(ref xs) => xs[i].cmp(&xs[j]),
+        }
+    }
+
+    /// Returns a key for grouping equal values.
+    fn key(&self, i: usize) -> Key {
+        match *self {
+            Column::F64(ref xs) => Key::F64(xs[i].to_bits()),
+            Column::Str(ref xs) => Key::Str(xs[i].clone()),
+            Column::Bool(ref xs) => Key::Bool(xs[i]),
+        }
+    }
+
+    fn to_var(&self) -> Variable {
+        Variable::Array(Arc::new((0..self.len()).map(|i| self.get(i)).collect()))
+    }
+}
+
+#[derive(PartialEq, Eq, Hash)]
+enum Key {
+    F64(u64),
+    Str(Arc<String>),
+    Bool(bool),
+}
+
+/// An aggregation of the values in a group.
+#[derive(Clone, Copy)]
+pub(crate) enum Agg {
+    Count,
+    Sum,
+    Mean,
+    Min,
+    Max,
+}
+
+impl Agg {
+    pub(crate) fn parse(name: &str) -> Option<Agg> {
+        Some(match name {
+            "count" => Agg::Count,
+            "sum" => Agg::Sum,
+            "mean" => Agg::Mean,
+            "min" => Agg::Min,
+            "max" => Agg::Max,
+            _ => return None,
+        })
+    }
+
+    pub(crate) fn apply(self, xs: &[f64]) -> f64 {
+        match self {
+            Agg::Count => xs.len() as f64,
+            Agg::Sum => xs.iter().sum(),
+            Agg::Mean => xs.iter().sum::<f64>() / xs.len() as f64,
+            Agg::Min => xs.iter().cloned().fold(f64::INFINITY, f64::min),
+            Agg::Max => xs.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+        }
+    }
+}
+
+/// Named columns of equal length.
+pub(crate) struct Frame {
+    names: Vec<Arc<String>>,
+    columns: Vec<Column>,
+}
+
+impl Frame {
+    fn new(names: Vec<Arc<String>>, columns: Vec<Column>) -> Result<Frame, String> {
+        if let Some(first) = columns.first() {
+            for (name, col) in names.iter().zip(&columns) {
+                if col.len() != first.len() {
+                    return Err(format!(
+                        "Expected {} rows in column `{}`, found {}",
+                        first.len(),
+                        name,
+                        col.len()
+                    ));
+                }
+            }
+        }
+        Ok(Frame { names, columns })
+    }
+
+    /// Creates a frame from an object of arrays, with columns sorted by name.
+    pub(crate) fn from_data(obj: &HashMap<Arc<String>, Variable>) -> Result<Frame, String> {
+        let mut names: Vec<Arc<String>> = obj.keys().cloned().collect();
+        names.sort();
+        let mut columns = vec![];
+        for name in &names {
+            match obj[name] {
+                Variable::Array(ref arr) => columns.push(Column::from_vars(name, arr)?),
+                _ => return Err(format!("Expected array in column `{}`", name)),
+            }
+        }
+        Frame::new(names, columns)
+    }
+
+    /// Returns an object of arrays.
+    pub(crate) fn to_data(&self) -> Variable {
+        let obj = self
+            .names
+            .iter()
+            .zip(&self.columns)
+            .map(|(name, col)| (name.clone(), col.to_var()))
+            .collect();
+        Variable::Object(Arc::new(obj))
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.columns.first().map_or(0, |col| col.len())
+    }
+
+    fn column(&self, name: &str) -> Result<&Column, String> {
+        self.names
+            .iter()
+            .position(|n| **n == name)
+            .map(|i| &self.columns[i])
+            .ok_or_else(|| format!("Could not find column `{}`", name))
+    }
+
+    /// Returns a row as an object.
+    pub(crate) fn row(&self, i: usize) -> Variable {
+        let obj = self
+            .names
+            .iter()
+            .zip(&self.columns)
+            .map(|(name, col)| (name.clone(), col.get(i)))
+            .collect();
+        Variable::Object(Arc::new(obj))
+    }
+
+    pub(crate) fn select(&self, names: &[Arc<String>]) -> Result<Frame, String> {
+        let mut columns = vec![];
+        for name in names {
+            columns.push(self.column(name)?.clone());
+        }
+        Frame::new(names.to_vec(), columns)
+    }
+
+    /// Returns the rows at the indices, in order.
+    pub(crate) fn take(&self, indices: &[usize]) -> Frame {
+        Frame {
+            names: self.names.clone(),
+            columns: self.columns.iter().map(|col| col.take(indices)).collect(),
+        }
+    }
+
+    pub(crate) fn head(&self, n: usize) -> Frame {
+        let indices: Vec<usize> = (0..n.min(self.len())).collect();
+        self.take(&indices)
+    }
+
+    /// Sorts the rows by a column, keeping the order of equal values.
+    pub(crate) fn sort(&self, name: &str, ascending: bool) -> Result<Frame, String> {
+        let col = self.column(name)?;
+        let mut indices: Vec<usize> = (0..self.len()).collect();
+        indices.sort_by(|&i, &j| {
+            let ord = col.cmp(i, j);
+            if ascending {
+                ord
+            } else {
+                ord.reverse()
+            }
+        });
+        Ok(self.take(&indices))
+    }
+
+    /// Groups rows by the values of a column and aggregates numeric columns.
+    ///
+    /// The groups are in order of first appearance.
+    /// Each aggregated column is named `<column>_<aggregation>`.
+    pub(crate) fn group_agg(
+        &self,
+        by: &Arc<String>,
+        aggs: &[(Arc<String>, Arc<String>, Agg)],
+    ) -> Result<Frame, String> {
+        let key_col = self.column(by)?;
+        let mut groups: HashMap<Key, usize> = HashMap::new();
+        let mut firsts = vec![];
+        let mut members: Vec<Vec<usize>> = vec![];
+        for i in 0..self.len() {
+            let g = *groups.entry(key_col.key(i)).or_insert_with(|| {
+                firsts.push(i);
+                members.push(vec![]);
+                members.len() - 1
+            });
+            members[g].push(i);
+        }
+        let mut names = vec![by.clone()];
+        let mut columns = vec![key_col.take(&firsts)];
+        for &(ref name, ref agg_name, agg) in aggs {
+            let col = self.column(name)?;
+            let res = match (col, agg) {
+                (Column::F64(xs), _) => members
+                    .iter()
+                    .map(|rows| agg.apply(&rows.iter().map(|&i| xs[i]).collect::<Vec<_>>()))
+                    .collect(),
+                (_, Agg::Count) => members.iter().map(|rows| rows.len() as f64).collect(),
+                _ => {
+                    return Err(format!(
+                        "Expected numbers in column `{}` for `{}`",
+                        name, agg_name
+                    ))
+                }
+            };
+            names.push(Arc::new(format!("{}_{}", name, agg_name)));
+            columns.push(Column::F64(res));
+        }
+        Frame::new(names, columns)
+    }
+
+    /// Parses CSV text with a header row.
+    ///
+    /// Fields can be quoted with `"`, where `""` is a quote inside a field.
+    pub(crate) fn from_csv(text: &str) -> Result<Frame, String> {
+        let mut rows = parse_csv(text)?.into_iter();
+        let names: Vec<Arc<String>> = match rows.next() {
+            Some(header) => header.into_iter().map(Arc::new).collect(),
+            None => return Err("Expected header row".into()),
+        };
+        let mut fields: Vec<Vec<String>> = vec![vec![]; names.len()];
+        for (i, row) in rows.enumerate() {
+            if row.len() != names.len() {
+                return Err(format!(
+                    "Expected {} fields in row {}, found {}",
+                    names.len(),
+                    i + 1,
+                    row.len()
+                ));
+            }
+            for (col, field) in fields.iter_mut().zip(row) {
+                col.push(field);
+            }
+        }
+        let columns = fields.into_iter().map(Column::from_text).collect();
+        Frame::new(names, columns)
+    }
+
+    pub(crate) fn to_csv(&self) -> String {
+        let mut res = String::new();
+        let line = |res: &mut String, fields: Vec<String>| {
+            let fields: Vec<String> = fields.iter().map(|s| quote_csv(s)).collect();
+            res.push_str(&fields.join(","));
+            res.push('\n');
+        };
+        line(&mut res, self.names.iter().map(|n| (**n).clone()).collect());
+        for i in 0..self.len() {
+            line(
+                &mut res,
+                self.columns.iter().map(|col| col.text(i)).collect(),
+            );
+        }
+        res
+    }
+}
+
+fn quote_csv(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.into()
+    }
+}
+
+fn parse_csv(text: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut rows = vec![];
+    let mut row = vec![];
+    let mut field = String::new();
+    let mut chars = text.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        if quoted {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => quoted = false,
+                _ => field.push(c),
+            }
+            continue;
+        }
+        match c {
+            '"' if field.is_empty() => quoted = true,
+            ',' => row.push(::std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                row.push(::std::mem::take(&mut field));
+                rows.push(::std::mem::take(&mut row));
+            }
+            _ => field.push(c),
+        }
+    }
+    if quoted {
+        return Err("Expected `\"` at end of quoted field".into());
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    Ok(rows)
+}
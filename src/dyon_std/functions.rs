@@ -5,7 +5,83 @@ use Lt;
 use Module;
 use Variable;
 
+/// The documentation of the standard library, used for docs of external functions.
+const LIB_DYON: &str = include_str!("../lib.dyon");
+
+lazy_static! {
+    static ref LIB_DOCS: HashMap<&'static str, String> = {
+        let mut docs = HashMap::new();
+        let mut doc: Vec<&str> = vec![];
+        for line in LIB_DYON.lines() {
+            if let Some(text) = doc_line(line) {
+                doc.push(text);
+            } else {
+                if let Some(rest) = line.strip_prefix("fn ") {
+                    let name = rest.split('(').next().unwrap();
+                    if !doc.is_empty() {
+                        docs.insert(name, doc.join("\n"));
+                    }
+                }
+                doc.clear();
+            }
+        }
+        docs
+    };
+}
+
+/// Returns the text of a `///` doc comment line.
+fn doc_line(line: &str) -> Option<&str> {
+    let text = line.trim().strip_prefix("///")?;
+    Some(text.strip_prefix(' ').unwrap_or(text))
+}
+
+/// Returns the doc comment right above a line, where lines start at 1.
+fn doc_above(source: &str, line: usize) -> Option<String> {
+    let mut doc: Vec<&str> = source
+        .lines()
+        .take(line - 1)
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+        .map_while(doc_line)
+        .collect();
+    if doc.is_empty() {
+        None
+    } else {
+        doc.reverse();
+        Some(doc.join("\n"))
+    }
+}
+
+/// Returns the line of a character offset, where lines start at 1.
+fn line_of(source: &str, offset: usize) -> usize {
+    1 + source.chars().take(offset).filter(|&c| c == '\n').count()
+}
+
+fn opt_str(s: Option<String>) -> Variable {
+    Variable::Option(s.map(|s| Box::new(Variable::Str(Arc::new(s)))))
+}
+
+/// Lists functions available in a module whose names contain a pattern.
+pub fn find_functions(module: &Module, pattern: &str) -> Vec<Variable> {
+    let name: Arc<String> = Arc::new("name".into());
+    list_functions(module)
+        .into_iter()
+        .filter(|f| match *f {
+            Variable::Object(ref obj) => match obj[&name] {
+                Variable::Str(ref s) => s.contains(pattern),
+                _ => false,
+            },
+            _ => false,
+        })
+        .collect()
+}
+
 /// Lists all functions available in a module.
+///
+/// Loaded functions include the file and lines they were loaded from.
+/// Doc comments are read from the source of loaded functions,
+/// and from the standard library docs for external functions.
 pub fn list_functions(module: &Module) -> Vec<Variable> {
     let mut functions = vec![];
     let name: Arc<String> = Arc::new("name".into());
@@ -17,6 +93,9 @@ pub fn list_functions(module: &Module) -> Vec<Variable> {
     let ty: Arc<String> = Arc::new("type".into());
     let external: Arc<String> = Arc::new("external".into());
     let loaded: Arc<String> = Arc::new("loaded".into());
+    let file: Arc<String> = Arc::new("file".into());
+    let lines: Arc<String> = Arc::new("lines".into());
+    let doc: Arc<String> = Arc::new("doc".into());
     for f in &*module.ext_prelude {
         let mut obj = HashMap::new();
         obj.insert(name.clone(), Variable::Str(f.name.clone()));
@@ -25,6 +104,9 @@ pub fn list_functions(module: &Module) -> Vec<Variable> {
             Variable::Str(Arc::new(f.p.ret.description())),
         );
         obj.insert(ty.clone(), Variable::Str(external.clone()));
+        obj.insert(file.clone(), Variable::Option(None));
+        obj.insert(lines.clone(), Variable::Option(None));
+        obj.insert(doc.clone(), opt_str(LIB_DOCS.get(&**f.name).cloned()));
         let mut args = vec![];
         for (i, lt) in f.p.lts.iter().enumerate() {
             let mut obj_arg = HashMap::new();
@@ -58,6 +140,27 @@ pub fn list_functions(module: &Module) -> Vec<Variable> {
             Variable::Str(Arc::new(f.ret.description())),
         );
         obj.insert(ty.clone(), Variable::Str(loaded.clone()));
+        obj.insert(
+            file.clone(),
+            Variable::Option(Some(Box::new(Variable::Str(f.file.clone())))),
+        );
+        let start = line_of(&f.source, f.source_range.offset);
+        // The range of a function includes whitespace after it.
+        let trailing = f
+            .source
+            .chars()
+            .skip(f.source_range.offset)
+            .take(f.source_range.length)
+            .collect::<String>();
+        let end = start + trailing.trim_end().matches('\n').count();
+        obj.insert(
+            lines.clone(),
+            Variable::Option(Some(Box::new(Variable::Array(Arc::new(vec![
+                Variable::f64(start as f64),
+                Variable::f64(end as f64),
+            ]))))),
+        );
+        obj.insert(doc.clone(), opt_str(doc_above(&f.source, start)));
         let mut args = vec![];
         for arg in &f.args {
             let mut obj_arg = HashMap::new();
@@ -0,0 +1,233 @@
+//! Runs numeric kernels on the GPU with WGSL compute shaders.
+//!
+//! Numbers are converted to `f32` on the GPU.
+//! The device is created on first use and shared between all runtimes.
+
+use std::sync::mpsc::channel;
+use std::sync::Mutex;
+
+use wgpu::util::DeviceExt;
+
+/// Number of invocations per workgroup, must match the shaders.
+const WORKGROUP_SIZE: usize = 64;
+/// Largest number of workgroups per dimension.
+const MAX_WORKGROUPS: usize = 65535;
+
+const MAP_SHADER: &str = "
+@group(0) @binding(0) var<storage, read_write> data: array<f32>;
+
+{kernel}
+
+@compute @workgroup_size(64)
+fn main(@builtin(global_invocation_id) id: vec3<u32>, @builtin(num_workgroups) n: vec3<u32>) {
+    let i = id.x + id.y * n.x * 64u;
+    if (i < arrayLength(&data)) {
+        data[i] = kernel(data[i]);
+    }
+}
+";
+
+const MAT_MUL_SHADER: &str = "
+struct Dims {
+    m: u32,
+    k: u32,
+    n: u32,
+    pad: u32,
+}
+
+@group(0) @binding(0) var<storage, read_write> res: array<f32>;
+@group(0) @binding(1) var<storage, read> a: array<f32>;
+@group(0) @binding(2) var<storage, read> b: array<f32>;
+@group(0) @binding(3) var<uniform> dims: Dims;
+
+@compute @workgroup_size(64)
+fn main(@builtin(global_invocation_id) id: vec3<u32>, @builtin(num_workgroups) n: vec3<u32>) {
+    let i = id.x + id.y * n.x * 64u;
+    if (i >= dims.m * dims.n) {
+        return;
+    }
+    let row = i / dims.n;
+    let col = i % dims.n;
+    var sum = 0.0;
+    for (var j = 0u; j < dims.k; j++) {
+        sum += a[row * dims.k + j] * b[j * dims.n + col];
+    }
+    res[i] = sum;
+}
+";
+
+struct Gpu {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+}
+
+lazy_static! {
+    static ref GPU: Mutex<Option<Result<Gpu, String>>> = Mutex::new(None);
+}
+
+fn init() -> Result<Gpu, String> {
+    let instance =
+        wgpu::Instance::new(wgpu::InstanceDescriptor::new_without_display_handle_from_env());
+    let adapter =
+        pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))
+            .map_err(|err| format!("Could not find GPU adapter: {}", err))?;
+    let (device, queue) =
+        pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor::default()))
+            .map_err(|err| format!("Could not create GPU device: {}", err))?;
+    Ok(Gpu { device, queue })
+}
+
+/// Runs a compute shader writing to `res`, returning the new values of `res`.
+///
+/// The shader reads `inputs` from the following bindings,
+/// followed by `dims` as a uniform.
+fn run(
+    source: &str,
+    res: &[f32],
+    inputs: &[&[f32]],
+    dims: Option<[u32; 4]>,
+) -> Result<Vec<f32>, String> {
+    if res.is_empty() {
+        return Ok(vec![]);
+    }
+    let mut guard = GPU.lock().unwrap();
+    let gpu = match *guard.get_or_insert_with(init) {
+        Ok(ref gpu) => gpu,
+        Err(ref err) => return Err(err.clone()),
+    };
+    let device = &gpu.device;
+
+    // Report errors in the shader instead of panicking.
+    let scope = device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: None,
+        source: wgpu::ShaderSource::Wgsl(source.into()),
+    });
+    let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: None,
+        layout: None,
+        module: &module,
+        entry_point: Some("main"),
+        compilation_options: Default::default(),
+        cache: None,
+    });
+    if let Some(err) = pollster::block_on(scope.pop()) {
+        return Err(format!("GPU kernel error: {}", err));
+    }
+
+    let storage = |data: &[f32], usage: wgpu::BufferUsages| {
+        let bytes: Vec<u8> = data.iter().flat_map(|x| x.to_le_bytes()).collect();
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: &bytes,
+            usage,
+        })
+    };
+    let res_buf = storage(
+        res,
+        wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+    );
+    let mut buffers = vec![];
+    for input in inputs {
+        buffers.push(storage(input, wgpu::BufferUsages::STORAGE));
+    }
+    if let Some(dims) = dims {
+        let bytes: Vec<u8> = dims.iter().flat_map(|x| x.to_le_bytes()).collect();
+        buffers.push(
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: &bytes,
+                usage: wgpu::BufferUsages::UNIFORM,
+            }),
+        );
+    }
+    let mut entries = vec![wgpu::BindGroupEntry {
+        binding: 0,
+        resource: res_buf.as_entire_binding(),
+    }];
+    for (i, buf) in buffers.iter().enumerate() {
+        entries.push(wgpu::BindGroupEntry {
+            binding: i as u32 + 1,
+            resource: buf.as_entire_binding(),
+        });
+    }
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: None,
+        layout: &pipeline.get_bind_group_layout(0),
+        entries: &entries,
+    });
+
+    let size = (res.len() * 4) as u64;
+    let staging = device.create_buffer(&wgpu::BufferDescriptor {
+        label: None,
+        size,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let groups = res.len().div_ceil(WORKGROUP_SIZE);
+    let x = groups.min(MAX_WORKGROUPS);
+    let y = groups.div_ceil(x);
+    if y > MAX_WORKGROUPS {
+        return Err(format!("Too many GPU elements `{}`", res.len()));
+    }
+    let mut encoder = device.create_command_encoder(&Default::default());
+    {
+        let mut pass = encoder.begin_compute_pass(&Default::default());
+        pass.set_pipeline(&pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.dispatch_workgroups(x as u32, y as u32, 1);
+    }
+    encoder.copy_buffer_to_buffer(&res_buf, 0, &staging, 0, size);
+    gpu.queue.submit(Some(encoder.finish()));
+
+    let slice = staging.slice(..);
+    let (tx, rx) = channel();
+    slice.map_async(wgpu::MapMode::Read, move |res| {
+        let _ = tx.send(res);
+    });
+    device
+        .poll(wgpu::PollType::wait_indefinitely())
+        .map_err(|err| format!("GPU error: {}", err))?;
+    match rx.recv() {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => return Err(format!("Could not read GPU buffer: {}", err)),
+        Err(_) => return Err("Could not read GPU buffer".into()),
+    }
+    let data = slice.get_mapped_range();
+    let res = data
+        .chunks(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect();
+    Ok(res)
+}
+
+/// Calls `fn kernel(x: f32) -> f32` in WGSL source for each number.
+pub(crate) fn map(data: &[f64], kernel: &str) -> Result<Vec<f64>, String> {
+    let data: Vec<f32> = data.iter().map(|&x| x as f32).collect();
+    let source = MAP_SHADER.replace("{kernel}", kernel);
+    let res = run(&source, &data, &[], None)?;
+    Ok(res.into_iter().map(f64::from).collect())
+}
+
+/// Multiplies matrices stored as arrays of rows.
+pub(crate) fn mat_mul(a: &[Vec<f64>], b: &[Vec<f64>]) -> Result<Vec<Vec<f64>>, String> {
+    let (m, k) = (a.len(), b.len());
+    let n = b.first().map_or(0, |row| row.len());
+    if a.iter().any(|row| row.len() != k) {
+        return Err(format!("Expected {} columns in first matrix", k));
+    }
+    if b.iter().any(|row| row.len() != n) {
+        return Err("Expected rows of equal length in second matrix".into());
+    }
+    let flat = |x: &[Vec<f64>]| -> Vec<f32> { x.iter().flatten().map(|&x| x as f32).collect() };
+    let res = run(
+        MAT_MUL_SHADER,
+        &vec![0.0; m * n],
+        &[&flat(a), &flat(b)],
+        Some([m as u32, k as u32, n as u32, 0]),
+    )?;
+    Ok(res
+        .chunks(n.max(1))
+        .map(|row| row.iter().map(|&x| f64::from(x)).collect())
+        .collect())
+}
@@ -0,0 +1,108 @@
+//! Hashing of values and data, using the `sha2` and `crc32fast` crates.
+
+use super::sha256::{Digest, Sha256};
+use Variable;
+
+/// Adds a number to a hash.
+fn hash_f64(h: &mut Sha256, x: f64) {
+    // Make `-0` and `0` equal, since they compare equal.
+    let x = if x == 0.0 { 0.0 } else { x };
+    h.update(x.to_bits().to_le_bytes());
+}
+
+/// Adds a length to a hash.
+fn hash_len(h: &mut Sha256, n: usize) {
+    h.update((n as u64).to_le_bytes());
+}
+
+/// Hashes a value, such that equal values get the same hash in every run.
+///
+/// Object keys are hashed in sorted order, and the SHA-256 hash is truncated
+/// to 53 bits, so it is exact as a number.
+pub(crate) fn hash(v: &Variable) -> Result<f64, String> {
+    let mut h = Sha256::new();
+    hash_var(&mut h, v)?;
+    let mut bits = [0; 8];
+    bits.copy_from_slice(&h.finalize()[..8]);
+    Ok((u64::from_le_bytes(bits) & ((1 << 53) - 1)) as f64)
+}
+
+fn hash_var(h: &mut Sha256, v: &Variable) -> Result<(), String> {
+    // Each kind of value starts with a tag, so e.g. `[]` and `{}` differ.
+    match *v {
+        Variable::Bool(b, _) => h.update([0, b as u8]),
+        Variable::F64(x, _) => {
+            h.update([1]);
+            hash_f64(h, x);
+        }
+        Variable::Vec4(v) => {
+            h.update([2]);
+            for &x in &v {
+                hash_f64(h, f64::from(x));
+            }
+        }
+        Variable::Mat4(ref m) => {
+            h.update([3]);
+            for col in m.iter() {
+                for &x in col {
+                    hash_f64(h, f64::from(x));
+                }
+            }
+        }
+        Variable::Str(ref s) => {
+            h.update([4]);
+            hash_len(h, s.len());
+            h.update(s.as_bytes());
+        }
+        Variable::Array(ref arr) => {
+            h.update([5]);
+            hash_len(h, arr.len());
+            for it in arr.iter() {
+                hash_var(h, it)?;
+            }
+        }
+        Variable::Object(ref obj) => {
+            h.update([6]);
+            hash_len(h, obj.len());
+            let mut keys: Vec<_> = obj.keys().collect();
+            keys.sort();
+            for key in keys {
+                hash_len(h, key.len());
+                h.update(key.as_bytes());
+                hash_var(h, &obj[key])?;
+            }
+        }
+        Variable::Option(None) => h.update([7]),
+        Variable::Option(Some(ref v)) => {
+            h.update([8]);
+            hash_var(h, v)?;
+        }
+        Variable::Result(Ok(ref v)) => {
+            h.update([9]);
+            hash_var(h, v)?;
+        }
+        Variable::Result(Err(ref err)) => {
+            h.update([10]);
+            hash_var(h, &err.message)?;
+        }
+        Variable::Bytes(ref b) => {
+            h.update([11]);
+            hash_len(h, b.len());
+            h.update(&**b);
+        }
+        Variable::F64Array(ref arr) => {
+            h.update([12]);
+            hash_len(h, arr.len());
+            for &x in arr.iter() {
+                hash_f64(h, x);
+            }
+        }
+        _ => return Err(format!("Can not hash `{}`", v.typeof_var())),
+    }
+    Ok(())
+}
+
+/// Computes the CRC-32 checksum used by e.g. zip and PNG.
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    crc32fast::hash(data)
+}
@@ -1,5 +1,10 @@
 use std::error::Error;
+use std::fs::File;
 use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use vfs::Vfs;
 
 /// Returns a string representation of an IO error.
 pub fn io_error(action: &str, file: &str, err: &io::Error) -> String {
@@ -14,3 +19,132 @@ pub fn io_error(action: &str, file: &str, err: &io::Error) -> String {
         }
     )
 }
+
+/// Writes a file by writing to a temporary file in the same directory,
+/// syncing it to disk and renaming it over the file.
+///
+/// If the program crashes, the file contains either the old or the new data.
+pub fn write_atomic<F>(file: &str, f: F) -> io::Result<()>
+where
+    F: FnOnce(&mut io::BufWriter<File>) -> io::Result<()>,
+{
+    use std::fs;
+    use std::io::Write;
+
+    let path = Path::new(file);
+    let name = match path.file_name() {
+        Some(name) => name.to_string_lossy(),
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Expected path to file",
+            ))
+        }
+    };
+    let tmp = path.with_file_name(format!(".{}.{}.tmp", name, ::std::process::id()));
+    let res = File::create(&tmp).and_then(|t| {
+        let mut w = io::BufWriter::new(t);
+        f(&mut w)?;
+        w.flush()?;
+        w.get_ref().sync_all()?;
+        fs::rename(&tmp, path)
+    });
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp);
+        return res;
+    }
+    // Sync the directory, such that the rename is stored on disk.
+    #[cfg(unix)]
+    {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Computes the SHA-256 hash of everything read, as lowercase hexadecimal digits.
+#[cfg(feature = "crypto")]
+pub fn read_sha256<R: io::Read>(mut r: R) -> io::Result<String> {
+    use super::sha256::{hex, Digest, Sha256};
+
+    let mut hash = Sha256::new();
+    let mut buf = [0; 8192];
+    loop {
+        let n = r.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hash.update(&buf[..n]);
+    }
+    Ok(hex(&hash.finalize()))
+}
+
+/// A temporary file or directory, removed when dropped.
+pub struct TempPath {
+    pub path: String,
+    dir: bool,
+    vfs: Arc<dyn Vfs>,
+}
+
+impl TempPath {
+    /// Creates an empty file or directory with a unique name
+    /// in the temporary directory of a file system.
+    pub fn create(vfs: Arc<dyn Vfs>, prefix: &str, dir: bool) -> io::Result<TempPath> {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        if prefix.contains(['/', '\\']) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Expected prefix without path separators",
+            ));
+        }
+        let temp_dir = vfs.temp_dir()?;
+        vfs.create_dir(&temp_dir)?;
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or(0);
+        loop {
+            let name = format!(
+                "{}{}-{}-{}",
+                prefix,
+                ::std::process::id(),
+                nanos,
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            );
+            let path = Path::new(&temp_dir)
+                .join(name)
+                .to_string_lossy()
+                .into_owned();
+            let res = if dir {
+                if vfs.metadata(&path).is_ok() {
+                    continue;
+                }
+                vfs.create_dir(&path)
+            } else {
+                vfs.create_new(&path).map(|_| ())
+            };
+            match res {
+                Ok(()) => return Ok(TempPath { path, dir, vfs }),
+                Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = if self.dir {
+            self.vfs.remove_dir(&self.path)
+        } else {
+            self.vfs.remove_file(&self.path)
+        };
+    }
+}
@@ -88,5 +88,8 @@ fn min_ref(v: &Variable, min: &mut Option<usize>) {
         }
         Closure(_, _) => {}
         In(_) => {}
+        Out(_) => {}
+        Bytes(_) => {}
+        F64Array(_) => {}
     }
 }
@@ -3,13 +3,12 @@ use super::io::io_error;
 use piston_meta::{parse_errstr, MetaData, Syntax};
 #[cfg(all(not(target_family = "wasm"), feature = "file"))]
 use piston_meta::syntax_errstr;
-#[cfg(all(not(target_family = "wasm"), feature = "file"))]
-use std::fs::File;
 use std::io;
-#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+#[cfg(all(not(target_family = "wasm"), feature = "http"))]
 use std::io::Read;
 use std::sync::Arc;
 
+use vfs::Vfs;
 use Variable;
 
 pub fn parse_syntax_data(rules: &Syntax, file: &str, d: &str) -> Result<Vec<Variable>, String> {
@@ -66,22 +65,18 @@ fn load_metarules_data(meta: &str, s: &str, file: &str, d: &str) -> Result<Vec<V
 
 /// Loads a file using a meta file as syntax.
 #[cfg(all(not(target_family = "wasm"), feature = "file"))]
-pub fn load_meta_file(meta: &str, file: &str) -> Result<Vec<Variable>, String> {
-    let mut syntax_file = File::open(meta).map_err(|err| io_error("open", meta, &err))?;
-    let mut s = String::new();
-    syntax_file
-        .read_to_string(&mut s)
+pub fn load_meta_file(vfs: &dyn Vfs, meta: &str, file: &str) -> Result<Vec<Variable>, String> {
+    let s = vfs
+        .read_to_string(meta)
         .map_err(|err| io_error("read", meta, &err))?;
-    let mut data_file = File::open(file).map_err(|err| io_error("open", file, &err))?;
-    let mut d = String::new();
-    data_file
-        .read_to_string(&mut d)
+    let d = vfs
+        .read_to_string(file)
         .map_err(|err| io_error("read", file, &err))?;
     load_metarules_data(meta, &s, file, &d)
 }
 
 #[cfg(not(all(not(target_family = "wasm"), feature = "file")))]
-pub fn load_meta_file(_: &str, _: &str) -> Result<Vec<Variable>, String> {
+pub fn load_meta_file(_: &dyn Vfs, _: &str, _: &str) -> Result<Vec<Variable>, String> {
     Err(super::FILE_SUPPORT_DISABLED.into())
 }
 
@@ -126,26 +121,24 @@ pub fn load_text_file_from_url(_url: &str) -> Result<String, String> {
 
 /// Loads an url using a meta file as syntax.
 #[cfg(all(not(target_family = "wasm"), feature = "http"))]
-pub fn load_meta_url(meta: &str, url: &str) -> Result<Vec<Variable>, String> {
-    let mut syntax_file = File::open(meta).map_err(|err| io_error("open", meta, &err))?;
-    let mut s = String::new();
-    syntax_file
-        .read_to_string(&mut s)
+pub fn load_meta_url(vfs: &dyn Vfs, meta: &str, url: &str) -> Result<Vec<Variable>, String> {
+    let s = vfs
+        .read_to_string(meta)
         .map_err(|err| io_error("read", meta, &err))?;
     let d = load_text_file_from_url(url)?;
     load_metarules_data(meta, &s, url, &d)
 }
 
 #[cfg(not(all(not(target_family = "wasm"), feature = "http")))]
-pub fn load_meta_url(_meta: &str, _url: &str) -> Result<Vec<Variable>, String> {
+pub fn load_meta_url(_vfs: &dyn Vfs, _meta: &str, _url: &str) -> Result<Vec<Variable>, String> {
     Err(super::HTTP_SUPPORT_DISABLED.into())
 }
 
 // Downloads a file from url.
 #[cfg(all(not(target_family = "wasm"), feature = "http"))]
-pub fn download_url_to_file(url: &str, file: &str) -> Result<String, String> {
+pub fn download_url_to_file(vfs: &dyn Vfs, url: &str, file: &str) -> Result<String, String> {
     use reqwest::{Client, StatusCode, Url};
-    use std::io::copy;
+    use std::io::{copy, Write};
 
     let url_address = Url::parse(url).map_err(|e| format!("Error parsing url:\n`{}`\n", e))?;
     let client = Client::new();
@@ -158,15 +151,18 @@ pub fn download_url_to_file(url: &str, file: &str) -> Result<String, String> {
         )
     })?;
     if response.status() == StatusCode::OK {
-        let mut f = File::create(file)
+        let mut f = vfs
+            .create(file)
             .map_err(|err| format!("Could not create file `{}`:\n{}", file, err.to_string()))?;
-        copy(&mut response, &mut f).map_err(|e| {
-            format!(
-                "Error fetching file over http `{}`:\n{}\n",
-                url,
-                e.to_string()
-            )
-        })?;
+        copy(&mut response, &mut f)
+            .and_then(|_| f.flush())
+            .map_err(|e| {
+                format!(
+                    "Error fetching file over http `{}`:\n{}\n",
+                    url,
+                    e.to_string()
+                )
+            })?;
         Ok(file.into())
     } else {
         Err(format!(
@@ -178,7 +174,7 @@ pub fn download_url_to_file(url: &str, file: &str) -> Result<String, String> {
 }
 
 #[cfg(not(all(not(target_family = "wasm"), feature = "http")))]
-pub fn download_url_to_file(_url: &str, _file: &str) -> Result<String, String> {
+pub fn download_url_to_file(_vfs: &dyn Vfs, _url: &str, _file: &str) -> Result<String, String> {
     Err(super::HTTP_SUPPORT_DISABLED.into())
 }
 
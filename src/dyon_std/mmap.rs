@@ -0,0 +1,141 @@
+//! Read-only memory mapped files.
+//!
+//! On Unix, the file is mapped into memory, such that the operating system
+//! loads pages when they are read.
+//! On other platforms, reads are done from the file at the offset.
+//!
+//! Reading a page of a mapped file that another process truncated crashes
+//! the program with `SIGBUS`.
+//! To avoid this, a shared lock is held while the file is mapped,
+//! and when the lock can not be taken, reads are done from the file instead.
+//! Locks are advisory, so a process that truncates the file without locking it
+//! can still crash the program.
+//! Sandboxed file systems do not allow mapping, see `Vfs::allow_mmap`,
+//! in which case reads are always done from the file.
+
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use runtime::{ErrorKind, RuntimeError};
+
+/// A file opened for random access reads.
+pub struct Mmap {
+    /// The mapped memory, or null when reading from the file.
+    #[cfg(unix)]
+    ptr: *mut ::libc::c_void,
+    file: File,
+    len: usize,
+}
+
+impl Mmap {
+    /// Opens a file, mapping it into memory if `map` is `true`
+    /// and a shared lock can be taken.
+    #[cfg(unix)]
+    pub fn open(path: &Path, map: bool) -> io::Result<Mmap> {
+        use std::os::unix::io::AsRawFd;
+        use std::ptr;
+
+        let file = File::open(path)?;
+        let len = file.metadata()?.len() as usize;
+        // Mapping an empty file fails.
+        if !map || len == 0 || ::fs2::FileExt::try_lock_shared(&file).is_err() {
+            return Ok(Mmap {
+                ptr: ptr::null_mut(),
+                file,
+                len,
+            });
+        }
+        let ptr = unsafe {
+            ::libc::mmap(
+                ptr::null_mut(),
+                len,
+                ::libc::PROT_READ,
+                ::libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == ::libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mmap { ptr, file, len })
+    }
+
+    /// Opens a file.
+    #[cfg(not(unix))]
+    pub fn open(path: &Path, _map: bool) -> io::Result<Mmap> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len() as usize;
+        Ok(Mmap { file, len })
+    }
+
+    /// Returns the length of the file in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Reads bytes at an offset, filling the buffer.
+    ///
+    /// Returns an error if the range is out of bounds or reading fails.
+    pub fn read_at(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), RuntimeError> {
+        if offset > self.len || buf.len() > self.len - offset {
+            return Err(RuntimeError::new(
+                ErrorKind::IndexOutOfBounds {
+                    index: offset as f64,
+                },
+                None,
+                format!(
+                    "Range {}..{} is out of bounds for length {}",
+                    offset,
+                    offset + buf.len(),
+                    self.len
+                ),
+            ));
+        }
+        self.read_unchecked(offset, buf).map_err(|err| {
+            RuntimeError::new(
+                ErrorKind::Io,
+                None,
+                format!("Could not read memory mapped file:\n{}", err),
+            )
+        })
+    }
+
+    #[cfg(unix)]
+    fn read_unchecked(&mut self, offset: usize, buf: &mut [u8]) -> io::Result<()> {
+        use std::os::unix::fs::FileExt;
+
+        if buf.is_empty() {
+            return Ok(());
+        }
+        if self.ptr.is_null() {
+            // Fails instead of crashing when the file was truncated.
+            return self.file.read_exact_at(buf, offset as u64);
+        }
+        let data = unsafe { ::std::slice::from_raw_parts(self.ptr as *const u8, self.len) };
+        buf.copy_from_slice(&data[offset..offset + buf.len()]);
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn read_unchecked(&mut self, offset: usize, buf: &mut [u8]) -> io::Result<()> {
+        use std::io::{Read, Seek, SeekFrom};
+
+        self.file
+            .seek(SeekFrom::Start(offset as u64))
+            .and_then(|_| self.file.read_exact(buf))
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        // The shared lock is released when the file is closed, after unmapping.
+        if !self.ptr.is_null() {
+            unsafe {
+                ::libc::munmap(self.ptr, self.len);
+            }
+        }
+    }
+}
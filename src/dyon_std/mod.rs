@@ -2,12 +2,57 @@
 
 use *;
 
-mod data;
+use persistent::Persistent;
+use rope::Rope;
+use runtime::ErrorKind;
+
+#[cfg(all(not(target_family = "wasm"), feature = "threading"))]
+mod actor;
+pub(crate) mod bigint;
+mod bulk;
+mod calendar;
+mod cells;
+mod cluster;
+#[cfg(feature = "crypto")]
+mod content_hash;
+pub(crate) mod data;
+mod derivative;
+mod formula;
+mod frame;
 mod functions;
+#[cfg(all(not(target_family = "wasm"), feature = "gpu"))]
+mod gpu;
+#[cfg(feature = "crypto")]
+mod hash;
 #[cfg(all(not(target_family = "wasm"), feature = "file"))]
-mod io;
+pub(crate) mod io;
 mod lifetimechk;
 mod meta;
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+mod mmap;
+#[cfg(feature = "msgpack")]
+mod msgpack;
+mod nn;
+mod noise;
+mod ode;
+mod optimize;
+#[cfg(all(not(target_family = "wasm"), feature = "threading"))]
+mod parallel;
+mod procgen;
+mod query;
+#[cfg(all(not(target_family = "wasm"), feature = "rpc"))]
+mod rpc;
+#[cfg(feature = "crypto")]
+pub(crate) mod sha256;
+mod solve;
+mod spline;
+#[cfg(all(not(target_family = "wasm"), feature = "threading"))]
+mod supervisor;
+mod sync;
+#[cfg(feature = "toml")]
+mod toml;
+#[cfg(feature = "yaml")]
+mod yaml;
 
 #[cfg(not(all(not(target_family = "wasm"), feature = "http")))]
 const HTTP_SUPPORT_DISABLED: &'static str = "Http support is disabled";
@@ -15,7 +60,110 @@ const HTTP_SUPPORT_DISABLED: &'static str = "Http support is disabled";
 #[cfg(not(all(not(target_family = "wasm"), feature = "file")))]
 const FILE_SUPPORT_DISABLED: &'static str = "File support is disabled";
 
-pub(crate) fn and_also(rt: &mut Runtime) -> Result<Variable, String> {
+#[cfg(not(feature = "regex"))]
+const REGEX_SUPPORT_DISABLED: &str = "Regex support is disabled";
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "process")))]
+const PROCESS_SUPPORT_DISABLED: &str = "Process support is disabled";
+
+#[cfg(not(feature = "msgpack"))]
+const MSGPACK_SUPPORT_DISABLED: &str = "MessagePack support is disabled";
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "rpc")))]
+const RPC_SUPPORT_DISABLED: &str = "RPC support is disabled";
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "threading")))]
+const THREADING_SUPPORT_DISABLED: &str = "Threading support is disabled";
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "gpu")))]
+const GPU_SUPPORT_DISABLED: &str = "GPU support is disabled";
+
+#[cfg(not(feature = "crypto"))]
+const CRYPTO_SUPPORT_DISABLED: &str = "Crypto support is disabled";
+
+#[cfg(not(feature = "toml"))]
+const TOML_SUPPORT_DISABLED: &str = "TOML support is disabled";
+
+#[cfg(not(feature = "yaml"))]
+const YAML_SUPPORT_DISABLED: &str = "YAML support is disabled";
+
+/// Intrinsics that report an error when the crate feature they need is disabled.
+///
+/// Keep this in sync when adding a stub for a disabled feature.
+pub(crate) const FEATURE_FUNCTIONS: &[(&str, &[&str])] = &[
+    (
+        "file",
+        &[
+            "save__string_file",
+            "save_atomic__string_file",
+            "save__string_file_atomic",
+            "sha256__file",
+            "verify__file_sha256",
+            "load_string__file",
+            "load_bytes__file",
+            "save_bytes__file",
+            "load_data__file",
+            "load_toml__file",
+            "load_yaml__file",
+            "save__data_file",
+            "save__data_file_atomic",
+            "load__meta_file",
+            "mmap",
+            "mmap_len",
+            "read_f32_at",
+            "read_bytes_at",
+            "lock_file",
+            "lock_file_shared",
+            "unlock",
+            "temp_file",
+            "temp_dir",
+            "temp_path",
+            "read_dir",
+            "create_dir",
+            "remove_file",
+            "file_exists",
+            "is_dir",
+            "file_size",
+            "data_writer",
+        ],
+    ),
+    (
+        "http",
+        &["load_string__url", "load__meta_url", "download__url_file"],
+    ),
+    ("regex", &["regex_match", "regex_captures", "regex_replace"]),
+    ("process", &["exec", "exec_stream"]),
+    ("msgpack", &["to_msgpack", "from_msgpack"]),
+    ("rpc", &["rpc_serve", "rpc_connect"]),
+    (
+        "threading",
+        &[
+            "actor",
+            "ask",
+            "supervisor",
+            "supervise",
+            "failures",
+            "parallel_for",
+            "parallel_reduce",
+        ],
+    ),
+    ("gpu", &["gpu_map", "gpu_mat_mul"]),
+    (
+        "crypto",
+        &[
+            "hash",
+            "crc32",
+            "sha256",
+            "content_hash",
+            "sha256__file",
+            "verify__file_sha256",
+        ],
+    ),
+    ("toml", &["load_toml__file", "load_toml__string"]),
+    ("yaml", &["load_yaml__file", "load_yaml__string"]),
+];
+
+pub(crate) fn and_also(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     use Variable::*;
 
     let b = rt.stack.pop().expect(TINVOTS);
@@ -26,7 +174,7 @@ pub(crate) fn and_also(rt: &mut Runtime) -> Result<Variable, String> {
     })
 }
 
-pub(crate) fn or_else(rt: &mut Runtime) -> Result<Variable, String> {
+pub(crate) fn or_else(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     use Variable::*;
 
     let b = rt.stack.pop().expect(TINVOTS);
@@ -43,6 +191,9 @@ pub(crate) fn less(a: &Variable, b: &Variable) -> Result<Variable, String> {
     Ok(match (a, b) {
         (&F64(a, ref sec), &F64(b, _)) => Bool(a < b, sec.clone()),
         (&Str(ref a), &Str(ref b)) => Variable::bool(a < b),
+        (&RustObject(_), _) | (_, &RustObject(_)) => {
+            bigint::binop(a, b, |a, b| Ok(Variable::bool(a < b)))?
+        }
         _ => return Err("Expected `f64` or `str`".into()),
     })
 }
@@ -53,6 +204,9 @@ pub(crate) fn less_or_equal(a: &Variable, b: &Variable) -> Result<Variable, Stri
     Ok(match (a, b) {
         (&F64(a, ref sec), &F64(b, _)) => Bool(a <= b, sec.clone()),
         (&Str(ref a), &Str(ref b)) => Variable::bool(a <= b),
+        (&RustObject(_), _) | (_, &RustObject(_)) => {
+            bigint::binop(a, b, |a, b| Ok(Variable::bool(a <= b)))?
+        }
         _ => return Err("Expected `f64` or `str`".into()),
     })
 }
@@ -85,6 +239,8 @@ pub(crate) fn equal(a: &Variable, b: &Variable) -> Result<Variable, String> {
         (&Str(ref a), &Str(ref b)) => Variable::bool(a == b),
         (&Bool(a, ref sec), &Bool(b, _)) => Bool(a == b, sec.clone()),
         (&Vec4(a), &Vec4(b)) => Variable::bool(a == b),
+        (&Bytes(ref a), &Bytes(ref b)) => Variable::bool(a == b),
+        (&F64Array(ref a), &F64Array(ref b)) => Variable::bool(a == b),
         (&Object(ref a), &Object(ref b)) => Variable::bool(
             a.len() == b.len()
                 && a.iter().all(|a| {
@@ -105,7 +261,14 @@ pub(crate) fn equal(a: &Variable, b: &Variable) -> Result<Variable, String> {
         (&Option(None), &Option(_)) => Variable::bool(false),
         (&Option(_), &Option(None)) => Variable::bool(false),
         (&Option(Some(ref a)), &Option(Some(ref b))) => equal(a, b)?,
-        _ => return Err("Expected `f64`, `str`, `bool`, `vec4`, `{}`, `[]` or `opt`".into()),
+        (&RustObject(_), _) | (_, &RustObject(_)) => {
+            bigint::binop(a, b, |a, b| Ok(Variable::bool(a == b)))?
+        }
+        _ => {
+            return Err(
+                "Expected `f64`, `str`, `bool`, `vec4`, `bytes`, `{}`, `[]` or `opt`".into(),
+            )
+        }
     })
 }
 
@@ -126,12 +289,12 @@ pub(crate) fn add(a: &Variable, b: &Variable) -> Result<Variable, String> {
         (&F64(a, ref sec), &F64(b, _)) => F64(a + b, sec.clone()),
         (&Vec4(a), &Vec4(b)) => Vec4(vecmath::vec4_add(a, b)),
         (&Vec4(a), &F64(b, _)) | (&F64(b, _), &Vec4(a)) => {
-            let b = b as f32;
+            let b = b as Scalar;
             Vec4([a[0] + b, a[1] + b, a[2] + b, a[3] + b])
         }
         (&Mat4(ref a), &Mat4(ref b)) => Mat4(Box::new(vecmath::mat4_add(**a, **b))),
         (&F64(a, _), &Mat4(ref b)) | (&Mat4(ref b), &F64(a, _)) => {
-            let a = a as f32;
+            let a = a as Scalar;
             Mat4(Box::new([
                 [b[0][0] + a, b[0][1] + a, b[0][2] + a, b[0][3] + a],
                 [b[1][0] + a, b[1][1] + a, b[1][2] + a, b[1][3] + a],
@@ -147,6 +310,9 @@ pub(crate) fn add(a: &Variable, b: &Variable) -> Result<Variable, String> {
             Str(Arc::new(res))
         }
         (&Link(ref a), &Link(ref b)) => Link(Box::new(a.add(b))),
+        (&RustObject(_), _) | (_, &RustObject(_)) => {
+            bigint::binop(a, b, |a, b| Ok(bigint::var(a.add(&b))))?
+        }
         _ => return Err("Expected `f64`, `vec4`, `mat4`, `bool`, `str` or `link`".into()),
     })
 }
@@ -158,16 +324,16 @@ pub(crate) fn sub(a: &Variable, b: &Variable) -> Result<Variable, String> {
         (&F64(a, ref sec), &F64(b, _)) => F64(a - b, sec.clone()),
         (&Vec4(a), &Vec4(b)) => Vec4(vecmath::vec4_sub(a, b)),
         (&Vec4(a), &F64(b, _)) => {
-            let b = b as f32;
+            let b = b as Scalar;
             Vec4([a[0] - b, a[1] - b, a[2] - b, a[3] - b])
         }
         (&F64(a, _), &Vec4(b)) => {
-            let a = a as f32;
+            let a = a as Scalar;
             Vec4([a - b[0], a - b[1], a - b[2], a - b[3]])
         }
         (&Mat4(ref a), &Mat4(ref b)) => Mat4(Box::new(vecmath::mat4_sub(**a, **b))),
         (&F64(a, _), &Mat4(ref b)) => {
-            let a = a as f32;
+            let a = a as Scalar;
             Mat4(Box::new([
                 [a - b[0][0], a - b[0][1], a - b[0][2], a - b[0][3]],
                 [a - b[1][0], a - b[1][1], a - b[1][2], a - b[1][3]],
@@ -176,7 +342,7 @@ pub(crate) fn sub(a: &Variable, b: &Variable) -> Result<Variable, String> {
             ]))
         }
         (&Mat4(ref b), &F64(a, _)) => {
-            let a = a as f32;
+            let a = a as Scalar;
             Mat4(Box::new([
                 [b[0][0] - a, b[0][1] - a, b[0][2] - a, b[0][3] - a],
                 [b[1][0] - a, b[1][1] - a, b[1][2] - a, b[1][3] - a],
@@ -185,6 +351,9 @@ pub(crate) fn sub(a: &Variable, b: &Variable) -> Result<Variable, String> {
             ]))
         }
         (&Bool(a, ref sec), &Bool(b, _)) => Bool(a && !b, sec.clone()),
+        (&RustObject(_), _) | (_, &RustObject(_)) => {
+            bigint::binop(a, b, |a, b| Ok(bigint::var(a.sub(&b))))?
+        }
         _ => return Err("Expected `f64`, `vec4`, `mat4` or `bool`".into()),
     })
 }
@@ -196,12 +365,12 @@ pub(crate) fn mul(a: &Variable, b: &Variable) -> Result<Variable, String> {
         (&F64(a, ref sec), &F64(b, _)) => F64(a * b, sec.clone()),
         (&Vec4(a), &Vec4(b)) => Vec4(vecmath::vec4_mul(a, b)),
         (&Vec4(a), &F64(b, _)) | (&F64(b, _), &Vec4(a)) => {
-            let b = b as f32;
+            let b = b as Scalar;
             Vec4([a[0] * b, a[1] * b, a[2] * b, a[3] * b])
         }
         (&Mat4(ref a), &Mat4(ref b)) => Mat4(Box::new(vecmath::col_mat4_mul(**a, **b))),
         (&F64(a, _), &Mat4(ref b)) | (&Mat4(ref b), &F64(a, _)) => {
-            let a = a as f32;
+            let a = a as Scalar;
             Mat4(Box::new([
                 [b[0][0] * a, b[0][1] * a, b[0][2] * a, b[0][3] * a],
                 [b[1][0] * a, b[1][1] * a, b[1][2] * a, b[1][3] * a],
@@ -211,6 +380,9 @@ pub(crate) fn mul(a: &Variable, b: &Variable) -> Result<Variable, String> {
         }
         (&Mat4(ref a), &Vec4(b)) => Vec4(vecmath::col_mat4_transform(**a, b)),
         (&Bool(a, ref sec), &Bool(b, _)) => Bool(a && b, sec.clone()),
+        (&RustObject(_), _) | (_, &RustObject(_)) => {
+            bigint::binop(a, b, |a, b| Ok(bigint::var(a.mul(&b))))?
+        }
         _ => return Err("Expected `f64`, `vec4`, `mat4` or `bool`".into()),
     })
 }
@@ -222,13 +394,16 @@ pub(crate) fn div(a: &Variable, b: &Variable) -> Result<Variable, String> {
         (&F64(a, ref sec), &F64(b, _)) => F64(a / b, sec.clone()),
         (&Vec4(a), &Vec4(b)) => Vec4([a[0] / b[0], a[1] / b[1], a[2] / b[2], a[3] / b[3]]),
         (&Vec4(a), &F64(b, _)) => {
-            let b = b as f32;
+            let b = b as Scalar;
             Vec4([a[0] / b, a[1] / b, a[2] / b, a[3] / b])
         }
         (&F64(a, _), &Vec4(b)) => {
-            let a = a as f32;
+            let a = a as Scalar;
             Vec4([a / b[0], a / b[1], a / b[2], a / b[3]])
         }
+        (&RustObject(_), _) | (_, &RustObject(_)) => {
+            bigint::binop(a, b, |a, b| Ok(bigint::var(a.div_rem(&b)?.0)))?
+        }
         _ => return Err("Expected `f64` or `vec4`".into()),
     })
 }
@@ -240,13 +415,16 @@ pub(crate) fn rem(a: &Variable, b: &Variable) -> Result<Variable, String> {
         (&F64(a, ref sec), &F64(b, _)) => F64(a % b, sec.clone()),
         (&Vec4(a), &Vec4(b)) => Vec4([a[0] % b[0], a[1] % b[1], a[2] % b[2], a[3] % b[3]]),
         (&Vec4(a), &F64(b, _)) => {
-            let b = b as f32;
+            let b = b as Scalar;
             Vec4([a[0] % b, a[1] % b, a[2] % b, a[3] % b])
         }
         (&F64(a, _), &Vec4(b)) => {
-            let a = a as f32;
+            let a = a as Scalar;
             Vec4([a % b[0], a % b[1], a % b[2], a % b[3]])
         }
+        (&RustObject(_), _) | (_, &RustObject(_)) => {
+            bigint::binop(a, b, |a, b| Ok(bigint::var(a.div_rem(&b)?.1)))?
+        }
         _ => return Err("Expected `f64` or `vec4`".into()),
     })
 }
@@ -263,11 +441,11 @@ pub(crate) fn pow(a: &Variable, b: &Variable) -> Result<Variable, String> {
             a[3].powf(b[3]),
         ]),
         (&Vec4(a), &F64(b, _)) => {
-            let b = b as f32;
+            let b = b as Scalar;
             Vec4([a[0].powf(b), a[1].powf(b), a[2].powf(b), a[3].powf(b)])
         }
         (&F64(a, _), &Vec4(b)) => {
-            let a = a as f32;
+            let a = a as Scalar;
             Vec4([a.powf(b[0]), a.powf(b[1]), a.powf(b[2]), a.powf(b[3])])
         }
         (&Bool(a, ref sec), &Bool(ref b, _)) => Bool(a ^ b, sec.clone()),
@@ -292,6 +470,10 @@ pub(crate) fn neg(a: &Variable) -> Result<Variable, String> {
             [-m[2][0], -m[2][1], -m[2][2], -m[2][3]],
             [-m[3][0], -m[3][1], -m[3][2], -m[3][3]],
         ])),
+        Variable::RustObject(_) => match bigint::operand(a) {
+            Some(x) => bigint::var(x.neg()),
+            None => return Err("Expected `f64`, `vec4`, `mat4` or `bigint`".into()),
+        },
         _ => return Err("Expected `f64`, `vec4` or `mat4`".into()),
     })
 }
@@ -300,7 +482,7 @@ pub(crate) fn dot(a: &Variable, b: &Variable) -> Result<Variable, String> {
     Ok(Variable::f64(match (a, b) {
         (&Variable::Vec4(a), &Variable::Vec4(b)) => vecmath::vec4_dot(a, b) as f64,
         (&Variable::Vec4(a), &Variable::F64(b, _)) | (&Variable::F64(b, _), &Variable::Vec4(a)) => {
-            let b = b as f32;
+            let b = b as Scalar;
             (a[0] * b + a[1] * b + a[2] * b + a[3] * b) as f64
         }
         _ => return Err("Expected (vec4, vec4), (vec4, f64) or (f64, vec4)".into()),
@@ -326,18 +508,136 @@ pub(crate) fn norm(v: &Variable) -> Result<Variable, String> {
     }
 }
 
-pub(crate) fn s(rt: &mut Runtime) -> Result<Variable, String> {
+pub(crate) fn s(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     let ind: f64 = rt.pop().expect(TINVOTS);
     let ind = ind as usize;
     if ind >= 4 {
-        return Err(format!("Index out of bounds `{}`", ind));
+        return Err(format!("Index out of bounds `{}`", ind).into());
     };
-    let v: [f32; 4] = rt.pop_vec4().expect(TINVOTS);
+    let v: [Scalar; 4] = rt.pop_vec4().expect(TINVOTS);
     Ok(Variable::f64(f64::from(v[ind])))
 }
 
+pub(crate) fn swizzle(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let components: Arc<String> = rt.pop().expect(TINVOTS);
+    let v: [Scalar; 4] = rt.pop_vec4().expect(TINVOTS);
+    if components.is_empty() || components.chars().count() > 4 {
+        return Err({
+            rt.arg_err_index.set(Some(1));
+            "Expected 1 to 4 components".into()
+        });
+    }
+    let mut res = [0.0; 4];
+    for (r, ch) in res.iter_mut().zip(components.chars()) {
+        *r = match ch {
+            'x' | 'r' => v[0],
+            'y' | 'g' => v[1],
+            'z' | 'b' => v[2],
+            'w' | 'a' => v[3],
+            _ => {
+                return Err({
+                    rt.arg_err_index.set(Some(1));
+                    format!(
+                        "Unknown component `{}`, expected `x`, `y`, `z`, `w` or `r`, `g`, `b`, `a`",
+                        ch
+                    )
+                    .into()
+                })
+            }
+        };
+    }
+    Ok(Variable::Vec4(res))
+}
+
 dyon_fn! {fn det(m: Mat4) -> f64 {f64::from(vecmath::mat4_det(m.0))}}
 dyon_fn! {fn inv(m: Mat4) -> Mat4 {Mat4(vecmath::mat4_inv(m.0))}}
+dyon_fn! {fn transpose(m: Mat4) -> Mat4 {Mat4(vecmath::mat4_transposed(m.0))}}
+dyon_fn! {fn mat_lerp(a: Mat4, b: Mat4, t: f64) -> Mat4 {
+    let t = t as Scalar;
+    let mut m = a.0;
+    for (col, b) in m.iter_mut().zip(&b.0) {
+        for (x, b) in col.iter_mut().zip(b) {
+            *x += (b - *x) * t;
+        }
+    }
+    Mat4(m)
+}}
+
+/// Decomposes a transform into translation, rotation quaternion and scale.
+pub(crate) fn decompose(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let m: [[Scalar; 4]; 4] = rt.pop_mat4().expect(TINVOTS);
+    let col = |i: usize| [f64::from(m[i][0]), f64::from(m[i][1]), f64::from(m[i][2])];
+    let mut scale = [
+        vecmath::vec3_len(col(0)),
+        vecmath::vec3_len(col(1)),
+        vecmath::vec3_len(col(2)),
+    ];
+    // A mirrored transform is decomposed with a negative x scale.
+    if vecmath::mat4_det(m) < 0.0 {
+        scale[0] = -scale[0];
+    }
+    // Rotation matrix `r[row][col]` with the scale removed.
+    let mut r = [[0.0; 3]; 3];
+    for (i, s) in scale.iter().enumerate() {
+        let c = col(i);
+        for (j, row) in r.iter_mut().enumerate() {
+            row[i] = if *s == 0.0 { 0.0 } else { c[j] / s };
+        }
+    }
+    let trace = r[0][0] + r[1][1] + r[2][2];
+    let q = if trace > 0.0 {
+        let s = 0.5 / (trace + 1.0).sqrt();
+        [
+            (r[2][1] - r[1][2]) * s,
+            (r[0][2] - r[2][0]) * s,
+            (r[1][0] - r[0][1]) * s,
+            0.25 / s,
+        ]
+    } else if r[0][0] > r[1][1] && r[0][0] > r[2][2] {
+        let s = 2.0 * (1.0 + r[0][0] - r[1][1] - r[2][2]).sqrt();
+        [
+            0.25 * s,
+            (r[0][1] + r[1][0]) / s,
+            (r[0][2] + r[2][0]) / s,
+            (r[2][1] - r[1][2]) / s,
+        ]
+    } else if r[1][1] > r[2][2] {
+        let s = 2.0 * (1.0 + r[1][1] - r[0][0] - r[2][2]).sqrt();
+        [
+            (r[0][1] + r[1][0]) / s,
+            0.25 * s,
+            (r[1][2] + r[2][1]) / s,
+            (r[0][2] - r[2][0]) / s,
+        ]
+    } else {
+        let s = 2.0 * (1.0 + r[2][2] - r[0][0] - r[1][1]).sqrt();
+        [
+            (r[0][2] + r[2][0]) / s,
+            (r[1][2] + r[2][1]) / s,
+            0.25 * s,
+            (r[1][0] - r[0][1]) / s,
+        ]
+    };
+    let mut obj = HashMap::new();
+    obj.insert(
+        Arc::new("translation".into()),
+        Variable::Vec4([m[3][0], m[3][1], m[3][2], 0.0]),
+    );
+    obj.insert(
+        Arc::new("rotation".into()),
+        Variable::Vec4(quat_vec4(vecmath::vec4_normalized(q)).0),
+    );
+    obj.insert(
+        Arc::new("scale".into()),
+        Variable::Vec4([
+            scale[0] as Scalar,
+            scale[1] as Scalar,
+            scale[2] as Scalar,
+            0.0,
+        ]),
+    );
+    Ok(Variable::Object(Arc::new(obj)))
+}
 dyon_fn! {fn mov(v: Vec4) -> Mat4 {Mat4([
     [1.0, 0.0, 0.0, 0.0],
     [0.0, 1.0, 0.0, 0.0],
@@ -351,26 +651,113 @@ dyon_fn! {fn rot__axis_angle(axis: Vec4, ang: f64) -> Mat4 {
     let inv_cos = 1.0 - cos;
     Mat4([
         [
-            (cos + axis[0] * axis[0] * inv_cos) as f32,
-            (axis[0] * axis[1] * inv_cos - axis[2] * sin) as f32,
-            (axis[0] * axis[2] * inv_cos + axis[1] * sin) as f32,
+            (cos + axis[0] * axis[0] * inv_cos) as Scalar,
+            (axis[0] * axis[1] * inv_cos - axis[2] * sin) as Scalar,
+            (axis[0] * axis[2] * inv_cos + axis[1] * sin) as Scalar,
             0.0
         ],
         [
-            (axis[1] * axis[0] * inv_cos + axis[2] * sin) as f32,
-            (cos + axis[1] * axis[1] * inv_cos) as f32,
-            (axis[1] * axis[2] * inv_cos - axis[0] * sin) as f32,
+            (axis[1] * axis[0] * inv_cos + axis[2] * sin) as Scalar,
+            (cos + axis[1] * axis[1] * inv_cos) as Scalar,
+            (axis[1] * axis[2] * inv_cos - axis[0] * sin) as Scalar,
             0.0
         ],
         [
-            (axis[2] * axis[0] * inv_cos - axis[1] * sin) as f32,
-            (axis[2] * axis[1] * inv_cos + axis[0] * sin) as f32,
-            (cos + axis[2] * axis[2] * inv_cos) as f32,
+            (axis[2] * axis[0] * inv_cos - axis[1] * sin) as Scalar,
+            (axis[2] * axis[1] * inv_cos + axis[0] * sin) as Scalar,
+            (cos + axis[2] * axis[2] * inv_cos) as Scalar,
             0.0
         ],
         [0.0,0.0,0.0,1.0]
     ])
 }}
+/// Converts a quaternion `(x, y, z, w)` to `f64` for precision.
+fn quat_f64(q: Vec4) -> [f64; 4] {
+    [
+        f64::from(q.0[0]),
+        f64::from(q.0[1]),
+        f64::from(q.0[2]),
+        f64::from(q.0[3]),
+    ]
+}
+fn quat_vec4(q: [f64; 4]) -> Vec4 {
+    Vec4([
+        q[0] as Scalar,
+        q[1] as Scalar,
+        q[2] as Scalar,
+        q[3] as Scalar,
+    ])
+}
+/// Multiplies quaternions, which combines the rotation `b` followed by `a`.
+fn quat_mul_f64(a: [f64; 4], b: [f64; 4]) -> [f64; 4] {
+    use vecmath::{vec3_add as add, vec3_cross as cross, vec3_dot as dot, vec3_scale as scale};
+    let (av, bv) = ([a[0], a[1], a[2]], [b[0], b[1], b[2]]);
+    let v = add(add(scale(bv, a[3]), scale(av, b[3])), cross(av, bv));
+    [v[0], v[1], v[2], a[3] * b[3] - dot(av, bv)]
+}
+dyon_fn! {fn quat__axis_angle(axis: Vec4, ang: f64) -> Vec4 {
+    let axis = vecmath::vec3_normalized([
+        f64::from(axis.0[0]),
+        f64::from(axis.0[1]),
+        f64::from(axis.0[2])
+    ]);
+    let sin = (0.5 * ang).sin();
+    quat_vec4([axis[0] * sin, axis[1] * sin, axis[2] * sin, (0.5 * ang).cos()])
+}}
+dyon_fn! {fn quat_mul(a: Vec4, b: Vec4) -> Vec4 {
+    quat_vec4(quat_mul_f64(quat_f64(a), quat_f64(b)))
+}}
+dyon_fn! {fn quat_rotate(q: Vec4, v: Vec4) -> Vec4 {
+    let q = quat_f64(q);
+    let conj = [-q[0], -q[1], -q[2], q[3]];
+    let v = quat_f64(v);
+    let r = quat_mul_f64(quat_mul_f64(q, [v[0], v[1], v[2], 0.0]), conj);
+    quat_vec4([r[0], r[1], r[2], v[3]])
+}}
+dyon_fn! {fn slerp(a: Vec4, b: Vec4, t: f64) -> Vec4 {
+    let a = quat_f64(a);
+    let mut b = quat_f64(b);
+    let mut cos = vecmath::vec4_dot(a, b);
+    // Take the shortest path, since `q` and `-q` are the same rotation.
+    if cos < 0.0 {
+        b = vecmath::vec4_neg(b);
+        cos = -cos;
+    }
+    let (wa, wb) = if cos > 0.9995 {
+        // Interpolate linearly when the angle is too small to divide by its sine.
+        (1.0 - t, t)
+    } else {
+        let ang = cos.acos();
+        let sin = ang.sin();
+        (((1.0 - t) * ang).sin() / sin, (t * ang).sin() / sin)
+    };
+    let q = vecmath::vec4_add(vecmath::vec4_scale(a, wa), vecmath::vec4_scale(b, wb));
+    quat_vec4(vecmath::vec4_normalized(q))
+}}
+dyon_fn! {fn quat_to_mat(q: Vec4) -> Mat4 {
+    let [x, y, z, w] = quat_f64(q);
+    Mat4([
+        [
+            (1.0 - 2.0 * (y * y + z * z)) as Scalar,
+            (2.0 * (x * y + z * w)) as Scalar,
+            (2.0 * (x * z - y * w)) as Scalar,
+            0.0
+        ],
+        [
+            (2.0 * (x * y - z * w)) as Scalar,
+            (1.0 - 2.0 * (x * x + z * z)) as Scalar,
+            (2.0 * (y * z + x * w)) as Scalar,
+            0.0
+        ],
+        [
+            (2.0 * (x * z + y * w)) as Scalar,
+            (2.0 * (y * z - x * w)) as Scalar,
+            (1.0 - 2.0 * (x * x + y * y)) as Scalar,
+            0.0
+        ],
+        [0.0, 0.0, 0.0, 1.0]
+    ])
+}}
 dyon_fn! {fn ortho__pos_right_up_forward(pos: Vec4, right: Vec4, up: Vec4, forward: Vec4) -> Mat4 {
     use vecmath::vec4_dot as dot;
     Mat4([
@@ -383,10 +770,10 @@ dyon_fn! {fn ortho__pos_right_up_forward(pos: Vec4, right: Vec4, up: Vec4, forwa
 dyon_fn! {fn proj__fov_near_far_ar(fov: f64, near: f64, far: f64, ar: f64) -> Mat4 {
     let f = 1.0 / (fov * ::std::f64::consts::PI).tan();
     Mat4([
-        [(f/ar) as f32, 0.0, 0.0, 0.0],
-        [0.0, f as f32, 0.0, 0.0],
-        [0.0, 0.0, ((far + near) / (near - far)) as f32, -1.0],
-        [0.0, 0.0, ((2.0 * far * near) / (near - far)) as f32, 0.0],
+        [(f/ar) as Scalar, 0.0, 0.0, 0.0],
+        [0.0, f as Scalar, 0.0, 0.0],
+        [0.0, 0.0, ((far + near) / (near - far)) as Scalar, -1.0],
+        [0.0, 0.0, ((2.0 * far * near) / (near - far)) as Scalar, 0.0],
     ])
 }}
 dyon_fn! {fn mvp__model_view_projection(model: Mat4, view: Mat4, proj: Mat4) -> Mat4 {
@@ -400,18 +787,119 @@ dyon_fn! {fn scale(v: Vec4) -> Mat4 {Mat4([
     [0.0, 0.0, 0.0, 1.0],
 ])}}
 
+dyon_fn! {fn mov2(v: Vec4) -> Mat4 {Mat4([
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [v.0[0], v.0[1], 0.0, 1.0],
+])}}
+dyon_fn! {fn rot2(ang: f64) -> Mat4 {
+    let (sin, cos) = (ang.sin() as Scalar, ang.cos() as Scalar);
+    Mat4([
+        [cos, sin, 0.0, 0.0],
+        [-sin, cos, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+    ])
+}}
+dyon_fn! {fn scale2(v: Vec4) -> Mat4 {Mat4([
+    [v.0[0], 0.0, 0.0, 0.0],
+    [0.0, v.0[1], 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+])}}
+dyon_fn! {fn transform2(m: Mat4, v: Vec4) -> Vec4 {
+    let m = m.0;
+    let (x, y) = (v.0[0], v.0[1]);
+    Vec4([
+        m[0][0] * x + m[1][0] * y + m[3][0],
+        m[0][1] * x + m[1][1] * y + m[3][1],
+        0.0,
+        0.0,
+    ])
+}}
+
+/// Reads an array of vec4 argument.
+fn vec4_array_arg(rt: &Runtime, i: usize, v: &Variable) -> Result<Vec<[Scalar; 4]>, RuntimeError> {
+    let arr = match *rt.resolve(v) {
+        Variable::Array(ref arr) => arr,
+        ref x => return Err(rt.expected_arg(i, x, "[vec4]")),
+    };
+    let mut res = Vec::with_capacity(arr.len());
+    for v in arr.iter() {
+        match *rt.resolve(v) {
+            Variable::Vec4(v) => res.push(v),
+            ref x => return Err(rt.expected_arg(i, x, "vec4")),
+        }
+    }
+    Ok(res)
+}
+
+/// Checks that two arrays of vec4 have equal length.
+fn equal_len(a: &[[Scalar; 4]], b: &[[Scalar; 4]]) -> Result<(), String> {
+    if a.len() != b.len() {
+        return Err(format!(
+            "Expected arrays of equal length, found `{}` and `{}`",
+            a.len(),
+            b.len()
+        ));
+    }
+    Ok(())
+}
+
+fn vec4_array(arr: Vec<[Scalar; 4]>) -> Variable {
+    Variable::Array(Arc::new(arr.into_iter().map(Variable::Vec4).collect()))
+}
+
+pub(crate) fn add_all(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let b = rt.stack.pop().expect(TINVOTS);
+    let b = vec4_array_arg(rt, 1, &b)?;
+    let a = rt.stack.pop().expect(TINVOTS);
+    let a = vec4_array_arg(rt, 0, &a)?;
+    equal_len(&a, &b)?;
+    Ok(vec4_array(bulk::add_all(&a, &b)))
+}
+
+pub(crate) fn scale_all(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let s: f64 = rt.pop().expect(TINVOTS);
+    let arr = rt.stack.pop().expect(TINVOTS);
+    let arr = vec4_array_arg(rt, 0, &arr)?;
+    Ok(vec4_array(bulk::scale_all(&arr, s as Scalar)))
+}
+
+pub(crate) fn dot_all(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let b = rt.stack.pop().expect(TINVOTS);
+    let b = vec4_array_arg(rt, 1, &b)?;
+    let a = rt.stack.pop().expect(TINVOTS);
+    let a = vec4_array_arg(rt, 0, &a)?;
+    equal_len(&a, &b)?;
+    Ok(Variable::Array(Arc::new(
+        bulk::dot_all(&a, &b)
+            .into_iter()
+            .map(|x| Variable::f64(f64::from(x)))
+            .collect(),
+    )))
+}
+
+pub(crate) fn transform_all(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let points = rt.stack.pop().expect(TINVOTS);
+    let points = vec4_array_arg(rt, 1, &points)?;
+    let m: [[Scalar; 4]; 4] = rt.pop_mat4().expect(TINVOTS);
+    Ok(vec4_array(bulk::transform_all(&m, &points)))
+}
+
 dyon_fn! {fn rx(m: Mat4) -> Vec4 {Vec4([m.0[0][0], m.0[1][0], m.0[2][0], m.0[3][0]])}}
 dyon_fn! {fn ry(m: Mat4) -> Vec4 {Vec4([m.0[0][1], m.0[1][1], m.0[2][1], m.0[3][1]])}}
 dyon_fn! {fn rz(m: Mat4) -> Vec4 {Vec4([m.0[0][2], m.0[1][2], m.0[2][2], m.0[3][2]])}}
 dyon_fn! {fn rw(m: Mat4) -> Vec4 {Vec4([m.0[0][3], m.0[1][3], m.0[2][3], m.0[3][3]])}}
 
-pub(crate) fn rv(rt: &mut Runtime) -> Result<Variable, String> {
+pub(crate) fn rv(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     let ind: f64 = rt.pop().expect(TINVOTS);
     let ind = ind as usize;
     if ind >= 4 {
-        return Err(format!("Index out of bounds `{}`", ind));
+        return Err(format!("Index out of bounds `{}`", ind).into());
     };
-    let m: [[f32; 4]; 4] = rt.pop_mat4().expect(TINVOTS);
+    let m: [[Scalar; 4]; 4] = rt.pop_mat4().expect(TINVOTS);
     Ok(Variable::Vec4([m[0][ind], m[1][ind], m[2][ind], m[3][ind]]))
 }
 
@@ -420,22 +908,22 @@ dyon_fn! {fn cy(m: Mat4) -> Vec4 {Vec4(m.0[1])}}
 dyon_fn! {fn cz(m: Mat4) -> Vec4 {Vec4(m.0[2])}}
 dyon_fn! {fn cw(m: Mat4) -> Vec4 {Vec4(m.0[3])}}
 
-pub(crate) fn cv(rt: &mut Runtime) -> Result<Variable, String> {
+pub(crate) fn cv(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     let ind: f64 = rt.pop().expect(TINVOTS);
     let ind = ind as usize;
     if ind >= 4 {
-        return Err(format!("Index out of bounds `{}`", ind));
+        return Err(format!("Index out of bounds `{}`", ind).into());
     };
-    let m: [[f32; 4]; 4] = rt.pop_mat4().expect(TINVOTS);
+    let m: [[Scalar; 4]; 4] = rt.pop_mat4().expect(TINVOTS);
     Ok(Variable::Mat4(Box::new(m)))
 }
 
-pub(crate) fn clone(rt: &mut Runtime) -> Result<Variable, String> {
+pub(crate) fn clone(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     let v = rt.stack.pop().expect(TINVOTS);
     Ok(rt.resolve(&v).deep_clone(&rt.stack))
 }
 
-pub(crate) fn why(rt: &mut Runtime) -> Result<Variable, String> {
+pub(crate) fn why(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     let v = rt.stack.pop().expect(TINVOTS);
     Ok(Variable::Array(Arc::new(match rt.resolve(&v) {
         &Variable::Bool(true, Some(ref sec)) => {
@@ -459,7 +947,7 @@ pub(crate) fn why(rt: &mut Runtime) -> Result<Variable, String> {
     })))
 }
 
-pub(crate) fn _where(rt: &mut Runtime) -> Result<Variable, String> {
+pub(crate) fn _where(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     let v = rt.stack.pop().expect(TINVOTS);
     Ok(Variable::Array(Arc::new(match rt.resolve(&v) {
         &Variable::F64(val, Some(ref sec)) => {
@@ -484,7 +972,7 @@ pub(crate) fn _where(rt: &mut Runtime) -> Result<Variable, String> {
     })))
 }
 
-pub(crate) fn explain_why(rt: &mut Runtime) -> Result<Variable, String> {
+pub(crate) fn explain_why(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     let why = rt.stack.pop().expect(TINVOTS);
     let val = rt.stack.pop().expect(TINVOTS);
     let (val, why) = match rt.resolve(&val) {
@@ -504,7 +992,7 @@ pub(crate) fn explain_why(rt: &mut Runtime) -> Result<Variable, String> {
     Ok(Variable::Bool(val, Some(why)))
 }
 
-pub(crate) fn explain_where(rt: &mut Runtime) -> Result<Variable, String> {
+pub(crate) fn explain_where(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     let wh = rt.stack.pop().expect(TINVOTS);
     let val = rt.stack.pop().expect(TINVOTS);
     let (val, wh) = match rt.resolve(&val) {
@@ -525,7 +1013,7 @@ pub(crate) fn explain_where(rt: &mut Runtime) -> Result<Variable, String> {
 }
 
 #[cfg(feature = "stdio")]
-pub(crate) fn println(rt: &mut Runtime) -> Result<(), String> {
+pub(crate) fn println(rt: &mut Runtime) -> Result<(), RuntimeError> {
     use write::{print_variable, EscapeString};
 
     let x = rt.stack.pop().expect(TINVOTS);
@@ -535,7 +1023,7 @@ pub(crate) fn println(rt: &mut Runtime) -> Result<(), String> {
 }
 
 #[cfg(feature = "stdio")]
-pub(crate) fn print(rt: &mut Runtime) -> Result<(), String> {
+pub(crate) fn print(rt: &mut Runtime) -> Result<(), RuntimeError> {
     use write::{print_variable, EscapeString};
 
     let x = rt.stack.pop().expect(TINVOTS);
@@ -559,17 +1047,164 @@ dyon_fn! {fn round(a: f64) -> f64 {a.round()}}
 dyon_fn! {fn abs(a: f64) -> f64 {a.abs()}}
 dyon_fn! {fn floor(a: f64) -> f64 {a.floor()}}
 dyon_fn! {fn ceil(a: f64) -> f64 {a.ceil()}}
+dyon_fn! {fn lerp(a: f64, b: f64, t: f64) -> f64 {a + (b - a) * t}}
+dyon_fn! {fn smoothstep(edge0: f64, edge1: f64, x: f64) -> f64 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}}
+dyon_fn! {fn clamp(x: f64, min: f64, max: f64) -> f64 {x.max(min).min(max)}}
+
+/// The largest integer where all smaller integers are exact in `f64`.
+const MAX_SAFE_INT: f64 = 9007199254740992.0;
+
+/// Reads an integer argument for bit operations.
+fn int_arg(rt: &Runtime, i: usize, v: &Variable) -> Result<i64, RuntimeError> {
+    match *rt.resolve(v) {
+        Variable::F64(x, _) if x.fract() == 0.0 && x.abs() <= MAX_SAFE_INT => Ok(x as i64),
+        ref x => Err(rt.expected_arg(i, x, "53 bit integer")),
+    }
+}
+
+/// Converts the result of a bit operation back to a number.
+fn int_result(x: i64) -> Result<Variable, String> {
+    if (x as f64).abs() > MAX_SAFE_INT {
+        return Err(format!("Result `{}` does not fit in 53 bits", x));
+    }
+    Ok(Variable::f64(x as f64))
+}
+
+fn int_binop(
+    rt: &mut Runtime,
+    f: fn(i64, i64) -> Result<i64, String>,
+) -> Result<Variable, RuntimeError> {
+    let b = rt.stack.pop().expect(TINVOTS);
+    let b = int_arg(rt, 1, &b)?;
+    let a = rt.stack.pop().expect(TINVOTS);
+    let a = int_arg(rt, 0, &a)?;
+    Ok(int_result(f(a, b)?)?)
+}
+
+fn shift_arg(n: i64) -> Result<u32, String> {
+    if (0..=53).contains(&n) {
+        Ok(n as u32)
+    } else {
+        Err(format!("Expected shift in range `[0, 53]`, found `{}`", n))
+    }
+}
+
+pub(crate) fn band(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    int_binop(rt, |a, b| Ok(a & b))
+}
+
+pub(crate) fn bor(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    int_binop(rt, |a, b| Ok(a | b))
+}
+
+pub(crate) fn bxor(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    int_binop(rt, |a, b| Ok(a ^ b))
+}
+
+pub(crate) fn bnot(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let a = rt.stack.pop().expect(TINVOTS);
+    let a = int_arg(rt, 0, &a)?;
+    Ok(int_result(!a)?)
+}
+
+pub(crate) fn shl(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    int_binop(rt, |a, n| {
+        let n = shift_arg(n)?;
+        // Shifting 53 bits left by up to 53 bits fits in `i128`.
+        let x = i128::from(a) << n;
+        if x.abs() > i128::from(MAX_SAFE_INT as i64) {
+            return Err(format!(
+                "Result of `shl({}, {})` does not fit in 53 bits",
+                a, n
+            ));
+        }
+        Ok(x as i64)
+    })
+}
+
+pub(crate) fn shr(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    int_binop(rt, |a, n| Ok(a >> shift_arg(n)?))
+}
+
 #[cfg(all(not(target_family = "wasm"), feature = "threading"))]
-dyon_fn! {fn sleep(v: f64) {
-    use std::thread::sleep;
+pub(crate) fn sleep(rt: &mut Runtime) -> Result<(), RuntimeError> {
     use std::time::Duration;
 
+    let v = rt.stack.pop().expect(TINVOTS);
+    let v = match rt.resolve(&v) {
+        &Variable::F64(v, _) => v,
+        x => return Err(rt.expected_arg(0, x, "number")),
+    };
     let secs = v as u64;
     let nanos = (v.fract() * 1.0e9) as u32;
-    sleep(Duration::new(secs, nanos));
-}}
+    rt.sleep(Duration::new(secs, nanos))
+}
+
+pub(crate) fn yield_frame(rt: &mut Runtime) -> Result<(), RuntimeError> {
+    Ok(rt.yield_frame()?)
+}
+
+pub(crate) fn sleep_until(rt: &mut Runtime) -> Result<(), RuntimeError> {
+    let time = rt.stack.pop().expect(TINVOTS);
+    let time = match rt.resolve(&time) {
+        &Variable::F64(v, _) if v.is_finite() => v,
+        &Variable::F64(_, _) => {
+            return Err({
+                rt.arg_err_index.set(Some(0));
+                "Expected finite time".into()
+            })
+        }
+        x => return Err(rt.expected_arg(0, x, "number")),
+    };
+    Ok(rt.sleep_until(time)?)
+}
+
+fn start_timer(rt: &mut Runtime, repeat: bool) -> Result<Variable, RuntimeError> {
+    let f = rt.stack.pop().expect(TINVOTS);
+    let secs = rt.stack.pop().expect(TINVOTS);
+    let secs = match rt.resolve(&secs) {
+        &Variable::F64(v, _) if v.is_finite() && (v > 0.0 || !repeat && v == 0.0) => v,
+        &Variable::F64(_, _) => {
+            return Err({
+                rt.arg_err_index.set(Some(0));
+                if repeat {
+                    "Expected positive seconds".into()
+                } else {
+                    "Expected non-negative seconds".into()
+                }
+            })
+        }
+        x => return Err(rt.expected_arg(0, x, "number")),
+    };
+    let f = match rt.resolve(&f) {
+        x @ &Variable::Closure(_, _) => x.clone(),
+        x => return Err(rt.expected_arg(1, x, "closure")),
+    };
+    let interval = if repeat { Some(secs) } else { None };
+    Ok(Variable::f64(rt.timers.start(secs, interval, f) as f64))
+}
+
+pub(crate) fn after(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    start_timer(rt, false)
+}
+
+pub(crate) fn every(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    start_timer(rt, true)
+}
+
+pub(crate) fn cancel_timer(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let id = rt.stack.pop().expect(TINVOTS);
+    let id = match rt.resolve(&id) {
+        &Variable::F64(v, _) => v,
+        x => return Err(rt.expected_arg(0, x, "number")),
+    };
+    Ok(Variable::bool(id >= 0.0 && rt.timers.cancel(id as u64)))
+}
 
-pub(crate) fn head(rt: &mut Runtime) -> Result<Variable, String> {
+pub(crate) fn head(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     let v = rt.stack.pop().expect(TINVOTS);
     Ok(Variable::Option(match rt.resolve(&v) {
         &Variable::Link(ref link) => link.head(),
@@ -577,7 +1212,7 @@ pub(crate) fn head(rt: &mut Runtime) -> Result<Variable, String> {
     }))
 }
 
-pub(crate) fn tip(rt: &mut Runtime) -> Result<Variable, String> {
+pub(crate) fn tip(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     let v = rt.stack.pop().expect(TINVOTS);
     Ok(Variable::Option(match rt.resolve(&v) {
         &Variable::Link(ref link) => link.tip(),
@@ -585,7 +1220,7 @@ pub(crate) fn tip(rt: &mut Runtime) -> Result<Variable, String> {
     }))
 }
 
-pub(crate) fn tail(rt: &mut Runtime) -> Result<Variable, String> {
+pub(crate) fn tail(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     let v = rt.stack.pop().expect(TINVOTS);
     Ok(Variable::Link(Box::new(match rt.resolve(&v) {
         &Variable::Link(ref link) => link.tail(),
@@ -593,7 +1228,7 @@ pub(crate) fn tail(rt: &mut Runtime) -> Result<Variable, String> {
     })))
 }
 
-pub(crate) fn neck(rt: &mut Runtime) -> Result<Variable, String> {
+pub(crate) fn neck(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     let v = rt.stack.pop().expect(TINVOTS);
     Ok(Variable::Link(Box::new(match rt.resolve(&v) {
         &Variable::Link(ref link) => link.neck(),
@@ -601,7 +1236,7 @@ pub(crate) fn neck(rt: &mut Runtime) -> Result<Variable, String> {
     })))
 }
 
-pub(crate) fn is_empty(rt: &mut Runtime) -> Result<Variable, String> {
+pub(crate) fn is_empty(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     let v = rt.stack.pop().expect(TINVOTS);
     Ok(Variable::bool(match rt.resolve(&v) {
         &Variable::Link(ref link) => link.is_empty(),
@@ -609,26 +1244,119 @@ pub(crate) fn is_empty(rt: &mut Runtime) -> Result<Variable, String> {
     }))
 }
 
-pub(crate) fn random(rt: &mut Runtime) -> Result<Variable, String> {
+pub(crate) fn random(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     use rand::Rng;
 
     Ok(Variable::f64(rt.rng.gen()))
 }
 
-dyon_fn! {fn tau() -> f64 {6.283_185_307_179_586}}
+pub(crate) fn seed(rt: &mut Runtime) -> Result<(), RuntimeError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    let v = match rt.resolve(&v) {
+        &Variable::F64(v, _) => v,
+        x => return Err(rt.expected_arg(0, x, "number")),
+    };
+    rt.set_seed(v as u64);
+    Ok(())
+}
+
+pub(crate) fn random_range(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    use rand::Rng;
+
+    let max = rt.stack.pop().expect(TINVOTS);
+    let min = rt.stack.pop().expect(TINVOTS);
+    let min = match rt.resolve(&min) {
+        &Variable::F64(v, _) => v,
+        x => return Err(rt.expected_arg(0, x, "number")),
+    };
+    let max = match rt.resolve(&max) {
+        &Variable::F64(v, _) => v,
+        x => return Err(rt.expected_arg(1, x, "number")),
+    };
+    if min.partial_cmp(&max) != Some(std::cmp::Ordering::Less) {
+        return Err({
+            rt.arg_err_index.set(Some(1));
+            format!("Expected `min < max`, found `{} >= {}`", min, max).into()
+        });
+    }
+    Ok(Variable::f64(rt.rng.gen_range(min, max)))
+}
+
+pub(crate) fn random_int(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    use rand::distributions::Uniform;
+    use rand::Rng;
+
+    // The integers from `i64::MIN` up to, but not including, this number fit in `i64`.
+    const I64_END: f64 = 9_223_372_036_854_775_808.0;
+
+    let max = rt.stack.pop().expect(TINVOTS);
+    let min = rt.stack.pop().expect(TINVOTS);
+    let min = match rt.resolve(&min) {
+        &Variable::F64(v, _) if v.ceil() >= -I64_END && v.ceil() < I64_END => v.ceil() as i64,
+        &Variable::F64(v, _) => {
+            return Err({
+                rt.arg_err_index.set(Some(0));
+                format!("Expected finite number in `i64` range, found `{}`", v).into()
+            })
+        }
+        x => return Err(rt.expected_arg(0, x, "number")),
+    };
+    let max = match rt.resolve(&max) {
+        &Variable::F64(v, _) if v.floor() >= -I64_END && v.floor() < I64_END => v.floor() as i64,
+        &Variable::F64(v, _) => {
+            return Err({
+                rt.arg_err_index.set(Some(1));
+                format!("Expected finite number in `i64` range, found `{}`", v).into()
+            })
+        }
+        x => return Err(rt.expected_arg(1, x, "number")),
+    };
+    if min > max {
+        return Err({
+            rt.arg_err_index.set(Some(1));
+            format!("Expected `min <= max`, found `{} > {}`", min, max).into()
+        });
+    }
+    // Sampling the inclusive range does not overflow when `max` is `i64::MAX`.
+    Ok(Variable::f64(
+        rt.rng.sample(Uniform::new_inclusive(min, max)) as f64,
+    ))
+}
+
+pub(crate) fn shuffle(rt: &mut Runtime) -> Result<(), RuntimeError> {
+    use rand::seq::SliceRandom;
+
+    let v = rt.stack.pop().expect(TINVOTS);
+    if let Variable::Ref(ind) = v {
+        if let Variable::Array(ref mut arr) = rt.stack[ind] {
+            Arc::make_mut(arr).shuffle(&mut rt.rng);
+            return Ok(());
+        }
+    }
+    Err({
+        rt.arg_err_index.set(Some(0));
+        "Expected reference to array".into()
+    })
+}
+
+dyon_fn! {fn tau() -> f64 {::std::f64::consts::TAU}}
 
 pub(crate) fn len(a: &Variable) -> Result<Variable, String> {
     match a {
         Variable::Array(ref arr) => Ok(Variable::f64(arr.len() as f64)),
+        Variable::F64Array(ref arr) => Ok(Variable::f64(arr.len() as f64)),
         _ => Err("Expected array".into()),
     }
 }
 
-pub(crate) fn push_ref(rt: &mut Runtime) -> Result<(), String> {
+pub(crate) fn push_ref(rt: &mut Runtime) -> Result<(), RuntimeError> {
     let item = rt.stack.pop().expect(TINVOTS);
     let v = rt.stack.pop().expect(TINVOTS);
 
     if let Variable::Ref(ind) = v {
+        if let Variable::Array(ref arr) = rt.stack[ind] {
+            rt.check_array_len(arr.len() + 1)?;
+        }
         let ok = if let Variable::Array(ref mut arr) = rt.stack[ind] {
             Arc::make_mut(arr).push(item);
             true
@@ -650,7 +1378,7 @@ pub(crate) fn push_ref(rt: &mut Runtime) -> Result<(), String> {
     Ok(())
 }
 
-pub(crate) fn insert_ref(rt: &mut Runtime) -> Result<(), String> {
+pub(crate) fn insert_ref(rt: &mut Runtime) -> Result<(), RuntimeError> {
     let item = rt.stack.pop().expect(TINVOTS);
     let index = rt.stack.pop().expect(TINVOTS);
     let index = match rt.resolve(&index) {
@@ -665,6 +1393,7 @@ pub(crate) fn insert_ref(rt: &mut Runtime) -> Result<(), String> {
             if index > arr.len() {
                 return Err("Index out of bounds".into());
             }
+            rt.check_array_len(arr.len() + 1)?;
         }
         let ok = if let Variable::Array(ref mut arr) = rt.stack[ind] {
             Arc::make_mut(arr).insert(index as usize, item);
@@ -687,12 +1416,15 @@ pub(crate) fn insert_ref(rt: &mut Runtime) -> Result<(), String> {
     Ok(())
 }
 
-pub(crate) fn push(rt: &mut Runtime) -> Result<(), String> {
+pub(crate) fn push(rt: &mut Runtime) -> Result<(), RuntimeError> {
     let item = rt.stack.pop().expect(TINVOTS);
     let item = rt.resolve(&item).deep_clone(&rt.stack);
     let v = rt.stack.pop().expect(TINVOTS);
 
     if let Variable::Ref(ind) = v {
+        if let Variable::Array(ref arr) = rt.stack[ind] {
+            rt.check_array_len(arr.len() + 1)?;
+        }
         let ok = if let Variable::Array(ref mut arr) = rt.stack[ind] {
             Arc::make_mut(arr).push(item);
             true
@@ -714,22 +1446,35 @@ pub(crate) fn push(rt: &mut Runtime) -> Result<(), String> {
     Ok(())
 }
 
-pub(crate) fn insert(rt: &mut Runtime) -> Result<(), String> {
+pub(crate) fn insert(rt: &mut Runtime) -> Result<(), RuntimeError> {
     let item = rt.stack.pop().expect(TINVOTS);
     let item = rt.resolve(&item).deep_clone(&rt.stack);
     let index = rt.stack.pop().expect(TINVOTS);
+    let v = rt.stack.pop().expect(TINVOTS);
+
+    if let Variable::Ref(ind) = v {
+        if let Variable::Object(_) = rt.stack[ind] {
+            let key = match rt.resolve(&index) {
+                &Variable::Str(ref key) => key.clone(),
+                x => return Err(rt.expected_arg(1, x, "str")),
+            };
+            if let Variable::Object(ref mut obj) = rt.stack[ind] {
+                Arc::make_mut(obj).insert(key, item);
+            }
+            return Ok(());
+        }
+    }
     let index = match rt.resolve(&index) {
         &Variable::F64(index, _) => index,
         x => return Err(rt.expected_arg(1, x, "number")),
     };
-    let v = rt.stack.pop().expect(TINVOTS);
-
     if let Variable::Ref(ind) = v {
         if let Variable::Array(ref arr) = rt.stack[ind] {
             let index = index as usize;
             if index > arr.len() {
                 return Err("Index out of bounds".into());
             }
+            rt.check_array_len(arr.len() + 1)?;
         }
         let ok = if let Variable::Array(ref mut arr) = rt.stack[ind] {
             Arc::make_mut(arr).insert(index as usize, item);
@@ -740,19 +1485,19 @@ pub(crate) fn insert(rt: &mut Runtime) -> Result<(), String> {
         if !ok {
             return Err({
                 rt.arg_err_index.set(Some(0));
-                "Expected reference to array".into()
+                "Expected reference to array or object".into()
             });
         }
     } else {
         return Err({
             rt.arg_err_index.set(Some(0));
-            "Expected reference to array".into()
+            "Expected reference to array or object".into()
         });
     }
     Ok(())
 }
 
-pub(crate) fn pop(rt: &mut Runtime) -> Result<Variable, String> {
+pub(crate) fn pop(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     let arr = rt.stack.pop().expect(TINVOTS);
     let mut v: Option<Variable> = None;
     if let Variable::Ref(ind) = arr {
@@ -786,7 +1531,7 @@ pub(crate) fn pop(rt: &mut Runtime) -> Result<Variable, String> {
     Ok(v)
 }
 
-pub(crate) fn remove(rt: &mut Runtime) -> Result<Variable, String> {
+pub(crate) fn remove(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     let index = rt.stack.pop().expect(TINVOTS);
     let index = match rt.resolve(&index) {
         &Variable::F64(index, _) => index,
@@ -816,7 +1561,7 @@ pub(crate) fn remove(rt: &mut Runtime) -> Result<Variable, String> {
     }
 }
 
-pub(crate) fn reverse(rt: &mut Runtime) -> Result<(), String> {
+pub(crate) fn reverse(rt: &mut Runtime) -> Result<(), RuntimeError> {
     let v = rt.stack.pop().expect(TINVOTS);
     if let Variable::Ref(ind) = v {
         let ok = if let Variable::Array(ref mut arr) = rt.stack[ind] {
@@ -840,7 +1585,7 @@ pub(crate) fn reverse(rt: &mut Runtime) -> Result<(), String> {
     Ok(())
 }
 
-pub(crate) fn clear(rt: &mut Runtime) -> Result<(), String> {
+pub(crate) fn clear(rt: &mut Runtime) -> Result<(), RuntimeError> {
     let v = rt.stack.pop().expect(TINVOTS);
     if let Variable::Ref(ind) = v {
         let ok = if let Variable::Array(ref mut arr) = rt.stack[ind] {
@@ -864,59 +1609,223 @@ pub(crate) fn clear(rt: &mut Runtime) -> Result<(), String> {
     Ok(())
 }
 
-pub(crate) fn swap(rt: &mut Runtime) -> Result<(), String> {
-    let j = rt.stack.pop().expect(TINVOTS);
-    let i = rt.stack.pop().expect(TINVOTS);
-    let j = match rt.resolve(&j) {
-        &Variable::F64(val, _) => val,
-        x => return Err(rt.expected_arg(2, x, "number")),
-    };
-    let i = match rt.resolve(&i) {
-        &Variable::F64(val, _) => val,
-        x => return Err(rt.expected_arg(1, x, "number")),
-    };
-    let v = rt.stack.pop().expect(TINVOTS);
-    if let Variable::Ref(ind) = v {
-        let ok = if let Variable::Array(ref mut arr) = rt.stack[ind] {
-            Arc::make_mut(arr).swap(i as usize, j as usize);
-            true
-        } else {
-            false
-        };
-        if !ok {
-            return Err({
-                rt.arg_err_index.set(Some(0));
-                "Expected reference to array".into()
-            });
+/// Returns the stack index of an array passed as `mut` argument.
+fn mut_array_arg(rt: &Runtime, v: &Variable) -> Result<usize, RuntimeError> {
+    if let Variable::Ref(ind) = *v {
+        if let Variable::Array(_) = rt.stack[ind] {
+            return Ok(ind);
         }
-    } else {
-        return Err({
-            rt.arg_err_index.set(Some(0));
-            "Expected reference to array".into()
+    }
+    Err({
+        rt.arg_err_index.set(Some(0));
+        "Expected reference to array".into()
+    })
+}
+
+pub(crate) fn sort(rt: &mut Runtime) -> Result<(), RuntimeError> {
+    use std::cmp::Ordering;
+
+    let v = rt.stack.pop().expect(TINVOTS);
+    let ind = mut_array_arg(rt, &v)?;
+    let mut arr = match rt.stack[ind] {
+        Variable::Array(ref arr) => arr.clone(),
+        _ => unreachable!(),
+    };
+    // Check that all items are numbers or all are strings.
+    let mut numbers = true;
+    for (i, it) in arr.iter().enumerate() {
+        match (rt.resolve(it), i == 0) {
+            (&Variable::F64(_, _), true) => {}
+            (&Variable::Str(_), true) => numbers = false,
+            (&Variable::F64(_, _), false) if numbers => {}
+            (&Variable::Str(_), false) if !numbers => {}
+            (x, _) => {
+                return Err({
+                    rt.arg_err_index.set(Some(0));
+                    format!(
+                        "Expected array of numbers or strings, found `{}` at index {}",
+                        x.typeof_var(),
+                        i
+                    )
+                    .into()
+                })
+            }
+        }
+    }
+    // Take the array from the stack to sort it without copying.
+    rt.stack[ind] = Variable::Return;
+    Arc::make_mut(&mut arr).sort_by(|a, b| match (rt.resolve(a), rt.resolve(b)) {
+        (&Variable::F64(a, _), &Variable::F64(b, _)) => a.total_cmp(&b),
+        (&Variable::Str(ref a), &Variable::Str(ref b)) => a.cmp(b),
+        _ => Ordering::Equal,
+    });
+    rt.stack[ind] = Variable::Array(arr);
+    Ok(())
+}
+
+/// Sorts items with a comparator that might fail.
+///
+/// Uses merge sort, which is stable and never calls the comparator
+/// on the same items twice in a single pass.
+fn merge_sort_by<F>(items: &mut Vec<Variable>, mut cmp: F) -> Result<(), RuntimeError>
+where
+    F: FnMut(&Variable, &Variable) -> Result<::std::cmp::Ordering, RuntimeError>,
+{
+    use std::cmp::Ordering;
+
+    let n = items.len();
+    let mut buf: Vec<Variable> = Vec::with_capacity(n);
+    let mut width = 1;
+    while width < n {
+        buf.clear();
+        let mut start = 0;
+        while start < n {
+            let mid = (start + width).min(n);
+            let end = (start + 2 * width).min(n);
+            let (mut i, mut j) = (start, mid);
+            while i < mid && j < end {
+                if cmp(&items[j], &items[i])? == Ordering::Less {
+                    buf.push(items[j].clone());
+                    j += 1;
+                } else {
+                    buf.push(items[i].clone());
+                    i += 1;
+                }
+            }
+            buf.extend_from_slice(&items[i..mid]);
+            buf.extend_from_slice(&items[j..end]);
+            start = end;
+        }
+        ::std::mem::swap(items, &mut buf);
+        width *= 2;
+    }
+    Ok(())
+}
+
+pub(crate) fn sort_by(rt: &mut Runtime) -> Result<(), RuntimeError> {
+    use std::cmp::Ordering;
+
+    let f = rt.stack.pop().expect(TINVOTS);
+    let v = rt.stack.pop().expect(TINVOTS);
+    let ind = mut_array_arg(rt, &v)?;
+    let mut items = match rt.stack[ind] {
+        Variable::Array(ref arr) => (**arr).clone(),
+        _ => unreachable!(),
+    };
+    merge_sort_by(&mut items, |a, b| {
+        let a = rt.resolve(a).clone();
+        let b = rt.resolve(b).clone();
+        match rt.call_closure_ret(&f, &[a, b])? {
+            Variable::F64(x, _) if x < 0.0 => Ok(Ordering::Less),
+            Variable::F64(x, _) if x > 0.0 => Ok(Ordering::Greater),
+            Variable::F64(_, _) => Ok(Ordering::Equal),
+            ref x => Err(rt.expected(x, "number from comparator")),
+        }
+    })?;
+    rt.stack[ind] = Variable::Array(Arc::new(items));
+    Ok(())
+}
+
+pub(crate) fn map(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let f = rt.stack.pop().expect(TINVOTS);
+    let v = rt.stack.pop().expect(TINVOTS);
+    let arr = match rt.resolve(&v) {
+        &Variable::Array(ref arr) => arr.clone(),
+        x => return Err(rt.expected_arg(0, x, "array")),
+    };
+    let mut res = Vec::with_capacity(arr.len());
+    for it in arr.iter() {
+        let it = rt.resolve(it).clone();
+        res.push(rt.call_closure_ret(&f, &[it])?);
+    }
+    Ok(Variable::Array(Arc::new(res)))
+}
+
+pub(crate) fn filter(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let f = rt.stack.pop().expect(TINVOTS);
+    let v = rt.stack.pop().expect(TINVOTS);
+    let arr = match rt.resolve(&v) {
+        &Variable::Array(ref arr) => arr.clone(),
+        x => return Err(rt.expected_arg(0, x, "array")),
+    };
+    let mut res = vec![];
+    for it in arr.iter() {
+        let it = rt.resolve(it).clone();
+        match rt.call_closure_ret(&f, std::slice::from_ref(&it))? {
+            Variable::Bool(true, _) => res.push(it),
+            Variable::Bool(false, _) => {}
+            ref x => return Err(rt.expected(x, "bool from filter")),
+        }
+    }
+    Ok(Variable::Array(Arc::new(res)))
+}
+
+pub(crate) fn fold(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let f = rt.stack.pop().expect(TINVOTS);
+    let init = rt.stack.pop().expect(TINVOTS);
+    let v = rt.stack.pop().expect(TINVOTS);
+    let arr = match rt.resolve(&v) {
+        &Variable::Array(ref arr) => arr.clone(),
+        x => return Err(rt.expected_arg(0, x, "array")),
+    };
+    let mut acc = rt.resolve(&init).clone();
+    for it in arr.iter() {
+        let it = rt.resolve(it).clone();
+        acc = rt.call_closure_ret(&f, &[acc, it])?;
+    }
+    Ok(acc)
+}
+
+pub(crate) fn swap(rt: &mut Runtime) -> Result<(), RuntimeError> {
+    let j = rt.stack.pop().expect(TINVOTS);
+    let i = rt.stack.pop().expect(TINVOTS);
+    let j = match rt.resolve(&j) {
+        &Variable::F64(val, _) => val,
+        x => return Err(rt.expected_arg(2, x, "number")),
+    };
+    let i = match rt.resolve(&i) {
+        &Variable::F64(val, _) => val,
+        x => return Err(rt.expected_arg(1, x, "number")),
+    };
+    let v = rt.stack.pop().expect(TINVOTS);
+    if let Variable::Ref(ind) = v {
+        let ok = if let Variable::Array(ref mut arr) = rt.stack[ind] {
+            Arc::make_mut(arr).swap(i as usize, j as usize);
+            true
+        } else {
+            false
+        };
+        if !ok {
+            return Err({
+                rt.arg_err_index.set(Some(0));
+                "Expected reference to array".into()
+            });
+        }
+    } else {
+        return Err({
+            rt.arg_err_index.set(Some(0));
+            "Expected reference to array".into()
         });
     }
     Ok(())
 }
 
 #[cfg(feature = "stdio")]
-pub(crate) fn read_line(_rt: &mut Runtime) -> Result<Variable, String> {
+pub(crate) fn read_line(_rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     use std::io::{self, Write};
 
     let mut input = String::new();
     io::stdout().flush().unwrap();
-    let error = match io::stdin().read_line(&mut input) {
-        Ok(_) => None,
-        Err(error) => Some(error),
-    };
+    let error = io::stdin().read_line(&mut input).err();
     Ok(if let Some(error) = error {
-        return Err(error.to_string());
+        return Err(error.to_string().into());
     } else {
         Variable::Str(Arc::new(input))
     })
 }
 
 #[cfg(feature = "stdio")]
-pub(crate) fn read_number(rt: &mut Runtime) -> Result<Variable, String> {
+pub(crate) fn read_number(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     use std::io::{self, Write};
 
     let err: Arc<String> = rt.pop().expect(TINVOTS);
@@ -928,7 +1837,7 @@ pub(crate) fn read_number(rt: &mut Runtime) -> Result<Variable, String> {
         stdout.flush().unwrap();
         match stdin.read_line(&mut input) {
             Ok(_) => {}
-            Err(error) => return Err(error.to_string()),
+            Err(error) => return Err(error.to_string().into()),
         };
         match input.trim().parse::<f64>() {
             Ok(v) => break v,
@@ -943,30 +1852,288 @@ dyon_fn! {fn trim(v: Arc<String>) -> Arc<String> {Arc::new(v.trim().into())}}
 dyon_fn! {fn trim_left(v: Arc<String>) -> Arc<String> {Arc::new(v.trim_start().into())}}
 dyon_fn! {fn trim_right(v: Arc<String>) -> Arc<String> {Arc::new(v.trim_end().into())}}
 
-pub(crate) fn _str(rt: &mut Runtime) -> Result<Variable, String> {
+pub(crate) fn split(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let sep = rt.stack.pop().expect(TINVOTS);
+    let sep = str_arg(rt, 1, &sep)?;
+    let text = rt.stack.pop().expect(TINVOTS);
+    let text = str_arg(rt, 0, &text)?;
+    if sep.is_empty() {
+        rt.check_array_len(text.chars().count())?;
+        Ok(Variable::Array(Arc::new(
+            text.chars()
+                .map(|ch| Variable::Str(Arc::new(ch.to_string())))
+                .collect(),
+        )))
+    } else {
+        rt.check_array_len(text.split(&**sep).count())?;
+        Ok(Variable::Array(Arc::new(
+            text.split(&**sep)
+                .map(|s| Variable::Str(Arc::new(s.into())))
+                .collect(),
+        )))
+    }
+}
+dyon_fn! {fn join(arr: Vec<Arc<String>>, sep: Arc<String>) -> Arc<String> {
+    let parts: Vec<&str> = arr.iter().map(|s| &***s).collect();
+    Arc::new(parts.join(&sep))
+}}
+dyon_fn! {fn replace(text: Arc<String>, from: Arc<String>, to: Arc<String>) -> Arc<String> {
+    if from.is_empty() {
+        text
+    } else {
+        Arc::new(text.replace(&**from, &to))
+    }
+}}
+dyon_fn! {fn starts_with(text: Arc<String>, pat: Arc<String>) -> bool {text.starts_with(&**pat)}}
+dyon_fn! {fn ends_with(text: Arc<String>, pat: Arc<String>) -> bool {text.ends_with(&**pat)}}
+dyon_fn! {fn contains(text: Arc<String>, pat: Arc<String>) -> bool {text.contains(&**pat)}}
+
+/// Returns the number of character insertions, deletions and substitutions
+/// needed to change one string into another.
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut cur = vec![0; b.len() + 1];
+    for (i, ca) in a.chars().enumerate() {
+        cur[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let cost = if ca == cb { 0 } else { 1 };
+            cur[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        ::std::mem::swap(&mut prev, &mut cur);
+    }
+    prev[b.len()]
+}
+
+dyon_fn! {fn edit_distance(a: Arc<String>, b: Arc<String>) -> f64 {
+    levenshtein(&a, &b) as f64
+}}
+
+dyon_fn! {fn similarity(a: Arc<String>, b: Arc<String>) -> f64 {
+    let n = a.chars().count().max(b.chars().count());
+    if n == 0 {
+        1.0
+    } else {
+        1.0 - levenshtein(&a, &b) as f64 / n as f64
+    }
+}}
+
+/// Scores how well a query matches a candidate, from 0 to 1.
+///
+/// The characters of the query must appear in order, ignoring case.
+/// Consecutive characters and characters at the start of words score higher.
+fn fuzzy_score(query: &[char], candidate: &str) -> Option<f64> {
+    let chars: Vec<char> = candidate.chars().collect();
+    if query.is_empty() {
+        return Some(1.0);
+    }
+    let mut points = 0;
+    let mut qi = 0;
+    let mut last: Option<usize> = None;
+    for (i, &ch) in chars.iter().enumerate() {
+        if qi == query.len() {
+            break;
+        }
+        if !ch.to_lowercase().eq(query[qi].to_lowercase()) {
+            continue;
+        }
+        points += 1;
+        if last.map(|j| j + 1 == i).unwrap_or(i == 0) {
+            points += 1;
+        }
+        let word_start = i == 0
+            || !chars[i - 1].is_alphanumeric()
+            || (chars[i - 1].is_lowercase() && ch.is_uppercase());
+        if word_start {
+            points += 1;
+        }
+        last = Some(i);
+        qi += 1;
+    }
+    if qi < query.len() {
+        return None;
+    }
+    let matched = points as f64 / (3 * query.len()) as f64;
+    let coverage = query.len() as f64 / chars.len() as f64;
+    Some(0.8 * matched + 0.2 * coverage)
+}
+
+dyon_fn! {fn fuzzy_match(query: Arc<String>, candidates: Vec<Arc<String>>) -> Vec<Variable> {
+    let query: Vec<char> = query.chars().collect();
+    let mut res: Vec<(Arc<String>, f64)> = candidates
+        .into_iter()
+        .filter_map(|text| fuzzy_score(&query, &text).map(|score| (text, score)))
+        .collect();
+    res.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(::std::cmp::Ordering::Equal));
+    res.into_iter()
+        .map(|(text, score)| {
+            let mut obj = HashMap::new();
+            obj.insert(Arc::new("text".into()), Variable::Str(text));
+            obj.insert(Arc::new("score".into()), Variable::f64(score));
+            Variable::Object(Arc::new(obj))
+        })
+        .collect()
+}}
+
+/// Compiles a regular expression argument, reusing recently compiled expressions.
+#[cfg(feature = "regex")]
+fn regex_arg(rt: &Runtime, i: usize, pattern: &Variable) -> Result<::regex::Regex, RuntimeError> {
+    use regex::Regex;
+    use std::collections::HashMap;
+
+    lazy_static! {
+        static ref CACHE: Mutex<HashMap<Arc<String>, Regex>> = Mutex::new(HashMap::new());
+    }
+
+    let pattern = match rt.resolve(pattern) {
+        &Variable::Str(ref t) => t.clone(),
+        x => return Err(rt.expected_arg(i, x, "str")),
+    };
+    let mut cache = CACHE
+        .lock()
+        .map_err(|err| format!("Can not lock regex cache:\n{}", err))?;
+    if let Some(re) = cache.get(&pattern) {
+        return Ok(re.clone());
+    }
+    let re = Regex::new(&pattern).map_err(|err| {
+        rt.arg_err_index.set(Some(i));
+        format!("Invalid regex `{}`:\n{}", pattern, err)
+    })?;
+    if cache.len() >= 64 {
+        cache.clear();
+    }
+    cache.insert(pattern, re.clone());
+    Ok(re)
+}
+
+#[cfg(feature = "regex")]
+pub(crate) fn regex_match(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let pattern = rt.stack.pop().expect(TINVOTS);
+    let text = rt.stack.pop().expect(TINVOTS);
+    let re = regex_arg(rt, 1, &pattern)?;
+    match rt.resolve(&text) {
+        &Variable::Str(ref t) => Ok(Variable::bool(re.is_match(t))),
+        x => Err(rt.expected_arg(0, x, "str")),
+    }
+}
+
+#[cfg(not(feature = "regex"))]
+pub(crate) fn regex_match(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(REGEX_SUPPORT_DISABLED.into())
+}
+
+#[cfg(feature = "regex")]
+pub(crate) fn regex_captures(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let pattern = rt.stack.pop().expect(TINVOTS);
+    let text = rt.stack.pop().expect(TINVOTS);
+    let re = regex_arg(rt, 1, &pattern)?;
+    let text = match rt.resolve(&text) {
+        &Variable::Str(ref t) => t.clone(),
+        x => return Err(rt.expected_arg(0, x, "str")),
+    };
+    Ok(Variable::Option(re.captures(&text).map(|caps| {
+        Box::new(Variable::Array(Arc::new(
+            caps.iter()
+                .map(|m| Variable::Str(Arc::new(m.map(|m| m.as_str()).unwrap_or("").into())))
+                .collect(),
+        )))
+    })))
+}
+
+#[cfg(not(feature = "regex"))]
+pub(crate) fn regex_captures(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(REGEX_SUPPORT_DISABLED.into())
+}
+
+#[cfg(feature = "regex")]
+pub(crate) fn regex_replace(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let replacement = rt.stack.pop().expect(TINVOTS);
+    let pattern = rt.stack.pop().expect(TINVOTS);
+    let text = rt.stack.pop().expect(TINVOTS);
+    let re = regex_arg(rt, 1, &pattern)?;
+    let text = match rt.resolve(&text) {
+        &Variable::Str(ref t) => t.clone(),
+        x => return Err(rt.expected_arg(0, x, "str")),
+    };
+    let replacement = match rt.resolve(&replacement) {
+        &Variable::Str(ref t) => t.clone(),
+        x => return Err(rt.expected_arg(2, x, "str")),
+    };
+    Ok(Variable::Str(Arc::new(
+        re.replace_all(&text, &**replacement).into_owned(),
+    )))
+}
+
+#[cfg(not(feature = "regex"))]
+pub(crate) fn regex_replace(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(REGEX_SUPPORT_DISABLED.into())
+}
+
+pub(crate) fn _str(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     use write::{write_variable, EscapeString};
 
     let v = rt.stack.pop().expect(TINVOTS);
     let mut buf: Vec<u8> = vec![];
-    write_variable(&mut buf, rt, rt.resolve(&v), EscapeString::None, 0).unwrap();
+    // Not resolved, to detect a reference cycle at top level.
+    write_variable(&mut buf, rt, &v, EscapeString::None, 0).unwrap();
     Ok(Variable::Str(Arc::new(String::from_utf8(buf).unwrap())))
 }
 
-pub(crate) fn json_string(rt: &mut Runtime) -> Result<Variable, String> {
+pub(crate) fn bigint(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    let x =
+        match *rt.resolve(&v) {
+            Variable::Str(ref s) => bigint::BigInt::parse(s)
+                .ok_or_else(|| format!("Could not parse `{}` as integer", s))?,
+            Variable::F64(x, _) => bigint::BigInt::from_f64(x)
+                .ok_or_else(|| format!("Expected integer, found `{}`", x))?,
+            ref x => return Err(rt.expected_arg(0, x, "str or f64")),
+        };
+    Ok(bigint::var(x))
+}
+
+pub(crate) fn json_string(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     use write::{write_variable, EscapeString};
 
     let v = rt.stack.pop().expect(TINVOTS);
     let mut buf: Vec<u8> = vec![];
-    write_variable(&mut buf, rt, rt.resolve(&v), EscapeString::Json, 0).unwrap();
+    // Not resolved, to detect a reference cycle at top level.
+    write_variable(&mut buf, rt, &v, EscapeString::Json, 0).unwrap();
+    Ok(Variable::Str(Arc::new(String::from_utf8(buf).unwrap())))
+}
+
+fn max_depth_arg(rt: &mut Runtime) -> Result<usize, RuntimeError> {
+    let max_depth = rt.stack.pop().expect(TINVOTS);
+    match rt.resolve(&max_depth) {
+        &Variable::F64(v, _) if v >= 0.0 => Ok(v as usize),
+        x => Err(rt.expected_arg(1, x, "non-negative number")),
+    }
+}
+
+pub(crate) fn str__max_depth(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    use write::{write_variable_max_depth, EscapeString};
+
+    let max_depth = max_depth_arg(rt)?;
+    let v = rt.stack.pop().expect(TINVOTS);
+    let mut buf: Vec<u8> = vec![];
+    write_variable_max_depth(&mut buf, rt, &v, EscapeString::None, 0, max_depth).unwrap();
+    Ok(Variable::Str(Arc::new(String::from_utf8(buf).unwrap())))
+}
+
+pub(crate) fn json_string__max_depth(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    use write::{write_variable_max_depth, EscapeString};
+
+    let max_depth = max_depth_arg(rt)?;
+    let v = rt.stack.pop().expect(TINVOTS);
+    let mut buf: Vec<u8> = vec![];
+    write_variable_max_depth(&mut buf, rt, &v, EscapeString::Json, 0, max_depth).unwrap();
     Ok(Variable::Str(Arc::new(String::from_utf8(buf).unwrap())))
 }
 
 dyon_fn! {fn str__color(v: Vec4) -> Arc<String> {
     let v = v.0;
     let mut buf: Vec<u8> = vec![];
-    let clamp = |x| {
-        if x < 0.0 { 0.0 } else if x > 1.0 { 1.0 } else { x }
-    };
+    let clamp = |x: Scalar| x.clamp(0.0, 1.0);
     let r = (clamp(v[0]) * 255.0) as usize;
     let g = (clamp(v[1]) * 255.0) as usize;
     let b = (clamp(v[2]) * 255.0) as usize;
@@ -989,7 +2156,7 @@ dyon_fn! {fn str__color(v: Vec4) -> Arc<String> {
 
 dyon_fn! {fn srgb_to_linear__color(v: Vec4) -> Vec4 {
     let v = v.0;
-    let to_linear = |f: f32| {
+    let to_linear = |f: Scalar| {
         if f <= 0.04045 {
             f / 12.92
         } else {
@@ -1001,7 +2168,7 @@ dyon_fn! {fn srgb_to_linear__color(v: Vec4) -> Vec4 {
 
 dyon_fn! {fn linear_to_srgb__color(v: Vec4) -> Vec4 {
     let v = v.0;
-    let to_srgb = |f: f32| {
+    let to_srgb = |f: Scalar| {
         if f <= 0.003_130_8 {
             f * 12.92
         } else {
@@ -1011,7 +2178,7 @@ dyon_fn! {fn linear_to_srgb__color(v: Vec4) -> Vec4 {
     Vec4([to_srgb(v[0]), to_srgb(v[1]), to_srgb(v[2]), v[3]])
 }}
 
-pub(crate) fn _typeof(rt: &mut Runtime) -> Result<Variable, String> {
+pub(crate) fn _typeof(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     use crate::runtime::*;
     use crate::Variable::*;
 
@@ -1035,36 +2202,58 @@ pub(crate) fn _typeof(rt: &mut Runtime) -> Result<Variable, String> {
         Thread(_) => THREAD_TYPE.clone(),
         Closure(_, _) => CLOSURE_TYPE.clone(),
         In(_) => IN_TYPE.clone(),
+        Out(_) => OUT_TYPE.clone(),
+        Bytes(_) => BYTES_TYPE.clone(),
+        F64Array(_) => F64_ARRAY_TYPE.clone(),
     }))
 }
 
-pub(crate) fn debug(rt: &mut Runtime) -> Result<(), String> {
+pub(crate) fn debug(rt: &mut Runtime) -> Result<(), RuntimeError> {
     println!("Stack {:#?}", rt.stack);
     println!("Locals {:#?}", rt.local_stack);
     println!("Currents {:#?}", rt.current_stack);
     Ok(())
 }
 
-pub(crate) fn backtrace(rt: &mut Runtime) -> Result<(), String> {
+pub(crate) fn backtrace(rt: &mut Runtime) -> Result<(), RuntimeError> {
     println!("{:#?}", rt.call_stack);
     Ok(())
 }
 
-pub(crate) fn load(rt: &mut Runtime) -> Result<Variable, String> {
-    use load;
+/// Loads a module from a file in the file system of the runtime.
+fn load_module(rt: &Runtime, source: &str, module: &mut Module) -> Result<(), RuntimeError> {
+    let data = rt.vfs.read_to_string(source).map_err(|err| {
+        RuntimeError::new(
+            ErrorKind::Io,
+            None,
+            format!("Could not open `{}`, {}", source, err),
+        )
+    })?;
+    Ok(load_str(source, Arc::new(data), module)?)
+}
+
+/// Returns a string argument.
+fn str_arg(rt: &Runtime, i: usize, v: &Variable) -> Result<Arc<String>, RuntimeError> {
+    match rt.resolve(v) {
+        &Variable::Str(ref t) => Ok(t.clone()),
+        x => Err(rt.expected_arg(i, x, "str")),
+    }
+}
 
+pub(crate) fn load(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     let v = rt.stack.pop().expect(TINVOTS);
     Ok(match rt.resolve(&v) {
         &Variable::Str(ref text) => {
             let mut m = Module::empty();
             m.import_ext_prelude(&rt.module);
-            if let Err(err) = load(text, &mut m) {
+            if let Err(err) = load_module(rt, text, &mut m) {
                 Variable::Result(Err(Box::new(Error {
                     message: Variable::Str(Arc::new(format!(
                         "When attempting to load module:\n{}",
                         err
                     ))),
                     trace: vec![],
+                    kind: None,
                 })))
             } else {
                 Variable::Result(Ok(Box::new(Variable::RustObject(Arc::new(Mutex::new(
@@ -1078,9 +2267,7 @@ pub(crate) fn load(rt: &mut Runtime) -> Result<Variable, String> {
     })
 }
 
-pub(crate) fn load__source_imports(rt: &mut Runtime) -> Result<Variable, String> {
-    use load;
-
+pub(crate) fn load__source_imports(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     let modules = rt.stack.pop().expect(TINVOTS);
     let source = rt.stack.pop().expect(TINVOTS);
     let mut new_module = Module::empty();
@@ -1104,13 +2291,14 @@ pub(crate) fn load__source_imports(rt: &mut Runtime) -> Result<Variable, String>
     }
     Ok(match rt.resolve(&source) {
         &Variable::Str(ref text) => {
-            if let Err(err) = load(text, &mut new_module) {
+            if let Err(err) = load_module(rt, text, &mut new_module) {
                 Variable::Result(Err(Box::new(Error {
                     message: Variable::Str(Arc::new(format!(
                         "When attempting to load module:\n{}",
                         err
                     ))),
                     trace: vec![],
+                    kind: None,
                 })))
             } else {
                 Variable::Result(Ok(Box::new(Variable::RustObject(Arc::new(Mutex::new(
@@ -1122,7 +2310,52 @@ pub(crate) fn load__source_imports(rt: &mut Runtime) -> Result<Variable, String>
     })
 }
 
-pub(crate) fn module__in_string_imports(rt: &mut Runtime) -> Result<Variable, String> {
+pub(crate) fn sandbox(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    use std::collections::HashSet;
+
+    let source = rt.stack.pop().expect(TINVOTS);
+    let source = str_arg(rt, 1, &source)?;
+    let allowed = rt.stack.pop().expect(TINVOTS);
+    let mut names: HashSet<Arc<String>> = HashSet::new();
+    match rt.resolve(&allowed) {
+        &Variable::Array(ref arr) => {
+            for it in &**arr {
+                names.insert(str_arg(rt, 0, it)?);
+            }
+        }
+        x => return Err(rt.expected_arg(0, x, "[str]")),
+    }
+    let mut m = Module::empty();
+    for f in &rt.module.ext_prelude {
+        // Operators can not access the runtime, so they are always allowed.
+        let operator = matches!(f.f, FnExt::BinOp(_) | FnExt::UnOp(_));
+        if operator || names.contains(&f.name) {
+            m.ext_prelude.push(f.clone());
+        }
+    }
+    for name in &names {
+        if !m.ext_prelude.iter().any(|f| &f.name == name) {
+            rt.arg_err_index.set(Some(0));
+            return Err(format!("Could not find function `{}` to allow", name).into());
+        }
+    }
+    Ok(if let Err(err) = load_module(rt, &source, &mut m) {
+        Variable::Result(Err(Box::new(Error {
+            message: Variable::Str(Arc::new(format!(
+                "When attempting to load module:\n{}",
+                err
+            ))),
+            trace: vec![],
+            kind: None,
+        })))
+    } else {
+        Variable::Result(Ok(Box::new(Variable::RustObject(Arc::new(Mutex::new(
+            Arc::new(m),
+        ))))))
+    })
+}
+
+pub(crate) fn module__in_string_imports(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     let modules = rt.stack.pop().expect(TINVOTS);
     let source = rt.stack.pop().expect(TINVOTS);
     let source = match rt.resolve(&source) {
@@ -1160,6 +2393,7 @@ pub(crate) fn module__in_string_imports(rt: &mut Runtime) -> Result<Variable, St
                 err
             ))),
             trace: vec![],
+            kind: None,
         })))
     } else {
         Variable::Result(Ok(Box::new(Variable::RustObject(Arc::new(Mutex::new(
@@ -1168,8 +2402,12 @@ pub(crate) fn module__in_string_imports(rt: &mut Runtime) -> Result<Variable, St
     })
 }
 
+dyon_fn! {fn fmt(source: Arc<String>) -> Result<String, String> {
+    ::format_source(&source)
+}}
+
 #[allow(non_snake_case)]
-pub(crate) fn check__in_string_imports(rt: &mut Runtime) -> Result<Variable, String> {
+pub(crate) fn check__in_string_imports(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     let modules = rt.stack.pop().expect(TINVOTS);
     let source = rt.stack.pop().expect(TINVOTS);
     let source = match rt.resolve(&source) {
@@ -1207,6 +2445,7 @@ pub(crate) fn check__in_string_imports(rt: &mut Runtime) -> Result<Variable, Str
                 err
             ))),
             trace: vec![],
+            kind: None,
         }))),
         Ok(nodes) => Variable::Result(Ok(Box::new(Variable::Array({
             use embed::PushVariable;
@@ -1270,7 +2509,7 @@ pub(crate) fn check__in_string_imports(rt: &mut Runtime) -> Result<Variable, Str
     })
 }
 
-pub(crate) fn _call(rt: &mut Runtime) -> Result<(), String> {
+pub(crate) fn _call(rt: &mut Runtime) -> Result<(), RuntimeError> {
     // Use the source from calling function.
     let source = rt.module.functions[rt.call_stack.last().unwrap().index]
         .source
@@ -1291,6 +2530,12 @@ pub(crate) fn _call(rt: &mut Runtime) -> Result<(), String> {
         &Variable::RustObject(ref obj) => obj.clone(),
         x => return Err(rt.expected_arg(0, x, "Module")),
     };
+    #[cfg(all(not(target_family = "wasm"), feature = "rpc"))]
+    {
+        if let Some(res) = rpc_call(rt, &obj, &fn_name, &args) {
+            return Ok(res.map(|_| ())?);
+        }
+    }
 
     match obj.lock().unwrap().downcast_ref::<Arc<Module>>() {
         Some(m) => {
@@ -1306,11 +2551,11 @@ pub(crate) fn _call(rt: &mut Runtime) -> Result<(), String> {
                                 f.args.len(),
                                 args.len()
                             )
+                            .into()
                         });
                     }
-                    lifetimechk::check(f, &args).map_err(|err| {
+                    lifetimechk::check(f, &args).inspect_err(|_| {
                         rt.arg_err_index.set(Some(2));
-                        err
                     })?;
                 }
                 FnIndex::None
@@ -1318,7 +2563,9 @@ pub(crate) fn _call(rt: &mut Runtime) -> Result<(), String> {
                 | FnIndex::Return(_)
                 | FnIndex::Lazy(_, _)
                 | FnIndex::BinOp(_)
-                | FnIndex::UnOp(_) => return Err(format!("Could not find function `{}`", fn_name)),
+                | FnIndex::UnOp(_) => {
+                    return Err(format!("Could not find function `{}`", fn_name).into())
+                }
             }
             // Use empty range instead of `call.source_range` (from when it was intrinsic).
             let call_range = Range::empty(0);
@@ -1343,7 +2590,7 @@ pub(crate) fn _call(rt: &mut Runtime) -> Result<(), String> {
     Ok(())
 }
 
-pub(crate) fn call_ret(rt: &mut Runtime) -> Result<Variable, String> {
+pub(crate) fn call_ret(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     // Use the source from calling function.
     let source = rt.module.functions[rt.call_stack.last().unwrap().index]
         .source
@@ -1364,6 +2611,12 @@ pub(crate) fn call_ret(rt: &mut Runtime) -> Result<Variable, String> {
         &Variable::RustObject(ref obj) => obj.clone(),
         x => return Err(rt.expected_arg(0, x, "Module")),
     };
+    #[cfg(all(not(target_family = "wasm"), feature = "rpc"))]
+    {
+        if let Some(res) = rpc_call(rt, &obj, &fn_name, &args) {
+            return Ok(res?);
+        }
+    }
 
     let v = match obj.lock().unwrap().downcast_ref::<Arc<Module>>() {
         Some(m) => {
@@ -1379,11 +2632,11 @@ pub(crate) fn call_ret(rt: &mut Runtime) -> Result<Variable, String> {
                                 f.args.len(),
                                 args.len()
                             )
+                            .into()
                         });
                     }
-                    lifetimechk::check(f, &args).map_err(|err| {
+                    lifetimechk::check(f, &args).inspect_err(|_| {
                         rt.arg_err_index.set(Some(2));
-                        err
                     })?;
                 }
                 FnIndex::None
@@ -1391,7 +2644,9 @@ pub(crate) fn call_ret(rt: &mut Runtime) -> Result<Variable, String> {
                 | FnIndex::Return(_)
                 | FnIndex::Lazy(_, _)
                 | FnIndex::BinOp(_)
-                | FnIndex::UnOp(_) => return Err(format!("Could not find function `{}`", fn_name)),
+                | FnIndex::UnOp(_) => {
+                    return Err(format!("Could not find function `{}`", fn_name).into())
+                }
             }
             // Use empty range instead of `call.source_range` (from when it was intrinsic).
             let call_range = Range::empty(0);
@@ -1412,7 +2667,7 @@ pub(crate) fn call_ret(rt: &mut Runtime) -> Result<Variable, String> {
             if let Some(v) = rt.call(&call, m)?.0 {
                 v
             } else {
-                return Err(format!("Expected some return value `{}`", fn_name));
+                return Err(format!("Expected some return value `{}`", fn_name).into());
             }
         }
         None => return Err(rt.expected_arg(0, x, "Module")),
@@ -1421,14 +2676,88 @@ pub(crate) fn call_ret(rt: &mut Runtime) -> Result<Variable, String> {
     Ok(v)
 }
 
-pub(crate) fn functions(rt: &mut Runtime) -> Result<Variable, String> {
+/// Calls a function on a server when the object is an RPC proxy.
+#[cfg(all(not(target_family = "wasm"), feature = "rpc"))]
+fn rpc_call(
+    rt: &Runtime,
+    obj: &RustObject,
+    fn_name: &Arc<String>,
+    args: &Arc<Vec<Variable>>,
+) -> Option<Result<Variable, RuntimeError>> {
+    let mut guard = obj.lock().unwrap();
+    let proxy = guard.downcast_mut::<rpc::Proxy>()?;
+    Some(rpc::call(rt, proxy, fn_name, args))
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "rpc"))]
+pub(crate) fn rpc_serve(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let m = rt.stack.pop().expect(TINVOTS);
+    let addr = rt.stack.pop().expect(TINVOTS);
+    let addr = str_arg(rt, 0, &addr)?;
+    let module = match rt.resolve(&m) {
+        &Variable::RustObject(ref obj) => {
+            obj.lock().unwrap().downcast_ref::<Arc<Module>>().cloned()
+        }
+        _ => None,
+    };
+    let module = match module {
+        Some(m) => m,
+        None => return Err(rt.expected_arg(1, &m, "Module")),
+    };
+    Ok(Variable::Result(match rpc::serve(&addr, module) {
+        Ok(addr) => Ok(Box::new(Variable::Str(Arc::new(addr)))),
+        Err(err) => Err(Box::new(Error {
+            message: Variable::Str(Arc::new(format!("Could not serve at `{}`:\n{}", addr, err))),
+            trace: vec![],
+            kind: None,
+        })),
+    }))
+}
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "rpc")))]
+pub(crate) fn rpc_serve(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(RPC_SUPPORT_DISABLED.into())
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "rpc"))]
+pub(crate) fn rpc_connect(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let addr = rt.stack.pop().expect(TINVOTS);
+    let addr = str_arg(rt, 0, &addr)?;
+    Ok(Variable::Result(match rpc::connect(&addr) {
+        Ok(proxy) => Ok(Box::new(Variable::RustObject(Arc::new(Mutex::new(proxy))))),
+        Err(err) => Err(Box::new(Error {
+            message: Variable::Str(Arc::new(format!(
+                "Could not connect to `{}`:\n{}",
+                addr, err
+            ))),
+            trace: vec![],
+            kind: None,
+        })),
+    }))
+}
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "rpc")))]
+pub(crate) fn rpc_connect(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(RPC_SUPPORT_DISABLED.into())
+}
+
+pub(crate) fn functions(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     // List available functions in scope.
     Ok(Variable::Array(Arc::new(functions::list_functions(
         &rt.module,
     ))))
 }
 
-pub(crate) fn functions__module(rt: &mut Runtime) -> Result<Variable, String> {
+pub(crate) fn find_fn(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    // List available functions in scope with names matching a pattern.
+    let pattern = rt.stack.pop().expect(TINVOTS);
+    let pattern = str_arg(rt, 0, &pattern)?;
+    Ok(Variable::Array(Arc::new(functions::find_functions(
+        &rt.module, &pattern,
+    ))))
+}
+
+pub(crate) fn functions__module(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     // List available functions in scope.
     let m = rt.stack.pop().expect(TINVOTS);
     let x = rt.resolve(&m);
@@ -1445,521 +2774,4215 @@ pub(crate) fn functions__module(rt: &mut Runtime) -> Result<Variable, String> {
     Ok(Variable::Array(Arc::new(functions)))
 }
 
-dyon_fn! {fn none() -> Variable {Variable::Option(None)}}
-
-pub(crate) fn some(rt: &mut Runtime) -> Result<Variable, String> {
-    let v = rt.stack.pop().expect(TINVOTS);
-    Ok(Variable::Option(Some(Box::new(
-        rt.resolve(&v).deep_clone(&rt.stack),
-    ))))
-}
-
-pub(crate) fn ok(rt: &mut Runtime) -> Result<Variable, String> {
-    let v = rt.stack.pop().expect(TINVOTS);
-    let v = Variable::Result(Ok(Box::new(rt.resolve(&v).deep_clone(&rt.stack))));
-    Ok(v)
+pub(crate) fn glsl_of(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let fn_name = rt.stack.pop().expect(TINVOTS);
+    let fn_name = match rt.resolve(&fn_name) {
+        &Variable::Str(ref text) => text.clone(),
+        x => return Err(rt.expected_arg(0, x, "str")),
+    };
+    Ok(Variable::Result(
+        match transpile::to_glsl(&rt.module, &fn_name) {
+            Ok(res) => Ok(Box::new(Variable::Str(Arc::new(res)))),
+            Err(err) => Err(Box::new(Error {
+                message: Variable::Str(Arc::new(err)),
+                trace: vec![],
+                kind: None,
+            })),
+        },
+    ))
 }
 
-pub(crate) fn err(rt: &mut Runtime) -> Result<Variable, String> {
-    let v = rt.stack.pop().expect(TINVOTS);
+pub(crate) fn rust_struct_of(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let name = rt.stack.pop().expect(TINVOTS);
+    let name = match rt.resolve(&name) {
+        &Variable::Str(ref text) => text.clone(),
+        x => return Err(rt.expected_arg(1, x, "str")),
+    };
+    let data = rt.stack.pop().expect(TINVOTS);
+    let data = rt.resolve(&data).deep_clone(&rt.stack);
+    match transpile::to_rust_struct(&data, &name) {
+        Ok(res) => Ok(Variable::Str(Arc::new(res))),
+        Err(err) => Err({
+            rt.arg_err_index.set(Some(0));
+            err.into()
+        }),
+    }
+}
+
+pub(crate) fn rust_struct_check(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let name = rt.stack.pop().expect(TINVOTS);
+    let name = match rt.resolve(&name) {
+        &Variable::Str(ref text) => text.clone(),
+        x => return Err(rt.expected_arg(2, x, "str")),
+    };
+    let source = rt.stack.pop().expect(TINVOTS);
+    let source = match rt.resolve(&source) {
+        &Variable::Str(ref text) => text.clone(),
+        x => return Err(rt.expected_arg(1, x, "str")),
+    };
+    let data = rt.stack.pop().expect(TINVOTS);
+    let data = rt.resolve(&data).deep_clone(&rt.stack);
+    Ok(Variable::Result(
+        match transpile::check_rust_struct(&data, &source, &name) {
+            Ok(()) => Ok(Box::new(Variable::bool(true))),
+            Err(err) => Err(Box::new(Error {
+                message: Variable::Str(Arc::new(err)),
+                trace: vec![],
+                kind: None,
+            })),
+        },
+    ))
+}
+
+pub(crate) fn display_html(rt: &mut Runtime) -> Result<(), RuntimeError> {
+    use kernel::Display;
+
+    let html = rt.stack.pop().expect(TINVOTS);
+    let html = match rt.resolve(&html) {
+        &Variable::Str(ref html) => html.clone(),
+        x => return Err(rt.expected_arg(0, x, "str")),
+    };
+    if let Some(ref mut display) = rt.display {
+        display.push(Display::Html((*html).clone()));
+    }
+    Ok(())
+}
+
+pub(crate) fn display_png(rt: &mut Runtime) -> Result<(), RuntimeError> {
+    use kernel::Display;
+
+    let bytes = rt.stack.pop().expect(TINVOTS);
+    let bytes = match rt.resolve(&bytes) {
+        &Variable::Bytes(ref b) => (**b).clone(),
+        &Variable::Array(ref arr) => {
+            let mut bytes = Vec::with_capacity(arr.len());
+            for it in arr.iter() {
+                match rt.resolve(it) {
+                    &Variable::F64(v, _) if (0.0..=255.0).contains(&v) && v.fract() == 0.0 => {
+                        bytes.push(v as u8)
+                    }
+                    x => return Err(rt.expected_arg(0, x, "byte (integer 0-255)")),
+                }
+            }
+            bytes
+        }
+        x => return Err(rt.expected_arg(0, x, "bytes or [f64]")),
+    };
+    if let Some(ref mut display) = rt.display {
+        display.push(Display::Png(bytes));
+    }
+    Ok(())
+}
+
+/// A weak reference to the shared storage of an array or object.
+pub(crate) enum Weak {
+    Array(::std::sync::Weak<Vec<Variable>>),
+    Object(::std::sync::Weak<HashMap<Arc<String>, Variable>>),
+}
+
+pub(crate) fn weak(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    let weak = match rt.resolve(&v) {
+        &Variable::Array(ref arr) => Weak::Array(Arc::downgrade(arr)),
+        &Variable::Object(ref obj) => Weak::Object(Arc::downgrade(obj)),
+        x => return Err(rt.expected_arg(0, x, "array or object")),
+    };
+    Ok(Variable::RustObject(
+        Arc::new(Mutex::new(weak)) as RustObject
+    ))
+}
+
+pub(crate) fn upgrade(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    let obj = match rt.resolve(&v) {
+        &Variable::RustObject(ref obj) => obj.clone(),
+        x => return Err(rt.expected_arg(0, x, "weak")),
+    };
+    let guard = obj
+        .lock()
+        .map_err(|err| format!("Can not lock weak reference:\n{}", err))?;
+    let v = match guard.downcast_ref::<Weak>() {
+        Some(&Weak::Array(ref arr)) => arr.upgrade().map(Variable::Array),
+        Some(&Weak::Object(ref obj)) => obj.upgrade().map(Variable::Object),
+        None => {
+            return Err({
+                rt.arg_err_index.set(Some(0));
+                "Expected weak reference".into()
+            })
+        }
+    };
+    Ok(Variable::Option(v.map(Box::new)))
+}
+
+fn is_weak(v: &Variable) -> bool {
+    match *v {
+        Variable::RustObject(ref obj) => match obj.lock() {
+            Ok(guard) => guard.is::<Weak>(),
+            Err(_) => false,
+        },
+        _ => false,
+    }
+}
+
+pub(crate) fn is_ref(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    Ok(Variable::bool(is_weak(rt.resolve(&v))))
+}
+
+pub(crate) fn deref(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    if !is_weak(rt.resolve(&v)) {
+        return Ok(rt.resolve(&v).deep_clone(&rt.stack));
+    }
+    rt.stack.push(v);
+    match upgrade(rt)? {
+        Variable::Option(Some(v)) => Ok(*v),
+        _ => Err({
+            rt.arg_err_index.set(Some(0));
+            "Weak reference is no longer valid".into()
+        }),
+    }
+}
+
+const GC_DISABLED: &str = "Garbage collected heap is disabled, use `Runtime::with_gc()`";
+
+pub(crate) fn gc_new(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    let v = rt.resolve(&v).deep_clone(&rt.stack);
+    match rt.gc {
+        Some(ref mut heap) => Ok(heap.alloc(v, &rt.stack)),
+        None => Err(GC_DISABLED.into()),
+    }
+}
+
+pub(crate) fn gc_get(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let handle = rt.stack.pop().expect(TINVOTS);
+    let res = match rt.gc {
+        Some(ref heap) => heap.get(rt.resolve(&handle)).cloned(),
+        None => return Err(GC_DISABLED.into()),
+    };
+    if res.is_err() {
+        rt.arg_err_index.set(Some(0));
+    }
+    Ok(res?)
+}
+
+pub(crate) fn gc_set(rt: &mut Runtime) -> Result<(), RuntimeError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    let handle = rt.stack.pop().expect(TINVOTS);
+    let v = rt.resolve(&v).deep_clone(&rt.stack);
+    let handle = rt.resolve(&handle).clone();
+    let res = match rt.gc {
+        Some(ref mut heap) => heap.set(&handle, v),
+        None => return Err(GC_DISABLED.into()),
+    };
+    if res.is_err() {
+        rt.arg_err_index.set(Some(0));
+    }
+    Ok(res?)
+}
+
+pub(crate) fn gc_collect(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    match rt.gc {
+        Some(ref mut heap) => Ok(Variable::f64(heap.collect(&rt.stack) as f64)),
+        None => Err(GC_DISABLED.into()),
+    }
+}
+
+fn persistent_arg(rt: &Runtime, i: usize, v: &Variable) -> Result<Persistent, RuntimeError> {
+    let err = || {
+        rt.arg_err_index.set(Some(i));
+        "Expected persistent vector or map".to_string()
+    };
+    match *rt.resolve(v) {
+        Variable::RustObject(ref obj) => match obj.lock() {
+            Ok(guard) => Ok(guard
+                .downcast_ref::<Persistent>()
+                .cloned()
+                .ok_or_else(err)?),
+            Err(_) => Err(err().into()),
+        },
+        _ => Err(err().into()),
+    }
+}
+
+fn persistent_var(p: Persistent) -> Variable {
+    Variable::RustObject(Arc::new(Mutex::new(p)) as RustObject)
+}
+
+/// Returns a new persistent structure with a key set to a value.
+fn passoc_internal(
+    rt: &Runtime,
+    p: &Persistent,
+    key: &Variable,
+    v: Variable,
+) -> Result<Persistent, RuntimeError> {
+    match (p, rt.resolve(key)) {
+        (&Persistent::Vec(ref vec), &Variable::F64(i, _)) => {
+            let res = if i >= 0.0 {
+                vec.set(i as usize, v)
+            } else {
+                None
+            };
+            match res {
+                Some(vec) => Ok(Persistent::Vec(vec)),
+                None => Err({
+                    rt.arg_err_index.set(Some(1));
+                    format!("Index {} is out of bounds for length {}", i, vec.len()).into()
+                }),
+            }
+        }
+        (&Persistent::Map(ref map), &Variable::Str(ref key)) => {
+            Ok(Persistent::Map(map.insert(key.clone(), v)))
+        }
+        (&Persistent::Vec(_), x) => Err(rt.expected_arg(1, x, "f64")),
+        (&Persistent::Map(_), x) => Err(rt.expected_arg(1, x, "str")),
+    }
+}
+
+fn pget_internal(
+    rt: &Runtime,
+    p: &Persistent,
+    key: &Variable,
+) -> Result<Option<Variable>, RuntimeError> {
+    match (p, rt.resolve(key)) {
+        (&Persistent::Vec(ref vec), &Variable::F64(i, _)) => Ok(if i >= 0.0 {
+            vec.get(i as usize).cloned()
+        } else {
+            None
+        }),
+        (&Persistent::Map(ref map), &Variable::Str(ref key)) => Ok(map.get(key).cloned()),
+        (&Persistent::Vec(_), x) => Err(rt.expected_arg(1, x, "f64")),
+        (&Persistent::Map(_), x) => Err(rt.expected_arg(1, x, "str")),
+    }
+}
+
+pub(crate) fn pvec(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    use persistent::PVec;
+
+    let arr = rt.stack.pop().expect(TINVOTS);
+    let arr = match rt.resolve(&arr) {
+        &Variable::Array(ref arr) => arr.clone(),
+        x => return Err(rt.expected_arg(0, x, "array")),
+    };
+    let mut vec = PVec::new();
+    for (i, it) in arr.iter().enumerate() {
+        vec = vec.set(i, rt.resolve(it).deep_clone(&rt.stack)).unwrap();
+    }
+    Ok(persistent_var(Persistent::Vec(vec)))
+}
+
+pub(crate) fn pmap(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    use persistent::PMap;
+
+    let obj = rt.stack.pop().expect(TINVOTS);
+    let obj = match rt.resolve(&obj) {
+        &Variable::Object(ref obj) => obj.clone(),
+        x => return Err(rt.expected_arg(0, x, "object")),
+    };
+    let mut map = PMap::new();
+    for (key, it) in obj.iter() {
+        map = map.insert(key.clone(), rt.resolve(it).deep_clone(&rt.stack));
+    }
+    Ok(persistent_var(Persistent::Map(map)))
+}
+
+pub(crate) fn passoc(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    let key = rt.stack.pop().expect(TINVOTS);
+    let p = rt.stack.pop().expect(TINVOTS);
+    let p = persistent_arg(rt, 0, &p)?;
+    let v = rt.resolve(&v).deep_clone(&rt.stack);
+    Ok(persistent_var(passoc_internal(rt, &p, &key, v)?))
+}
+
+pub(crate) fn pupdate(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let f = rt.stack.pop().expect(TINVOTS);
+    let key = rt.stack.pop().expect(TINVOTS);
+    let p = rt.stack.pop().expect(TINVOTS);
+    let p = persistent_arg(rt, 0, &p)?;
+    let old = match pget_internal(rt, &p, &key)? {
+        Some(old) => old,
+        None => {
+            return Err({
+                rt.arg_err_index.set(Some(1));
+                "Could not find key".into()
+            })
+        }
+    };
+    let v = rt.call_closure_ret(&f, &[old])?;
+    let v = rt.resolve(&v).deep_clone(&rt.stack);
+    Ok(persistent_var(passoc_internal(rt, &p, &key, v)?))
+}
+
+pub(crate) fn pget(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let key = rt.stack.pop().expect(TINVOTS);
+    let p = rt.stack.pop().expect(TINVOTS);
+    let p = persistent_arg(rt, 0, &p)?;
+    Ok(Variable::Option(pget_internal(rt, &p, &key)?.map(Box::new)))
+}
+
+pub(crate) fn plen(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let p = rt.stack.pop().expect(TINVOTS);
+    Ok(Variable::f64(match persistent_arg(rt, 0, &p)? {
+        Persistent::Vec(vec) => vec.len(),
+        Persistent::Map(map) => map.len(),
+    } as f64))
+}
+
+pub(crate) fn pcollect(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let p = rt.stack.pop().expect(TINVOTS);
+    Ok(match persistent_arg(rt, 0, &p)? {
+        Persistent::Vec(vec) => Variable::Array(Arc::new(vec.to_vec())),
+        Persistent::Map(map) => Variable::Object(Arc::new(map.to_map())),
+    })
+}
+
+/// Appends text to a string without copying the whole string.
+pub(crate) struct StringBuilder {
+    /// Always valid UTF-8.
+    buf: Vec<u8>,
+    chars: usize,
+}
+
+impl StringBuilder {
+    /// Appends bytes written by a function, counting the new characters.
+    fn write<F>(&mut self, f: F)
+    where
+        F: FnOnce(&mut Vec<u8>),
+    {
+        let start = self.buf.len();
+        f(&mut self.buf);
+        self.chars += ::std::str::from_utf8(&self.buf[start..])
+            .unwrap()
+            .chars()
+            .count();
+    }
+}
+
+/// Calls a function with the string builder argument locked.
+fn with_builder<T, F>(rt: &Runtime, i: usize, b: &Variable, f: F) -> Result<T, RuntimeError>
+where
+    F: FnOnce(&Runtime, &mut StringBuilder) -> T,
+{
+    let obj = match rt.resolve(b) {
+        &Variable::RustObject(ref obj) => obj.clone(),
+        x => return Err(rt.expected_arg(i, x, "string builder")),
+    };
+    let mut guard = obj
+        .lock()
+        .map_err(|err| format!("Can not lock string builder:\n{}", err))?;
+    match guard.downcast_mut::<StringBuilder>() {
+        Some(sb) => Ok(f(rt, sb)),
+        None => Err({
+            rt.arg_err_index.set(Some(i));
+            "Expected string builder".into()
+        }),
+    }
+}
+
+dyon_fn! {fn string_builder() -> Variable {
+    Variable::RustObject(Arc::new(Mutex::new(StringBuilder {
+        buf: vec![],
+        chars: 0,
+    })) as RustObject)
+}}
+
+pub(crate) fn sb_push(rt: &mut Runtime) -> Result<(), RuntimeError> {
+    let text = rt.stack.pop().expect(TINVOTS);
+    let b = rt.stack.pop().expect(TINVOTS);
+    let text = match rt.resolve(&text) {
+        &Variable::Str(ref text) => text.clone(),
+        x => return Err(rt.expected_arg(1, x, "str")),
+    };
+    with_builder(rt, 0, &b, |_, sb| {
+        sb.buf.extend_from_slice(text.as_bytes());
+        sb.chars += text.chars().count();
+    })
+}
+
+pub(crate) fn sb_push_num(rt: &mut Runtime) -> Result<(), RuntimeError> {
+    use write::{write_variable, EscapeString};
+
+    let num = rt.stack.pop().expect(TINVOTS);
+    let b = rt.stack.pop().expect(TINVOTS);
+    let num = match rt.resolve(&num) {
+        x @ &Variable::F64(_, _) => x.clone(),
+        x => return Err(rt.expected_arg(1, x, "f64")),
+    };
+    with_builder(rt, 0, &b, |rt, sb| {
+        sb.write(|buf| write_variable(buf, rt, &num, EscapeString::None, 0).unwrap())
+    })
+}
+
+pub(crate) fn sb_len(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let b = rt.stack.pop().expect(TINVOTS);
+    with_builder(rt, 0, &b, |_, sb| Variable::f64(sb.chars as f64))
+}
+
+pub(crate) fn sb_str(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let b = rt.stack.pop().expect(TINVOTS);
+    with_builder(rt, 0, &b, |_, sb| {
+        Variable::Str(Arc::new(String::from_utf8(sb.buf.clone()).unwrap()))
+    })
+}
+
+pub(crate) fn str__builder(rt: &mut Runtime) -> Result<(), RuntimeError> {
+    use write::{write_variable, EscapeString};
+
+    let b = rt.stack.pop().expect(TINVOTS);
+    let v = rt.stack.pop().expect(TINVOTS);
+    with_builder(rt, 1, &b, |rt, sb| {
+        sb.write(|buf| write_variable(buf, rt, &v, EscapeString::None, 0).unwrap())
+    })
+}
+
+pub(crate) fn json_string__builder(rt: &mut Runtime) -> Result<(), RuntimeError> {
+    use write::{write_variable, EscapeString};
+
+    let b = rt.stack.pop().expect(TINVOTS);
+    let v = rt.stack.pop().expect(TINVOTS);
+    with_builder(rt, 1, &b, |rt, sb| {
+        sb.write(|buf| write_variable(buf, rt, &v, EscapeString::Json, 0).unwrap())
+    })
+}
+
+/// Calls a function with the text buffer argument locked.
+fn with_text_buffer<T, F>(rt: &Runtime, b: &Variable, f: F) -> Result<T, RuntimeError>
+where
+    F: FnOnce(&mut Rope) -> Result<T, String>,
+{
+    let obj = match rt.resolve(b) {
+        &Variable::RustObject(ref obj) => obj.clone(),
+        x => return Err(rt.expected_arg(0, x, "text buffer")),
+    };
+    let mut guard = obj
+        .lock()
+        .map_err(|err| format!("Can not lock text buffer:\n{}", err))?;
+    match guard.downcast_mut::<Rope>() {
+        Some(rope) => Ok(f(rope)?),
+        None => Err({
+            rt.arg_err_index.set(Some(0));
+            "Expected text buffer".into()
+        }),
+    }
+}
+
+/// Reads an index or length argument, which must be an integer that fits in `usize`.
+fn index_arg(rt: &Runtime, i: usize, v: &Variable) -> Result<usize, RuntimeError> {
+    match rt.resolve(v) {
+        &Variable::F64(v, _) if v >= 0.0 && v.fract() == 0.0 && v < usize::MAX as f64 => {
+            Ok(v as usize)
+        }
+        x => Err(rt.expected_arg(i, x, "non-negative integer")),
+    }
+}
+
+pub(crate) fn text_buffer(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let text = rt.stack.pop().expect(TINVOTS);
+    let rope = match rt.resolve(&text) {
+        &Variable::Str(ref text) => Rope::new(text),
+        x => return Err(rt.expected_arg(0, x, "str")),
+    };
+    Ok(Variable::RustObject(
+        Arc::new(Mutex::new(rope)) as RustObject
+    ))
+}
+
+pub(crate) fn insert_at(rt: &mut Runtime) -> Result<(), RuntimeError> {
+    let text = rt.stack.pop().expect(TINVOTS);
+    let pos = rt.stack.pop().expect(TINVOTS);
+    let b = rt.stack.pop().expect(TINVOTS);
+    let pos = index_arg(rt, 1, &pos)?;
+    let text = match rt.resolve(&text) {
+        &Variable::Str(ref text) => text.clone(),
+        x => return Err(rt.expected_arg(2, x, "str")),
+    };
+    with_text_buffer(rt, &b, |rope| rope.insert(pos, &text))
+}
+
+pub(crate) fn delete_range(rt: &mut Runtime) -> Result<(), RuntimeError> {
+    let end = rt.stack.pop().expect(TINVOTS);
+    let start = rt.stack.pop().expect(TINVOTS);
+    let b = rt.stack.pop().expect(TINVOTS);
+    let start = index_arg(rt, 1, &start)?;
+    let end = index_arg(rt, 2, &end)?;
+    with_text_buffer(rt, &b, |rope| rope.delete(start, end))
+}
+
+pub(crate) fn line(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let i = rt.stack.pop().expect(TINVOTS);
+    let b = rt.stack.pop().expect(TINVOTS);
+    let i = index_arg(rt, 1, &i)?;
+    let line = with_text_buffer(rt, &b, |rope| {
+        rope.line(i).ok_or_else(|| {
+            format!(
+                "Line {} is out of bounds for line count {}",
+                i,
+                rope.line_count()
+            )
+        })
+    })?;
+    Ok(Variable::Str(Arc::new(line)))
+}
+
+pub(crate) fn line_count(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let b = rt.stack.pop().expect(TINVOTS);
+    let n = with_text_buffer(rt, &b, |rope| Ok(rope.line_count()))?;
+    Ok(Variable::f64(n as f64))
+}
+
+pub(crate) fn text_len(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let b = rt.stack.pop().expect(TINVOTS);
+    let n = with_text_buffer(rt, &b, |rope| Ok(rope.len()))?;
+    Ok(Variable::f64(n as f64))
+}
+
+pub(crate) fn to_str(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let b = rt.stack.pop().expect(TINVOTS);
+    let text = with_text_buffer(rt, &b, |rope| Ok(rope.text()))?;
+    Ok(Variable::Str(Arc::new(text)))
+}
+
+dyon_fn! {fn none() -> Variable {Variable::Option(None)}}
+
+pub(crate) fn some(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    Ok(Variable::Option(Some(Box::new(
+        rt.resolve(&v).deep_clone(&rt.stack),
+    ))))
+}
+
+pub(crate) fn ok(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    let v = Variable::Result(Ok(Box::new(rt.resolve(&v).deep_clone(&rt.stack))));
+    Ok(v)
+}
+
+pub(crate) fn err(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let v = rt.stack.pop().expect(TINVOTS);
     Ok(Variable::Result(Err(Box::new(Error {
         message: rt.resolve(&v).deep_clone(&rt.stack),
         trace: vec![],
+        kind: None,
     }))))
 }
 
-pub(crate) fn is_err(rt: &mut Runtime) -> Result<Variable, String> {
-    let v = rt.stack.pop().expect(TINVOTS);
-    Ok(match rt.resolve(&v) {
-        &Variable::Result(Err(_)) => Variable::bool(true),
-        &Variable::Result(Ok(_)) => Variable::bool(false),
-        x => return Err(rt.expected_arg(0, x, "result")),
+pub(crate) fn is_err(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    Ok(match rt.resolve(&v) {
+        &Variable::Result(Err(_)) => Variable::bool(true),
+        &Variable::Result(Ok(_)) => Variable::bool(false),
+        x => return Err(rt.expected_arg(0, x, "result")),
+    })
+}
+
+pub(crate) fn is_ok(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    Ok(match rt.resolve(&v) {
+        &Variable::Result(Err(_)) => Variable::bool(false),
+        &Variable::Result(Ok(_)) => Variable::bool(true),
+        x => return Err(rt.expected_arg(0, x, "result")),
+    })
+}
+
+pub(crate) fn min(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    Ok(Variable::f64(match rt.resolve(&v) {
+        &Variable::Array(ref arr) => {
+            let mut min: f64 = ::std::f64::NAN;
+            for v in &**arr {
+                if let Variable::F64(val, _) = *rt.resolve(v) {
+                    if val < min || min.is_nan() {
+                        min = val
+                    }
+                }
+            }
+            min
+        }
+        &Variable::F64Array(ref arr) => {
+            arr.iter().fold(
+                f64::NAN,
+                |min, &val| {
+                    if val < min || min.is_nan() {
+                        val
+                    } else {
+                        min
+                    }
+                },
+            )
+        }
+        x => return Err(rt.expected_arg(0, x, "array")),
+    }))
+}
+
+pub(crate) fn max(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    Ok(Variable::f64(match rt.resolve(&v) {
+        &Variable::Array(ref arr) => {
+            let mut max: f64 = ::std::f64::NAN;
+            for v in &**arr {
+                if let Variable::F64(val, _) = *rt.resolve(v) {
+                    if val > max || max.is_nan() {
+                        max = val
+                    }
+                }
+            }
+            max
+        }
+        &Variable::F64Array(ref arr) => {
+            arr.iter().fold(
+                f64::NAN,
+                |max, &val| {
+                    if val > max || max.is_nan() {
+                        val
+                    } else {
+                        max
+                    }
+                },
+            )
+        }
+        x => return Err(rt.expected_arg(0, x, "array")),
+    }))
+}
+
+/// Folds an array of numbers, either `[f64]` or `farr`.
+fn fold_numbers(
+    rt: &Runtime,
+    v: &Variable,
+    init: f64,
+    f: fn(f64, f64) -> f64,
+) -> Result<f64, RuntimeError> {
+    match rt.resolve(v) {
+        &Variable::Array(ref arr) => {
+            let mut acc = init;
+            for it in arr.iter() {
+                match *rt.resolve(it) {
+                    Variable::F64(x, _) => acc = f(acc, x),
+                    ref x => return Err(rt.expected_arg(0, x, "array of numbers")),
+                }
+            }
+            Ok(acc)
+        }
+        &Variable::F64Array(ref arr) => Ok(arr.iter().fold(init, |acc, &x| f(acc, x))),
+        x => Err(rt.expected_arg(0, x, "array of numbers")),
+    }
+}
+
+pub(crate) fn sum(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    Ok(Variable::f64(fold_numbers(rt, &v, 0.0, |a, b| a + b)?))
+}
+
+pub(crate) fn prod(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    Ok(Variable::f64(fold_numbers(rt, &v, 1.0, |a, b| a * b)?))
+}
+
+fn f64_array_arg(rt: &Runtime, i: usize, v: &Variable) -> Result<Arc<Vec<f64>>, RuntimeError> {
+    match rt.resolve(v) {
+        &Variable::F64Array(ref arr) => Ok(arr.clone()),
+        x => Err(rt.expected_arg(i, x, "farr")),
+    }
+}
+
+pub(crate) fn farr(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    match rt.resolve(&v) {
+        &Variable::F64Array(ref arr) => Ok(Variable::F64Array(arr.clone())),
+        &Variable::Array(ref arr) => {
+            let mut res = Vec::with_capacity(arr.len());
+            for it in arr.iter() {
+                match *rt.resolve(it) {
+                    Variable::F64(x, _) => res.push(x),
+                    ref x => return Err(rt.expected_arg(0, x, "array of numbers")),
+                }
+            }
+            Ok(Variable::F64Array(Arc::new(res)))
+        }
+        x => Err(rt.expected_arg(0, x, "array of numbers")),
+    }
+}
+
+pub(crate) fn farr_zeros(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let n = rt.stack.pop().expect(TINVOTS);
+    let n = index_arg(rt, 0, &n)?;
+    rt.check_array_len(n)?;
+    let mut arr = vec![];
+    if arr.try_reserve_exact(n).is_err() {
+        return Err({
+            rt.arg_err_index.set(Some(0));
+            format!("Could not allocate `{}` numbers", n).into()
+        });
+    }
+    arr.resize(n, 0.0);
+    Ok(Variable::F64Array(Arc::new(arr)))
+}
+
+pub(crate) fn arr_from_farr(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    let arr = f64_array_arg(rt, 0, &v)?;
+    Ok(Variable::Array(Arc::new(
+        arr.iter().map(|&x| Variable::f64(x)).collect(),
+    )))
+}
+
+pub(crate) fn farr_get(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let i = rt.stack.pop().expect(TINVOTS);
+    let v = rt.stack.pop().expect(TINVOTS);
+    let arr = f64_array_arg(rt, 0, &v)?;
+    let i = index_arg(rt, 1, &i)?;
+    match arr.get(i) {
+        Some(&x) => Ok(Variable::f64(x)),
+        None => Err({
+            rt.arg_err_index.set(Some(1));
+            format!("Index `{}` is out of bounds `0..{}`", i, arr.len()).into()
+        }),
+    }
+}
+
+pub(crate) fn farr_set(rt: &mut Runtime) -> Result<(), RuntimeError> {
+    let x = rt.stack.pop().expect(TINVOTS);
+    let i = rt.stack.pop().expect(TINVOTS);
+    let v = rt.stack.pop().expect(TINVOTS);
+    let x = match *rt.resolve(&x) {
+        Variable::F64(x, _) => x,
+        ref x => return Err(rt.expected_arg(2, x, "f64")),
+    };
+    let i = index_arg(rt, 1, &i)?;
+    if let Variable::Ref(ind) = v {
+        if let Variable::F64Array(ref mut arr) = rt.stack[ind] {
+            let len = arr.len();
+            return match Arc::make_mut(arr).get_mut(i) {
+                Some(it) => {
+                    *it = x;
+                    Ok(())
+                }
+                None => Err({
+                    rt.arg_err_index.set(Some(1));
+                    format!("Index `{}` is out of bounds `0..{}`", i, len).into()
+                }),
+            };
+        }
+    }
+    Err({
+        rt.arg_err_index.set(Some(0));
+        "Expected reference to farr".into()
+    })
+}
+
+pub(crate) fn unwrap(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    use write::{write_variable, EscapeString};
+
+    // Return value does not depend on lifetime of argument since
+    // `ok(x)` and `some(x)` perform a deep clone.
+    let v = rt.stack.pop().expect(TINVOTS);
+    Ok(match rt.resolve(&v) {
+        &Variable::Option(Some(ref v)) => (**v).clone(),
+        &Variable::Option(None) => {
+            return Err({
+                rt.arg_err_index.set(Some(0));
+                "Expected `some(_)`".into()
+            })
+        }
+        &Variable::Result(Ok(ref ok)) => (**ok).clone(),
+        &Variable::Result(Err(ref err)) => {
+            use std::str::from_utf8;
+
+            // Print out error message.
+            let mut w: Vec<u8> = vec![];
+            w.extend_from_slice(rt.stack_trace().as_bytes());
+            w.extend_from_slice("\n".as_bytes());
+            write_variable(&mut w, rt, &err.message, EscapeString::None, 0).unwrap();
+            for t in &err.trace {
+                w.extend_from_slice("\n".as_bytes());
+                w.extend_from_slice(t.as_bytes());
+            }
+            return Err({
+                rt.arg_err_index.set(Some(0));
+                RuntimeError::new(
+                    err.kind
+                        .clone()
+                        .unwrap_or_else(|| ErrorKind::User(err.message.clone())),
+                    None,
+                    from_utf8(&w).unwrap().into(),
+                )
+            });
+        }
+        x => return Err(rt.expected_arg(0, x, "some(_) or ok(_)")),
+    })
+}
+
+pub(crate) fn unwrap_or(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    // Return value does not depend on lifetime of argument since
+    // `ok(x)` and `some(x)` perform a deep clone.
+    let def = rt.stack.pop().expect(TINVOTS);
+    let v = rt.stack.pop().expect(TINVOTS);
+    Ok(match rt.resolve(&v) {
+        &Variable::Option(Some(ref v)) => (**v).clone(),
+        &Variable::Result(Ok(ref ok)) => (**ok).clone(),
+        &Variable::Option(None) | &Variable::Result(Err(_)) => rt.resolve(&def).clone(),
+        x => return Err(rt.expected_arg(0, x, "some(_) or ok(_)")),
+    })
+}
+
+pub(crate) fn unwrap_err(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    Ok(match rt.resolve(&v) {
+        &Variable::Result(Err(ref err)) => err.message.clone(),
+        x => return Err(rt.expected_arg(0, x, "err(_)")),
+    })
+}
+
+pub(crate) fn try_call(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let f = rt.stack.pop().expect(TINVOTS);
+    let f = closure_arg(rt, 0, f)?;
+    let point = rt.catch_point();
+    match rt.call_closure_ret(&f, &[]) {
+        Ok(x) => Ok(Variable::Result(Ok(Box::new(x)))),
+        Err(err) => rt.catch(point, err),
+    }
+}
+
+dyon_fn! {fn dir__angle(val: f64) -> Vec4 {Vec4([val.cos() as Scalar, val.sin() as Scalar, 0.0, 0.0])}}
+
+pub(crate) fn load__meta_file(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let file = rt.stack.pop().expect(TINVOTS);
+    let file = str_arg(rt, 1, &file)?;
+    let meta = rt.stack.pop().expect(TINVOTS);
+    let meta = str_arg(rt, 0, &meta)?;
+    let res = meta::load_meta_file(&*rt.vfs, &meta, &file);
+    Ok(Variable::Result(match res {
+        Ok(res) => Ok(Box::new(Variable::Array(Arc::new(res)))),
+        Err(err) => Err(Box::new(Error {
+            message: Variable::Str(Arc::new(err)),
+            trace: vec![],
+            kind: None,
+        })),
+    }))
+}
+
+pub(crate) fn load__meta_url(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let url = rt.stack.pop().expect(TINVOTS);
+    let url = str_arg(rt, 1, &url)?;
+    let meta = rt.stack.pop().expect(TINVOTS);
+    let meta = str_arg(rt, 0, &meta)?;
+    let res = meta::load_meta_url(&*rt.vfs, &meta, &url);
+    Ok(Variable::Result(match res {
+        Ok(res) => Ok(Box::new(Variable::Array(Arc::new(res)))),
+        Err(err) => Err(Box::new(Error {
+            message: Variable::Str(Arc::new(err)),
+            trace: vec![],
+            kind: None,
+        })),
+    }))
+}
+
+dyon_fn! {fn syntax__in_string(name: Arc<String>, text: Arc<String>) -> Variable {
+    use piston_meta::syntax_errstr;
+
+    let res = syntax_errstr(&text).map_err(|err|
+        format!("When parsing meta syntax in `{}`:\n{}", name, err));
+    Variable::Result(match res {
+        Ok(res) => Ok(Box::new(Variable::RustObject(Arc::new(Mutex::new(Arc::new(res)))))),
+        Err(err) => Err(Box::new(Error {
+            message: Variable::Str(Arc::new(err)),
+            trace: vec![], kind: None,
+        }))
+    })
+}}
+
+pub(crate) fn meta__syntax_in_string(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let text = rt.stack.pop().expect(TINVOTS);
+    let text = match rt.resolve(&text) {
+        &Variable::Str(ref t) => t.clone(),
+        x => return Err(rt.expected_arg(2, x, "str")),
+    };
+    let name = rt.stack.pop().expect(TINVOTS);
+    let name = match rt.resolve(&name) {
+        &Variable::Str(ref t) => t.clone(),
+        x => return Err(rt.expected_arg(1, x, "str")),
+    };
+    let syntax_var = rt.stack.pop().expect(TINVOTS);
+    let syntax_var = rt.resolve(&syntax_var);
+    let syntax = match syntax_var {
+        &Variable::RustObject(ref obj) => obj.clone(),
+        x => return Err(rt.expected_arg(0, x, "Syntax")),
+    };
+    let res = meta::parse_syntax_data(
+        match syntax.lock().unwrap().downcast_ref::<Arc<Syntax>>() {
+            Some(s) => s,
+            None => return Err(rt.expected_arg(0, syntax_var, "Syntax")),
+        },
+        &name,
+        &text,
+    );
+    Ok(Variable::Result(match res {
+        Ok(res) => Ok(Box::new(Variable::Array(Arc::new(res)))),
+        Err(err) => Err(Box::new(Error {
+            message: Variable::Str(Arc::new(err)),
+            trace: vec![],
+            kind: None,
+        })),
+    }))
+}
+
+pub(crate) fn download__url_file(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let file = rt.stack.pop().expect(TINVOTS);
+    let file = str_arg(rt, 1, &file)?;
+    let url = rt.stack.pop().expect(TINVOTS);
+    let url = str_arg(rt, 0, &url)?;
+    let res = meta::download_url_to_file(&*rt.vfs, &url, &file);
+    Ok(Variable::Result(match res {
+        Ok(res) => Ok(Box::new(Variable::Str(Arc::new(res)))),
+        Err(err) => Err(Box::new(Error {
+            message: Variable::Str(Arc::new(err)),
+            trace: vec![],
+            kind: None,
+        })),
+    }))
+}
+
+/// Saves a string to a file, optionally writing it atomically.
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+fn save_string(rt: &mut Runtime, atomic: bool) -> Result<Variable, RuntimeError> {
+    use std::io::Write;
+
+    let file = rt.stack.pop().expect(TINVOTS);
+    let file = str_arg(rt, 1, &file)?;
+    let text = rt.stack.pop().expect(TINVOTS);
+    let text = str_arg(rt, 0, &text)?;
+    let res = if atomic {
+        rt.vfs.write_atomic(&file, text.as_bytes())
+    } else {
+        rt.vfs
+            .create(&file)
+            .and_then(|mut f| f.write_all(text.as_bytes()).and_then(|()| f.flush()))
+    };
+    Ok(Variable::Result(match res {
+        Ok(()) => Ok(Box::new(Variable::Str(file))),
+        Err(err) => Err(io_err(err.to_string())),
+    }))
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+pub(crate) fn save__string_file(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    save_string(rt, false)
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+pub(crate) fn save_atomic__string_file(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    save_string(rt, true)
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+pub(crate) fn save__string_file_atomic(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let atomic = rt.stack.pop().expect(TINVOTS);
+    let atomic = match rt.resolve(&atomic) {
+        &Variable::Bool(b, _) => b,
+        x => return Err(rt.expected_arg(2, x, "bool")),
+    };
+    save_string(rt, atomic)
+}
+
+/// Computes the SHA-256 hash of a file in the file system of the runtime.
+#[cfg(all(not(target_family = "wasm"), feature = "file", feature = "crypto"))]
+fn file_sha256(rt: &Runtime, file: &str) -> Result<String, Box<Error>> {
+    rt.vfs
+        .open(file)
+        .and_then(self::io::read_sha256)
+        .map_err(|err| io_err(self::io::io_error("open", file, &err)))
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "file", feature = "crypto"))]
+pub(crate) fn sha256__file(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let file = rt.stack.pop().expect(TINVOTS);
+    let file = str_arg(rt, 0, &file)?;
+    Ok(Variable::Result(match file_sha256(rt, &file) {
+        Ok(hash) => Ok(Box::new(Variable::Str(Arc::new(hash)))),
+        Err(err) => Err(err),
+    }))
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "file", feature = "crypto"))]
+pub(crate) fn verify__file_sha256(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let hash = rt.stack.pop().expect(TINVOTS);
+    let hash = str_arg(rt, 1, &hash)?;
+    let file = rt.stack.pop().expect(TINVOTS);
+    let file = str_arg(rt, 0, &file)?;
+    Ok(Variable::Result(match file_sha256(rt, &file) {
+        Ok(h) => Ok(Box::new(Variable::bool(
+            h.eq_ignore_ascii_case(hash.trim()),
+        ))),
+        Err(err) => Err(err),
+    }))
+}
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "file")))]
+pub(crate) fn save_atomic__string_file(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(FILE_SUPPORT_DISABLED.into())
+}
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "file")))]
+pub(crate) fn save__string_file_atomic(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(FILE_SUPPORT_DISABLED.into())
+}
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "file")))]
+pub(crate) fn sha256__file(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(FILE_SUPPORT_DISABLED.into())
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "file", not(feature = "crypto")))]
+pub(crate) fn sha256__file(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(CRYPTO_SUPPORT_DISABLED.into())
+}
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "file")))]
+pub(crate) fn verify__file_sha256(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(FILE_SUPPORT_DISABLED.into())
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "file", not(feature = "crypto")))]
+pub(crate) fn verify__file_sha256(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(CRYPTO_SUPPORT_DISABLED.into())
+}
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "file")))]
+pub(crate) fn save__string_file(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(FILE_SUPPORT_DISABLED.into())
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+pub(crate) fn load_string__file(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let file = rt.stack.pop().expect(TINVOTS);
+    let file = str_arg(rt, 0, &file)?;
+    Ok(Variable::Result(match rt.vfs.read_to_string(&file) {
+        Ok(s) => Ok(Box::new(Variable::Str(Arc::new(s)))),
+        Err(err) => Err(io_err(err.to_string())),
+    }))
+}
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "file")))]
+pub(crate) fn load_string__file(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(FILE_SUPPORT_DISABLED.into())
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+pub(crate) fn load_bytes__file(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    use std::io::Read;
+
+    let file = rt.stack.pop().expect(TINVOTS);
+    let file = str_arg(rt, 0, &file)?;
+    let mut data = vec![];
+    let res = rt
+        .vfs
+        .open(&file)
+        .and_then(|mut f| f.read_to_end(&mut data));
+    Ok(Variable::Result(match res {
+        Ok(_) => Ok(Box::new(Variable::Bytes(Arc::new(data)))),
+        Err(err) => Err(io_err(self::io::io_error("open", &file, &err))),
+    }))
+}
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "file")))]
+pub(crate) fn load_bytes__file(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(FILE_SUPPORT_DISABLED.into())
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+pub(crate) fn save_bytes__file(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    use std::io::Write;
+
+    let file = rt.stack.pop().expect(TINVOTS);
+    let file = str_arg(rt, 1, &file)?;
+    let data = rt.stack.pop().expect(TINVOTS);
+    let data = bytes_arg(rt, 0, &data)?;
+    let res = rt
+        .vfs
+        .create(&file)
+        .and_then(|mut f| f.write_all(&data).and_then(|()| f.flush()));
+    Ok(Variable::Result(match res {
+        Ok(()) => Ok(Box::new(Variable::Str(file))),
+        Err(err) => Err(io_err(err.to_string())),
+    }))
+}
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "file")))]
+pub(crate) fn save_bytes__file(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(FILE_SUPPORT_DISABLED.into())
+}
+
+fn bytes_arg(rt: &Runtime, i: usize, v: &Variable) -> Result<Arc<Vec<u8>>, RuntimeError> {
+    match rt.resolve(v) {
+        &Variable::Bytes(ref b) => Ok(b.clone()),
+        x => Err(rt.expected_arg(i, x, "bytes")),
+    }
+}
+
+pub(crate) fn byte(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let i = rt.stack.pop().expect(TINVOTS);
+    let bytes = rt.stack.pop().expect(TINVOTS);
+    let bytes = bytes_arg(rt, 0, &bytes)?;
+    let i = index_arg(rt, 1, &i)?;
+    if i >= bytes.len() {
+        return Err({
+            rt.arg_err_index.set(Some(1));
+            format!("Index `{}` is out of bounds `0..{}`", i, bytes.len()).into()
+        });
+    }
+    Ok(Variable::f64(f64::from(bytes[i])))
+}
+
+pub(crate) fn bytes_len(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let bytes = rt.stack.pop().expect(TINVOTS);
+    let bytes = bytes_arg(rt, 0, &bytes)?;
+    Ok(Variable::f64(bytes.len() as f64))
+}
+
+pub(crate) fn slice_bytes(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let end = rt.stack.pop().expect(TINVOTS);
+    let start = rt.stack.pop().expect(TINVOTS);
+    let bytes = rt.stack.pop().expect(TINVOTS);
+    let bytes = bytes_arg(rt, 0, &bytes)?;
+    let end = index_arg(rt, 2, &end)?;
+    if end > bytes.len() {
+        return Err({
+            rt.arg_err_index.set(Some(2));
+            format!("End `{}` is greater than length `{}`", end, bytes.len()).into()
+        });
+    }
+    let start = index_arg(rt, 1, &start)?;
+    if start > end {
+        return Err({
+            rt.arg_err_index.set(Some(1));
+            format!("Start `{}` is greater than end `{}`", start, end).into()
+        });
+    }
+    Ok(Variable::Bytes(Arc::new(bytes[start..end].to_vec())))
+}
+
+/// Copies a range of items into a new array.
+///
+/// There are no array views, so this allocates unless every item is taken.
+pub(crate) fn slice(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let end = rt.stack.pop().expect(TINVOTS);
+    let start = rt.stack.pop().expect(TINVOTS);
+    let v = rt.stack.pop().expect(TINVOTS);
+    let arr = match rt.resolve(&v) {
+        &Variable::Array(ref arr) => arr.clone(),
+        x => return Err(rt.expected_arg(0, x, "array")),
+    };
+    let end = index_arg(rt, 2, &end)?;
+    if end > arr.len() {
+        return Err({
+            rt.arg_err_index.set(Some(2));
+            format!("End `{}` is greater than length `{}`", end, arr.len()).into()
+        });
+    }
+    let start = index_arg(rt, 1, &start)?;
+    if start > end {
+        return Err({
+            rt.arg_err_index.set(Some(1));
+            format!("Start `{}` is greater than end `{}`", start, end).into()
+        });
+    }
+    if start == 0 && end == arr.len() {
+        return Ok(Variable::Array(arr).deep_clone(&rt.stack));
+    }
+    Ok(Variable::Array(Arc::new(
+        arr[start..end]
+            .iter()
+            .map(|it| it.deep_clone(&rt.stack))
+            .collect(),
+    )))
+}
+
+pub(crate) fn str_from_utf8(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let bytes = rt.stack.pop().expect(TINVOTS);
+    let bytes = bytes_arg(rt, 0, &bytes)?;
+    Ok(Variable::Result(match String::from_utf8(bytes.to_vec()) {
+        Ok(s) => Ok(Box::new(Variable::Str(Arc::new(s)))),
+        Err(err) => Err(Box::new(Error {
+            message: Variable::Str(Arc::new(err.to_string())),
+            trace: vec![],
+            kind: None,
+        })),
+    }))
+}
+
+pub(crate) fn bytes_from_str(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let text = rt.stack.pop().expect(TINVOTS);
+    let text = str_arg(rt, 0, &text)?;
+    Ok(Variable::Bytes(Arc::new(text.as_bytes().to_vec())))
+}
+
+/// Reads the bytes of a `bytes` or the UTF-8 encoding of a `str`.
+#[cfg(feature = "crypto")]
+fn bytes_or_str_arg(rt: &Runtime, i: usize, v: &Variable) -> Result<Arc<Vec<u8>>, RuntimeError> {
+    match rt.resolve(v) {
+        &Variable::Bytes(ref b) => Ok(b.clone()),
+        &Variable::Str(ref s) => Ok(Arc::new(s.as_bytes().to_vec())),
+        x => Err(rt.expected_arg(i, x, "bytes or str")),
+    }
+}
+
+#[cfg(feature = "crypto")]
+pub(crate) fn hash(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    match self::hash::hash(rt.resolve(&v)) {
+        Ok(h) => Ok(Variable::f64(h)),
+        Err(err) => {
+            rt.arg_err_index.set(Some(0));
+            Err(err.into())
+        }
+    }
+}
+
+#[cfg(not(feature = "crypto"))]
+pub(crate) fn hash(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(CRYPTO_SUPPORT_DISABLED.into())
+}
+
+#[cfg(feature = "crypto")]
+pub(crate) fn crc32(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let data = rt.stack.pop().expect(TINVOTS);
+    let data = bytes_or_str_arg(rt, 0, &data)?;
+    Ok(Variable::f64(f64::from(self::hash::crc32(&data))))
+}
+
+#[cfg(not(feature = "crypto"))]
+pub(crate) fn crc32(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(CRYPTO_SUPPORT_DISABLED.into())
+}
+
+#[cfg(feature = "crypto")]
+pub(crate) fn sha256(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let data = rt.stack.pop().expect(TINVOTS);
+    let data = bytes_or_str_arg(rt, 0, &data)?;
+    use self::sha256::{hex, Digest, Sha256};
+
+    Ok(Variable::Str(Arc::new(hex(&Sha256::digest(&*data)))))
+}
+
+#[cfg(not(feature = "crypto"))]
+pub(crate) fn sha256(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(CRYPTO_SUPPORT_DISABLED.into())
+}
+
+#[cfg(feature = "crypto")]
+pub(crate) fn content_hash(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    // Deep clone shares arrays and objects without references,
+    // which keeps their cached hashes.
+    let v = rt.resolve(&v).deep_clone(&rt.stack);
+    match self::content_hash::content_hash(&v) {
+        Ok(h) => Ok(Variable::Str(Arc::new(h))),
+        Err(err) => {
+            rt.arg_err_index.set(Some(0));
+            Err(err.into())
+        }
+    }
+}
+
+#[cfg(not(feature = "crypto"))]
+pub(crate) fn content_hash(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(CRYPTO_SUPPORT_DISABLED.into())
+}
+
+#[cfg(feature = "msgpack")]
+pub(crate) fn to_msgpack(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    let mut data = vec![];
+    if let Err(err) = msgpack::write(rt, &v, &mut data) {
+        rt.arg_err_index.set(Some(0));
+        return Err(err);
+    }
+    Ok(Variable::Bytes(Arc::new(data)))
+}
+
+#[cfg(not(feature = "msgpack"))]
+pub(crate) fn to_msgpack(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(MSGPACK_SUPPORT_DISABLED.into())
+}
+
+#[cfg(feature = "msgpack")]
+pub(crate) fn from_msgpack(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let data = rt.stack.pop().expect(TINVOTS);
+    let data = bytes_arg(rt, 0, &data)?;
+    Ok(Variable::Result(match msgpack::read(&data) {
+        Ok(v) => Ok(Box::new(v)),
+        Err(err) => Err(Box::new(Error {
+            message: Variable::Str(Arc::new(err)),
+            trace: vec![],
+            kind: None,
+        })),
+    }))
+}
+
+#[cfg(not(feature = "msgpack"))]
+pub(crate) fn from_msgpack(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(MSGPACK_SUPPORT_DISABLED.into())
+}
+
+pub(crate) fn sync_encode(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let next = rt.stack.pop().expect(TINVOTS);
+    let prev = rt.stack.pop().expect(TINVOTS);
+    Ok(Variable::Bytes(Arc::new(sync::encode(
+        rt, &prev, &next, None,
+    )?)))
+}
+
+pub(crate) fn sync_encode__interest(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let interest = rt.stack.pop().expect(TINVOTS);
+    let next = rt.stack.pop().expect(TINVOTS);
+    let prev = rt.stack.pop().expect(TINVOTS);
+    let interest = match rt.resolve(&interest) {
+        &Variable::Array(ref arr) => arr
+            .iter()
+            .map(|v| str_arg(rt, 2, v))
+            .collect::<Result<Vec<_>, _>>()?,
+        x => return Err(rt.expected_arg(2, x, "array of strings")),
+    };
+    Ok(Variable::Bytes(Arc::new(sync::encode(
+        rt,
+        &prev,
+        &next,
+        Some(&interest),
+    )?)))
+}
+
+pub(crate) fn sync_apply(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let patch = rt.stack.pop().expect(TINVOTS);
+    let state = rt.stack.pop().expect(TINVOTS);
+    let patch = bytes_arg(rt, 1, &patch)?;
+    let state = rt.resolve(&state).deep_clone(&rt.stack);
+    Ok(Variable::Result(match sync::apply(state, &patch) {
+        Ok(v) => Ok(Box::new(v)),
+        Err(err) => Err(Box::new(Error {
+            message: Variable::Str(Arc::new(err)),
+            trace: vec![],
+            kind: None,
+        })),
+    }))
+}
+
+dyon_fn! {fn load_string__url(url: Arc<String>) -> Variable {
+    Variable::Result(match meta::load_text_file_from_url(&**url) {
+        Ok(s) => {
+            Ok(Box::new(Variable::Str(Arc::new(s))))
+        }
+        Err(err) => {
+            Err(Box::new(Error {
+                message: Variable::Str(Arc::new(err)),
+                trace: vec![], kind: None,
+            }))
+        }
+    })
+}}
+
+#[cfg(all(not(target_family = "wasm"), feature = "threading"))]
+pub(crate) fn join__thread(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let thread = rt.stack.pop().expect(TINVOTS);
+    let handle_res = Thread::invalidate_handle(rt, thread);
+    Ok(Variable::Result({
+        match handle_res {
+            Ok(handle) => match handle.join() {
+                Ok(res) => match res {
+                    Ok(res) => Ok(Box::new(res)),
+                    Err(err) => Err(Box::new(Error {
+                        message: Variable::Str(Arc::new(err.into())),
+                        trace: vec![],
+                        kind: None,
+                    })),
+                },
+                Err(_err) => Err(Box::new(Error {
+                    message: Variable::Str(Arc::new("Thread did not exit successfully".into())),
+                    trace: vec![],
+                    kind: None,
+                })),
+            },
+            Err(err) => Err(Box::new(Error {
+                message: Variable::Str(Arc::new(err.into())),
+                trace: vec![],
+                kind: None,
+            })),
+        }
+    }))
+}
+
+pub(crate) fn load_data__file(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let file = rt.stack.pop().expect(TINVOTS);
+    let file = str_arg(rt, 0, &file)?;
+    let res = match data::load_file(&*rt.vfs, &file, rt.settings.max_array_len) {
+        Ok(data) => Ok(Box::new(data)),
+        Err(err) => Err(Box::new(Error {
+            message: Variable::Str(Arc::new(format!(
+                "Error loading data from file `{}`:\n{}",
+                file, err
+            ))),
+            trace: vec![],
+            kind: None,
+        })),
+    };
+    Ok(Variable::Result(res))
+}
+
+pub(crate) fn load_model__file(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let file = rt.stack.pop().expect(TINVOTS);
+    let file = str_arg(rt, 0, &file)?;
+    let res = data::load_file(&*rt.vfs, &file, rt.settings.max_array_len)
+        .and_then(|data| nn::Mlp::from_data(&data))
+        .map(|model| Box::new(Variable::RustObject(Arc::new(Mutex::new(model)))))
+        .map_err(|err| {
+            Box::new(Error {
+                message: Variable::Str(Arc::new(format!(
+                    "Error loading model from file `{}`:\n{}",
+                    file, err
+                ))),
+                trace: vec![],
+                kind: None,
+            })
+        });
+    Ok(Variable::Result(res))
+}
+
+pub(crate) fn infer(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let input = rt.stack.pop().expect(TINVOTS);
+    let input = match *rt.resolve(&input) {
+        Variable::Array(ref arr) => {
+            let mut res = Vec::with_capacity(arr.len());
+            for x in arr.iter() {
+                match *rt.resolve(x) {
+                    Variable::F64(x, _) => res.push(x),
+                    ref x => return Err(rt.expected_arg(1, x, "number")),
+                }
+            }
+            res
+        }
+        ref x => return Err(rt.expected_arg(1, x, "[f64]")),
+    };
+    let model = rt.stack.pop().expect(TINVOTS);
+    let output = match *rt.resolve(&model) {
+        Variable::RustObject(ref obj) => match obj.lock().unwrap().downcast_ref::<nn::Mlp>() {
+            Some(model) => model.infer(&input)?,
+            None => return Err(rt.expected_arg(0, &model, "model")),
+        },
+        ref x => return Err(rt.expected_arg(0, x, "model")),
+    };
+    Ok(Variable::Array(Arc::new(
+        output.into_iter().map(Variable::f64).collect(),
+    )))
+}
+
+/// Migrations of data, sorted by version.
+struct Migrations(Vec<(f64, Variable)>);
+
+lazy_static! {
+    static ref VERSION: Arc<String> = Arc::new("version".into());
+}
+
+pub(crate) fn migrations(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    lazy_static! {
+        static ref UP: Arc<String> = Arc::new("up".into());
+    }
+
+    let list = rt.stack.pop().expect(TINVOTS);
+    let list = match rt.resolve(&list) {
+        &Variable::Array(ref arr) => arr.clone(),
+        x => return Err(rt.expected_arg(0, x, "array")),
+    };
+    let mut res: Vec<(f64, Variable)> = Vec::with_capacity(list.len());
+    for m in list.iter() {
+        let (version, up) = match rt.resolve(m) {
+            &Variable::Object(ref obj) => match (obj.get(&*VERSION), obj.get(&*UP)) {
+                (Some(version), Some(up)) => {
+                    (rt.var::<f64>(version)?, closure_arg(rt, 0, up.clone())?)
+                }
+                _ => {
+                    return Err({
+                        rt.arg_err_index.set(Some(0));
+                        "Expected migration `{version: _, up: _}`".into()
+                    })
+                }
+            },
+            x => return Err(rt.expected_arg(0, x, "object")),
+        };
+        if let Some(&(prev, _)) = res.last() {
+            if version <= prev || version.is_nan() {
+                return Err({
+                    rt.arg_err_index.set(Some(0));
+                    format!(
+                        "Expected increasing versions, found {} after {}",
+                        version, prev
+                    )
+                    .into()
+                });
+            }
+        }
+        res.push((version, up));
+    }
+    Ok(Variable::RustObject(Arc::new(Mutex::new(Migrations(res)))))
+}
+
+pub(crate) fn migrate(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let data = rt.stack.pop().expect(TINVOTS);
+    let m = rt.stack.pop().expect(TINVOTS);
+    let migrations = match rt.resolve(&m) {
+        &Variable::RustObject(ref obj) => obj
+            .lock()
+            .unwrap()
+            .downcast_ref::<Migrations>()
+            .map(|m| m.0.clone()),
+        _ => None,
+    };
+    let migrations = match migrations {
+        Some(x) => x,
+        None => return Err(rt.expected_arg(0, &m, "migrations")),
+    };
+    let mut data = match rt.resolve(&data) {
+        &Variable::Object(ref obj) => obj.clone(),
+        x => return Err(rt.expected_arg(1, x, "object")),
+    };
+    let latest = migrations.last().map(|m| m.0).unwrap_or(0.0);
+    let version = match data.get(&*VERSION).map(|v| rt.resolve(v)) {
+        None => 0.0,
+        Some(&Variable::F64(v, _)) if v <= latest => v,
+        Some(&Variable::F64(v, _)) => {
+            return Ok(Variable::Result(Err(Box::new(Error {
+                message: Variable::Str(Arc::new(format!(
+                    "Data version {} is newer than the latest migration {}",
+                    v, latest
+                ))),
+                trace: vec![],
+                kind: None,
+            }))))
+        }
+        Some(x) => {
+            return Ok(Variable::Result(Err(Box::new(Error {
+                message: Variable::Str(Arc::new(format!(
+                    "Expected number for `version`, found `{}`",
+                    x.typeof_var()
+                ))),
+                trace: vec![],
+                kind: None,
+            }))))
+        }
+    };
+    for (v, up) in migrations.iter().filter(|m| m.0 > version) {
+        data = match rt.call_closure_ret(up, &[Variable::Object(data)])? {
+            Variable::Object(obj) => obj,
+            x => return Err(rt.expected(&x, "object")),
+        };
+        Arc::make_mut(&mut data).insert(VERSION.clone(), Variable::f64(*v));
+    }
+    Ok(Variable::Result(Ok(Box::new(Variable::Object(data)))))
+}
+
+pub(crate) fn load_data__string(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let text = rt.stack.pop().expect(TINVOTS);
+    let text = str_arg(rt, 0, &text)?;
+    let res = match data::load_data(&text, rt.settings.max_array_len) {
+        Ok(data) => Ok(Box::new(data)),
+        Err(err) => Err(Box::new(Error {
+            message: Variable::Str(Arc::new(format!(
+                "Error loading data from string `{}`:\n{}",
+                text, err
+            ))),
+            trace: vec![],
+            kind: None,
+        })),
+    };
+    Ok(Variable::Result(res))
+}
+
+/// Loads a file in another data format, such as TOML or YAML.
+#[cfg(any(feature = "toml", feature = "yaml"))]
+fn load_format_file(
+    rt: &mut Runtime,
+    format: &str,
+    parse: fn(&str) -> Result<Variable, String>,
+) -> Result<Variable, RuntimeError> {
+    let file = rt.stack.pop().expect(TINVOTS);
+    let file = str_arg(rt, 0, &file)?;
+    let res = data::read_file(&*rt.vfs, &file).and_then(|text| parse(&text));
+    Ok(Variable::Result(res.map(Box::new).map_err(|err| {
+        Box::new(Error {
+            message: Variable::Str(Arc::new(format!(
+                "Error loading {} from file `{}`:\n{}",
+                format, file, err
+            ))),
+            trace: vec![],
+            kind: None,
+        })
+    })))
+}
+
+/// Loads a string in another data format, such as TOML or YAML.
+#[cfg(any(feature = "toml", feature = "yaml"))]
+fn load_format_string(
+    rt: &mut Runtime,
+    format: &str,
+    parse: fn(&str) -> Result<Variable, String>,
+) -> Result<Variable, RuntimeError> {
+    let text = rt.stack.pop().expect(TINVOTS);
+    let text = str_arg(rt, 0, &text)?;
+    Ok(Variable::Result(parse(&text).map(Box::new).map_err(
+        |err| {
+            Box::new(Error {
+                message: Variable::Str(Arc::new(format!(
+                    "Error loading {} from string:\n{}",
+                    format, err
+                ))),
+                trace: vec![],
+                kind: None,
+            })
+        },
+    )))
+}
+
+#[cfg(feature = "toml")]
+pub(crate) fn load_toml__file(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    load_format_file(rt, "TOML", toml::parse)
+}
+
+#[cfg(not(feature = "toml"))]
+pub(crate) fn load_toml__file(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(TOML_SUPPORT_DISABLED.into())
+}
+
+#[cfg(feature = "toml")]
+pub(crate) fn load_toml__string(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    load_format_string(rt, "TOML", toml::parse)
+}
+
+#[cfg(not(feature = "toml"))]
+pub(crate) fn load_toml__string(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(TOML_SUPPORT_DISABLED.into())
+}
+
+#[cfg(feature = "yaml")]
+pub(crate) fn load_yaml__file(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    load_format_file(rt, "YAML", yaml::parse)
+}
+
+#[cfg(not(feature = "yaml"))]
+pub(crate) fn load_yaml__file(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(YAML_SUPPORT_DISABLED.into())
+}
+
+#[cfg(feature = "yaml")]
+pub(crate) fn load_yaml__string(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    load_format_string(rt, "YAML", yaml::parse)
+}
+
+#[cfg(not(feature = "yaml"))]
+pub(crate) fn load_yaml__string(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(YAML_SUPPORT_DISABLED.into())
+}
+
+pub(crate) fn args_os(_rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let mut arr: Vec<Variable> = vec![];
+    for arg in ::std::env::args_os() {
+        if let Ok(t) = arg.into_string() {
+            arr.push(Variable::Str(Arc::new(t)))
+        } else {
+            return Err("Invalid unicode in os argument".into());
+        }
+    }
+    Ok(Variable::Array(Arc::new(arr)))
+}
+
+/// Pops the command and arguments of `exec` and `exec_stream`.
+#[cfg(all(not(target_family = "wasm"), feature = "process"))]
+fn command_arg(rt: &mut Runtime) -> Result<(Arc<String>, std::process::Command), RuntimeError> {
+    use std::process::{Command, Stdio};
+
+    let args = rt.stack.pop().expect(TINVOTS);
+    let args = match rt.resolve(&args) {
+        &Variable::Array(ref arr) => arr.clone(),
+        x => return Err(rt.expected_arg(1, x, "[str]")),
+    };
+    let cmd = rt.stack.pop().expect(TINVOTS);
+    let cmd = match rt.resolve(&cmd) {
+        &Variable::Str(ref t) => t.clone(),
+        x => return Err(rt.expected_arg(0, x, "str")),
+    };
+    let mut command = Command::new(&**cmd);
+    command.stdin(Stdio::null());
+    for arg in args.iter() {
+        match rt.resolve(arg) {
+            &Variable::Str(ref t) => {
+                command.arg(&**t);
+            }
+            x => return Err(rt.expected_arg(1, x, "[str]")),
+        }
+    }
+    Ok((cmd, command))
+}
+
+/// Converts the result of running a command to a Dyon result.
+#[cfg(all(not(target_family = "wasm"), feature = "process"))]
+fn process_res(cmd: &str, res: std::io::Result<Variable>) -> Variable {
+    Variable::Result(match res {
+        Ok(v) => Ok(Box::new(v)),
+        Err(err) => Err(Box::new(Error {
+            message: Variable::Str(Arc::new(format!("Could not run `{}`:\n{}", cmd, err))),
+            trace: vec![],
+            kind: None,
+        })),
+    })
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "process"))]
+pub(crate) fn exec(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let (cmd, mut command) = command_arg(rt)?;
+    let res = command.output().map(|out| {
+        let text = |bytes: &[u8]| Variable::Str(Arc::new(String::from_utf8_lossy(bytes).into()));
+        let mut obj = HashMap::new();
+        obj.insert(Arc::new("stdout".into()), text(&out.stdout));
+        obj.insert(Arc::new("stderr".into()), text(&out.stderr));
+        // A process stopped by a signal has no exit code.
+        let status = out.status.code().map(f64::from).unwrap_or(-1.0);
+        obj.insert(Arc::new("status".into()), Variable::f64(status));
+        Variable::Object(Arc::new(obj))
+    });
+    Ok(process_res(&cmd, res))
+}
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "process")))]
+pub(crate) fn exec(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(PROCESS_SUPPORT_DISABLED.into())
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "process"))]
+pub(crate) fn exec_stream(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    use std::io::{BufRead, BufReader};
+    use std::process::Stdio;
+    use std::sync::mpsc::channel;
+    use std::thread;
+
+    let (cmd, mut command) = command_arg(rt)?;
+    let res = command.stdout(Stdio::piped()).spawn().map(|mut child| {
+        let (tx, rx) = channel();
+        let stdout = child.stdout.take();
+        thread::spawn(move || {
+            if let Some(stdout) = stdout {
+                for line in BufReader::new(stdout).lines() {
+                    let line = match line {
+                        Ok(line) => line,
+                        Err(_) => break,
+                    };
+                    if tx.send(Variable::Str(Arc::new(line))).is_err() {
+                        // The channel is dropped, so the output is not needed.
+                        let _ = child.kill();
+                        break;
+                    }
+                }
+            }
+            let _ = child.wait();
+        });
+        Variable::In(Arc::new(Mutex::new(rx)))
+    });
+    Ok(process_res(&cmd, res))
+}
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "process")))]
+pub(crate) fn exec_stream(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(PROCESS_SUPPORT_DISABLED.into())
+}
+
+/// Saves data to a file, optionally writing it atomically.
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+fn save_data(rt: &mut Runtime, atomic: bool) -> Result<Variable, RuntimeError> {
+    use std::io::Write;
+    use write::write_data;
+
+    let file = rt.stack.pop().expect(TINVOTS);
+    let file = match rt.resolve(&file) {
+        &Variable::Str(ref t) => t.clone(),
+        x => return Err(rt.expected_arg(1, x, "str")),
+    };
+    let data = rt.stack.pop().expect(TINVOTS);
+
+    let res = if atomic {
+        let mut buf = vec![];
+        write_data(&mut buf, rt, &data, 0).and_then(|()| rt.vfs.write_atomic(&file, &buf))
+    } else {
+        let mut f = match rt.vfs.create(&file) {
+            Ok(f) => f,
+            Err(err) => {
+                return Err({
+                    rt.arg_err_index.set(Some(0));
+                    RuntimeError::new(
+                        ErrorKind::Io,
+                        None,
+                        format!("Error when creating file `{}`:\n{}", file, err),
+                    )
+                });
+            }
+        };
+        write_data(&mut f, rt, &data, 0).and_then(|()| f.flush())
+    };
+    let res = match res {
+        Ok(()) => Ok(Box::new(Variable::Str(file))),
+        Err(err) => Err(io_err(format!(
+            "Error when writing to file `{}`:\n{}",
+            file, err
+        ))),
+    };
+    Ok(Variable::Result(res))
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+pub(crate) fn save__data_file(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    save_data(rt, false)
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+pub(crate) fn save__data_file_atomic(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let atomic = rt.stack.pop().expect(TINVOTS);
+    let atomic = match rt.resolve(&atomic) {
+        &Variable::Bool(b, _) => b,
+        x => return Err(rt.expected_arg(2, x, "bool")),
+    };
+    save_data(rt, atomic)
+}
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "file")))]
+pub(crate) fn save__data_file_atomic(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(FILE_SUPPORT_DISABLED.into())
+}
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "file")))]
+pub(crate) fn save__data_file(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(FILE_SUPPORT_DISABLED.into())
+}
+
+/// Calls a function with the memory mapped file argument locked.
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+fn with_mmap<T, F>(rt: &Runtime, m: &Variable, f: F) -> Result<T, RuntimeError>
+where
+    F: FnOnce(&mut self::mmap::Mmap) -> Result<T, RuntimeError>,
+{
+    let obj = match rt.resolve(m) {
+        &Variable::RustObject(ref obj) => obj.clone(),
+        x => return Err(rt.expected_arg(0, x, "memory mapped file")),
+    };
+    let mut guard = obj
+        .lock()
+        .map_err(|err| format!("Can not lock memory mapped file:\n{}", err))?;
+    match guard.downcast_mut::<self::mmap::Mmap>() {
+        Some(m) => Ok(f(m)?),
+        None => Err({
+            rt.arg_err_index.set(Some(0));
+            "Expected memory mapped file".into()
+        }),
+    }
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+pub(crate) fn mmap(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    use self::mmap::Mmap;
+
+    let file = rt.stack.pop().expect(TINVOTS);
+    let file = match rt.resolve(&file) {
+        &Variable::Str(ref t) => t.clone(),
+        x => return Err(rt.expected_arg(0, x, "str")),
+    };
+    let map = rt.vfs.allow_mmap();
+    let res = rt
+        .vfs
+        .os_path(&file)
+        .and_then(|path| Mmap::open(&path, map));
+    Ok(Variable::Result(match res {
+        Ok(m) => Ok(Box::new(Variable::RustObject(
+            Arc::new(Mutex::new(m)) as RustObject
+        ))),
+        Err(err) => Err(io_err(self::io::io_error("open", &file, &err))),
+    }))
+}
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "file")))]
+pub(crate) fn mmap(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(FILE_SUPPORT_DISABLED.into())
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+pub(crate) fn mmap_len(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let m = rt.stack.pop().expect(TINVOTS);
+    let len = with_mmap(rt, &m, |m| Ok(m.len()))?;
+    Ok(Variable::f64(len as f64))
+}
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "file")))]
+pub(crate) fn mmap_len(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(FILE_SUPPORT_DISABLED.into())
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+pub(crate) fn read_f32_at(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let offset = rt.stack.pop().expect(TINVOTS);
+    let m = rt.stack.pop().expect(TINVOTS);
+    let offset = index_arg(rt, 1, &offset)?;
+    let mut buf = [0; 4];
+    with_mmap(rt, &m, |m| m.read_at(offset, &mut buf))?;
+    Ok(Variable::f64(f64::from(f32::from_le_bytes(buf))))
+}
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "file")))]
+pub(crate) fn read_f32_at(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(FILE_SUPPORT_DISABLED.into())
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+pub(crate) fn read_bytes_at(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let len = rt.stack.pop().expect(TINVOTS);
+    let offset = rt.stack.pop().expect(TINVOTS);
+    let m = rt.stack.pop().expect(TINVOTS);
+    let offset = index_arg(rt, 1, &offset)?;
+    let len = index_arg(rt, 2, &len)?;
+    let mut buf = vec![0; len];
+    with_mmap(rt, &m, |m| m.read_at(offset, &mut buf))?;
+    Ok(Variable::Array(Arc::new(
+        buf.into_iter()
+            .map(|b| Variable::f64(f64::from(b)))
+            .collect(),
+    )))
+}
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "file")))]
+pub(crate) fn read_bytes_at(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(FILE_SUPPORT_DISABLED.into())
+}
+
+/// An advisory lock on a file, released by `unlock` or when dropped.
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+pub(crate) struct FileLock {
+    file: Option<std::fs::File>,
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+fn lock_file_with(rt: &mut Runtime, shared: bool) -> Result<Variable, RuntimeError> {
+    use fs2::FileExt;
+    use std::fs::OpenOptions;
+
+    let file = rt.stack.pop().expect(TINVOTS);
+    let file = match rt.resolve(&file) {
+        &Variable::Str(ref t) => t.clone(),
+        x => return Err(rt.expected_arg(0, x, "str")),
+    };
+    let res = rt
+        .vfs
+        .os_path(&file)
+        .and_then(|path| {
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(path)
+        })
+        .and_then(|f| {
+            // `File` has methods with the same names in newer Rust versions.
+            if shared {
+                FileExt::lock_shared(&f)?;
+            } else {
+                FileExt::lock_exclusive(&f)?;
+            }
+            Ok(f)
+        });
+    Ok(Variable::Result(match res {
+        Ok(f) => Ok(Box::new(Variable::RustObject(
+            Arc::new(Mutex::new(FileLock { file: Some(f) })) as RustObject,
+        ))),
+        Err(err) => Err(io_err(self::io::io_error("lock", &file, &err))),
+    }))
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+pub(crate) fn lock_file(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    lock_file_with(rt, false)
+}
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "file")))]
+pub(crate) fn lock_file(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(FILE_SUPPORT_DISABLED.into())
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+pub(crate) fn lock_file_shared(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    lock_file_with(rt, true)
+}
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "file")))]
+pub(crate) fn lock_file_shared(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(FILE_SUPPORT_DISABLED.into())
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+pub(crate) fn unlock(rt: &mut Runtime) -> Result<(), RuntimeError> {
+    let lock = rt.stack.pop().expect(TINVOTS);
+    let obj = match rt.resolve(&lock) {
+        &Variable::RustObject(ref obj) => obj.clone(),
+        x => return Err(rt.expected_arg(0, x, "file lock")),
+    };
+    let mut guard = obj
+        .lock()
+        .map_err(|err| format!("Can not lock file lock:\n{}", err))?;
+    let file = match guard.downcast_mut::<FileLock>() {
+        Some(lock) => lock.file.take(),
+        None => None,
+    };
+    match file {
+        Some(f) => Ok(fs2::FileExt::unlock(&f).map_err(|err| {
+            RuntimeError::new(
+                ErrorKind::Io,
+                None,
+                format!("Could not unlock file:\n{}", err),
+            )
+        })?),
+        None => Err({
+            rt.arg_err_index.set(Some(0));
+            "Expected file lock that is not released".into()
+        }),
+    }
+}
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "file")))]
+pub(crate) fn unlock(_: &mut Runtime) -> Result<(), RuntimeError> {
+    Err(FILE_SUPPORT_DISABLED.into())
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+fn temp_path_with(rt: &Runtime, prefix: &str, dir: bool) -> Variable {
+    use self::io::TempPath;
+
+    let temp_dir = rt.vfs.temp_dir().unwrap_or_default();
+    let res = TempPath::create(rt.vfs.clone(), prefix, dir)
+        .map(|tmp| Variable::RustObject(Arc::new(Mutex::new(tmp)) as RustObject));
+    io_res("create temporary file in", &temp_dir, res)
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+pub(crate) fn temp_file(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let prefix = rt.stack.pop().expect(TINVOTS);
+    let prefix = match rt.resolve(&prefix) {
+        &Variable::Str(ref t) => t.clone(),
+        x => return Err(rt.expected_arg(0, x, "str")),
+    };
+    Ok(temp_path_with(rt, &prefix, false))
+}
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "file")))]
+pub(crate) fn temp_file(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(FILE_SUPPORT_DISABLED.into())
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+pub(crate) fn temp_dir(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Ok(temp_path_with(rt, "dyon-", true))
+}
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "file")))]
+pub(crate) fn temp_dir(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(FILE_SUPPORT_DISABLED.into())
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+pub(crate) fn temp_path(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    use self::io::TempPath;
+
+    let tmp = rt.stack.pop().expect(TINVOTS);
+    let obj = match rt.resolve(&tmp) {
+        &Variable::RustObject(ref obj) => obj.clone(),
+        x => return Err(rt.expected_arg(0, x, "temporary file")),
+    };
+    let guard = obj
+        .lock()
+        .map_err(|err| format!("Can not lock temporary file:\n{}", err))?;
+    match guard.downcast_ref::<TempPath>() {
+        Some(tmp) => Ok(Variable::Str(Arc::new(tmp.path.clone()))),
+        None => Err({
+            rt.arg_err_index.set(Some(0));
+            "Expected temporary file".into()
+        }),
+    }
+}
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "file")))]
+pub(crate) fn temp_path(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(FILE_SUPPORT_DISABLED.into())
+}
+
+/// Returns the error of a failed file operation, which is raised as `ErrorKind::Io` when unwrapped.
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+fn io_err(message: String) -> Box<Error> {
+    Box::new(Error {
+        message: Variable::Str(Arc::new(message)),
+        trace: vec![],
+        kind: Some(ErrorKind::Io),
+    })
+}
+
+/// Converts the result of a file system operation to a Dyon result.
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+fn io_res(action: &str, file: &str, res: std::io::Result<Variable>) -> Variable {
+    Variable::Result(match res {
+        Ok(v) => Ok(Box::new(v)),
+        Err(err) => Err(io_err(self::io::io_error(action, file, &err))),
+    })
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+pub(crate) fn read_dir(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let path = rt.stack.pop().expect(TINVOTS);
+    let path = str_arg(rt, 0, &path)?;
+    let res = rt.vfs.read_dir(&path).map(|names| {
+        Variable::Array(Arc::new(
+            names
+                .into_iter()
+                .map(|name| Variable::Str(Arc::new(name)))
+                .collect(),
+        ))
+    });
+    Ok(io_res("read directory", &path, res))
+}
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "file")))]
+pub(crate) fn read_dir(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(FILE_SUPPORT_DISABLED.into())
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+pub(crate) fn create_dir(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let path = rt.stack.pop().expect(TINVOTS);
+    let path = str_arg(rt, 0, &path)?;
+    let res = rt
+        .vfs
+        .create_dir(&path)
+        .map(|_| Variable::Str(path.clone()));
+    Ok(io_res("create directory", &path, res))
+}
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "file")))]
+pub(crate) fn create_dir(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(FILE_SUPPORT_DISABLED.into())
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+pub(crate) fn remove_file(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let path = rt.stack.pop().expect(TINVOTS);
+    let path = str_arg(rt, 0, &path)?;
+    let res = rt
+        .vfs
+        .remove_file(&path)
+        .map(|_| Variable::Str(path.clone()));
+    Ok(io_res("remove", &path, res))
+}
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "file")))]
+pub(crate) fn remove_file(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(FILE_SUPPORT_DISABLED.into())
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+pub(crate) fn file_exists(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let path = rt.stack.pop().expect(TINVOTS);
+    let path = str_arg(rt, 0, &path)?;
+    Ok(Variable::bool(rt.vfs.metadata(&path).is_ok()))
+}
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "file")))]
+pub(crate) fn file_exists(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(FILE_SUPPORT_DISABLED.into())
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+pub(crate) fn is_dir(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let path = rt.stack.pop().expect(TINVOTS);
+    let path = str_arg(rt, 0, &path)?;
+    Ok(Variable::bool(
+        rt.vfs.metadata(&path).map(|m| m.is_dir).unwrap_or(false),
+    ))
+}
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "file")))]
+pub(crate) fn is_dir(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(FILE_SUPPORT_DISABLED.into())
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+pub(crate) fn file_size(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let path = rt.stack.pop().expect(TINVOTS);
+    let path = str_arg(rt, 0, &path)?;
+    let res = rt.vfs.metadata(&path).map(|m| Variable::f64(m.len as f64));
+    Ok(io_res("get size of", &path, res))
+}
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "file")))]
+pub(crate) fn file_size(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(FILE_SUPPORT_DISABLED.into())
+}
+
+/// Writes Dyon data incrementally.
+pub(crate) struct DataWriter {
+    file: Arc<String>,
+    out: Box<dyn std::io::Write>,
+    /// Whether an item is written, for each open array.
+    arrays: Vec<bool>,
+    /// Whether a value is written at top level.
+    done: bool,
+}
+
+impl DataWriter {
+    /// Writes separator and indention before an item.
+    fn begin_item(&mut self) -> Result<(), RuntimeError> {
+        use std::io::Write;
+
+        let res = match self.arrays.last_mut() {
+            None if self.done => return Err("Expected only one value at top level".into()),
+            None => {
+                self.done = true;
+                Ok(())
+            }
+            Some(first) => {
+                let sep = if *first { ",\n" } else { "\n" };
+                *first = true;
+                let tabs = self.arrays.len();
+                write!(self.out, "{}{}", sep, "    ".repeat(tabs))
+            }
+        };
+        res.map_err(|err| self.error(err))
+    }
+
+    fn error(&self, err: ::std::io::Error) -> RuntimeError {
+        RuntimeError::new(
+            ErrorKind::Io,
+            None,
+            format!("Error when writing to file `{}`:\n{}", self.file, err),
+        )
+    }
+}
+
+/// Calls a function with the data writer argument locked.
+fn with_data_writer<T, F>(rt: &Runtime, w: &Variable, f: F) -> Result<T, RuntimeError>
+where
+    F: FnOnce(&Runtime, &mut DataWriter) -> Result<T, RuntimeError>,
+{
+    let obj = match rt.resolve(w) {
+        &Variable::RustObject(ref obj) => obj.clone(),
+        x => return Err(rt.expected_arg(0, x, "data writer")),
+    };
+    let mut guard = obj
+        .lock()
+        .map_err(|err| format!("Can not lock data writer:\n{}", err))?;
+    match guard.downcast_mut::<DataWriter>() {
+        Some(w) => Ok(f(rt, w)?),
+        None => Err({
+            rt.arg_err_index.set(Some(0));
+            "Expected data writer".into()
+        }),
+    }
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+pub(crate) fn data_writer(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let file = rt.stack.pop().expect(TINVOTS);
+    let file = match rt.resolve(&file) {
+        &Variable::Str(ref t) => t.clone(),
+        x => return Err(rt.expected_arg(0, x, "str")),
+    };
+    Ok(Variable::Result(match rt.vfs.create(&file) {
+        Ok(out) => Ok(Box::new(Variable::RustObject(
+            Arc::new(Mutex::new(DataWriter {
+                file,
+                out,
+                arrays: vec![],
+                done: false,
+            })) as RustObject,
+        ))),
+        Err(err) => Err(io_err(format!(
+            "Error when creating file `{}`:\n{}",
+            file, err
+        ))),
+    }))
+}
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "file")))]
+pub(crate) fn data_writer(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(FILE_SUPPORT_DISABLED.into())
+}
+
+pub(crate) fn begin_array(rt: &mut Runtime) -> Result<(), RuntimeError> {
+    use std::io::Write;
+
+    let w = rt.stack.pop().expect(TINVOTS);
+    with_data_writer(rt, &w, |_, w| {
+        w.begin_item()?;
+        w.arrays.push(false);
+        write!(w.out, "[").map_err(|err| w.error(err))
+    })
+}
+
+pub(crate) fn write_item(rt: &mut Runtime) -> Result<(), RuntimeError> {
+    use write::write_data;
+
+    let v = rt.stack.pop().expect(TINVOTS);
+    let w = rt.stack.pop().expect(TINVOTS);
+    with_data_writer(rt, &w, |rt, w| {
+        w.begin_item()?;
+        let tabs = w.arrays.len() as u32;
+        write_data(&mut w.out, rt, &v, tabs).map_err(|err| w.error(err))
+    })
+}
+
+pub(crate) fn end_array(rt: &mut Runtime) -> Result<(), RuntimeError> {
+    use std::io::Write;
+
+    let w = rt.stack.pop().expect(TINVOTS);
+    with_data_writer(rt, &w, |_, w| {
+        let first = match w.arrays.pop() {
+            Some(first) => first,
+            None => return Err("Expected `begin_array` before `end_array`".into()),
+        };
+        let res = if first {
+            write!(w.out, "\n{}]", "    ".repeat(w.arrays.len()))
+        } else {
+            write!(w.out, "]")
+        };
+        // Flush when the top level array is complete.
+        let res = res.and_then(|()| {
+            if w.arrays.is_empty() {
+                w.out.flush()
+            } else {
+                Ok(())
+            }
+        });
+        res.map_err(|err| w.error(err))
+    })
+}
+
+pub(crate) fn json_from_meta_data(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let meta_data = rt.stack.pop().expect(TINVOTS);
+    let json = match rt.resolve(&meta_data) {
+        &Variable::Array(ref arr) => meta::json_from_meta_data(arr).map_err(|err| {
+            format!(
+                "{}\nError when generating JSON:\n{}",
+                rt.stack_trace(),
+                err.to_string()
+            )
+        })?,
+        x => return Err(rt.expected_arg(0, x, "array")),
+    };
+    Ok(Variable::Str(Arc::new(json)))
+}
+
+pub(crate) fn errstr__string_start_len_msg(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    use piston_meta::ParseErrorHandler;
+
+    let msg = rt.stack.pop().expect(TINVOTS);
+    let msg = match rt.resolve(&msg) {
+        &Variable::Str(ref t) => t.clone(),
+        x => return Err(rt.expected_arg(3, x, "str")),
+    };
+    let len = rt.stack.pop().expect(TINVOTS);
+    let len = match rt.resolve(&len) {
+        &Variable::F64(v, _) => v as usize,
+        x => return Err(rt.expected_arg(2, x, "f64")),
+    };
+    let start = rt.stack.pop().expect(TINVOTS);
+    let start = match rt.resolve(&start) {
+        &Variable::F64(v, _) => v as usize,
+        x => return Err(rt.expected_arg(1, x, "f64")),
+    };
+    let source = rt.stack.pop().expect(TINVOTS);
+    let source = match rt.resolve(&source) {
+        &Variable::Str(ref t) => t.clone(),
+        x => return Err(rt.expected_arg(0, x, "str")),
+    };
+
+    let mut buf: Vec<u8> = vec![];
+    ParseErrorHandler::new(&source)
+        .write_msg(&mut buf, Range::new(start, len), &msg)
+        .unwrap();
+    Ok(Variable::Str(Arc::new(String::from_utf8(buf).unwrap())))
+}
+
+pub(crate) fn has(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let key = rt.stack.pop().expect(TINVOTS);
+    let key = match rt.resolve(&key) {
+        &Variable::Str(ref t) => t.clone(),
+        x => return Err(rt.expected_arg(1, x, "str")),
+    };
+    let obj = rt.stack.pop().expect(TINVOTS);
+    Ok(Variable::bool(match rt.resolve(&obj) {
+        &Variable::Object(ref obj) => obj.contains_key(&key),
+        x => return Err(rt.expected_arg(0, x, "object")),
+    }))
+}
+
+pub(crate) fn keys(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let obj = rt.stack.pop().expect(TINVOTS);
+    Ok(Variable::Array(Arc::new(match rt.resolve(&obj) {
+        &Variable::Object(ref obj) => obj.keys().map(|k| Variable::Str(k.clone())).collect(),
+        x => return Err(rt.expected_arg(0, x, "object")),
+    })))
+}
+
+pub(crate) fn remove_key(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let key = rt.stack.pop().expect(TINVOTS);
+    let key = match rt.resolve(&key) {
+        &Variable::Str(ref t) => t.clone(),
+        x => return Err(rt.expected_arg(1, x, "str")),
+    };
+    let obj = rt.stack.pop().expect(TINVOTS);
+    if let Variable::Ref(ind) = obj {
+        if let Variable::Object(ref mut obj) = rt.stack[ind] {
+            return Ok(Variable::Option(
+                Arc::make_mut(obj).remove(&key).map(Box::new),
+            ));
+        }
+    }
+    Err({
+        rt.arg_err_index.set(Some(0));
+        "Expected reference to object".into()
+    })
+}
+
+pub(crate) fn entries(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let obj = rt.stack.pop().expect(TINVOTS);
+    Ok(Variable::Array(Arc::new(match rt.resolve(&obj) {
+        &Variable::Object(ref obj) => obj
+            .iter()
+            .map(|(k, v)| Variable::Array(Arc::new(vec![Variable::Str(k.clone()), v.clone()])))
+            .collect(),
+        x => return Err(rt.expected_arg(0, x, "object")),
+    })))
+}
+
+pub(crate) fn values(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let obj = rt.stack.pop().expect(TINVOTS);
+    Ok(Variable::Array(Arc::new(match rt.resolve(&obj) {
+        &Variable::Object(ref obj) => obj.values().cloned().collect(),
+        x => return Err(rt.expected_arg(0, x, "object")),
+    })))
+}
+
+pub(crate) fn chars(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let t = rt.stack.pop().expect(TINVOTS);
+    let t = match rt.resolve(&t) {
+        &Variable::Str(ref t) => t.clone(),
+        x => return Err(rt.expected_arg(0, x, "str")),
+    };
+    rt.check_array_len(t.chars().count())?;
+    Ok(Variable::Array(Arc::new(
+        t.chars()
+            .map(|ch| {
+                let mut s = String::new();
+                s.push(ch);
+                Variable::Str(Arc::new(s))
+            })
+            .collect::<Vec<_>>(),
+    )))
+}
+
+pub(crate) fn flag(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let name = rt.stack.pop().expect(TINVOTS);
+    let name = match rt.resolve(&name) {
+        &Variable::Str(ref t) => t.clone(),
+        x => return Err(rt.expected_arg(0, x, "str")),
+    };
+    Ok(Variable::bool(rt.flag(&name)))
+}
+
+dyon_fn! {fn features() -> Vec<Arc<String>> {
+    ::features().into_iter().map(|f| Arc::new(f.into())).collect()
+}}
+dyon_fn! {fn disabled_functions() -> Vec<Arc<String>> {
+    ::disabled_functions().into_iter().map(|f| Arc::new(f.into())).collect()
+}}
+
+pub(crate) fn run_manifest(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Ok(rt.run_manifest(&rt.module))
+}
+
+pub(crate) fn profile_report(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Ok(rt.profile_report())
+}
+
+dyon_fn! {fn now() -> f64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(val) => val.as_secs() as f64 +
+                   f64::from(val.subsec_nanos()) / 1.0e9,
+        Err(err) => -{
+            let val = err.duration();
+            val.as_secs() as f64 +
+            f64::from(val.subsec_nanos()) / 1.0e9
+        }
+    }
+}}
+
+dyon_fn! {fn clock() -> f64 {
+    use std::time::Instant;
+
+    lazy_static! {
+        static ref START: Instant = Instant::now();
+    }
+    START.elapsed().as_secs_f64()
+}}
+
+/// Reads a timestamp argument as a date.
+fn datetime_arg(rt: &Runtime, i: usize, v: &Variable) -> Result<calendar::DateTime, RuntimeError> {
+    match rt.resolve(v) {
+        &Variable::F64(ts, _) => calendar::DateTime::from_timestamp(ts).map_err(|err| {
+            rt.arg_err_index.set(Some(i));
+            err.into()
+        }),
+        x => Err(rt.expected_arg(i, x, "number")),
+    }
+}
+
+pub(crate) fn datetime(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let ts = rt.stack.pop().expect(TINVOTS);
+    let dt = datetime_arg(rt, 0, &ts)?;
+    let mut obj = HashMap::new();
+    obj.insert(Arc::new("year".into()), Variable::f64(dt.year as f64));
+    obj.insert(Arc::new("month".into()), Variable::f64(f64::from(dt.month)));
+    obj.insert(Arc::new("day".into()), Variable::f64(f64::from(dt.day)));
+    obj.insert(Arc::new("hour".into()), Variable::f64(f64::from(dt.hour)));
+    obj.insert(Arc::new("min".into()), Variable::f64(f64::from(dt.min)));
+    obj.insert(Arc::new("sec".into()), Variable::f64(dt.sec));
+    Ok(Variable::Object(Arc::new(obj)))
+}
+
+pub(crate) fn format_time(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let fmt = rt.stack.pop().expect(TINVOTS);
+    let fmt = str_arg(rt, 1, &fmt)?;
+    let ts = rt.stack.pop().expect(TINVOTS);
+    let dt = datetime_arg(rt, 0, &ts)?;
+    match calendar::format(&dt, &fmt) {
+        Ok(s) => Ok(Variable::Str(Arc::new(s))),
+        Err(err) => Err({
+            rt.arg_err_index.set(Some(1));
+            err.into()
+        }),
+    }
+}
+
+dyon_fn! {fn parse_time(text: Arc<String>, fmt: Arc<String>) -> Result<f64, String> {
+    calendar::parse(&text, &fmt)
+}}
+
+dyon_fn! {fn parse_expr(text: Arc<String>) -> Variable {
+    Variable::Result(match formula::parse(&text) {
+        Ok(expr) => Ok(Box::new(Variable::RustObject(Arc::new(Mutex::new(expr))))),
+        Err(err) => Err(Box::new(Error {
+            message: Variable::Str(Arc::new(err)),
+            trace: vec![], kind: None,
+        })),
+    })
+}}
+
+pub(crate) fn eval_expr(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let vars = rt.stack.pop().expect(TINVOTS);
+    let vars = match rt.resolve(&vars) {
+        &Variable::Object(ref obj) => obj.clone(),
+        x => return Err(rt.expected_arg(1, x, "object")),
+    };
+    let expr = rt.stack.pop().expect(TINVOTS);
+    let obj = match rt.resolve(&expr) {
+        &Variable::RustObject(ref obj) => obj.clone(),
+        x => return Err(rt.expected_arg(0, x, "expression")),
+    };
+    let guard = obj.lock().unwrap();
+    let expr = match guard.downcast_ref::<formula::Expr>() {
+        Some(expr) => expr,
+        None => {
+            return Err({
+                rt.arg_err_index.set(Some(0));
+                "Expected expression".into()
+            })
+        }
+    };
+    let mut var = |name: &str| match vars.get(&Arc::new(name.to_string())) {
+        Some(v) => match *rt.resolve(v) {
+            Variable::F64(x, _) => Ok(x),
+            Variable::Bool(b, _) => Ok(if b { 1.0 } else { 0.0 }),
+            _ => Err(format!("Variable `{}` is not a number", name)),
+        },
+        None => Err(format!("Unknown variable `{}`", name)),
+    };
+    Ok(Variable::Result(match expr.eval(&mut var) {
+        Ok(x) => Ok(Box::new(Variable::f64(x))),
+        Err(err) => Err(Box::new(Error {
+            message: Variable::Str(Arc::new(err)),
+            trace: vec![],
+            kind: None,
+        })),
+    }))
+}
+
+pub(crate) fn cells(_rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Ok(Variable::RustObject(Arc::new(Mutex::new(
+        cells::Cells::default(),
+    ))))
+}
+
+fn cells_arg(rt: &Runtime, v: &Variable) -> Result<RustObject, RuntimeError> {
+    match rt.resolve(v) {
+        &Variable::RustObject(ref obj) if obj.lock().unwrap().is::<cells::Cells>() => {
+            Ok(obj.clone())
+        }
+        x => Err(rt.expected_arg(0, x, "cells")),
+    }
+}
+
+pub(crate) fn set_formula(rt: &mut Runtime) -> Result<(), RuntimeError> {
+    use self::cells::Formula;
+
+    let f = rt.stack.pop().expect(TINVOTS);
+    let name = rt.stack.pop().expect(TINVOTS);
+    let name = str_arg(rt, 1, &name)?;
+    let c = rt.stack.pop().expect(TINVOTS);
+    let obj = cells_arg(rt, &c)?;
+    let formula = match rt.resolve(&f) {
+        &Variable::F64(x, _) => Formula::Value(x),
+        &Variable::Str(ref text) => match formula::parse(text) {
+            Ok(expr) => Formula::Expr(expr),
+            Err(err) => {
+                return Err({
+                    rt.arg_err_index.set(Some(2));
+                    err.into()
+                })
+            }
+        },
+        &Variable::Closure(_, _) => Formula::Closure(f.clone()),
+        &Variable::RustObject(ref expr) => {
+            match expr.lock().unwrap().downcast_ref::<formula::Expr>() {
+                Some(expr) => Formula::Expr(expr.clone()),
+                None => return Err(rt.expected_arg(2, &f, "expression")),
+            }
+        }
+        x => return Err(rt.expected_arg(2, x, "number, formula or closure")),
+    };
+    let mut guard = obj.lock().unwrap();
+    guard
+        .downcast_mut::<cells::Cells>()
+        .unwrap()
+        .set(name, formula);
+    Ok(())
+}
+
+pub(crate) fn get(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let name = rt.stack.pop().expect(TINVOTS);
+    let name = str_arg(rt, 1, &name)?;
+    let c = rt.stack.pop().expect(TINVOTS);
+    let obj = cells_arg(rt, &c)?;
+    Ok(Variable::Result(match cells::get(rt, &obj, &name)? {
+        Ok(x) => Ok(Box::new(Variable::f64(x))),
+        Err(err) => Err(Box::new(Error {
+            message: Variable::Str(Arc::new(err)),
+            trace: vec![],
+            kind: None,
+        })),
+    }))
+}
+
+pub(crate) fn spline(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let points = rt.stack.pop().expect(TINVOTS);
+    let points = match rt.resolve(&points) {
+        &Variable::Array(ref arr) if !arr.is_empty() => arr.clone(),
+        x => return Err(rt.expected_arg(0, x, "non-empty array of vec4")),
+    };
+    let mut ps = Vec::with_capacity(points.len());
+    for p in points.iter() {
+        match *rt.resolve(p) {
+            Variable::Vec4(v) => ps.push([
+                f64::from(v[0]),
+                f64::from(v[1]),
+                f64::from(v[2]),
+                f64::from(v[3]),
+            ]),
+            ref x => return Err(rt.expected_arg(0, x, "vec4")),
+        }
+    }
+    Ok(Variable::RustObject(Arc::new(Mutex::new(
+        spline::Spline::new(ps),
+    ))))
+}
+
+/// Calls a function with the spline of the first argument.
+fn with_spline<T, F>(rt: &Runtime, s: &Variable, f: F) -> Result<T, RuntimeError>
+where
+    F: FnOnce(&spline::Spline) -> T,
+{
+    if let &Variable::RustObject(ref obj) = rt.resolve(s) {
+        if let Some(s) = obj.lock().unwrap().downcast_ref::<spline::Spline>() {
+            return Ok(f(s));
+        }
+    }
+    Err(rt.expected_arg(0, s, "spline"))
+}
+
+fn vec4_from(p: [f64; 4]) -> Variable {
+    Variable::Vec4([
+        p[0] as Scalar,
+        p[1] as Scalar,
+        p[2] as Scalar,
+        p[3] as Scalar,
+    ])
+}
+
+fn grid_arg(rt: &Runtime, i: usize, v: &Variable) -> Result<Vec<Vec<bool>>, RuntimeError> {
+    let rows = match rt.resolve(v) {
+        &Variable::Array(ref arr) => arr.clone(),
+        x => return Err(rt.expected_arg(i, x, "[[bool]]")),
+    };
+    let mut grid = Vec::with_capacity(rows.len());
+    for row in rows.iter() {
+        match *rt.resolve(row) {
+            Variable::Array(ref arr) => {
+                let mut cells = Vec::with_capacity(arr.len());
+                for c in arr.iter() {
+                    match *rt.resolve(c) {
+                        Variable::Bool(b, _) => cells.push(b),
+                        ref x => return Err(rt.expected_arg(i, x, "bool")),
+                    }
+                }
+                grid.push(cells);
+            }
+            ref x => return Err(rt.expected_arg(i, x, "[bool]")),
+        }
+    }
+    Ok(grid)
+}
+
+fn grid_var(grid: Vec<Vec<bool>>) -> Variable {
+    Variable::Array(Arc::new(
+        grid.into_iter()
+            .map(|row| Variable::Array(Arc::new(row.into_iter().map(Variable::bool).collect())))
+            .collect(),
+    ))
+}
+
+pub(crate) fn cellular_automata(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    lazy_static! {
+        static ref BIRTH: Arc<String> = Arc::new("birth".into());
+        static ref SURVIVE: Arc<String> = Arc::new("survive".into());
+        static ref EDGE: Arc<String> = Arc::new("edge".into());
+    }
+
+    let steps = rt.stack.pop().expect(TINVOTS);
+    let steps = index_arg(rt, 2, &steps)?;
+    let rules = rt.stack.pop().expect(TINVOTS);
+    let rules = match rt.resolve(&rules) {
+        &Variable::Object(ref obj) => obj.clone(),
+        x => return Err(rt.expected_arg(1, x, "object")),
+    };
+    let counts = |key: &Arc<String>| -> Result<[bool; 9], RuntimeError> {
+        let mut res = [false; 9];
+        if let Some(v) = rules.get(key) {
+            let arr = match rt.resolve(v) {
+                &Variable::Array(ref arr) => arr,
+                x => return Err(rt.expected_arg(1, x, "[f64]")),
+            };
+            for n in arr.iter() {
+                match *rt.resolve(n) {
+                    Variable::F64(n, _) if (0.0..=8.0).contains(&n) => res[n as usize] = true,
+                    ref x => return Err(rt.expected_arg(1, x, "number from 0 to 8")),
+                }
+            }
+        }
+        Ok(res)
+    };
+    let edge = match rules.get(&*EDGE).map(|v| rt.resolve(v)) {
+        None => false,
+        Some(&Variable::Bool(b, _)) => b,
+        Some(x) => return Err(rt.expected_arg(1, x, "bool")),
+    };
+    let rules = procgen::Rules {
+        birth: counts(&BIRTH)?,
+        survive: counts(&SURVIVE)?,
+        edge,
+    };
+    let grid = rt.stack.pop().expect(TINVOTS);
+    let grid = grid_arg(rt, 0, &grid)?;
+    Ok(grid_var(procgen::cellular_automata(grid, &rules, steps)))
+}
+
+/// Returns the width and height of a grid from a vec4 argument.
+fn size_arg(rt: &Runtime, i: usize, v: &Variable) -> Result<(f64, f64), RuntimeError> {
+    match *rt.resolve(v) {
+        Variable::Vec4(v) if v[0] >= 0.0 && v[1] >= 0.0 => Ok((f64::from(v[0]), f64::from(v[1]))),
+        ref x => Err(rt.expected_arg(i, x, "vec4 with non-negative x and y")),
+    }
+}
+
+pub(crate) fn drunkard_walk(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let steps = rt.stack.pop().expect(TINVOTS);
+    let steps = index_arg(rt, 1, &steps)?;
+    let size = rt.stack.pop().expect(TINVOTS);
+    let (w, h) = size_arg(rt, 0, &size)?;
+    let grid = procgen::drunkard_walk(&mut rt.rng, w as usize, h as usize, steps);
+    Ok(grid_var(grid))
+}
+
+pub(crate) fn poisson_disc(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let radius = rt.stack.pop().expect(TINVOTS);
+    let radius = match *rt.resolve(&radius) {
+        Variable::F64(r, _) if r > 0.0 => r,
+        ref x => return Err(rt.expected_arg(1, x, "positive number")),
+    };
+    let size = rt.stack.pop().expect(TINVOTS);
+    let (w, h) = size_arg(rt, 0, &size)?;
+    let points = procgen::poisson_disc(&mut rt.rng, w, h, radius);
+    Ok(Variable::Array(Arc::new(
+        points
+            .into_iter()
+            .map(|p| vec4_from([p[0], p[1], 0.0, 0.0]))
+            .collect(),
+    )))
+}
+
+fn frame_var(f: frame::Frame) -> Variable {
+    Variable::RustObject(Arc::new(Mutex::new(Arc::new(f))))
+}
+
+fn frame_arg(rt: &Runtime, i: usize, v: &Variable) -> Result<Arc<frame::Frame>, RuntimeError> {
+    match *rt.resolve(v) {
+        Variable::RustObject(ref obj) => {
+            if let Some(f) = obj.lock().unwrap().downcast_ref::<Arc<frame::Frame>>() {
+                return Ok(f.clone());
+            }
+        }
+        ref x => return Err(rt.expected_arg(i, x, "frame")),
+    }
+    Err(rt.expected_arg(i, v, "frame"))
+}
+
+pub(crate) fn frame(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let columns = rt.stack.pop().expect(TINVOTS);
+    let columns = rt.resolve(&columns).deep_clone(&rt.stack);
+    match columns {
+        Variable::Object(ref obj) => Ok(frame_var(frame::Frame::from_data(obj)?)),
+        ref x => Err(rt.expected_arg(0, x, "object")),
+    }
+}
+
+pub(crate) fn frame_len(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let f = rt.stack.pop().expect(TINVOTS);
+    let f = frame_arg(rt, 0, &f)?;
+    Ok(Variable::f64(f.len() as f64))
+}
+
+pub(crate) fn frame_data(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let f = rt.stack.pop().expect(TINVOTS);
+    let f = frame_arg(rt, 0, &f)?;
+    Ok(f.to_data())
+}
+
+pub(crate) fn frame_select(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let names = rt.stack.pop().expect(TINVOTS);
+    let names = match *rt.resolve(&names) {
+        Variable::Array(ref arr) => arr
+            .iter()
+            .map(|name| str_arg(rt, 1, name))
+            .collect::<Result<Vec<_>, _>>()?,
+        ref x => return Err(rt.expected_arg(1, x, "[str]")),
+    };
+    let f = rt.stack.pop().expect(TINVOTS);
+    let f = frame_arg(rt, 0, &f)?;
+    Ok(frame_var(f.select(&names)?))
+}
+
+pub(crate) fn frame_filter(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let pred = rt.stack.pop().expect(TINVOTS);
+    let f = rt.stack.pop().expect(TINVOTS);
+    let f = frame_arg(rt, 0, &f)?;
+    let mut indices = vec![];
+    for i in 0..f.len() {
+        match rt.call_closure_ret(&pred, &[f.row(i)])? {
+            Variable::Bool(true, _) => indices.push(i),
+            Variable::Bool(false, _) => {}
+            ref x => return Err(rt.expected(x, "bool from filter")),
+        }
+    }
+    Ok(frame_var(f.take(&indices)))
+}
+
+pub(crate) fn frame_sort(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let ascending = rt.stack.pop().expect(TINVOTS);
+    let ascending = match *rt.resolve(&ascending) {
+        Variable::Bool(b, _) => b,
+        ref x => return Err(rt.expected_arg(2, x, "bool")),
+    };
+    let column = rt.stack.pop().expect(TINVOTS);
+    let column = str_arg(rt, 1, &column)?;
+    let f = rt.stack.pop().expect(TINVOTS);
+    let f = frame_arg(rt, 0, &f)?;
+    Ok(frame_var(f.sort(&column, ascending)?))
+}
+
+pub(crate) fn frame_head(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let n: f64 = rt.pop().expect(TINVOTS);
+    let f = rt.stack.pop().expect(TINVOTS);
+    let f = frame_arg(rt, 0, &f)?;
+    Ok(frame_var(f.head(n.max(0.0) as usize)))
+}
+
+pub(crate) fn frame_group_agg(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let aggs = rt.stack.pop().expect(TINVOTS);
+    let aggs = match *rt.resolve(&aggs) {
+        Variable::Object(ref obj) => obj.clone(),
+        ref x => return Err(rt.expected_arg(2, x, "object")),
+    };
+    let mut columns: Vec<&Arc<String>> = aggs.keys().collect();
+    columns.sort();
+    let mut list = vec![];
+    for column in columns {
+        let names = match *rt.resolve(&aggs[column]) {
+            Variable::Str(ref name) => vec![name.clone()],
+            Variable::Array(ref arr) => arr
+                .iter()
+                .map(|name| str_arg(rt, 2, name))
+                .collect::<Result<Vec<_>, _>>()?,
+            ref x => return Err(rt.expected_arg(2, x, "str or [str]")),
+        };
+        for name in names {
+            match frame::Agg::parse(&name) {
+                Some(agg) => list.push((column.clone(), name, agg)),
+                None => {
+                    return Err(format!(
+                        "Expected `count`, `sum`, `mean`, `min` or `max`, found `{}`",
+                        name
+                    )
+                    .into())
+                }
+            }
+        }
+    }
+    let by = rt.stack.pop().expect(TINVOTS);
+    let by = str_arg(rt, 1, &by)?;
+    let f = rt.stack.pop().expect(TINVOTS);
+    let f = frame_arg(rt, 0, &f)?;
+    Ok(frame_var(f.group_agg(&by, &list)?))
+}
+
+pub(crate) fn frame_from_csv(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let text = rt.stack.pop().expect(TINVOTS);
+    let text = str_arg(rt, 0, &text)?;
+    Ok(Variable::Result(
+        frame::Frame::from_csv(&text)
+            .map(|f| Box::new(frame_var(f)))
+            .map_err(|err| {
+                Box::new(Error {
+                    message: Variable::Str(Arc::new(format!("Error loading CSV:\n{}", err))),
+                    trace: vec![],
+                    kind: None,
+                })
+            }),
+    ))
+}
+
+pub(crate) fn frame_to_csv(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let f = rt.stack.pop().expect(TINVOTS);
+    let f = frame_arg(rt, 0, &f)?;
+    Ok(Variable::Str(Arc::new(f.to_csv())))
+}
+
+pub(crate) fn query(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let text = rt.stack.pop().expect(TINVOTS);
+    let text = str_arg(rt, 1, &text)?;
+    let v = rt.stack.pop().expect(TINVOTS);
+    let v = rt.resolve(&v).deep_clone(&rt.stack);
+    let arr = match v {
+        Variable::Array(ref arr) => arr,
+        ref x => return Err(rt.expected_arg(0, x, "array of objects")),
+    };
+    let mut rows = Vec::with_capacity(arr.len());
+    for it in arr.iter() {
+        match *it {
+            Variable::Object(ref obj) => rows.push(&**obj),
+            ref x => return Err(rt.expected_arg(0, x, "array of objects")),
+        }
+    }
+    let q = match query::Query::parse(&text) {
+        Ok(q) => q,
+        Err(err) => {
+            rt.arg_err_index.set(Some(1));
+            return Err(format!("Error parsing query:\n{}", err).into());
+        }
+    };
+    Ok(q.run(&rows)?)
+}
+
+/// Reads points to cluster, either as `[vec4]` or as rows of numbers.
+///
+/// Returns `true` for `vec4`.
+fn cluster_points_arg(
+    rt: &Runtime,
+    i: usize,
+    v: &Variable,
+) -> Result<(Vec<Vec<f64>>, bool), RuntimeError> {
+    let arr = match *rt.resolve(v) {
+        Variable::Array(ref arr) => arr,
+        ref x => return Err(rt.expected_arg(i, x, "[vec4] or [[f64]]")),
+    };
+    let mut points = Vec::with_capacity(arr.len());
+    let mut is_vec4 = false;
+    for p in arr.iter() {
+        match *rt.resolve(p) {
+            Variable::Vec4(v) => {
+                is_vec4 = true;
+                points.push(v.iter().map(|&x| f64::from(x)).collect());
+            }
+            ref row @ Variable::Array(_) => {
+                points.push(cluster_row(rt, i, row)?);
+            }
+            ref x => return Err(rt.expected_arg(i, x, "vec4 or [f64]")),
+        }
+    }
+    if points.iter().any(|p: &Vec<f64>| p.len() != points[0].len()) {
+        return Err("Expected points of equal dimension".into());
+    }
+    Ok((points, is_vec4))
+}
+
+fn cluster_row(rt: &Runtime, i: usize, row: &Variable) -> Result<Vec<f64>, RuntimeError> {
+    let mut res = vec![];
+    if let Variable::Array(ref arr) = *row {
+        for x in arr.iter() {
+            match *rt.resolve(x) {
+                Variable::F64(x, _) => res.push(x),
+                ref x => return Err(rt.expected_arg(i, x, "number")),
+            }
+        }
+    }
+    Ok(res)
+}
+
+pub(crate) fn kmeans(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let iters: f64 = rt.pop().expect(TINVOTS);
+    let k: f64 = rt.pop().expect(TINVOTS);
+    if k < 1.0 {
+        return Err(format!("Expected `k` of at least 1, found `{}`", k).into());
+    }
+    let points = rt.stack.pop().expect(TINVOTS);
+    let (points, is_vec4) = cluster_points_arg(rt, 0, &points)?;
+    let (centroids, labels) =
+        cluster::kmeans(&mut rt.rng, &points, k as usize, iters.max(0.0) as usize);
+    let centroids = centroids
+        .into_iter()
+        .map(|c| {
+            if is_vec4 {
+                vec4_from([c[0], c[1], c[2], c[3]])
+            } else {
+                Variable::Array(Arc::new(c.into_iter().map(Variable::f64).collect()))
+            }
+        })
+        .collect();
+    let mut obj = HashMap::new();
+    obj.insert(
+        Arc::new("centroids".into()),
+        Variable::Array(Arc::new(centroids)),
+    );
+    obj.insert(
+        Arc::new("labels".into()),
+        Variable::Array(Arc::new(
+            labels
+                .into_iter()
+                .map(|i| Variable::f64(i as f64))
+                .collect(),
+        )),
+    );
+    Ok(Variable::Object(Arc::new(obj)))
+}
+
+pub(crate) fn dbscan(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let min_pts: f64 = rt.pop().expect(TINVOTS);
+    let eps: f64 = rt.pop().expect(TINVOTS);
+    let points = rt.stack.pop().expect(TINVOTS);
+    let (points, _) = cluster_points_arg(rt, 0, &points)?;
+    let labels = cluster::dbscan(&points, eps, min_pts.max(1.0) as usize);
+    Ok(Variable::Array(Arc::new(
+        labels
+            .into_iter()
+            .map(|label| Variable::f64(label.map_or(-1.0, |i| i as f64)))
+            .collect(),
+    )))
+}
+
+dyon_fn! {fn perlin2(v: Vec4) -> f64 {
+    noise::perlin2(f64::from(v.0[0]), f64::from(v.0[1]))
+}}
+dyon_fn! {fn perlin3(v: Vec4) -> f64 {
+    noise::perlin3(f64::from(v.0[0]), f64::from(v.0[1]), f64::from(v.0[2]))
+}}
+dyon_fn! {fn simplex2(v: Vec4) -> f64 {
+    noise::simplex2(f64::from(v.0[0]), f64::from(v.0[1]))
+}}
+
+pub(crate) fn eval(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let t = rt.stack.pop().expect(TINVOTS);
+    let t: f64 = rt.var(&t)?;
+    let s = rt.stack.pop().expect(TINVOTS);
+    with_spline(rt, &s, |s| vec4_from(s.eval(t)))
+}
+
+pub(crate) fn length(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let s = rt.stack.pop().expect(TINVOTS);
+    with_spline(rt, &s, |s| Variable::f64(s.length()))
+}
+
+pub(crate) fn sample(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let n = rt.stack.pop().expect(TINVOTS);
+    let n = index_arg(rt, 1, &n)?;
+    let s = rt.stack.pop().expect(TINVOTS);
+    with_spline(rt, &s, |s| {
+        let points = (0..n)
+            .map(|i| {
+                let t = if n == 1 {
+                    0.0
+                } else {
+                    i as f64 / (n - 1) as f64
+                };
+                vec4_from(s.eval(t))
+            })
+            .collect();
+        Variable::Array(Arc::new(points))
+    })
+}
+
+/// Reads an object of numbers, such as `{x: 1, y: 2}`.
+fn coeffs_arg(
+    rt: &Runtime,
+    i: usize,
+    v: &Variable,
+) -> Result<Vec<(Arc<String>, f64)>, RuntimeError> {
+    let obj = match rt.resolve(v) {
+        &Variable::Object(ref obj) => obj.clone(),
+        x => return Err(rt.expected_arg(i, x, "object of numbers")),
+    };
+    let mut res = vec![];
+    for (k, v) in obj.iter() {
+        match *rt.resolve(v) {
+            Variable::F64(x, _) => res.push((k.clone(), x)),
+            ref x => return Err(rt.expected_arg(i, x, "number")),
+        }
+    }
+    Ok(res)
+}
+
+pub(crate) fn solve_linear(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    use self::solve::{Constraint, Relation};
+    use std::collections::BTreeSet;
+
+    lazy_static! {
+        static ref MAXIMIZE: Arc<String> = Arc::new("maximize".into());
+        static ref MINIMIZE: Arc<String> = Arc::new("minimize".into());
+        static ref TERMS: Arc<String> = Arc::new("terms".into());
+        static ref LE: Arc<String> = Arc::new("le".into());
+        static ref GE: Arc<String> = Arc::new("ge".into());
+        static ref EQ: Arc<String> = Arc::new("eq".into());
+    }
+
+    let objective = rt.stack.pop().expect(TINVOTS);
+    let constraints = rt.stack.pop().expect(TINVOTS);
+    let (maximize, objective) = match rt.resolve(&objective) {
+        &Variable::Object(ref obj) => match (obj.get(&*MAXIMIZE), obj.get(&*MINIMIZE)) {
+            (Some(v), None) => (true, coeffs_arg(rt, 1, v)?),
+            (None, Some(v)) => (false, coeffs_arg(rt, 1, v)?),
+            _ => {
+                return Err({
+                    rt.arg_err_index.set(Some(1));
+                    "Expected objective `{maximize: {...}}` or `{minimize: {...}}`".into()
+                })
+            }
+        },
+        x => return Err(rt.expected_arg(1, x, "object")),
+    };
+    let constraints = match rt.resolve(&constraints) {
+        &Variable::Array(ref arr) => arr.clone(),
+        x => return Err(rt.expected_arg(0, x, "array")),
+    };
+    let mut rows = vec![];
+    for c in constraints.iter() {
+        let obj = match rt.resolve(c) {
+            &Variable::Object(ref obj) => obj.clone(),
+            x => return Err(rt.expected_arg(0, x, "object")),
+        };
+        let terms = match obj.get(&*TERMS) {
+            Some(v) => coeffs_arg(rt, 0, v)?,
+            None => {
+                return Err({
+                    rt.arg_err_index.set(Some(0));
+                    "Expected `terms` in constraint".into()
+                })
+            }
+        };
+        let mut relation = None;
+        for &(key, r) in &[
+            (&*LE, Relation::Le),
+            (&*GE, Relation::Ge),
+            (&*EQ, Relation::Eq),
+        ] {
+            if let Some(v) = obj.get(key) {
+                match *rt.resolve(v) {
+                    Variable::F64(x, _) if relation.is_none() => relation = Some((r, x)),
+                    ref x => return Err(rt.expected_arg(0, x, "one number `le`, `ge` or `eq`")),
+                }
+            }
+        }
+        match relation {
+            Some((r, rhs)) => rows.push((terms, r, rhs)),
+            None => {
+                return Err({
+                    rt.arg_err_index.set(Some(0));
+                    "Expected `le`, `ge` or `eq` in constraint".into()
+                })
+            }
+        }
+    }
+
+    let names: BTreeSet<Arc<String>> = objective
+        .iter()
+        .chain(rows.iter().flat_map(|r| r.0.iter()))
+        .map(|t| t.0.clone())
+        .collect();
+    let names: Vec<Arc<String>> = names.into_iter().collect();
+    let dense = |terms: &[(Arc<String>, f64)]| {
+        let mut res = vec![0.0; names.len()];
+        for &(ref name, x) in terms {
+            res[names.binary_search(name).unwrap()] += x;
+        }
+        res
+    };
+    let mut c = dense(&objective);
+    if !maximize {
+        for x in &mut c {
+            *x = -*x;
+        }
+    }
+    let rows: Vec<Constraint> = rows
+        .iter()
+        .map(|&(ref terms, relation, rhs)| Constraint {
+            coeffs: dense(terms),
+            relation,
+            rhs,
+        })
+        .collect();
+    Ok(Variable::Result(match solve::simplex(&rows, &c) {
+        Ok((x, value)) => {
+            let vars: HashMap<_, _> = names
+                .into_iter()
+                .zip(x.into_iter().map(Variable::f64))
+                .collect();
+            let mut obj = HashMap::new();
+            obj.insert(
+                Arc::new("value".into()),
+                Variable::f64(if maximize { value } else { -value }),
+            );
+            obj.insert(Arc::new("vars".into()), Variable::Object(Arc::new(vars)));
+            Ok(Box::new(Variable::Object(Arc::new(obj))))
+        }
+        Err(err) => Err(Box::new(Error {
+            message: Variable::Str(Arc::new(err)),
+            trace: vec![],
+            kind: None,
+        })),
+    }))
+}
+
+pub(crate) fn solve_csp(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    use self::solve::Check;
+
+    lazy_static! {
+        static ref VARS: Arc<String> = Arc::new("vars".into());
+        static ref CHECK: Arc<String> = Arc::new("check".into());
+    }
+
+    let constraints = rt.stack.pop().expect(TINVOTS);
+    let vars = rt.stack.pop().expect(TINVOTS);
+    let vars = match rt.resolve(&vars) {
+        &Variable::Object(ref obj) => obj.clone(),
+        x => return Err(rt.expected_arg(0, x, "object")),
+    };
+    let mut names: Vec<Arc<String>> = vars.keys().cloned().collect();
+    names.sort();
+    let mut domains = vec![];
+    for name in &names {
+        match rt.resolve(&vars[name]) {
+            &Variable::Array(ref arr) => domains.push(arr.to_vec()),
+            x => return Err(rt.expected_arg(0, x, "array of values")),
+        }
+    }
+    let constraints = match rt.resolve(&constraints) {
+        &Variable::Array(ref arr) => arr.clone(),
+        x => return Err(rt.expected_arg(1, x, "array")),
+    };
+    let mut checks = vec![];
+    for c in constraints.iter() {
+        let obj = match rt.resolve(c) {
+            &Variable::Object(ref obj) => obj.clone(),
+            x => return Err(rt.expected_arg(1, x, "object")),
+        };
+        let (vars, closure) = match (obj.get(&*VARS), obj.get(&*CHECK)) {
+            (Some(vars), Some(closure)) => (vars, closure),
+            _ => {
+                return Err({
+                    rt.arg_err_index.set(Some(1));
+                    "Expected `vars` and `check` in constraint".into()
+                })
+            }
+        };
+        let mut indices = vec![];
+        if let &Variable::Array(ref arr) = rt.resolve(vars) {
+            for v in arr.iter() {
+                let name = str_arg(rt, 1, v)?;
+                match names.binary_search(&name) {
+                    Ok(i) => indices.push(i),
+                    Err(_) => {
+                        return Err({
+                            rt.arg_err_index.set(Some(1));
+                            format!("Unknown variable `{}` in constraint", name).into()
+                        })
+                    }
+                }
+            }
+        } else {
+            return Err(rt.expected_arg(1, vars, "array of variable names"));
+        }
+        checks.push(Check {
+            vars: indices,
+            closure: closure.clone(),
+        });
+    }
+    Ok(Variable::Result(match solve::csp(rt, &domains, &checks)? {
+        Some(values) => {
+            let obj: HashMap<_, _> = names.into_iter().zip(values).collect();
+            Ok(Box::new(Variable::Object(Arc::new(obj))))
+        }
+        None => Err(Box::new(Error {
+            message: Variable::Str(Arc::new("No solution satisfies the constraints".into())),
+            trace: vec![],
+            kind: None,
+        })),
+    }))
+}
+
+pub(crate) fn derivative(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let var = rt.stack.pop().expect(TINVOTS);
+    let var = str_arg(rt, 1, &var)?;
+    let f = rt.stack.pop().expect(TINVOTS);
+    let closure = match rt.resolve(&f) {
+        &Variable::Closure(ref closure, _) => closure.clone(),
+        x => return Err(rt.expected_arg(0, x, "closure")),
+    };
+    let index = match closure.args.iter().position(|arg| arg.name == var) {
+        Some(i) => i,
+        None => {
+            return Err({
+                rt.arg_err_index.set(Some(1));
+                format!("Could not find argument `{}` of closure", var).into()
+            })
+        }
+    };
+    let source = Arc::new(derivative::module_source(&closure, index));
+    let mut m = Module::empty();
+    m.import_ext_prelude(&rt.module);
+    load_str("derivative", source.clone(), &mut m)?;
+    let name: Arc<String> = Arc::new(derivative::FN_NAME.into());
+    let call_range = Range::empty(0);
+    let call = ast::Call {
+        f_index: m.find_function(&name, 0),
+        args: vec![ast::Expression::Variable(Box::new((
+            call_range,
+            rt.resolve(&f).clone(),
+        )))],
+        custom_source: Some(source),
+        info: Box::new(ast::CallInfo {
+            source_range: call_range,
+            alias: None,
+            name,
+        }),
+    };
+    match rt.call(&call, &Arc::new(m))?.0 {
+        Some(v) => Ok(v),
+        None => Err("Expected derivative to return a closure".into()),
+    }
+}
+
+/// Calls a closure, expecting it to return a number.
+fn call_f64(rt: &mut Runtime, f: &Variable, args: &[Variable]) -> Result<f64, RuntimeError> {
+    let v = rt.call_closure_ret(f, args)?;
+    match *rt.resolve(&v) {
+        Variable::F64(x, _) => Ok(x),
+        ref x => Err(rt.expected(x, "f64")),
+    }
+}
+
+fn closure_arg(rt: &Runtime, i: usize, v: Variable) -> Result<Variable, RuntimeError> {
+    match *rt.resolve(&v) {
+        Variable::Closure(..) => Ok(v),
+        ref x => Err(rt.expected_arg(i, x, "closure")),
+    }
+}
+
+fn f64_res(res: Result<f64, String>) -> Variable {
+    Variable::Result(match res {
+        Ok(x) => Ok(Box::new(Variable::f64(x))),
+        Err(err) => Err(Box::new(Error {
+            message: Variable::Str(Arc::new(err)),
+            trace: vec![],
+            kind: None,
+        })),
     })
 }
 
-pub(crate) fn is_ok(rt: &mut Runtime) -> Result<Variable, String> {
-    let v = rt.stack.pop().expect(TINVOTS);
-    Ok(match rt.resolve(&v) {
-        &Variable::Result(Err(_)) => Variable::bool(false),
-        &Variable::Result(Ok(_)) => Variable::bool(true),
-        x => return Err(rt.expected_arg(0, x, "result")),
-    })
+/// The shape of numbers passed to and from closures.
+#[derive(Clone, Copy, PartialEq)]
+enum Shape {
+    F64,
+    Vec4,
+    Array(usize),
 }
 
-pub(crate) fn min(rt: &mut Runtime) -> Result<Variable, String> {
-    let v = rt.stack.pop().expect(TINVOTS);
-    Ok(Variable::f64(match rt.resolve(&v) {
-        &Variable::Array(ref arr) => {
-            let mut min: f64 = ::std::f64::NAN;
-            for v in &**arr {
-                if let Variable::F64(val, _) = *rt.resolve(v) {
-                    if val < min || min.is_nan() {
-                        min = val
-                    }
+impl Shape {
+    fn var(self, x: &[f64]) -> Variable {
+        match self {
+            Shape::F64 => Variable::f64(x[0]),
+            Shape::Vec4 => Variable::Vec4([
+                x[0] as Scalar,
+                x[1] as Scalar,
+                x[2] as Scalar,
+                x[3] as Scalar,
+            ]),
+            Shape::Array(_) => {
+                Variable::Array(Arc::new(x.iter().map(|&x| Variable::f64(x)).collect()))
+            }
+        }
+    }
+}
+
+/// Reads a number, a vec4 or an array of numbers.
+fn numbers(rt: &Runtime, v: &Variable) -> Option<(Shape, Vec<f64>)> {
+    match *rt.resolve(v) {
+        Variable::F64(x, _) => Some((Shape::F64, vec![x])),
+        Variable::Vec4(v) => Some((Shape::Vec4, v.iter().map(|&x| f64::from(x)).collect())),
+        Variable::Array(ref arr) => {
+            let mut res = Vec::with_capacity(arr.len());
+            for v in arr.iter() {
+                match *rt.resolve(v) {
+                    Variable::F64(x, _) => res.push(x),
+                    _ => return None,
                 }
             }
-            min
+            Some((Shape::Array(res.len()), res))
         }
-        x => return Err(rt.expected_arg(0, x, "array")),
-    }))
+        _ => None,
+    }
 }
 
-pub(crate) fn max(rt: &mut Runtime) -> Result<Variable, String> {
-    let v = rt.stack.pop().expect(TINVOTS);
-    Ok(Variable::f64(match rt.resolve(&v) {
-        &Variable::Array(ref arr) => {
-            let mut max: f64 = ::std::f64::NAN;
-            for v in &**arr {
-                if let Variable::F64(val, _) = *rt.resolve(v) {
-                    if val > max || max.is_nan() {
-                        max = val
-                    }
+pub(crate) fn minimize(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    lazy_static! {
+        static ref TOL: Arc<String> = Arc::new("tol".into());
+        static ref MAX_ITER: Arc<String> = Arc::new("max_iter".into());
+        static ref STEP: Arc<String> = Arc::new("step".into());
+    }
+
+    let opts = rt.stack.pop().expect(TINVOTS);
+    let x0 = rt.stack.pop().expect(TINVOTS);
+    let f = rt.stack.pop().expect(TINVOTS);
+    let f = closure_arg(rt, 0, f)?;
+    let (shape, x0) = match numbers(rt, &x0) {
+        Some(x) => x,
+        None => return Err(rt.expected_arg(1, &x0, "number, vec4 or array of numbers")),
+    };
+    let mut options = optimize::Options::default();
+    match rt.resolve(&opts) {
+        &Variable::Object(ref obj) => {
+            for (key, v) in obj.iter() {
+                let x = match *rt.resolve(v) {
+                    Variable::F64(x, _) => x,
+                    ref x => return Err(rt.expected_arg(2, x, "number")),
+                };
+                if key == &*TOL {
+                    options.tol = x;
+                } else if key == &*MAX_ITER {
+                    options.max_iter = x as usize;
+                } else if key == &*STEP {
+                    options.step = x;
+                } else {
+                    return Err({
+                        rt.arg_err_index.set(Some(2));
+                        format!(
+                            "Unknown option `{}`, expected `tol`, `max_iter` or `step`",
+                            key
+                        )
+                        .into()
+                    });
                 }
             }
-            max
         }
-        x => return Err(rt.expected_arg(0, x, "array")),
+        x => return Err(rt.expected_arg(2, x, "object")),
+    }
+    let res = optimize::nelder_mead(
+        &mut |x| Ok(call_f64(rt, &f, &[shape.var(x)])?),
+        &x0,
+        &options,
+    )?;
+    Ok(Variable::Result(match res {
+        Ok((x, fx)) => {
+            let mut obj = HashMap::new();
+            obj.insert(Arc::new("x".into()), shape.var(&x));
+            obj.insert(Arc::new("fx".into()), Variable::f64(fx));
+            Ok(Box::new(Variable::Object(Arc::new(obj))))
+        }
+        Err(err) => Err(Box::new(Error {
+            message: Variable::Str(Arc::new(err)),
+            trace: vec![],
+            kind: None,
+        })),
     }))
 }
 
-pub(crate) fn unwrap(rt: &mut Runtime) -> Result<Variable, String> {
-    use write::{write_variable, EscapeString};
+pub(crate) fn bisect(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let b = rt.stack.pop().expect(TINVOTS);
+    let b = rt.var(&b)?;
+    let a = rt.stack.pop().expect(TINVOTS);
+    let a = rt.var(&a)?;
+    let f = rt.stack.pop().expect(TINVOTS);
+    let f = closure_arg(rt, 0, f)?;
+    let res = optimize::bisect(&mut |x| Ok(call_f64(rt, &f, &[Variable::f64(x)])?), a, b)?;
+    Ok(f64_res(res))
+}
 
-    // Return value does not depend on lifetime of argument since
-    // `ok(x)` and `some(x)` perform a deep clone.
-    let v = rt.stack.pop().expect(TINVOTS);
-    Ok(match rt.resolve(&v) {
-        &Variable::Option(Some(ref v)) => (**v).clone(),
-        &Variable::Option(None) => {
-            return Err({
-                rt.arg_err_index.set(Some(0));
-                "Expected `some(_)`".into()
-            })
-        }
-        &Variable::Result(Ok(ref ok)) => (**ok).clone(),
-        &Variable::Result(Err(ref err)) => {
-            use std::str::from_utf8;
+pub(crate) fn newton(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let x0 = rt.stack.pop().expect(TINVOTS);
+    let x0 = rt.var(&x0)?;
+    let df = rt.stack.pop().expect(TINVOTS);
+    let df = closure_arg(rt, 1, df)?;
+    let f = rt.stack.pop().expect(TINVOTS);
+    let f = closure_arg(rt, 0, f)?;
+    let res = optimize::newton(
+        &mut |x| {
+            Ok((
+                call_f64(rt, &f, &[Variable::f64(x)])?,
+                call_f64(rt, &df, &[Variable::f64(x)])?,
+            ))
+        },
+        x0,
+    )?;
+    Ok(f64_res(res))
+}
 
-            // Print out error message.
-            let mut w: Vec<u8> = vec![];
-            w.extend_from_slice(rt.stack_trace().as_bytes());
-            w.extend_from_slice("\n".as_bytes());
-            write_variable(&mut w, rt, &err.message, EscapeString::None, 0).unwrap();
-            for t in &err.trace {
-                w.extend_from_slice("\n".as_bytes());
-                w.extend_from_slice(t.as_bytes());
+fn integrate(rt: &mut Runtime, method: ode::Method) -> Result<Variable, RuntimeError> {
+    let dt = rt.stack.pop().expect(TINVOTS);
+    let dt: f64 = rt.var(&dt)?;
+    let t1 = rt.stack.pop().expect(TINVOTS);
+    let t1: f64 = rt.var(&t1)?;
+    let t0 = rt.stack.pop().expect(TINVOTS);
+    let t0: f64 = rt.var(&t0)?;
+    let state = rt.stack.pop().expect(TINVOTS);
+    let f = rt.stack.pop().expect(TINVOTS);
+    let f = closure_arg(rt, 0, f)?;
+    let (shape, x0) = match numbers(rt, &state) {
+        Some(x) => x,
+        None => return Err(rt.expected_arg(1, &state, "number, vec4 or array of numbers")),
+    };
+    if !(dt > 0.0 && dt.is_finite()) {
+        return Err({
+            rt.arg_err_index.set(Some(4));
+            format!("Expected positive time step, found {}", dt).into()
+        });
+    }
+    if !(t0 <= t1 && (t1 - t0).is_finite()) {
+        return Err({
+            rt.arg_err_index.set(Some(3));
+            format!("Expected end time after start time {}, found {}", t0, t1).into()
+        });
+    }
+    let states = ode::integrate(
+        &mut |t, x| {
+            let v = rt.call_closure_ret(&f, &[Variable::f64(t), shape.var(x)])?;
+            match numbers(rt, &v) {
+                Some((s, dx)) if s == shape => Ok(dx),
+                _ => Err(rt.expected(&v, "derivative with the same shape as the state")),
             }
-            return Err({
-                rt.arg_err_index.set(Some(0));
-                from_utf8(&w).unwrap().into()
-            });
-        }
-        x => return Err(rt.expected_arg(0, x, "some(_) or ok(_)")),
-    })
+        },
+        method,
+        x0,
+        t0,
+        t1,
+        dt,
+    )?;
+    Ok(Variable::Array(Arc::new(
+        states.iter().map(|x| shape.var(x)).collect(),
+    )))
 }
 
-pub(crate) fn unwrap_or(rt: &mut Runtime) -> Result<Variable, String> {
-    // Return value does not depend on lifetime of argument since
-    // `ok(x)` and `some(x)` perform a deep clone.
-    let def = rt.stack.pop().expect(TINVOTS);
-    let v = rt.stack.pop().expect(TINVOTS);
-    Ok(match rt.resolve(&v) {
-        &Variable::Option(Some(ref v)) => (**v).clone(),
-        &Variable::Result(Ok(ref ok)) => (**ok).clone(),
-        &Variable::Option(None) | &Variable::Result(Err(_)) => rt.resolve(&def).clone(),
-        x => return Err(rt.expected_arg(0, x, "some(_) or ok(_)")),
-    })
+pub(crate) fn integrate_euler(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    integrate(rt, ode::Method::Euler)
+}
+
+pub(crate) fn integrate_rk4(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    integrate(rt, ode::Method::Rk4)
 }
 
-pub(crate) fn unwrap_err(rt: &mut Runtime) -> Result<Variable, String> {
+dyon_fn! {fn is_nan(v: f64) -> bool {v.is_nan()}}
+
+pub(crate) fn wait_next(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     let v = rt.stack.pop().expect(TINVOTS);
     Ok(match rt.resolve(&v) {
-        &Variable::Result(Err(ref err)) => err.message.clone(),
-        x => return Err(rt.expected_arg(0, x, "err(_)")),
+        &Variable::In(ref mutex) => match mutex.lock() {
+            Ok(x) => match x.recv() {
+                Ok(x) => Variable::Option(Some(Box::new(x))),
+                Err(_) => Variable::Option(None),
+            },
+            Err(err) => return Err(format!("Can not lock In mutex:\n{}", err.to_string()).into()),
+        },
+        x => return Err(rt.expected_arg(0, x, "in")),
     })
 }
 
-dyon_fn! {fn dir__angle(val: f64) -> Vec4 {Vec4([val.cos() as f32, val.sin() as f32, 0.0, 0.0])}}
+pub(crate) fn counter(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    use runtime::metrics::Counter;
 
-dyon_fn! {fn load__meta_file(meta: Arc<String>, file: Arc<String>) -> Variable {
-    let res = meta::load_meta_file(&**meta, &**file);
-    Variable::Result(match res {
-        Ok(res) => Ok(Box::new(Variable::Array(Arc::new(res)))),
-        Err(err) => Err(Box::new(Error {
-            message: Variable::Str(Arc::new(err)),
-            trace: vec![]
-        }))
-    })
-}}
+    let name = rt.stack.pop().expect(TINVOTS);
+    let name = str_arg(rt, 0, &name)?;
+    let counter = Counter(rt.metrics.counter(&name));
+    Ok(Variable::RustObject(Arc::new(Mutex::new(counter))))
+}
 
-dyon_fn! {fn load__meta_url(meta: Arc<String>, url: Arc<String>) -> Variable {
-    let res = meta::load_meta_url(&**meta, &**url);
-    Variable::Result(match res {
-        Ok(res) => Ok(Box::new(Variable::Array(Arc::new(res)))),
-        Err(err) => Err(Box::new(Error {
-            message: Variable::Str(Arc::new(err)),
-            trace: vec![]
-        }))
-    })
-}}
+pub(crate) fn inc(rt: &mut Runtime) -> Result<(), RuntimeError> {
+    use runtime::metrics::Counter;
 
-dyon_fn! {fn syntax__in_string(name: Arc<String>, text: Arc<String>) -> Variable {
-    use piston_meta::syntax_errstr;
+    let n = rt.stack.pop().expect(TINVOTS);
+    let n = match rt.resolve(&n) {
+        &Variable::F64(n, _) if n >= 0.0 => n,
+        &Variable::F64(_, _) => {
+            return Err({
+                rt.arg_err_index.set(Some(1));
+                "Expected non-negative number, counters can not decrease".into()
+            })
+        }
+        x => return Err(rt.expected_arg(1, x, "number")),
+    };
+    let v = rt.stack.pop().expect(TINVOTS);
+    let obj = match rt.resolve(&v) {
+        &Variable::RustObject(ref obj) => obj.clone(),
+        x => return Err(rt.expected_arg(0, x, "counter")),
+    };
+    let guard = obj.lock().unwrap();
+    match guard.downcast_ref::<Counter>() {
+        Some(counter) => {
+            *counter.0.lock().unwrap() += n;
+            Ok(())
+        }
+        None => Err({
+            rt.arg_err_index.set(Some(0));
+            "Expected counter".into()
+        }),
+    }
+}
 
-    let res = syntax_errstr(&text).map_err(|err|
-        format!("When parsing meta syntax in `{}`:\n{}", name, err));
-    Variable::Result(match res {
-        Ok(res) => Ok(Box::new(Variable::RustObject(Arc::new(Mutex::new(Arc::new(res)))))),
-        Err(err) => Err(Box::new(Error {
-            message: Variable::Str(Arc::new(err)),
-            trace: vec![]
-        }))
-    })
-}}
+pub(crate) fn histogram(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    use runtime::metrics::HistogramHandle;
 
-pub(crate) fn meta__syntax_in_string(rt: &mut Runtime) -> Result<Variable, String> {
-    let text = rt.stack.pop().expect(TINVOTS);
-    let text = match rt.resolve(&text) {
-        &Variable::Str(ref t) => t.clone(),
-        x => return Err(rt.expected_arg(2, x, "str")),
-    };
     let name = rt.stack.pop().expect(TINVOTS);
-    let name = match rt.resolve(&name) {
-        &Variable::Str(ref t) => t.clone(),
-        x => return Err(rt.expected_arg(1, x, "str")),
+    let name = str_arg(rt, 0, &name)?;
+    let h = HistogramHandle(rt.metrics.histogram(&name));
+    Ok(Variable::RustObject(Arc::new(Mutex::new(h))))
+}
+
+pub(crate) fn observe(rt: &mut Runtime) -> Result<(), RuntimeError> {
+    use runtime::metrics::HistogramHandle;
+
+    let val = rt.stack.pop().expect(TINVOTS);
+    let val = match rt.resolve(&val) {
+        &Variable::F64(val, _) => val,
+        x => return Err(rt.expected_arg(1, x, "number")),
     };
-    let syntax_var = rt.stack.pop().expect(TINVOTS);
-    let syntax_var = rt.resolve(&syntax_var);
-    let syntax = match syntax_var {
+    let v = rt.stack.pop().expect(TINVOTS);
+    let obj = match rt.resolve(&v) {
         &Variable::RustObject(ref obj) => obj.clone(),
-        x => return Err(rt.expected_arg(0, x, "Syntax")),
+        x => return Err(rt.expected_arg(0, x, "histogram")),
     };
-    let res = meta::parse_syntax_data(
-        match syntax.lock().unwrap().downcast_ref::<Arc<Syntax>>() {
-            Some(s) => s,
-            None => return Err(rt.expected_arg(0, syntax_var, "Syntax")),
-        },
-        &name,
-        &text,
-    );
-    Ok(Variable::Result(match res {
-        Ok(res) => Ok(Box::new(Variable::Array(Arc::new(res)))),
-        Err(err) => Err(Box::new(Error {
-            message: Variable::Str(Arc::new(err)),
-            trace: vec![],
-        })),
-    }))
+    let guard = obj.lock().unwrap();
+    match guard.downcast_ref::<HistogramHandle>() {
+        Some(h) => {
+            h.0.lock().unwrap().observe(val);
+            Ok(())
+        }
+        None => Err({
+            rt.arg_err_index.set(Some(0));
+            "Expected histogram".into()
+        }),
+    }
 }
 
-dyon_fn! {fn download__url_file(url: Arc<String>, file: Arc<String>) -> Variable {
-    let res = meta::download_url_to_file(&**url, &**file);
-    Variable::Result(match res {
-        Ok(res) => Ok(Box::new(Variable::Str(Arc::new(res)))),
-        Err(err) => Err(Box::new(Error {
-            message: Variable::Str(Arc::new(err)),
-            trace: vec![]
-        }))
-    })
-}}
+pub(crate) fn metrics_report(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Ok(rt.metrics.report())
+}
 
-#[cfg(all(not(target_family = "wasm"), feature = "file"))]
-dyon_fn! {fn save__string_file(text: Arc<String>, file: Arc<String>) -> Variable {
-    use std::fs::File;
-    use std::io::Write;
+pub(crate) fn metrics_prometheus(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Ok(Variable::Str(Arc::new(rt.metrics.prometheus())))
+}
 
-    Variable::Result(match File::create(&**file) {
-        Ok(mut f) => {
-            match f.write_all(text.as_bytes()) {
-                Ok(_) => Ok(Box::new(Variable::Str(file))),
-                Err(err) => Err(Box::new(Error {
-                    message: Variable::Str(Arc::new(err.to_string())),
-                    trace: vec![]
-                }))
-            }
-        }
-        Err(err) => Err(Box::new(Error {
-            message: Variable::Str(Arc::new(err.to_string())),
-            trace: vec![]
-        }))
-    })
-}}
+/// A value shared between threads, stored in a Rust object.
+struct Shared(Variable);
 
-#[cfg(not(all(not(target_family = "wasm"), feature = "file")))]
-pub(crate) fn save__string_file(_: &mut Runtime) -> Result<Variable, String> {
-    Err(FILE_SUPPORT_DISABLED.into())
+pub(crate) fn shared(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    let v = rt.resolve(&v).deep_clone(&rt.stack);
+    Ok(Variable::RustObject(Arc::new(Mutex::new(Shared(v)))))
 }
 
-#[cfg(all(not(target_family = "wasm"), feature = "file"))]
-dyon_fn! {fn load_string__file(file: Arc<String>) -> Variable {
-    use std::fs::File;
-    use std::io::Read;
+fn shared_arg(rt: &Runtime, v: &Variable) -> Result<RustObject, RuntimeError> {
+    match rt.resolve(v) {
+        &Variable::RustObject(ref obj) => Ok(obj.clone()),
+        x => Err(rt.expected_arg(0, x, "shared")),
+    }
+}
 
-    Variable::Result(match File::open(&**file) {
-        Ok(mut f) => {
-            let mut s = String::new();
-            match f.read_to_string(&mut s) {
-                Ok(_) => {
-                    Ok(Box::new(Variable::Str(Arc::new(s))))
-                }
-                Err(err) => {
-                    Err(Box::new(Error {
-                        message: Variable::Str(Arc::new(err.to_string())),
-                        trace: vec![]
-                    }))
-                }
-            }
+pub(crate) fn lock_read(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let f = rt.stack.pop().expect(TINVOTS);
+    let s = rt.stack.pop().expect(TINVOTS);
+    let obj = shared_arg(rt, &s)?;
+    let guard = obj
+        .lock()
+        .map_err(|err| format!("Can not lock shared mutex:\n{}", err))?;
+    let v = match guard.downcast_ref::<Shared>() {
+        Some(s) => s.0.clone(),
+        None => {
+            return Err({
+                rt.arg_err_index.set(Some(0));
+                "Expected shared".into()
+            })
         }
-        Err(err) => Err(Box::new(Error {
-            message: Variable::Str(Arc::new(err.to_string())),
-            trace: vec![]
-        }))
-    })
-}}
+    };
+    rt.call_closure_ret(&f, &[v])
+}
 
-#[cfg(not(all(not(target_family = "wasm"), feature = "file")))]
-pub(crate) fn load_string__file(_: &mut Runtime) -> Result<Variable, String> {
-    Err(FILE_SUPPORT_DISABLED.into())
+pub(crate) fn lock_write(rt: &mut Runtime) -> Result<(), RuntimeError> {
+    let f = rt.stack.pop().expect(TINVOTS);
+    let s = rt.stack.pop().expect(TINVOTS);
+    let obj = shared_arg(rt, &s)?;
+    let mut guard = obj
+        .lock()
+        .map_err(|err| format!("Can not lock shared mutex:\n{}", err))?;
+    let shared = match guard.downcast_mut::<Shared>() {
+        Some(s) => s,
+        None => {
+            return Err({
+                rt.arg_err_index.set(Some(0));
+                "Expected shared".into()
+            })
+        }
+    };
+    let v = rt.call_closure_ret(&f, ::std::slice::from_ref(&shared.0))?;
+    shared.0 = v.deep_clone(&rt.stack);
+    Ok(())
 }
 
-dyon_fn! {fn load_string__url(url: Arc<String>) -> Variable {
-    Variable::Result(match meta::load_text_file_from_url(&**url) {
-        Ok(s) => {
-            Ok(Box::new(Variable::Str(Arc::new(s))))
+pub(crate) fn channel(_rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    use std::sync::mpsc;
+
+    let (tx, rx) = mpsc::channel();
+    let mut obj = HashMap::new();
+    obj.insert(
+        Arc::new("out".into()),
+        Variable::Out(Arc::new(Mutex::new(tx))),
+    );
+    obj.insert(
+        Arc::new("in".into()),
+        Variable::In(Arc::new(Mutex::new(rx))),
+    );
+    Ok(Variable::Object(Arc::new(obj)))
+}
+
+pub(crate) fn send(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let msg = rt.stack.pop().expect(TINVOTS);
+    let msg = rt.resolve(&msg).deep_clone(&rt.stack);
+    let v = rt.stack.pop().expect(TINVOTS);
+    #[cfg(all(not(target_family = "wasm"), feature = "threading"))]
+    {
+        if let Some(actor) = actor_of(rt.resolve(&v))? {
+            return Ok(Variable::bool(actor.send(msg)));
         }
-        Err(err) => {
-            Err(Box::new(Error {
-                message: Variable::Str(Arc::new(err)),
-                trace: vec![]
-            }))
+    }
+    Ok(match rt.resolve(&v) {
+        &Variable::Out(ref mutex) => match mutex.lock() {
+            Ok(x) => Variable::bool(x.send(msg).is_ok()),
+            Err(err) => return Err(format!("Can not lock Out mutex:\n{}", err).into()),
+        },
+        &Variable::RustObject(ref obj) if obj.lock().unwrap().is::<CheckedOut>() => {
+            let mut guard = obj.lock().unwrap();
+            guard.downcast_mut::<CheckedOut>().unwrap().send(msg)?
         }
+        x => return Err(rt.expected_arg(0, x, "out")),
     })
-}}
-
-#[cfg(all(not(target_family = "wasm"), feature = "threading"))]
-pub(crate) fn join__thread(rt: &mut Runtime) -> Result<Variable, String> {
-    let thread = rt.stack.pop().expect(TINVOTS);
-    let handle_res = Thread::invalidate_handle(rt, thread);
-    Ok(Variable::Result({
-        match handle_res {
-            Ok(handle) => match handle.join() {
-                Ok(res) => match res {
-                    Ok(res) => Ok(Box::new(res)),
-                    Err(err) => Err(Box::new(Error {
-                        message: Variable::Str(Arc::new(err)),
-                        trace: vec![],
-                    })),
-                },
-                Err(_err) => Err(Box::new(Error {
-                    message: Variable::Str(Arc::new("Thread did not exit successfully".into())),
-                    trace: vec![],
-                })),
-            },
-            Err(err) => Err(Box::new(Error {
-                message: Variable::Str(Arc::new(err)),
-                trace: vec![],
-            })),
-        }
-    }))
 }
 
-dyon_fn! {fn load_data__file(file: Arc<String>) -> Variable {
-    use Error;
-
-    let res = match data::load_file(&file) {
-        Ok(data) => Ok(Box::new(data)),
-        Err(err) => Err(Box::new(Error {
-            message: Variable::Str(Arc::new(format!(
-                        "Error loading data from file `{}`:\n{}",
-                        file, err))),
-            trace: vec![]
-        }))
-    };
-    Variable::Result(res)
-}}
+/// The number of messages kept in the trace of a checked channel.
+const PROTOCOL_TRACE: usize = 16;
+
+/// The `out` of a channel that checks messages against a protocol,
+/// stored in a Rust object.
+struct CheckedOut {
+    out: Arc<Mutex<::std::sync::mpsc::Sender<Variable>>>,
+    /// The allowed message kinds per state, with the next state.
+    states: HashMap<Arc<String>, HashMap<Arc<String>, Arc<String>>>,
+    state: Arc<String>,
+    /// The last messages, as `(kind, from, to)`.
+    trace: ::std::collections::VecDeque<(Arc<String>, Arc<String>, Arc<String>)>,
+}
 
-dyon_fn! {fn load_data__string(text: Arc<String>) -> Variable {
-    use Error;
+impl CheckedOut {
+    fn send(&mut self, msg: Variable) -> Result<Variable, String> {
+        lazy_static! {
+            static ref KIND: Arc<String> = Arc::new("kind".into());
+        }
 
-    let res = match data::load_data(&text) {
-        Ok(data) => Ok(Box::new(data)),
-        Err(err) => Err(Box::new(Error {
-            message: Variable::Str(Arc::new(format!(
-                        "Error loading data from string `{}`:\n{}",
-                        text, err))),
-            trace: vec![]
+        let kind = match msg {
+            Variable::Str(ref kind) => Some(kind.clone()),
+            Variable::Object(ref obj) => match obj.get(&*KIND) {
+                Some(&Variable::Str(ref kind)) => Some(kind.clone()),
+                _ => None,
+            },
+            _ => None,
+        };
+        let kind = match kind {
+            Some(x) => x,
+            None => {
+                return Err(format!(
+                    "Expected string or object with `kind` as message in protocol\n{}",
+                    self.trace()
+                ))
+            }
+        };
+        let next = match self.states[&self.state].get(&kind) {
+            Some(x) => x.clone(),
+            None => {
+                return Err(format!(
+                    "Message `{}` is not allowed in state `{}`\n{}",
+                    kind,
+                    self.state,
+                    self.trace()
+                ))
+            }
+        };
+        if self.trace.len() == PROTOCOL_TRACE {
+            self.trace.pop_front();
+        }
+        self.trace
+            .push_back((kind, self.state.clone(), next.clone()));
+        self.state = next;
+        Ok(Variable::bool(match self.out.lock() {
+            Ok(x) => x.send(msg).is_ok(),
+            Err(err) => return Err(format!("Can not lock Out mutex:\n{}", err)),
         }))
-    };
-    Variable::Result(res)
-}}
+    }
 
-pub(crate) fn args_os(_rt: &mut Runtime) -> Result<Variable, String> {
-    let mut arr: Vec<Variable> = vec![];
-    for arg in ::std::env::args_os() {
-        if let Ok(t) = arg.into_string() {
-            arr.push(Variable::Str(Arc::new(t)))
-        } else {
-            return Err("Invalid unicode in os argument".into());
+    fn trace(&self) -> String {
+        let mut res = String::from("Protocol trace:");
+        if self.trace.is_empty() {
+            res.push_str(&format!("\n    start in `{}`", self.state));
         }
+        for &(ref kind, ref from, ref to) in &self.trace {
+            res.push_str(&format!("\n    `{}` -> `{}` -> `{}`", from, kind, to));
+        }
+        res
     }
-    Ok(Variable::Array(Arc::new(arr)))
 }
 
-#[cfg(all(not(target_family = "wasm"), feature = "file"))]
-pub(crate) fn save__data_file(rt: &mut Runtime) -> Result<Variable, String> {
-    use std::fs::File;
-    use std::io::BufWriter;
-    use write::{write_variable, EscapeString};
+pub(crate) fn checked_channel(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    lazy_static! {
+        static ref START: Arc<String> = Arc::new("start".into());
+        static ref STATES: Arc<String> = Arc::new("states".into());
+        static ref OUT: Arc<String> = Arc::new("out".into());
+    }
 
-    let file = rt.stack.pop().expect(TINVOTS);
-    let file = match rt.resolve(&file) {
-        &Variable::Str(ref t) => t.clone(),
-        x => return Err(rt.expected_arg(1, x, "str")),
+    let protocol = rt.stack.pop().expect(TINVOTS);
+    let ch = rt.stack.pop().expect(TINVOTS);
+    let ch = match rt.resolve(&ch) {
+        &Variable::Object(ref obj) => obj.clone(),
+        x => return Err(rt.expected_arg(0, x, "channel")),
     };
-    let data = rt.stack.pop().expect(TINVOTS);
-
-    let mut f = match File::create(&**file) {
-        Ok(f) => BufWriter::new(f),
-        Err(err) => {
+    let out = match ch.get(&*OUT) {
+        Some(&Variable::Out(ref out)) => out.clone(),
+        _ => {
             return Err({
                 rt.arg_err_index.set(Some(0));
-                format!("Error when creating file `{}`:\n{}", file, err.to_string())
+                "Expected channel with `out`".into()
             })
         }
     };
-    let res = match write_variable(&mut f, rt, &data, EscapeString::Json, 0) {
-        Ok(()) => Ok(Box::new(Variable::Str(file))),
-        Err(err) => Err(Box::new(::Error {
-            message: Variable::Str(Arc::new(format!(
-                "Error when writing to file `{}`:\n{}",
-                file,
-                err.to_string()
-            ))),
-            trace: vec![],
-        })),
+    let protocol_err = |msg: String| -> String {
+        rt.arg_err_index.set(Some(1));
+        msg
     };
-    Ok(Variable::Result(res))
+    let protocol = match rt.resolve(&protocol) {
+        &Variable::Object(ref obj) => obj.clone(),
+        x => return Err(rt.expected_arg(1, x, "protocol")),
+    };
+    let start = match protocol.get(&*START) {
+        Some(&Variable::Str(ref start)) => start.clone(),
+        _ => return Err(protocol_err("Expected `start: str` in protocol".into()).into()),
+    };
+    let mut states = HashMap::new();
+    match protocol.get(&*STATES) {
+        Some(&Variable::Object(ref obj)) => {
+            for (state, kinds) in obj.iter() {
+                let kinds = match *kinds {
+                    Variable::Object(ref kinds) => kinds,
+                    _ => {
+                        return Err(protocol_err(format!(
+                            "Expected object of message kinds for state `{}`",
+                            state
+                        ))
+                        .into())
+                    }
+                };
+                let mut next_states = HashMap::new();
+                for (kind, next) in kinds.iter() {
+                    match *next {
+                        Variable::Str(ref next) if obj.contains_key(next) => {
+                            next_states.insert(kind.clone(), next.clone());
+                        }
+                        _ => {
+                            return Err(protocol_err(format!(
+                                "Expected state after `{}` in state `{}`",
+                                kind, state
+                            ))
+                            .into())
+                        }
+                    }
+                }
+                states.insert(state.clone(), next_states);
+            }
+        }
+        _ => return Err(protocol_err("Expected `states: {}` in protocol".into()).into()),
+    }
+    if !states.contains_key(&start) {
+        return Err(protocol_err(format!("Expected start state `{}` in `states`", start)).into());
+    }
+    let checked = CheckedOut {
+        out,
+        states,
+        state: start,
+        trace: Default::default(),
+    };
+    let mut ch = (*ch).clone();
+    ch.insert(
+        OUT.clone(),
+        Variable::RustObject(Arc::new(Mutex::new(checked))),
+    );
+    Ok(Variable::Object(Arc::new(ch)))
 }
 
-#[cfg(not(all(not(target_family = "wasm"), feature = "file")))]
-pub(crate) fn save__data_file(_: &mut Runtime) -> Result<Variable, String> {
-    Err(FILE_SUPPORT_DISABLED.into())
+/// Returns the actor stored in a Rust object, if any.
+#[cfg(all(not(target_family = "wasm"), feature = "threading"))]
+fn actor_of(v: &Variable) -> Result<Option<actor::Actor>, RuntimeError> {
+    match *v {
+        Variable::RustObject(ref obj) => match obj.lock() {
+            Ok(guard) => Ok(guard.downcast_ref::<actor::Actor>().cloned()),
+            Err(err) => Err(format!("Can not lock Rust object:\n{}", err).into()),
+        },
+        _ => Ok(None),
+    }
 }
 
-pub(crate) fn json_from_meta_data(rt: &mut Runtime) -> Result<Variable, String> {
-    let meta_data = rt.stack.pop().expect(TINVOTS);
-    let json = match rt.resolve(&meta_data) {
-        &Variable::Array(ref arr) => meta::json_from_meta_data(arr).map_err(|err| {
-            format!(
-                "{}\nError when generating JSON:\n{}",
-                rt.stack_trace(),
-                err.to_string()
-            )
-        })?,
-        x => return Err(rt.expected_arg(0, x, "array")),
+#[cfg(all(not(target_family = "wasm"), feature = "threading"))]
+pub(crate) fn actor(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let handler = rt.stack.pop().expect(TINVOTS);
+    let handler = match rt.resolve(&handler) {
+        x @ &Variable::Closure(..) => x.deep_clone(&rt.stack),
+        x => return Err(rt.expected_arg(1, x, "closure")),
     };
-    Ok(Variable::Str(Arc::new(json)))
+    let state = rt.stack.pop().expect(TINVOTS);
+    let state = rt.resolve(&state).deep_clone(&rt.stack);
+    let actor = actor::spawn(rt, state, handler);
+    Ok(Variable::RustObject(Arc::new(Mutex::new(actor))))
 }
 
-pub(crate) fn errstr__string_start_len_msg(rt: &mut Runtime) -> Result<Variable, String> {
-    use piston_meta::ParseErrorHandler;
+#[cfg(not(all(not(target_family = "wasm"), feature = "threading")))]
+pub(crate) fn actor(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(THREADING_SUPPORT_DISABLED.into())
+}
 
+#[cfg(all(not(target_family = "wasm"), feature = "threading"))]
+pub(crate) fn ask(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     let msg = rt.stack.pop().expect(TINVOTS);
-    let msg = match rt.resolve(&msg) {
-        &Variable::Str(ref t) => t.clone(),
-        x => return Err(rt.expected_arg(3, x, "str")),
-    };
-    let len = rt.stack.pop().expect(TINVOTS);
-    let len = match rt.resolve(&len) {
-        &Variable::F64(v, _) => v as usize,
-        x => return Err(rt.expected_arg(2, x, "f64")),
-    };
-    let start = rt.stack.pop().expect(TINVOTS);
-    let start = match rt.resolve(&start) {
-        &Variable::F64(v, _) => v as usize,
-        x => return Err(rt.expected_arg(1, x, "f64")),
-    };
-    let source = rt.stack.pop().expect(TINVOTS);
-    let source = match rt.resolve(&source) {
-        &Variable::Str(ref t) => t.clone(),
-        x => return Err(rt.expected_arg(0, x, "str")),
+    let msg = rt.resolve(&msg).deep_clone(&rt.stack);
+    let v = rt.stack.pop().expect(TINVOTS);
+    // The actor is cloned to release the lock while waiting,
+    // such that other threads can use the actor.
+    let actor = match actor_of(rt.resolve(&v))? {
+        Some(actor) => actor,
+        None => return Err(rt.expected_arg(0, rt.resolve(&v), "actor")),
     };
+    let res = actor.ask(msg);
+    Ok(Variable::Result(match res {
+        Ok(v) => Ok(Box::new(v)),
+        Err(err) => Err(Box::new(Error {
+            message: Variable::Str(Arc::new(err)),
+            trace: vec![],
+            kind: None,
+        })),
+    }))
+}
 
-    let mut buf: Vec<u8> = vec![];
-    ParseErrorHandler::new(&source)
-        .write_msg(&mut buf, Range::new(start, len), &msg)
-        .unwrap();
-    Ok(Variable::Str(Arc::new(String::from_utf8(buf).unwrap())))
+#[cfg(not(all(not(target_family = "wasm"), feature = "threading")))]
+pub(crate) fn ask(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(THREADING_SUPPORT_DISABLED.into())
 }
 
-pub(crate) fn has(rt: &mut Runtime) -> Result<Variable, String> {
-    let key = rt.stack.pop().expect(TINVOTS);
-    let key = match rt.resolve(&key) {
-        &Variable::Str(ref t) => t.clone(),
-        x => return Err(rt.expected_arg(1, x, "str")),
+#[cfg(all(not(target_family = "wasm"), feature = "threading"))]
+pub(crate) fn supervisor(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let max_retries = rt.stack.pop().expect(TINVOTS);
+    let max_retries = match rt.resolve(&max_retries) {
+        &Variable::F64(v, _) if v >= 0.0 => v as u32,
+        x => return Err(rt.expected_arg(0, x, "non-negative number")),
     };
-    let obj = rt.stack.pop().expect(TINVOTS);
-    Ok(Variable::bool(match rt.resolve(&obj) {
-        &Variable::Object(ref obj) => obj.contains_key(&key),
-        x => return Err(rt.expected_arg(0, x, "object")),
-    }))
+    let sup = supervisor::Supervisor::new(max_retries);
+    Ok(Variable::RustObject(Arc::new(Mutex::new(sup))))
 }
 
-pub(crate) fn keys(rt: &mut Runtime) -> Result<Variable, String> {
-    let obj = rt.stack.pop().expect(TINVOTS);
-    Ok(Variable::Array(Arc::new(match rt.resolve(&obj) {
-        &Variable::Object(ref obj) => obj.keys().map(|k| Variable::Str(k.clone())).collect(),
-        x => return Err(rt.expected_arg(0, x, "object")),
-    })))
+#[cfg(not(all(not(target_family = "wasm"), feature = "threading")))]
+pub(crate) fn supervisor(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(THREADING_SUPPORT_DISABLED.into())
 }
 
-pub(crate) fn chars(rt: &mut Runtime) -> Result<Variable, String> {
-    let t = rt.stack.pop().expect(TINVOTS);
-    let t = match rt.resolve(&t) {
-        &Variable::Str(ref t) => t.clone(),
-        x => return Err(rt.expected_arg(0, x, "str")),
+#[cfg(all(not(target_family = "wasm"), feature = "threading"))]
+fn supervisor_arg(rt: &Runtime, v: &Variable) -> Result<RustObject, RuntimeError> {
+    match rt.resolve(v) {
+        &Variable::RustObject(ref obj) if obj.lock().unwrap().is::<supervisor::Supervisor>() => {
+            Ok(obj.clone())
+        }
+        x => Err(rt.expected_arg(0, x, "supervisor")),
+    }
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "threading"))]
+pub(crate) fn supervise(rt: &mut Runtime) -> Result<(), RuntimeError> {
+    let task = rt.stack.pop().expect(TINVOTS);
+    let task = match rt.resolve(&task) {
+        x @ &Variable::Closure(..) => x.deep_clone(&rt.stack),
+        x => return Err(rt.expected_arg(2, x, "closure")),
     };
-    Ok(Variable::Array(Arc::new(
-        t.chars()
-            .map(|ch| {
-                let mut s = String::new();
-                s.push(ch);
-                Variable::Str(Arc::new(s))
-            })
-            .collect::<Vec<_>>(),
-    )))
+    let name = rt.stack.pop().expect(TINVOTS);
+    let name = str_arg(rt, 1, &name)?;
+    let sup = rt.stack.pop().expect(TINVOTS);
+    let obj = supervisor_arg(rt, &sup)?;
+    let guard = obj.lock().unwrap();
+    guard
+        .downcast_ref::<supervisor::Supervisor>()
+        .unwrap()
+        .spawn(rt, name, task);
+    Ok(())
 }
 
-dyon_fn! {fn now() -> f64 {
-    use std::time::{SystemTime, UNIX_EPOCH};
+#[cfg(not(all(not(target_family = "wasm"), feature = "threading")))]
+pub(crate) fn supervise(_: &mut Runtime) -> Result<(), RuntimeError> {
+    Err(THREADING_SUPPORT_DISABLED.into())
+}
 
-    match SystemTime::now().duration_since(UNIX_EPOCH) {
-        Ok(val) => val.as_secs() as f64 +
-                   f64::from(val.subsec_nanos()) / 1.0e9,
-        Err(err) => -{
-            let val = err.duration();
-            val.as_secs() as f64 +
-            f64::from(val.subsec_nanos()) / 1.0e9
-        }
-    }
+#[cfg(all(not(target_family = "wasm"), feature = "threading"))]
+pub(crate) fn failures(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let sup = rt.stack.pop().expect(TINVOTS);
+    let obj = supervisor_arg(rt, &sup)?;
+    let guard = obj.lock().unwrap();
+    Ok(guard
+        .downcast_ref::<supervisor::Supervisor>()
+        .unwrap()
+        .failures())
+}
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "threading")))]
+pub(crate) fn failures(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(THREADING_SUPPORT_DISABLED.into())
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "gpu"))]
+dyon_fn! {fn gpu_map(array: Vec<f64>, kernel: Arc<String>) -> Result<Vec<f64>, String> {
+    gpu::map(&array, &kernel)
 }}
 
-dyon_fn! {fn is_nan(v: f64) -> bool {v.is_nan()}}
+#[cfg(not(all(not(target_family = "wasm"), feature = "gpu")))]
+pub(crate) fn gpu_map(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(GPU_SUPPORT_DISABLED.into())
+}
 
-pub(crate) fn wait_next(rt: &mut Runtime) -> Result<Variable, String> {
-    let v = rt.stack.pop().expect(TINVOTS);
-    Ok(match rt.resolve(&v) {
-        &Variable::In(ref mutex) => match mutex.lock() {
-            Ok(x) => match x.recv() {
-                Ok(x) => Variable::Option(Some(Box::new(x))),
-                Err(_) => Variable::Option(None),
-            },
-            Err(err) => return Err(format!("Can not lock In mutex:\n{}", err.to_string())),
-        },
-        x => return Err(rt.expected_arg(0, x, "in")),
-    })
+#[cfg(all(not(target_family = "wasm"), feature = "gpu"))]
+dyon_fn! {fn gpu_mat_mul(a: Vec<Vec<f64>>, b: Vec<Vec<f64>>) -> Result<Vec<Vec<f64>>, String> {
+    gpu::mat_mul(&a, &b)
+}}
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "gpu")))]
+pub(crate) fn gpu_mat_mul(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(GPU_SUPPORT_DISABLED.into())
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "threading"))]
+fn parallel(
+    rt: &mut Runtime,
+    kind: parallel::Kind,
+    n: usize,
+) -> Result<Vec<Variable>, RuntimeError> {
+    let mut fns = vec![];
+    for i in (2..2 + n).rev() {
+        let f = rt.stack.pop().expect(TINVOTS);
+        fns.push(match rt.resolve(&f) {
+            x @ &Variable::Closure(..) => x.deep_clone(&rt.stack),
+            x => return Err(rt.expected_arg(i, x, "closure")),
+        });
+    }
+    fns.reverse();
+    let chunk = rt.stack.pop().expect(TINVOTS);
+    let chunk = match rt.resolve(&chunk) {
+        &Variable::F64(v, _) if v >= 1.0 => v as usize,
+        x => return Err(rt.expected_arg(1, x, "number >= 1")),
+    };
+    let range = rt.stack.pop().expect(TINVOTS);
+    let (start, end) = match rt.resolve(&range) {
+        &Variable::Array(ref arr) if arr.len() == 2 => {
+            match (rt.resolve(&arr[0]), rt.resolve(&arr[1])) {
+                (&Variable::F64(start, _), &Variable::F64(end, _)) => (start, end),
+                _ => return Err(rt.expected_arg(0, &range, "[start, end]")),
+            }
+        }
+        x => return Err(rt.expected_arg(0, x, "[start, end]")),
+    };
+    parallel::run(rt, kind, start, end, chunk, fns)
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "threading"))]
+pub(crate) fn parallel_for(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let res = parallel(rt, parallel::Kind::For, 1)?;
+    Ok(Variable::Array(Arc::new(res)))
+}
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "threading")))]
+pub(crate) fn parallel_for(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(THREADING_SUPPORT_DISABLED.into())
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "threading"))]
+pub(crate) fn parallel_reduce(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let res = parallel(rt, parallel::Kind::Reduce, 2)?;
+    Ok(Variable::Option(res.into_iter().next().map(Box::new)))
+}
+
+#[cfg(not(all(not(target_family = "wasm"), feature = "threading")))]
+pub(crate) fn parallel_reduce(_: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Err(THREADING_SUPPORT_DISABLED.into())
 }
 
-pub(crate) fn next(rt: &mut Runtime) -> Result<Variable, String> {
+pub(crate) fn next(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
     let v = rt.stack.pop().expect(TINVOTS);
     Ok(match rt.resolve(&v) {
         &Variable::In(ref mutex) => match mutex.lock() {
@@ -1967,8 +6990,84 @@ pub(crate) fn next(rt: &mut Runtime) -> Result<Variable, String> {
                 Ok(x) => Variable::Option(Some(Box::new(x))),
                 Err(_) => Variable::Option(None),
             },
-            Err(err) => return Err(format!("Can not lock In mutex:\n{}", err.to_string())),
+            Err(err) => return Err(format!("Can not lock In mutex:\n{}", err.to_string()).into()),
         },
         x => return Err(rt.expected_arg(0, x, "in")),
     })
 }
+
+/// A topic of a broker created by `topics`.
+#[derive(Default)]
+struct Topic {
+    subscribers: Vec<::std::sync::mpsc::Sender<Variable>>,
+    /// The last message, for new subscribers when the broker is sticky.
+    last: Option<Variable>,
+}
+
+/// A publish/subscribe broker, stored in a Rust object.
+struct Topics {
+    sticky: bool,
+    topics: HashMap<Arc<String>, Topic>,
+}
+
+fn new_topics(sticky: bool) -> Variable {
+    Variable::RustObject(Arc::new(Mutex::new(Topics {
+        sticky,
+        topics: HashMap::new(),
+    })))
+}
+
+pub(crate) fn topics(_rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    Ok(new_topics(false))
+}
+
+pub(crate) fn topics__sticky(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let sticky = rt.stack.pop().expect(TINVOTS);
+    let sticky: bool = rt.var(&sticky)?;
+    Ok(new_topics(sticky))
+}
+
+fn topics_arg(rt: &Runtime, v: &Variable) -> Result<RustObject, RuntimeError> {
+    match rt.resolve(v) {
+        &Variable::RustObject(ref obj) if obj.lock().unwrap().is::<Topics>() => Ok(obj.clone()),
+        x => Err(rt.expected_arg(0, x, "topics")),
+    }
+}
+
+pub(crate) fn publish(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    let msg = rt.stack.pop().expect(TINVOTS);
+    let msg = rt.resolve(&msg).deep_clone(&rt.stack);
+    let topic = rt.stack.pop().expect(TINVOTS);
+    let topic = str_arg(rt, 1, &topic)?;
+    let t = rt.stack.pop().expect(TINVOTS);
+    let obj = topics_arg(rt, &t)?;
+    let mut guard = obj.lock().unwrap();
+    let t = guard.downcast_mut::<Topics>().unwrap();
+    let sticky = t.sticky;
+    let topic = t.topics.entry(topic).or_default();
+    // Drop subscribers that dropped their `in`.
+    topic.subscribers.retain(|tx| tx.send(msg.clone()).is_ok());
+    if sticky {
+        topic.last = Some(msg);
+    }
+    Ok(Variable::f64(topic.subscribers.len() as f64))
+}
+
+pub(crate) fn subscribe(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+    use std::sync::mpsc;
+
+    let topic = rt.stack.pop().expect(TINVOTS);
+    let topic = str_arg(rt, 1, &topic)?;
+    let t = rt.stack.pop().expect(TINVOTS);
+    let obj = topics_arg(rt, &t)?;
+    let mut guard = obj.lock().unwrap();
+    let t = guard.downcast_mut::<Topics>().unwrap();
+    let topic = t.topics.entry(topic).or_default();
+    let (tx, rx) = mpsc::channel();
+    if let Some(ref last) = topic.last {
+        // The receiver is alive, so sending can not fail.
+        let _ = tx.send(last.clone());
+    }
+    topic.subscribers.push(tx);
+    Ok(Variable::In(Arc::new(Mutex::new(rx))))
+}
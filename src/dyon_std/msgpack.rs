@@ -0,0 +1,227 @@
+//! Converts variables to and from MessagePack.
+//!
+//! Numbers without fraction are written as integers, other numbers as `f64`.
+//! `none()` is written as nil, and `some(x)` as extension type 2 holding `x`,
+//! such that e.g. `some(none())` is read back unchanged.
+//! Binary data is written as bin, and `vec4` as extension type 1
+//! holding four big-endian `f32`, or `f64` with the `f64_vec4` feature.
+//! Both sizes of `vec4` are read with any feature.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use rmp::decode::{self, RmpRead};
+use rmp::encode;
+use rmp::Marker;
+
+use embed::ConvertVec4;
+use Runtime;
+use RuntimeError;
+use Scalar;
+use Variable;
+
+/// The MessagePack extension type used for `vec4`.
+const VEC4_EXT: i8 = 1;
+/// The MessagePack extension type used for `some(x)`.
+const SOME_EXT: i8 = 2;
+
+/// The maximum nesting of arrays and maps when reading.
+const MAX_DEPTH: usize = 256;
+
+fn len(n: usize) -> Result<u32, String> {
+    if n > u32::MAX as usize {
+        Err("Length is too large for MessagePack".into())
+    } else {
+        Ok(n as u32)
+    }
+}
+
+/// Writes a variable as MessagePack.
+pub(crate) fn write(rt: &Runtime, v: &Variable, out: &mut Vec<u8>) -> Result<(), RuntimeError> {
+    let err = |err: encode::ValueWriteError| err.to_string();
+    match *rt.resolve(v) {
+        Variable::F64(x, _) => {
+            if x.fract() == 0.0 && x.abs() < 9_007_199_254_740_992.0 {
+                encode::write_sint(out, x as i64).map_err(err)?;
+            } else {
+                encode::write_f64(out, x).map_err(err)?;
+            }
+        }
+        Variable::Bool(b, _) => encode::write_bool(out, b).map_err(|err| err.to_string())?,
+        Variable::Str(ref s) => encode::write_str(out, s).map_err(err)?,
+        Variable::Bytes(ref b) => encode::write_bin(out, b).map_err(err)?,
+        Variable::Vec4(v) => {
+            let v: [Scalar; 4] = ConvertVec4::from(v);
+            encode::write_ext_meta(out, 4 * ::std::mem::size_of::<Scalar>() as u32, VEC4_EXT)
+                .map_err(err)?;
+            for x in &v {
+                out.extend_from_slice(&x.to_be_bytes());
+            }
+        }
+        Variable::Array(ref arr) => {
+            encode::write_array_len(out, len(arr.len())?).map_err(err)?;
+            for it in arr.iter() {
+                write(rt, it, out)?;
+            }
+        }
+        Variable::Object(ref obj) => {
+            encode::write_map_len(out, len(obj.len())?).map_err(err)?;
+            for (k, v) in obj.iter() {
+                encode::write_str(out, k).map_err(err)?;
+                write(rt, v, out)?;
+            }
+        }
+        Variable::Option(None) => encode::write_nil(out).map_err(|err| err.to_string())?,
+        Variable::Option(Some(ref v)) => {
+            let mut inner = vec![];
+            write(rt, v, &mut inner)?;
+            encode::write_ext_meta(out, len(inner.len())?, SOME_EXT).map_err(err)?;
+            out.extend_from_slice(&inner);
+        }
+        ref x => return Err(format!("Can not convert `{}` to MessagePack", x.typeof_var()).into()),
+    }
+    Ok(())
+}
+
+/// Reads a variable from MessagePack, which must use all the data.
+pub(crate) fn read(data: &[u8]) -> Result<Variable, String> {
+    let mut rd = data;
+    let v = read_var(&mut rd, 0)?;
+    if !rd.is_empty() {
+        return Err(format!(
+            "Unexpected data after MessagePack value at byte {}",
+            data.len() - rd.len()
+        ));
+    }
+    Ok(v)
+}
+
+fn read_bytes(rd: &mut &[u8], n: usize) -> Result<Vec<u8>, String> {
+    if n > rd.len() {
+        return Err("Unexpected end of MessagePack data".into());
+    }
+    let (bytes, rest) = rd.split_at(n);
+    *rd = rest;
+    Ok(bytes.to_vec())
+}
+
+/// Reads the length of a string, binary data, array, map or extension.
+fn read_len(rd: &mut &[u8], marker: Marker) -> Result<usize, String> {
+    let err = |err: decode::ValueReadError| err.to_string();
+    Ok(match marker {
+        Marker::FixStr(n) | Marker::FixArray(n) | Marker::FixMap(n) => n as usize,
+        Marker::FixExt1 => 1,
+        Marker::FixExt2 => 2,
+        Marker::FixExt4 => 4,
+        Marker::FixExt8 => 8,
+        Marker::FixExt16 => 16,
+        Marker::Str8 | Marker::Bin8 | Marker::Ext8 => rd.read_data_u8().map_err(err)? as usize,
+        Marker::Str16 | Marker::Bin16 | Marker::Ext16 | Marker::Array16 | Marker::Map16 => {
+            rd.read_data_u16().map_err(err)? as usize
+        }
+        _ => rd.read_data_u32().map_err(err)? as usize,
+    })
+}
+
+fn read_var(rd: &mut &[u8], depth: usize) -> Result<Variable, String> {
+    if depth > MAX_DEPTH {
+        return Err(format!("MessagePack is nested deeper than {}", MAX_DEPTH));
+    }
+    let marker = decode::read_marker(rd).map_err(|_| "Unexpected end of MessagePack data")?;
+    let err = |err: decode::ValueReadError| err.to_string();
+    Ok(match marker {
+        Marker::Null => Variable::Option(None),
+        Marker::True => Variable::bool(true),
+        Marker::False => Variable::bool(false),
+        Marker::FixPos(x) => Variable::f64(f64::from(x)),
+        Marker::FixNeg(x) => Variable::f64(f64::from(x)),
+        Marker::U8 => Variable::f64(f64::from(rd.read_data_u8().map_err(err)?)),
+        Marker::U16 => Variable::f64(f64::from(rd.read_data_u16().map_err(err)?)),
+        Marker::U32 => Variable::f64(f64::from(rd.read_data_u32().map_err(err)?)),
+        Marker::U64 => Variable::f64(rd.read_data_u64().map_err(err)? as f64),
+        Marker::I8 => Variable::f64(f64::from(rd.read_data_i8().map_err(err)?)),
+        Marker::I16 => Variable::f64(f64::from(rd.read_data_i16().map_err(err)?)),
+        Marker::I32 => Variable::f64(f64::from(rd.read_data_i32().map_err(err)?)),
+        Marker::I64 => Variable::f64(rd.read_data_i64().map_err(err)? as f64),
+        Marker::F32 => Variable::f64(f64::from(rd.read_data_f32().map_err(err)?)),
+        Marker::F64 => Variable::f64(rd.read_data_f64().map_err(err)?),
+        Marker::FixStr(_) | Marker::Str8 | Marker::Str16 | Marker::Str32 => {
+            let n = read_len(rd, marker)?;
+            let bytes = read_bytes(rd, n)?;
+            match String::from_utf8(bytes) {
+                Ok(s) => Variable::Str(Arc::new(s)),
+                Err(_) => return Err("Invalid UTF-8 in MessagePack string".into()),
+            }
+        }
+        Marker::Bin8 | Marker::Bin16 | Marker::Bin32 => {
+            let n = read_len(rd, marker)?;
+            Variable::Bytes(Arc::new(read_bytes(rd, n)?))
+        }
+        Marker::FixArray(_) | Marker::Array16 | Marker::Array32 => {
+            let n = read_len(rd, marker)?;
+            // Every item takes at least one byte.
+            let mut arr = Vec::with_capacity(n.min(rd.len()));
+            for _ in 0..n {
+                arr.push(read_var(rd, depth + 1)?);
+            }
+            Variable::Array(Arc::new(arr))
+        }
+        Marker::FixMap(_) | Marker::Map16 | Marker::Map32 => {
+            let n = read_len(rd, marker)?;
+            let mut obj = HashMap::with_capacity(n.min(rd.len() / 2));
+            for _ in 0..n {
+                let key = match read_var(rd, depth + 1)? {
+                    Variable::Str(key) => key,
+                    x => {
+                        return Err(format!(
+                            "Expected `str` as MessagePack map key, found `{}`",
+                            x.typeof_var()
+                        ))
+                    }
+                };
+                obj.insert(key, read_var(rd, depth + 1)?);
+            }
+            Variable::Object(Arc::new(obj))
+        }
+        Marker::FixExt1
+        | Marker::FixExt2
+        | Marker::FixExt4
+        | Marker::FixExt8
+        | Marker::FixExt16
+        | Marker::Ext8
+        | Marker::Ext16
+        | Marker::Ext32 => {
+            let n = read_len(rd, marker)?;
+            let ty = rd.read_data_i8().map_err(err)?;
+            let bytes = read_bytes(rd, n)?;
+            match (ty, n) {
+                (VEC4_EXT, 16) => {
+                    let mut v = [0.0f32; 4];
+                    for (x, b) in v.iter_mut().zip(bytes.chunks(4)) {
+                        *x = f32::from_be_bytes([b[0], b[1], b[2], b[3]]);
+                    }
+                    Variable::Vec4(v.to())
+                }
+                (VEC4_EXT, 32) => {
+                    let mut v = [0.0f64; 4];
+                    for (x, b) in v.iter_mut().zip(bytes.chunks(8)) {
+                        let mut be = [0; 8];
+                        be.copy_from_slice(b);
+                        *x = f64::from_be_bytes(be);
+                    }
+                    Variable::Vec4(v.to())
+                }
+                (SOME_EXT, _) => {
+                    let mut inner = &bytes[..];
+                    let v = read_var(&mut inner, depth + 1)?;
+                    if !inner.is_empty() {
+                        return Err("Unexpected data after MessagePack value in `some(_)`".into());
+                    }
+                    Variable::Option(Some(Box::new(v)))
+                }
+                _ => return Err(format!("Unknown MessagePack extension type `{}`", ty)),
+            }
+        }
+        Marker::Reserved => return Err("Unexpected reserved MessagePack marker".into()),
+    })
+}
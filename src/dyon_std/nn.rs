@@ -0,0 +1,167 @@
+//! Inference for small neural networks.
+//!
+//! A model is a multilayer perceptron stored as Dyon data:
+//!
+//! ```dyon
+//! {layers: [
+//!     {weights: [[0.5, -1], [2, 0]], bias: [0, 1], activation: "relu"},
+//!     {weights: [[1, 1]], bias: [0], activation: "sigmoid"}
+//! ]}
+//! ```
+//!
+//! Each layer has a row of weights per output, with a weight per input.
+
+use std::sync::Arc;
+
+use Variable;
+
+#[derive(Clone, Copy)]
+enum Activation {
+    Linear,
+    Relu,
+    Sigmoid,
+    Tanh,
+    Softmax,
+}
+
+struct Layer {
+    weights: Vec<Vec<f64>>,
+    bias: Vec<f64>,
+    activation: Activation,
+}
+
+/// A multilayer perceptron.
+pub(crate) struct Mlp {
+    layers: Vec<Layer>,
+}
+
+impl Mlp {
+    /// Reads a model from data, checking that the layers fit together.
+    pub(crate) fn from_data(data: &Variable) -> Result<Mlp, String> {
+        let layers = match field(data, "layers")? {
+            Variable::Array(ref arr) if !arr.is_empty() => arr,
+            _ => return Err("Expected non-empty array `layers`".into()),
+        };
+        let mut res: Vec<Layer> = vec![];
+        for (i, layer) in layers.iter().enumerate() {
+            let layer = read_layer(layer).map_err(|err| format!("In layer {}: {}", i, err))?;
+            if let Some(prev) = res.last() {
+                if layer.inputs() != prev.bias.len() {
+                    return Err(format!(
+                        "In layer {}: Expected {} inputs, found {}",
+                        i,
+                        prev.bias.len(),
+                        layer.inputs()
+                    ));
+                }
+            }
+            res.push(layer);
+        }
+        Ok(Mlp { layers: res })
+    }
+
+    /// Runs the model on an input, returning the output of the last layer.
+    pub(crate) fn infer(&self, input: &[f64]) -> Result<Vec<f64>, String> {
+        let inputs = self.layers[0].inputs();
+        if input.len() != inputs {
+            return Err(format!("Expected {} inputs, found {}", inputs, input.len()));
+        }
+        let mut x = input.to_vec();
+        for layer in &self.layers {
+            x = layer.forward(&x);
+        }
+        Ok(x)
+    }
+}
+
+impl Layer {
+    fn inputs(&self) -> usize {
+        self.weights[0].len()
+    }
+
+    fn forward(&self, x: &[f64]) -> Vec<f64> {
+        let mut y: Vec<f64> = self
+            .weights
+            .iter()
+            .zip(&self.bias)
+            .map(|(row, b)| row.iter().zip(x).map(|(w, x)| w * x).sum::<f64>() + b)
+            .collect();
+        match self.activation {
+            Activation::Linear => {}
+            Activation::Relu => y.iter_mut().for_each(|v| *v = v.max(0.0)),
+            Activation::Sigmoid => y.iter_mut().for_each(|v| *v = 1.0 / (1.0 + (-*v).exp())),
+            Activation::Tanh => y.iter_mut().for_each(|v| *v = v.tanh()),
+            Activation::Softmax => {
+                // Subtract the maximum to avoid overflow.
+                let max = y.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+                y.iter_mut().for_each(|v| *v = (*v - max).exp());
+                let sum: f64 = y.iter().sum();
+                y.iter_mut().for_each(|v| *v /= sum);
+            }
+        }
+        y
+    }
+}
+
+fn field<'a>(obj: &'a Variable, name: &str) -> Result<&'a Variable, String> {
+    match *obj {
+        Variable::Object(ref obj) => obj
+            .get(&Arc::new(name.into()))
+            .ok_or_else(|| format!("Could not find `{}`", name)),
+        _ => Err("Expected object".into()),
+    }
+}
+
+fn numbers(v: &Variable, name: &str) -> Result<Vec<f64>, String> {
+    let arr = match *v {
+        Variable::Array(ref arr) => arr,
+        _ => return Err(format!("Expected array of numbers in `{}`", name)),
+    };
+    arr.iter()
+        .map(|x| match *x {
+            Variable::F64(x, _) => Ok(x),
+            _ => Err(format!("Expected number in `{}`", name)),
+        })
+        .collect()
+}
+
+fn read_layer(layer: &Variable) -> Result<Layer, String> {
+    let weights = match *field(layer, "weights")? {
+        Variable::Array(ref rows) => rows
+            .iter()
+            .map(|row| numbers(row, "weights"))
+            .collect::<Result<Vec<_>, _>>()?,
+        _ => return Err("Expected array of rows in `weights`".into()),
+    };
+    let bias = numbers(field(layer, "bias")?, "bias")?;
+    if weights.is_empty() || weights[0].is_empty() {
+        return Err("Expected at least one weight".into());
+    }
+    if weights.iter().any(|row| row.len() != weights[0].len()) {
+        return Err("Expected rows of equal length in `weights`".into());
+    }
+    if bias.len() != weights.len() {
+        return Err(format!(
+            "Expected {} numbers in `bias`, found {}",
+            weights.len(),
+            bias.len()
+        ));
+    }
+    let activation = match field(layer, "activation") {
+        Err(_) => Activation::Linear,
+        Ok(Variable::Str(s)) => match &***s {
+            "linear" => Activation::Linear,
+            "relu" => Activation::Relu,
+            "sigmoid" => Activation::Sigmoid,
+            "tanh" => Activation::Tanh,
+            "softmax" => Activation::Softmax,
+            _ => return Err(format!("Unknown activation `{}`", s)),
+        },
+        Ok(_) => return Err("Expected string in `activation`".into()),
+    };
+    Ok(Layer {
+        weights,
+        bias,
+        activation,
+    })
+}
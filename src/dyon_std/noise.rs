@@ -0,0 +1,131 @@
+//! Gradient noise for procedural generation.
+//!
+//! The noise is deterministic, using the permutation table from
+//! Ken Perlin's reference implementation, and is in the range `[-1, 1]`.
+
+const PERM: [u8; 256] = [
+    151, 160, 137, 91, 90, 15, 131, 13, 201, 95, 96, 53, 194, 233, 7, 225, 140, 36, 103, 30, 69,
+    142, 8, 99, 37, 240, 21, 10, 23, 190, 6, 148, 247, 120, 234, 75, 0, 26, 197, 62, 94, 252, 219,
+    203, 117, 35, 11, 32, 57, 177, 33, 88, 237, 149, 56, 87, 174, 20, 125, 136, 171, 168, 68, 175,
+    74, 165, 71, 134, 139, 48, 27, 166, 77, 146, 158, 231, 83, 111, 229, 122, 60, 211, 133, 230,
+    220, 105, 92, 41, 55, 46, 245, 40, 244, 102, 143, 54, 65, 25, 63, 161, 1, 216, 80, 73, 209, 76,
+    132, 187, 208, 89, 18, 169, 200, 196, 135, 130, 116, 188, 159, 86, 164, 100, 109, 198, 173,
+    186, 3, 64, 52, 217, 226, 250, 124, 123, 5, 202, 38, 147, 118, 126, 255, 82, 85, 212, 207, 206,
+    59, 227, 47, 16, 58, 17, 182, 189, 28, 42, 223, 183, 170, 213, 119, 248, 152, 2, 44, 154, 163,
+    70, 221, 153, 101, 155, 167, 43, 172, 9, 129, 22, 39, 253, 19, 98, 108, 110, 79, 113, 224, 232,
+    178, 185, 112, 104, 218, 246, 97, 228, 251, 34, 242, 193, 238, 210, 144, 12, 191, 179, 162,
+    241, 81, 51, 145, 235, 249, 14, 239, 107, 49, 192, 214, 31, 181, 199, 106, 157, 184, 84, 204,
+    176, 115, 121, 50, 45, 127, 4, 150, 254, 138, 236, 205, 93, 222, 114, 67, 29, 24, 72, 243, 141,
+    128, 195, 78, 66, 215, 61, 156, 180,
+];
+
+/// Hashes lattice coordinates to a permutation value.
+fn hash(coords: &[i64]) -> usize {
+    coords
+        .iter()
+        .fold(0, |h, &c| PERM[(h + (c & 255) as usize) & 255] as usize)
+}
+
+/// Eases the interpolation weight, such that the noise is smooth.
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+/// Dot product of a pseudo-random gradient with the offset.
+fn grad2(h: usize, x: f64, y: f64) -> f64 {
+    match h & 7 {
+        0 => x + y,
+        1 => -x + y,
+        2 => x - y,
+        3 => -x - y,
+        4 => x,
+        5 => -x,
+        6 => y,
+        _ => -y,
+    }
+}
+
+/// Dot product of a pseudo-random gradient with the offset.
+fn grad3(h: usize, x: f64, y: f64, z: f64) -> f64 {
+    let u = if h & 15 < 8 { x } else { y };
+    let v = match h & 15 {
+        0..=3 => y,
+        12 | 14 => x,
+        _ => z,
+    };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+/// Perlin noise in 2D.
+pub(crate) fn perlin2(x: f64, y: f64) -> f64 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (xi, yi) = (x0 as i64, y0 as i64);
+    let (x, y) = (x - x0, y - y0);
+    let (u, v) = (fade(x), fade(y));
+    let g = |dx: i64, dy: i64| grad2(hash(&[xi + dx, yi + dy]), x - dx as f64, y - dy as f64);
+    lerp(lerp(g(0, 0), g(1, 0), u), lerp(g(0, 1), g(1, 1), u), v)
+}
+
+/// Perlin noise in 3D.
+pub(crate) fn perlin3(x: f64, y: f64, z: f64) -> f64 {
+    let (x0, y0, z0) = (x.floor(), y.floor(), z.floor());
+    let (xi, yi, zi) = (x0 as i64, y0 as i64, z0 as i64);
+    let (x, y, z) = (x - x0, y - y0, z - z0);
+    let (u, v, w) = (fade(x), fade(y), fade(z));
+    let g = |dx: i64, dy: i64, dz: i64| {
+        grad3(
+            hash(&[xi + dx, yi + dy, zi + dz]),
+            x - dx as f64,
+            y - dy as f64,
+            z - dz as f64,
+        )
+    };
+    let plane = |dz: i64| {
+        lerp(
+            lerp(g(0, 0, dz), g(1, 0, dz), u),
+            lerp(g(0, 1, dz), g(1, 1, dz), u),
+            v,
+        )
+    };
+    lerp(plane(0), plane(1), w)
+}
+
+/// Simplex noise in 2D.
+///
+/// Has fewer directional artifacts than `perlin2` and is cheaper to compute,
+/// since it sums 3 corners of a triangle instead of 4 corners of a square.
+pub(crate) fn simplex2(x: f64, y: f64) -> f64 {
+    let f2 = 0.5 * (3.0_f64.sqrt() - 1.0);
+    let g2 = (3.0 - 3.0_f64.sqrt()) / 6.0;
+
+    // Skew to find the triangle containing the point.
+    let s = (x + y) * f2;
+    let (i, j) = ((x + s).floor(), (y + s).floor());
+    let t = (i + j) * g2;
+    let (x0, y0) = (x - (i - t), y - (j - t));
+    let (i1, j1) = if x0 > y0 { (1, 0) } else { (0, 1) };
+    let corners = [
+        (0, 0, x0, y0),
+        (i1, j1, x0 - i1 as f64 + g2, y0 - j1 as f64 + g2),
+        (1, 1, x0 - 1.0 + 2.0 * g2, y0 - 1.0 + 2.0 * g2),
+    ];
+    let (i, j) = (i as i64, j as i64);
+    let sum: f64 = corners
+        .iter()
+        .map(|&(di, dj, x, y)| {
+            let t = 0.5 - x * x - y * y;
+            if t < 0.0 {
+                0.0
+            } else {
+                let t = t * t;
+                t * t * grad2(hash(&[i + di, j + dj]), x, y)
+            }
+        })
+        .sum();
+    // Scale to fit `[-1, 1]`.
+    70.0 * sum
+}
@@ -0,0 +1,64 @@
+//! Integration of ordinary differential equations.
+
+use RuntimeError;
+
+/// Computes the derivative of the state at a time.
+pub(crate) type Deriv<'a> = dyn FnMut(f64, &[f64]) -> Result<Vec<f64>, RuntimeError> + 'a;
+
+/// The method used for each step.
+#[derive(Clone, Copy)]
+pub(crate) enum Method {
+    Euler,
+    Rk4,
+}
+
+fn add_scaled(x: &[f64], dx: &[f64], h: f64) -> Vec<f64> {
+    x.iter().zip(dx.iter()).map(|(x, dx)| x + h * dx).collect()
+}
+
+fn step(
+    f: &mut Deriv,
+    method: Method,
+    t: f64,
+    x: &[f64],
+    h: f64,
+) -> Result<Vec<f64>, RuntimeError> {
+    let k1 = f(t, x)?;
+    Ok(match method {
+        Method::Euler => add_scaled(x, &k1, h),
+        Method::Rk4 => {
+            let k2 = f(t + h / 2.0, &add_scaled(x, &k1, h / 2.0))?;
+            let k3 = f(t + h / 2.0, &add_scaled(x, &k2, h / 2.0))?;
+            let k4 = f(t + h, &add_scaled(x, &k3, h))?;
+            x.iter()
+                .enumerate()
+                .map(|(i, x)| x + h / 6.0 * (k1[i] + 2.0 * k2[i] + 2.0 * k3[i] + k4[i]))
+                .collect()
+        }
+    })
+}
+
+/// Integrates from `t0` to `t1` with steps of `dt`.
+///
+/// The last step is shortened to end at `t1`.
+/// Returns the initial state followed by the state after each step.
+pub(crate) fn integrate(
+    f: &mut Deriv,
+    method: Method,
+    x0: Vec<f64>,
+    t0: f64,
+    t1: f64,
+    dt: f64,
+) -> Result<Vec<Vec<f64>>, RuntimeError> {
+    // Ignore rounding errors that would add a tiny last step.
+    let steps = ((t1 - t0) / dt - 1e-9).ceil().max(0.0) as usize;
+    let mut res = Vec::with_capacity(steps + 1);
+    res.push(x0);
+    for i in 0..steps {
+        let t = t0 + i as f64 * dt;
+        let h = if i + 1 == steps { t1 - t } else { dt };
+        let x = step(f, method, t, &res[i], h)?;
+        res.push(x);
+    }
+    Ok(res)
+}
@@ -0,0 +1,191 @@
+//! Numerical minimization and root finding.
+//!
+//! The functions take a closure to evaluate,
+//! which returns `Err(msg)` for runtime errors.
+//! Failing to converge is reported as `Ok(Err(msg))`.
+
+/// Options for `nelder_mead`.
+pub(crate) struct Options {
+    /// Stop when the values and the size of the simplex are within tolerance.
+    pub tol: f64,
+    pub max_iter: usize,
+    /// The size of the initial simplex, relative to the start point.
+    pub step: f64,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            tol: 1e-8,
+            max_iter: 1000,
+            step: 0.05,
+        }
+    }
+}
+
+/// Minimizes a function using the Nelder-Mead simplex method.
+///
+/// Returns the point and the value at the point.
+pub(crate) fn nelder_mead(
+    f: &mut dyn FnMut(&[f64]) -> Result<f64, String>,
+    x0: &[f64],
+    opts: &Options,
+) -> Result<Result<(Vec<f64>, f64), String>, String> {
+    let n = x0.len();
+    let mut simplex: Vec<(Vec<f64>, f64)> = Vec::with_capacity(n + 1);
+    simplex.push((x0.to_vec(), f(x0)?));
+    if n == 0 {
+        return Ok(Ok(simplex.swap_remove(0)));
+    }
+    for i in 0..n {
+        let mut x = x0.to_vec();
+        x[i] = if x[i] == 0.0 {
+            opts.step
+        } else {
+            x[i] * (1.0 + opts.step)
+        };
+        let fx = f(&x)?;
+        simplex.push((x, fx));
+    }
+    let point = |a: &[f64], b: &[f64], t: f64| -> Vec<f64> {
+        a.iter()
+            .zip(b.iter())
+            .map(|(a, b)| a + t * (b - a))
+            .collect()
+    };
+    for _ in 0..opts.max_iter {
+        // NaN is sorted last, such that it is replaced first.
+        simplex.sort_by(|a, b| {
+            a.1.partial_cmp(&b.1)
+                .unwrap_or_else(|| a.1.is_nan().cmp(&b.1.is_nan()))
+        });
+        let (best, worst) = (&simplex[0], &simplex[n]);
+        let size = simplex[1..]
+            .iter()
+            .flat_map(|v| v.0.iter().zip(best.0.iter()).map(|(a, b)| (a - b).abs()))
+            .fold(0.0, f64::max);
+        if (worst.1 - best.1).abs() <= opts.tol && size <= opts.tol {
+            return Ok(Ok(simplex.swap_remove(0)));
+        }
+
+        let mut centroid = vec![0.0; n];
+        for v in &simplex[..n] {
+            for (c, x) in centroid.iter_mut().zip(v.0.iter()) {
+                *c += x / n as f64;
+            }
+        }
+        let reflected = point(&centroid, &simplex[n].0, -1.0);
+        let fr = f(&reflected)?;
+        if fr < simplex[0].1 {
+            let expanded = point(&centroid, &simplex[n].0, -2.0);
+            let fe = f(&expanded)?;
+            simplex[n] = if fe < fr {
+                (expanded, fe)
+            } else {
+                (reflected, fr)
+            };
+        } else if fr < simplex[n - 1].1 {
+            simplex[n] = (reflected, fr);
+        } else {
+            // Contract toward the better of the reflected and the worst point.
+            let (t, bound) = if fr < simplex[n].1 {
+                (-0.5, fr)
+            } else {
+                (0.5, simplex[n].1)
+            };
+            let contracted = point(&centroid, &simplex[n].0, t);
+            let fc = f(&contracted)?;
+            if fc < bound {
+                simplex[n] = (contracted, fc);
+            } else {
+                // Shrink toward the best point.
+                for i in 1..=n {
+                    let x = point(&simplex[0].0, &simplex[i].0, 0.5);
+                    let fx = f(&x)?;
+                    simplex[i] = (x, fx);
+                }
+            }
+        }
+    }
+    Ok(Err(format!(
+        "Did not converge after {} iterations",
+        opts.max_iter
+    )))
+}
+
+/// Finds a root between `a` and `b` by bisection.
+///
+/// The function must have opposite signs at the end points.
+pub(crate) fn bisect(
+    f: &mut dyn FnMut(f64) -> Result<f64, String>,
+    mut a: f64,
+    mut b: f64,
+) -> Result<Result<f64, String>, String> {
+    let (mut fa, mut fb) = (f(a)?, f(b)?);
+    if fa == 0.0 {
+        return Ok(Ok(a));
+    }
+    if fb == 0.0 {
+        return Ok(Ok(b));
+    }
+    if fa.is_nan() || fb.is_nan() || (fa < 0.0) == (fb < 0.0) {
+        return Ok(Err(format!(
+            "Expected values of opposite signs at end points, found {} and {}",
+            fa, fb
+        )));
+    }
+    // Each step halves the interval, until it can not be split any further.
+    loop {
+        let mid = a + (b - a) / 2.0;
+        if mid == a || mid == b {
+            return Ok(Ok(if fa.abs() <= fb.abs() { a } else { b }));
+        }
+        let fm = f(mid)?;
+        if fm == 0.0 {
+            return Ok(Ok(mid));
+        }
+        if fm.is_nan() {
+            return Ok(Err(format!("Function returned NaN at {}", mid)));
+        }
+        if (fm < 0.0) == (fa < 0.0) {
+            a = mid;
+            fa = fm;
+        } else {
+            b = mid;
+            fb = fm;
+        }
+    }
+}
+
+/// Finds a root near `x0` using Newton's method.
+///
+/// The closure returns the value of the function and its derivative.
+pub(crate) fn newton(
+    f: &mut dyn FnMut(f64) -> Result<(f64, f64), String>,
+    x0: f64,
+) -> Result<Result<f64, String>, String> {
+    const MAX_ITER: usize = 100;
+
+    let mut x = x0;
+    for _ in 0..MAX_ITER {
+        let (fx, d) = f(x)?;
+        if fx == 0.0 {
+            return Ok(Ok(x));
+        }
+        if d == 0.0 || !d.is_finite() {
+            return Ok(Err(format!("Derivative is {} at {}", d, x)));
+        }
+        let step = fx / d;
+        x -= step;
+        if !x.is_finite() {
+            return Ok(Err("Diverged".into()));
+        }
+        if step.abs() <= 1e-12 * (1.0 + x.abs()) {
+            return Ok(Ok(x));
+        }
+    }
+    Ok(Err(format!(
+        "Did not converge after {} iterations",
+        MAX_ITER
+    )))
+}
@@ -0,0 +1,177 @@
+//! Parallel loops over a range, split into chunks.
+//!
+//! The chunks run on a persistent pool of worker threads, which is shared
+//! between all runtimes. Workers take the next chunk when they are done,
+//! such that fast workers take over work from slow ones.
+//! Each worker gets its own runtime for the duration of a loop.
+//! The calling thread works on chunks too, so nested loops do not deadlock
+//! when all workers are busy.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use Runtime;
+use RuntimeError;
+use Variable;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+lazy_static! {
+    static ref POOL: Pool = Pool::new();
+}
+
+/// Persistent worker threads that run jobs.
+struct Pool {
+    jobs: Mutex<Sender<Job>>,
+    threads: usize,
+}
+
+impl Pool {
+    fn new() -> Pool {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        let (tx, rx) = channel::<Job>();
+        let rx = Arc::new(Mutex::new(rx));
+        for _ in 0..threads {
+            let rx = rx.clone();
+            thread::spawn(move || loop {
+                let job = match rx.lock() {
+                    Ok(rx) => rx.recv(),
+                    Err(_) => return,
+                };
+                match job {
+                    Ok(job) => job(),
+                    Err(_) => return,
+                }
+            });
+        }
+        Pool {
+            jobs: Mutex::new(tx),
+            threads,
+        }
+    }
+
+    fn spawn(&self, job: Job) {
+        let _ = self.jobs.lock().unwrap().send(job);
+    }
+}
+
+/// What to compute per chunk.
+#[derive(Clone, Copy)]
+pub(crate) enum Kind {
+    /// Collects the results of `\(i) -> any` into an array.
+    For,
+    /// Combines the results of `\(i) -> any` with `\(a, b) -> any`.
+    Reduce,
+}
+
+/// Shared state of a loop.
+struct Work {
+    kind: Kind,
+    start: f64,
+    len: usize,
+    chunk: usize,
+    next: AtomicUsize,
+    failed: AtomicBool,
+}
+
+impl Work {
+    fn chunks(&self) -> usize {
+        self.len.div_ceil(self.chunk)
+    }
+
+    /// Runs chunks until there are none left, sending the result of each.
+    fn run(&self, rt: &mut Runtime, fns: &[Variable], done: &Sender<(usize, ChunkResult)>) {
+        loop {
+            let i = self.next.fetch_add(1, Ordering::SeqCst);
+            if i >= self.chunks() || self.failed.load(Ordering::SeqCst) {
+                return;
+            }
+            let res = self.chunk(rt, fns, i);
+            if res.is_err() {
+                self.failed.store(true, Ordering::SeqCst);
+            }
+            let _ = done.send((i, res));
+        }
+    }
+
+    fn chunk(&self, rt: &mut Runtime, fns: &[Variable], i: usize) -> ChunkResult {
+        let end = ((i + 1) * self.chunk).min(self.len);
+        let mut acc: Vec<Variable> = vec![];
+        for k in i * self.chunk..end {
+            let x = Variable::f64(self.start + k as f64);
+            let v = rt.call_closure_ret(&fns[0], &[x])?;
+            let v = v.deep_clone(&rt.stack);
+            match (self.kind, acc.pop()) {
+                (Kind::Reduce, Some(a)) => {
+                    let v = rt.call_closure_ret(&fns[1], &[a, v])?;
+                    acc.push(v.deep_clone(&rt.stack));
+                }
+                (_, a) => {
+                    acc.extend(a);
+                    acc.push(v);
+                }
+            }
+        }
+        Ok(acc)
+    }
+}
+
+type ChunkResult = Result<Vec<Variable>, String>;
+
+/// Runs a loop over `[start, end)` with chunks of `chunk` indices.
+///
+/// The closures must be deep cloned, such that they do not
+/// refer to values on the stack of the caller.
+/// Returns the results in order, or only the reduced value for `Kind::Reduce`,
+/// which is empty when the range is empty.
+pub(crate) fn run(
+    rt: &mut Runtime,
+    kind: Kind,
+    start: f64,
+    end: f64,
+    chunk: usize,
+    fns: Vec<Variable>,
+) -> Result<Vec<Variable>, RuntimeError> {
+    let work = Arc::new(Work {
+        kind,
+        start,
+        len: (end - start).ceil().max(0.0) as usize,
+        chunk: chunk.max(1),
+        next: AtomicUsize::new(0),
+        failed: AtomicBool::new(false),
+    });
+    let chunks = work.chunks();
+    let (tx, rx) = channel();
+    // The calling thread takes one of the chunks.
+    for _ in 0..POOL.threads.min(chunks.saturating_sub(1)) {
+        let work = work.clone();
+        let fns = fns.clone();
+        let tx = tx.clone();
+        let mut new_rt = rt.thread_runtime(vec![]);
+        POOL.spawn(Box::new(move || work.run(&mut new_rt, &fns, &tx)));
+    }
+    work.run(rt, &fns, &tx);
+    drop(tx);
+
+    let mut results: Vec<Option<Vec<Variable>>> = vec![None; chunks];
+    // Receive until all helpers have stopped.
+    for (i, res) in rx.iter() {
+        results[i] = Some(res?);
+    }
+    let mut res = vec![];
+    for chunk in results.into_iter().flatten() {
+        match (kind, res.pop()) {
+            (Kind::Reduce, Some(a)) => {
+                let v = rt.call_closure_ret(&fns[1], &[a, chunk[0].clone()])?;
+                res.push(v.deep_clone(&rt.stack));
+            }
+            (_, a) => {
+                res.extend(a);
+                res.extend(chunk);
+            }
+        }
+    }
+    Ok(res)
+}
@@ -0,0 +1,161 @@
+//! Generators for random maps.
+//!
+//! Grids are rows of cells, where `true` is alive or open.
+
+use rand::Rng;
+
+/// The number of candidates tried around each point in `poisson_disc`.
+const POISSON_CANDIDATES: usize = 30;
+
+/// Rules of a cellular automaton, by the number of alive neighbours.
+pub(crate) struct Rules {
+    /// A dead cell becomes alive with these numbers of alive neighbours.
+    pub birth: [bool; 9],
+    /// An alive cell stays alive with these numbers of alive neighbours.
+    pub survive: [bool; 9],
+    /// Whether cells outside the grid count as alive.
+    pub edge: bool,
+}
+
+/// Runs a cellular automaton on a grid for a number of steps.
+///
+/// Rows can have different lengths.
+pub(crate) fn cellular_automata(
+    mut grid: Vec<Vec<bool>>,
+    rules: &Rules,
+    steps: usize,
+) -> Vec<Vec<bool>> {
+    for _ in 0..steps {
+        let cell = |y: isize, x: isize| -> bool {
+            if y < 0 || x < 0 {
+                return rules.edge;
+            }
+            grid.get(y as usize)
+                .and_then(|row| row.get(x as usize))
+                .cloned()
+                .unwrap_or(rules.edge)
+        };
+        let next = grid
+            .iter()
+            .enumerate()
+            .map(|(y, row)| {
+                let y = y as isize;
+                (0..row.len() as isize)
+                    .map(|x| {
+                        let mut n = 0;
+                        for dy in -1..=1 {
+                            for dx in -1..=1 {
+                                if (dy, dx) != (0, 0) && cell(y + dy, x + dx) {
+                                    n += 1;
+                                }
+                            }
+                        }
+                        if cell(y, x) {
+                            rules.survive[n]
+                        } else {
+                            rules.birth[n]
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        grid = next;
+    }
+    grid
+}
+
+/// Opens cells along a random walk starting at the center of a grid.
+///
+/// The walk moves one cell up, down, left or right per step,
+/// staying inside the grid.
+pub(crate) fn drunkard_walk<R: Rng>(
+    rng: &mut R,
+    width: usize,
+    height: usize,
+    steps: usize,
+) -> Vec<Vec<bool>> {
+    let mut grid = vec![vec![false; width]; height];
+    if width == 0 || height == 0 {
+        return grid;
+    }
+    let (mut x, mut y) = (width / 2, height / 2);
+    grid[y][x] = true;
+    for _ in 0..steps {
+        match rng.gen_range(0, 4) {
+            0 if x + 1 < width => x += 1,
+            1 if x > 0 => x -= 1,
+            2 if y + 1 < height => y += 1,
+            3 if y > 0 => y -= 1,
+            _ => {}
+        }
+        grid[y][x] = true;
+    }
+    grid
+}
+
+/// Places random points in a rectangle, at least `radius` apart,
+/// until there is no room for more points.
+///
+/// Uses Bridson's algorithm.
+pub(crate) fn poisson_disc<R: Rng>(
+    rng: &mut R,
+    width: f64,
+    height: f64,
+    radius: f64,
+) -> Vec<[f64; 2]> {
+    use std::f64::consts::PI;
+
+    if !(width > 0.0 && height > 0.0) {
+        return vec![];
+    }
+    // Each cell contains at most one point.
+    let cell = radius / 2f64.sqrt();
+    let cols = (width / cell).ceil() as usize;
+    let rows = (height / cell).ceil() as usize;
+    let mut cells: Vec<Option<usize>> = vec![None; cols * rows];
+    let index = |p: [f64; 2]| -> (usize, usize) {
+        (
+            ((p[0] / cell) as usize).min(cols - 1),
+            ((p[1] / cell) as usize).min(rows - 1),
+        )
+    };
+
+    let mut points = vec![[rng.gen::<f64>() * width, rng.gen::<f64>() * height]];
+    let (i, j) = index(points[0]);
+    cells[j * cols + i] = Some(0);
+    let mut active = vec![0];
+    while !active.is_empty() {
+        let k = rng.gen_range(0, active.len());
+        let p = points[active[k]];
+        let mut found = false;
+        for _ in 0..POISSON_CANDIDATES {
+            let angle = rng.gen::<f64>() * 2.0 * PI;
+            let dist = radius * (1.0 + rng.gen::<f64>());
+            let q = [p[0] + dist * angle.cos(), p[1] + dist * angle.sin()];
+            if q[0] < 0.0 || q[0] >= width || q[1] < 0.0 || q[1] >= height {
+                continue;
+            }
+            let (i, j) = index(q);
+            let near = (j.saturating_sub(2)..(j + 3).min(rows)).any(|j| {
+                (i.saturating_sub(2)..(i + 3).min(cols)).any(|i| match cells[j * cols + i] {
+                    Some(n) => {
+                        let d = [points[n][0] - q[0], points[n][1] - q[1]];
+                        d[0] * d[0] + d[1] * d[1] < radius * radius
+                    }
+                    None => false,
+                })
+            });
+            if !near {
+                cells[j * cols + i] = Some(points.len());
+                active.push(points.len());
+                points.push(q);
+                found = true;
+                break;
+            }
+        }
+        if !found {
+            active.swap_remove(k);
+        }
+    }
+    points
+}
@@ -0,0 +1,653 @@
+//! SQL-like queries over arrays of objects.
+//!
+//! Supports a subset of `select`:
+//!
+//! ```text
+//! select <items> [where <cond>] [group by <fields>]
+//!     [order by <field> [asc | desc], ...] [limit <n>]
+//! ```
+//!
+//! An item is `*`, a field or an aggregate `count`, `sum`, `mean`, `min` or `max`
+//! of a field, optionally renamed with `as`.
+//! Keywords are case insensitive.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::frame::Agg;
+use Variable;
+
+type Row = HashMap<Arc<String>, Variable>;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Num(f64),
+    Str(String),
+    Sym(&'static str),
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    const SYMBOLS: &[&str] = &[
+        "==", "!=", "<>", "<=", ">=", "=", "<", ">", ",", "(", ")", "*", "-",
+    ];
+    let mut tokens = vec![];
+    let mut chars = text.char_indices().peekable();
+    'next: while let Some(&(i, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_alphabetic() || c == '_' {
+            let mut end = text.len();
+            while let Some(&(j, c)) = chars.peek() {
+                if !(c.is_alphanumeric() || c == '_') {
+                    end = j;
+                    break;
+                }
+                chars.next();
+            }
+            tokens.push(Token::Ident(text[i..end].into()));
+        } else if c.is_ascii_digit() || c == '.' {
+            let mut end = text.len();
+            while let Some(&(j, c)) = chars.peek() {
+                if !(c.is_ascii_digit() || c == '.') {
+                    end = j;
+                    break;
+                }
+                chars.next();
+            }
+            let x = text[i..end]
+                .parse()
+                .map_err(|_| format!("Invalid number `{}`", &text[i..end]))?;
+            tokens.push(Token::Num(x));
+        } else if c == '\'' || c == '"' {
+            // A doubled quote is a quote inside the string.
+            chars.next();
+            let mut s = String::new();
+            loop {
+                match chars.next() {
+                    None => return Err("Expected end of string".into()),
+                    Some((_, ch)) if ch == c => {
+                        if chars.peek().map(|&(_, ch)| ch) == Some(c) {
+                            chars.next();
+                            s.push(c);
+                        } else {
+                            break;
+                        }
+                    }
+                    Some((_, ch)) => s.push(ch),
+                }
+            }
+            tokens.push(Token::Str(s));
+        } else {
+            for sym in SYMBOLS {
+                if text[i..].starts_with(sym) {
+                    for _ in 0..sym.len() {
+                        chars.next();
+                    }
+                    tokens.push(Token::Sym(sym));
+                    continue 'next;
+                }
+            }
+            return Err(format!("Unexpected character `{}`", c));
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Clone, Copy)]
+enum Cmp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+enum Expr {
+    Lit(Variable),
+    Field(Arc<String>),
+    Cmp(Cmp, Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+}
+
+enum Item {
+    All,
+    Field {
+        name: Arc<String>,
+        alias: Arc<String>,
+    },
+    Agg {
+        agg: Agg,
+        field: Option<Arc<String>>,
+        alias: Arc<String>,
+    },
+}
+
+/// A parsed query.
+pub(crate) struct Query {
+    items: Vec<Item>,
+    filter: Option<Expr>,
+    group_by: Vec<Arc<String>>,
+    order_by: Vec<(Arc<String>, bool)>,
+    limit: Option<usize>,
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn keyword(&mut self, kw: &str) -> bool {
+        match self.peek() {
+            Some(Token::Ident(id)) if id.eq_ignore_ascii_case(kw) => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn expect_keyword(&mut self, kw: &str) -> Result<(), String> {
+        if self.keyword(kw) {
+            Ok(())
+        } else {
+            Err(format!("Expected `{}`", kw))
+        }
+    }
+
+    fn sym(&mut self, sym: &str) -> bool {
+        match self.peek() {
+            Some(&Token::Sym(s)) if s == sym => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn expect_sym(&mut self, sym: &str) -> Result<(), String> {
+        if self.sym(sym) {
+            Ok(())
+        } else {
+            Err(format!("Expected `{}`", sym))
+        }
+    }
+
+    fn ident(&mut self) -> Result<Arc<String>, String> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Ident(id)) if !is_keyword(id) => {
+                self.pos += 1;
+                Ok(Arc::new(id.clone()))
+            }
+            _ => Err("Expected field name".into()),
+        }
+    }
+
+    fn item(&mut self) -> Result<Item, String> {
+        if self.sym("*") {
+            return Ok(Item::All);
+        }
+        let name = self.ident()?;
+        let item = match Agg::parse(&name.to_lowercase()) {
+            Some(agg) if self.sym("(") => {
+                let field = if self.sym("*") {
+                    match agg {
+                        Agg::Count => None,
+                        _ => return Err(format!("Expected field name in `{}`", name)),
+                    }
+                } else {
+                    Some(self.ident()?)
+                };
+                self.expect_sym(")")?;
+                // Name the result `<field>_<aggregation>`, like `frame_group_agg`.
+                let alias = match field {
+                    None => "count".into(),
+                    Some(ref field) => format!("{}_{}", field, name.to_lowercase()),
+                };
+                Item::Agg {
+                    agg,
+                    field,
+                    alias: Arc::new(alias),
+                }
+            }
+            _ => Item::Field {
+                alias: name.clone(),
+                name,
+            },
+        };
+        Ok(if self.keyword("as") {
+            let alias = self.ident()?;
+            match item {
+                Item::Field { name, .. } => Item::Field { name, alias },
+                Item::Agg { agg, field, .. } => Item::Agg { agg, field, alias },
+                Item::All => unreachable!(),
+            }
+        } else {
+            item
+        })
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut a = self.and()?;
+        while self.keyword("or") {
+            a = Expr::Or(Box::new(a), Box::new(self.and()?));
+        }
+        Ok(a)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut a = self.not()?;
+        while self.keyword("and") {
+            a = Expr::And(Box::new(a), Box::new(self.not()?));
+        }
+        Ok(a)
+    }
+
+    fn not(&mut self) -> Result<Expr, String> {
+        if self.keyword("not") {
+            Ok(Expr::Not(Box::new(self.not()?)))
+        } else {
+            self.cmp()
+        }
+    }
+
+    fn cmp(&mut self) -> Result<Expr, String> {
+        let a = self.operand()?;
+        let op = match self.peek() {
+            Some(&Token::Sym("=")) | Some(&Token::Sym("==")) => Cmp::Eq,
+            Some(&Token::Sym("!=")) | Some(&Token::Sym("<>")) => Cmp::Ne,
+            Some(&Token::Sym("<")) => Cmp::Lt,
+            Some(&Token::Sym("<=")) => Cmp::Le,
+            Some(&Token::Sym(">")) => Cmp::Gt,
+            Some(&Token::Sym(">=")) => Cmp::Ge,
+            _ => return Ok(a),
+        };
+        self.pos += 1;
+        Ok(Expr::Cmp(op, Box::new(a), Box::new(self.operand()?)))
+    }
+
+    fn operand(&mut self) -> Result<Expr, String> {
+        if self.sym("(") {
+            let e = self.or()?;
+            self.expect_sym(")")?;
+            return Ok(e);
+        }
+        let neg = self.sym("-");
+        let e = match self.tokens.get(self.pos).cloned() {
+            Some(Token::Num(x)) => Expr::Lit(Variable::f64(if neg { -x } else { x })),
+            _ if neg => return Err("Expected number after `-`".into()),
+            Some(Token::Str(s)) => Expr::Lit(Variable::Str(Arc::new(s))),
+            Some(Token::Ident(ref id)) if id.eq_ignore_ascii_case("true") => {
+                Expr::Lit(Variable::bool(true))
+            }
+            Some(Token::Ident(ref id)) if id.eq_ignore_ascii_case("false") => {
+                Expr::Lit(Variable::bool(false))
+            }
+            _ => return Ok(Expr::Field(self.ident()?)),
+        };
+        self.pos += 1;
+        Ok(e)
+    }
+}
+
+fn is_keyword(id: &str) -> bool {
+    [
+        "select", "where", "group", "by", "order", "asc", "desc", "limit", "and", "or", "not",
+        "as", "true", "false",
+    ]
+    .iter()
+    .any(|kw| id.eq_ignore_ascii_case(kw))
+}
+
+impl Query {
+    pub(crate) fn parse(text: &str) -> Result<Query, String> {
+        let mut p = Parser {
+            tokens: tokenize(text)?,
+            pos: 0,
+        };
+        p.expect_keyword("select")?;
+        let mut items = vec![p.item()?];
+        while p.sym(",") {
+            items.push(p.item()?);
+        }
+        let filter = if p.keyword("where") {
+            Some(p.or()?)
+        } else {
+            None
+        };
+        let mut group_by = vec![];
+        if p.keyword("group") {
+            p.expect_keyword("by")?;
+            group_by.push(p.ident()?);
+            while p.sym(",") {
+                group_by.push(p.ident()?);
+            }
+        }
+        let mut order_by = vec![];
+        if p.keyword("order") {
+            p.expect_keyword("by")?;
+            loop {
+                let name = p.ident()?;
+                let ascending = !p.keyword("desc");
+                if ascending {
+                    p.keyword("asc");
+                }
+                order_by.push((name, ascending));
+                if !p.sym(",") {
+                    break;
+                }
+            }
+        }
+        let limit = if p.keyword("limit") {
+            match p.peek() {
+                Some(&Token::Num(n)) if n >= 0.0 && n.fract() == 0.0 => {
+                    p.pos += 1;
+                    Some(n as usize)
+                }
+                _ => return Err("Expected non-negative integer after `limit`".into()),
+            }
+        } else {
+            None
+        };
+        if p.pos < p.tokens.len() {
+            return Err(format!("Unexpected `{}`", describe(&p.tokens[p.pos])));
+        }
+        let query = Query {
+            items,
+            filter,
+            group_by,
+            order_by,
+            limit,
+        };
+        if query.aggregated() {
+            for it in &query.items {
+                match *it {
+                    Item::All => return Err("Can not select `*` with aggregates".into()),
+                    Item::Field { ref name, .. } if !query.group_by.contains(name) => {
+                        return Err(format!(
+                            "Expected `{}` in `group by` or in an aggregate",
+                            name
+                        ))
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(query)
+    }
+
+    fn aggregated(&self) -> bool {
+        !self.group_by.is_empty() || self.items.iter().any(|it| matches!(it, Item::Agg { .. }))
+    }
+
+    /// Runs the query on rows, returning an array of objects.
+    pub(crate) fn run(&self, rows: &[&Row]) -> Result<Variable, String> {
+        let mut matching = vec![];
+        for &row in rows {
+            let keep = match self.filter {
+                None => true,
+                Some(ref filter) => truth(filter, row)? == Some(true),
+            };
+            if keep {
+                matching.push(row);
+            }
+        }
+        // Pair each output with the row it came from, to order by fields that are not selected.
+        let mut res: Vec<(Option<&Row>, Row)> = if self.aggregated() {
+            self.groups(&matching)?
+                .into_iter()
+                .map(|group| self.aggregate(&group).map(|out| (None, out)))
+                .collect::<Result<_, _>>()?
+        } else {
+            matching
+                .into_iter()
+                .map(|row| (Some(row), self.project(row)))
+                .collect()
+        };
+        if !self.order_by.is_empty() {
+            res.sort_by(|a, b| {
+                for &(ref name, ascending) in &self.order_by {
+                    let ord = cmp_vars(sort_key(a, name), sort_key(b, name));
+                    if ord != Ordering::Equal {
+                        return if ascending { ord } else { ord.reverse() };
+                    }
+                }
+                Ordering::Equal
+            });
+        }
+        if let Some(n) = self.limit {
+            res.truncate(n);
+        }
+        Ok(Variable::Array(Arc::new(
+            res.into_iter()
+                .map(|(_, out)| Variable::Object(Arc::new(out)))
+                .collect(),
+        )))
+    }
+
+    fn project(&self, row: &Row) -> Row {
+        let mut out = HashMap::new();
+        for it in &self.items {
+            match *it {
+                Item::All => out.extend(row.iter().map(|(k, v)| (k.clone(), v.clone()))),
+                Item::Field {
+                    ref name,
+                    ref alias,
+                } => {
+                    if let Some(v) = row.get(name) {
+                        out.insert(alias.clone(), v.clone());
+                    }
+                }
+                Item::Agg { .. } => {}
+            }
+        }
+        out
+    }
+
+    /// Groups rows by the `group by` fields, in order of first appearance.
+    ///
+    /// Without `group by`, all rows form one group, even when there are none.
+    fn groups<'a>(&self, rows: &[&'a Row]) -> Result<Vec<Vec<&'a Row>>, String> {
+        if self.group_by.is_empty() {
+            return Ok(vec![rows.to_vec()]);
+        }
+        let mut index: HashMap<Vec<Option<Key>>, usize> = HashMap::new();
+        let mut groups: Vec<Vec<&Row>> = vec![];
+        for &row in rows {
+            let key = self
+                .group_by
+                .iter()
+                .map(|name| row.get(name).map(|v| key(name, v)).transpose())
+                .collect::<Result<Vec<_>, _>>()?;
+            let g = *index.entry(key).or_insert_with(|| {
+                groups.push(vec![]);
+                groups.len() - 1
+            });
+            groups[g].push(row);
+        }
+        Ok(groups)
+    }
+
+    fn aggregate(&self, group: &[&Row]) -> Result<Row, String> {
+        let mut out = HashMap::new();
+        for it in &self.items {
+            match *it {
+                Item::All => {}
+                Item::Field {
+                    ref name,
+                    ref alias,
+                } => {
+                    if let Some(v) = group.first().and_then(|row| row.get(name)) {
+                        out.insert(alias.clone(), v.clone());
+                    }
+                }
+                Item::Agg {
+                    field: None,
+                    ref alias,
+                    ..
+                } => {
+                    out.insert(alias.clone(), Variable::f64(group.len() as f64));
+                }
+                Item::Agg {
+                    agg,
+                    field: Some(ref field),
+                    ref alias,
+                } => {
+                    // Rows without the field are skipped.
+                    let mut xs = vec![];
+                    for v in group.iter().filter_map(|row| row.get(field)) {
+                        match *v {
+                            Variable::F64(x, _) => xs.push(x),
+                            _ if matches!(agg, Agg::Count) => xs.push(0.0),
+                            _ => return Err(format!("Expected numbers in `{}`", field)),
+                        }
+                    }
+                    // There is no minimum, maximum or mean of nothing.
+                    if !xs.is_empty() || matches!(agg, Agg::Count | Agg::Sum) {
+                        out.insert(alias.clone(), Variable::f64(agg.apply(&xs)));
+                    }
+                }
+            }
+        }
+        Ok(out)
+    }
+}
+
+fn describe(token: &Token) -> String {
+    match *token {
+        Token::Ident(ref id) => id.clone(),
+        Token::Num(x) => x.to_string(),
+        Token::Str(ref s) => format!("'{}'", s),
+        Token::Sym(s) => s.into(),
+    }
+}
+
+#[derive(PartialEq, Eq, Hash)]
+enum Key {
+    F64(u64),
+    Str(Arc<String>),
+    Bool(bool),
+}
+
+fn key(name: &str, v: &Variable) -> Result<Key, String> {
+    Ok(match *v {
+        // Make `-0` and `0` the same group.
+        Variable::F64(x, _) => Key::F64((x + 0.0).to_bits()),
+        Variable::Str(ref s) => Key::Str(s.clone()),
+        Variable::Bool(b, _) => Key::Bool(b),
+        _ => {
+            return Err(format!(
+                "Can not group by `{}` of type `{}`",
+                name,
+                v.typeof_var()
+            ))
+        }
+    })
+}
+
+/// Evaluates an expression, where `None` means a field is missing.
+fn eval(expr: &Expr, row: &Row) -> Result<Option<Variable>, String> {
+    Ok(match *expr {
+        Expr::Lit(ref v) => Some(v.clone()),
+        Expr::Field(ref name) => row.get(name).cloned(),
+        _ => truth(expr, row)?.map(Variable::bool),
+    })
+}
+
+/// Evaluates a condition, where `None` means unknown because a field is missing.
+fn truth(expr: &Expr, row: &Row) -> Result<Option<bool>, String> {
+    Ok(match *expr {
+        Expr::Cmp(op, ref a, ref b) => match (eval(a, row)?, eval(b, row)?) {
+            (Some(a), Some(b)) => Some(compare(op, &a, &b)?),
+            _ => None,
+        },
+        Expr::And(ref a, ref b) => match (truth(a, row)?, truth(b, row)?) {
+            (Some(false), _) | (_, Some(false)) => Some(false),
+            (Some(true), Some(true)) => Some(true),
+            _ => None,
+        },
+        Expr::Or(ref a, ref b) => match (truth(a, row)?, truth(b, row)?) {
+            (Some(true), _) | (_, Some(true)) => Some(true),
+            (Some(false), Some(false)) => Some(false),
+            _ => None,
+        },
+        Expr::Not(ref a) => truth(a, row)?.map(|b| !b),
+        Expr::Lit(_) | Expr::Field(_) => match eval(expr, row)? {
+            Some(Variable::Bool(b, _)) => Some(b),
+            None => None,
+            Some(v) => return Err(format!("Expected `bool`, found `{}`", v.typeof_var())),
+        },
+    })
+}
+
+fn compare(op: Cmp, a: &Variable, b: &Variable) -> Result<bool, String> {
+    let ord = match (a, b) {
+        (&Variable::F64(a, _), &Variable::F64(b, _)) => a.partial_cmp(&b),
+        (Variable::Str(a), Variable::Str(b)) => Some(a.cmp(b)),
+        (&Variable::Bool(a, _), &Variable::Bool(b, _)) => match op {
+            Cmp::Eq | Cmp::Ne => Some(a.cmp(&b)),
+            _ => return Err("Can not order `bool`".into()),
+        },
+        _ => match op {
+            Cmp::Eq => return Ok(false),
+            Cmp::Ne => return Ok(true),
+            _ => {
+                return Err(format!(
+                    "Can not compare `{}` with `{}`",
+                    a.typeof_var(),
+                    b.typeof_var()
+                ))
+            }
+        },
+    };
+    Ok(match (op, ord) {
+        // NaN is not equal to anything.
+        (Cmp::Ne, None) => true,
+        (_, None) => false,
+        (Cmp::Eq, Some(ord)) => ord == Ordering::Equal,
+        (Cmp::Ne, Some(ord)) => ord != Ordering::Equal,
+        (Cmp::Lt, Some(ord)) => ord == Ordering::Less,
+        (Cmp::Le, Some(ord)) => ord != Ordering::Greater,
+        (Cmp::Gt, Some(ord)) => ord == Ordering::Greater,
+        (Cmp::Ge, Some(ord)) => ord != Ordering::Less,
+    })
+}
+
+/// Looks up a field to order by in the output, or else in the row it came from.
+fn sort_key<'a>(
+    &(src, ref out): &'a (Option<&'a Row>, Row),
+    name: &Arc<String>,
+) -> Option<&'a Variable> {
+    out.get(name).or_else(|| src.and_then(|src| src.get(name)))
+}
+
+/// Orders values for `order by`, with missing values first,
+/// then booleans, numbers and strings.
+fn cmp_vars(a: Option<&Variable>, b: Option<&Variable>) -> Ordering {
+    fn rank(v: Option<&Variable>) -> u8 {
+        match v {
+            None => 0,
+            Some(Variable::Bool(..)) => 1,
+            Some(Variable::F64(..)) => 2,
+            Some(Variable::Str(_)) => 3,
+            Some(_) => 4,
+        }
+    }
+    match (a, b) {
+        (Some(Variable::Bool(a, _)), Some(Variable::Bool(b, _))) => a.cmp(b),
+        (Some(Variable::F64(a, _)), Some(Variable::F64(b, _))) => {
+            a.partial_cmp(b).unwrap_or(Ordering::Equal)
+        }
+        (Some(Variable::Str(a)), Some(Variable::Str(b))) => a.cmp(b),
+        _ => rank(a).cmp(&rank(b)),
+    }
+}
@@ -0,0 +1,179 @@
+//! Calls to functions of a module in another runtime over TCP.
+//!
+//! Each message is a 4 byte big-endian length followed by MessagePack.
+//! A request is `[name, args]`,
+//! and the response is `[true, value]` or `[false, error message]`.
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+
+use super::lifetimechk;
+use super::msgpack;
+use FnIndex;
+use Module;
+use Runtime;
+use RuntimeError;
+use Variable;
+
+/// The maximum length of a message, to avoid allocating for garbage.
+const MAX_MESSAGE_LEN: usize = 1 << 26;
+
+/// A connection to a server, used with `call` and `call_ret`.
+pub(crate) struct Proxy {
+    stream: TcpStream,
+}
+
+/// Connects to a server.
+pub(crate) fn connect(addr: &str) -> io::Result<Proxy> {
+    let stream = TcpStream::connect(addr)?;
+    stream.set_nodelay(true)?;
+    Ok(Proxy { stream })
+}
+
+/// Serves the functions declared in a module on a background thread.
+///
+/// Each connection is handled by its own thread and runtime.
+/// Returns the local address, which has the port when binding to port 0.
+pub(crate) fn serve(addr: &str, module: Arc<Module>) -> io::Result<String> {
+    let listener = TcpListener::bind(addr)?;
+    let local_addr = listener.local_addr()?.to_string();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(x) => x,
+                Err(_) => continue,
+            };
+            let module = module.clone();
+            thread::spawn(move || {
+                // The connection is closed on errors.
+                let _ = handle(stream, &module);
+            });
+        }
+    });
+    Ok(local_addr)
+}
+
+/// Calls a function on the server.
+///
+/// Errors on the server and connection errors are returned as `Err`.
+pub(crate) fn call(
+    rt: &Runtime,
+    proxy: &mut Proxy,
+    name: &Arc<String>,
+    args: &Arc<Vec<Variable>>,
+) -> Result<Variable, RuntimeError> {
+    let req = Variable::Array(Arc::new(vec![
+        Variable::Str(name.clone()),
+        Variable::Array(args.clone()),
+    ]));
+    let mut data = vec![];
+    msgpack::write(rt, &req, &mut data)?;
+    let res = write_message(&mut proxy.stream, &data)
+        .and_then(|()| read_message(&mut proxy.stream))
+        .map_err(|err| format!("RPC call to `{}` failed:\n{}", name, err))?;
+    match res {
+        Some(res) => match msgpack::read(&res)? {
+            Variable::Array(ref arr) if arr.len() == 2 => match (&arr[0], &arr[1]) {
+                (&Variable::Bool(true, _), v) => Ok(v.clone()),
+                (&Variable::Bool(false, _), &Variable::Str(ref msg)) => {
+                    Err(format!("RPC call to `{}` failed:\n{}", name, msg).into())
+                }
+                _ => Err("Expected RPC response".into()),
+            },
+            _ => Err("Expected RPC response".into()),
+        },
+        None => Err(format!("RPC call to `{}` failed:\nConnection closed", name).into()),
+    }
+}
+
+/// Answers requests until the connection is closed.
+fn handle(mut stream: TcpStream, module: &Arc<Module>) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let mut rt = Runtime::new();
+    while let Some(data) = read_message(&mut stream)? {
+        let res = match msgpack::read(&data).and_then(|req| Ok(respond(&mut rt, module, req)?)) {
+            Ok(v) => vec![Variable::bool(true), v],
+            Err(err) => vec![Variable::bool(false), Variable::Str(Arc::new(err))],
+        };
+        let res = Variable::Array(Arc::new(res));
+        let mut data = vec![];
+        if let Err(err) = msgpack::write(&rt, &res, &mut data) {
+            data.clear();
+            let err = Variable::Array(Arc::new(vec![
+                Variable::bool(false),
+                Variable::Str(Arc::new(err.into())),
+            ]));
+            msgpack::write(&rt, &err, &mut data).expect("Expected valid MessagePack");
+        }
+        write_message(&mut stream, &data)?;
+    }
+    Ok(())
+}
+
+/// Calls the function of a request.
+fn respond(
+    rt: &mut Runtime,
+    module: &Arc<Module>,
+    req: Variable,
+) -> Result<Variable, RuntimeError> {
+    let (name, args) = match req {
+        Variable::Array(ref arr) if arr.len() == 2 => match (&arr[0], &arr[1]) {
+            (&Variable::Str(ref name), &Variable::Array(ref args)) => (name.clone(), args.clone()),
+            _ => return Err("Expected RPC request `[name, args]`".into()),
+        },
+        _ => return Err("Expected RPC request `[name, args]`".into()),
+    };
+    let f = match module.find_function(&name, 0) {
+        FnIndex::Loaded(i) => &module.functions[i as usize],
+        _ => return Err(format!("Could not find function `{}`", name).into()),
+    };
+    if f.args.len() != args.len() {
+        return Err(format!(
+            "Expected `{}` arguments, found `{}`",
+            f.args.len(),
+            args.len()
+        )
+        .into());
+    }
+    lifetimechk::check(f, &args)?;
+    if f.returns() {
+        rt.call_str_ret(&name, &args, module)
+    } else {
+        rt.call_str(&name, &args, module)?;
+        Ok(Variable::Option(None))
+    }
+}
+
+/// Reads a message, or `None` when the connection is closed.
+fn read_message(r: &mut TcpStream) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0; 4];
+    match r.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_MESSAGE_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Message of {} bytes is too long", len),
+        ));
+    }
+    let mut data = vec![0; len];
+    r.read_exact(&mut data)?;
+    Ok(Some(data))
+}
+
+fn write_message(w: &mut TcpStream, data: &[u8]) -> io::Result<()> {
+    if data.len() > MAX_MESSAGE_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Message of {} bytes is too long", data.len()),
+        ));
+    }
+    w.write_all(&(data.len() as u32).to_be_bytes())?;
+    w.write_all(data)?;
+    w.flush()
+}
@@ -0,0 +1,12 @@
+//! SHA-256 hashing, using the `sha2` crate.
+
+pub(crate) use sha2::{Digest, Sha256};
+
+/// Returns a hash as lowercase hexadecimal digits.
+pub(crate) fn hex(hash: &[u8]) -> String {
+    let mut s = String::with_capacity(2 * hash.len());
+    for b in hash {
+        s.push_str(&format!("{:02x}", b));
+    }
+    s
+}
@@ -0,0 +1,271 @@
+//! Linear programming and finite-domain constraint solving.
+
+use Runtime;
+use RuntimeError;
+use Variable;
+
+const EPS: f64 = 1e-9;
+
+/// The relation of a linear constraint to its right hand side.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Relation {
+    Le,
+    Ge,
+    Eq,
+}
+
+/// A linear constraint `coeffs * x <relation> rhs`.
+pub(crate) struct Constraint {
+    pub coeffs: Vec<f64>,
+    pub relation: Relation,
+    pub rhs: f64,
+}
+
+struct Tableau {
+    /// Constraint rows, with the right hand side last.
+    rows: Vec<Vec<f64>>,
+    /// Objective row of reduced costs, with the objective value last.
+    obj: Vec<f64>,
+    basis: Vec<usize>,
+}
+
+impl Tableau {
+    fn pivot(&mut self, r: usize, c: usize) {
+        let p = self.rows[r][c];
+        for x in &mut self.rows[r] {
+            *x /= p;
+        }
+        let pivot_row = self.rows[r].clone();
+        for (i, row) in self.rows.iter_mut().enumerate() {
+            let f = row[c];
+            if i != r && f != 0.0 {
+                for (x, &y) in row.iter_mut().zip(pivot_row.iter()) {
+                    *x -= f * y;
+                }
+            }
+        }
+        let f = self.obj[c];
+        if f != 0.0 {
+            for (x, &y) in self.obj.iter_mut().zip(pivot_row.iter()) {
+                *x -= f * y;
+            }
+        }
+        self.basis[r] = c;
+    }
+
+    /// Maximizes the objective using columns below `cols`.
+    ///
+    /// Uses Bland's rule, which never cycles.
+    /// Returns `false` if the objective is unbounded.
+    fn maximize(&mut self, cols: usize) -> bool {
+        loop {
+            let c = match (0..cols).find(|&j| self.obj[j] < -EPS) {
+                Some(c) => c,
+                None => return true,
+            };
+            let last = self.obj.len() - 1;
+            let mut best: Option<(f64, usize, usize)> = None;
+            for (i, row) in self.rows.iter().enumerate() {
+                if row[c] > EPS {
+                    let ratio = row[last] / row[c];
+                    let better = match best {
+                        None => true,
+                        Some((r, _, b)) => {
+                            ratio < r - EPS || (ratio < r + EPS && self.basis[i] < b)
+                        }
+                    };
+                    if better {
+                        best = Some((ratio, i, self.basis[i]));
+                    }
+                }
+            }
+            match best {
+                Some((_, r, _)) => self.pivot(r, c),
+                None => return false,
+            }
+        }
+    }
+}
+
+/// Maximizes `objective * x` subject to constraints, where `x >= 0`.
+///
+/// Returns the values of the variables and of the objective.
+pub(crate) fn simplex(
+    constraints: &[Constraint],
+    objective: &[f64],
+) -> Result<(Vec<f64>, f64), String> {
+    let n = objective.len();
+    let m = constraints.len();
+    // Slack or surplus variables come after the variables,
+    // then artificial variables.
+    let slacks = constraints
+        .iter()
+        .filter(|c| c.relation != Relation::Eq)
+        .count();
+    let artificial = n + slacks;
+    let cols = artificial + m;
+    let mut t = Tableau {
+        rows: Vec::with_capacity(m),
+        obj: vec![0.0; cols + 1],
+        basis: Vec::with_capacity(m),
+    };
+    let mut slack = n;
+    for (i, c) in constraints.iter().enumerate() {
+        // Make the right hand side non-negative.
+        let sign = if c.rhs < 0.0 { -1.0 } else { 1.0 };
+        let mut row = vec![0.0; cols + 1];
+        for (x, &a) in row.iter_mut().zip(c.coeffs.iter()) {
+            *x = sign * a;
+        }
+        row[cols] = sign * c.rhs;
+        let relation = match (c.relation, sign < 0.0) {
+            (Relation::Le, true) => Relation::Ge,
+            (Relation::Ge, true) => Relation::Le,
+            (r, _) => r,
+        };
+        match relation {
+            Relation::Le => {
+                row[slack] = 1.0;
+                t.basis.push(slack);
+            }
+            Relation::Ge => {
+                row[slack] = -1.0;
+                row[artificial + i] = 1.0;
+                t.basis.push(artificial + i);
+            }
+            Relation::Eq => {
+                row[artificial + i] = 1.0;
+                t.basis.push(artificial + i);
+            }
+        }
+        if c.relation != Relation::Eq {
+            slack += 1;
+        }
+        t.rows.push(row);
+    }
+
+    // Phase 1: Minimize the sum of artificial variables.
+    for j in artificial..cols {
+        t.obj[j] = 1.0;
+    }
+    for i in 0..m {
+        if t.basis[i] >= artificial {
+            for j in 0..=cols {
+                t.obj[j] -= t.rows[i][j];
+            }
+        }
+    }
+    t.maximize(cols);
+    if t.obj[cols] < -EPS {
+        return Err("No solution satisfies the constraints".into());
+    }
+    // Remove artificial variables from the basis where possible.
+    for i in 0..m {
+        if t.basis[i] >= artificial {
+            if let Some(c) = (0..artificial).find(|&j| t.rows[i][j].abs() > EPS) {
+                t.pivot(i, c);
+            }
+        }
+    }
+
+    // Phase 2: Maximize the objective.
+    t.obj = vec![0.0; cols + 1];
+    for (x, &c) in t.obj.iter_mut().zip(objective.iter()) {
+        *x = -c;
+    }
+    for i in 0..m {
+        let f = t.obj[t.basis[i]];
+        if f != 0.0 {
+            for j in 0..=cols {
+                t.obj[j] -= f * t.rows[i][j];
+            }
+        }
+    }
+    if !t.maximize(artificial) {
+        return Err("The objective is unbounded".into());
+    }
+    let mut x = vec![0.0; n];
+    for (i, &b) in t.basis.iter().enumerate() {
+        if b < n {
+            x[b] = t.rows[i][cols];
+        }
+    }
+    Ok((x, t.obj[cols]))
+}
+
+/// A constraint on some variables of a CSP, checked by a closure.
+pub(crate) struct Check {
+    pub vars: Vec<usize>,
+    pub closure: Variable,
+}
+
+/// Finds values from the domains that pass all checks, using backtracking.
+///
+/// Variables with smaller domains are assigned first,
+/// and each check runs as soon as its variables are assigned.
+pub(crate) fn csp(
+    rt: &mut Runtime,
+    domains: &[Vec<Variable>],
+    checks: &[Check],
+) -> Result<Option<Vec<Variable>>, RuntimeError> {
+    let mut order: Vec<usize> = (0..domains.len()).collect();
+    order.sort_by_key(|&i| domains[i].len());
+    let mut rank = vec![0; domains.len()];
+    for (k, &i) in order.iter().enumerate() {
+        rank[i] = k;
+    }
+    // The checks to run after assigning the variable at each step.
+    let mut due: Vec<Vec<&Check>> = vec![vec![]; order.len()];
+    for check in checks {
+        match check.vars.iter().map(|&v| rank[v]).max() {
+            Some(k) => due[k].push(check),
+            None => {
+                if !run_check(rt, check, &[])? {
+                    return Ok(None);
+                }
+            }
+        }
+    }
+    let mut values: Vec<Option<Variable>> = vec![None; domains.len()];
+    let mut choice = vec![0; order.len()];
+    let mut k = 0;
+    while k < order.len() {
+        let var = order[k];
+        if choice[k] >= domains[var].len() {
+            // Backtrack.
+            values[var] = None;
+            choice[k] = 0;
+            if k == 0 {
+                return Ok(None);
+            }
+            k -= 1;
+            continue;
+        }
+        values[var] = Some(domains[var][choice[k]].clone());
+        choice[k] += 1;
+        let mut ok = true;
+        for check in &due[k] {
+            let args: Vec<Variable> = check
+                .vars
+                .iter()
+                .map(|&v| values[v].clone().unwrap())
+                .collect();
+            if !run_check(rt, check, &args)? {
+                ok = false;
+                break;
+            }
+        }
+        if ok {
+            k += 1;
+        }
+    }
+    Ok(Some(values.into_iter().map(|v| v.unwrap()).collect()))
+}
+
+fn run_check(rt: &mut Runtime, check: &Check, args: &[Variable]) -> Result<bool, RuntimeError> {
+    let v = rt.call_closure_ret(&check.closure, args)?;
+    match *rt.resolve(&v) {
+        Variable::Bool(b, _) => Ok(b),
+        ref x => Err(rt.expected(x, "bool")),
+    }
+}
@@ -0,0 +1,61 @@
+//! Catmull-Rom splines through 4D points.
+
+/// The number of line segments per curve segment when computing the length.
+const LENGTH_STEPS: usize = 32;
+
+/// A curve through points, stored in a Rust object.
+pub(crate) struct Spline {
+    points: Vec<[f64; 4]>,
+    length: f64,
+}
+
+impl Spline {
+    /// Creates a spline, which requires at least one point.
+    pub(crate) fn new(points: Vec<[f64; 4]>) -> Spline {
+        let mut spline = Spline {
+            points,
+            length: 0.0,
+        };
+        let n = (spline.points.len() - 1) * LENGTH_STEPS;
+        let mut prev = spline.eval(0.0);
+        for i in 1..=n {
+            let p = spline.eval(i as f64 / n as f64);
+            spline.length += (0..4).map(|j| (p[j] - prev[j]).powi(2)).sum::<f64>().sqrt();
+            prev = p;
+        }
+        spline
+    }
+
+    /// Returns the point at `t`, from 0 at the first point to 1 at the last point.
+    ///
+    /// Each segment between two points takes an equal part of `t`.
+    pub(crate) fn eval(&self, t: f64) -> [f64; 4] {
+        let segments = self.points.len() - 1;
+        if segments == 0 || t.is_nan() {
+            return self.points[0];
+        }
+        let s = t.clamp(0.0, 1.0) * segments as f64;
+        let i = (s.floor() as usize).min(segments - 1);
+        let u = s - i as f64;
+        // The end points are repeated to get tangents at the ends.
+        let p0 = self.points[i.saturating_sub(1)];
+        let p1 = self.points[i];
+        let p2 = self.points[i + 1];
+        let p3 = self.points[(i + 2).min(segments)];
+        let (u2, u3) = (u * u, u * u * u);
+        let mut res = [0.0; 4];
+        for (j, r) in res.iter_mut().enumerate() {
+            *r = 0.5
+                * (2.0 * p1[j]
+                    + (p2[j] - p0[j]) * u
+                    + (2.0 * p0[j] - 5.0 * p1[j] + 4.0 * p2[j] - p3[j]) * u2
+                    + (3.0 * p1[j] - p0[j] - 3.0 * p2[j] + p3[j]) * u3);
+        }
+        res
+    }
+
+    /// Returns the approximate length of the curve.
+    pub(crate) fn length(&self) -> f64 {
+        self.length
+    }
+}
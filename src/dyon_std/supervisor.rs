@@ -0,0 +1,92 @@
+//! Supervisors that restart failing child threads.
+//!
+//! Each child runs a closure `\() -> any` on its own thread.
+//! When the closure fails, returns an `err` or panics,
+//! the failure is reported and only that child is restarted (one-for-one),
+//! until it has been restarted `max_retries` times.
+
+use std::collections::HashMap;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use Runtime;
+use Variable;
+
+/// Stores the restart policy and the channel for failure reports.
+pub(crate) struct Supervisor {
+    max_retries: u32,
+    reports: Sender<Variable>,
+    failures: Arc<Mutex<Receiver<Variable>>>,
+}
+
+impl Supervisor {
+    /// Creates a supervisor that restarts a child at most `max_retries` times.
+    pub(crate) fn new(max_retries: u32) -> Supervisor {
+        let (tx, rx) = channel();
+        Supervisor {
+            max_retries,
+            reports: tx,
+            failures: Arc::new(Mutex::new(rx)),
+        }
+    }
+
+    /// Returns the channel that receives failure reports.
+    pub(crate) fn failures(&self) -> Variable {
+        Variable::In(self.failures.clone())
+    }
+
+    /// Starts a child thread running a task closure.
+    ///
+    /// The closure must be deep cloned, such that it does not
+    /// refer to values on the stack of the caller.
+    pub(crate) fn spawn(&self, rt: &Runtime, name: Arc<String>, task: Variable) {
+        let template = rt.thread_runtime(vec![]);
+        let max_retries = self.max_retries;
+        let reports = self.reports.clone();
+        thread::spawn(move || run(template, name, task, max_retries, reports));
+    }
+}
+
+fn run(
+    template: Runtime,
+    name: Arc<String>,
+    task: Variable,
+    max_retries: u32,
+    reports: Sender<Variable>,
+) {
+    let mut restarts = 0;
+    loop {
+        // Restart with a clean runtime.
+        let mut rt = template.thread_runtime(vec![]);
+        let res = catch_unwind(AssertUnwindSafe(|| rt.call_closure_ret(&task, &[])));
+        let err = match res {
+            Ok(Ok(Variable::Result(Err(err)))) => match err.message {
+                Variable::Str(ref s) => (**s).clone(),
+                ref x => format!("{:?}", x),
+            },
+            Ok(Ok(_)) => return,
+            Ok(Err(err)) => err.into(),
+            Err(_) => "Thread panicked".into(),
+        };
+        let stopped = restarts >= max_retries;
+        let _ = reports.send(report(&name, err, restarts, stopped));
+        if stopped {
+            return;
+        }
+        restarts += 1;
+    }
+}
+
+fn report(name: &Arc<String>, err: String, restarts: u32, stopped: bool) -> Variable {
+    let mut obj = HashMap::new();
+    obj.insert(Arc::new("name".into()), Variable::Str(name.clone()));
+    obj.insert(Arc::new("error".into()), Variable::Str(Arc::new(err)));
+    obj.insert(
+        Arc::new("restarts".into()),
+        Variable::f64(f64::from(restarts)),
+    );
+    obj.insert(Arc::new("stopped".into()), Variable::bool(stopped));
+    Variable::Object(Arc::new(obj))
+}
@@ -0,0 +1,473 @@
+//! Patches for replicating changes of a state, e.g. a game world over a network.
+//!
+//! A patch starts with a version byte, followed by an operation unless the states are equal.
+//! Objects and arrays are patched per key and per item,
+//! and other values are replaced when they change.
+//! Lengths and indices are `u32` and numbers are `f64`, little-endian.
+
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::sync::Arc;
+
+use Runtime;
+use RuntimeError;
+use Scalar;
+use Variable;
+
+/// The version of the patch format.
+const VERSION: u8 = 1;
+
+/// The maximum nesting of arrays and objects when reading.
+const MAX_DEPTH: usize = 256;
+
+// Operations.
+const SET: u8 = 0;
+const OBJECT: u8 = 1;
+const ARRAY: u8 = 2;
+const REMOVE: u8 = 3;
+
+// Values.
+const NONE: u8 = 0;
+const SOME: u8 = 1;
+const FALSE: u8 = 2;
+const TRUE: u8 = 3;
+const F64: u8 = 4;
+const STR: u8 = 5;
+const VEC4: u8 = 6;
+const BYTES: u8 = 7;
+const ARR: u8 = 8;
+const OBJ: u8 = 9;
+
+/// How much of a value is of interest.
+#[derive(Clone, Copy, PartialEq)]
+enum Interest {
+    All,
+    /// Some keys of an object.
+    Keys,
+    Nothing,
+}
+
+/// Returns the interest in the value at a path of keys joined by `.`.
+fn interest(prefixes: Option<&[Arc<String>]>, path: &str) -> Interest {
+    let prefixes = match prefixes {
+        None => return Interest::All,
+        Some(x) => x,
+    };
+    let mut res = Interest::Nothing;
+    for prefix in prefixes {
+        if path.starts_with(&***prefix) {
+            return Interest::All;
+        }
+        if path.is_empty() || prefix.starts_with(path) && prefix[path.len()..].starts_with('.') {
+            res = Interest::Keys;
+        }
+    }
+    res
+}
+
+/// Encodes the changes from `prev` to `next`.
+///
+/// When `prefixes` is set, only changes to keys where the path starts with a prefix
+/// are included. Values of interest that are not objects are included as a whole.
+pub(crate) fn encode(
+    rt: &Runtime,
+    prev: &Variable,
+    next: &Variable,
+    prefixes: Option<&[Arc<String>]>,
+) -> Result<Vec<u8>, RuntimeError> {
+    let mut out = vec![VERSION];
+    diff(rt, prev, next, prefixes, "", &mut out)?;
+    Ok(out)
+}
+
+/// Writes the operation that changes `prev` to `next`.
+///
+/// Returns `false` without writing when there is no change of interest.
+fn diff(
+    rt: &Runtime,
+    prev: &Variable,
+    next: &Variable,
+    prefixes: Option<&[Arc<String>]>,
+    path: &str,
+    out: &mut Vec<u8>,
+) -> Result<bool, RuntimeError> {
+    let filter = interest(prefixes, path);
+    if filter == Interest::Nothing {
+        return Ok(false);
+    }
+    let start = out.len();
+    match (rt.resolve(prev), rt.resolve(next)) {
+        (Variable::Object(a), Variable::Object(b)) => {
+            out.push(OBJECT);
+            let count_pos = out.len();
+            write_u32(out, 0);
+            let mut count = 0;
+            let child_prefixes = if filter == Interest::All {
+                None
+            } else {
+                prefixes
+            };
+            let child_path = |key: &str| {
+                if path.is_empty() {
+                    key.to_string()
+                } else {
+                    format!("{}.{}", path, key)
+                }
+            };
+            for (key, v) in b.iter() {
+                let item = out.len();
+                write_str(out, key)?;
+                let changed = match a.get(key) {
+                    Some(prev) => diff(rt, prev, v, child_prefixes, &child_path(key), out)?,
+                    None => match interest(child_prefixes, &child_path(key)) {
+                        Interest::Nothing => false,
+                        Interest::Keys if !is_object(rt, v) => false,
+                        _ => {
+                            out.push(SET);
+                            write_filtered(rt, v, child_prefixes, &child_path(key), out)?;
+                            true
+                        }
+                    },
+                };
+                if changed {
+                    count += 1;
+                } else {
+                    out.truncate(item);
+                }
+            }
+            for key in a.keys() {
+                if !b.contains_key(key)
+                    && interest(child_prefixes, &child_path(key)) != Interest::Nothing
+                {
+                    write_str(out, key)?;
+                    out.push(REMOVE);
+                    count += 1;
+                }
+            }
+            if count == 0 {
+                out.truncate(start);
+                return Ok(false);
+            }
+            out[count_pos..count_pos + 4].copy_from_slice(&(count as u32).to_le_bytes());
+            Ok(true)
+        }
+        (_, &Variable::Object(_)) if filter == Interest::Keys => {
+            out.push(SET);
+            write_filtered(rt, next, prefixes, path, out)?;
+            Ok(true)
+        }
+        // Only keys of objects can be of interest.
+        _ if filter == Interest::Keys => Ok(false),
+        (Variable::Array(a), Variable::Array(b)) => {
+            out.push(ARRAY);
+            write_u32(out, len(b.len())?);
+            let count_pos = out.len();
+            write_u32(out, 0);
+            let mut count = 0;
+            for (i, v) in b.iter().enumerate() {
+                let item = out.len();
+                write_u32(out, i as u32);
+                let changed = match a.get(i) {
+                    Some(prev) => diff(rt, prev, v, None, path, out)?,
+                    None => {
+                        out.push(SET);
+                        write_value(rt, v, out, 0)?;
+                        true
+                    }
+                };
+                if changed {
+                    count += 1;
+                } else {
+                    out.truncate(item);
+                }
+            }
+            if count == 0 && a.len() == b.len() {
+                out.truncate(start);
+                return Ok(false);
+            }
+            out[count_pos..count_pos + 4].copy_from_slice(&(count as u32).to_le_bytes());
+            Ok(true)
+        }
+        (a, b) => {
+            if same(rt, a, b) {
+                return Ok(false);
+            }
+            out.push(SET);
+            write_value(rt, b, out, 0)?;
+            Ok(true)
+        }
+    }
+}
+
+/// Returns `true` if two values that are not both objects or arrays are equal.
+fn same(rt: &Runtime, a: &Variable, b: &Variable) -> bool {
+    match (rt.resolve(a), rt.resolve(b)) {
+        (&Variable::F64(a, _), &Variable::F64(b, _)) => a == b,
+        (&Variable::Bool(a, _), &Variable::Bool(b, _)) => a == b,
+        (Variable::Str(a), Variable::Str(b)) => a == b,
+        (Variable::Bytes(a), Variable::Bytes(b)) => a == b,
+        (&Variable::Vec4(a), &Variable::Vec4(b)) => a == b,
+        (&Variable::Option(None), &Variable::Option(None)) => true,
+        (&Variable::Option(Some(ref a)), &Variable::Option(Some(ref b))) => {
+            let mut out = vec![];
+            // Options are rare in state, so compare through `diff`.
+            !diff(rt, a, b, None, "", &mut out).unwrap_or(true)
+        }
+        _ => false,
+    }
+}
+
+fn is_object(rt: &Runtime, v: &Variable) -> bool {
+    matches!(*rt.resolve(v), Variable::Object(_))
+}
+
+/// Writes an object with only the keys of interest.
+fn write_filtered(
+    rt: &Runtime,
+    v: &Variable,
+    prefixes: Option<&[Arc<String>]>,
+    path: &str,
+    out: &mut Vec<u8>,
+) -> Result<(), RuntimeError> {
+    let obj = match *rt.resolve(v) {
+        Variable::Object(ref obj) if interest(prefixes, path) == Interest::Keys => obj,
+        ref x => return write_value(rt, x, out, 0),
+    };
+    out.push(OBJ);
+    let count_pos = out.len();
+    write_u32(out, 0);
+    let mut count = 0;
+    for (key, v) in obj.iter() {
+        let child_path = if path.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", path, key)
+        };
+        match interest(prefixes, &child_path) {
+            Interest::Nothing => continue,
+            Interest::Keys if !is_object(rt, v) => continue,
+            _ => {}
+        }
+        write_str(out, key)?;
+        write_filtered(rt, v, prefixes, &child_path, out)?;
+        count += 1;
+    }
+    out[count_pos..count_pos + 4].copy_from_slice(&(count as u32).to_le_bytes());
+    Ok(())
+}
+
+fn len(n: usize) -> Result<u32, String> {
+    if n > u32::MAX as usize {
+        Err("Length is too large for a patch".into())
+    } else {
+        Ok(n as u32)
+    }
+}
+
+fn write_u32(out: &mut Vec<u8>, n: u32) {
+    out.extend_from_slice(&n.to_le_bytes());
+}
+
+fn write_str(out: &mut Vec<u8>, s: &str) -> Result<(), String> {
+    write_u32(out, len(s.len())?);
+    out.extend_from_slice(s.as_bytes());
+    Ok(())
+}
+
+fn write_value(
+    rt: &Runtime,
+    v: &Variable,
+    out: &mut Vec<u8>,
+    depth: usize,
+) -> Result<(), RuntimeError> {
+    if depth > MAX_DEPTH {
+        return Err(format!("State is nested deeper than {}", MAX_DEPTH).into());
+    }
+    match *rt.resolve(v) {
+        Variable::F64(x, _) => {
+            out.push(F64);
+            out.extend_from_slice(&x.to_le_bytes());
+        }
+        Variable::Bool(b, _) => out.push(if b { TRUE } else { FALSE }),
+        Variable::Str(ref s) => {
+            out.push(STR);
+            write_str(out, s)?;
+        }
+        Variable::Bytes(ref b) => {
+            out.push(BYTES);
+            write_u32(out, len(b.len())?);
+            out.extend_from_slice(b);
+        }
+        Variable::Vec4(v) => {
+            out.push(VEC4);
+            for x in &v {
+                out.extend_from_slice(&x.to_le_bytes());
+            }
+        }
+        Variable::Array(ref arr) => {
+            out.push(ARR);
+            write_u32(out, len(arr.len())?);
+            for it in arr.iter() {
+                write_value(rt, it, out, depth + 1)?;
+            }
+        }
+        Variable::Object(ref obj) => {
+            out.push(OBJ);
+            write_u32(out, len(obj.len())?);
+            for (k, v) in obj.iter() {
+                write_str(out, k)?;
+                write_value(rt, v, out, depth + 1)?;
+            }
+        }
+        Variable::Option(None) => out.push(NONE),
+        Variable::Option(Some(ref v)) => {
+            out.push(SOME);
+            write_value(rt, v, out, depth + 1)?;
+        }
+        ref x => return Err(format!("Can not synchronize `{}`", x.typeof_var()).into()),
+    }
+    Ok(())
+}
+
+/// Applies a patch to a state, returning the new state.
+pub(crate) fn apply(state: Variable, patch: &[u8]) -> Result<Variable, String> {
+    let mut rd = patch;
+    match read_u8(&mut rd) {
+        Ok(VERSION) => {}
+        Ok(x) => return Err(format!("Unsupported patch version `{}`", x)),
+        Err(err) => return Err(err),
+    }
+    if rd.is_empty() {
+        return Ok(state);
+    }
+    let mut state = state;
+    apply_op(&mut state, &mut rd, 0)?;
+    if !rd.is_empty() {
+        return Err(format!(
+            "Unexpected data after patch at byte {}",
+            patch.len() - rd.len()
+        ));
+    }
+    Ok(state)
+}
+
+fn apply_op(state: &mut Variable, rd: &mut &[u8], depth: usize) -> Result<(), String> {
+    if depth > MAX_DEPTH {
+        return Err(format!("Patch is nested deeper than {}", MAX_DEPTH));
+    }
+    match read_u8(rd)? {
+        SET => *state = read_value(rd, depth)?,
+        OBJECT => {
+            let obj = match *state {
+                Variable::Object(ref mut obj) => Arc::make_mut(obj),
+                _ => return Err("Expected object in state".into()),
+            };
+            for _ in 0..read_u32(rd)? {
+                let key = Arc::new(read_str(rd)?);
+                if rd.first() == Some(&REMOVE) {
+                    *rd = &rd[1..];
+                    obj.remove(&key);
+                    continue;
+                }
+                let v = obj.entry(key).or_insert(Variable::Option(None));
+                apply_op(v, rd, depth + 1)?;
+            }
+        }
+        ARRAY => {
+            let arr = match *state {
+                Variable::Array(ref mut arr) => Arc::make_mut(arr),
+                _ => return Err("Expected array in state".into()),
+            };
+            let n = read_u32(rd)? as usize;
+            if n > arr.len() && n - arr.len() > rd.len() {
+                return Err("Unexpected end of patch".into());
+            }
+            arr.resize(n, Variable::Option(None));
+            for _ in 0..read_u32(rd)? {
+                let i = read_u32(rd)? as usize;
+                match arr.get_mut(i) {
+                    Some(v) => apply_op(v, rd, depth + 1)?,
+                    None => return Err(format!("Index `{}` is out of bounds in patch", i)),
+                }
+            }
+        }
+        x => return Err(format!("Unknown patch operation `{}`", x)),
+    }
+    Ok(())
+}
+
+// Arrays and objects are shared by `Arc`, even if variables are not `Sync`.
+#[allow(clippy::arc_with_non_send_sync)]
+fn read_value(rd: &mut &[u8], depth: usize) -> Result<Variable, String> {
+    if depth > MAX_DEPTH {
+        return Err(format!("Patch is nested deeper than {}", MAX_DEPTH));
+    }
+    Ok(match read_u8(rd)? {
+        NONE => Variable::Option(None),
+        SOME => Variable::Option(Some(Box::new(read_value(rd, depth + 1)?))),
+        FALSE => Variable::bool(false),
+        TRUE => Variable::bool(true),
+        F64 => {
+            let mut b = [0; 8];
+            b.copy_from_slice(read_bytes(rd, 8)?);
+            Variable::f64(f64::from_le_bytes(b))
+        }
+        STR => Variable::Str(Arc::new(read_str(rd)?)),
+        VEC4 => {
+            let mut v = [0.0; 4];
+            for x in &mut v {
+                let mut b = [0; size_of::<Scalar>()];
+                b.copy_from_slice(read_bytes(rd, size_of::<Scalar>())?);
+                *x = Scalar::from_le_bytes(b);
+            }
+            Variable::Vec4(v)
+        }
+        BYTES => {
+            let n = read_u32(rd)? as usize;
+            Variable::Bytes(Arc::new(read_bytes(rd, n)?.to_vec()))
+        }
+        ARR => {
+            let n = read_u32(rd)? as usize;
+            // Each item takes at least one byte.
+            let mut arr = Vec::with_capacity(n.min(rd.len()));
+            for _ in 0..n {
+                arr.push(read_value(rd, depth + 1)?);
+            }
+            Variable::Array(Arc::new(arr))
+        }
+        OBJ => {
+            let n = read_u32(rd)? as usize;
+            let mut obj = HashMap::with_capacity(n.min(rd.len()));
+            for _ in 0..n {
+                let key = Arc::new(read_str(rd)?);
+                obj.insert(key, read_value(rd, depth + 1)?);
+            }
+            Variable::Object(Arc::new(obj))
+        }
+        x => return Err(format!("Unknown value type `{}` in patch", x)),
+    })
+}
+
+fn read_bytes<'a>(rd: &mut &'a [u8], n: usize) -> Result<&'a [u8], String> {
+    if n > rd.len() {
+        return Err("Unexpected end of patch".into());
+    }
+    let (bytes, rest) = rd.split_at(n);
+    *rd = rest;
+    Ok(bytes)
+}
+
+fn read_u8(rd: &mut &[u8]) -> Result<u8, String> {
+    Ok(read_bytes(rd, 1)?[0])
+}
+
+fn read_u32(rd: &mut &[u8]) -> Result<u32, String> {
+    let mut b = [0; 4];
+    b.copy_from_slice(read_bytes(rd, 4)?);
+    Ok(u32::from_le_bytes(b))
+}
+
+fn read_str(rd: &mut &[u8]) -> Result<String, String> {
+    let n = read_u32(rd)? as usize;
+    String::from_utf8(read_bytes(rd, n)?.to_vec()).map_err(|_| "Expected UTF-8 in patch".into())
+}
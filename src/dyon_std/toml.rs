@@ -0,0 +1,37 @@
+//! Reads TOML into variables.
+//!
+//! Tables become objects and arrays of tables become arrays of objects.
+//! Integers become `f64`, and dates and times are kept as strings.
+
+use std::sync::Arc;
+
+use toml::{Table, Value};
+
+use Variable;
+
+fn table(t: Table) -> Variable {
+    Variable::Object(Arc::new(
+        t.into_iter()
+            .map(|(key, v)| (Arc::new(key), value(v)))
+            .collect(),
+    ))
+}
+
+fn value(v: Value) -> Variable {
+    match v {
+        Value::String(s) => Variable::Str(Arc::new(s)),
+        Value::Integer(x) => Variable::f64(x as f64),
+        Value::Float(x) => Variable::f64(x),
+        Value::Boolean(b) => Variable::bool(b),
+        Value::Datetime(dt) => Variable::Str(Arc::new(dt.to_string())),
+        Value::Array(arr) => Variable::Array(Arc::new(arr.into_iter().map(value).collect())),
+        Value::Table(t) => table(t),
+    }
+}
+
+/// Reads TOML.
+pub(crate) fn parse(text: &str) -> Result<Variable, String> {
+    text.parse::<Table>()
+        .map(table)
+        .map_err(|err| err.to_string().trim_end().into())
+}
@@ -0,0 +1,46 @@
+//! Reads YAML into variables.
+//!
+//! Supports one document, with anchors, aliases and merge keys.
+//! Null becomes `none()` and integers become `f64`.
+//! Keys that are numbers or bools become strings.
+//! Tags and complex keys are not supported.
+
+use std::sync::Arc;
+
+use serde_yaml::Value;
+
+use Variable;
+
+fn key(k: Value) -> Result<Arc<String>, String> {
+    match k {
+        Value::String(s) => Ok(Arc::new(s)),
+        Value::Number(x) => Ok(Arc::new(x.to_string())),
+        Value::Bool(b) => Ok(Arc::new(b.to_string())),
+        _ => Err("Complex keys are not supported".into()),
+    }
+}
+
+fn value(v: Value) -> Result<Variable, String> {
+    Ok(match v {
+        Value::Null => Variable::Option(None),
+        Value::Bool(b) => Variable::bool(b),
+        Value::Number(x) => Variable::f64(x.as_f64().unwrap_or(f64::NAN)),
+        Value::String(s) => Variable::Str(Arc::new(s)),
+        Value::Sequence(arr) => Variable::Array(Arc::new(
+            arr.into_iter().map(value).collect::<Result<_, _>>()?,
+        )),
+        Value::Mapping(map) => Variable::Object(Arc::new(
+            map.into_iter()
+                .map(|(k, v)| Ok((key(k)?, value(v)?)))
+                .collect::<Result<_, String>>()?,
+        )),
+        Value::Tagged(tagged) => return Err(format!("Tag `{}` is not supported", tagged.tag)),
+    })
+}
+
+/// Reads a YAML document.
+pub(crate) fn parse(text: &str) -> Result<Variable, String> {
+    let mut v: Value = serde_yaml::from_str(text).map_err(|err| err.to_string())?;
+    v.apply_merge().map_err(|err| err.to_string())?;
+    value(v)
+}
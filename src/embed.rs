@@ -5,11 +5,17 @@ use std::sync::Arc;
 use Error;
 use Object;
 use Runtime;
+use RuntimeError;
 use RustObject;
+use Scalar;
 use Variable;
 
 /// Gets value of object field.
-pub fn obj_field<T: PopVariable>(rt: &Runtime, obj: &Object, name: &str) -> Result<T, String> {
+pub fn obj_field<T: PopVariable>(
+    rt: &Runtime,
+    obj: &Object,
+    name: &str,
+) -> Result<T, RuntimeError> {
     let var = obj
         .get(&Arc::new(name.into()))
         .ok_or_else(|| format!("Object has no key `{}`", name))?;
@@ -20,7 +26,7 @@ pub fn obj_field<T: PopVariable>(rt: &Runtime, obj: &Object, name: &str) -> Resu
 pub trait PopVariable: Sized {
     /// Converts variable to self.
     /// The variable should be resolved before call.
-    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, String>;
+    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, RuntimeError>;
 }
 
 /// Implemented by types that can be pushed to the runtime stack.
@@ -32,27 +38,27 @@ pub trait PushVariable {
 /// Implemented by types that can be converted to and from vec4.
 pub trait ConvertVec4: Sized {
     /// Converts vec4 to self.
-    fn from(val: [f32; 4]) -> Self;
+    fn from(val: [Scalar; 4]) -> Self;
     /// Converts from self to mat4.
-    fn to(&self) -> [f32; 4];
+    fn to(&self) -> [Scalar; 4];
 }
 
 /// Convert from and to mat4.
 pub trait ConvertMat4: Sized {
     /// Converts mat4 to self.
-    fn from(val: [[f32; 4]; 4]) -> Self;
+    fn from(val: [[Scalar; 4]; 4]) -> Self;
     /// Converts from self to mat4.
-    fn to(&self) -> [[f32; 4]; 4];
+    fn to(&self) -> [[Scalar; 4]; 4];
 }
 
 impl PopVariable for Variable {
-    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, String> {
+    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, RuntimeError> {
         Ok(var.deep_clone(&rt.stack))
     }
 }
 
 impl PopVariable for RustObject {
-    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, String> {
+    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, RuntimeError> {
         if let Variable::RustObject(ref robj) = *var {
             Ok(robj.clone())
         } else {
@@ -62,7 +68,7 @@ impl PopVariable for RustObject {
 }
 
 impl PopVariable for bool {
-    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, String> {
+    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, RuntimeError> {
         if let Variable::Bool(b, _) = *var {
             Ok(b)
         } else {
@@ -72,7 +78,7 @@ impl PopVariable for bool {
 }
 
 impl PopVariable for String {
-    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, String> {
+    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, RuntimeError> {
         if let Variable::Str(ref s) = *var {
             Ok((&**s).clone())
         } else {
@@ -82,7 +88,7 @@ impl PopVariable for String {
 }
 
 impl PopVariable for Arc<String> {
-    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, String> {
+    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, RuntimeError> {
         if let Variable::Str(ref s) = *var {
             Ok(s.clone())
         } else {
@@ -92,7 +98,7 @@ impl PopVariable for Arc<String> {
 }
 
 impl PopVariable for u32 {
-    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, String> {
+    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, RuntimeError> {
         if let Variable::F64(n, _) = *var {
             Ok(n as u32)
         } else {
@@ -102,7 +108,7 @@ impl PopVariable for u32 {
 }
 
 impl PopVariable for usize {
-    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, String> {
+    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, RuntimeError> {
         if let Variable::F64(n, _) = *var {
             Ok(n as usize)
         } else {
@@ -112,7 +118,7 @@ impl PopVariable for usize {
 }
 
 impl PopVariable for f32 {
-    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, String> {
+    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, RuntimeError> {
         if let Variable::F64(n, _) = *var {
             Ok(n as f32)
         } else {
@@ -122,7 +128,7 @@ impl PopVariable for f32 {
 }
 
 impl PopVariable for f64 {
-    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, String> {
+    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, RuntimeError> {
         if let Variable::F64(n, _) = *var {
             Ok(n)
         } else {
@@ -132,7 +138,7 @@ impl PopVariable for f64 {
 }
 
 impl<T: PopVariable> PopVariable for Option<T> {
-    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, String> {
+    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, RuntimeError> {
         if let Variable::Option(ref s) = *var {
             Ok(match *s {
                 Some(ref s) => Some(PopVariable::pop_var(rt, rt.resolve(s))?),
@@ -145,7 +151,7 @@ impl<T: PopVariable> PopVariable for Option<T> {
 }
 
 impl<T: PopVariable, U: PopVariable> PopVariable for Result<T, U> {
-    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, String> {
+    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, RuntimeError> {
         if let Variable::Result(ref s) = *var {
             Ok(match *s {
                 Ok(ref s) => Ok(PopVariable::pop_var(rt, rt.resolve(s))?),
@@ -158,7 +164,7 @@ impl<T: PopVariable, U: PopVariable> PopVariable for Result<T, U> {
 }
 
 impl<T: PopVariable> PopVariable for [T; 2] {
-    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, String> {
+    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, RuntimeError> {
         if let Variable::Array(ref arr) = *var {
             Ok([
                 PopVariable::pop_var(rt, rt.resolve(&arr[0]))?,
@@ -171,7 +177,7 @@ impl<T: PopVariable> PopVariable for [T; 2] {
 }
 
 impl<T: PopVariable> PopVariable for [T; 3] {
-    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, String> {
+    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, RuntimeError> {
         if let Variable::Array(ref arr) = *var {
             Ok([
                 PopVariable::pop_var(rt, rt.resolve(&arr[0]))?,
@@ -185,7 +191,7 @@ impl<T: PopVariable> PopVariable for [T; 3] {
 }
 
 impl<T: PopVariable> PopVariable for [T; 4] {
-    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, String> {
+    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, RuntimeError> {
         if let Variable::Array(ref arr) = *var {
             Ok([
                 PopVariable::pop_var(rt, rt.resolve(&arr[0]))?,
@@ -200,7 +206,7 @@ impl<T: PopVariable> PopVariable for [T; 4] {
 }
 
 impl<T: PopVariable, U: PopVariable> PopVariable for (T, U) {
-    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, String> {
+    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, RuntimeError> {
         if let Variable::Array(ref arr) = *var {
             Ok((
                 PopVariable::pop_var(rt, rt.resolve(&arr[0]))?,
@@ -213,7 +219,7 @@ impl<T: PopVariable, U: PopVariable> PopVariable for (T, U) {
 }
 
 impl<T: PopVariable, U: PopVariable, V: PopVariable> PopVariable for (T, U, V) {
-    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, String> {
+    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, RuntimeError> {
         if let Variable::Array(ref arr) = *var {
             Ok((
                 PopVariable::pop_var(rt, rt.resolve(&arr[0]))?,
@@ -227,7 +233,7 @@ impl<T: PopVariable, U: PopVariable, V: PopVariable> PopVariable for (T, U, V) {
 }
 
 impl<T: PopVariable, U: PopVariable, V: PopVariable, W: PopVariable> PopVariable for (T, U, V, W) {
-    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, String> {
+    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, RuntimeError> {
         if let Variable::Array(ref arr) = *var {
             Ok((
                 PopVariable::pop_var(rt, rt.resolve(&arr[0]))?,
@@ -242,7 +248,7 @@ impl<T: PopVariable, U: PopVariable, V: PopVariable, W: PopVariable> PopVariable
 }
 
 impl<T: PopVariable> PopVariable for Vec<T> {
-    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, String> {
+    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, RuntimeError> {
         if let Variable::Array(ref arr) = *var {
             let mut res = Vec::with_capacity(arr.len());
             for it in &**arr {
@@ -327,6 +333,7 @@ impl<T: PushVariable, U: PushVariable> PushVariable for Result<T, U> {
             Box::new(Error {
                 message: e.push_var(),
                 trace: vec![],
+                kind: None,
             })
         }))
     }
@@ -394,53 +401,64 @@ impl<T: PushVariable> PushVariable for Vec<T> {
     }
 }
 
+// Casting is only needed with the `f64_vec4` feature.
+#[allow(clippy::unnecessary_cast)]
 impl ConvertVec4 for [f32; 2] {
-    fn from(val: [f32; 4]) -> Self {
-        [val[0], val[1]]
+    fn from(val: [Scalar; 4]) -> Self {
+        [val[0] as f32, val[1] as f32]
     }
-    fn to(&self) -> [f32; 4] {
-        [self[0], self[1], 0.0, 0.0]
+    fn to(&self) -> [Scalar; 4] {
+        [self[0] as Scalar, self[1] as Scalar, 0.0, 0.0]
     }
 }
 
+// Casting is only needed with the `f64_vec4` feature.
+#[allow(clippy::unnecessary_cast)]
 impl ConvertVec4 for [f32; 3] {
-    fn from(val: [f32; 4]) -> Self {
-        [val[0], val[1], val[2]]
+    fn from(val: [Scalar; 4]) -> Self {
+        [val[0] as f32, val[1] as f32, val[2] as f32]
     }
-    fn to(&self) -> [f32; 4] {
-        [self[0], self[1], self[2], 0.0]
+    fn to(&self) -> [Scalar; 4] {
+        [self[0] as Scalar, self[1] as Scalar, self[2] as Scalar, 0.0]
     }
 }
 
+// Casting is only needed with the `f64_vec4` feature.
+#[allow(clippy::unnecessary_cast)]
 impl ConvertVec4 for [f32; 4] {
-    fn from(val: [f32; 4]) -> Self {
-        val
+    fn from(val: [Scalar; 4]) -> Self {
+        [val[0] as f32, val[1] as f32, val[2] as f32, val[3] as f32]
     }
-    fn to(&self) -> [f32; 4] {
-        *self
+    fn to(&self) -> [Scalar; 4] {
+        [
+            self[0] as Scalar,
+            self[1] as Scalar,
+            self[2] as Scalar,
+            self[3] as Scalar,
+        ]
     }
 }
 
 impl ConvertVec4 for [f64; 2] {
-    fn from(val: [f32; 4]) -> Self {
+    fn from(val: [Scalar; 4]) -> Self {
         [f64::from(val[0]), f64::from(val[1])]
     }
-    fn to(&self) -> [f32; 4] {
-        [self[0] as f32, self[1] as f32, 0.0, 0.0]
+    fn to(&self) -> [Scalar; 4] {
+        [self[0] as Scalar, self[1] as Scalar, 0.0, 0.0]
     }
 }
 
 impl ConvertVec4 for [f64; 3] {
-    fn from(val: [f32; 4]) -> Self {
+    fn from(val: [Scalar; 4]) -> Self {
         [f64::from(val[0]), f64::from(val[1]), f64::from(val[2])]
     }
-    fn to(&self) -> [f32; 4] {
-        [self[0] as f32, self[1] as f32, self[2] as f32, 0.0]
+    fn to(&self) -> [Scalar; 4] {
+        [self[0] as Scalar, self[1] as Scalar, self[2] as Scalar, 0.0]
     }
 }
 
 impl ConvertVec4 for [f64; 4] {
-    fn from(val: [f32; 4]) -> Self {
+    fn from(val: [Scalar; 4]) -> Self {
         [
             f64::from(val[0]),
             f64::from(val[1]),
@@ -448,21 +466,46 @@ impl ConvertVec4 for [f64; 4] {
             f64::from(val[3]),
         ]
     }
-    fn to(&self) -> [f32; 4] {
+    fn to(&self) -> [Scalar; 4] {
         [
-            self[0] as f32,
-            self[1] as f32,
-            self[2] as f32,
-            self[3] as f32,
+            self[0] as Scalar,
+            self[1] as Scalar,
+            self[2] as Scalar,
+            self[3] as Scalar,
         ]
     }
 }
 
 impl ConvertMat4 for [[f32; 4]; 4] {
-    fn from(val: [[f32; 4]; 4]) -> Self {
-        val
+    fn from(val: [[Scalar; 4]; 4]) -> Self {
+        let mut res = [[0.0; 4]; 4];
+        for (col, val) in res.iter_mut().zip(&val) {
+            *col = ConvertVec4::from(*val);
+        }
+        res
+    }
+    fn to(&self) -> [[Scalar; 4]; 4] {
+        let mut res = [[0.0; 4]; 4];
+        for (col, val) in res.iter_mut().zip(self) {
+            *col = val.to();
+        }
+        res
     }
-    fn to(&self) -> [[f32; 4]; 4] {
-        *self
+}
+
+impl ConvertMat4 for [[f64; 4]; 4] {
+    fn from(val: [[Scalar; 4]; 4]) -> Self {
+        let mut res = [[0.0; 4]; 4];
+        for (col, val) in res.iter_mut().zip(&val) {
+            *col = ConvertVec4::from(*val);
+        }
+        res
+    }
+    fn to(&self) -> [[Scalar; 4]; 4] {
+        let mut res = [[0.0; 4]; 4];
+        for (col, val) in res.iter_mut().zip(self) {
+            *col = val.to();
+        }
+        res
     }
 }
@@ -0,0 +1,139 @@
+//! Re-indentation of Dyon source.
+//!
+//! This is not a pretty printer: the syntax is only used to reject invalid source,
+//! and the output keeps the tokens, line breaks and spacing within lines as written.
+
+use piston_meta::parse_errstr;
+use std::sync::Arc;
+
+use version_pragma;
+use SYNTAX_RULES;
+
+/// The state of the scanner at the end of a line.
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Code,
+    Str,
+    /// Inside nested multi-line comments.
+    Comment(usize),
+}
+
+/// Re-indents Dyon source.
+///
+/// The source is checked against the syntax, but the output is not printed from the AST.
+/// Only leading and trailing whitespace and empty lines change:
+/// Lines are indented by 4 spaces per open bracket, at most one level per line,
+/// trailing whitespace is removed and consecutive empty lines are merged.
+/// Lines that start inside a multi-line string or comment are not indented.
+pub fn format_source(source: &str) -> Result<String, String> {
+    let syntax_rules = SYNTAX_RULES.as_ref().map_err(|err| err.clone())?;
+    let d = version_pragma("source", Arc::new(source.into()))?;
+    let mut data = vec![];
+    parse_errstr(syntax_rules, &d, &mut data)?;
+
+    let mut out = String::with_capacity(source.len());
+    // The indentation of the line where each open bracket is.
+    let mut open: Vec<usize> = vec![];
+    let mut state = State::Code;
+    let mut empty_lines = 0;
+    let mut after_open = false;
+    let mut continued = false;
+    for line in source.lines() {
+        let start = state;
+        let mut text = if start == State::Code {
+            line.trim_start()
+        } else {
+            line
+        };
+        let mut brackets = vec![];
+        state = scan(text, start, &mut brackets);
+        if state != State::Str {
+            text = text.trim_end();
+        }
+
+        if text.is_empty() && start != State::Str {
+            empty_lines += 1;
+            continue;
+        }
+        let closing = text
+            .chars()
+            .take_while(|c| [')', ']', '}'].contains(c))
+            .count();
+        // Empty lines are removed at the start and end of blocks.
+        if empty_lines > 0 && !after_open && closing == 0 && !out.is_empty() {
+            out.push('\n');
+        }
+        empty_lines = 0;
+        let mut indent = if start == State::Code && closing > 0 && closing <= open.len() {
+            open[open.len() - closing]
+        } else {
+            open.last().map(|&i| i + 1).unwrap_or(0)
+        };
+        // The body of a function declared with `=` continues on the next line.
+        if continued && closing == 0 {
+            indent += 1;
+        }
+        if start == State::Code {
+            for _ in 0..indent {
+                out.push_str("    ");
+            }
+        }
+        for bracket in brackets {
+            if bracket {
+                open.push(indent);
+            } else {
+                open.pop();
+            }
+        }
+        out.push_str(text);
+        out.push('\n');
+        after_open = state == State::Code && text.ends_with(['(', '[', '{']);
+        continued = state == State::Code && text.ends_with('=');
+    }
+    Ok(out)
+}
+
+/// Scans a line, pushing `true` for open and `false` for close brackets.
+///
+/// Returns the state at the end of the line.
+fn scan(line: &str, mut state: State, brackets: &mut Vec<bool>) -> State {
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match state {
+            State::Str => match c {
+                '\\' => {
+                    chars.next();
+                }
+                '"' => state = State::Code,
+                _ => {}
+            },
+            State::Comment(depth) => match (c, chars.peek()) {
+                ('*', Some(&'/')) => {
+                    chars.next();
+                    state = if depth == 1 {
+                        State::Code
+                    } else {
+                        State::Comment(depth - 1)
+                    };
+                }
+                ('/', Some(&'*')) => {
+                    chars.next();
+                    state = State::Comment(depth + 1);
+                }
+                _ => {}
+            },
+            State::Code => match (c, chars.peek()) {
+                ('/', Some(&'/')) => break,
+                ('/', Some(&'*')) => {
+                    chars.next();
+                    state = State::Comment(1);
+                }
+                ('"', _) => state = State::Str,
+                ('(', _) | ('[', _) | ('{', _) => brackets.push(true),
+                (')', _) | (']', _) | ('}', _) => brackets.push(false),
+                _ => {}
+            },
+        }
+    }
+    state
+}
@@ -0,0 +1,265 @@
+//! Garbage collected heap for scripts with cyclic data.
+//!
+//! Arrays and objects in Dyon are values, so cyclic data structures,
+//! e.g. a world graph of entities referencing each other,
+//! can not be stored directly.
+//! When the heap is enabled with `Runtime::with_gc()`,
+//! scripts can store values on the heap using `gc_new`
+//! and refer to them by handle.
+//! Handles can be stored in arrays and objects on the heap,
+//! forming cycles.
+//! Arrays and objects are not moved to the heap by themselves,
+//! also when the heap is enabled, so only values passed to `gc_new` are traced.
+//!
+//! Values that are no longer reachable from the stack are freed
+//! by an incremental mark and sweep collector.
+//! A little of the marking work is done each time a value is allocated,
+//! such that scripts with large heaps do not pause for a full collection.
+//! Values are kept for at least one collection after being allocated,
+//! since handles in temporary values, e.g. an array being constructed,
+//! are not on the stack.
+//! `gc_collect` runs a full collection, freeing all unreachable values.
+//!
+//! Handles are traced inside arrays, objects, options, results
+//! and values captured by closures with `grab`.
+//! Handles sent to other threads are not roots, and might become invalid.
+
+use std::sync::{Arc, Mutex};
+
+use RustObject;
+use Variable;
+
+/// Number of allocations before the first collection starts.
+const MIN_THRESHOLD: usize = 1024;
+/// Number of values marked per allocation while collecting.
+const STEP: usize = 64;
+
+/// A handle to a value on the heap.
+struct Handle {
+    index: usize,
+    generation: u64,
+}
+
+struct Slot {
+    value: Variable,
+    generation: u64,
+    live: bool,
+    marked: bool,
+}
+
+/// Stores values referenced by handles.
+pub(crate) struct Heap {
+    slots: Vec<Slot>,
+    free: Vec<usize>,
+    gray: Vec<usize>,
+    /// Values allocated since last collection.
+    young: Vec<usize>,
+    marking: bool,
+    allocated: usize,
+    threshold: usize,
+}
+
+impl Heap {
+    pub(crate) fn new() -> Heap {
+        Heap {
+            slots: vec![],
+            free: vec![],
+            gray: vec![],
+            young: vec![],
+            marking: false,
+            allocated: 0,
+            threshold: MIN_THRESHOLD,
+        }
+    }
+
+    /// Stores a value on the heap and returns a handle to it.
+    ///
+    /// The stack is used as roots when collecting.
+    pub(crate) fn alloc(&mut self, value: Variable, stack: &[Variable]) -> Variable {
+        self.step(stack);
+        self.allocated += 1;
+        // Values allocated while marking are kept,
+        // including the values they refer to.
+        let marked = self.marking;
+        if marked {
+            self.trace(&value);
+        }
+        let index = match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index];
+                slot.value = value;
+                slot.live = true;
+                slot.marked = marked;
+                index
+            }
+            None => {
+                self.slots.push(Slot {
+                    value,
+                    generation: 0,
+                    live: true,
+                    marked,
+                });
+                self.slots.len() - 1
+            }
+        };
+        if !marked {
+            self.young.push(index);
+        }
+        let handle = Handle {
+            index,
+            generation: self.slots[index].generation,
+        };
+        Variable::RustObject(Arc::new(Mutex::new(handle)) as RustObject)
+    }
+
+    /// Returns the value that a handle refers to.
+    pub(crate) fn get(&self, handle: &Variable) -> Result<&Variable, String> {
+        let index = self.index(handle)?;
+        Ok(&self.slots[index].value)
+    }
+
+    /// Changes the value that a handle refers to.
+    pub(crate) fn set(&mut self, handle: &Variable, value: Variable) -> Result<(), String> {
+        let index = self.index(handle)?;
+        // Handles stored in a value that is already marked
+        // must be marked too, or they would be freed.
+        if self.marking {
+            self.trace(&value);
+        }
+        self.slots[index].value = value;
+        Ok(())
+    }
+
+    /// Runs a full collection and returns the number of freed values.
+    pub(crate) fn collect(&mut self, stack: &[Variable]) -> usize {
+        if !self.marking {
+            self.young.clear();
+            self.start(stack);
+        }
+        self.finish(stack)
+    }
+
+    fn index(&self, handle: &Variable) -> Result<usize, String> {
+        let invalid = || "Expected valid handle to value on heap".to_string();
+        let (index, generation) = match handle_of(handle) {
+            Some(x) => x,
+            None => return Err(invalid()),
+        };
+        match self.slots.get(index) {
+            Some(slot) if slot.live && slot.generation == generation => Ok(index),
+            _ => Err(invalid()),
+        }
+    }
+
+    fn step(&mut self, stack: &[Variable]) {
+        if !self.marking {
+            if self.allocated >= self.threshold {
+                self.start(stack);
+            }
+            return;
+        }
+        for _ in 0..STEP {
+            match self.gray.pop() {
+                Some(index) => self.scan(index),
+                None => {
+                    self.finish(stack);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn start(&mut self, stack: &[Variable]) {
+        self.marking = true;
+        self.allocated = 0;
+        for index in ::std::mem::take(&mut self.young) {
+            let slot = &mut self.slots[index];
+            if slot.live && !slot.marked {
+                slot.marked = true;
+                self.gray.push(index);
+            }
+        }
+        for v in stack {
+            self.trace(v);
+        }
+    }
+
+    fn finish(&mut self, stack: &[Variable]) -> usize {
+        // The stack is not tracked while marking,
+        // so it is scanned again before sweeping.
+        for v in stack {
+            self.trace(v);
+        }
+        while let Some(index) = self.gray.pop() {
+            self.scan(index);
+        }
+        self.marking = false;
+
+        let mut freed = 0;
+        for (index, slot) in self.slots.iter_mut().enumerate() {
+            if slot.live && !slot.marked {
+                slot.value = Variable::Option(None);
+                slot.live = false;
+                slot.generation += 1;
+                self.free.push(index);
+                freed += 1;
+            }
+            slot.marked = false;
+        }
+        let live = self.slots.len() - self.free.len();
+        self.threshold = MIN_THRESHOLD.max(live);
+        freed
+    }
+
+    fn scan(&mut self, index: usize) {
+        let value = self.slots[index].value.clone();
+        self.trace(&value);
+    }
+
+    fn trace(&mut self, v: &Variable) {
+        match *v {
+            Variable::RustObject(_) => {
+                if let Some((index, generation)) = handle_of(v) {
+                    if let Some(slot) = self.slots.get_mut(index) {
+                        if slot.live && slot.generation == generation && !slot.marked {
+                            slot.marked = true;
+                            self.gray.push(index);
+                        }
+                    }
+                }
+            }
+            Variable::Array(ref arr) => {
+                for it in arr.iter() {
+                    self.trace(it);
+                }
+            }
+            Variable::Object(ref obj) => {
+                for it in obj.values() {
+                    self.trace(it);
+                }
+            }
+            Variable::Option(Some(ref v)) => self.trace(v),
+            Variable::Result(Ok(ref v)) => self.trace(v),
+            Variable::Result(Err(ref err)) => self.trace(&err.message),
+            Variable::Closure(_, ref env) => {
+                for it in &env.captures {
+                    self.trace(it);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Returns the slot index and generation of a handle.
+fn handle_of(v: &Variable) -> Option<(usize, u64)> {
+    match *v {
+        Variable::RustObject(ref obj) => match obj.lock() {
+            Ok(guard) => guard
+                .downcast_ref::<Handle>()
+                .map(|h| (h.index, h.generation)),
+            Err(_) => None,
+        },
+        _ => None,
+    }
+}
@@ -4,7 +4,7 @@ are constants.
 */
 
 use ast;
-use runtime::{Flow, Runtime, Side};
+use runtime::{Flow, Runtime, RuntimeError, Side};
 use std::sync::Arc;
 use Variable;
 
@@ -22,7 +22,7 @@ pub fn grab_expr(
     rt: &mut Runtime,
     expr: &ast::Expression,
     side: Side,
-) -> Result<(Grabbed, Flow), String> {
+) -> Result<(Grabbed, Flow), RuntimeError> {
     use ast::Expression as E;
 
     match *expr {
@@ -35,18 +35,16 @@ pub fn grab_expr(
                         return Ok((Grabbed::Variable(x), Flow::Return));
                     }
                     _ => {
-                        return Err(rt.module.error(
+                        return Err(rt.error_at(
                             expr.source_range(),
                             &format!("{}\nExpected something", rt.stack_trace()),
-                            rt,
                         ))
                     }
                 };
+                let v = v.deep_clone(&rt.stack);
+                rt.grabbed.push(v.clone());
                 Ok((
-                    Grabbed::Expression(E::Variable(Box::new((
-                        expr.source_range(),
-                        v.deep_clone(&rt.stack),
-                    )))),
+                    Grabbed::Expression(E::Variable(Box::new((expr.source_range(), v)))),
                     Flow::Continue,
                 ))
             } else {
@@ -678,7 +676,7 @@ fn grab_block(
     rt: &mut Runtime,
     block: &ast::Block,
     side: Side,
-) -> Result<(Grabbed, Flow), String> {
+) -> Result<(Grabbed, Flow), RuntimeError> {
     Ok((
         Grabbed::Block(ast::Block {
             expressions: {
@@ -702,7 +700,7 @@ fn grab_item(
     rt: &mut Runtime,
     item: &ast::Item,
     side: Side,
-) -> Result<(Grabbed, Flow), String> {
+) -> Result<(Grabbed, Flow), RuntimeError> {
     Ok((
         Grabbed::Item(ast::Item {
             name: item.name.clone(),
@@ -736,7 +734,7 @@ fn grab_for_n(
     rt: &mut Runtime,
     for_n: &ast::ForN,
     side: Side,
-) -> Result<(Grabbed, Flow), String> {
+) -> Result<(Grabbed, Flow), RuntimeError> {
     Ok((
         Grabbed::ForN(ast::ForN {
             name: for_n.name.clone(),
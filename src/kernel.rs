@@ -0,0 +1,296 @@
+//! Primitives for building a notebook kernel, e.g. for Jupyter.
+//!
+//! A kernel evaluates cells incrementally.
+//! A cell either contains function definitions, which are added to the module,
+//! or statements, which are evaluated with variables from previous cells in scope.
+//! The value of the last expression in a statement cell is returned as result.
+//!
+//! Rich output, such as `display_html`, is collected per cell.
+//! A running cell can be stopped from another thread using an `Interrupt`.
+
+use piston_meta::parse_errstr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use ast;
+use Module;
+use Runtime;
+use Variable;
+use SYNTAX_RULES;
+
+/// Rich output from a cell.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Display {
+    /// HTML, from `display_html`.
+    Html(String),
+    /// PNG image data, from `display_png`.
+    Png(Vec<u8>),
+}
+
+/// The output of evaluating a cell.
+#[derive(Debug)]
+pub struct Output {
+    /// The value of the last expression, if any.
+    pub value: Option<Variable>,
+    /// Rich output in the order it was displayed.
+    pub display: Vec<Display>,
+}
+
+/// Stops a running cell with an error.
+///
+/// Can be sent to another thread, e.g. to handle interrupt requests.
+#[derive(Clone)]
+pub struct Interrupt(Arc<AtomicBool>);
+
+impl Interrupt {
+    /// Stops the running cell.
+    pub fn interrupt(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Evaluates cells incrementally with a persistent scope.
+pub struct Kernel {
+    module: Arc<Module>,
+    runtime: Runtime,
+    scope: Vec<(Arc<String>, Variable)>,
+    cells: usize,
+    interrupt: Arc<AtomicBool>,
+}
+
+impl Default for Kernel {
+    fn default() -> Kernel {
+        Kernel::new()
+    }
+}
+
+/// The name of the variable used to return the value of a cell.
+const VALUE: &str = "__value__";
+
+impl Kernel {
+    /// Creates a new kernel with the standard external functions.
+    pub fn new() -> Kernel {
+        Kernel::with_module(Module::new())
+    }
+
+    /// Creates a new kernel using a module,
+    /// e.g. with custom external functions or preloaded functions.
+    pub fn with_module(module: Module) -> Kernel {
+        let interrupt = Arc::new(AtomicBool::new(false));
+        let mut runtime = Runtime::new();
+        runtime.interrupt = Some(interrupt.clone());
+        Kernel {
+            module: Arc::new(module),
+            runtime,
+            scope: vec![],
+            cells: 0,
+            interrupt,
+        }
+    }
+
+    /// Returns a handle to interrupt running cells.
+    pub fn interrupt_handle(&self) -> Interrupt {
+        Interrupt(self.interrupt.clone())
+    }
+
+    /// Returns the module with all functions defined so far.
+    pub fn module(&self) -> &Arc<Module> {
+        &self.module
+    }
+
+    /// Returns the runtime used to evaluate cells.
+    pub fn runtime(&mut self) -> &mut Runtime {
+        &mut self.runtime
+    }
+
+    /// Returns the variables in scope, in the order they were declared.
+    pub fn scope(&self) -> &[(Arc<String>, Variable)] {
+        &self.scope
+    }
+
+    /// Formats a value the way Dyon prints it, with strings quoted.
+    pub fn format(&self, value: &Variable) -> String {
+        use write::{write_variable, EscapeString};
+
+        let mut buf: Vec<u8> = vec![];
+        write_variable(&mut buf, &self.runtime, value, EscapeString::Json, 0).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    /// Removes all variables from scope.
+    pub fn clear_scope(&mut self) {
+        self.scope.clear();
+    }
+
+    /// Evaluates a cell.
+    ///
+    /// A cell with a line starting with `fn ` is loaded as function definitions.
+    /// Otherwise, the cell is evaluated as statements.
+    pub fn eval(&mut self, source: &str) -> Result<Output, String> {
+        self.cells += 1;
+        let name = format!("cell[{}]", self.cells);
+        if source.lines().any(|line| line.starts_with("fn ")) {
+            let mut module = (*self.module).clone();
+            ::load_str(&name, Arc::new(source.into()), &mut module)?;
+            self.module = Arc::new(module);
+            return Ok(Output {
+                value: None,
+                display: vec![],
+            });
+        }
+
+        let (declared, last) = self.analyze(&name, source)?;
+        let fn_name = format!("__cell{}__", self.cells);
+        let mut names: Vec<Arc<String>> = self.scope.iter().map(|v| v.0.clone()).collect();
+        for name in declared {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+
+        // Try returning the last expression as value first,
+        // which fails when it has no value.
+        let mut module = (*self.module).clone();
+        let mut res = Err(String::new());
+        if let Some((start, end)) = last {
+            let body = format!(
+                "{}{} := {}{}",
+                &source[..start],
+                VALUE,
+                &source[start..end],
+                &source[end..]
+            );
+            let mut with_value = names.clone();
+            with_value.push(Arc::new(VALUE.into()));
+            let wrapped = self.wrap(&fn_name, &body, &with_value);
+            res = ::load_str(&name, Arc::new(wrapped), &mut module);
+            if res.is_err() {
+                module = (*self.module).clone();
+            }
+        }
+        if res.is_err() {
+            let wrapped = self.wrap(&fn_name, source, &names);
+            ::load_str(&name, Arc::new(wrapped), &mut module)?;
+        }
+        self.module = Arc::new(module);
+
+        let args: Vec<Variable> = self.scope.iter().map(|v| v.1.clone()).collect();
+        let mut fn_name = fn_name;
+        if !args.is_empty() {
+            fn_name.push('(');
+            for i in 0..args.len() {
+                if i > 0 {
+                    fn_name.push(',');
+                }
+                fn_name.push_str("mut");
+            }
+            fn_name.push(')');
+        }
+        self.interrupt.store(false, Ordering::Relaxed);
+        self.runtime.display = Some(vec![]);
+        let res = self.runtime.call_str_ret(&fn_name, &args, &self.module);
+        let display = self.runtime.display.take().unwrap_or_default();
+        let mut obj = match res {
+            Ok(v) => match self.runtime.resolve(&v).deep_clone(&self.runtime.stack) {
+                Variable::Object(obj) => obj,
+                _ => return Err("Expected object from cell".into()),
+            },
+            Err(err) => {
+                // Reset runtime state after error.
+                self.runtime.stack.clear();
+                self.runtime.call_stack.clear();
+                self.runtime.local_stack.clear();
+                self.runtime.current_stack.clear();
+                return Err(err.into());
+            }
+        };
+        let obj = Arc::make_mut(&mut obj);
+        let value = obj.remove(&Arc::new(VALUE.to_string()));
+        self.scope = names
+            .into_iter()
+            .filter_map(|name| obj.remove(&name).map(|v| (name, v)))
+            .collect();
+        Ok(Output { value, display })
+    }
+
+    /// Wraps statements in a function taking the scope as arguments
+    /// and returning the new scope as an object.
+    ///
+    /// The first line of the cell is kept on the first line,
+    /// such that line numbers in error messages match the cell.
+    fn wrap(&self, fn_name: &str, body: &str, names: &[Arc<String>]) -> String {
+        let mut s = format!("fn {}(", fn_name);
+        for (i, var) in self.scope.iter().enumerate() {
+            if i > 0 {
+                s.push_str(", ");
+            }
+            s.push_str("mut ");
+            s.push_str(&var.0);
+        }
+        s.push_str(") -> {} { ");
+        s.push_str(body);
+        s.push_str("\nreturn {");
+        for (i, name) in names.iter().enumerate() {
+            if i > 0 {
+                s.push_str(", ");
+            }
+            s.push_str(&format!("{}: clone({})", name, name));
+        }
+        s.push_str("}\n}\n");
+        s
+    }
+
+    /// Finds variables declared at top level of the cell,
+    /// and the range of the last expression if it might have a value.
+    #[allow(clippy::type_complexity)]
+    fn analyze(
+        &self,
+        name: &str,
+        source: &str,
+    ) -> Result<(Vec<Arc<String>>, Option<(usize, usize)>), String> {
+        use ast::Expression as E;
+
+        let prefix = "fn __analyze__() { ";
+        let d = Arc::new(format!("{}{}\n}}\n", prefix, source));
+        let syntax_rules = SYNTAX_RULES.as_ref().map_err(|err| err.clone())?;
+        let mut data = vec![];
+        parse_errstr(syntax_rules, &d, &mut data)
+            .map_err(|err| format!("In `{}:`\n{}", name, err))?;
+        let mut module = (*self.module).clone();
+        ::load_meta(name, d, &data, &mut module)?;
+        let block = &module.functions.last().unwrap().block;
+
+        let mut declared = vec![];
+        for expr in &block.expressions {
+            if let E::Assign(ref assign) = *expr {
+                if let E::Item(ref item) = assign.left {
+                    if assign.op == ast::AssignOp::Assign
+                        && item.ids.is_empty()
+                        && !declared.contains(&item.name)
+                    {
+                        declared.push(item.name.clone());
+                    }
+                }
+            }
+        }
+        let last = match block.expressions.last() {
+            None
+            | Some(&E::Assign(_))
+            | Some(&E::For(_))
+            | Some(&E::ForN(_))
+            | Some(&E::ForIn(_))
+            | Some(&E::Break(_))
+            | Some(&E::Continue(_))
+            | Some(&E::Return(_))
+            | Some(&E::ReturnVoid(_)) => None,
+            Some(expr) => {
+                let range = expr.source_range();
+                Some((
+                    range.offset - prefix.len(),
+                    range.next_offset() - prefix.len(),
+                ))
+            }
+        };
+        Ok((declared, last))
+    }
+}
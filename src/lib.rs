@@ -9,17 +9,45 @@
 
 #![cfg_attr(test, feature(test))]
 #![deny(missing_docs)]
+#[cfg(feature = "python")]
+extern crate core;
+#[cfg(feature = "crypto")]
+extern crate crc32fast;
+#[cfg(feature = "derive")]
+extern crate dyon_derive;
+#[cfg(feature = "python")]
+extern crate numpy;
 extern crate piston_meta;
+#[cfg(all(not(target_family = "wasm"), feature = "gpu"))]
+extern crate pollster;
+#[cfg(feature = "python")]
+extern crate pyo3;
 extern crate rand;
 extern crate range;
 extern crate read_color;
 extern crate read_token;
+#[cfg(feature = "regex")]
+extern crate regex;
 #[cfg(all(not(target_family = "wasm"), feature = "http"))]
 extern crate reqwest;
+#[cfg(feature = "yaml")]
+extern crate serde_yaml;
+#[cfg(feature = "crypto")]
+extern crate sha2;
+#[cfg(feature = "msgpack")]
+extern crate rmp;
+#[cfg(feature = "toml")]
+extern crate toml;
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+extern crate fs2;
 #[macro_use]
 extern crate lazy_static;
+#[cfg(all(unix, feature = "file"))]
+extern crate libc;
 extern crate tree_mem_sort;
 extern crate vecmath;
+#[cfg(all(not(target_family = "wasm"), feature = "gpu"))]
+extern crate wgpu;
 
 use piston_meta::{parse_errstr, syntax_errstr, MetaData, Syntax};
 use range::Range;
@@ -31,33 +59,65 @@ use std::sync::{Arc, Mutex};
 use std::thread::JoinHandle;
 
 pub mod ast;
+mod bytecode;
+#[cfg(feature = "capi")]
+pub mod capi;
 pub mod embed;
+mod format;
+mod gc;
+pub mod kernel;
 mod lifetime;
 mod link;
+pub mod literate;
 pub mod macros;
 mod mat4;
+pub mod mods;
 mod module;
+mod persistent;
+#[cfg(all(not(target_family = "wasm"), feature = "threading"))]
+pub mod pool;
 mod prelude;
+#[cfg(feature = "python")]
+pub mod python;
+mod repl;
+mod rope;
 pub mod runtime;
+pub mod sim;
+pub mod transpile;
 mod ty;
 mod vec4;
+pub mod vfs;
 mod write;
 
 mod dyon_std;
 mod grab;
 
 pub use ast::Lazy;
+#[cfg(feature = "derive")]
+pub use dyon_derive::DyonObject;
+pub use format::format_source;
 pub use link::Link;
 pub use mat4::Mat4;
+pub use mods::ModScheduler;
 pub use module::Module;
+#[cfg(all(not(target_family = "wasm"), feature = "threading"))]
+pub use pool::RuntimePool;
 pub use prelude::{Dfn, Lt, Prelude};
-pub use runtime::Runtime;
+pub use repl::Repl;
+pub use runtime::{CancelHandle, ErrorKind, Runtime, RuntimeError, RuntimeSettings};
+#[cfg(all(not(target_family = "wasm"), feature = "threading"))]
+pub use runtime::{Continuation, FuelRun};
 pub use ty::Type;
 pub use vec4::Vec4;
 
 /// A common error message when there is no value on the stack.
 pub const TINVOTS: &str = "There is no value on the stack";
 
+/// The source of the standard library written in Dyon.
+///
+/// Use `Module::add_std_dyon` to load it.
+pub const STD_DYON: &str = include_str!("std.dyon");
+
 lazy_static! {
     pub(crate) static ref LESS: Arc<String> = Arc::new("less".into());
     pub(crate) static ref LESS_OR_EQUAL: Arc<String> = Arc::new("less_or_equal".into());
@@ -99,6 +159,19 @@ pub type Array = Arc<Vec<Variable>>;
 pub type Object = Arc<HashMap<Arc<String>, Variable>>;
 /// Type alias for Rust objects.
 pub type RustObject = Arc<Mutex<dyn Any>>;
+/// Type alias for the shared handle of a Dyon thread.
+#[cfg(all(not(target_family = "wasm"), feature = "threading"))]
+pub type ThreadHandle = Arc<Mutex<JoinHandle<Result<Variable, RuntimeError>>>>;
+/// The component type of `vec4` and `mat4`.
+///
+/// This is `f64` with the `f64_vec4` feature, for precision in e.g. world coordinates.
+#[cfg(not(feature = "f64_vec4"))]
+pub type Scalar = f32;
+/// The component type of `vec4` and `mat4`.
+///
+/// This is `f64` with the `f64_vec4` feature, for precision in e.g. world coordinates.
+#[cfg(feature = "f64_vec4")]
+pub type Scalar = f64;
 
 /// Stores Dyon errors.
 #[derive(Debug, Clone)]
@@ -108,6 +181,10 @@ pub struct Error {
     /// Extra information to help debug error.
     /// Stores error messages for all `?` operators.
     pub trace: Vec<String>,
+    /// The kind of error raised when unwrapped, e.g. `ErrorKind::Io` for failed file operations.
+    ///
+    /// This is `None` for `err(_)` values created by scripts.
+    pub kind: Option<ErrorKind>,
 }
 
 /// Stores a thread handle.
@@ -115,13 +192,13 @@ pub struct Error {
 #[derive(Clone)]
 pub struct Thread {
     /// The handle of the thread.
-    pub handle: Option<Arc<Mutex<JoinHandle<Result<Variable, String>>>>>,
+    pub handle: Option<ThreadHandle>,
 }
 
 #[cfg(all(not(target_family = "wasm"), feature = "threading"))]
 impl Thread {
     /// Creates a new thread handle.
-    pub fn new(handle: JoinHandle<Result<Variable, String>>) -> Thread {
+    pub fn new(handle: JoinHandle<Result<Variable, RuntimeError>>) -> Thread {
         Thread {
             handle: Some(Arc::new(Mutex::new(handle))),
         }
@@ -132,7 +209,7 @@ impl Thread {
     pub fn invalidate_handle(
         rt: &mut Runtime,
         var: Variable,
-    ) -> Result<JoinHandle<Result<Variable, String>>, String> {
+    ) -> Result<JoinHandle<Result<Variable, RuntimeError>>, RuntimeError> {
         let thread = match var {
             Variable::Ref(ind) => {
                 use std::mem::replace;
@@ -160,11 +237,7 @@ impl Thread {
             )
         })?;
         mutex.into_inner().map_err(|err| {
-            format!(
-                "{}\nCan not lock Thread mutex:\n{}",
-                rt.stack_trace(),
-                err.to_string()
-            )
+            format!("{}\nCan not lock Thread mutex:\n{}", rt.stack_trace(), err).into()
         })
     }
 }
@@ -187,6 +260,8 @@ pub struct ClosureEnvironment {
     pub module: Arc<Module>,
     /// Relative index, used to resolve function indices.
     pub relative: usize,
+    /// The values of `grab` expressions, which the garbage collector uses as roots.
+    pub captures: Vec<Variable>,
 }
 
 impl fmt::Debug for ClosureEnvironment {
@@ -207,9 +282,9 @@ pub enum Variable {
     /// F64.
     F64(f64, Option<Box<Vec<Variable>>>),
     /// 4D vector.
-    Vec4([f32; 4]),
+    Vec4([Scalar; 4]),
     /// 4D matrix.
-    Mat4(Box<[[f32; 4]; 4]>),
+    Mat4(Box<[[Scalar; 4]; 4]>),
     /// Text.
     Str(Arc<String>),
     /// Array.
@@ -235,6 +310,12 @@ pub enum Variable {
     Closure(Arc<ast::Closure>, Box<ClosureEnvironment>),
     /// In-type.
     In(Arc<Mutex<::std::sync::mpsc::Receiver<Variable>>>),
+    /// Out-type, sending to an in-type.
+    Out(Arc<Mutex<::std::sync::mpsc::Sender<Variable>>>),
+    /// Binary data.
+    Bytes(Arc<Vec<u8>>),
+    /// Packed array of numbers.
+    F64Array(Arc<Vec<f64>>),
 }
 
 /// This is requires because `UnsafeRef(*mut Variable)` can not be sent across threads.
@@ -277,48 +358,63 @@ impl Variable {
             Thread(_) => THREAD_TYPE.clone(),
             Closure(_, _) => CLOSURE_TYPE.clone(),
             In(_) => IN_TYPE.clone(),
+            Out(_) => OUT_TYPE.clone(),
+            Bytes(_) => BYTES_TYPE.clone(),
+            F64Array(_) => F64_ARRAY_TYPE.clone(),
         }
     }
 
     fn deep_clone(&self, stack: &[Variable]) -> Variable {
+        self.resolve_refs(stack).unwrap_or_else(|| self.clone())
+    }
+
+    /// Deep clones the variable when it contains references to the stack,
+    /// or returns `None` when the variable can be shared as it is,
+    /// such that passing large values around is cheap.
+    fn resolve_refs(&self, stack: &[Variable]) -> Option<Variable> {
         use Variable::*;
 
         match *self {
-            F64(_, _) => self.clone(),
-            Vec4(_) => self.clone(),
-            Mat4(_) => self.clone(),
-            Return => self.clone(),
-            Bool(_, _) => self.clone(),
-            Str(_) => self.clone(),
+            F64(_, _) => None,
+            Vec4(_) => None,
+            Mat4(_) => None,
+            Return => None,
+            Bool(_, _) => None,
+            Str(_) => None,
             Object(ref obj) => {
-                let mut res = obj.clone();
-                for val in Arc::make_mut(&mut res).values_mut() {
-                    *val = val.deep_clone(stack);
+                let mut res = None;
+                for (key, val) in obj.iter() {
+                    if let Some(val) = val.resolve_refs(stack) {
+                        Arc::make_mut(res.get_or_insert_with(|| obj.clone()))
+                            .insert(key.clone(), val);
+                    }
                 }
-                Object(res)
+                res.map(Object)
             }
             Array(ref arr) => {
-                let mut res = arr.clone();
-                for it in Arc::make_mut(&mut res) {
-                    *it = it.deep_clone(stack);
+                let mut res = None;
+                for (i, it) in arr.iter().enumerate() {
+                    if let Some(it) = it.resolve_refs(stack) {
+                        Arc::make_mut(res.get_or_insert_with(|| arr.clone()))[i] = it;
+                    }
                 }
-                Array(res)
+                res.map(Array)
             }
-            Link(_) => self.clone(),
-            Ref(ind) => stack[ind].deep_clone(stack),
+            Link(_) => None,
+            Ref(ind) => Some(stack[ind].deep_clone(stack)),
             UnsafeRef(_) => panic!("Unsafe reference can not be cloned"),
-            RustObject(_) => self.clone(),
-            Option(None) => Variable::Option(None),
-            // `some(x)` always uses deep clone, so it does not contain references.
-            Option(Some(ref v)) => Option(Some(v.clone())),
-            // `ok(x)` always uses deep clone, so it does not contain references.
-            Result(Ok(ref ok)) => Result(Ok(ok.clone())),
-            // `err(x)` always uses deep clone, so it does not contain references.
-            Result(Err(ref err)) => Result(Err(err.clone())),
+            RustObject(_) => None,
+            // `some(x)`, `ok(x)` and `err(x)` always use deep clone,
+            // so they do not contain references.
+            Option(_) => None,
+            Result(_) => None,
             #[cfg(all(not(target_family = "wasm"), feature = "threading"))]
-            Thread(_) => self.clone(),
-            Closure(_, _) => self.clone(),
-            In(_) => self.clone(),
+            Thread(_) => None,
+            Closure(_, _) => None,
+            In(_) => None,
+            Out(_) => None,
+            Bytes(_) => None,
+            F64Array(_) => None,
         }
     }
 }
@@ -332,6 +428,8 @@ impl PartialEq for Variable {
             (&Variable::Str(ref a), &Variable::Str(ref b)) => a == b,
             (&Variable::Object(ref a), &Variable::Object(ref b)) => a == b,
             (&Variable::Array(ref a), &Variable::Array(ref b)) => a == b,
+            (&Variable::Bytes(ref a), &Variable::Bytes(ref b)) => a == b,
+            (&Variable::F64Array(ref a), &Variable::F64Array(ref b)) => a == b,
             (&Variable::Ref(_), _) => false,
             (&Variable::UnsafeRef(_), _) => false,
             (&Variable::RustObject(_), _) => false,
@@ -363,23 +461,23 @@ pub enum FnIndex {
 #[derive(Clone, Copy)]
 pub enum FnExt {
     /// External function with no return value.
-    Void(fn(&mut Runtime) -> Result<(), String>),
+    Void(fn(&mut Runtime) -> Result<(), RuntimeError>),
     /// External function with return value.
-    Return(fn(&mut Runtime) -> Result<Variable, String>),
+    Return(fn(&mut Runtime) -> Result<Variable, RuntimeError>),
     /// External binary operator.
     BinOp(fn(&Variable, &Variable) -> Result<Variable, String>),
     /// External unary operator.
     UnOp(fn(&Variable) -> Result<Variable, String>),
 }
 
-impl From<fn(&mut Runtime) -> Result<(), String>> for FnExt {
-    fn from(val: fn(&mut Runtime) -> Result<(), String>) -> Self {
+impl From<fn(&mut Runtime) -> Result<(), RuntimeError>> for FnExt {
+    fn from(val: fn(&mut Runtime) -> Result<(), RuntimeError>) -> Self {
         FnExt::Void(val)
     }
 }
 
-impl From<fn(&mut Runtime) -> Result<Variable, String>> for FnExt {
-    fn from(val: fn(&mut Runtime) -> Result<Variable, String>) -> Self {
+impl From<fn(&mut Runtime) -> Result<Variable, RuntimeError>> for FnExt {
+    fn from(val: fn(&mut Runtime) -> Result<Variable, RuntimeError>) -> Self {
         FnExt::Return(val)
     }
 }
@@ -436,7 +534,7 @@ impl fmt::Debug for FnBinOpRef {
 
 /// Used to store direct reference to external function.
 #[derive(Copy)]
-pub struct FnReturnRef(pub fn(&mut Runtime) -> Result<Variable, String>);
+pub struct FnReturnRef(pub fn(&mut Runtime) -> Result<Variable, RuntimeError>);
 
 impl Clone for FnReturnRef {
     fn clone(&self) -> FnReturnRef {
@@ -452,7 +550,7 @@ impl fmt::Debug for FnReturnRef {
 
 /// Used to store direct reference to external function that does not return anything.
 #[derive(Copy)]
-pub struct FnVoidRef(pub fn(&mut Runtime) -> Result<(), String>);
+pub struct FnVoidRef(pub fn(&mut Runtime) -> Result<(), RuntimeError>);
 
 impl Clone for FnVoidRef {
     fn clone(&self) -> FnVoidRef {
@@ -485,7 +583,7 @@ impl Clone for FnExternal {
 }
 
 /// Runs a program using a source file.
-pub fn run(source: &str) -> Result<(), String> {
+pub fn run(source: &str) -> Result<(), RuntimeError> {
     let mut module = Module::new();
     load(source, &mut module)?;
     let mut runtime = runtime::Runtime::new();
@@ -494,7 +592,7 @@ pub fn run(source: &str) -> Result<(), String> {
 }
 
 /// Runs a program from a string.
-pub fn run_str(source: &str, d: Arc<String>) -> Result<(), String> {
+pub fn run_str(source: &str, d: Arc<String>) -> Result<(), RuntimeError> {
     let mut module = Module::new();
     load_str(source, d, &mut module)?;
     let mut runtime = runtime::Runtime::new();
@@ -537,7 +635,7 @@ impl Call {
     }
 
     /// Run call without any return value.
-    pub fn run(&self, runtime: &mut Runtime, module: &Arc<Module>) -> Result<(), String> {
+    pub fn run(&self, runtime: &mut Runtime, module: &Arc<Module>) -> Result<(), RuntimeError> {
         runtime.call_str(&self.name, &self.args, module)
     }
 
@@ -546,7 +644,7 @@ impl Call {
         &self,
         runtime: &mut Runtime,
         module: &Arc<Module>,
-    ) -> Result<T, String> {
+    ) -> Result<T, RuntimeError> {
         let val = runtime.call_str_ret(&self.name, &self.args, module)?;
         T::pop_var(runtime, runtime.resolve(&val))
     }
@@ -556,7 +654,7 @@ impl Call {
         &self,
         runtime: &mut Runtime,
         module: &Arc<Module>,
-    ) -> Result<T, String> {
+    ) -> Result<T, RuntimeError> {
         let val = runtime.call_str_ret(&self.name, &self.args, module)?;
         match runtime.resolve(&val) {
             &Variable::Vec4(val) => Ok(T::from(val)),
@@ -577,6 +675,191 @@ pub fn load(source: &str, module: &mut Module) -> Result<(), String> {
     load_str(source, data, module)
 }
 
+/// Returns the crate features that Dyon is compiled with.
+///
+/// Features that are not supported on the target, e.g. `file` on WebAssembly, are left out.
+pub fn features() -> Vec<&'static str> {
+    let mut res = vec![];
+    if cfg!(feature = "debug_resolve") {
+        res.push("debug_resolve");
+    }
+    if cfg!(all(not(target_family = "wasm"), feature = "http")) {
+        res.push("http");
+    }
+    if cfg!(all(not(target_family = "wasm"), feature = "file")) {
+        res.push("file");
+    }
+    if cfg!(all(not(target_family = "wasm"), feature = "threading")) {
+        res.push("threading");
+    }
+    if cfg!(feature = "stdio") {
+        res.push("stdio");
+    }
+    if cfg!(feature = "capi") {
+        res.push("capi");
+    }
+    if cfg!(feature = "python") {
+        res.push("python");
+    }
+    if cfg!(feature = "regex") {
+        res.push("regex");
+    }
+    if cfg!(all(not(target_family = "wasm"), feature = "process")) {
+        res.push("process");
+    }
+    if cfg!(feature = "derive") {
+        res.push("derive");
+    }
+    if cfg!(feature = "msgpack") {
+        res.push("msgpack");
+    }
+    if cfg!(all(not(target_family = "wasm"), feature = "rpc")) {
+        res.push("rpc");
+    }
+    if cfg!(feature = "toml") {
+        res.push("toml");
+    }
+    if cfg!(feature = "yaml") {
+        res.push("yaml");
+    }
+    if cfg!(feature = "gpu") {
+        res.push("gpu");
+    }
+    if cfg!(feature = "crypto") {
+        res.push("crypto");
+    }
+    if cfg!(feature = "f64_vec4") {
+        res.push("f64_vec4");
+    }
+    res
+}
+
+/// Returns the intrinsics that report an error when called,
+/// because the crate feature they need is disabled.
+///
+/// Tools can use this to warn about scripts calling these functions.
+pub fn disabled_functions() -> Vec<&'static str> {
+    let features = features();
+    let mut res = vec![];
+    // Some functions need more than one feature.
+    for f in dyon_std::FEATURE_FUNCTIONS
+        .iter()
+        .filter(|(feature, _)| !features.contains(feature))
+        .flat_map(|(_, functions)| functions.iter().cloned())
+    {
+        if !res.contains(&f) {
+            res.push(f);
+        }
+    }
+    res
+}
+
+/// Removes functions with `#[cfg(..)]` attributes from meta data
+/// when the condition does not hold for the module.
+fn strip_cfg(data: &mut Vec<Range<MetaData>>, module: &Module) {
+    let fn_node: Arc<String> = Arc::new("fn".into());
+    let mut res = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        if let MetaData::StartNode(ref name) = data[i].data {
+            if *name == fn_node && i + 1 < data.len() {
+                let keep = match data[i + 1].data {
+                    MetaData::String(ref cfg, ref flag) if &**cfg == "cfg" => {
+                        Some(module.cfg.contains(flag))
+                    }
+                    MetaData::String(ref cfg, ref flag) if &**cfg == "cfg_not" => {
+                        Some(!module.cfg.contains(flag))
+                    }
+                    _ => None,
+                };
+                match keep {
+                    None => {}
+                    Some(true) => {
+                        res.push(data[i].clone());
+                        i += 2;
+                        continue;
+                    }
+                    Some(false) => {
+                        // Skip to the end of the function.
+                        let mut depth = 0;
+                        loop {
+                            match data[i].data {
+                                MetaData::StartNode(_) => depth += 1,
+                                MetaData::EndNode(_) => depth -= 1,
+                                _ => {}
+                            }
+                            i += 1;
+                            if depth == 0 {
+                                break;
+                            }
+                        }
+                        continue;
+                    }
+                }
+            }
+        }
+        res.push(data[i].clone());
+        i += 1;
+    }
+    *data = res;
+}
+
+/// Checks the `#version` pragma at the top of a source, if any.
+///
+/// The pragma declares the version of Dyon a script is written for,
+/// e.g. `#version 0.46`, and must come before any code.
+/// Loading fails with an error when the script requires a newer version.
+///
+/// Returns the source with the pragma replaced by whitespace,
+/// such that error messages point to the same lines.
+fn version_pragma(source: &str, d: Arc<String>) -> Result<Arc<String>, String> {
+    let mut pos = 0;
+    let mut found = None;
+    for line in d.split('\n') {
+        let trimmed = line.trim();
+        if let Some(version) = trimmed.strip_prefix("#version") {
+            found = Some((pos, line.len(), version.trim()));
+            break;
+        } else if !trimmed.is_empty() && !trimmed.starts_with("//") {
+            break;
+        }
+        pos += line.len() + 1;
+    }
+    let (pos, len, version) = match found {
+        None => return Ok(d),
+        Some(x) => x,
+    };
+
+    let parse = |v: &str| -> Option<(u32, u32)> {
+        let mut parts = v.split('.');
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next()?.parse().ok()?;
+        match parts.next() {
+            None => Some((major, minor)),
+            Some(patch) => patch.parse::<u32>().ok().map(|_| (major, minor)),
+        }
+    };
+    let current = (
+        env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap(),
+        env!("CARGO_PKG_VERSION_MINOR").parse().unwrap(),
+    );
+    match parse(version) {
+        None => Err(format!(
+            "In `{}`:\nExpected `#version <major>.<minor>`, found `#version {}`",
+            source, version
+        )),
+        Some(v) if v > current => Err(format!(
+            "In `{}`:\nThe script requires Dyon {}.{}, but this is Dyon {}.{}",
+            source, v.0, v.1, current.0, current.1
+        )),
+        Some(_) => {
+            let mut d = (*d).clone();
+            d.replace_range(pos..pos + len, &" ".repeat(len));
+            Ok(Arc::new(d))
+        }
+    }
+}
+
 lazy_static! {
     static ref SYNTAX_RULES: Result<Syntax, String> = {
         let syntax = include_str!("../assets/syntax.txt");
@@ -596,10 +879,12 @@ pub(crate) fn check_str(
     module: &Module,
 ) -> Result<Vec<lifetime::Node>, String> {
     let syntax_rules = SYNTAX_RULES.as_ref().map_err(|err| err.clone())?;
+    let d = version_pragma(source, d)?;
 
     let mut data = vec![];
     parse_errstr(syntax_rules, &d, &mut data)
         .map_err(|err| format!("In `{}:`\n{}", source, err))?;
+    strip_cfg(&mut data, module);
 
     let check_data = data.clone();
     let prelude = Arc::new(Prelude::from_module(module));
@@ -651,10 +936,12 @@ pub fn load_str(source: &str, d: Arc<String>, module: &mut Module) -> Result<(),
     }
 
     let syntax_rules = SYNTAX_RULES.as_ref().map_err(|err| err.clone())?;
+    let d = version_pragma(source, d)?;
 
     let mut data = vec![];
     parse_errstr(syntax_rules, &d, &mut data)
         .map_err(|err| format!("In `{}:`\n{}", source, err))?;
+    strip_cfg(&mut data, module);
 
     let check_data = data.clone();
     let prelude = Arc::new(Prelude::from_module(module));
@@ -763,7 +1050,7 @@ fn check_ignored_meta_data(
 }
 
 /// Reports and error to standard output.
-pub fn error(res: Result<(), String>) -> bool {
+pub fn error<E: fmt::Display>(res: Result<(), E>) -> bool {
     match res {
         Err(err) => {
             println!();
@@ -793,7 +1080,7 @@ mod tests {
         Return,
         Bool(bool, Option<Box<Vec<Variable>>>),
         F64(f64, Option<Box<Vec<Variable>>>),
-        Vec4([f32; 4]),
+        Vec4([Scalar; 4]),
         Text(Arc<String>),
         Array(Array),
         Object(Object),
@@ -806,7 +1093,7 @@ mod tests {
         */
 
         println!("Link {}", size_of::<Box<Link>>());
-        println!("[f32; 4] {}", size_of::<[f32; 4]>());
+        println!("[Scalar; 4] {}", size_of::<[Scalar; 4]>());
         println!("Result {}", size_of::<Result<Box<Variable>, Box<Error>>>());
         #[cfg(all(not(target_family = "wasm"), feature = "threading"))]
         println!("Thread {}", size_of::<Thread>());
@@ -814,9 +1101,27 @@ mod tests {
         println!("Text {}", size_of::<Arc<String>>());
         println!("Array {}", size_of::<Array>());
         println!("Object {}", size_of::<Object>());
+        // A `vec4` of `f64` makes variables larger.
+        #[cfg(not(feature = "f64_vec4"))]
         assert_eq!(size_of::<Variable>(), 24);
     }
 
+    #[test]
+    fn feature_functions_are_registered() {
+        use super::*;
+
+        let module = Module::new();
+        for &(_, functions) in dyon_std::FEATURE_FUNCTIONS {
+            for name in functions {
+                assert!(
+                    module.ext_prelude.iter().any(|f| **f.name == **name),
+                    "`{}` is not registered",
+                    name
+                );
+            }
+        }
+    }
+
     #[test]
     fn expression_size() {
         use super::*;
@@ -899,6 +1204,7 @@ mod tests {
         b.iter(|| run_bench("source/bench/threads_no_go.dyon"));
     }
 
+    #[cfg(feature = "threading")]
     #[bench]
     fn bench_threads_go(b: &mut Bencher) {
         b.iter(|| run_bench("source/bench/threads_go.dyon"));
@@ -919,6 +1225,7 @@ mod tests {
         b.iter(|| run_bench("source/bench/push_link_for.dyon"));
     }
 
+    #[cfg(feature = "threading")]
     #[bench]
     fn bench_push_link_go(b: &mut Bencher) {
         b.iter(|| run_bench("source/bench/push_link_go.dyon"));
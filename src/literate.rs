@@ -0,0 +1,135 @@
+//! Runs Markdown documents with embedded Dyon code.
+//!
+//! Code blocks starting with ` ```dyon ` are evaluated in order,
+//! sharing variables and functions like cells in a notebook.
+//! Blocks starting with ` ```dyon,no_run ` are skipped.
+//!
+//! `render` returns the document with the results inlined after each block:
+//! the value of the last expression as text, HTML as it is,
+//! and PNG images as data URLs.
+
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+use std::fs::File;
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+use std::io::Read;
+
+use kernel::{Display, Kernel};
+
+/// Runs the Dyon code blocks in a Markdown file.
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+pub fn run(path: &str) -> Result<(), String> {
+    let text = read(path)?;
+    eval(path, &text, &mut Kernel::new(), None)
+}
+
+/// Runs the Dyon code blocks in a Markdown file,
+/// and returns the document with results inlined.
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+pub fn render(path: &str) -> Result<String, String> {
+    let text = read(path)?;
+    render_str(path, &text, &mut Kernel::new())
+}
+
+/// Runs the Dyon code blocks in Markdown text using a kernel,
+/// and returns the document with results inlined.
+///
+/// The name is used in error messages.
+pub fn render_str(name: &str, text: &str, kernel: &mut Kernel) -> Result<String, String> {
+    let mut out = String::new();
+    eval(name, text, kernel, Some(&mut out))?;
+    Ok(out)
+}
+
+#[cfg(all(not(target_family = "wasm"), feature = "file"))]
+fn read(path: &str) -> Result<String, String> {
+    let mut text = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut text))
+        .map_err(|err| format!("Could not read `{}`:\n{}", path, err))?;
+    Ok(text)
+}
+
+fn eval(
+    name: &str,
+    text: &str,
+    kernel: &mut Kernel,
+    mut out: Option<&mut String>,
+) -> Result<(), String> {
+    // The code of the current block and the line it starts.
+    let mut block: Option<(String, usize)> = None;
+    let mut skip = false;
+    for (i, line) in text.lines().enumerate() {
+        if let Some(ref mut out) = out {
+            out.push_str(line);
+            out.push('\n');
+        }
+        let fence = line.trim_end();
+        if let Some((code, start)) = block.take() {
+            if fence != "```" {
+                block = Some((code + line + "\n", start));
+                continue;
+            }
+            let output = kernel
+                .eval(&code)
+                .map_err(|err| format!("In `{}`, block at line {}:\n{}", name, start, err))?;
+            if let Some(ref mut out) = out {
+                if let Some(ref value) = output.value {
+                    out.push_str("\n```text\n");
+                    out.push_str(&kernel.format(value));
+                    out.push_str("\n```\n");
+                }
+                for display in &output.display {
+                    match *display {
+                        Display::Html(ref html) => {
+                            out.push('\n');
+                            out.push_str(html);
+                            out.push('\n');
+                        }
+                        Display::Png(ref bytes) => {
+                            out.push_str("\n<img src=\"data:image/png;base64,");
+                            out.push_str(&base64(bytes));
+                            out.push_str("\">\n");
+                        }
+                    }
+                }
+            }
+        } else if skip {
+            if fence == "```" {
+                skip = false;
+            }
+        } else if fence == "```dyon" {
+            block = Some((String::new(), i + 1));
+        } else if fence.starts_with("```") {
+            skip = true;
+        }
+    }
+    match block {
+        Some((_, start)) => Err(format!(
+            "In `{}`, block at line {}:\nExpected end of block",
+            name, start
+        )),
+        None => Ok(()),
+    }
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut s = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).cloned().unwrap_or(0),
+            chunk.get(2).cloned().unwrap_or(0),
+        ];
+        let n = (u32::from(b[0]) << 16) | (u32::from(b[1]) << 8) | u32::from(b[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                s.push(CHARS[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                s.push('=');
+            }
+        }
+    }
+    s
+}
@@ -67,7 +67,7 @@ macro_rules! dyon_fn_pop {
 macro_rules! dyon_fn {
     (fn $name:ident () -> # $rt:ty $b:block) => {
         #[allow(non_snake_case)]
-        pub fn $name(_rt: &mut $crate::Runtime) -> Result<$crate::Variable, String> {
+        pub fn $name(_rt: &mut $crate::Runtime) -> Result<$crate::Variable, $crate::RuntimeError> {
             use std::sync::{Arc, Mutex};
 
             fn inner() -> $rt {
@@ -80,7 +80,7 @@ macro_rules! dyon_fn {
     (fn $name:ident ($($rust_arg:tt : #&$rust_t:ty),+) -> # $rt:ty $b:block) => {
         dyon_macro_items!{
             #[allow(non_snake_case)]
-            pub fn $name(rt: &mut $crate::Runtime) -> Result<$crate::Variable, String> {
+            pub fn $name(rt: &mut $crate::Runtime) -> Result<$crate::Variable, $crate::RuntimeError> {
                 use std::sync::{Arc, Mutex};
 
                 fn inner($($rust_arg: &$rust_t),+) -> $rt {
@@ -95,7 +95,7 @@ macro_rules! dyon_fn {
     (fn $name:ident ($rust_arg:tt : #&$rust_t:ty, $($arg:tt : $t:ty),+) -> # $rt:ty $b:block) => {
         dyon_macro_items!{
             #[allow(non_snake_case)]
-            pub fn $name(rt: &mut $crate::Runtime) -> Result<$crate::Variable, String> {
+            pub fn $name(rt: &mut $crate::Runtime) -> Result<$crate::Variable, $crate::RuntimeError> {
                 use std::sync::{Arc, Mutex};
 
                 fn inner($rust_arg: &$rust_t, $($arg: $t),+) -> $rt {
@@ -111,7 +111,7 @@ macro_rules! dyon_fn {
     (fn $name:ident ($rust_arg:tt : #&$rust_t:ty , $rust_arg2:tt : #&$rust_t2:ty $(, $arg:tt : $t:ty)*) -> $rt:ty $b:block) => {
         dyon_macro_items!{
             #[allow(non_snake_case)]
-            pub fn $name(rt: &mut $crate::Runtime) -> Result<$crate::Variable, String> {
+            pub fn $name(rt: &mut $crate::Runtime) -> Result<$crate::Variable, $crate::RuntimeError> {
                 fn inner($rust_arg: &$rust_t, $rust_arg2: &$rust_t2 $(, $arg: $t)*) -> $rt {
                     $b
                 }
@@ -126,7 +126,7 @@ macro_rules! dyon_fn {
     (fn $name:ident ($rust_arg:tt : #&$rust_t:ty $(, $arg:tt : $t:ty)*) -> $rt:ty $b:block) => {
         dyon_macro_items!{
             #[allow(non_snake_case)]
-            pub fn $name(rt: &mut $crate::Runtime) -> Result<$crate::Variable, String> {
+            pub fn $name(rt: &mut $crate::Runtime) -> Result<$crate::Variable, $crate::RuntimeError> {
                 fn inner($rust_arg: &$rust_t $(, $arg: $t)*) -> $rt {
                     $b
                 }
@@ -140,7 +140,7 @@ macro_rules! dyon_fn {
     (fn $name:ident ($rust_arg:tt : #$rust_t:ty, $($arg:tt : $t:ty),+) -> $rt:ty $b:block) => {
         dyon_macro_items!{
             #[allow(non_snake_case)]
-            pub fn $name(rt: &mut $crate::Runtime) -> Result<$crate::Variable, String> {
+            pub fn $name(rt: &mut $crate::Runtime) -> Result<$crate::Variable, $crate::RuntimeError> {
                 fn inner($rust_arg: $rust_t, $($arg: $t),+) -> $rt {
                     $b
                 }
@@ -157,7 +157,7 @@ macro_rules! dyon_fn {
         $(, $arg:tt : $t:ty)*) -> $rt:ty $b:block) => {
         dyon_macro_items!{
             #[allow(non_snake_case)]
-            pub fn $name(rt: &mut $crate::Runtime) -> Result<$crate::Variable, String> {
+            pub fn $name(rt: &mut $crate::Runtime) -> Result<$crate::Variable, $crate::RuntimeError> {
                 fn inner($rust_arg: &mut $rust_ty, $rust_arg2: &$rust_ty2, $($arg: $t),*) -> $rt {
                     $b
                 }
@@ -175,7 +175,7 @@ macro_rules! dyon_fn {
         $(, $arg:tt : $t:ty)*) -> $rt:ty $b:block) => {
         dyon_macro_items!{
             #[allow(non_snake_case)]
-            pub fn $name(rt: &mut $crate::Runtime) -> Result<$crate::Variable, String> {
+            pub fn $name(rt: &mut $crate::Runtime) -> Result<$crate::Variable, $crate::RuntimeError> {
                 fn inner($rust_arg: &mut $rust_ty, $rust_arg2: $rust_ty2, $($arg: $t),*) -> $rt {
                     $b
                 }
@@ -190,7 +190,7 @@ macro_rules! dyon_fn {
     (fn $name:ident ($($arg:tt : $t:ty),*) -> # $rt:ty $b:block) => {
         dyon_macro_items!{
             #[allow(non_snake_case)]
-            pub fn $name(rt: &mut $crate::Runtime) -> Result<$crate::Variable, String> {
+            pub fn $name(rt: &mut $crate::Runtime) -> Result<$crate::Variable, $crate::RuntimeError> {
                 use std::sync::{Arc, Mutex};
 
                 fn inner($($arg: $t),*) -> $rt {
@@ -205,7 +205,7 @@ macro_rules! dyon_fn {
     (fn $name:ident ($($arg:tt : $t:ty),*) -> $rt:ty $b:block) => {
         dyon_macro_items!{
             #[allow(non_snake_case)]
-            pub fn $name(_rt: &mut $crate::Runtime) -> Result<$crate::Variable, String> {
+            pub fn $name(_rt: &mut $crate::Runtime) -> Result<$crate::Variable, $crate::RuntimeError> {
                 fn inner($($arg: $t),*) -> $rt {
                     $b
                 }
@@ -217,7 +217,7 @@ macro_rules! dyon_fn {
     };
     (fn $name:ident () $b:block) => {
         #[allow(non_snake_case)]
-        pub fn $name(_: &mut $crate::Runtime) -> Result<(), String> {
+        pub fn $name(_: &mut $crate::Runtime) -> Result<(), $crate::RuntimeError> {
             fn inner() {
                 $b
             }
@@ -232,7 +232,7 @@ macro_rules! dyon_fn {
         $(, $arg:tt : $t:ty)*) $b:block) => {
         dyon_macro_items!{
             #[allow(non_snake_case)]
-            pub fn $name(rt: &mut $crate::Runtime) -> Result<(), String> {
+            pub fn $name(rt: &mut $crate::Runtime) -> Result<(), $crate::RuntimeError> {
                 fn inner($rust_arg: &mut $rust_ty, $rust_arg2: &$rust_ty2, $($arg: $t),*) {
                     $b
                 }
@@ -251,7 +251,7 @@ macro_rules! dyon_fn {
         $(, $arg:tt : $t:ty)*) $b:block) => {
         dyon_macro_items!{
             #[allow(non_snake_case)]
-            pub fn $name(rt: &mut $crate::Runtime) -> Result<(), String> {
+            pub fn $name(rt: &mut $crate::Runtime) -> Result<(), $crate::RuntimeError> {
                 fn inner($rust_arg: &mut $rust_ty, $rust_arg2: $rust_ty2, $($arg: $t),*) {
                     $b
                 }
@@ -267,7 +267,7 @@ macro_rules! dyon_fn {
     (fn $name:ident ($rust_arg:tt : #&mut $rust_ty:ty $(, $arg:tt : $t:ty)*) $b:block) => {
         dyon_macro_items!{
             #[allow(non_snake_case)]
-            pub fn $name(rt: &mut $crate::Runtime) -> Result<(), String> {
+            pub fn $name(rt: &mut $crate::Runtime) -> Result<(), $crate::RuntimeError> {
                 fn inner($rust_arg: &mut $rust_ty, $($arg: $t),*) {
                     $b
                 }
@@ -282,7 +282,7 @@ macro_rules! dyon_fn {
     (fn $name:ident ($rust_arg:tt : #&$rust_ty:ty , $($arg:tt : $t:ty),*) $b:block) => {
         dyon_macro_items!{
             #[allow(non_snake_case)]
-            pub fn $name(rt: &mut $crate::Runtime) -> Result<(), String> {
+            pub fn $name(rt: &mut $crate::Runtime) -> Result<(), $crate::RuntimeError> {
                 fn inner($rust_arg: &$rust_ty, $($arg: $t),*) {
                     $b
                 }
@@ -297,7 +297,7 @@ macro_rules! dyon_fn {
     (fn $name:ident ($rust_arg:tt : # $rust_ty:ty , $($arg:tt : $t:ty),*) $b:block) => {
         dyon_macro_items!{
             #[allow(non_snake_case)]
-            pub fn $name(rt: &mut $crate::Runtime) -> Result<(), String> {
+            pub fn $name(rt: &mut $crate::Runtime) -> Result<(), $crate::RuntimeError> {
                 fn inner($rust_arg: $rust_ty, $($arg: $t),*) {
                     $b
                 }
@@ -312,7 +312,7 @@ macro_rules! dyon_fn {
     (fn $name:ident ($($arg:tt : #$t:ty),+) $b:block) => {
         dyon_macro_items!{
             #[allow(non_snake_case)]
-            pub fn $name(rt: &mut $crate::Runtime) -> Result<(), String> {
+            pub fn $name(rt: &mut $crate::Runtime) -> Result<(), $crate::RuntimeError> {
                 fn inner($($arg: $t),+) {
                     $b
                 }
@@ -326,7 +326,7 @@ macro_rules! dyon_fn {
     (fn $name:ident ($($arg:tt : $t:ty),+) $b:block) => {
         dyon_macro_items!{
             #[allow(non_snake_case)]
-            pub fn $name(rt: &mut $crate::Runtime) -> Result<(), String> {
+            pub fn $name(rt: &mut $crate::Runtime) -> Result<(), $crate::RuntimeError> {
                 fn inner($($arg: $t),+) {
                     $b
                 }
@@ -342,12 +342,13 @@ macro_rules! dyon_fn {
 /// Used to implement `embed::PopVariable` and `embed::PushVariable` for some object.
 ///
 /// For example, see "examples/functions.rs".
+/// With the `derive` feature, `#[derive(DyonObject)]` can be used instead.
 #[macro_export]
 macro_rules! dyon_obj {
     ($t:tt { $($f:tt),* }) => {
         dyon_macro_items!{
             impl $crate::embed::PopVariable for $t {
-                fn pop_var(rt: &$crate::Runtime, var: &$crate::Variable) -> Result<Self, String> {
+                fn pop_var(rt: &$crate::Runtime, var: &$crate::Variable) -> Result<Self, $crate::RuntimeError> {
                     use dyon::embed::obj_field;
                     let var = rt.resolve(var);
                     if let &$crate::Variable::Object(ref obj) = var {
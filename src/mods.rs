@@ -0,0 +1,291 @@
+//! Runs many scripts side by side, such as the mods of a game.
+//!
+//! A `ModScheduler` loads each mod into its own module and runtime,
+//! and calls the `update` function of every enabled mod once per tick.
+//! An error or panic in one mod is recorded and does not stop the others,
+//! and budgets of steps and time keep a slow mod from blocking the tick.
+//!
+//! The step budget counts statements and loop iterations.
+//! The time budget is checked at the same points and while sleeping,
+//! so a mod blocked in another function, e.g. reading a file, stops when the function returns.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::time::Duration;
+
+use FnIndex;
+use Module;
+use Runtime;
+use RuntimeError;
+use Variable;
+
+type Factory = Box<dyn Fn() -> Runtime>;
+
+struct Mod {
+    name: String,
+    path: String,
+    priority: i32,
+    enabled: bool,
+    step_budget: Option<u64>,
+    time_budget: Option<Duration>,
+    module: Arc<Module>,
+    rt: Runtime,
+    last_error: Option<String>,
+}
+
+impl Mod {
+    /// Applies the step and time budgets of the mod to the next call.
+    fn set_budgets(&mut self) {
+        let mut settings = *self.rt.settings();
+        settings.instruction_budget = self.step_budget;
+        settings.time_budget = self.time_budget;
+        self.rt.set_settings(settings);
+    }
+
+    /// Runs a call into the mod, turning a panic into an error.
+    fn catch<T, F>(&mut self, f: F) -> Result<T, String>
+    where
+        F: FnOnce(&mut Mod) -> Result<T, String>,
+    {
+        let res = panic::catch_unwind(AssertUnwindSafe(|| f(self))).unwrap_or_else(|payload| {
+            let msg = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "Unknown panic".into());
+            Err(format!("Mod `{}` panicked: {}", self.name, msg))
+        });
+        if let Err(ref err) = res {
+            self.fail(err);
+        }
+        res
+    }
+
+    /// Records an error, cleaning up after the failed call.
+    fn fail(&mut self, err: &str) {
+        // A failed call might leave values behind.
+        self.rt.stack.clear();
+        self.rt.call_stack.clear();
+        self.rt.local_stack.clear();
+        self.rt.current_stack.clear();
+        self.last_error = Some(err.into());
+    }
+
+    fn call(&mut self, function: &str, args: &[Variable]) -> Result<Variable, String> {
+        self.catch(|m| m.call_unchecked(function, args))
+    }
+
+    fn call_unchecked(&mut self, function: &str, args: &[Variable]) -> Result<Variable, String> {
+        self.set_budgets();
+        match self.module.find_function(&Arc::new(function.into()), 0) {
+            FnIndex::Loaded(i) if self.module.functions[i as usize].returns() => {
+                Ok(self.rt.call_str_ret(function, args, &self.module)?)
+            }
+            _ => {
+                self.rt.call_str(function, args, &self.module)?;
+                Ok(Variable::Option(None))
+            }
+        }
+    }
+
+    fn tick(&mut self, dt: f64) -> Result<(), String> {
+        self.catch(|m| {
+            m.set_budgets();
+            m.rt.tick(dt)?;
+            let update: Arc<String> = Arc::new("update".into());
+            match m.module.find_function(&update, 0) {
+                FnIndex::Loaded(i) => {
+                    let args = if m.module.functions[i as usize].args.len() == 1 {
+                        vec![Variable::f64(dt)]
+                    } else {
+                        vec![]
+                    };
+                    m.call_unchecked(&update, &args).map(|_| ())
+                }
+                _ => Ok(()),
+            }
+        })
+    }
+}
+
+/// Loads scripts as mods and updates them once per tick, in order of priority.
+pub struct ModScheduler {
+    prelude: Module,
+    factory: Factory,
+    mods: Vec<Mod>,
+}
+
+impl Default for ModScheduler {
+    fn default() -> ModScheduler {
+        ModScheduler::new()
+    }
+}
+
+impl ModScheduler {
+    /// Creates a new scheduler, where mods can use the standard library.
+    pub fn new() -> ModScheduler {
+        ModScheduler::with_factory(Module::new(), Runtime::new)
+    }
+
+    /// Creates a new scheduler, where mods can use the external functions of `prelude`,
+    /// and run with runtimes created by a function,
+    /// e.g. to set a virtual file system or limits.
+    ///
+    /// Mods are loaded with the file system of their runtime.
+    pub fn with_factory<F>(prelude: Module, factory: F) -> ModScheduler
+    where
+        F: Fn() -> Runtime + 'static,
+    {
+        ModScheduler {
+            prelude,
+            factory: Box::new(factory),
+            mods: vec![],
+        }
+    }
+
+    fn load_module(&self, rt: &Runtime, path: &str) -> Result<Module, RuntimeError> {
+        let source = rt
+            .vfs()
+            .read_to_string(path)
+            .map_err(|err| format!("Could not open `{}`, {}", path, err))?;
+        let mut module = Module::empty();
+        module.import_ext_prelude(&self.prelude);
+        ::load_str(path, Arc::new(source), &mut module)?;
+        Ok(module)
+    }
+
+    fn find(&self, name: &str) -> Result<usize, String> {
+        self.mods
+            .iter()
+            .position(|m| m.name == name)
+            .ok_or_else(|| format!("Could not find mod `{}`", name))
+    }
+
+    /// Loads a mod from a file, replacing any mod with the same name.
+    ///
+    /// Mods with higher priority are updated first,
+    /// and mods with the same priority in the order they were loaded.
+    pub fn load(&mut self, name: &str, path: &str, priority: i32) -> Result<(), String> {
+        let rt = (self.factory)();
+        let module = self.load_module(&rt, path)?;
+        let step_budget = rt.settings().instruction_budget;
+        let time_budget = rt.settings().time_budget;
+        self.mods.retain(|m| m.name != name);
+        let i = self
+            .mods
+            .iter()
+            .position(|m| m.priority < priority)
+            .unwrap_or(self.mods.len());
+        self.mods.insert(
+            i,
+            Mod {
+                name: name.into(),
+                path: path.into(),
+                priority,
+                enabled: true,
+                step_budget,
+                time_budget,
+                module: Arc::new(module),
+                rt,
+                last_error: None,
+            },
+        );
+        Ok(())
+    }
+
+    /// Loads a mod again from its file, keeping its runtime.
+    ///
+    /// When loading fails, the mod keeps running the code it had.
+    pub fn reload(&mut self, name: &str) -> Result<(), String> {
+        let i = self.find(name)?;
+        let module = self.load_module(&self.mods[i].rt, &self.mods[i].path)?;
+        let m = &mut self.mods[i];
+        m.module = Arc::new(module);
+        m.last_error = None;
+        Ok(())
+    }
+
+    /// Removes a mod, returning `false` if there is no mod with the name.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let n = self.mods.len();
+        self.mods.retain(|m| m.name != name);
+        self.mods.len() != n
+    }
+
+    /// Enables or disables a mod. Disabled mods are not updated.
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> Result<(), String> {
+        let i = self.find(name)?;
+        self.mods[i].enabled = enabled;
+        Ok(())
+    }
+
+    /// Returns `true` if a mod is enabled.
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.mods.iter().any(|m| m.name == name && m.enabled)
+    }
+
+    /// Sets the number of steps a mod can use per call, or `None` for no limit.
+    ///
+    /// The budget starts as the instruction budget of the runtime of the mod.
+    /// A step is a statement or a loop iteration,
+    /// so this limits the work done by the script and not the time it takes.
+    pub fn set_step_budget(&mut self, name: &str, budget: Option<u64>) -> Result<(), String> {
+        let i = self.find(name)?;
+        self.mods[i].step_budget = budget;
+        Ok(())
+    }
+
+    /// Sets the wall-clock time a mod can use per call, or `None` for no limit.
+    ///
+    /// The budget starts as the time budget of the runtime of the mod.
+    /// Unlike the step budget, this also stops a mod that sleeps.
+    pub fn set_time_budget(&mut self, name: &str, budget: Option<Duration>) -> Result<(), String> {
+        let i = self.find(name)?;
+        self.mods[i].time_budget = budget;
+        Ok(())
+    }
+
+    /// Returns the names of the mods, in the order they are updated.
+    pub fn names(&self) -> Vec<&str> {
+        self.mods.iter().map(|m| &*m.name).collect()
+    }
+
+    /// Returns the last error of a mod, which is cleared by reloading.
+    pub fn last_error(&self, name: &str) -> Option<&str> {
+        self.mods
+            .iter()
+            .find(|m| m.name == name)
+            .and_then(|m| m.last_error.as_deref())
+    }
+
+    /// Calls a function of a mod, returning `none()` if it returns nothing.
+    ///
+    /// A panic in the call is returned as an error.
+    pub fn call(
+        &mut self,
+        name: &str,
+        function: &str,
+        args: &[Variable],
+    ) -> Result<Variable, String> {
+        let i = self.find(name)?;
+        self.mods[i].call(function, args)
+    }
+
+    /// Advances every enabled mod by `dt` seconds.
+    ///
+    /// This runs the timers of the mod and then calls `update`,
+    /// with `dt` as argument if `update` takes one.
+    /// Mods without `update` only run their timers.
+    ///
+    /// Returns the errors of this tick, with the names of the mods.
+    /// A mod that panics reports the panic as an error.
+    pub fn tick(&mut self, dt: f64) -> Vec<(String, String)> {
+        let mut errors = vec![];
+        for m in self.mods.iter_mut().filter(|m| m.enabled) {
+            if let Err(err) = m.tick(dt) {
+                errors.push((m.name.clone(), err));
+            }
+        }
+        errors
+    }
+}
@@ -1,11 +1,19 @@
 use super::*;
 
+use std::collections::HashSet;
+
 /// Stores functions for a Dyon module.
 #[derive(Clone)]
 pub struct Module {
     pub(crate) functions: Vec<ast::Function>,
     pub(crate) ext_prelude: Vec<FnExternal>,
     pub(crate) register_namespace: Arc<Vec<Arc<String>>>,
+    /// Compiled functions, by index.
+    pub(crate) compiled: Vec<Option<bytecode::Chunk>>,
+    /// Flags enabled for `#[cfg(..)]` attributes on loaded functions.
+    pub(crate) cfg: HashSet<Arc<String>>,
+    /// String literals and object keys shared by loaded functions.
+    pub(crate) strings: ast::Interner,
 }
 
 impl Default for Module {
@@ -21,6 +29,25 @@ impl Module {
             functions: vec![],
             ext_prelude: vec![],
             register_namespace: Arc::new(vec![]),
+            compiled: vec![],
+            cfg: features().into_iter().map(|f| Arc::new(f.into())).collect(),
+            strings: ast::Interner::default(),
+        }
+    }
+
+    /// Enables or disables a flag for conditional compilation.
+    ///
+    /// Functions marked with `#[cfg(name)]` are only loaded when the flag is enabled,
+    /// and functions marked with `#[cfg(not(name))]` only when it is disabled.
+    /// The crate features that Dyon is compiled with are enabled by default,
+    /// see `dyon::features`.
+    ///
+    /// This must be set before loading the source.
+    pub fn set_cfg(&mut self, name: &str, enabled: bool) {
+        if enabled {
+            self.cfg.insert(Arc::new(name.into()));
+        } else {
+            self.cfg.remove(&Arc::new(name.into()));
         }
     }
 
@@ -47,6 +74,8 @@ impl Module {
         for f in &other.functions {
             self.functions.push(f.clone())
         }
+        self.strings.extend(&other.strings);
+        self.compiled.clear();
     }
 
     /// Creates a new module with standard library.
@@ -154,6 +183,8 @@ impl Module {
                     ),
                     (vec![], vec![Bool; 2], Bool),
                     (vec![], vec![Vec4; 2], Bool),
+                    (vec![], vec![Type::Bytes; 2], Bool),
+                    (vec![], vec![Type::F64Array; 2], Bool),
                     (vec![], vec![Type::object(), Type::object()], Bool),
                     (vec![], vec![Type::array(), Type::array()], Bool),
                     (vec![], vec![Type::option(), Type::option()], Bool),
@@ -183,6 +214,8 @@ impl Module {
                     ),
                     (vec![], vec![Bool; 2], Bool),
                     (vec![], vec![Vec4; 2], Bool),
+                    (vec![], vec![Type::Bytes; 2], Bool),
+                    (vec![], vec![Type::F64Array; 2], Bool),
                     (vec![], vec![Type::object(), Type::object()], Bool),
                     (vec![], vec![Type::array(), Type::array()], Bool),
                     (vec![], vec![Type::option(), Type::option()], Bool),
@@ -394,12 +427,24 @@ impl Module {
         m.add_unop_str("norm", norm, Dfn::nl(vec![Vec4], F64));
         m.add_str("det", det, Dfn::nl(vec![Mat4], F64));
         m.add_str("inv", inv, Dfn::nl(vec![Mat4], Mat4));
+        m.add_str("transpose", transpose, Dfn::nl(vec![Mat4], Mat4));
+        m.add_str("mat_lerp", mat_lerp, Dfn::nl(vec![Mat4, Mat4, F64], Mat4));
+        m.add_str("decompose", decompose, Dfn::nl(vec![Mat4], Object));
         m.add_str("mov", mov, Dfn::nl(vec![Vec4], Mat4));
         m.add_str(
             "rot__axis_angle",
             rot__axis_angle,
             Dfn::nl(vec![Vec4, F64], Mat4),
         );
+        m.add_str(
+            "quat__axis_angle",
+            quat__axis_angle,
+            Dfn::nl(vec![Vec4, F64], Vec4),
+        );
+        m.add_str("quat_mul", quat_mul, Dfn::nl(vec![Vec4, Vec4], Vec4));
+        m.add_str("quat_rotate", quat_rotate, Dfn::nl(vec![Vec4, Vec4], Vec4));
+        m.add_str("slerp", slerp, Dfn::nl(vec![Vec4, Vec4, F64], Vec4));
+        m.add_str("quat_to_mat", quat_to_mat, Dfn::nl(vec![Vec4], Mat4));
         m.add_str(
             "ortho__pos_right_up_forward",
             ortho__pos_right_up_forward,
@@ -416,6 +461,42 @@ impl Module {
             Dfn::nl(vec![Mat4; 3], Mat4),
         );
         m.add_str("scale", scale, Dfn::nl(vec![Vec4], Mat4));
+        m.add_str("mov2", mov2, Dfn::nl(vec![Vec4], Mat4));
+        m.add_str("rot2", rot2, Dfn::nl(vec![F64], Mat4));
+        m.add_str("scale2", scale2, Dfn::nl(vec![Vec4], Mat4));
+        m.add_str("transform2", transform2, Dfn::nl(vec![Mat4, Vec4], Vec4));
+        m.add_str(
+            "add_all",
+            add_all,
+            Dfn::nl(
+                vec![Type::Array(Box::new(Vec4)), Type::Array(Box::new(Vec4))],
+                Type::Array(Box::new(Vec4)),
+            ),
+        );
+        m.add_str(
+            "scale_all",
+            scale_all,
+            Dfn::nl(
+                vec![Type::Array(Box::new(Vec4)), F64],
+                Type::Array(Box::new(Vec4)),
+            ),
+        );
+        m.add_str(
+            "dot_all",
+            dot_all,
+            Dfn::nl(
+                vec![Type::Array(Box::new(Vec4)), Type::Array(Box::new(Vec4))],
+                Type::Array(Box::new(F64)),
+            ),
+        );
+        m.add_str(
+            "transform_all",
+            transform_all,
+            Dfn::nl(
+                vec![Mat4, Type::Array(Box::new(Vec4))],
+                Type::Array(Box::new(Vec4)),
+            ),
+        );
         m.add_str("rx", rx, Dfn::nl(vec![Mat4], Vec4));
         m.add_str("ry", ry, Dfn::nl(vec![Mat4], Vec4));
         m.add_str("rz", rz, Dfn::nl(vec![Mat4], Vec4));
@@ -428,6 +509,7 @@ impl Module {
         m.add_str("clone", clone, Dfn::nl(vec![Any], Any));
         m.add_str("rv", rv, Dfn::nl(vec![Mat4, Type::F64], Vec4));
         m.add_str("s", s, Dfn::nl(vec![Vec4, F64], F64));
+        m.add_str("swizzle", swizzle, Dfn::nl(vec![Vec4, Str], Vec4));
         #[cfg(feature = "stdio")]
         m.add_str("println", println, Dfn::nl(vec![Any], Void));
         #[cfg(feature = "stdio")]
@@ -448,9 +530,41 @@ impl Module {
         m.add_str("abs", abs, Dfn::nl(vec![F64], F64));
         m.add_str("floor", floor, Dfn::nl(vec![F64], F64));
         m.add_str("ceil", ceil, Dfn::nl(vec![F64], F64));
+        m.add_str("lerp", lerp, Dfn::nl(vec![F64; 3], F64));
+        m.add_str("smoothstep", smoothstep, Dfn::nl(vec![F64; 3], F64));
+        m.add_str("clamp", clamp, Dfn::nl(vec![F64; 3], F64));
+        m.add_str("band", band, Dfn::nl(vec![F64; 2], F64));
+        m.add_str("bor", bor, Dfn::nl(vec![F64; 2], F64));
+        m.add_str("bxor", bxor, Dfn::nl(vec![F64; 2], F64));
+        m.add_str("bnot", bnot, Dfn::nl(vec![F64], F64));
+        m.add_str("shl", shl, Dfn::nl(vec![F64; 2], F64));
+        m.add_str("shr", shr, Dfn::nl(vec![F64; 2], F64));
         #[cfg(all(not(target_family = "wasm"), feature = "threading"))]
         m.add_str("sleep", sleep, Dfn::nl(vec![F64], Void));
+        m.add_str("yield_frame", yield_frame, Dfn::nl(vec![], Void));
+        m.add_str("sleep_until", sleep_until, Dfn::nl(vec![F64], Void));
+        m.add_str(
+            "after",
+            after,
+            Dfn::nl(
+                vec![F64, Type::Closure(Box::new(Dfn::nl(vec![], Void)))],
+                F64,
+            ),
+        );
+        m.add_str(
+            "every",
+            every,
+            Dfn::nl(
+                vec![F64, Type::Closure(Box::new(Dfn::nl(vec![], Void)))],
+                F64,
+            ),
+        );
+        m.add_str("cancel_timer", cancel_timer, Dfn::nl(vec![F64], Bool));
         m.add_str("random", random, Dfn::nl(vec![], F64));
+        m.add_str("seed", seed, Dfn::nl(vec![F64], Void));
+        m.add_str("random_range", random_range, Dfn::nl(vec![F64; 2], F64));
+        m.add_str("random_int", random_int, Dfn::nl(vec![F64; 2], F64));
+        m.add_str("shuffle(mut)", shuffle, Dfn::nl(vec![Type::array()], Void));
         m.add_str("tau", tau, Dfn::nl(vec![], F64));
         #[cfg(feature = "stdio")]
         m.add_str("read_line", read_line, Dfn::nl(vec![], Str));
@@ -464,8 +578,57 @@ impl Module {
         m.add_str("trim", trim, Dfn::nl(vec![Str], Str));
         m.add_str("trim_left", trim_left, Dfn::nl(vec![Str], Str));
         m.add_str("trim_right", trim_right, Dfn::nl(vec![Str], Str));
+        m.add_str(
+            "split",
+            split,
+            Dfn::nl(vec![Str, Str], Type::Array(Box::new(Str))),
+        );
+        m.add_str(
+            "join",
+            join,
+            Dfn::nl(vec![Type::Array(Box::new(Str)), Str], Str),
+        );
+        m.add_str("replace", replace, Dfn::nl(vec![Str, Str, Str], Str));
+        m.add_str("starts_with", starts_with, Dfn::nl(vec![Str, Str], Bool));
+        m.add_str("ends_with", ends_with, Dfn::nl(vec![Str, Str], Bool));
+        m.add_str("contains", contains, Dfn::nl(vec![Str, Str], Bool));
+        m.add_str("edit_distance", edit_distance, Dfn::nl(vec![Str, Str], F64));
+        m.add_str("similarity", similarity, Dfn::nl(vec![Str, Str], F64));
+        m.add_str(
+            "fuzzy_match",
+            fuzzy_match,
+            Dfn::nl(
+                vec![Str, Type::Array(Box::new(Str))],
+                Type::Array(Box::new(Object)),
+            ),
+        );
+        m.add_str("regex_match", regex_match, Dfn::nl(vec![Str, Str], Bool));
+        m.add_str(
+            "regex_captures",
+            regex_captures,
+            Dfn::nl(
+                vec![Str, Str],
+                Type::Option(Box::new(Type::Array(Box::new(Str)))),
+            ),
+        );
+        m.add_str(
+            "regex_replace",
+            regex_replace,
+            Dfn::nl(vec![Str, Str, Str], Str),
+        );
         m.add_str("str", _str, Dfn::nl(vec![Any], Str));
+        m.add_str("bigint", bigint, Dfn::nl(vec![Any], Any));
         m.add_str("json_string", json_string, Dfn::nl(vec![Str], Str));
+        m.add_str(
+            "str__max_depth",
+            str__max_depth,
+            Dfn::nl(vec![Any, F64], Str),
+        );
+        m.add_str(
+            "json_string__max_depth",
+            json_string__max_depth,
+            Dfn::nl(vec![Any, F64], Str),
+        );
         m.add_str("str__color", str__color, Dfn::nl(vec![Vec4], Str));
         m.add_str(
             "srgb_to_linear__color",
@@ -516,11 +679,88 @@ impl Module {
             save__string_file,
             Dfn::nl(vec![Type::Str; 2], Type::Result(Box::new(Str))),
         );
+        m.add_str(
+            "save_atomic__string_file",
+            save_atomic__string_file,
+            Dfn::nl(vec![Type::Str; 2], Type::Result(Box::new(Str))),
+        );
+        m.add_str(
+            "save__string_file_atomic",
+            save__string_file_atomic,
+            Dfn::nl(vec![Str, Str, Bool], Type::Result(Box::new(Str))),
+        );
+        m.add_str(
+            "sha256__file",
+            sha256__file,
+            Dfn::nl(vec![Str], Type::Result(Box::new(Str))),
+        );
+        m.add_str(
+            "verify__file_sha256",
+            verify__file_sha256,
+            Dfn::nl(vec![Str; 2], Type::Result(Box::new(Bool))),
+        );
         m.add_str(
             "load_string__file",
             load_string__file,
             Dfn::nl(vec![Str], Type::Result(Box::new(Str))),
         );
+        m.add_str(
+            "load_bytes__file",
+            load_bytes__file,
+            Dfn::nl(vec![Str], Type::Result(Box::new(Type::Bytes))),
+        );
+        m.add_str(
+            "save_bytes__file",
+            save_bytes__file,
+            Dfn::nl(vec![Type::Bytes, Str], Type::Result(Box::new(Str))),
+        );
+        m.add_str("byte", byte, Dfn::nl(vec![Type::Bytes, F64], F64));
+        m.add_str("bytes_len", bytes_len, Dfn::nl(vec![Type::Bytes], F64));
+        m.add_str(
+            "slice_bytes",
+            slice_bytes,
+            Dfn::nl(vec![Type::Bytes, F64, F64], Type::Bytes),
+        );
+        m.add_str(
+            "slice",
+            slice,
+            Dfn::nl(vec![Type::array(), F64, F64], Type::array()),
+        );
+        m.add_str(
+            "str_from_utf8",
+            str_from_utf8,
+            Dfn::nl(vec![Type::Bytes], Type::Result(Box::new(Str))),
+        );
+        m.add_str(
+            "bytes_from_str",
+            bytes_from_str,
+            Dfn::nl(vec![Str], Type::Bytes),
+        );
+        m.add_str("hash", hash, Dfn::nl(vec![Any], F64));
+        m.add_str("crc32", crc32, Dfn::nl(vec![Any], F64));
+        m.add_str("sha256", sha256, Dfn::nl(vec![Any], Str));
+        m.add_str("content_hash", content_hash, Dfn::nl(vec![Any], Str));
+        m.add_str("to_msgpack", to_msgpack, Dfn::nl(vec![Any], Type::Bytes));
+        m.add_str(
+            "from_msgpack",
+            from_msgpack,
+            Dfn::nl(vec![Type::Bytes], Type::Result(Box::new(Any))),
+        );
+        m.add_str(
+            "sync_encode",
+            sync_encode,
+            Dfn::nl(vec![Any; 2], Type::Bytes),
+        );
+        m.add_str(
+            "sync_encode__interest",
+            sync_encode__interest,
+            Dfn::nl(vec![Any, Any, Type::Array(Box::new(Str))], Type::Bytes),
+        );
+        m.add_str(
+            "sync_apply",
+            sync_apply,
+            Dfn::nl(vec![Any, Type::Bytes], Type::Result(Box::new(Any))),
+        );
         m.add_str(
             "load_string__url",
             load_string__url,
@@ -537,17 +777,244 @@ impl Module {
             load_data__file,
             Dfn::nl(vec![Str], Type::Result(Box::new(Any))),
         );
+        m.add_str(
+            "load_model__file",
+            load_model__file,
+            Dfn::nl(vec![Str], Type::Result(Box::new(Any))),
+        );
+        m.add_str(
+            "infer",
+            infer,
+            Dfn::nl(
+                vec![Any, Type::Array(Box::new(F64))],
+                Type::Array(Box::new(F64)),
+            ),
+        );
+        m.add_str(
+            "migrations",
+            migrations,
+            Dfn::nl(vec![Type::Array(Box::new(Object))], Any),
+        );
+        m.add_str(
+            "migrate",
+            migrate,
+            Dfn::nl(vec![Any, Object], Type::Result(Box::new(Object))),
+        );
         m.add_str(
             "load_data__string",
             load_data__string,
             Dfn::nl(vec![Str], Type::Result(Box::new(Any))),
         );
+        m.add_str(
+            "load_toml__file",
+            load_toml__file,
+            Dfn::nl(vec![Str], Type::Result(Box::new(Any))),
+        );
+        m.add_str(
+            "load_toml__string",
+            load_toml__string,
+            Dfn::nl(vec![Str], Type::Result(Box::new(Any))),
+        );
+        m.add_str(
+            "load_yaml__file",
+            load_yaml__file,
+            Dfn::nl(vec![Str], Type::Result(Box::new(Any))),
+        );
+        m.add_str(
+            "load_yaml__string",
+            load_yaml__string,
+            Dfn::nl(vec![Str], Type::Result(Box::new(Any))),
+        );
         m.add_str(
             "args_os",
             args_os,
             Dfn::nl(vec![], Type::Array(Box::new(Str))),
         );
+        m.add_str(
+            "exec",
+            exec,
+            Dfn::nl(
+                vec![Str, Type::Array(Box::new(Str))],
+                Type::Result(Box::new(Object)),
+            ),
+        );
+        m.add_str(
+            "exec_stream",
+            exec_stream,
+            Dfn::nl(
+                vec![Str, Type::Array(Box::new(Str))],
+                Type::Result(Box::new(Type::In(Box::new(Str)))),
+            ),
+        );
         m.add_str("now", now, Dfn::nl(vec![], F64));
+        m.add_str("clock", clock, Dfn::nl(vec![], F64));
+        m.add_str("datetime", datetime, Dfn::nl(vec![F64], Object));
+        m.add_str("format_time", format_time, Dfn::nl(vec![F64, Str], Str));
+        m.add_str(
+            "parse_time",
+            parse_time,
+            Dfn::nl(vec![Str, Str], Type::Result(Box::new(F64))),
+        );
+        m.add_str(
+            "parse_expr",
+            parse_expr,
+            Dfn::nl(vec![Str], Type::Result(Box::new(Any))),
+        );
+        m.add_str(
+            "eval_expr",
+            eval_expr,
+            Dfn::nl(vec![Any, Object], Type::Result(Box::new(F64))),
+        );
+        m.add_str(
+            "spline",
+            spline,
+            Dfn::nl(vec![Type::Array(Box::new(Vec4))], Any),
+        );
+        m.add_str("eval", eval, Dfn::nl(vec![Any, F64], Vec4));
+        m.add_str("length", length, Dfn::nl(vec![Any], F64));
+        m.add_str(
+            "sample",
+            sample,
+            Dfn::nl(vec![Any, F64], Type::Array(Box::new(Vec4))),
+        );
+        m.add_str(
+            "cellular_automata",
+            cellular_automata,
+            Dfn::nl(
+                vec![
+                    Type::Array(Box::new(Type::Array(Box::new(Bool)))),
+                    Object,
+                    F64,
+                ],
+                Type::Array(Box::new(Type::Array(Box::new(Bool)))),
+            ),
+        );
+        m.add_str(
+            "drunkard_walk",
+            drunkard_walk,
+            Dfn::nl(
+                vec![Vec4, F64],
+                Type::Array(Box::new(Type::Array(Box::new(Bool)))),
+            ),
+        );
+        m.add_str(
+            "poisson_disc",
+            poisson_disc,
+            Dfn::nl(vec![Vec4, F64], Type::Array(Box::new(Vec4))),
+        );
+        m.add_str("frame", frame, Dfn::nl(vec![Object], Any));
+        m.add_str("frame_len", frame_len, Dfn::nl(vec![Any], F64));
+        m.add_str("frame_data", frame_data, Dfn::nl(vec![Any], Object));
+        m.add_str(
+            "frame_select",
+            frame_select,
+            Dfn::nl(vec![Any, Type::Array(Box::new(Str))], Any),
+        );
+        m.add_str(
+            "frame_filter",
+            frame_filter,
+            Dfn::nl(
+                vec![Any, Type::Closure(Box::new(Dfn::nl(vec![Object], Bool)))],
+                Any,
+            ),
+        );
+        m.add_str("frame_sort", frame_sort, Dfn::nl(vec![Any, Str, Bool], Any));
+        m.add_str("frame_head", frame_head, Dfn::nl(vec![Any, F64], Any));
+        m.add_str(
+            "frame_group_agg",
+            frame_group_agg,
+            Dfn::nl(vec![Any, Str, Object], Any),
+        );
+        m.add_str(
+            "frame_from_csv",
+            frame_from_csv,
+            Dfn::nl(vec![Str], Type::Result(Box::new(Any))),
+        );
+        m.add_str("frame_to_csv", frame_to_csv, Dfn::nl(vec![Any], Str));
+        m.add_str(
+            "query",
+            query,
+            Dfn::nl(vec![Type::array(), Str], Type::array()),
+        );
+        m.add_str(
+            "kmeans",
+            kmeans,
+            Dfn::nl(vec![Type::array(), F64, F64], Object),
+        );
+        m.add_str(
+            "dbscan",
+            dbscan,
+            Dfn::nl(vec![Type::array(), F64, F64], Type::Array(Box::new(F64))),
+        );
+        m.add_str("perlin2", perlin2, Dfn::nl(vec![Vec4], F64));
+        m.add_str("perlin3", perlin3, Dfn::nl(vec![Vec4], F64));
+        m.add_str("simplex2", simplex2, Dfn::nl(vec![Vec4], F64));
+        m.add_str(
+            "solve_linear",
+            solve_linear,
+            Dfn::nl(
+                vec![Type::Array(Box::new(Object)), Object],
+                Type::Result(Box::new(Object)),
+            ),
+        );
+        m.add_str(
+            "solve_csp",
+            solve_csp,
+            Dfn::nl(
+                vec![Object, Type::Array(Box::new(Object))],
+                Type::Result(Box::new(Object)),
+            ),
+        );
+        m.add_str("derivative", derivative, Dfn::nl(vec![Any, Str], Any));
+        m.add_str(
+            "minimize",
+            minimize,
+            Dfn::nl(vec![Any, Any, Object], Type::Result(Box::new(Object))),
+        );
+        m.add_str(
+            "bisect",
+            bisect,
+            Dfn::nl(vec![Any, F64, F64], Type::Result(Box::new(F64))),
+        );
+        m.add_str(
+            "newton",
+            newton,
+            Dfn::nl(vec![Any, Any, F64], Type::Result(Box::new(F64))),
+        );
+        m.add_str(
+            "integrate_euler",
+            integrate_euler,
+            Dfn::nl(vec![Any, Any, F64, F64, F64], Type::array()),
+        );
+        m.add_str(
+            "integrate_rk4",
+            integrate_rk4,
+            Dfn::nl(vec![Any, Any, F64, F64, F64], Type::array()),
+        );
+        m.add_str("cells", cells, Dfn::nl(vec![], Any));
+        m.add_str(
+            "set_formula",
+            set_formula,
+            Dfn::nl(vec![Any, Str, Any], Void),
+        );
+        m.add_str(
+            "get",
+            get,
+            Dfn::nl(vec![Any, Str], Type::Result(Box::new(F64))),
+        );
+        m.add_str("flag", flag, Dfn::nl(vec![Str], Bool));
+        m.add_str(
+            "features",
+            features,
+            Dfn::nl(vec![], Type::Array(Box::new(Str))),
+        );
+        m.add_str(
+            "disabled_functions",
+            disabled_functions,
+            Dfn::nl(vec![], Type::Array(Box::new(Str))),
+        );
+        m.add_str("run_manifest", run_manifest, Dfn::nl(vec![], Object));
+        m.add_str("profile_report", profile_report, Dfn::nl(vec![], Object));
         m.add_str("is_nan", is_nan, Dfn::nl(vec![F64], Bool));
         m.add_str("load", load, Dfn::nl(vec![Str], Type::result()));
         m.add_str(
@@ -555,6 +1022,11 @@ impl Module {
             load__source_imports,
             Dfn::nl(vec![Str, Type::array()], Type::result()),
         );
+        m.add_str(
+            "sandbox",
+            sandbox,
+            Dfn::nl(vec![Type::Array(Box::new(Str)), Str], Type::result()),
+        );
         m.add_str(
             "module__in_string_imports",
             module__in_string_imports,
@@ -568,22 +1040,132 @@ impl Module {
                 Type::Result(Box::new(Type::Array(Box::new(Type::Object)))),
             ),
         );
+        m.add_str("fmt", fmt, Dfn::nl(vec![Str], Type::Result(Box::new(Str))));
         m.add_str("call", _call, Dfn::nl(vec![Any, Str, Type::array()], Void));
         m.add_str(
             "call_ret",
             call_ret,
             Dfn::nl(vec![Any, Str, Type::array()], Any),
         );
+        m.add_str(
+            "rpc_serve",
+            rpc_serve,
+            Dfn::nl(vec![Str, Any], Type::Result(Box::new(Str))),
+        );
+        m.add_str(
+            "rpc_connect",
+            rpc_connect,
+            Dfn::nl(vec![Str], Type::Result(Box::new(Any))),
+        );
         m.add_str("functions", functions, Dfn::nl(vec![], Any));
+        m.add_str("find_fn", find_fn, Dfn::nl(vec![Str], Type::array()));
         m.add_str(
             "functions__module",
             functions__module,
             Dfn::nl(vec![Any], Any),
         );
+        m.add_str(
+            "glsl_of",
+            glsl_of,
+            Dfn::nl(vec![Str], Type::Result(Box::new(Str))),
+        );
+        m.add_str(
+            "rust_struct_of",
+            rust_struct_of,
+            Dfn::nl(vec![Any, Str], Str),
+        );
+        m.add_str(
+            "rust_struct_check",
+            rust_struct_check,
+            Dfn::nl(vec![Any, Str, Str], Type::Result(Box::new(Bool))),
+        );
+        m.add_str("display_html", display_html, Dfn::nl(vec![Str], Void));
+        m.add_str("display_png", display_png, Dfn::nl(vec![Any], Void));
+        m.add_str("weak", weak, Dfn::nl(vec![Any], Any));
+        m.add_str(
+            "upgrade",
+            upgrade,
+            Dfn::nl(vec![Any], Type::Option(Box::new(Any))),
+        );
+        m.add_str("is_ref", is_ref, Dfn::nl(vec![Any], Bool));
+        m.add_str("deref", deref, Dfn::nl(vec![Any], Any));
+        m.add_str("gc_new", gc_new, Dfn::nl(vec![Any], Any));
+        m.add_str("gc_get", gc_get, Dfn::nl(vec![Any], Any));
+        m.add_str("gc_set", gc_set, Dfn::nl(vec![Any, Any], Void));
+        m.add_str("gc_collect", gc_collect, Dfn::nl(vec![], F64));
+        m.add_str("pvec", pvec, Dfn::nl(vec![Type::array()], Any));
+        m.add_str("pmap", pmap, Dfn::nl(vec![Object], Any));
+        m.add_str("passoc", passoc, Dfn::nl(vec![Any, Any, Any], Any));
+        m.add_str(
+            "pupdate",
+            pupdate,
+            Dfn::nl(
+                vec![Any, Any, Type::Closure(Box::new(Dfn::nl(vec![Any], Any)))],
+                Any,
+            ),
+        );
+        m.add_str(
+            "pget",
+            pget,
+            Dfn::nl(vec![Any, Any], Type::Option(Box::new(Any))),
+        );
+        m.add_str("plen", plen, Dfn::nl(vec![Any], F64));
+        m.add_str("pcollect", pcollect, Dfn::nl(vec![Any], Any));
+        m.add_str("string_builder", string_builder, Dfn::nl(vec![], Any));
+        m.add_str("sb_push", sb_push, Dfn::nl(vec![Any, Str], Void));
+        m.add_str("sb_push_num", sb_push_num, Dfn::nl(vec![Any, F64], Void));
+        m.add_str("sb_len", sb_len, Dfn::nl(vec![Any], F64));
+        m.add_str("sb_str", sb_str, Dfn::nl(vec![Any], Str));
+        m.add_str("str__builder", str__builder, Dfn::nl(vec![Any, Any], Void));
+        m.add_str(
+            "json_string__builder",
+            json_string__builder,
+            Dfn::nl(vec![Any, Any], Void),
+        );
+        m.add_str("text_buffer", text_buffer, Dfn::nl(vec![Str], Any));
+        m.add_str("insert_at", insert_at, Dfn::nl(vec![Any, F64, Str], Void));
+        m.add_str(
+            "delete_range",
+            delete_range,
+            Dfn::nl(vec![Any, F64, F64], Void),
+        );
+        m.add_str("line", line, Dfn::nl(vec![Any, F64], Str));
+        m.add_str("line_count", line_count, Dfn::nl(vec![Any], F64));
+        m.add_str("text_len", text_len, Dfn::nl(vec![Any], F64));
+        m.add_str("to_str", to_str, Dfn::nl(vec![Any], Str));
         m.add_str("is_err", is_err, Dfn::nl(vec![Type::result()], Bool));
         m.add_str("is_ok", is_ok, Dfn::nl(vec![Type::result()], Bool));
-        m.add_str("min", min, Dfn::nl(vec![Type::Array(Box::new(F64))], F64));
-        m.add_str("max", max, Dfn::nl(vec![Type::Array(Box::new(F64))], F64));
+        let numbers = Dfn {
+            lts: vec![Lt::Default],
+            tys: vec![Any],
+            ret: F64,
+            ext: vec![
+                (vec![], vec![Type::Array(Box::new(F64))], F64),
+                (vec![], vec![Type::F64Array], F64),
+            ],
+            lazy: LAZY_NO,
+        };
+        m.add_str("min", min, numbers.clone());
+        m.add_str("max", max, numbers.clone());
+        m.add_str("sum", sum, numbers.clone());
+        m.add_str("prod", prod, numbers);
+        m.add_str("farr", farr, Dfn::nl(vec![Any], Type::F64Array));
+        m.add_str("farr_zeros", farr_zeros, Dfn::nl(vec![F64], Type::F64Array));
+        m.add_str(
+            "arr_from_farr",
+            arr_from_farr,
+            Dfn::nl(vec![Type::F64Array], Type::Array(Box::new(F64))),
+        );
+        m.add_str(
+            "farr_get",
+            farr_get,
+            Dfn::nl(vec![Type::F64Array, F64], F64),
+        );
+        m.add_str(
+            "farr_set(mut,_,_)",
+            farr_set,
+            Dfn::nl(vec![Type::F64Array, F64, F64], Void),
+        );
         m.add_str("unwrap", unwrap, Dfn::nl(vec![Any], Any));
         m.add_str(
             "why",
@@ -610,7 +1192,20 @@ impl Module {
         m.add_str("tail", tail, Dfn::nl(vec![Link], Link));
         m.add_str("neck", neck, Dfn::nl(vec![Link], Link));
         m.add_str("is_empty", is_empty, Dfn::nl(vec![Link], Bool));
-        m.add_unop_str("len", len, Dfn::nl(vec![Type::array()], F64));
+        m.add_unop_str(
+            "len",
+            len,
+            Dfn {
+                lts: vec![Lt::Default],
+                tys: vec![Any],
+                ret: F64,
+                ext: vec![
+                    (vec![], vec![Type::array()], F64),
+                    (vec![], vec![Type::F64Array], F64),
+                ],
+                lazy: LAZY_NO,
+            },
+        );
         m.add_str(
             "push_ref(mut,_)",
             push_ref,
@@ -639,9 +1234,12 @@ impl Module {
             insert,
             Dfn {
                 lts: vec![Lt::Default; 3],
-                tys: vec![Type::array(), F64, Any],
+                tys: vec![Any; 3],
                 ret: Void,
-                ext: vec![],
+                ext: vec![
+                    (vec![], vec![Type::array(), F64, Any], Void),
+                    (vec![], vec![Object, Str, Any], Void),
+                ],
                 lazy: LAZY_NO,
             },
         );
@@ -669,6 +1267,52 @@ impl Module {
         );
         m.add_str("reverse(mut)", reverse, Dfn::nl(vec![Type::array()], Void));
         m.add_str("clear(mut)", clear, Dfn::nl(vec![Type::array()], Void));
+        m.add_str("sort(mut)", sort, Dfn::nl(vec![Type::array()], Void));
+        m.add_str(
+            "sort_by(mut,_)",
+            sort_by,
+            Dfn::nl(
+                vec![
+                    Type::array(),
+                    Type::Closure(Box::new(Dfn::nl(vec![Any, Any], F64))),
+                ],
+                Void,
+            ),
+        );
+        m.add_str(
+            "map",
+            map,
+            Dfn::nl(
+                vec![
+                    Type::array(),
+                    Type::Closure(Box::new(Dfn::nl(vec![Any], Any))),
+                ],
+                Type::array(),
+            ),
+        );
+        m.add_str(
+            "filter",
+            filter,
+            Dfn::nl(
+                vec![
+                    Type::array(),
+                    Type::Closure(Box::new(Dfn::nl(vec![Any], Bool))),
+                ],
+                Type::array(),
+            ),
+        );
+        m.add_str(
+            "fold",
+            fold,
+            Dfn::nl(
+                vec![
+                    Type::array(),
+                    Any,
+                    Type::Closure(Box::new(Dfn::nl(vec![Any, Any], Any))),
+                ],
+                Any,
+            ),
+        );
         m.add_str(
             "swap(mut,_,_)",
             swap,
@@ -686,6 +1330,11 @@ impl Module {
             },
         );
         m.add_str("unwrap_err", unwrap_err, Dfn::nl(vec![Any], Any));
+        m.add_str(
+            "try",
+            try_call,
+            Dfn::nl(vec![Any], Type::Result(Box::new(Any))),
+        );
         m.add_str(
             "meta__syntax_in_string",
             meta__syntax_in_string,
@@ -699,6 +1348,78 @@ impl Module {
             save__data_file,
             Dfn::nl(vec![Any, Str], Str),
         );
+        m.add_str(
+            "save__data_file_atomic",
+            save__data_file_atomic,
+            Dfn::nl(vec![Any, Str, Bool], Type::Result(Box::new(Str))),
+        );
+        m.add_str(
+            "mmap",
+            mmap,
+            Dfn::nl(vec![Str], Type::Result(Box::new(Any))),
+        );
+        m.add_str("mmap_len", mmap_len, Dfn::nl(vec![Any], F64));
+        m.add_str("read_f32_at", read_f32_at, Dfn::nl(vec![Any, F64], F64));
+        m.add_str(
+            "read_bytes_at",
+            read_bytes_at,
+            Dfn::nl(vec![Any, F64, F64], Type::Array(Box::new(F64))),
+        );
+        m.add_str(
+            "lock_file",
+            lock_file,
+            Dfn::nl(vec![Str], Type::Result(Box::new(Any))),
+        );
+        m.add_str(
+            "lock_file_shared",
+            lock_file_shared,
+            Dfn::nl(vec![Str], Type::Result(Box::new(Any))),
+        );
+        m.add_str("unlock", unlock, Dfn::nl(vec![Any], Void));
+        m.add_str(
+            "temp_file",
+            temp_file,
+            Dfn::nl(vec![Str], Type::Result(Box::new(Any))),
+        );
+        m.add_str(
+            "temp_dir",
+            temp_dir,
+            Dfn::nl(vec![], Type::Result(Box::new(Any))),
+        );
+        m.add_str("temp_path", temp_path, Dfn::nl(vec![Any], Str));
+        m.add_str(
+            "read_dir",
+            read_dir,
+            Dfn::nl(
+                vec![Str],
+                Type::Result(Box::new(Type::Array(Box::new(Str)))),
+            ),
+        );
+        m.add_str(
+            "create_dir",
+            create_dir,
+            Dfn::nl(vec![Str], Type::Result(Box::new(Str))),
+        );
+        m.add_str(
+            "remove_file",
+            remove_file,
+            Dfn::nl(vec![Str], Type::Result(Box::new(Str))),
+        );
+        m.add_str("file_exists", file_exists, Dfn::nl(vec![Str], Bool));
+        m.add_str("is_dir", is_dir, Dfn::nl(vec![Str], Bool));
+        m.add_str(
+            "file_size",
+            file_size,
+            Dfn::nl(vec![Str], Type::Result(Box::new(F64))),
+        );
+        m.add_str(
+            "data_writer",
+            data_writer,
+            Dfn::nl(vec![Str], Type::Result(Box::new(Any))),
+        );
+        m.add_str("begin_array", begin_array, Dfn::nl(vec![Any], Void));
+        m.add_str("write_item", write_item, Dfn::nl(vec![Any, Any], Void));
+        m.add_str("end_array", end_array, Dfn::nl(vec![Any], Void));
         m.add_str(
             "json_from_meta_data",
             json_from_meta_data,
@@ -715,6 +1436,23 @@ impl Module {
             keys,
             Dfn::nl(vec![Object], Type::Array(Box::new(Str))),
         );
+        m.add_str(
+            "remove_key(mut,_)",
+            remove_key,
+            Dfn {
+                lts: vec![Lt::Return, Lt::Default],
+                tys: vec![Object, Str],
+                ret: Type::option(),
+                ext: vec![],
+                lazy: LAZY_NO,
+            },
+        );
+        m.add_str(
+            "entries",
+            entries,
+            Dfn::nl(vec![Object], Type::Array(Box::new(Type::array()))),
+        );
+        m.add_str("values", values, Dfn::nl(vec![Object], Type::array()));
         m.add_str(
             "chars",
             chars,
@@ -722,11 +1460,126 @@ impl Module {
         );
         m.add_str("wait_next", wait_next, Dfn::nl(vec![Type::in_ty()], Any));
         m.add_str("next", next, Dfn::nl(vec![Type::in_ty()], Type::option()));
+        m.add_str("channel", channel, Dfn::nl(vec![], Object));
+        m.add_str("send", send, Dfn::nl(vec![Type::out_ty(), Any], Bool));
+        m.add_str(
+            "checked_channel",
+            checked_channel,
+            Dfn::nl(vec![Object, Object], Object),
+        );
+        m.add_str(
+            "actor",
+            actor,
+            Dfn::nl(
+                vec![Any, Type::Closure(Box::new(Dfn::nl(vec![Any, Any], Any)))],
+                Any,
+            ),
+        );
+        m.add_str(
+            "ask",
+            ask,
+            Dfn::nl(vec![Any, Any], Type::Result(Box::new(Any))),
+        );
+        m.add_str("supervisor", supervisor, Dfn::nl(vec![F64], Any));
+        m.add_str(
+            "supervise",
+            supervise,
+            Dfn::nl(
+                vec![Any, Str, Type::Closure(Box::new(Dfn::nl(vec![], Any)))],
+                Void,
+            ),
+        );
+        m.add_str("failures", failures, Dfn::nl(vec![Any], Type::in_ty()));
+        m.add_str(
+            "gpu_map",
+            gpu_map,
+            Dfn::nl(
+                vec![Type::Array(Box::new(F64)), Str],
+                Type::Result(Box::new(Type::Array(Box::new(F64)))),
+            ),
+        );
+        m.add_str(
+            "gpu_mat_mul",
+            gpu_mat_mul,
+            Dfn::nl(
+                vec![Type::Array(Box::new(Type::Array(Box::new(F64)))); 2],
+                Type::Result(Box::new(Type::Array(Box::new(Type::Array(Box::new(F64)))))),
+            ),
+        );
+        m.add_str(
+            "parallel_for",
+            parallel_for,
+            Dfn::nl(
+                vec![
+                    Type::Array(Box::new(F64)),
+                    F64,
+                    Type::Closure(Box::new(Dfn::nl(vec![F64], Any))),
+                ],
+                Type::array(),
+            ),
+        );
+        m.add_str(
+            "parallel_reduce",
+            parallel_reduce,
+            Dfn::nl(
+                vec![
+                    Type::Array(Box::new(F64)),
+                    F64,
+                    Type::Closure(Box::new(Dfn::nl(vec![F64], Any))),
+                    Type::Closure(Box::new(Dfn::nl(vec![Any, Any], Any))),
+                ],
+                Type::option(),
+            ),
+        );
+        m.add_str("topics", topics, Dfn::nl(vec![], Any));
+        m.add_str("topics__sticky", topics__sticky, Dfn::nl(vec![Bool], Any));
+        m.add_str("publish", publish, Dfn::nl(vec![Any, Str, Any], F64));
+        m.add_str(
+            "subscribe",
+            subscribe,
+            Dfn::nl(vec![Any, Str], Type::in_ty()),
+        );
+        m.add_str("shared", shared, Dfn::nl(vec![Any], Any));
+        m.add_str(
+            "lock_read",
+            lock_read,
+            Dfn::nl(
+                vec![Any, Type::Closure(Box::new(Dfn::nl(vec![Any], Any)))],
+                Any,
+            ),
+        );
+        m.add_str(
+            "lock_write(mut,_)",
+            lock_write,
+            Dfn::nl(
+                vec![Any, Type::Closure(Box::new(Dfn::nl(vec![Any], Any)))],
+                Void,
+            ),
+        );
+        m.add_str("counter", counter, Dfn::nl(vec![Str], Any));
+        m.add_str("inc", inc, Dfn::nl(vec![Any, F64], Void));
+        m.add_str("histogram", histogram, Dfn::nl(vec![Str], Any));
+        m.add_str("observe", observe, Dfn::nl(vec![Any, F64], Void));
+        m.add_str("metrics_report", metrics_report, Dfn::nl(vec![], Object));
+        m.add_str(
+            "metrics_prometheus",
+            metrics_prometheus,
+            Dfn::nl(vec![], Str),
+        );
 
         m.no_ns();
         m
     }
 
+    /// Loads the standard library written in Dyon.
+    ///
+    /// This adds helper functions for strings, arrays and math,
+    /// e.g. `repeat`, `pad_left`, `range`, `any_of`, `zip`, `clamp` and `lerp`.
+    /// See `src/std.dyon` for the full list.
+    pub fn add_std_dyon(&mut self) -> Result<(), String> {
+        load_str("std.dyon", Arc::new(STD_DYON.into()), self)
+    }
+
     /// Sets namespace for following added functions.
     pub fn ns(&mut self, ns: &str) {
         self.register_namespace = Arc::new(ns.split("::").map(|s| Arc::new(s.into())).collect());
@@ -737,8 +1590,52 @@ impl Module {
         self.register_namespace = Arc::new(vec![]);
     }
 
-    pub(crate) fn register(&mut self, function: ast::Function) {
+    pub(crate) fn register(&mut self, mut function: ast::Function) {
+        // Share string literals and object keys with previously loaded functions.
+        self.strings.function(&mut function);
         self.functions.push(function);
+        // Calls might resolve to the new function.
+        self.compiled.clear();
+    }
+
+    /// Optimizes loaded functions, after loading all sources.
+    ///
+    /// Computes operators on constants, including `vec4` and `mat4` constructors,
+    /// and removes branches of `if` expressions that are never taken.
+    /// Calls are resolved to function indices when loading, so they need no work here.
+    ///
+    /// Call this before `compile`, since it discards compiled code.
+    /// Returns the number of simplified expressions.
+    pub fn finish(&mut self) -> usize {
+        let n = ast::optimize(&mut self.functions);
+        self.compiled.clear();
+        n
+    }
+
+    /// Compiles loaded functions to bytecode.
+    ///
+    /// Compiled functions run faster than the interpreter,
+    /// which is still used for functions that can not be compiled.
+    /// See the `bytecode` module for what is supported.
+    ///
+    /// Loading more functions into the module discards compiled code,
+    /// so call this after loading.
+    /// Returns the number of compiled functions.
+    pub fn compile(&mut self) -> usize {
+        self.compiled = bytecode::compile(self);
+        self.compiled.iter().filter(|c| c.is_some()).count()
+    }
+
+    /// Checks functions referenced by name before running.
+    ///
+    /// Names in string literals passed to `call` and `call_ret` must be
+    /// loaded functions in `target`, which is the module these calls go to.
+    /// When the arguments are an array literal, their number must match.
+    /// Names passed to `glsl_of` must be loaded functions in this module.
+    ///
+    /// Returns all unresolved references as one error, with source ranges.
+    pub fn check_fn_refs(&self, target: &Module) -> Result<(), String> {
+        ast::check_fn_refs(self, target)
     }
 
     /// Find function relative another function index.
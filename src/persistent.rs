@@ -0,0 +1,362 @@
+//! Persistent data structures.
+//!
+//! A persistent structure is never changed.
+//! Instead, an update returns a new structure sharing most of its nodes
+//! with the old one, so keeping old versions, e.g. for undo or rollback,
+//! costs `O(log n)` per update instead of a deep clone.
+//!
+//! Vectors are stored in a trie of 32 children per node, indexed by position.
+//! Maps are stored in a hash array mapped trie, indexed by hash of the key.
+
+// Nodes hold variables, which are not `Sync`, and are shared by `Arc`
+// the same way as arrays and objects are.
+#![allow(clippy::arc_with_non_send_sync)]
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use Variable;
+
+const BITS: u32 = 5;
+const WIDTH: usize = 1 << BITS;
+const MASK: usize = WIDTH - 1;
+
+/// A persistent vector or map.
+#[derive(Clone)]
+pub(crate) enum Persistent {
+    Vec(PVec),
+    Map(PMap),
+}
+
+/// A persistent vector.
+#[derive(Clone)]
+pub(crate) struct PVec {
+    len: usize,
+    shift: u32,
+    root: Arc<VNode>,
+}
+
+enum VNode {
+    Branch(Vec<Arc<VNode>>),
+    Leaf(Vec<Variable>),
+}
+
+impl PVec {
+    pub(crate) fn new() -> PVec {
+        PVec {
+            len: 0,
+            shift: 0,
+            root: Arc::new(VNode::Leaf(vec![])),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn get(&self, i: usize) -> Option<&Variable> {
+        if i >= self.len {
+            return None;
+        }
+        let mut node = &*self.root;
+        let mut shift = self.shift;
+        loop {
+            match *node {
+                VNode::Branch(ref children) => {
+                    node = &children[(i >> shift) & MASK];
+                    shift -= BITS;
+                }
+                VNode::Leaf(ref values) => return values.get(i & MASK),
+            }
+        }
+    }
+
+    /// Returns a new vector with the value at an index changed,
+    /// or pushed to the end when the index equals the length.
+    pub(crate) fn set(&self, i: usize, v: Variable) -> Option<PVec> {
+        if i > self.len {
+            return None;
+        }
+        if i < self.len {
+            return Some(PVec {
+                len: self.len,
+                shift: self.shift,
+                root: Arc::new(VNode::set(&self.root, self.shift, i, v)),
+            });
+        }
+        if i == 1 << (self.shift + BITS) {
+            // The trie is full, so add a level.
+            let shift = self.shift + BITS;
+            Some(PVec {
+                len: self.len + 1,
+                shift,
+                root: Arc::new(VNode::Branch(vec![
+                    self.root.clone(),
+                    Arc::new(VNode::path(shift - BITS, v)),
+                ])),
+            })
+        } else {
+            Some(PVec {
+                len: self.len + 1,
+                shift: self.shift,
+                root: Arc::new(VNode::push(&self.root, self.shift, i, v)),
+            })
+        }
+    }
+
+    pub(crate) fn to_vec(&self) -> Vec<Variable> {
+        fn collect(node: &VNode, res: &mut Vec<Variable>) {
+            match *node {
+                VNode::Branch(ref children) => {
+                    for child in children {
+                        collect(child, res)
+                    }
+                }
+                VNode::Leaf(ref values) => res.extend(values.iter().cloned()),
+            }
+        }
+
+        let mut res = Vec::with_capacity(self.len);
+        collect(&self.root, &mut res);
+        res
+    }
+}
+
+impl VNode {
+    fn path(shift: u32, v: Variable) -> VNode {
+        if shift == 0 {
+            VNode::Leaf(vec![v])
+        } else {
+            VNode::Branch(vec![Arc::new(VNode::path(shift - BITS, v))])
+        }
+    }
+
+    fn set(node: &VNode, shift: u32, i: usize, v: Variable) -> VNode {
+        match *node {
+            VNode::Branch(ref children) => {
+                let mut children = children.clone();
+                let ind = (i >> shift) & MASK;
+                children[ind] = Arc::new(VNode::set(&children[ind], shift - BITS, i, v));
+                VNode::Branch(children)
+            }
+            VNode::Leaf(ref values) => {
+                let mut values = values.clone();
+                values[i & MASK] = v;
+                VNode::Leaf(values)
+            }
+        }
+    }
+
+    fn push(node: &VNode, shift: u32, i: usize, v: Variable) -> VNode {
+        match *node {
+            VNode::Branch(ref children) => {
+                let mut children = children.clone();
+                let ind = (i >> shift) & MASK;
+                if ind < children.len() {
+                    children[ind] = Arc::new(VNode::push(&children[ind], shift - BITS, i, v));
+                } else {
+                    children.push(Arc::new(VNode::path(shift - BITS, v)));
+                }
+                VNode::Branch(children)
+            }
+            VNode::Leaf(ref values) => {
+                let mut values = values.clone();
+                values.push(v);
+                VNode::Leaf(values)
+            }
+        }
+    }
+}
+
+/// A persistent map with string keys.
+#[derive(Clone)]
+pub(crate) struct PMap {
+    len: usize,
+    root: Arc<MNode>,
+}
+
+enum MNode {
+    Branch {
+        bitmap: u32,
+        children: Vec<Arc<MNode>>,
+    },
+    /// Entries with the same hash.
+    Leaf {
+        hash: u64,
+        entries: Vec<(Arc<String>, Variable)>,
+    },
+}
+
+fn hash(key: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn bit(hash: u64, shift: u32) -> u32 {
+    1 << ((hash >> shift) as usize & MASK)
+}
+
+impl PMap {
+    pub(crate) fn new() -> PMap {
+        PMap {
+            len: 0,
+            root: Arc::new(MNode::Branch {
+                bitmap: 0,
+                children: vec![],
+            }),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn get(&self, key: &str) -> Option<&Variable> {
+        let hash = hash(key);
+        let mut node = &*self.root;
+        let mut shift = 0;
+        loop {
+            match *node {
+                MNode::Branch {
+                    bitmap,
+                    ref children,
+                } => {
+                    let bit = bit(hash, shift);
+                    if bitmap & bit == 0 {
+                        return None;
+                    }
+                    node = &children[(bitmap & (bit - 1)).count_ones() as usize];
+                    shift += BITS;
+                }
+                MNode::Leaf {
+                    hash: h,
+                    ref entries,
+                } => {
+                    if h != hash {
+                        return None;
+                    }
+                    return entries.iter().find(|e| &**e.0 == key).map(|e| &e.1);
+                }
+            }
+        }
+    }
+
+    /// Returns a new map with a key set to a value.
+    pub(crate) fn insert(&self, key: Arc<String>, v: Variable) -> PMap {
+        let hash = hash(&key);
+        let (root, added) = MNode::insert(&self.root, 0, hash, key, v);
+        PMap {
+            len: if added { self.len + 1 } else { self.len },
+            root: Arc::new(root),
+        }
+    }
+
+    pub(crate) fn to_map(&self) -> HashMap<Arc<String>, Variable> {
+        fn collect(node: &MNode, res: &mut HashMap<Arc<String>, Variable>) {
+            match *node {
+                MNode::Branch { ref children, .. } => {
+                    for child in children {
+                        collect(child, res)
+                    }
+                }
+                MNode::Leaf { ref entries, .. } => {
+                    res.extend(entries.iter().cloned());
+                }
+            }
+        }
+
+        let mut res = HashMap::with_capacity(self.len);
+        collect(&self.root, &mut res);
+        res
+    }
+}
+
+impl MNode {
+    fn insert(
+        node: &Arc<MNode>,
+        shift: u32,
+        hash: u64,
+        key: Arc<String>,
+        v: Variable,
+    ) -> (MNode, bool) {
+        match **node {
+            MNode::Branch {
+                bitmap,
+                ref children,
+            } => {
+                let bit = bit(hash, shift);
+                let pos = (bitmap & (bit - 1)).count_ones() as usize;
+                let mut children = children.clone();
+                let added = if bitmap & bit == 0 {
+                    children.insert(
+                        pos,
+                        Arc::new(MNode::Leaf {
+                            hash,
+                            entries: vec![(key, v)],
+                        }),
+                    );
+                    true
+                } else {
+                    let (child, added) = MNode::insert(&children[pos], shift + BITS, hash, key, v);
+                    children[pos] = Arc::new(child);
+                    added
+                };
+                (
+                    MNode::Branch {
+                        bitmap: bitmap | bit,
+                        children,
+                    },
+                    added,
+                )
+            }
+            MNode::Leaf {
+                hash: h,
+                ref entries,
+            } => {
+                if h == hash {
+                    let mut entries = entries.clone();
+                    let added = match entries.iter().position(|e| e.0 == key) {
+                        Some(i) => {
+                            entries[i].1 = v;
+                            false
+                        }
+                        None => {
+                            entries.push((key, v));
+                            true
+                        }
+                    };
+                    (MNode::Leaf { hash, entries }, added)
+                } else {
+                    let leaf = Arc::new(MNode::Leaf {
+                        hash,
+                        entries: vec![(key, v)],
+                    });
+                    (MNode::split(node.clone(), h, leaf, hash, shift), true)
+                }
+            }
+        }
+    }
+
+    /// Creates a branch containing two leaves with different hashes.
+    fn split(a: Arc<MNode>, ha: u64, b: Arc<MNode>, hb: u64, shift: u32) -> MNode {
+        let (bit_a, bit_b) = (bit(ha, shift), bit(hb, shift));
+        if bit_a == bit_b {
+            MNode::Branch {
+                bitmap: bit_a,
+                children: vec![Arc::new(MNode::split(a, ha, b, hb, shift + BITS))],
+            }
+        } else {
+            MNode::Branch {
+                bitmap: bit_a | bit_b,
+                children: if bit_a < bit_b {
+                    vec![a, b]
+                } else {
+                    vec![b, a]
+                },
+            }
+        }
+    }
+}
@@ -0,0 +1,206 @@
+//! Runs scripts concurrently on a pool of worker threads.
+//!
+//! A `RuntimePool` shares one module between all workers,
+//! and reuses runtimes between calls instead of creating a new one per call.
+//! This is useful for e.g. servers evaluating many scripts.
+
+use std::ops::{Deref, DerefMut};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use Module;
+use Runtime;
+use RuntimeError;
+use Variable;
+
+type Job = Box<dyn FnOnce() + Send>;
+type Factory = Arc<dyn Fn() -> Runtime + Send + Sync>;
+
+/// Shared state between the pool and its workers.
+struct Shared {
+    module: Arc<Module>,
+    idle: Mutex<Vec<Runtime>>,
+    factory: Factory,
+}
+
+impl Shared {
+    fn take(&self) -> Runtime {
+        let rt = self.idle.lock().ok().and_then(|mut idle| idle.pop());
+        rt.unwrap_or_else(|| (self.factory)())
+    }
+
+    fn put(&self, mut rt: Runtime) {
+        // A failed call might leave values behind.
+        rt.stack.clear();
+        rt.call_stack.clear();
+        rt.local_stack.clear();
+        rt.current_stack.clear();
+        if let Ok(mut idle) = self.idle.lock() {
+            idle.push(rt);
+        }
+    }
+}
+
+/// A pool of runtimes sharing a module, with worker threads for running calls.
+pub struct RuntimePool {
+    shared: Arc<Shared>,
+    jobs: Option<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl RuntimePool {
+    /// Creates a new pool with a number of worker threads.
+    pub fn new(module: Arc<Module>, threads: usize) -> RuntimePool {
+        RuntimePool::with_factory(module, threads, Runtime::new)
+    }
+
+    /// Creates a new pool that uses a function to create runtimes,
+    /// e.g. to set flags or enable the garbage collected heap.
+    pub fn with_factory<F>(module: Arc<Module>, threads: usize, factory: F) -> RuntimePool
+    where
+        F: Fn() -> Runtime + Send + Sync + 'static,
+    {
+        let shared = Arc::new(Shared {
+            module,
+            idle: Mutex::new(vec![]),
+            factory: Arc::new(factory),
+        });
+        let (jobs, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..threads.max(1))
+            .map(|_| {
+                let receiver = receiver.clone();
+                thread::spawn(move || loop {
+                    let job = match receiver.lock() {
+                        Ok(receiver) => receiver.recv(),
+                        Err(_) => return,
+                    };
+                    match job {
+                        Ok(job) => job(),
+                        // The pool is dropped.
+                        Err(_) => return,
+                    }
+                })
+            })
+            .collect();
+        RuntimePool {
+            shared,
+            jobs: Some(jobs),
+            workers,
+        }
+    }
+
+    /// Returns the shared module.
+    pub fn module(&self) -> &Arc<Module> {
+        &self.shared.module
+    }
+
+    /// Returns the number of worker threads.
+    pub fn threads(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Takes a runtime from the pool, to use on the current thread.
+    ///
+    /// The runtime is returned to the pool when dropped.
+    pub fn runtime(&self) -> PooledRuntime {
+        PooledRuntime {
+            shared: self.shared.clone(),
+            rt: Some(self.shared.take()),
+        }
+    }
+
+    /// Calls a function with return value on a worker thread.
+    ///
+    /// Returns a handle to wait for the result.
+    pub fn call(&self, function: &str, args: Vec<Variable>) -> PoolCall {
+        let (sender, receiver) = mpsc::channel();
+        let shared = self.shared.clone();
+        let function = function.to_string();
+        let job: Job = Box::new(move || {
+            let mut rt = shared.take();
+            let res = rt.call_str_ret(&function, &args, &shared.module);
+            shared.put(rt);
+            let _ = sender.send(res);
+        });
+        if let Some(ref jobs) = self.jobs {
+            let _ = jobs.send(job);
+        }
+        PoolCall { receiver }
+    }
+
+    /// Calls many functions with return values on the worker threads,
+    /// and waits for the results.
+    ///
+    /// The results are in the same order as the calls.
+    pub fn call_all<S: AsRef<str>>(
+        &self,
+        calls: Vec<(S, Vec<Variable>)>,
+    ) -> Vec<Result<Variable, RuntimeError>> {
+        let handles: Vec<PoolCall> = calls
+            .into_iter()
+            .map(|(function, args)| self.call(function.as_ref(), args))
+            .collect();
+        handles.into_iter().map(|h| h.join()).collect()
+    }
+}
+
+impl Drop for RuntimePool {
+    fn drop(&mut self) {
+        // Closing the channel stops the workers after the remaining calls.
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// The result of a call running on a worker thread.
+pub struct PoolCall {
+    receiver: Receiver<Result<Variable, RuntimeError>>,
+}
+
+impl PoolCall {
+    /// Waits for the call to finish.
+    pub fn join(self) -> Result<Variable, RuntimeError> {
+        self.receiver
+            .recv()
+            .unwrap_or_else(|_| Err("Worker thread stopped before call finished".into()))
+    }
+}
+
+/// A runtime taken from a pool, returned to the pool when dropped.
+pub struct PooledRuntime {
+    shared: Arc<Shared>,
+    rt: Option<Runtime>,
+}
+
+impl PooledRuntime {
+    /// Returns the module shared by the pool.
+    pub fn module(&self) -> &Arc<Module> {
+        &self.shared.module
+    }
+}
+
+impl Deref for PooledRuntime {
+    type Target = Runtime;
+
+    fn deref(&self) -> &Runtime {
+        self.rt.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledRuntime {
+    fn deref_mut(&mut self) -> &mut Runtime {
+        self.rt.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledRuntime {
+    fn drop(&mut self) {
+        if let Some(rt) = self.rt.take() {
+            self.shared.put(rt);
+        }
+    }
+}
@@ -0,0 +1,290 @@
+//! Python bindings.
+//!
+//! Requires the `python` feature.
+//! The extension module is built with:
+//!
+//! ```text
+//! cargo rustc --release --features python --crate-type cdylib
+//! ```
+//!
+//! Rename the library to `dyon.so` (or `dyon.pyd` on Windows) to import it:
+//!
+//! ```python
+//! import dyon
+//! module = dyon.Module()
+//! module.load("source/hello_world.dyon")
+//! runtime = dyon.Runtime()
+//! runtime.run(module)
+//! ```
+//!
+//! Dyon values are converted to Python values and back:
+//!
+//! - `f64` <-> `float` (Python `int` is converted to `f64`)
+//! - `bool` <-> `bool`
+//! - `str` <-> `str`
+//! - `bytes` <-> `bytes`
+//! - `vec4` <-> tuple of 4 floats
+//! - `mat4` -> tuple of 4 column tuples
+//! - `[]` <-> `list`
+//! - `{}` <-> `dict` with string keys
+//! - `opt` -> value or `None`, and `None` -> `none()`
+//! - `res` -> value, or raises `RuntimeError` for `err(_)`
+//!
+//! - `farr` <-> 1-dimensional numpy array of `float64`
+//!
+//! A numpy array of `float64` with more dimensions is converted to nested arrays of `farr`.
+
+// Variables hold `Arc`s of values that are not `Sync`, e.g. Rust objects,
+// so arrays and objects converted from Python are built the same way.
+#![allow(clippy::arc_with_non_send_sync)]
+
+use numpy::{PyArray1, PyReadonlyArrayDyn};
+use pyo3::exceptions::{PyRuntimeError, PyTypeError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBool, PyBytes, PyDict, PyFloat, PyInt, PyList, PyString, PyTuple};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use FnIndex;
+use Module;
+use Runtime;
+use Scalar;
+use Type;
+use Variable;
+
+fn err<T: ::std::fmt::Display>(msg: T) -> PyErr {
+    PyRuntimeError::new_err(msg.to_string())
+}
+
+/// A Dyon module.
+#[pyclass(name = "Module", unsendable)]
+pub struct PyDyonModule {
+    module: Arc<Module>,
+}
+
+impl PyDyonModule {
+    fn module_mut(&mut self) -> PyResult<&mut Module> {
+        Arc::get_mut(&mut self.module)
+            .ok_or_else(|| err("Can not load into a module that is in use"))
+    }
+}
+
+#[pymethods]
+impl PyDyonModule {
+    /// Creates a new module with the standard external functions.
+    #[new]
+    fn new() -> Self {
+        PyDyonModule {
+            module: Arc::new(Module::new()),
+        }
+    }
+
+    /// Loads a source file.
+    fn load(&mut self, source: &str) -> PyResult<()> {
+        ::load(source, self.module_mut()?).map_err(err)
+    }
+
+    /// Loads source from a string, using `name` as file name in error messages.
+    fn load_str(&mut self, name: &str, source: &str) -> PyResult<()> {
+        ::load_str(name, Arc::new(source.into()), self.module_mut()?).map_err(err)
+    }
+}
+
+/// A Dyon runtime.
+#[pyclass(name = "Runtime", unsendable)]
+pub struct PyRuntime {
+    runtime: Runtime,
+}
+
+#[pymethods]
+impl PyRuntime {
+    /// Creates a new runtime.
+    #[new]
+    fn new() -> Self {
+        PyRuntime {
+            runtime: Runtime::new(),
+        }
+    }
+
+    /// Runs the `main` function of a module.
+    fn run(&mut self, module: &PyDyonModule) -> PyResult<()> {
+        self.runtime.run(&module.module).map_err(err)
+    }
+
+    /// Calls a function with arguments and returns the result,
+    /// or `None` when the function has no return value.
+    #[pyo3(signature = (module, name, *args))]
+    fn call(
+        &mut self,
+        py: Python,
+        module: &PyDyonModule,
+        name: &str,
+        args: &Bound<PyTuple>,
+    ) -> PyResult<Py<PyAny>> {
+        let module = &module.module;
+        let args = args
+            .iter()
+            .map(|arg| from_py(&arg))
+            .collect::<PyResult<Vec<_>>>()?;
+        let returns = match module.find_function(&Arc::new(name.into()), 0) {
+            FnIndex::Loaded(f) => module.functions[f as usize].ret != Type::Void,
+            _ => return Err(err(format!("Could not find function `{}`", name))),
+        };
+        if returns {
+            let rt = &mut self.runtime;
+            let val = rt.call_str_ret(name, &args, module).map_err(err)?;
+            to_py(py, rt.resolve(&val))
+        } else {
+            self.runtime.call_str(name, &args, module).map_err(err)?;
+            Ok(py.None())
+        }
+    }
+}
+
+/// Loads data from a file in Dyon data format.
+#[pyfunction]
+fn load_data(py: Python, file: &str) -> PyResult<Py<PyAny>> {
+    to_py(
+        py,
+        &::dyon_std::data::load_file(&::vfs::OsFs, file, None).map_err(err)?,
+    )
+}
+
+/// Loads data from a string in Dyon data format.
+#[pyfunction]
+fn load_data_str(py: Python, text: &str) -> PyResult<Py<PyAny>> {
+    to_py(py, &::dyon_std::data::load_data(text, None).map_err(err)?)
+}
+
+/// Converts a Dyon variable to a Python object.
+///
+/// The variable should be resolved before call.
+pub fn to_py(py: Python, var: &Variable) -> PyResult<Py<PyAny>> {
+    Ok(match *var {
+        Variable::F64(v, _) => PyFloat::new(py, v).into_any().unbind(),
+        Variable::Bool(v, _) => PyBool::new(py, v).to_owned().into_any().unbind(),
+        Variable::Str(ref s) => PyString::new(py, s).into_any().unbind(),
+        Variable::Bytes(ref b) => PyBytes::new(py, b).into_any().unbind(),
+        Variable::F64Array(ref arr) => PyArray1::from_slice(py, arr).into_any().unbind(),
+        Variable::Vec4(v) => PyTuple::new(py, v.iter().map(|&x| f64::from(x)))?
+            .into_any()
+            .unbind(),
+        Variable::Mat4(ref m) => {
+            let cols = m
+                .iter()
+                .map(|col| PyTuple::new(py, col.iter().map(|&x| f64::from(x))))
+                .collect::<PyResult<Vec<_>>>()?;
+            PyTuple::new(py, cols)?.into_any().unbind()
+        }
+        Variable::Array(ref arr) => {
+            let items = arr
+                .iter()
+                .map(|it| to_py(py, it))
+                .collect::<PyResult<Vec<_>>>()?;
+            PyList::new(py, items)?.into_any().unbind()
+        }
+        Variable::Object(ref obj) => {
+            let dict = PyDict::new(py);
+            for (key, val) in obj.iter() {
+                dict.set_item(&**key, to_py(py, val)?)?;
+            }
+            dict.into_any().unbind()
+        }
+        Variable::Option(None) => py.None(),
+        Variable::Option(Some(ref v)) => to_py(py, v)?,
+        Variable::Result(Ok(ref v)) => to_py(py, v)?,
+        Variable::Result(Err(ref e)) => {
+            let mut msg = match e.message {
+                Variable::Str(ref s) => (**s).clone(),
+                ref x => format!("{:?}", x),
+            };
+            for t in e.trace.iter().rev() {
+                msg.push('\n');
+                msg.push_str(t);
+            }
+            return Err(err(msg));
+        }
+        ref x => {
+            return Err(PyTypeError::new_err(format!(
+                "Can not convert `{}` to Python",
+                x.typeof_var()
+            )))
+        }
+    })
+}
+
+/// Converts a Python object to a Dyon variable.
+pub fn from_py(obj: &Bound<PyAny>) -> PyResult<Variable> {
+    // Check `bool` before numbers, since `bool` is a subclass of `int`.
+    if let Ok(v) = obj.cast::<PyBool>() {
+        return Ok(Variable::bool(v.is_true()));
+    }
+    if obj.is_instance_of::<PyFloat>() || obj.is_instance_of::<PyInt>() {
+        return Ok(Variable::f64(obj.extract()?));
+    }
+    if let Ok(s) = obj.cast::<PyString>() {
+        return Ok(Variable::Str(Arc::new(s.to_str()?.into())));
+    }
+    if let Ok(b) = obj.cast::<PyBytes>() {
+        return Ok(Variable::Bytes(Arc::new(b.as_bytes().to_vec())));
+    }
+    if obj.is_none() {
+        return Ok(Variable::Option(None));
+    }
+    if let Ok(t) = obj.cast::<PyTuple>() {
+        if t.len() > 4 {
+            return Err(PyTypeError::new_err(
+                "Expected tuple of 4 or fewer numbers for `vec4`",
+            ));
+        }
+        let mut v = [0.0; 4];
+        for (i, it) in t.iter().enumerate() {
+            v[i] = it.extract::<f64>()? as Scalar;
+        }
+        return Ok(Variable::Vec4(v));
+    }
+    if let Ok(list) = obj.cast::<PyList>() {
+        let items = list
+            .iter()
+            .map(|it| from_py(&it))
+            .collect::<PyResult<Vec<_>>>()?;
+        return Ok(Variable::Array(Arc::new(items)));
+    }
+    if let Ok(dict) = obj.cast::<PyDict>() {
+        let mut res = HashMap::new();
+        for (key, val) in dict.iter() {
+            let key: String = key.extract()?;
+            res.insert(Arc::new(key), from_py(&val)?);
+        }
+        return Ok(Variable::Object(Arc::new(res)));
+    }
+    if let Ok(arr) = obj.extract::<PyReadonlyArrayDyn<f64>>() {
+        let view = arr.as_array();
+        return Ok(ndarray_to_var(&view.view()));
+    }
+    Err(PyTypeError::new_err(format!(
+        "Can not convert `{}` to Dyon",
+        obj.get_type().name()?
+    )))
+}
+
+fn ndarray_to_var(view: &::numpy::ndarray::ArrayViewD<f64>) -> Variable {
+    if view.ndim() == 0 {
+        return Variable::f64(view.iter().next().cloned().unwrap_or(0.0));
+    }
+    if view.ndim() == 1 {
+        return Variable::F64Array(Arc::new(view.iter().cloned().collect()));
+    }
+    let items = view.outer_iter().map(|sub| ndarray_to_var(&sub)).collect();
+    Variable::Array(Arc::new(items))
+}
+
+/// The `dyon` Python module.
+#[pymodule]
+fn dyon(m: &Bound<pyo3::types::PyModule>) -> PyResult<()> {
+    m.add_class::<PyDyonModule>()?;
+    m.add_class::<PyRuntime>()?;
+    m.add_function(wrap_pyfunction!(python::load_data, m)?)?;
+    m.add_function(wrap_pyfunction!(python::load_data_str, m)?)?;
+    Ok(())
+}
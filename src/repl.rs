@@ -0,0 +1,65 @@
+//! Evaluation of Dyon code one input at a time.
+
+use std::sync::Arc;
+
+use kernel::Kernel;
+use Module;
+use Runtime;
+use Variable;
+
+/// Evaluates statements, expressions and function declarations one at a time,
+/// keeping variables and functions between inputs.
+///
+/// Inputs are evaluated as cells of a `Kernel`, ignoring rich output.
+pub struct Repl {
+    kernel: Kernel,
+}
+
+impl Default for Repl {
+    fn default() -> Repl {
+        Repl::new()
+    }
+}
+
+impl Repl {
+    /// Creates a new REPL with the standard library.
+    pub fn new() -> Repl {
+        Repl::with_module(Module::new())
+    }
+
+    /// Creates a new REPL using functions from a module.
+    pub fn with_module(module: Module) -> Repl {
+        Repl {
+            kernel: Kernel::with_module(module),
+        }
+    }
+
+    /// Returns the runtime used to evaluate inputs.
+    pub fn runtime(&mut self) -> &mut Runtime {
+        self.kernel.runtime()
+    }
+
+    /// Returns the value of a variable declared in an earlier input.
+    pub fn var(&self, name: &str) -> Option<&Variable> {
+        self.kernel
+            .scope()
+            .iter()
+            .find(|v| &**v.0 == name)
+            .map(|v| &v.1)
+    }
+
+    /// Returns the names of the declared variables, in order of declaration.
+    pub fn var_names(&self) -> Vec<Arc<String>> {
+        self.kernel.scope().iter().map(|v| v.0.clone()).collect()
+    }
+
+    /// Evaluates an input.
+    ///
+    /// An input is either function declarations,
+    /// or statements that can end with an expression.
+    /// Returns the value of the last expression, if any.
+    /// Variables are unchanged when an error occurs.
+    pub fn eval(&mut self, input: &str) -> Result<Option<Variable>, String> {
+        self.kernel.eval(input).map(|out| out.value)
+    }
+}
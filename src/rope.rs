@@ -0,0 +1,215 @@
+//! Text stored in chunks, for editing large texts.
+//!
+//! Each chunk caches its number of characters and lines,
+//! so finding a position or line skips whole chunks,
+//! and an edit only copies the text of the chunks it touches.
+//! Positions are counted in characters.
+
+/// Preferred maximum number of bytes in a chunk.
+const CHUNK_SIZE: usize = 2048;
+
+struct Chunk {
+    text: String,
+    chars: usize,
+    newlines: usize,
+}
+
+impl Chunk {
+    fn new(text: String) -> Chunk {
+        Chunk {
+            chars: text.chars().count(),
+            newlines: text.matches('\n').count(),
+            text,
+        }
+    }
+}
+
+/// Returns the byte offset of a character in a text,
+/// or the length when the character is at the end.
+fn byte_offset(text: &str, ch: usize) -> usize {
+    text.char_indices()
+        .nth(ch)
+        .map(|(i, _)| i)
+        .unwrap_or(text.len())
+}
+
+/// Splits text into chunks, at character boundaries.
+fn chunks(mut text: &str) -> Vec<Chunk> {
+    let mut res = vec![];
+    while text.len() > CHUNK_SIZE {
+        let mut end = CHUNK_SIZE;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        res.push(Chunk::new(text[..end].into()));
+        text = &text[end..];
+    }
+    if !text.is_empty() {
+        res.push(Chunk::new(text.into()));
+    }
+    res
+}
+
+/// Text stored in chunks.
+pub(crate) struct Rope {
+    chunks: Vec<Chunk>,
+    chars: usize,
+    bytes: usize,
+    newlines: usize,
+}
+
+impl Rope {
+    pub(crate) fn new(text: &str) -> Rope {
+        let chunks = chunks(text);
+        Rope {
+            chars: chunks.iter().map(|c| c.chars).sum(),
+            newlines: chunks.iter().map(|c| c.newlines).sum(),
+            bytes: text.len(),
+            chunks,
+        }
+    }
+
+    /// Returns the number of characters.
+    pub(crate) fn len(&self) -> usize {
+        self.chars
+    }
+
+    pub(crate) fn line_count(&self) -> usize {
+        self.newlines + 1
+    }
+
+    /// Inserts text at a character position.
+    pub(crate) fn insert(&mut self, pos: usize, text: &str) -> Result<(), String> {
+        if pos > self.chars {
+            return Err(format!(
+                "Position {} is out of bounds for length {}",
+                pos, self.chars
+            ));
+        }
+        if text.is_empty() {
+            return Ok(());
+        }
+        // Find the chunk containing the position,
+        // preferring the end of a chunk over the start of the next.
+        let mut start = 0;
+        let mut ind = 0;
+        while ind + 1 < self.chunks.len() && start + self.chunks[ind].chars < pos {
+            start += self.chunks[ind].chars;
+            ind += 1;
+        }
+        let mut chunk = match self.chunks.get(ind) {
+            Some(chunk) => chunk.text.clone(),
+            None => String::new(),
+        };
+        let offset = byte_offset(&chunk, pos - start);
+        chunk.insert_str(offset, text);
+        let new_chunks = chunks(&chunk);
+        if ind < self.chunks.len() {
+            self.chunks.splice(ind..ind + 1, new_chunks);
+        } else {
+            self.chunks.extend(new_chunks);
+        }
+        self.chars += text.chars().count();
+        self.newlines += text.matches('\n').count();
+        self.bytes += text.len();
+        Ok(())
+    }
+
+    /// Deletes the characters from `start` up to, but not including, `end`.
+    pub(crate) fn delete(&mut self, start: usize, end: usize) -> Result<(), String> {
+        if start > end || end > self.chars {
+            return Err(format!(
+                "Range {}..{} is out of bounds for length {}",
+                start, end, self.chars
+            ));
+        }
+        let mut pos = 0;
+        for chunk in &mut self.chunks {
+            let chunk_end = pos + chunk.chars;
+            if chunk_end > start && pos < end {
+                let a = byte_offset(&chunk.text, start.max(pos) - pos);
+                let b = byte_offset(&chunk.text, end.min(chunk_end) - pos);
+                let removed = Chunk::new(chunk.text[a..b].into());
+                chunk.text.replace_range(a..b, "");
+                chunk.chars -= removed.chars;
+                chunk.newlines -= removed.newlines;
+                self.chars -= removed.chars;
+                self.newlines -= removed.newlines;
+                self.bytes -= removed.text.len();
+            }
+            pos = chunk_end;
+            if pos >= end {
+                break;
+            }
+        }
+        self.merge();
+        Ok(())
+    }
+
+    /// Removes empty chunks and merges small neighbour chunks.
+    fn merge(&mut self) {
+        let mut i = 0;
+        while i < self.chunks.len() {
+            if self.chunks[i].text.is_empty() {
+                self.chunks.remove(i);
+            } else if i + 1 < self.chunks.len()
+                && self.chunks[i].text.len() + self.chunks[i + 1].text.len() <= CHUNK_SIZE
+            {
+                let next = self.chunks.remove(i + 1);
+                let chunk = &mut self.chunks[i];
+                chunk.text.push_str(&next.text);
+                chunk.chars += next.chars;
+                chunk.newlines += next.newlines;
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    /// Returns a line without the line ending.
+    pub(crate) fn line(&self, i: usize) -> Option<String> {
+        if i > self.newlines {
+            return None;
+        }
+        // Skip chunks before the line starts.
+        let mut newlines = 0;
+        let mut ind = 0;
+        while ind < self.chunks.len() && newlines + self.chunks[ind].newlines < i {
+            newlines += self.chunks[ind].newlines;
+            ind += 1;
+        }
+        let mut line = String::new();
+        let mut skip = i - newlines;
+        for chunk in &self.chunks[ind..] {
+            let mut text = &chunk.text[..];
+            while skip > 0 {
+                match text.find('\n') {
+                    Some(n) => {
+                        text = &text[n + 1..];
+                        skip -= 1;
+                    }
+                    None => break,
+                }
+            }
+            match text.find('\n') {
+                Some(n) => {
+                    line.push_str(&text[..n]);
+                    break;
+                }
+                None => line.push_str(text),
+            }
+        }
+        if line.ends_with('\r') {
+            line.pop();
+        }
+        Some(line)
+    }
+
+    pub(crate) fn text(&self) -> String {
+        let mut s = String::with_capacity(self.bytes);
+        for chunk in &self.chunks {
+            s.push_str(&chunk.text);
+        }
+        s
+    }
+}
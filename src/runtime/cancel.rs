@@ -0,0 +1,59 @@
+//! Stopping running scripts from another thread.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::*;
+
+/// Stops a running script with an error at the next statement.
+///
+/// Can be sent to another thread, e.g. to stop a runaway script from a GUI.
+/// The cancellation is cleared when the host makes the next call,
+/// e.g. with `Runtime::run` or `Runtime::call_str`.
+#[derive(Clone, Debug)]
+pub struct CancelHandle(Arc<AtomicBool>);
+
+impl CancelHandle {
+    /// Stops the running script.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns `true` if the script is cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl Runtime {
+    /// Returns a handle to stop running scripts.
+    ///
+    /// Threads started with `go` are stopped too.
+    pub fn cancel_handle(&mut self) -> CancelHandle {
+        let flag = self
+            .interrupt
+            .get_or_insert_with(|| Arc::new(AtomicBool::new(false)));
+        CancelHandle(flag.clone())
+    }
+
+    /// Clears the cancellation, at each call from the host.
+    pub(crate) fn reset_cancel(&mut self) {
+        if let Some(ref interrupt) = self.interrupt {
+            interrupt.store(false, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns an error if the script is cancelled.
+    pub(crate) fn check_cancel(&self, range: Range) -> Result<(), RuntimeError> {
+        match self.interrupt {
+            Some(ref interrupt) if interrupt.load(Ordering::Relaxed) => {
+                let msg = format!("{}\nInterrupted", self.stack_trace());
+                Err(RuntimeError::new(
+                    ErrorKind::Cancelled,
+                    Some(range),
+                    self.module.error(range, &msg, self),
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+}
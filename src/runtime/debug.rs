@@ -0,0 +1,139 @@
+use std::time::{Duration, Instant};
+
+use super::*;
+
+/// A function called by the runtime for debug events.
+pub type DebugHook = Box<dyn FnMut(&DebugEvent) + Send>;
+
+/// A function called by the runtime with the name and duration of intrinsic calls.
+pub type TelemetryHook = Box<dyn FnMut(&str, Duration) + Send>;
+
+/// The kind of debug event.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DebugEventKind {
+    /// A statement is about to run.
+    Statement,
+    /// A function or closure is called, with arguments as locals.
+    Call,
+}
+
+/// Describes where a runtime is when running a program.
+pub struct DebugEvent<'a> {
+    /// The kind of event.
+    pub kind: DebugEventKind,
+    /// The source range of the statement, or the call site of a call.
+    pub source_range: Range,
+    rt: &'a Runtime,
+}
+
+impl<'a> DebugEvent<'a> {
+    /// Returns the name of the current function.
+    pub fn function(&self) -> &'a str {
+        self.rt
+            .call_stack
+            .last()
+            .map(|c| &**c.fn_name)
+            .unwrap_or("")
+    }
+
+    /// Returns the file of the current function.
+    pub fn file(&self) -> Option<&'a str> {
+        self.rt
+            .call_stack
+            .last()
+            .and_then(|c| c.file.as_ref())
+            .map(|f| &***f)
+    }
+
+    /// Returns the number of functions on the call stack.
+    pub fn depth(&self) -> usize {
+        self.rt.call_stack.len()
+    }
+
+    /// Returns the locals of the current function, in declaration order.
+    ///
+    /// Shadowed locals are left out.
+    pub fn locals(&self) -> Vec<(&'a str, &'a Variable)> {
+        let rt = self.rt;
+        let lc = rt.call_stack.last().map(|c| c.local_len).unwrap_or(0);
+        let mut res: Vec<(&'a str, &'a Variable)> = vec![];
+        for &(ref name, ind) in rt.local_stack[lc..].iter().rev() {
+            if *name == *RETURN_TYPE || res.iter().any(|&(n, _)| n == &***name) {
+                continue;
+            }
+            res.push((&***name, rt.resolve(&rt.stack[ind])));
+        }
+        res.reverse();
+        res
+    }
+}
+
+impl Runtime {
+    /// Sets a function called on every statement and call.
+    ///
+    /// Compiled functions are interpreted while a hook is set,
+    /// such that every statement is reported.
+    pub fn set_debug_hook(&mut self, hook: DebugHook) {
+        self.debug_hook = Some(hook);
+    }
+
+    /// Removes the debug hook.
+    pub fn clear_debug_hook(&mut self) -> Option<DebugHook> {
+        self.debug_hook.take()
+    }
+
+    pub(crate) fn debug_event(&mut self, kind: DebugEventKind, source_range: Range) {
+        if let Some(mut hook) = self.debug_hook.take() {
+            hook(&DebugEvent {
+                kind,
+                source_range,
+                rt: self,
+            });
+            self.debug_hook = Some(hook);
+        }
+    }
+
+    /// Sets a function called after every call to an intrinsic,
+    /// with the name of the intrinsic and how long the call took.
+    ///
+    /// The duration includes calls back into the script,
+    /// e.g. the closure passed to `map`.
+    pub fn set_telemetry_hook(&mut self, hook: TelemetryHook) {
+        self.telemetry_hook = Some(hook);
+    }
+
+    /// Removes the telemetry hook.
+    pub fn clear_telemetry_hook(&mut self) -> Option<TelemetryHook> {
+        self.telemetry_hook.take()
+    }
+
+    /// Calls an intrinsic, reporting the duration to the telemetry hook and profiler.
+    ///
+    /// The range of the call is used for the location in stack traces.
+    #[inline(always)]
+    pub(crate) fn call_intrinsic<T, F>(&mut self, name: &str, range: Range, f: F) -> T
+    where
+        F: FnOnce(&mut Runtime) -> T,
+    {
+        let prev = self.intrinsic_range.replace((self.call_stack.len(), range));
+        let res = if self.telemetry_hook.is_none() && self.profiler.is_none() {
+            f(self)
+        } else {
+            if let Some(ref mut p) = self.profiler {
+                p.enter(&Arc::new(name.into()));
+            }
+            let start = Instant::now();
+            let res = f(self);
+            let elapsed = start.elapsed();
+            if let Some(ref mut p) = self.profiler {
+                p.exit();
+            }
+            if let Some(ref mut hook) = self.telemetry_hook {
+                hook(name, elapsed);
+            }
+            res
+        };
+        self.intrinsic_range = prev;
+        res
+    }
+}
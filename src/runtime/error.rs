@@ -0,0 +1,221 @@
+//! Structured runtime errors.
+
+use range::Range;
+use std::error;
+use std::fmt;
+
+use super::*;
+
+/// The kind of error from running a program.
+#[derive(Clone, Debug)]
+pub enum ErrorKind {
+    /// A value did not have the expected type.
+    TypeMismatch {
+        /// The expected type.
+        expected: String,
+        /// The type of the value.
+        found: String,
+    },
+    /// An index was outside an array.
+    IndexOutOfBounds {
+        /// The index.
+        index: f64,
+    },
+    /// A key, variable or function was not found.
+    NotFound {
+        /// The name that was looked up.
+        name: String,
+    },
+    /// Reading or writing failed.
+    ///
+    /// File functions that return `err(_)` raise this kind when the error is unwrapped.
+    Io,
+    /// An `err(_)` was unwrapped, with the value inside.
+    User(Variable),
+    /// A limit of `RuntimeSettings` was exceeded.
+    LimitExceeded {
+        /// The limit.
+        limit: Limit,
+    },
+    /// The script was stopped with a `CancelHandle`.
+    Cancelled,
+    /// Any other error.
+    Custom,
+}
+
+/// An error from running a program.
+///
+/// Displays the error message, which shows where the error occurred and the stack trace.
+/// The error is boxed to keep results small on the interpreter stack.
+#[derive(Clone, Debug)]
+pub struct RuntimeError(Box<Inner>);
+
+#[derive(Clone, Debug)]
+struct Inner {
+    kind: ErrorKind,
+    range: Option<Range>,
+    message: String,
+}
+
+impl RuntimeError {
+    pub(crate) fn new(kind: ErrorKind, range: Option<Range>, message: String) -> RuntimeError {
+        RuntimeError(Box::new(Inner {
+            kind,
+            range,
+            message,
+        }))
+    }
+
+    /// Returns the kind of error.
+    pub fn kind(&self) -> &ErrorKind {
+        &self.0.kind
+    }
+
+    /// Returns the error message.
+    pub fn message(&self) -> &str {
+        &self.0.message
+    }
+
+    /// Returns the source range of the innermost expression that failed.
+    pub fn range(&self) -> Option<Range> {
+        self.0.range
+    }
+
+    /// Replaces the message, and sets the range unless already known.
+    ///
+    /// Used when an error is reported with its location in the source.
+    pub(crate) fn at(mut self, at: Range, msg: String) -> RuntimeError {
+        if self.0.range.is_none() {
+            self.0.range = Some(at);
+        }
+        self.0.message = msg;
+        self
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl error::Error for RuntimeError {}
+
+impl From<String> for RuntimeError {
+    fn from(message: String) -> RuntimeError {
+        RuntimeError::new(ErrorKind::Custom, None, message)
+    }
+}
+
+impl<'a> From<&'a str> for RuntimeError {
+    fn from(message: &'a str) -> RuntimeError {
+        RuntimeError::from(String::from(message))
+    }
+}
+
+impl From<RuntimeError> for String {
+    fn from(err: RuntimeError) -> String {
+        err.0.message
+    }
+}
+
+/// Returns the kind of error where a value had the wrong type.
+pub(crate) fn type_mismatch(expected: &str, found: &str) -> ErrorKind {
+    ErrorKind::TypeMismatch {
+        expected: expected.into(),
+        found: found.into(),
+    }
+}
+
+/// The lengths of the stacks before an expression that might fail,
+/// such that a caught error can remove what the expression left.
+pub(crate) struct CatchPoint {
+    call_stack: usize,
+    stack: usize,
+    local_stack: usize,
+    current_stack: usize,
+    profile_depth: Option<usize>,
+}
+
+impl Runtime {
+    /// Returns the point to unwind to when catching an error.
+    pub(crate) fn catch_point(&self) -> CatchPoint {
+        CatchPoint {
+            call_stack: self.call_stack.len(),
+            stack: self.stack.len(),
+            local_stack: self.local_stack.len(),
+            current_stack: self.current_stack.len(),
+            profile_depth: self.profiler.as_ref().map(|p| p.depth()),
+        }
+    }
+
+    /// Catches an error, returning `err(msg)` after unwinding to the catch point.
+    ///
+    /// Exceeding a limit or being cancelled is not caught, since it stops the whole program.
+    pub(crate) fn catch(
+        &mut self,
+        point: CatchPoint,
+        err: RuntimeError,
+    ) -> Result<Variable, RuntimeError> {
+        use Error;
+
+        match *err.kind() {
+            ErrorKind::LimitExceeded { .. } | ErrorKind::Cancelled => return Err(err),
+            _ => {}
+        }
+        self.call_stack.truncate(point.call_stack);
+        self.stack.truncate(point.stack);
+        self.local_stack.truncate(point.local_stack);
+        self.current_stack.truncate(point.current_stack);
+        if let (Some(p), Some(depth)) = (self.profiler.as_mut(), point.profile_depth) {
+            p.unwind(depth);
+        }
+        let kind = Some(err.kind().clone());
+        Ok(Variable::Result(Err(Box::new(Error {
+            message: Variable::Str(Arc::new(err.into())),
+            trace: vec![],
+            kind,
+        }))))
+    }
+
+    /// Returns an error at a range in the source, with a message that includes the stack trace.
+    pub(crate) fn error_at(&self, range: Range, msg: &str) -> RuntimeError {
+        RuntimeError::new(
+            ErrorKind::Custom,
+            Some(range),
+            self.module.error(range, msg, self),
+        )
+    }
+
+    /// Returns an error from an intrinsic, reported at a range in the source.
+    pub(crate) fn intrinsic_err(&self, range: Range, err: RuntimeError) -> RuntimeError {
+        let message = self.module.error(range, err.message(), self);
+        err.at(range, message)
+    }
+
+    /// Returns an error that a value had the wrong type.
+    pub(crate) fn type_err(
+        &self,
+        range: Range,
+        expected: &str,
+        found: &Variable,
+        msg: &str,
+    ) -> FlowResult {
+        Err(RuntimeError::new(
+            type_mismatch(expected, &found.typeof_var()),
+            Some(range),
+            self.module
+                .error(range, &format!("{}\n{}", self.stack_trace(), msg), self),
+        ))
+    }
+
+    /// Returns an error that a name was not found.
+    pub(crate) fn not_found_err(&self, range: Range, name: &str, msg: &str) -> FlowResult {
+        Err(RuntimeError::new(
+            ErrorKind::NotFound { name: name.into() },
+            Some(range),
+            self.module
+                .error(range, &format!("{}\n{}", self.stack_trace(), msg), self),
+        ))
+    }
+}
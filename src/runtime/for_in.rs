@@ -5,14 +5,12 @@ macro_rules! iter(
         let iter = match $rt.expression(&$for_in_expr.iter, Side::Right)? {
             (x, Flow::Return) => { return Ok((x, Flow::Return)); }
             (Some(x), Flow::Continue) => x,
-            _ => return Err($rt.module.error($for_in_expr.iter.source_range(),
-                &format!("{}\nExpected in-type from for iter",
-                    $rt.stack_trace()), $rt))
+            _ => return Err($rt.error_at($for_in_expr.iter.source_range(), &format!("{}\nExpected in-type from for iter",
+                    $rt.stack_trace())))
         };
         match $rt.resolve(&iter) {
             &Variable::In(ref val) => val.clone(),
-            x => return Err($rt.module.error($for_in_expr.iter.source_range(),
-                            &$rt.expected(x, "in"), $rt))
+            x => return Err($rt.intrinsic_err($for_in_expr.iter.source_range(), $rt.expected(x, "in")))
         }
     }};
 );
@@ -25,8 +23,7 @@ macro_rules! iter_val(
                 Err(_) => return Ok((None, Flow::Continue)),
             },
             Err(err) => {
-                return Err($rt.module.error($for_in_expr.source_range,
-                &format!("Can not lock In mutex:\n{}", err.to_string()), $rt));
+                return Err($rt.error_at($for_in_expr.source_range, &format!("Can not lock In mutex:\n{}", err.to_string())));
             }
         }
     };
@@ -70,18 +67,14 @@ macro_rules! iter_val_inc(
                 Err(_) => break,
             },
             Err(err) => {
-                return Err($rt.module.error($for_in_expr.source_range,
-                &format!("Can not lock In mutex:\n{}", err.to_string()), $rt));
+                return Err($rt.error_at($for_in_expr.source_range, &format!("Can not lock In mutex:\n{}", err.to_string())));
             }
         }
     };
 );
 
 impl Runtime {
-    pub(crate) fn for_in_expr(
-        &mut self,
-        for_in_expr: &ast::ForIn,
-    ) -> Result<(Option<Variable>, Flow), String> {
+    pub(crate) fn for_in_expr(&mut self, for_in_expr: &ast::ForIn) -> FlowResult {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
 
@@ -115,10 +108,7 @@ impl Runtime {
         Ok((None, flow))
     }
 
-    pub(crate) fn sum_in_expr(
-        &mut self,
-        for_in_expr: &ast::ForIn,
-    ) -> Result<(Option<Variable>, Flow), String> {
+    pub(crate) fn sum_in_expr(&mut self, for_in_expr: &ast::ForIn) -> FlowResult {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
 
@@ -141,10 +131,9 @@ impl Runtime {
                     match self.resolve(&x) {
                         &Variable::F64(val, _) => sum += val,
                         x => {
-                            return Err(self.module.error(
+                            return Err(self.intrinsic_err(
                                 for_in_expr.block.source_range,
-                                &self.expected(x, "number"),
-                                self,
+                                self.expected(x, "number"),
                             ))
                         }
                     };
@@ -166,10 +155,7 @@ impl Runtime {
         Ok((Some(Variable::f64(sum)), flow))
     }
 
-    pub(crate) fn prod_in_expr(
-        &mut self,
-        for_in_expr: &ast::ForIn,
-    ) -> Result<(Option<Variable>, Flow), String> {
+    pub(crate) fn prod_in_expr(&mut self, for_in_expr: &ast::ForIn) -> FlowResult {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
 
@@ -192,10 +178,9 @@ impl Runtime {
                     match self.resolve(&x) {
                         &Variable::F64(val, _) => prod *= val,
                         x => {
-                            return Err(self.module.error(
+                            return Err(self.intrinsic_err(
                                 for_in_expr.block.source_range,
-                                &self.expected(x, "number"),
-                                self,
+                                self.expected(x, "number"),
                             ))
                         }
                     };
@@ -217,10 +202,7 @@ impl Runtime {
         Ok((Some(Variable::f64(prod)), flow))
     }
 
-    pub(crate) fn min_in_expr(
-        &mut self,
-        for_in_expr: &ast::ForIn,
-    ) -> Result<(Option<Variable>, Flow), String> {
+    pub(crate) fn min_in_expr(&mut self, for_in_expr: &ast::ForIn) -> FlowResult {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
 
@@ -254,10 +236,9 @@ impl Runtime {
                             }
                         }
                         x => {
-                            return Err(self.module.error(
+                            return Err(self.intrinsic_err(
                                 for_in_expr.block.source_range,
-                                &self.expected(x, "number"),
-                                self,
+                                self.expected(x, "number"),
                             ))
                         }
                     };
@@ -266,10 +247,9 @@ impl Runtime {
                     return Ok((x, Flow::Return));
                 }
                 (None, Flow::Continue) => {
-                    return Err(self.module.error(
+                    return Err(self.error_at(
                         for_in_expr.block.source_range,
                         "Expected `number or option`",
-                        self,
                     ))
                 }
                 (_, Flow::Break(x)) => break_!(x, for_in_expr, flow),
@@ -285,10 +265,7 @@ impl Runtime {
         Ok((Some(Variable::F64(min, sec)), flow))
     }
 
-    pub(crate) fn max_in_expr(
-        &mut self,
-        for_in_expr: &ast::ForIn,
-    ) -> Result<(Option<Variable>, Flow), String> {
+    pub(crate) fn max_in_expr(&mut self, for_in_expr: &ast::ForIn) -> FlowResult {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
 
@@ -322,10 +299,9 @@ impl Runtime {
                             }
                         }
                         x => {
-                            return Err(self.module.error(
+                            return Err(self.intrinsic_err(
                                 for_in_expr.block.source_range,
-                                &self.expected(x, "number"),
-                                self,
+                                self.expected(x, "number"),
                             ))
                         }
                     };
@@ -334,10 +310,9 @@ impl Runtime {
                     return Ok((x, Flow::Return));
                 }
                 (None, Flow::Continue) => {
-                    return Err(self.module.error(
+                    return Err(self.error_at(
                         for_in_expr.block.source_range,
                         "Expected `number or option`",
-                        self,
                     ))
                 }
                 (_, Flow::Break(x)) => break_!(x, for_in_expr, flow),
@@ -353,10 +328,7 @@ impl Runtime {
         Ok((Some(Variable::F64(max, sec)), flow))
     }
 
-    pub(crate) fn any_in_expr(
-        &mut self,
-        for_in_expr: &ast::ForIn,
-    ) -> Result<(Option<Variable>, Flow), String> {
+    pub(crate) fn any_in_expr(&mut self, for_in_expr: &ast::ForIn) -> FlowResult {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
 
@@ -392,10 +364,9 @@ impl Runtime {
                             }
                         }
                         x => {
-                            return Err(self.module.error(
+                            return Err(self.intrinsic_err(
                                 for_in_expr.block.source_range,
-                                &self.expected(x, "boolean"),
-                                self,
+                                self.expected(x, "boolean"),
                             ))
                         }
                     };
@@ -404,11 +375,7 @@ impl Runtime {
                     return Ok((x, Flow::Return));
                 }
                 (None, Flow::Continue) => {
-                    return Err(self.module.error(
-                        for_in_expr.block.source_range,
-                        "Expected `boolean`",
-                        self,
-                    ))
+                    return Err(self.error_at(for_in_expr.block.source_range, "Expected `boolean`"))
                 }
                 (_, Flow::Break(x)) => break_!(x, for_in_expr, flow),
                 (_, Flow::ContinueLoop(x)) => continue_!(x, for_in_expr, flow),
@@ -423,10 +390,7 @@ impl Runtime {
         Ok((Some(Variable::Bool(any, sec)), flow))
     }
 
-    pub(crate) fn all_in_expr(
-        &mut self,
-        for_in_expr: &ast::ForIn,
-    ) -> Result<(Option<Variable>, Flow), String> {
+    pub(crate) fn all_in_expr(&mut self, for_in_expr: &ast::ForIn) -> FlowResult {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
 
@@ -462,10 +426,9 @@ impl Runtime {
                             }
                         }
                         x => {
-                            return Err(self.module.error(
+                            return Err(self.intrinsic_err(
                                 for_in_expr.block.source_range,
-                                &self.expected(x, "boolean"),
-                                self,
+                                self.expected(x, "boolean"),
                             ))
                         }
                     };
@@ -474,11 +437,7 @@ impl Runtime {
                     return Ok((x, Flow::Return));
                 }
                 (None, Flow::Continue) => {
-                    return Err(self.module.error(
-                        for_in_expr.block.source_range,
-                        "Expected `boolean`",
-                        self,
-                    ))
+                    return Err(self.error_at(for_in_expr.block.source_range, "Expected `boolean`"))
                 }
                 (_, Flow::Break(x)) => break_!(x, for_in_expr, flow),
                 (_, Flow::ContinueLoop(x)) => continue_!(x, for_in_expr, flow),
@@ -493,17 +452,14 @@ impl Runtime {
         Ok((Some(Variable::Bool(all, sec)), flow))
     }
 
-    pub(crate) fn link_for_in_expr(
-        &mut self,
-        for_in_expr: &ast::ForIn,
-    ) -> Result<(Option<Variable>, Flow), String> {
+    pub(crate) fn link_for_in_expr(&mut self, for_in_expr: &ast::ForIn) -> FlowResult {
         use Link;
 
         fn sub_link_for_in_expr(
             res: &mut Link,
             rt: &mut Runtime,
             for_in_expr: &ast::ForIn,
-        ) -> Result<(Option<Variable>, Flow), String> {
+        ) -> FlowResult {
             let prev_st = rt.stack.len();
             let prev_lc = rt.local_stack.len();
 
@@ -527,10 +483,9 @@ impl Runtime {
                             match rt.expression(item, Side::Right)? {
                                 (Some(ref x), Flow::Continue) => match res.push(rt.resolve(x)) {
                                     Err(err) => {
-                                        return Err(rt.module.error(
+                                        return Err(rt.error_at(
                                             for_in_expr.source_range,
                                             &format!("{}\n{}", rt.stack_trace(), err),
-                                            rt,
                                         ))
                                     }
                                     Ok(()) => {}
@@ -627,10 +582,7 @@ impl Runtime {
         }
     }
 
-    pub(crate) fn sift_in_expr(
-        &mut self,
-        for_in_expr: &ast::ForIn,
-    ) -> Result<(Option<Variable>, Flow), String> {
+    pub(crate) fn sift_in_expr(&mut self, for_in_expr: &ast::ForIn) -> FlowResult {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
         let mut res: Vec<Variable> = vec![];
@@ -648,16 +600,17 @@ impl Runtime {
         let mut flow = Flow::Continue;
         loop {
             match self.block(&for_in_expr.block)? {
-                (Some(x), Flow::Continue) => res.push(x),
+                (Some(x), Flow::Continue) => {
+                    if let Err(err) = self.check_array_len(res.len() + 1) {
+                        return self.err_from(for_in_expr.block.source_range, err);
+                    }
+                    res.push(x)
+                }
                 (x, Flow::Return) => {
                     return Ok((x, Flow::Return));
                 }
                 (None, Flow::Continue) => {
-                    return Err(self.module.error(
-                        for_in_expr.block.source_range,
-                        "Expected variable",
-                        self,
-                    ))
+                    return Err(self.error_at(for_in_expr.block.source_range, "Expected variable"))
                 }
                 (_, Flow::Break(x)) => break_!(x, for_in_expr, flow),
                 (_, Flow::ContinueLoop(x)) => continue_!(x, for_in_expr, flow),
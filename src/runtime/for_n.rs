@@ -6,14 +6,12 @@ macro_rules! start(
             let start = match $rt.expression(start_expr, Side::Right)? {
                 (x, Flow::Return) => { return Ok((x, Flow::Return)); }
                 (Some(x), Flow::Continue) => x,
-                _ => return Err($rt.module.error(start_expr.source_range(),
-                    &format!("{}\nExpected number from for start",
-                        $rt.stack_trace()), $rt))
+                _ => return Err($rt.error_at(start_expr.source_range(), &format!("{}\nExpected number from for start",
+                        $rt.stack_trace())))
             };
             let start = match $rt.resolve(&start) {
                 &Variable::F64(val, _) => val,
-                x => return Err($rt.module.error(start_expr.source_range(),
-                                &$rt.expected(x, "number"), $rt))
+                x => return Err($rt.intrinsic_err(start_expr.source_range(), $rt.expected(x, "number")))
             };
             start
         } else { 0.0 }
@@ -25,14 +23,12 @@ macro_rules! end(
         let end = match $rt.expression(&$for_n_expr.end, Side::Right)? {
             (x, Flow::Return) => { return Ok((x, Flow::Return)); }
             (Some(x), Flow::Continue) => x,
-            _ => return Err($rt.module.error($for_n_expr.end.source_range(),
-                &format!("{}\nExpected number from for end",
-                    $rt.stack_trace()), $rt))
+            _ => return Err($rt.error_at($for_n_expr.end.source_range(), &format!("{}\nExpected number from for end",
+                    $rt.stack_trace())))
         };
         match $rt.resolve(&end) {
             &Variable::F64(val, _) => val,
-            x => return Err($rt.module.error($for_n_expr.end.source_range(),
-                            &$rt.expected(x, "number"), $rt))
+            x => return Err($rt.intrinsic_err($for_n_expr.end.source_range(), $rt.expected(x, "number")))
         }
     }};
 );
@@ -45,8 +41,7 @@ macro_rules! cond(
                 else { break }
                 val
             }
-            x => return Err($rt.module.error($for_n_expr.source_range,
-                            &$rt.expected(x, "number"), $rt))
+            x => return Err($rt.intrinsic_err($for_n_expr.source_range, $rt.expected(x, "number")))
         }
     };
 );
@@ -100,17 +95,13 @@ macro_rules! inc(
             false
         } else { true };
         if error {
-            return Err($rt.module.error($for_n_expr.source_range,
-                       &$rt.expected(&$rt.stack[$st - 1], "number"), $rt))
+            return Err($rt.intrinsic_err($for_n_expr.source_range, $rt.expected(&$rt.stack[$st - 1], "number")))
         }
     }};
 );
 
 impl Runtime {
-    pub(crate) fn for_n_expr(
-        &mut self,
-        for_n_expr: &ast::ForN,
-    ) -> Result<(Option<Variable>, Flow), String> {
+    pub(crate) fn for_n_expr(&mut self, for_n_expr: &ast::ForN) -> FlowResult {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
 
@@ -144,10 +135,7 @@ impl Runtime {
         Ok((None, flow))
     }
 
-    pub(crate) fn sum_n_expr(
-        &mut self,
-        for_n_expr: &ast::ForN,
-    ) -> Result<(Option<Variable>, Flow), String> {
+    pub(crate) fn sum_n_expr(&mut self, for_n_expr: &ast::ForN) -> FlowResult {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
         let mut sum = 0.0;
@@ -170,10 +158,9 @@ impl Runtime {
                     match self.resolve(&x) {
                         &Variable::F64(val, _) => sum += val,
                         x => {
-                            return Err(self.module.error(
+                            return Err(self.intrinsic_err(
                                 for_n_expr.block.source_range,
-                                &self.expected(x, "number"),
-                                self,
+                                self.expected(x, "number"),
                             ))
                         }
                     };
@@ -182,11 +169,7 @@ impl Runtime {
                     return Ok((x, Flow::Return));
                 }
                 (None, Flow::Continue) => {
-                    return Err(self.module.error(
-                        for_n_expr.block.source_range,
-                        "Expected `number`",
-                        self,
-                    ))
+                    return Err(self.error_at(for_n_expr.block.source_range, "Expected `number`"))
                 }
                 (_, Flow::Break(x)) => break_!(x, for_n_expr, flow),
                 (_, Flow::ContinueLoop(x)) => continue_!(x, for_n_expr, flow),
@@ -200,10 +183,7 @@ impl Runtime {
         Ok((Some(Variable::f64(sum)), flow))
     }
 
-    pub(crate) fn prod_n_expr(
-        &mut self,
-        for_n_expr: &ast::ForN,
-    ) -> Result<(Option<Variable>, Flow), String> {
+    pub(crate) fn prod_n_expr(&mut self, for_n_expr: &ast::ForN) -> FlowResult {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
         let mut prod = 1.0;
@@ -226,10 +206,9 @@ impl Runtime {
                     match self.resolve(&x) {
                         &Variable::F64(val, _) => prod *= val,
                         x => {
-                            return Err(self.module.error(
+                            return Err(self.intrinsic_err(
                                 for_n_expr.block.source_range,
-                                &self.expected(x, "number"),
-                                self,
+                                self.expected(x, "number"),
                             ))
                         }
                     };
@@ -238,11 +217,7 @@ impl Runtime {
                     return Ok((x, Flow::Return));
                 }
                 (None, Flow::Continue) => {
-                    return Err(self.module.error(
-                        for_n_expr.block.source_range,
-                        "Expected `number`",
-                        self,
-                    ))
+                    return Err(self.error_at(for_n_expr.block.source_range, "Expected `number`"))
                 }
                 (_, Flow::Break(x)) => break_!(x, for_n_expr, flow),
                 (_, Flow::ContinueLoop(x)) => continue_!(x, for_n_expr, flow),
@@ -256,10 +231,7 @@ impl Runtime {
         Ok((Some(Variable::f64(prod)), flow))
     }
 
-    pub(crate) fn min_n_expr(
-        &mut self,
-        for_n_expr: &ast::ForN,
-    ) -> Result<(Option<Variable>, Flow), String> {
+    pub(crate) fn min_n_expr(&mut self, for_n_expr: &ast::ForN) -> FlowResult {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
 
@@ -294,10 +266,9 @@ impl Runtime {
                             }
                         }
                         x => {
-                            return Err(self.module.error(
+                            return Err(self.intrinsic_err(
                                 for_n_expr.block.source_range,
-                                &self.expected(x, "number"),
-                                self,
+                                self.expected(x, "number"),
                             ))
                         }
                     };
@@ -306,11 +277,9 @@ impl Runtime {
                     return Ok((x, Flow::Return));
                 }
                 (None, Flow::Continue) => {
-                    return Err(self.module.error(
-                        for_n_expr.block.source_range,
-                        "Expected `number or option`",
-                        self,
-                    ))
+                    return Err(
+                        self.error_at(for_n_expr.block.source_range, "Expected `number or option`")
+                    )
                 }
                 (_, Flow::Break(x)) => break_!(x, for_n_expr, flow),
                 (_, Flow::ContinueLoop(x)) => continue_!(x, for_n_expr, flow),
@@ -324,10 +293,7 @@ impl Runtime {
         Ok((Some(Variable::F64(min, sec)), flow))
     }
 
-    pub(crate) fn max_n_expr(
-        &mut self,
-        for_n_expr: &ast::ForN,
-    ) -> Result<(Option<Variable>, Flow), String> {
+    pub(crate) fn max_n_expr(&mut self, for_n_expr: &ast::ForN) -> FlowResult {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
 
@@ -363,10 +329,9 @@ impl Runtime {
                             }
                         }
                         x => {
-                            return Err(self.module.error(
+                            return Err(self.intrinsic_err(
                                 for_n_expr.block.source_range,
-                                &self.expected(x, "number"),
-                                self,
+                                self.expected(x, "number"),
                             ))
                         }
                     };
@@ -375,11 +340,7 @@ impl Runtime {
                     return Ok((x, Flow::Return));
                 }
                 (None, Flow::Continue) => {
-                    return Err(self.module.error(
-                        for_n_expr.block.source_range,
-                        "Expected `number`",
-                        self,
-                    ))
+                    return Err(self.error_at(for_n_expr.block.source_range, "Expected `number`"))
                 }
                 (_, Flow::Break(x)) => break_!(x, for_n_expr, flow),
                 (_, Flow::ContinueLoop(x)) => continue_!(x, for_n_expr, flow),
@@ -393,10 +354,7 @@ impl Runtime {
         Ok((Some(Variable::F64(max, sec)), flow))
     }
 
-    pub(crate) fn any_n_expr(
-        &mut self,
-        for_n_expr: &ast::ForN,
-    ) -> Result<(Option<Variable>, Flow), String> {
+    pub(crate) fn any_n_expr(&mut self, for_n_expr: &ast::ForN) -> FlowResult {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
 
@@ -433,10 +391,9 @@ impl Runtime {
                             }
                         }
                         x => {
-                            return Err(self.module.error(
+                            return Err(self.intrinsic_err(
                                 for_n_expr.block.source_range,
-                                &self.expected(x, "boolean"),
-                                self,
+                                self.expected(x, "boolean"),
                             ))
                         }
                     };
@@ -445,11 +402,7 @@ impl Runtime {
                     return Ok((x, Flow::Return));
                 }
                 (None, Flow::Continue) => {
-                    return Err(self.module.error(
-                        for_n_expr.block.source_range,
-                        "Expected `boolean`",
-                        self,
-                    ))
+                    return Err(self.error_at(for_n_expr.block.source_range, "Expected `boolean`"))
                 }
                 (_, Flow::Break(x)) => break_!(x, for_n_expr, flow),
                 (_, Flow::ContinueLoop(x)) => continue_!(x, for_n_expr, flow),
@@ -463,10 +416,7 @@ impl Runtime {
         Ok((Some(Variable::Bool(any, sec)), flow))
     }
 
-    pub(crate) fn all_n_expr(
-        &mut self,
-        for_n_expr: &ast::ForN,
-    ) -> Result<(Option<Variable>, Flow), String> {
+    pub(crate) fn all_n_expr(&mut self, for_n_expr: &ast::ForN) -> FlowResult {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
 
@@ -503,10 +453,9 @@ impl Runtime {
                             }
                         }
                         x => {
-                            return Err(self.module.error(
+                            return Err(self.intrinsic_err(
                                 for_n_expr.block.source_range,
-                                &self.expected(x, "boolean"),
-                                self,
+                                self.expected(x, "boolean"),
                             ))
                         }
                     };
@@ -515,11 +464,7 @@ impl Runtime {
                     return Ok((x, Flow::Return));
                 }
                 (None, Flow::Continue) => {
-                    return Err(self.module.error(
-                        for_n_expr.block.source_range,
-                        "Expected `boolean`",
-                        self,
-                    ))
+                    return Err(self.error_at(for_n_expr.block.source_range, "Expected `boolean`"))
                 }
                 (_, Flow::Break(x)) => break_!(x, for_n_expr, flow),
                 (_, Flow::ContinueLoop(x)) => continue_!(x, for_n_expr, flow),
@@ -533,17 +478,14 @@ impl Runtime {
         Ok((Some(Variable::Bool(all, sec)), flow))
     }
 
-    pub(crate) fn link_for_n_expr(
-        &mut self,
-        for_n_expr: &ast::ForN,
-    ) -> Result<(Option<Variable>, Flow), String> {
+    pub(crate) fn link_for_n_expr(&mut self, for_n_expr: &ast::ForN) -> FlowResult {
         use Link;
 
         fn sub_link_for_n_expr(
             res: &mut Link,
             rt: &mut Runtime,
             for_n_expr: &ast::ForN,
-        ) -> Result<(Option<Variable>, Flow), String> {
+        ) -> FlowResult {
             let prev_st = rt.stack.len();
             let prev_lc = rt.local_stack.len();
 
@@ -569,10 +511,9 @@ impl Runtime {
                             match rt.expression(item, Side::Right)? {
                                 (Some(ref x), Flow::Continue) => match res.push(rt.resolve(x)) {
                                     Err(err) => {
-                                        return Err(rt.module.error(
+                                        return Err(rt.error_at(
                                             for_n_expr.source_range,
                                             &format!("{}\n{}", rt.stack_trace(), err),
-                                            rt,
                                         ))
                                     }
                                     Ok(()) => {}
@@ -645,10 +586,7 @@ impl Runtime {
         }
     }
 
-    pub(crate) fn sift_n_expr(
-        &mut self,
-        for_n_expr: &ast::ForN,
-    ) -> Result<(Option<Variable>, Flow), String> {
+    pub(crate) fn sift_n_expr(&mut self, for_n_expr: &ast::ForN) -> FlowResult {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
         let mut res: Vec<Variable> = vec![];
@@ -667,16 +605,17 @@ impl Runtime {
         loop {
             cond!(self, for_n_expr, st, end);
             match self.block(&for_n_expr.block)? {
-                (Some(x), Flow::Continue) => res.push(x),
+                (Some(x), Flow::Continue) => {
+                    if let Err(err) = self.check_array_len(res.len() + 1) {
+                        return self.err_from(for_n_expr.block.source_range, err);
+                    }
+                    res.push(x)
+                }
                 (x, Flow::Return) => {
                     return Ok((x, Flow::Return));
                 }
                 (None, Flow::Continue) => {
-                    return Err(self.module.error(
-                        for_n_expr.block.source_range,
-                        "Expected variable",
-                        self,
-                    ))
+                    return Err(self.error_at(for_n_expr.block.source_range, "Expected variable"))
                 }
                 (_, Flow::Break(x)) => break_!(x, for_n_expr, flow),
                 (_, Flow::ContinueLoop(x)) => continue_!(x, for_n_expr, flow),
@@ -690,13 +629,10 @@ impl Runtime {
         Ok((Some(Variable::Array(Arc::new(res))), flow))
     }
 
-    pub(crate) fn sum_vec4_n_expr(
-        &mut self,
-        for_n_expr: &ast::ForN,
-    ) -> Result<(Option<Variable>, Flow), String> {
+    pub(crate) fn sum_vec4_n_expr(&mut self, for_n_expr: &ast::ForN) -> FlowResult {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
-        let mut sum: [f32; 4] = [0.0; 4];
+        let mut sum: [Scalar; 4] = [0.0; 4];
 
         let start = start!(self, for_n_expr);
         let end = end!(self, for_n_expr);
@@ -720,10 +656,9 @@ impl Runtime {
                             }
                         }
                         x => {
-                            return Err(self.module.error(
+                            return Err(self.intrinsic_err(
                                 for_n_expr.block.source_range,
-                                &self.expected(x, "vec4"),
-                                self,
+                                self.expected(x, "vec4"),
                             ))
                         }
                     };
@@ -732,11 +667,7 @@ impl Runtime {
                     return Ok((x, Flow::Return));
                 }
                 (None, Flow::Continue) => {
-                    return Err(self.module.error(
-                        for_n_expr.block.source_range,
-                        "Expected `vec4`",
-                        self,
-                    ))
+                    return Err(self.error_at(for_n_expr.block.source_range, "Expected `vec4`"))
                 }
                 (_, Flow::Break(x)) => break_!(x, for_n_expr, flow),
                 (_, Flow::ContinueLoop(x)) => continue_!(x, for_n_expr, flow),
@@ -750,13 +681,10 @@ impl Runtime {
         Ok((Some(Variable::Vec4(sum)), flow))
     }
 
-    pub(crate) fn prod_vec4_n_expr(
-        &mut self,
-        for_n_expr: &ast::ForN,
-    ) -> Result<(Option<Variable>, Flow), String> {
+    pub(crate) fn prod_vec4_n_expr(&mut self, for_n_expr: &ast::ForN) -> FlowResult {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
-        let mut prod: [f32; 4] = [1.0; 4];
+        let mut prod: [Scalar; 4] = [1.0; 4];
 
         let start = start!(self, for_n_expr);
         let end = end!(self, for_n_expr);
@@ -780,10 +708,9 @@ impl Runtime {
                             }
                         }
                         x => {
-                            return Err(self.module.error(
+                            return Err(self.intrinsic_err(
                                 for_n_expr.block.source_range,
-                                &self.expected(x, "vec4"),
-                                self,
+                                self.expected(x, "vec4"),
                             ))
                         }
                     };
@@ -792,11 +719,7 @@ impl Runtime {
                     return Ok((x, Flow::Return));
                 }
                 (None, Flow::Continue) => {
-                    return Err(self.module.error(
-                        for_n_expr.block.source_range,
-                        "Expected `vec4`",
-                        self,
-                    ))
+                    return Err(self.error_at(for_n_expr.block.source_range, "Expected `vec4`"))
                 }
                 (_, Flow::Break(x)) => break_!(x, for_n_expr, flow),
                 (_, Flow::ContinueLoop(x)) => continue_!(x, for_n_expr, flow),
@@ -0,0 +1,120 @@
+//! Running programs a limited number of steps at a time.
+
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+use super::*;
+
+/// Messages from a program running with fuel to the host.
+enum Message {
+    OutOfFuel,
+    Done(Box<Runtime>, Result<(), RuntimeError>),
+}
+
+/// The fuel of a runtime, stored while running with `Runtime::run_with_fuel`.
+pub(crate) struct Gauge {
+    left: u64,
+    host: Sender<Message>,
+    refill: Receiver<u64>,
+}
+
+/// The state of a program after running with fuel.
+pub enum FuelRun {
+    /// The program finished, giving back the runtime.
+    Done(Box<Runtime>, Result<(), RuntimeError>),
+    /// The program ran out of fuel.
+    OutOfFuel(Continuation),
+}
+
+/// A program that ran out of fuel, which can be resumed with more fuel.
+///
+/// Dropping the continuation stops the program.
+pub struct Continuation {
+    refill: Sender<u64>,
+    host: Receiver<Message>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Continuation {
+    /// Runs the program at most `fuel` more steps.
+    pub fn resume(self, fuel: u64) -> FuelRun {
+        // The program is waiting for fuel, so sending can not fail.
+        let _ = self.refill.send(fuel);
+        self.wait()
+    }
+
+    fn wait(mut self) -> FuelRun {
+        match self.host.recv() {
+            Ok(Message::OutOfFuel) => FuelRun::OutOfFuel(self),
+            Ok(Message::Done(rt, res)) => FuelRun::Done(rt, res),
+            Err(_) => {
+                // The program panicked, so pass on the panic.
+                let handle = self.handle.take().expect("Expected thread handle");
+                match handle.join() {
+                    Err(payload) => ::std::panic::resume_unwind(payload),
+                    Ok(()) => unreachable!("The program stopped without result"),
+                }
+            }
+        }
+    }
+}
+
+impl Runtime {
+    /// Runs the `main` function at most `fuel` steps,
+    /// returning a continuation if the program is not done.
+    ///
+    /// A step is a statement or a loop iteration.
+    /// The program runs on another thread, which waits while the program is out of fuel,
+    /// such that the host can run many programs a few steps at a time, e.g. per frame.
+    pub fn run_with_fuel(mut self, module: &Arc<Module>, fuel: u64) -> FuelRun {
+        let (host_tx, host_rx) = channel();
+        let (refill_tx, refill_rx) = channel();
+        self.fuel = Some(Gauge {
+            left: fuel,
+            host: host_tx.clone(),
+            refill: refill_rx,
+        });
+        let module = module.clone();
+        let handle = thread::spawn(move || {
+            let mut rt = self;
+            let res = rt.run(&module);
+            rt.fuel = None;
+            let _ = host_tx.send(Message::Done(Box::new(rt), res));
+        });
+        Continuation {
+            refill: refill_tx,
+            host: host_rx,
+            handle: Some(handle),
+        }
+        .wait()
+    }
+
+    /// Uses one step of fuel, waiting for more fuel when there is none left.
+    pub(crate) fn burn_fuel(&mut self, range: Range) -> Result<(), RuntimeError> {
+        let gauge = match self.fuel {
+            None => return Ok(()),
+            Some(ref mut gauge) => gauge,
+        };
+        while gauge.left == 0 {
+            let fuel = gauge
+                .host
+                .send(Message::OutOfFuel)
+                .ok()
+                .and_then(|()| gauge.refill.recv().ok());
+            match fuel {
+                Some(fuel) => gauge.left = fuel,
+                None => {
+                    // The continuation was dropped, so unwind the program.
+                    // Nobody receives this error, since the host is gone.
+                    return Err(RuntimeError::new(
+                        ErrorKind::Cancelled,
+                        Some(range),
+                        String::new(),
+                    ));
+                }
+            }
+        }
+        gauge.left -= 1;
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use super::*;
+
+/// The upper bounds of histogram buckets, the same as the Prometheus defaults.
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Observations of a histogram.
+#[derive(Clone, Debug)]
+pub(crate) struct Histogram {
+    count: u64,
+    sum: f64,
+    min: f64,
+    max: f64,
+    /// Number of observations less or equal to each bucket bound.
+    buckets: [u64; 11],
+}
+
+impl Default for Histogram {
+    fn default() -> Histogram {
+        Histogram {
+            count: 0,
+            sum: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            buckets: [0; 11],
+        }
+    }
+}
+
+impl Histogram {
+    pub(crate) fn observe(&mut self, v: f64) {
+        self.count += 1;
+        self.sum += v;
+        self.min = self.min.min(v);
+        self.max = self.max.max(v);
+        for (bucket, &le) in self.buckets.iter_mut().zip(BUCKETS.iter()) {
+            if v <= le {
+                *bucket += 1;
+            }
+        }
+    }
+}
+
+/// A counter handle, stored in a Rust object.
+pub(crate) struct Counter(pub Arc<Mutex<f64>>);
+
+/// A histogram handle, stored in a Rust object.
+pub(crate) struct HistogramHandle(pub Arc<Mutex<Histogram>>);
+
+/// Counters and histograms created by scripts.
+///
+/// Runtimes share metrics with the threads they start with `go`.
+/// Use `Runtime::set_metrics` to share metrics between other runtimes.
+#[derive(Default)]
+pub struct Metrics {
+    counters: Mutex<BTreeMap<Arc<String>, Arc<Mutex<f64>>>>,
+    histograms: Mutex<BTreeMap<Arc<String>, Arc<Mutex<Histogram>>>>,
+}
+
+impl Metrics {
+    /// Creates new empty metrics.
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    pub(crate) fn counter(&self, name: &Arc<String>) -> Arc<Mutex<f64>> {
+        let mut counters = self.counters.lock().unwrap();
+        counters.entry(name.clone()).or_default().clone()
+    }
+
+    pub(crate) fn histogram(&self, name: &Arc<String>) -> Arc<Mutex<Histogram>> {
+        let mut histograms = self.histograms.lock().unwrap();
+        histograms.entry(name.clone()).or_default().clone()
+    }
+
+    /// Returns the metrics as an object with `counters` and `histograms`.
+    ///
+    /// Histograms have `count`, `sum`, `min`, `max`
+    /// and `buckets` with `[upper bound, count]` pairs.
+    // The report is a new variable, which is shared by `Arc` like other objects.
+    #[allow(clippy::arc_with_non_send_sync)]
+    pub fn report(&self) -> Variable {
+        let key = |s: &str| Arc::new(s.to_string());
+        let mut counters = HashMap::new();
+        for (name, value) in self.counters.lock().unwrap().iter() {
+            counters.insert(name.clone(), Variable::f64(*value.lock().unwrap()));
+        }
+        let mut histograms = HashMap::new();
+        for (name, h) in self.histograms.lock().unwrap().iter() {
+            let h = h.lock().unwrap().clone();
+            let buckets = BUCKETS
+                .iter()
+                .zip(h.buckets.iter())
+                .map(|(&le, &n)| {
+                    Variable::Array(Arc::new(vec![Variable::f64(le), Variable::f64(n as f64)]))
+                })
+                .collect();
+            let mut obj = HashMap::new();
+            obj.insert(key("count"), Variable::f64(h.count as f64));
+            obj.insert(key("sum"), Variable::f64(h.sum));
+            obj.insert(key("min"), Variable::f64(h.min));
+            obj.insert(key("max"), Variable::f64(h.max));
+            obj.insert(key("buckets"), Variable::Array(Arc::new(buckets)));
+            histograms.insert(name.clone(), Variable::Object(Arc::new(obj)));
+        }
+        let mut obj = HashMap::new();
+        obj.insert(key("counters"), Variable::Object(Arc::new(counters)));
+        obj.insert(key("histograms"), Variable::Object(Arc::new(histograms)));
+        Variable::Object(Arc::new(obj))
+    }
+
+    /// Returns the metrics in the Prometheus text format.
+    ///
+    /// Characters in names that Prometheus does not allow are replaced by `_`.
+    pub fn prometheus(&self) -> String {
+        use std::fmt::Write;
+
+        let mut s = String::new();
+        for (name, value) in self.counters.lock().unwrap().iter() {
+            let name = prometheus_name(name);
+            let _ = writeln!(s, "# TYPE {} counter", name);
+            let _ = writeln!(s, "{} {}", name, *value.lock().unwrap());
+        }
+        for (name, h) in self.histograms.lock().unwrap().iter() {
+            let name = prometheus_name(name);
+            let h = h.lock().unwrap().clone();
+            let _ = writeln!(s, "# TYPE {} histogram", name);
+            for (&le, &n) in BUCKETS.iter().zip(h.buckets.iter()) {
+                let _ = writeln!(s, "{}_bucket{{le=\"{}\"}} {}", name, le, n);
+            }
+            let _ = writeln!(s, "{}_bucket{{le=\"+Inf\"}} {}", name, h.count);
+            let _ = writeln!(s, "{}_sum {}", name, h.sum);
+            let _ = writeln!(s, "{}_count {}", name, h.count);
+        }
+        s
+    }
+}
+
+fn prometheus_name(name: &str) -> String {
+    let mut res: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == ':' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if res.starts_with(|c: char| c.is_ascii_digit()) {
+        res.insert(0, '_');
+    }
+    res
+}
+
+impl Runtime {
+    /// Sets the metrics used by `counter` and `histogram`.
+    pub fn set_metrics(&mut self, metrics: Arc<Metrics>) {
+        self.metrics = metrics;
+    }
+
+    /// Returns the metrics used by `counter` and `histogram`.
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
+    }
+}
@@ -4,21 +4,50 @@ use rand;
 use range::Range;
 use std::cell::Cell;
 use std::collections::HashMap;
+use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
 
 use ast;
+use bytecode;
 use embed;
+use gc;
+use kernel;
+use vfs::{OsFs, Vfs};
 
 use FnIndex;
 use Module;
+use Scalar;
 use UnsafeRef;
 use Variable;
 use TINVOTS;
 
+mod cancel;
+mod debug;
+mod error;
 mod for_in;
+#[cfg(all(not(target_family = "wasm"), feature = "threading"))]
+mod fuel;
+pub(crate) mod metrics;
+mod profile;
+mod scheduler;
+mod settings;
+mod timer;
+
+pub use self::cancel::CancelHandle;
+pub use self::debug::{DebugEvent, DebugEventKind, DebugHook, TelemetryHook};
+use self::error::type_mismatch;
+pub use self::error::ErrorKind;
+pub use self::error::RuntimeError;
+#[cfg(all(not(target_family = "wasm"), feature = "threading"))]
+pub use self::fuel::{Continuation, FuelRun};
+pub use self::metrics::Metrics;
+use self::profile::Profiler;
+pub use self::scheduler::Scheduler;
+pub use self::settings::{Limit, RuntimeSettings};
+use self::timer::Timers;
 mod for_n;
 
-type FlowResult = Result<(Option<Variable>, Flow), String>;
+type FlowResult = Result<(Option<Variable>, Flow), RuntimeError>;
 
 /// Which side an expression is evaluated.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -50,11 +79,25 @@ pub struct Call {
     /// The index of the relative function in module.
     pub(crate) index: usize,
     file: Option<Arc<String>>,
+    /// The source of the function, used to find lines in stack traces.
+    source: Arc<String>,
+    /// Where the function was called from, in the source of the caller.
+    range: Option<Range>,
     // was .1
     stack_len: usize,
     // was .2
     local_len: usize,
     current_len: usize,
+    /// Whether a call in tail position can reuse this frame.
+    tail: bool,
+}
+
+/// A call in tail position, made by the caller after the callee returns.
+struct TailCall {
+    /// The index of the function in module.
+    index: usize,
+    name: Arc<String>,
+    args: Vec<Variable>,
 }
 
 lazy_static! {
@@ -75,6 +118,9 @@ lazy_static! {
     pub(crate) static ref THREAD_TYPE: Arc<String> = Arc::new("thread".into());
     pub(crate) static ref CLOSURE_TYPE: Arc<String> = Arc::new("closure".into());
     pub(crate) static ref IN_TYPE: Arc<String> = Arc::new("in".into());
+    pub(crate) static ref OUT_TYPE: Arc<String> = Arc::new("out".into());
+    pub(crate) static ref BYTES_TYPE: Arc<String> = Arc::new("bytes".into());
+    pub(crate) static ref F64_ARRAY_TYPE: Arc<String> = Arc::new("farr".into());
     pub(crate) static ref MAIN: Arc<String> = Arc::new("main".into());
 }
 
@@ -94,8 +140,53 @@ pub struct Runtime {
     /// until it finds the last current variable with the name.
     pub current_stack: Vec<(Arc<String>, usize)>,
     pub(crate) rng: rand::rngs::StdRng,
+    /// The last seed of the random number generator.
+    pub(crate) seed: u64,
     /// External functions can choose to report an error on an argument.
     pub arg_err_index: Cell<Option<usize>>,
+    /// The call stack length and range of the current intrinsic call.
+    pub(crate) intrinsic_range: Option<(usize, Range)>,
+    /// Stops the program with an error when set to `true`.
+    pub(crate) interrupt: Option<Arc<AtomicBool>>,
+    /// Collects rich output, e.g. from `display_html`.
+    /// Rich output is ignored when this is `None`.
+    pub(crate) display: Option<Vec<kernel::Display>>,
+    /// Stores values for `gc_new`, when enabled.
+    pub(crate) gc: Option<gc::Heap>,
+    /// The values of `grab` expressions in the closures being created.
+    pub(crate) grabbed: Vec<Variable>,
+    /// Flags set by the host, queried by `flag`.
+    pub(crate) flags: HashMap<Arc<String>, bool>,
+    /// The file system used by file functions.
+    pub(crate) vfs: Arc<dyn Vfs>,
+    /// Called on every statement and call, when set.
+    pub(crate) debug_hook: Option<DebugHook>,
+    /// Called after every intrinsic call, when set.
+    pub(crate) telemetry_hook: Option<TelemetryHook>,
+    /// Records time per function, when enabled.
+    pub(crate) profiler: Option<Profiler>,
+    /// Decides what happens in `yield_frame` and `sleep_until`, when set.
+    pub(crate) scheduler: Option<Box<dyn Scheduler>>,
+    /// Timers started by `after` and `every`.
+    pub(crate) timers: Timers,
+    /// Counters and histograms, shared with `go` threads.
+    pub(crate) metrics: Arc<Metrics>,
+    /// Limits for running untrusted scripts.
+    pub(crate) settings: RuntimeSettings,
+    /// The instructions left of the budget, if any.
+    pub(crate) budget: Option<u64>,
+    /// When the time budget runs out, if any.
+    #[cfg(not(target_family = "wasm"))]
+    pub(crate) deadline: Option<std::time::Instant>,
+    /// The fuel left when running with `run_with_fuel`.
+    #[cfg(all(not(target_family = "wasm"), feature = "threading"))]
+    pub(crate) fuel: Option<fuel::Gauge>,
+    /// The index of the number in `farr` to assign, after looking up the left side.
+    farr_index: Option<usize>,
+    /// Set when the next loaded call is in tail position.
+    tail_position: bool,
+    /// The call to make in the current frame when the function returns.
+    tail_call: Option<TailCall>,
 }
 
 impl Default for Runtime {
@@ -113,6 +204,7 @@ fn resolve<'a>(stack: &'a [Variable], var: &'a Variable) -> &'a Variable {
 }
 
 // Looks up an item from a variable property.
+#[allow(clippy::too_many_arguments)]
 fn item_lookup(
     module: &Module,
     var: *mut Variable,
@@ -123,7 +215,7 @@ fn item_lookup(
     expr_j: &mut usize,
     insert: bool, // Whether to insert key in object.
     last: bool,   // Whether it is the last property.
-) -> Result<*mut Variable, String> {
+) -> Result<*mut Variable, RuntimeError> {
     use ast::Id;
     use std::collections::hash_map::Entry;
 
@@ -145,21 +237,25 @@ fn item_lookup(
                                 *expr_j += 1;
                                 id.clone()
                             }
-                            _ => {
-                                return Err(module.error_fnindex(
+                            ref x => {
+                                return Err(lookup_err(
+                                    module,
+                                    call_stack,
+                                    type_mismatch("string", &x.typeof_var()),
                                     prop.source_range(),
-                                    &format!("{}\nExpected string", stack_trace(call_stack)),
-                                    call_stack.last().unwrap().index,
-                                ))
+                                    "Expected string",
+                                ));
                             }
                         }
                     }
                     Id::F64(range, _) => {
-                        return Err(module.error_fnindex(
+                        return Err(lookup_err(
+                            module,
+                            call_stack,
+                            type_mismatch("string", "number"),
                             range,
-                            &format!("{}\nExpected string", stack_trace(call_stack)),
-                            call_stack.last().unwrap().index,
-                        ))
+                            "Expected string",
+                        ));
                     }
                 };
                 let v = match Arc::make_mut(obj).entry(id.clone()) {
@@ -168,10 +264,14 @@ fn item_lookup(
                             // Insert a key to overwrite with new value.
                             vac.insert(Variable::Return)
                         } else {
-                            return Err(module.error_fnindex(
+                            return Err(lookup_err(
+                                module,
+                                call_stack,
+                                ErrorKind::NotFound {
+                                    name: (*id).clone(),
+                                },
                                 prop.source_range(),
-                                &format!("{}\nObject has no key `{}`", stack_trace(call_stack), id),
-                                call_stack.last().unwrap().index,
+                                &format!("Object has no key `{}`", id),
                             ));
                         }
                     }
@@ -221,6 +321,7 @@ fn item_lookup(
                                 //   \---- points to first array `[a, b]`.
                                 let mut arr: *mut Vec<Variable> = Arc::make_mut(arr);
                                 let n = indices.len();
+                                let mut cause = ErrorKind::Custom;
                                 for (i, ind) in indices.iter().enumerate() {
                                     let id = match ind {
                                         Variable::F64(id, _) => *id,
@@ -228,22 +329,27 @@ fn item_lookup(
                                             if let Variable::F64(id, _) = prev_stack[*x] {
                                                 id
                                             } else {
+                                                cause = type_mismatch(
+                                                    "number",
+                                                    &prev_stack[*x].typeof_var(),
+                                                );
                                                 break;
                                             }
                                         }
-                                        _ => break,
+                                        _ => {
+                                            cause = type_mismatch("number", &ind.typeof_var());
+                                            break;
+                                        }
                                     };
-                                    let v = match (*arr).get_mut(id as usize) {
+                                    let v = match (&mut *arr).get_mut(id as usize) {
                                         None => {
-                                            return Err(module.error_fnindex(
+                                            return Err(lookup_err(
+                                                module,
+                                                call_stack,
+                                                ErrorKind::IndexOutOfBounds { index: id },
                                                 prop.source_range(),
-                                                &format!(
-                                                    "{}\nOut of bounds `{}`",
-                                                    stack_trace(call_stack),
-                                                    id
-                                                ),
-                                                call_stack.last().unwrap().index,
-                                            ))
+                                                &format!("Out of bounds `{}`", id),
+                                            ));
                                         }
                                         Some(x) => x,
                                     };
@@ -270,45 +376,57 @@ fn item_lookup(
                                             {
                                                 arr = Arc::make_mut(new_arr);
                                             } else {
+                                                cause = type_mismatch(
+                                                    "array",
+                                                    &prev_stack[x].typeof_var(),
+                                                );
                                                 break;
                                             }
                                         }
-                                        _ => break,
+                                        ref x => {
+                                            cause = type_mismatch("array", &x.typeof_var());
+                                            break;
+                                        }
                                     }
                                 }
-                                return Err(module.error_fnindex(
+                                return Err(lookup_err(
+                                    module,
+                                    call_stack,
+                                    cause,
                                     prop.source_range(),
-                                    &format!(
-                                        "{}\nArray of indices did not match lookup array",
-                                        stack_trace(call_stack)
-                                    ),
-                                    call_stack.last().unwrap().index,
+                                    "Array of indices did not match lookup array",
                                 ));
                             }
-                            _ => {
-                                return Err(module.error_fnindex(
+                            ref x => {
+                                return Err(lookup_err(
+                                    module,
+                                    call_stack,
+                                    type_mismatch("number", &x.typeof_var()),
                                     prop.source_range(),
-                                    &format!("{}\nExpected number", stack_trace(call_stack)),
-                                    call_stack.last().unwrap().index,
-                                ))
+                                    "Expected number",
+                                ));
                             }
                         }
                     }
                     Id::String(range, _) => {
-                        return Err(module.error_fnindex(
+                        return Err(lookup_err(
+                            module,
+                            call_stack,
+                            type_mismatch("number", "string"),
                             range,
-                            &format!("{}\nExpected number", stack_trace(call_stack)),
-                            call_stack.last().unwrap().index,
-                        ))
+                            "Expected number",
+                        ));
                     }
                 };
                 let v = match Arc::make_mut(arr).get_mut(id as usize) {
                     None => {
-                        return Err(module.error_fnindex(
+                        return Err(lookup_err(
+                            module,
+                            call_stack,
+                            ErrorKind::IndexOutOfBounds { index: id },
                             prop.source_range(),
-                            &format!("{}\nOut of bounds `{}`", stack_trace(call_stack), id),
-                            call_stack.last().unwrap().index,
-                        ))
+                            &format!("Out of bounds `{}`", id),
+                        ));
                     }
                     Some(x) => x,
                 };
@@ -325,42 +443,300 @@ fn item_lookup(
                     Ok(v)
                 }
             }
-            _ => Err(module.error_fnindex(
+            ref x => Err(lookup_err(
+                module,
+                call_stack,
+                type_mismatch("object or array", &x.typeof_var()),
                 prop.source_range(),
-                &format!(
-                    "{}\nLook up requires object or array",
-                    stack_trace(call_stack)
-                ),
-                call_stack.last().unwrap().index,
+                "Look up requires object or array",
             )),
         }
     }
 }
 
+// Returns an error that a function called by the host was not found.
+fn not_found_fn(function: &str) -> RuntimeError {
+    RuntimeError::new(
+        ErrorKind::NotFound {
+            name: function.into(),
+        },
+        None,
+        format!("Could not find function `{}`", function),
+    )
+}
+
+// Returns an error from an operator.
+//
+// Operators do not have access to the runtime,
+// so the expected types are taken from the error message.
+fn operator_err(err: String, found: String) -> RuntimeError {
+    if err.starts_with("Expected") {
+        let kind = ErrorKind::TypeMismatch {
+            expected: err.trim_start_matches("Expected ").into(),
+            found,
+        };
+        RuntimeError::new(kind, None, err)
+    } else {
+        err.into()
+    }
+}
+
+// Returns an error from looking up an item.
+fn lookup_err(
+    module: &Module,
+    call_stack: &[Call],
+    kind: ErrorKind,
+    range: Range,
+    msg: &str,
+) -> RuntimeError {
+    let message = module.error_fnindex(
+        range,
+        &format!("{}\n{}", stack_trace(call_stack), msg),
+        call_stack.last().unwrap().index,
+    );
+    RuntimeError::new(kind, Some(range), message)
+}
+
+// Looks up the index of a number in `farr`.
+#[allow(clippy::too_many_arguments)]
+fn farr_lookup(
+    module: &Module,
+    len: usize,
+    stack: &[Variable],
+    call_stack: &[Call],
+    prop: &ast::Id,
+    start_stack_len: usize,
+    expr_j: &mut usize,
+) -> Result<usize, RuntimeError> {
+    use ast::Id;
+
+    let id = match *prop {
+        Id::F64(_, id) => id,
+        Id::Expression(_) => {
+            let id = start_stack_len + *expr_j;
+            // Resolve reference of computed expression.
+            let id = if let Variable::Ref(ref_id) = stack[id] {
+                ref_id
+            } else {
+                id
+            };
+            match stack[id] {
+                Variable::F64(id, _) => {
+                    *expr_j += 1;
+                    id
+                }
+                ref x => {
+                    return Err(lookup_err(
+                        module,
+                        call_stack,
+                        type_mismatch("number", &x.typeof_var()),
+                        prop.source_range(),
+                        "Expected number",
+                    ));
+                }
+            }
+        }
+        Id::String(range, _) => {
+            return Err(lookup_err(
+                module,
+                call_stack,
+                type_mismatch("number", "string"),
+                range,
+                "Expected number",
+            ));
+        }
+    };
+    if id as usize >= len {
+        return Err(lookup_err(
+            module,
+            call_stack,
+            ErrorKind::IndexOutOfBounds { index: id },
+            prop.source_range(),
+            &format!("Out of bounds `{}`", id),
+        ));
+    }
+    Ok(id as usize)
+}
+
 impl Runtime {
     /// Creates a new Runtime.
     pub fn new() -> Runtime {
-        use rand::FromEntropy;
+        use rand::SeedableRng;
 
+        // Keep the seed within the integers that `f64` represents exactly,
+        // such that scripts can pass it to `seed`.
+        let seed = rand::random::<u64>() >> 11;
         Runtime {
             module: Arc::new(Module::empty()),
             stack: vec![],
             call_stack: vec![],
             local_stack: vec![],
             current_stack: vec![],
-            rng: rand::rngs::StdRng::from_entropy(),
+            rng: rand::rngs::StdRng::seed_from_u64(seed),
+            seed,
             arg_err_index: Cell::new(None),
+            intrinsic_range: None,
+            interrupt: None,
+            display: None,
+            gc: None,
+            grabbed: vec![],
+            flags: HashMap::new(),
+            vfs: Arc::new(OsFs),
+            debug_hook: None,
+            telemetry_hook: None,
+            profiler: None,
+            scheduler: None,
+            timers: Timers::default(),
+            metrics: Arc::new(Metrics::new()),
+            settings: RuntimeSettings::default(),
+            budget: None,
+            #[cfg(not(target_family = "wasm"))]
+            deadline: None,
+            #[cfg(all(not(target_family = "wasm"), feature = "threading"))]
+            fuel: None,
+            farr_index: None,
+            tail_position: false,
+            tail_call: None,
+        }
+    }
+
+    /// Creates a new Runtime with a garbage collected heap.
+    ///
+    /// This enables `gc_new`, `gc_get`, `gc_set` and `gc_collect`,
+    /// which scripts can use to build cyclic data structures.
+    pub fn with_gc() -> Runtime {
+        let mut rt = Runtime::new();
+        rt.gc = Some(gc::Heap::new());
+        rt
+    }
+
+    /// Sets a flag that scripts can query with `flag`.
+    ///
+    /// Use this to tell scripts about the environment they run in,
+    /// e.g. `rt.set_flag("sandbox", true)`.
+    pub fn set_flag(&mut self, name: &str, value: bool) {
+        self.flags.insert(Arc::new(name.into()), value);
+    }
+
+    /// Returns the value of a flag.
+    ///
+    /// Flags not set by the host are `true` for enabled crate features,
+    /// see `dyon::features`, and `false` otherwise.
+    pub fn flag(&self, name: &str) -> bool {
+        match self.flags.get(&Arc::new(name.to_string())) {
+            Some(&value) => value,
+            None => ::features().contains(&name),
         }
     }
 
+    /// Sets the file system used by file functions.
+    ///
+    /// Use this to control what scripts can access,
+    /// e.g. `MemoryFs` for tests or `RestrictedFs` for sandboxes.
+    pub fn set_vfs(&mut self, vfs: Arc<dyn Vfs>) {
+        self.vfs = vfs;
+    }
+
+    /// Returns the file system used by file functions.
+    pub fn vfs(&self) -> &Arc<dyn Vfs> {
+        &self.vfs
+    }
+
+    /// Seeds the random number generator,
+    /// such that the following random numbers are reproducible.
+    pub fn set_seed(&mut self, seed: u64) {
+        use rand::SeedableRng;
+
+        self.rng = rand::rngs::StdRng::seed_from_u64(seed);
+        self.seed = seed;
+    }
+
+    /// Returns the last seed of the random number generator.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Returns the conditions of a run, such that results can be reproduced.
+    ///
+    /// This is an object with the Dyon version, enabled features,
+    /// the last seed, program arguments and SHA-256 hashes of the loaded files.
+    /// The hashes are `none()` without the `crypto` feature.
+    pub fn run_manifest(&self, module: &Module) -> Variable {
+        let mut modules = HashMap::new();
+        for f in &module.functions {
+            if !modules.contains_key(&f.file) {
+                #[cfg(feature = "crypto")]
+                let hash = {
+                    use dyon_std::sha256::{hex, Digest, Sha256};
+
+                    Variable::Str(Arc::new(hex(&Sha256::digest(f.source.as_bytes()))))
+                };
+                #[cfg(not(feature = "crypto"))]
+                let hash = Variable::Option(None);
+                modules.insert(f.file.clone(), hash);
+            }
+        }
+        let strs = |items: Vec<String>| {
+            Variable::Array(Arc::new(
+                items
+                    .into_iter()
+                    .map(|s| Variable::Str(Arc::new(s)))
+                    .collect(),
+            ))
+        };
+        let mut obj = HashMap::new();
+        obj.insert(
+            Arc::new("version".into()),
+            Variable::Str(Arc::new(env!("CARGO_PKG_VERSION").into())),
+        );
+        obj.insert(
+            Arc::new("features".into()),
+            strs(::features().into_iter().map(|f| f.into()).collect()),
+        );
+        obj.insert(Arc::new("seed".into()), Variable::f64(self.seed as f64));
+        obj.insert(
+            Arc::new("args".into()),
+            strs(
+                ::std::env::args_os()
+                    .map(|a| a.to_string_lossy().into_owned())
+                    .collect(),
+            ),
+        );
+        obj.insert(
+            Arc::new("modules".into()),
+            Variable::Object(Arc::new(modules)),
+        );
+        Variable::Object(Arc::new(obj))
+    }
+
+    /// Saves the run manifest next to an output file, as `<output>.manifest`.
+    ///
+    /// Returns the file name of the manifest.
+    pub fn save_run_manifest(&self, module: &Module, output: &str) -> Result<String, String> {
+        use write::{write_variable, EscapeString};
+
+        let file = format!("{}.manifest", output);
+        let mut buf = vec![];
+        write_variable(
+            &mut buf,
+            self,
+            &self.run_manifest(module),
+            EscapeString::Json,
+            0,
+        )
+        .and_then(|()| self.vfs.write_atomic(&file, &buf))
+        .map_err(|err| format!("Error when writing to file `{}`:\n{}", file, err))?;
+        Ok(file)
+    }
+
     /// Pops variable from stack.
-    pub fn pop<T: embed::PopVariable>(&mut self) -> Result<T, String> {
+    pub fn pop<T: embed::PopVariable>(&mut self) -> Result<T, RuntimeError> {
         let v = self.stack.pop().unwrap_or_else(|| panic!("{}", TINVOTS));
         T::pop_var(self, self.resolve(&v))
     }
 
     /// Pops 4D vector from stack.
-    pub fn pop_vec4<T: embed::ConvertVec4>(&mut self) -> Result<T, String> {
+    pub fn pop_vec4<T: embed::ConvertVec4>(&mut self) -> Result<T, RuntimeError> {
         let v = self.stack.pop().unwrap_or_else(|| panic!("{}", TINVOTS));
         match self.resolve(&v) {
             &Variable::Vec4(val) => Ok(T::from(val)),
@@ -369,7 +745,7 @@ impl Runtime {
     }
 
     /// Pops 4D matrix from stack.
-    pub fn pop_mat4<T: embed::ConvertMat4>(&mut self) -> Result<T, String> {
+    pub fn pop_mat4<T: embed::ConvertMat4>(&mut self) -> Result<T, RuntimeError> {
         let v = self.stack.pop().unwrap_or_else(|| panic!("{}", TINVOTS));
         match self.resolve(&v) {
             &Variable::Mat4(ref val) => Ok(T::from(**val)),
@@ -378,7 +754,7 @@ impl Runtime {
     }
 
     /// Gets variable.
-    pub fn var<T: embed::PopVariable>(&self, var: &Variable) -> Result<T, String> {
+    pub fn var<T: embed::PopVariable>(&self, var: &Variable) -> Result<T, RuntimeError> {
         T::pop_var(self, self.resolve(var))
     }
 
@@ -396,28 +772,34 @@ impl Runtime {
     /// ```
     /// Rust code:
     /// ```rust
-    /// use dyon::Runtime;
+    /// use dyon::{Runtime, RuntimeError};
     ///
-    /// fn teleport(rt: &mut Runtime) -> Result<(), String> {
+    /// fn teleport(rt: &mut Runtime) -> Result<(), RuntimeError> {
     ///     let current_entity_id = rt.current_object::<u32>("entity")?;
     ///     assert_eq!(current_entity_id, 42);
     ///     Ok(())
     /// }
     /// ```
-    pub fn current_object<T: embed::PopVariable>(&self, name: &str) -> Result<T, String> {
+    pub fn current_object<T: embed::PopVariable>(&self, name: &str) -> Result<T, RuntimeError> {
         let current_object_index = self
             .current_stack
             .iter()
             .rev()
             .find(|(name_found, _)| **name_found == name)
             .map(|x| x.1)
-            .ok_or(format!("Could not find current variable `{}`", name))?;
+            .ok_or_else(|| {
+                RuntimeError::new(
+                    ErrorKind::NotFound { name: name.into() },
+                    None,
+                    format!("Could not find current variable `{}`", name),
+                )
+            })?;
 
         T::pop_var(self, self.resolve(&self.stack[current_object_index]))
     }
 
     /// Gets 4D vector.
-    pub fn var_vec4<T: embed::ConvertVec4>(&self, var: &Variable) -> Result<T, String> {
+    pub fn var_vec4<T: embed::ConvertVec4>(&self, var: &Variable) -> Result<T, RuntimeError> {
         match self.resolve(var) {
             &Variable::Vec4(val) => Ok(T::from(val)),
             x => Err(self.expected(x, "vec4")),
@@ -425,7 +807,7 @@ impl Runtime {
     }
 
     /// Gets 4D matrix.
-    pub fn var_mat4<T: embed::ConvertMat4>(&self, var: &Variable) -> Result<T, String> {
+    pub fn var_mat4<T: embed::ConvertMat4>(&self, var: &Variable) -> Result<T, RuntimeError> {
         match self.resolve(var) {
             &Variable::Mat4(ref val) => Ok(T::from(**val)),
             x => Err(self.expected(x, "mat4")),
@@ -459,19 +841,23 @@ impl Runtime {
     ///
     /// Sets argument error index on runtime such that
     /// external functions can report proper range.
-    pub fn expected_arg(&self, arg: usize, var: &Variable, ty: &str) -> String {
+    pub fn expected_arg(&self, arg: usize, var: &Variable, ty: &str) -> RuntimeError {
         self.arg_err_index.set(Some(arg));
         self.expected(var, ty)
     }
 
     /// Generates error message that a certain type was expected.
-    pub fn expected(&self, var: &Variable, ty: &str) -> String {
+    pub fn expected(&self, var: &Variable, ty: &str) -> RuntimeError {
         let found_ty = var.typeof_var();
-        format!(
-            "{}\nExpected `{}`, found `{}`",
-            self.stack_trace(),
-            ty,
-            found_ty
+        RuntimeError::new(
+            type_mismatch(ty, &found_ty),
+            None,
+            format!(
+                "{}\nExpected `{}`, found `{}`",
+                self.stack_trace(),
+                ty,
+                found_ty
+            ),
         )
     }
 
@@ -481,26 +867,47 @@ impl Runtime {
         resolve(&self.stack, var)
     }
 
+    /// Moves an argument of a tail call out of the frame starting at `st`.
+    ///
+    /// References to variables in the frame are replaced by their values,
+    /// since the frame is reused by the call.
+    fn tail_arg(&self, mut v: Variable, st: usize) -> Variable {
+        while let Variable::Ref(ind) = v {
+            if ind < st {
+                break;
+            }
+            v = self.stack[ind].clone();
+        }
+        v
+    }
+
     #[inline(always)]
-    fn push_fn(
+    pub(crate) fn push_fn(
         &mut self,
         name: Arc<String>,
         index: usize,
-        file: Option<Arc<String>>,
-        st: usize,
-        lc: usize,
-        cu: usize,
-    ) {
+        (file, source): (Option<Arc<String>>, Arc<String>),
+        range: Option<Range>,
+        (st, lc, cu): (usize, usize, usize),
+    ) -> Result<(), RuntimeError> {
+        self.check_call_depth(range)?;
+        if let Some(ref mut p) = self.profiler {
+            p.enter(&name);
+        }
         self.call_stack.push(Call {
             fn_name: name,
             index,
             file,
+            source,
+            range,
             stack_len: st,
             local_len: lc,
             current_len: cu,
+            tail: false,
         });
+        Ok(())
     }
-    fn pop_fn(&mut self, name: Arc<String>) {
+    pub(crate) fn pop_fn(&mut self, name: Arc<String>) {
         match self.call_stack.pop() {
             None => panic!("Did not call `{}`", name),
             Some(Call {
@@ -513,6 +920,9 @@ impl Runtime {
                 if name != fn_name {
                     panic!("Calling `{}`, did not call `{}`", fn_name, name);
                 }
+                if let Some(ref mut p) = self.profiler {
+                    p.exit();
+                }
                 self.stack.truncate(st);
                 self.local_stack.truncate(lc);
                 self.current_stack.truncate(cu);
@@ -534,9 +944,14 @@ impl Runtime {
     }
 
     fn err(&self, range: Range, msg: &str) -> FlowResult {
-        Err(self
-            .module
-            .error(range, &format!("{}\n{}", self.stack_trace(), msg), self))
+        Err(self.error_at(range, &format!("{}\n{}", self.stack_trace(), msg)))
+    }
+
+    /// Reports an error at a range in the source, keeping its cause.
+    fn err_from(&self, range: Range, err: RuntimeError) -> FlowResult {
+        let msg = format!("{}\n{}", self.stack_trace(), err.message());
+        let msg = self.module.error(range, &msg, self);
+        Err(err.at(range, msg))
     }
 
     pub(crate) fn expression(&mut self, expr: &ast::Expression, side: Side) -> FlowResult {
@@ -549,6 +964,9 @@ impl Runtime {
             ArrayFill(ref array_fill) => self.array_fill(array_fill),
             Block(ref block) => self.block(block),
             Return(ref ret) => {
+                if let CallLoaded(_) = **ret {
+                    self.tail_position = self.call_stack.last().map(|c| c.tail).unwrap_or(false);
+                }
                 let x = match self.expression(ret, Side::Right)? {
                     (Some(x), Flow::Continue) => x,
                     (x, Flow::Return) => {
@@ -651,31 +1069,14 @@ impl Runtime {
     }
 
     fn try_expr(&mut self, try_expr: &ast::TryExpr) -> FlowResult {
-        use Error;
-
-        let cs = self.call_stack.len();
-        let st = self.stack.len();
-        let lc = self.local_stack.len();
-        let cu = self.current_stack.len();
+        let point = self.catch_point();
         match self.expression(&try_expr.expr, Side::Right) {
             Ok((Some(x), Flow::Continue)) => {
                 Ok((Some(Variable::Result(Ok(Box::new(x)))), Flow::Continue))
             }
             Ok((None, Flow::Continue)) => self.err(try_expr.source_range, "Expected something"),
             Ok((x, flow)) => Ok((x, flow)),
-            Err(err) => {
-                self.call_stack.truncate(cs);
-                self.stack.truncate(st);
-                self.local_stack.truncate(lc);
-                self.current_stack.truncate(cu);
-                Ok((
-                    Some(Variable::Result(Err(Box::new(Error {
-                        message: Variable::Str(Arc::new(err)),
-                        trace: vec![],
-                    })))),
-                    Flow::Continue,
-                ))
-            }
+            Err(err) => Ok((Some(self.catch(point, err)?), Flow::Continue)),
         }
     }
 
@@ -686,7 +1087,10 @@ impl Runtime {
         // Create closure.
         let relative = self.call_stack.last().map(|c| c.index).unwrap_or(0);
         // Evaluate `grab` expressions and generate new AST.
-        let new_expr = match grab::grab_expr(1, self, &closure.expr, Side::Right)? {
+        let start = self.grabbed.len();
+        let grabbed = grab::grab_expr(1, self, &closure.expr, Side::Right);
+        let captures = self.grabbed.split_off(start);
+        let new_expr = match grabbed? {
             (Grabbed::Expression(x), Flow::Continue) => x,
             (Grabbed::Variable(x), Flow::Return) => {
                 return Ok((x, Flow::Return));
@@ -708,6 +1112,7 @@ impl Runtime {
                 Box::new(ClosureEnvironment {
                     module: self.module.clone(),
                     relative,
+                    captures,
                 }),
             )),
             Flow::Continue,
@@ -724,6 +1129,7 @@ impl Runtime {
                 None => Err(Box::new(Error {
                     message: Variable::Str(Arc::new("Expected `some(_)`, found `none()`".into())),
                     trace: vec![],
+                    kind: None,
                 })),
             },
             Variable::Bool(true, None) => Err(Box::new(Error {
@@ -731,17 +1137,20 @@ impl Runtime {
                     "This does not make sense, perhaps an array is empty?".into(),
                 )),
                 trace: vec![],
+                kind: None,
             })),
             Variable::Bool(false, _) => Err(Box::new(Error {
                 message: Variable::Str(Arc::new(
                     "Must be `true` to have meaning, try add or remove `!`".into(),
                 )),
                 trace: vec![],
+                kind: None,
             })),
             Variable::Bool(true, ref sec) => match *sec {
                 None => Err(Box::new(Error {
                     message: Variable::Str(Arc::new("Expected `some(_)`, found `none()`".into())),
                     trace: vec![],
+                    kind: None,
                 })),
                 Some(_) => Ok(Box::new(Variable::Bool(true, sec.clone()))),
             },
@@ -750,6 +1159,7 @@ impl Runtime {
                     Err(Box::new(Error {
                         message: Variable::Str(Arc::new("Expected number, found `NaN`".into())),
                         trace: vec![],
+                        kind: None,
                     }))
                 } else if sec.is_none() {
                     Err(Box::new(Error {
@@ -757,6 +1167,7 @@ impl Runtime {
                             "This does not make sense, perhaps an array is empty?".into(),
                         )),
                         trace: vec![],
+                        kind: None,
                     }))
                 } else {
                     Ok(Box::new(Variable::F64(val, sec.clone())))
@@ -788,36 +1199,34 @@ impl Runtime {
             Err(mut err) => {
                 let call = self.call_stack.last().unwrap();
                 if call.stack_len == 0 {
-                    return Err(self.module.error(
+                    return Err(self.error_at(
                         expr.source_range(),
                         &format!(
                             "{}\nRequires `->` on function `{}`",
                             self.stack_trace(),
                             &call.fn_name
                         ),
-                        self,
                     ));
                 }
                 if let Variable::Return = self.stack[call.stack_len - 1] {
                 } else {
-                    return Err(self.module.error(
+                    return Err(self.error_at(
                         expr.source_range(),
                         &format!(
                             "{}\nRequires `->` on function `{}`",
                             self.stack_trace(),
                             &call.fn_name
                         ),
-                        self,
                     ));
                 }
                 let file = match call.file {
                     None => "".into(),
                     Some(ref f) => format!(" ({})", f),
                 };
-                err.trace.push(self.module.error(
+                err.trace.push(self.module.error_fnindex(
                     expr.source_range(),
                     &format!("In function `{}`{}", &call.fn_name, file),
-                    self,
+                    call.index,
                 ));
                 Ok((Some(Variable::Result(Err(err))), Flow::Return))
             }
@@ -825,9 +1234,11 @@ impl Runtime {
     }
 
     /// Run `main` function in a module.
-    pub fn run(&mut self, module: &Arc<Module>) -> Result<(), String> {
+    pub fn run(&mut self, module: &Arc<Module>) -> Result<(), RuntimeError> {
         use std::mem::replace;
 
+        self.reset_budget();
+        self.reset_cancel();
         let old_module = replace(&mut self.module, module.clone());
         let name: Arc<String> = MAIN.clone();
         let call = ast::Call {
@@ -845,10 +1256,11 @@ impl Runtime {
                 let f = &module.functions[f_index as usize];
                 if !f.args.is_empty() {
                     self.module = old_module;
-                    return Err(module.error(
-                        f.args[0].source_range,
-                        "`main` should not have arguments",
-                        self,
+                    let range = f.args[0].source_range;
+                    return Err(RuntimeError::new(
+                        ErrorKind::Custom,
+                        Some(range),
+                        module.error(range, "`main` should not have arguments", self),
                     ));
                 }
                 let loader = false;
@@ -865,21 +1277,28 @@ impl Runtime {
             }
             _ => {
                 self.module = old_module;
-                Err(module.error(
-                    call.info.source_range,
-                    "Could not find function `main`",
-                    self,
+                Err(RuntimeError::new(
+                    ErrorKind::NotFound {
+                        name: (*name).clone(),
+                    },
+                    None,
+                    "Could not find function `main`".into(),
                 ))
             }
         }
     }
 
     fn block(&mut self, block: &ast::Block) -> FlowResult {
+        self.step(block.source_range)?;
         let mut expect = None;
         let st = self.stack.len();
         let lc = self.local_stack.len();
         let cu = self.current_stack.len();
         for e in &block.expressions {
+            if self.debug_hook.is_some() {
+                self.debug_event(DebugEventKind::Statement, e.source_range());
+            }
+            self.step(e.source_range())?;
             expect = match self.expression(e, Side::Right)? {
                 (x, Flow::Continue) => x,
                 x => {
@@ -897,6 +1316,59 @@ impl Runtime {
         Ok((expect, Flow::Continue))
     }
 
+    /// Creates a runtime for another thread, with a stack of deep cloned values.
+    #[cfg(all(not(target_family = "wasm"), feature = "threading"))]
+    pub(crate) fn thread_runtime(&self, stack: Vec<Variable>) -> Runtime {
+        let last_call = self.call_stack.last().unwrap();
+        Runtime {
+            module: self.module.clone(),
+            stack,
+            local_stack: vec![],
+            current_stack: vec![],
+            // Add last call because of loaded functions
+            // use relative index to the function it is calling from.
+            call_stack: vec![Call {
+                fn_name: last_call.fn_name.clone(),
+                index: last_call.index,
+                file: last_call.file.clone(),
+                source: last_call.source.clone(),
+                range: None,
+                stack_len: 0,
+                local_len: 0,
+                current_len: 0,
+                tail: false,
+            }],
+            rng: self.rng.clone(),
+            seed: self.seed,
+            arg_err_index: Cell::new(None),
+            intrinsic_range: None,
+            interrupt: self.interrupt.clone(),
+            display: None,
+            gc: None,
+            grabbed: vec![],
+            flags: self.flags.clone(),
+            vfs: self.vfs.clone(),
+            debug_hook: None,
+            telemetry_hook: None,
+            profiler: None,
+            scheduler: None,
+            timers: Timers::default(),
+            metrics: self.metrics.clone(),
+            settings: self.settings,
+            budget: self.settings.instruction_budget,
+            #[cfg(not(target_family = "wasm"))]
+            deadline: self
+                .settings
+                .time_budget
+                .and_then(|d| std::time::Instant::now().checked_add(d)),
+            #[cfg(all(not(target_family = "wasm"), feature = "threading"))]
+            fuel: None,
+            farr_index: None,
+            tail_position: false,
+            tail_call: None,
+        }
+    }
+
     /// Start a new thread and return the handle.
     #[cfg(all(not(target_family = "wasm"), feature = "threading"))]
     pub fn go(&mut self, go: &ast::Go) -> FlowResult {
@@ -936,26 +1408,8 @@ impl Runtime {
         }
         stack.reverse();
 
-        let last_call = self.call_stack.last().unwrap();
-        let new_rt = Runtime {
-            module: self.module.clone(),
-            stack,
-            local_stack: vec![],
-            current_stack: vec![],
-            // Add last call because of loaded functions
-            // use relative index to the function it is calling from.
-            call_stack: vec![Call {
-                fn_name: last_call.fn_name.clone(),
-                index: last_call.index,
-                file: last_call.file.clone(),
-                stack_len: 0,
-                local_len: 0,
-                current_len: 0,
-            }],
-            rng: self.rng.clone(),
-            arg_err_index: Cell::new(None),
-        };
-        let handle: JoinHandle<Result<Variable, String>> = thread::spawn(move || {
+        let new_rt = self.thread_runtime(stack);
+        let handle: JoinHandle<Result<Variable, RuntimeError>> = thread::spawn(move || {
             let mut new_rt = new_rt;
             let fake_call = fake_call;
             let loader = false;
@@ -988,11 +1442,11 @@ impl Runtime {
 
         let (f, env) = match self.resolve(&item) {
             &Variable::Closure(ref f, ref env) => (f.clone(), env.clone()),
-            x => return self.err(call.source_range, &self.expected(x, "closure")),
+            x => return self.err_from(call.source_range, self.expected(x, "closure")),
         };
 
         if call.arg_len() != f.args.len() {
-            return Err(self.module.error(
+            return Err(self.error_at(
                 call.source_range,
                 &format!(
                     "{}\nExpected {} arguments but found {}",
@@ -1000,7 +1454,6 @@ impl Runtime {
                     f.args.len(),
                     call.arg_len()
                 ),
-                self,
             ));
         }
         // Arguments must be computed.
@@ -1044,15 +1497,11 @@ impl Runtime {
                         .push((current.name.clone(), self.stack.len()));
                     self.stack.push(Variable::Ref(ind));
                 } else {
-                    return Err(self.module.error(
+                    return self.not_found_err(
                         call.source_range,
-                        &format!(
-                            "{}\nCould not find current variable `{}`",
-                            self.stack_trace(),
-                            current.name
-                        ),
-                        self,
-                    ));
+                        &current.name,
+                        &format!("Could not find current variable `{}`", current.name),
+                    );
                 }
             }
         }
@@ -1060,11 +1509,10 @@ impl Runtime {
         self.push_fn(
             call.item.name.clone(),
             env.relative,
-            Some(f.file.clone()),
-            st,
-            lc,
-            cu,
-        );
+            (Some(f.file.clone()), f.source.clone()),
+            Some(call.source_range),
+            (st, lc, cu),
+        )?;
         if f.returns() {
             // Use return type because it has the same name.
             self.local_stack.push((RETURN_TYPE.clone(), st - 1));
@@ -1073,6 +1521,9 @@ impl Runtime {
             // Do not resolve locals to keep fixed length from end of stack.
             self.local_stack.push((arg.name.clone(), st + i));
         }
+        if self.debug_hook.is_some() {
+            self.debug_event(DebugEventKind::Call, call.source_range);
+        }
         let (x, flow) = self.expression_module(&f.expr, Side::Right, &env.module)?;
         match flow {
             Flow::Break(None) => return self.err(call.source_range, "Can not break from function"),
@@ -1080,25 +1531,23 @@ impl Runtime {
                 return self.err(call.source_range, "Can not continue from function")
             }
             Flow::Break(Some(ref label)) => {
-                return Err(self.module.error(
+                return Err(self.error_at(
                     call.source_range,
                     &format!(
                         "{}\nThere is no loop labeled `{}`",
                         self.stack_trace(),
                         label
                     ),
-                    self,
                 ))
             }
             Flow::ContinueLoop(Some(ref label)) => {
-                return Err(self.module.error(
+                return Err(self.error_at(
                     call.source_range,
                     &format!(
                         "{}\nThere is no loop labeled `{}`",
                         self.stack_trace(),
                         label
                     ),
-                    self,
                 ))
             }
             _ => {}
@@ -1107,14 +1556,13 @@ impl Runtime {
         match (f.returns(), x) {
             (true, None) => {
                 match self.stack.pop().expect(TINVOTS) {
-                    Variable::Return => Err(self.module.error(
+                    Variable::Return => Err(self.error_at(
                         call.source_range,
                         &format!(
                             "{}\nFunction `{}` did not return a value",
                             self.stack_trace(),
                             call.item.name
                         ),
-                        self,
                     )),
                     x => {
                         // This happens when return is only
@@ -1123,19 +1571,18 @@ impl Runtime {
                     }
                 }
             }
-            (false, Some(_)) => Err(self.module.error(
+            (false, Some(_)) => Err(self.error_at(
                 call.source_range,
                 &format!(
                     "{}\nFunction `{}` should not return a value",
                     self.stack_trace(),
                     call.item.name
                 ),
-                self,
             )),
             (true, Some(Variable::Return)) => {
                 // TODO: Could return the last value on the stack.
                 //       Requires .pop_fn delayed after.
-                Err(self.module.error(
+                Err(self.error_at(
                     call.source_range,
                     &format!(
                         "{}\nFunction `{}` did not return a value. \
@@ -1143,7 +1590,6 @@ impl Runtime {
                         self.stack_trace(),
                         call.item.name
                     ),
-                    self,
                 ))
             }
             (returns, b) => {
@@ -1155,6 +1601,119 @@ impl Runtime {
         }
     }
 
+    /// Calls a closure with arguments and returns the value.
+    ///
+    /// Used by external functions taking a closure as argument.
+    pub(crate) fn call_closure_ret(
+        &mut self,
+        closure: &Variable,
+        args: &[Variable],
+    ) -> Result<Variable, RuntimeError> {
+        if let &Variable::Closure(ref f, _) = self.resolve(closure) {
+            if !f.returns() {
+                return Err(
+                    format!("{}\nExpected closure to return a value", self.stack_trace()).into(),
+                );
+            }
+        }
+        match self.call_closure_opt(closure, args)? {
+            Some(x) => Ok(x),
+            None => Err(format!("{}\nClosure did not return a value", self.stack_trace()).into()),
+        }
+    }
+
+    /// Calls a closure with arguments, returning the value if any.
+    ///
+    /// Used by external functions calling a closure for its side effects.
+    pub(crate) fn call_closure_opt(
+        &mut self,
+        closure: &Variable,
+        args: &[Variable],
+    ) -> Result<Option<Variable>, RuntimeError> {
+        let (f, env) = match self.resolve(closure) {
+            &Variable::Closure(ref f, ref env) => (f.clone(), env.clone()),
+            x => return Err(self.expected(x, "closure")),
+        };
+        if args.len() != f.args.len() {
+            return Err(format!(
+                "{}\nExpected {} arguments but found {}",
+                self.stack_trace(),
+                f.args.len(),
+                args.len()
+            )
+            .into());
+        }
+        let name: Arc<String> = Arc::new("closure".into());
+        self.stack.push(Variable::Return);
+        let st = self.stack.len();
+        let lc = self.local_stack.len();
+        let cu = self.current_stack.len();
+        self.stack.extend(args.iter().cloned());
+        for current in &f.currents {
+            let ind = self
+                .current_stack
+                .iter()
+                .rev()
+                .find(|&&(ref cname, _)| cname == &current.name)
+                .map(|&(_, ind)| ind);
+            match ind {
+                Some(ind) => {
+                    self.local_stack
+                        .push((current.name.clone(), self.stack.len()));
+                    self.stack.push(Variable::Ref(ind));
+                }
+                None => {
+                    self.stack.truncate(st - 1);
+                    return Err(RuntimeError::new(
+                        ErrorKind::NotFound {
+                            name: (*current.name).clone(),
+                        },
+                        None,
+                        format!(
+                            "{}\nCould not find current variable `{}`",
+                            self.stack_trace(),
+                            current.name
+                        ),
+                    ));
+                }
+            }
+        }
+        // A closure called by an intrinsic is called from where the intrinsic is called.
+        let range = match self.intrinsic_range {
+            Some((n, range)) if n == self.call_stack.len() => Some(range),
+            _ => None,
+        };
+        self.push_fn(
+            name.clone(),
+            env.relative,
+            (Some(f.file.clone()), f.source.clone()),
+            range,
+            (st, lc, cu),
+        )?;
+        self.local_stack.push((RETURN_TYPE.clone(), st - 1));
+        for (i, arg) in f.args.iter().enumerate() {
+            self.local_stack.push((arg.name.clone(), st + i));
+        }
+        let (x, flow) = self.expression_module(&f.expr, Side::Right, &env.module)?;
+        match flow {
+            Flow::Break(_) => return Err("Can not break from function".into()),
+            Flow::ContinueLoop(_) => return Err("Can not continue from function".into()),
+            _ => {}
+        }
+        // Resolve before references to arguments become invalid.
+        let x = x.map(|x| self.resolve(&x).clone());
+        self.pop_fn(name);
+        let ret = self.stack.pop().expect(TINVOTS);
+        match x {
+            Some(Variable::Return) => Ok(None),
+            Some(x) => Ok(Some(x)),
+            None => match ret {
+                Variable::Return => Ok(None),
+                x => Ok(Some(x)),
+            },
+        }
+    }
+
     /// Called from the outside, e.g. a loader script by `call` or `call_ret` intrinsic.
     pub fn call(&mut self, call: &ast::Call, module: &Arc<Module>) -> FlowResult {
         use std::mem::replace;
@@ -1185,15 +1744,16 @@ impl Runtime {
                 }
             };
         }
-        (fun.0)(self).map_err(|err| {
-            let range = if let Some(ind) = self.arg_err_index.get() {
-                self.arg_err_index.set(None);
-                args[ind].source_range()
-            } else {
-                info.source_range
-            };
-            self.module.error(range, &err, self)
-        })?;
+        self.call_intrinsic(&info.name, info.source_range, fun.0)
+            .map_err(|err| {
+                let range = if let Some(ind) = self.arg_err_index.get() {
+                    self.arg_err_index.set(None);
+                    args[ind].source_range()
+                } else {
+                    info.source_range
+                };
+                self.intrinsic_err(range, err)
+            })?;
         Ok((None, Flow::Continue))
     }
 
@@ -1219,15 +1779,18 @@ impl Runtime {
             };
         }
         Ok((
-            Some((fun.0)(self).map_err(|err| {
-                let range = if let Some(ind) = self.arg_err_index.get() {
-                    self.arg_err_index.set(None);
-                    args[ind].source_range()
-                } else {
-                    info.source_range
-                };
-                self.module.error(range, &err, self)
-            })?),
+            Some(
+                self.call_intrinsic(&info.name, info.source_range, fun.0)
+                    .map_err(|err| {
+                        let range = if let Some(ind) = self.arg_err_index.get() {
+                            self.arg_err_index.set(None);
+                            args[ind].source_range()
+                        } else {
+                            info.source_range
+                        };
+                        self.intrinsic_err(range, err)
+                    })?,
+            ),
             Flow::Continue,
         ))
     }
@@ -1281,7 +1844,8 @@ impl Runtime {
                 } else {
                     info.source_range
                 };
-                self.module.error(range, &err, self)
+                let found = format!("({}, {})", left.typeof_var(), right.typeof_var());
+                self.intrinsic_err(range, operator_err(err, found))
             })?),
             Flow::Continue,
         ))
@@ -1319,7 +1883,7 @@ impl Runtime {
                 } else {
                     info.source_range
                 };
-                self.module.error(range, &err, self)
+                self.intrinsic_err(range, operator_err(err, (*r.typeof_var()).clone()))
             })?),
             Flow::Continue,
         ))
@@ -1379,15 +1943,18 @@ impl Runtime {
             };
         }
         Ok((
-            Some((fun.0)(self).map_err(|err| {
-                let range = if let Some(ind) = self.arg_err_index.get() {
-                    self.arg_err_index.set(None);
-                    args[ind].source_range()
-                } else {
-                    info.source_range
-                };
-                self.module.error(range, &err, self)
-            })?),
+            Some(
+                self.call_intrinsic(&info.name, info.source_range, fun.0)
+                    .map_err(|err| {
+                        let range = if let Some(ind) = self.arg_err_index.get() {
+                            self.arg_err_index.set(None);
+                            args[ind].source_range()
+                        } else {
+                            info.source_range
+                        };
+                        self.intrinsic_err(range, err)
+                    })?,
+            ),
             Flow::Continue,
         ))
     }
@@ -1400,8 +1967,10 @@ impl Runtime {
         custom_source: &Option<Arc<String>>,
         loader: bool,
     ) -> FlowResult {
+        use std::mem::replace;
         use std::sync::atomic::Ordering;
 
+        let tail = replace(&mut self.tail_position, false);
         let relative = if loader {
             0
         } else {
@@ -1484,94 +2053,147 @@ impl Runtime {
                         .push((current.name.clone(), self.stack.len()));
                     self.stack.push(Variable::Ref(ind));
                 } else {
-                    return Err(self.module.error(
+                    return self.not_found_err(
                         info.source_range,
-                        &format!(
-                            "{}\nCould not find current variable `{}`",
-                            self.stack_trace(),
-                            current.name
-                        ),
-                        self,
-                    ));
+                        &current.name,
+                        &format!("Could not find current variable `{}`", current.name),
+                    );
                 }
             }
         }
 
         // Send arguments to senders.
         if f.senders.0.load(Ordering::Relaxed) {
-            let n = self.stack.len();
-            let mut msg = Vec::with_capacity(n - st);
-            for i in st..n {
-                msg.push(self.stack[i].deep_clone(&self.stack));
-            }
-            let msg = Arc::new(msg);
-            // Uses smart swapping of channels to put the closed ones at the end.
-            let mut channels = f.senders.1.lock().unwrap();
-            let mut open = channels.len();
-            for i in (0..channels.len()).rev() {
-                match channels[i].send(Variable::Array(msg.clone())) {
-                    Ok(_) => {}
-                    Err(_) => {
-                        open -= 1;
-                        channels.swap(i, open);
-                    }
-                }
+            let msg = self.stack[st..]
+                .iter()
+                .map(|v| v.deep_clone(&self.stack))
+                .collect();
+            send_args(f, msg);
+        }
+
+        if tail && f.returns() && f.currents.is_empty() {
+            let frame = self.call_stack.last().unwrap();
+            if mod_copy.functions[frame.index].returns() && frame.current_len == cu {
+                // Let the caller reuse its frame for this call.
+                let frame_st = frame.stack_len;
+                let args = self
+                    .stack
+                    .split_off(st)
+                    .into_iter()
+                    .map(|v| self.tail_arg(v, frame_st))
+                    .collect();
+                self.stack.pop();
+                self.tail_call = Some(TailCall {
+                    index: new_index,
+                    name: info.name.clone(),
+                    args,
+                });
+                return Ok((None, Flow::Return));
             }
-            channels.truncate(open);
-            if channels.len() == 0 {
-                // Change of flag is guarded by the mutex.
-                f.senders.0.store(false, Ordering::Relaxed);
+        }
+
+        // Run compiled function when the arguments have the expected types.
+        // The debug hook needs the interpreter to report statements.
+        let compiled = if self.debug_hook.is_none() {
+            mod_copy.compiled.get(new_index).and_then(|c| c.as_ref())
+        } else {
+            None
+        };
+        if let Some(chunk) = compiled {
+            if let Some(locals) = chunk.frame(self, st) {
+                self.push_fn(
+                    info.name.clone(),
+                    new_index,
+                    (Some(f.file.clone()), f.source.clone()),
+                    Some(info.source_range),
+                    (st, lc, cu),
+                )?;
+                let v = bytecode::run(self, &mod_copy, chunk, locals)?;
+                self.pop_fn(info.name.clone());
+                return Ok(match chunk.ret {
+                    Some(ty) => {
+                        self.stack.pop();
+                        (Some(ty.variable(v)), Flow::Continue)
+                    }
+                    None => (None, Flow::Continue),
+                });
             }
-            drop(channels);
         }
 
         self.push_fn(
             info.name.clone(),
             new_index,
-            Some(f.file.clone()),
-            st,
-            lc,
-            cu,
-        );
-        if f.returns() {
-            // Use return type because it has same name.
-            self.local_stack.push((RETURN_TYPE.clone(), st - 1));
-        }
-        for (i, arg) in f.args.iter().enumerate() {
-            // Do not resolve locals to keep fixed length from end of stack.
-            self.local_stack.push((arg.name.clone(), st + i));
-        }
-        let (x, flow) = self.block(&f.block)?;
+            (Some(f.file.clone()), f.source.clone()),
+            Some(info.source_range),
+            (st, lc, cu),
+        )?;
+        self.call_stack.last_mut().unwrap().tail = true;
+        let mut f = f;
+        let mut name = info.name.clone();
+        let (x, flow) = loop {
+            if f.returns() {
+                // Use return type because it has same name.
+                self.local_stack.push((RETURN_TYPE.clone(), st - 1));
+            }
+            for (i, arg) in f.args.iter().enumerate() {
+                // Do not resolve locals to keep fixed length from end of stack.
+                self.local_stack.push((arg.name.clone(), st + i));
+            }
+            if self.debug_hook.is_some() {
+                self.debug_event(DebugEventKind::Call, info.source_range);
+            }
+            let res = self.block(&f.block)?;
+            let tail_call = match self.tail_call.take() {
+                None => break res,
+                Some(x) => x,
+            };
+            // Run the call in tail position in this frame,
+            // to use constant stack space for recursion.
+            f = &mod_copy.functions[tail_call.index];
+            name = tail_call.name;
+            self.stack.truncate(st);
+            self.local_stack.truncate(lc);
+            self.current_stack.truncate(cu);
+            self.stack[st - 1] = Variable::Return;
+            self.stack.extend(tail_call.args);
+            if let Some(ref mut p) = self.profiler {
+                p.exit();
+                p.enter(&name);
+            }
+            let call = self.call_stack.last_mut().unwrap();
+            call.fn_name = name.clone();
+            call.index = tail_call.index;
+            call.file = Some(f.file.clone());
+            call.source = f.source.clone();
+        };
         match flow {
             Flow::Break(None) => return self.err(info.source_range, "Can not break from function"),
             Flow::ContinueLoop(None) => {
                 return self.err(info.source_range, "Can not continue from function")
             }
             Flow::Break(Some(ref label)) => {
-                return Err(self.module.error(
+                return Err(self.error_at(
                     info.source_range,
                     &format!(
                         "{}\nThere is no loop labeled `{}`",
                         self.stack_trace(),
                         label
                     ),
-                    self,
                 ))
             }
             Flow::ContinueLoop(Some(ref label)) => {
-                return Err(self.module.error(
+                return Err(self.error_at(
                     info.source_range,
                     &format!(
                         "{}\nThere is no loop labeled `{}`",
                         self.stack_trace(),
                         label
                     ),
-                    self,
                 ))
             }
             _ => {}
         }
-        self.pop_fn(info.name.clone());
+        self.pop_fn(name);
         match (f.returns(), x) {
             (true, None) => {
                 match self.stack.pop().expect(TINVOTS) {
@@ -1579,14 +2201,18 @@ impl Runtime {
                         let source = custom_source.as_ref().unwrap_or(
                             &self.module.functions[self.call_stack.last().unwrap().index].source,
                         );
-                        Err(self.module.error_source(
-                            info.source_range,
-                            &format!(
-                                "{}\nFunction `{}` did not return a value",
-                                self.stack_trace(),
-                                f.name
+                        Err(RuntimeError::new(
+                            ErrorKind::Custom,
+                            Some(info.source_range),
+                            self.module.error_source(
+                                info.source_range,
+                                &format!(
+                                    "{}\nFunction `{}` did not return a value",
+                                    self.stack_trace(),
+                                    f.name
+                                ),
+                                source,
                             ),
-                            source,
                         ))
                     }
                     x => {
@@ -1600,14 +2226,18 @@ impl Runtime {
                 let source = custom_source.as_ref().unwrap_or(
                     &self.module.functions[self.call_stack.last().unwrap().index].source,
                 );
-                Err(self.module.error_source(
-                    info.source_range,
-                    &format!(
-                        "{}\nFunction `{}` should not return a value",
-                        self.stack_trace(),
-                        f.name
+                Err(RuntimeError::new(
+                    ErrorKind::Custom,
+                    Some(info.source_range),
+                    self.module.error_source(
+                        info.source_range,
+                        &format!(
+                            "{}\nFunction `{}` should not return a value",
+                            self.stack_trace(),
+                            f.name
+                        ),
+                        source,
                     ),
-                    source,
                 ))
             }
             (true, Some(Variable::Return)) => {
@@ -1616,15 +2246,19 @@ impl Runtime {
                 let source = custom_source.as_ref().unwrap_or(
                     &self.module.functions[self.call_stack.last().unwrap().index].source,
                 );
-                Err(self.module.error_source(
-                    info.source_range,
-                    &format!(
-                        "{}\nFunction `{}` did not return a value. \
-                    Did you forget a `return`?",
-                        self.stack_trace(),
-                        f.name
+                Err(RuntimeError::new(
+                    ErrorKind::Custom,
+                    Some(info.source_range),
+                    self.module.error_source(
+                        info.source_range,
+                        &format!(
+                            "{}\nFunction `{}` did not return a value. \
+                        Did you forget a `return`?",
+                            self.stack_trace(),
+                            f.name
+                        ),
+                        source,
                     ),
-                    source,
                 ))
             }
             (returns, b) => {
@@ -1651,14 +2285,13 @@ impl Runtime {
             FnIndex::Loaded(f_index) => {
                 self.call_loaded(&call.args, f_index, &call.info, &call.custom_source, loader)
             }
-            FnIndex::None => Err(self.module.error(
+            FnIndex::None => Err(self.error_at(
                 call.info.source_range,
                 &format!(
                     "{}\nUnknown function `{}`",
                     self.stack_trace(),
                     call.info.name
                 ),
-                self,
             )),
         }
     }
@@ -1669,7 +2302,9 @@ impl Runtime {
         function: &str,
         args: &[Variable],
         module: &Arc<Module>,
-    ) -> Result<(), String> {
+    ) -> Result<(), RuntimeError> {
+        self.reset_budget();
+        self.reset_cancel();
         let name: Arc<String> = Arc::new(function.into());
         match module.find_function(&name, 0) {
             FnIndex::Loaded(f_index) => {
@@ -1691,7 +2326,7 @@ impl Runtime {
                 self.call(&call, module)?;
                 Ok(())
             }
-            _ => Err(format!("Could not find function `{}`", function)),
+            _ => Err(not_found_fn(function)),
         }
     }
 
@@ -1701,11 +2336,13 @@ impl Runtime {
         function: &str,
         args: &[Variable],
         module: &Arc<Module>,
-    ) -> Result<Variable, String> {
+    ) -> Result<Variable, RuntimeError> {
+        self.reset_budget();
+        self.reset_cancel();
         let name: Arc<String> = Arc::new(function.into());
         let fn_index = module.find_function(&name, 0);
         if let FnIndex::None = fn_index {
-            return Err(format!("Could not find function `{}`", function));
+            return Err(not_found_fn(function));
         }
 
         let call = ast::Call {
@@ -1724,35 +2361,32 @@ impl Runtime {
         match self.call(&call, module) {
             Ok((Some(val), Flow::Continue)) => Ok(val),
             Err(err) => Err(err),
-            _ => Err(module.error(
-                call.info.source_range,
-                &format!("{}\nExpected something", self.stack_trace()),
-                self,
-            )),
+            _ => Err(module
+                .error(
+                    call.info.source_range,
+                    &format!("{}\nExpected something", self.stack_trace()),
+                    self,
+                )
+                .into()),
         }
     }
 
-    fn swizzle(&mut self, sw: &ast::Swizzle) -> Result<Flow, String> {
+    fn swizzle(&mut self, sw: &ast::Swizzle) -> Result<Flow, RuntimeError> {
         let v = match self.expression(&sw.expr, Side::Right)? {
             (Some(x), Flow::Continue) => x,
             (_, Flow::Return) => {
                 return Ok(Flow::Return);
             }
             _ => {
-                return Err(self.module.error(
+                return Err(self.error_at(
                     sw.expr.source_range(),
                     &format!("{}\nExpected something", self.stack_trace()),
-                    self,
                 ))
             }
         };
         let v = match self.resolve(&v) {
             &Variable::Vec4(v) => v,
-            x => {
-                return Err(self
-                    .module
-                    .error(sw.source_range, &self.expected(x, "vec4"), self))
-            }
+            x => return Err(self.intrinsic_err(sw.source_range, self.expected(x, "vec4"))),
         };
         self.stack.push(Variable::f64(f64::from(v[sw.sw0])));
         self.stack.push(Variable::f64(f64::from(v[sw.sw1])));
@@ -1770,7 +2404,7 @@ impl Runtime {
 
         Ok((
             Some(if link.items.is_empty() {
-                Variable::Link(Box::new(Link::new()))
+                Variable::Link(Box::default())
             } else {
                 let st = self.stack.len();
                 let lc = self.local_stack.len();
@@ -1784,15 +2418,11 @@ impl Runtime {
                             return Ok((res, flow));
                         }
                     };
-                    match new_link.push(self.resolve(&v)) {
-                        Err(err) => {
-                            return Err(self.module.error(
-                                item.source_range(),
-                                &format!("{}\n{}", self.stack_trace(), err),
-                                self,
-                            ))
-                        }
-                        Ok(()) => {}
+                    if let Err(err) = new_link.push(self.resolve(&v)) {
+                        return Err(self.error_at(
+                            item.source_range(),
+                            &format!("{}\n{}", self.stack_trace(), err),
+                        ));
                     }
                 }
                 self.stack.truncate(st);
@@ -1817,10 +2447,9 @@ impl Runtime {
             match object.insert(key.clone(), x) {
                 None => {}
                 Some(_) => {
-                    return Err(self.module.error(
+                    return Err(self.error_at(
                         expr.source_range(),
                         &format!("{}\nDuplicate key in object `{}`", self.stack_trace(), key),
-                        self,
                     ))
                 }
             }
@@ -1852,10 +2481,17 @@ impl Runtime {
             _ => return self.err(array_fill.n.source_range(), "Expected something"),
         };
         let v = match (self.resolve(&fill), self.resolve(&n)) {
-            (x, &Variable::F64(n, _)) => Variable::Array(Arc::new(vec![x.clone(); n as usize])),
-            _ => {
-                return self.err(
+            (x, &Variable::F64(n, _)) => {
+                if let Err(err) = self.check_array_len(n as usize) {
+                    return self.err_from(array_fill.source_range, err);
+                }
+                Variable::Array(Arc::new(vec![x.clone(); n as usize]))
+            }
+            (_, n) => {
+                return self.type_err(
                     array_fill.n.source_range(),
+                    "number",
+                    n,
                     "Expected number for length in `[value; length]`",
                 )
             }
@@ -1905,6 +2541,9 @@ impl Runtime {
                 Variable::Ref(ind) => UnsafeRef(&mut self.stack[ind] as *mut Variable),
                 x => panic!("Expected reference, found `{}`", x.typeof_var()),
             };
+            if let Some(i) = self.farr_index.take() {
+                return self.assign_farr(op, r, i, &b, left.source_range());
+            }
 
             match *self.resolve(&b) {
                 Variable::F64(b, ref sec) => unsafe {
@@ -1923,7 +2562,7 @@ impl Runtime {
                             *n_sec = sec.clone()
                         }
                         Variable::Vec4(ref mut n) => {
-                            let b = b as f32;
+                            let b = b as Scalar;
                             match op {
                                 Add => *n = [n[0] + b, n[1] + b, n[2] + b, n[3] + b],
                                 Sub => *n = [n[0] - b, n[1] - b, n[2] - b, n[3] - b],
@@ -1934,8 +2573,12 @@ impl Runtime {
                                     *n = [n[0].powf(b), n[1].powf(b), n[2].powf(b), n[3].powf(b)]
                                 }
                                 _ => {
-                                    return self
-                                        .err(left.source_range(), "Expected assigning to a number")
+                                    return self.type_err(
+                                        left.source_range(),
+                                        "number",
+                                        &*r.0,
+                                        "Expected assigning to a number",
+                                    )
                                 }
                             }
                         }
@@ -1958,7 +2601,12 @@ impl Runtime {
                             }
                         }
                         _ => {
-                            return self.err(left.source_range(), "Expected assigning to a number")
+                            return self.type_err(
+                                left.source_range(),
+                                "number",
+                                &*r.0,
+                                "Expected assigning to a number",
+                            )
                         }
                     };
                 },
@@ -1988,7 +2636,14 @@ impl Runtime {
                                 return self.err(left.source_range(), "Return has no value");
                             }
                         }
-                        _ => return self.err(left.source_range(), "Expected assigning to a vec4"),
+                        _ => {
+                            return self.type_err(
+                                left.source_range(),
+                                "vec4",
+                                &*r.0,
+                                "Expected assigning to a vec4",
+                            )
+                        }
                     };
                 },
                 Variable::Mat4(ref b) => unsafe {
@@ -2027,7 +2682,14 @@ impl Runtime {
                                 return self.err(left.source_range(), "Return has no value");
                             }
                         }
-                        _ => return self.err(left.source_range(), "Expected assigning to a mat4"),
+                        _ => {
+                            return self.type_err(
+                                left.source_range(),
+                                "mat4",
+                                &*r.0,
+                                "Expected assigning to a mat4",
+                            )
+                        }
                     }
                 },
                 Variable::Bool(b, ref sec) => unsafe {
@@ -2057,7 +2719,14 @@ impl Runtime {
                                 );
                             }
                         }
-                        _ => return self.err(left.source_range(), "Expected assigning to a bool"),
+                        _ => {
+                            return self.type_err(
+                                left.source_range(),
+                                "boolean",
+                                &*r.0,
+                                "Expected assigning to a bool",
+                            )
+                        }
                     };
                 },
                 Variable::Str(ref b) => unsafe {
@@ -2085,7 +2754,14 @@ impl Runtime {
                                 );
                             }
                         }
-                        _ => return self.err(left.source_range(), "Expected assigning to text"),
+                        _ => {
+                            return self.type_err(
+                                left.source_range(),
+                                "string",
+                                &*r.0,
+                                "Expected assigning to text",
+                            )
+                        }
                     }
                 },
                 Variable::Object(ref b) => unsafe {
@@ -2104,7 +2780,14 @@ impl Runtime {
                                 return self.err(left.source_range(), "Return has no value");
                             }
                         }
-                        _ => return self.err(left.source_range(), "Expected assigning to object"),
+                        _ => {
+                            return self.type_err(
+                                left.source_range(),
+                                "object",
+                                &*r.0,
+                                "Expected assigning to object",
+                            )
+                        }
                     }
                 },
                 Variable::Array(ref b) => unsafe {
@@ -2123,7 +2806,14 @@ impl Runtime {
                                 return self.err(left.source_range(), "Return has no value");
                             }
                         }
-                        _ => return self.err(left.source_range(), "Expected assigning to array"),
+                        _ => {
+                            return self.type_err(
+                                left.source_range(),
+                                "array",
+                                &*r.0,
+                                "Expected assigning to array",
+                            )
+                        }
                     }
                 },
                 Variable::Link(ref b) => unsafe {
@@ -2141,7 +2831,14 @@ impl Runtime {
                                 return self.err(left.source_range(), "Return has no value");
                             }
                         }
-                        _ => return self.err(left.source_range(), "Expected assigning to link"),
+                        _ => {
+                            return self.type_err(
+                                left.source_range(),
+                                "link",
+                                &*r.0,
+                                "Expected assigning to link",
+                            )
+                        }
                     }
                 },
                 Variable::Option(ref b) => unsafe {
@@ -2160,7 +2857,14 @@ impl Runtime {
                                 return self.err(left.source_range(), "Return has no value");
                             }
                         }
-                        _ => return self.err(left.source_range(), "Expected assigning to option"),
+                        _ => {
+                            return self.type_err(
+                                left.source_range(),
+                                "option",
+                                &*r.0,
+                                "Expected assigning to option",
+                            )
+                        }
                     }
                 },
                 Variable::Result(ref b) => unsafe {
@@ -2179,7 +2883,14 @@ impl Runtime {
                                 return self.err(left.source_range(), "Return has no value");
                             }
                         }
-                        _ => return self.err(left.source_range(), "Expected assigning to result"),
+                        _ => {
+                            return self.type_err(
+                                left.source_range(),
+                                "result",
+                                &*r.0,
+                                "Expected assigning to result",
+                            )
+                        }
                     }
                 },
                 Variable::RustObject(ref b) => unsafe {
@@ -2199,8 +2910,12 @@ impl Runtime {
                             }
                         }
                         _ => {
-                            return self
-                                .err(left.source_range(), "Expected assigning to rust_object")
+                            return self.type_err(
+                                left.source_range(),
+                                "rust_object",
+                                &*r.0,
+                                "Expected assigning to rust_object",
+                            )
                         }
                     }
                 },
@@ -2220,18 +2935,24 @@ impl Runtime {
                                 return self.err(left.source_range(), "Return has no value");
                             }
                         }
-                        _ => return self.err(left.source_range(), "Expected assigning to closure"),
+                        _ => {
+                            return self.type_err(
+                                left.source_range(),
+                                "closure",
+                                &*r.0,
+                                "Expected assigning to closure",
+                            )
+                        }
                     }
                 },
                 ref x => {
-                    return Err(self.module.error(
+                    return Err(self.error_at(
                         left.source_range(),
                         &format!(
                             "{}\nCan not use this assignment operator with `{}`",
                             self.stack_trace(),
                             x.typeof_var()
                         ),
-                        self,
                     ));
                 }
             };
@@ -2266,7 +2987,12 @@ impl Runtime {
                             }
                         };
                         match x {
-                            Variable::UnsafeRef(r) => unsafe { *r.0 = v },
+                            Variable::UnsafeRef(r) => {
+                                if let Some(i) = self.farr_index.take() {
+                                    return self.assign_farr(Set, r, i, &v, left.source_range());
+                                }
+                                unsafe { *r.0 = v }
+                            }
                             _ => panic!("Expected unsafe reference"),
                         }
                     } else {
@@ -2283,6 +3009,35 @@ impl Runtime {
             }
         }
     }
+    /// Assigns to the number at index `i` in `farr`.
+    fn assign_farr(
+        &mut self,
+        op: ast::AssignOp,
+        r: UnsafeRef,
+        i: usize,
+        b: &Variable,
+        range: Range,
+    ) -> FlowResult {
+        use ast::AssignOp::*;
+
+        let b = match *self.resolve(b) {
+            Variable::F64(b, _) => b,
+            ref x => return self.err_from(range, self.expected(x, "number")),
+        };
+        if let Variable::F64Array(ref mut arr) = *unsafe { &mut *r.0 } {
+            let n = &mut Arc::make_mut(arr)[i];
+            match op {
+                Set | Assign => *n = b,
+                Add => *n += b,
+                Sub => *n -= b,
+                Mul => *n *= b,
+                Div => *n /= b,
+                Rem => *n %= b,
+                Pow => *n = n.powf(b),
+            }
+        }
+        Ok((None, Flow::Continue))
+    }
     // `insert` is true for `:=` and false for `=`.
     // This works only on objects, but does not have to check since it is
     // ignored for arrays.
@@ -2291,7 +3046,7 @@ impl Runtime {
 
         #[inline(always)]
         fn try(
-            stack: &mut Vec<Variable>,
+            stack: &mut [Variable],
             call_stack: &[Call],
             v: Result<Box<Variable>, Box<Error>>,
             source_range: Range,
@@ -2302,26 +3057,34 @@ impl Runtime {
                 Err(mut err) => {
                     let call = call_stack.last().unwrap();
                     if call.stack_len == 0 {
-                        return Err(module.error_fnindex(
-                            source_range,
-                            &format!(
-                                "{}\nRequires `->` on function `{}`",
-                                stack_trace(call_stack),
-                                &call.fn_name
+                        return Err(RuntimeError::new(
+                            ErrorKind::Custom,
+                            Some(source_range),
+                            module.error_fnindex(
+                                source_range,
+                                &format!(
+                                    "{}\nRequires `->` on function `{}`",
+                                    stack_trace(call_stack),
+                                    &call.fn_name
+                                ),
+                                call.index,
                             ),
-                            call.index,
                         ));
                     }
                     if let Variable::Return = stack[call.stack_len - 1] {
                     } else {
-                        return Err(module.error_fnindex(
-                            source_range,
-                            &format!(
-                                "{}\nRequires `->` on function `{}`",
-                                stack_trace(call_stack),
-                                &call.fn_name
+                        return Err(RuntimeError::new(
+                            ErrorKind::Custom,
+                            Some(source_range),
+                            module.error_fnindex(
+                                source_range,
+                                &format!(
+                                    "{}\nRequires `->` on function `{}`",
+                                    stack_trace(call_stack),
+                                    &call.fn_name
+                                ),
+                                call.index,
                             ),
-                            call.index,
                         ));
                     }
                     let file = match call.file {
@@ -2348,7 +3111,7 @@ impl Runtime {
                 match item.stack_id.get() {
                     Some(val) => self.stack.len() - val,
                     None => {
-                        let name: &str = &**item.name;
+                        let name: &str = &item.name;
                         let mut found = false;
                         for &(ref n, id) in self.local_stack.iter().rev().take(locals) {
                             if **n == name {
@@ -2357,13 +3120,12 @@ impl Runtime {
 
                                 let static_stack_id = item.static_stack_id.get();
                                 if new_val != static_stack_id {
-                                    return Err(self.module.error(item.source_range,
-                                        &format!(
+                                    return Err(self.error_at(item.source_range, &format!(
                                             "DEBUG: resolved not same for {} `{:?}` vs static `{:?}`",
                                             name,
                                             new_val,
                                             static_stack_id
-                                        ), self));
+                                        )));
                                 }
 
                                 found = true;
@@ -2373,24 +3135,22 @@ impl Runtime {
                         if found {
                             self.stack.len() - item.stack_id.get().unwrap()
                         } else if name == "return" {
-                            return Err(self.module.error(
+                            return Err(self.error_at(
                                 item.source_range,
                                 &format!(
                                     "{}\nRequires `->` on function `{}`",
                                     self.stack_trace(),
                                     &self.call_stack.last().unwrap().fn_name
                                 ),
-                                self,
                             ));
                         } else {
-                            return Err(self.module.error(
+                            return Err(self.error_at(
                                 item.source_range,
                                 &format!(
                                     "{}\nCould not find local or current variable `{}`",
                                     self.stack_trace(),
                                     name
                                 ),
-                                self,
                             ));
                         }
                     }
@@ -2402,13 +3162,12 @@ impl Runtime {
             for &(ref n, id) in self.local_stack.iter().rev().take(locals) {
                 if **n == **item.name {
                     if stack_id != id {
-                        return Err(self.module.error(
+                        return Err(self.error_at(
                             item.source_range,
                             &format!(
                                 "DEBUG: Not same for {} stack_id `{:?}` vs id `{:?}`",
                                 item.name, stack_id, id
                             ),
-                            self,
                         ));
                     }
                     break;
@@ -2459,6 +3218,7 @@ impl Runtime {
         let &mut Runtime {
             ref mut stack,
             ref mut call_stack,
+            ref mut farr_index,
             ..
         } = self;
         let mut expr_j = 0;
@@ -2469,18 +3229,34 @@ impl Runtime {
 
         let v = {
             let item_len = item.ids.len();
+            // The index of the last number, when looking up a number in `farr`.
+            let mut farr = None;
             // Get the first variable (a.x).y
-            let mut var: *mut Variable = item_lookup(
-                &self.module,
-                &mut stack[stack_id],
-                stack,
-                call_stack,
-                &item.ids[0],
-                start_stack_len,
-                &mut expr_j,
-                insert,
-                item_len == 1,
-            )?;
+            let mut var: *mut Variable = match stack[stack_id] {
+                Variable::F64Array(ref arr) if item_len == 1 => {
+                    farr = Some(farr_lookup(
+                        &self.module,
+                        arr.len(),
+                        stack,
+                        call_stack,
+                        &item.ids[0],
+                        start_stack_len,
+                        &mut expr_j,
+                    )?);
+                    &mut stack[stack_id]
+                }
+                _ => item_lookup(
+                    &self.module,
+                    &mut stack[stack_id],
+                    stack,
+                    call_stack,
+                    &item.ids[0],
+                    start_stack_len,
+                    &mut expr_j,
+                    insert,
+                    item_len == 1,
+                )?,
+            };
             let mut try_id_ind = 0;
             if !item.try_ids.is_empty() && item.try_ids[try_id_ind] == 0 {
                 // Check for error on `?` for first id.
@@ -2488,13 +3264,17 @@ impl Runtime {
                     match Runtime::try_msg(&*var) {
                         Some(v) => v,
                         None => {
-                            return Err(self.module.error_fnindex(
-                                item.ids[0].source_range(),
-                                &format!(
-                                    "{}\nExpected `ok(_)` or `err(_)`",
-                                    stack_trace(call_stack)
+                            return Err(RuntimeError::new(
+                                ErrorKind::Custom,
+                                Some(item.ids[0].source_range()),
+                                self.module.error_fnindex(
+                                    item.ids[0].source_range(),
+                                    &format!(
+                                        "{}\nExpected `ok(_)` or `err(_)`",
+                                        stack_trace(call_stack)
+                                    ),
+                                    call_stack.last().unwrap().index,
                                 ),
-                                call_stack.last().unwrap().index,
                             ));
                         }
                     }
@@ -2507,26 +3287,34 @@ impl Runtime {
                     Err(ref err) => {
                         let call = call_stack.last().unwrap();
                         if call.stack_len == 0 {
-                            return Err(self.module.error_fnindex(
-                                item.ids[0].source_range(),
-                                &format!(
-                                    "{}\nRequires `->` on function `{}`",
-                                    stack_trace(call_stack),
-                                    &call.fn_name
+                            return Err(RuntimeError::new(
+                                ErrorKind::Custom,
+                                Some(item.ids[0].source_range()),
+                                self.module.error_fnindex(
+                                    item.ids[0].source_range(),
+                                    &format!(
+                                        "{}\nRequires `->` on function `{}`",
+                                        stack_trace(call_stack),
+                                        &call.fn_name
+                                    ),
+                                    call.index,
                                 ),
-                                call.index,
                             ));
                         }
                         if let Variable::Return = stack[call.stack_len - 1] {
                         } else {
-                            return Err(self.module.error_fnindex(
-                                item.ids[0].source_range(),
-                                &format!(
-                                    "{}\nRequires `->` on function `{}`",
-                                    stack_trace(call_stack),
-                                    &call.fn_name
+                            return Err(RuntimeError::new(
+                                ErrorKind::Custom,
+                                Some(item.ids[0].source_range()),
+                                self.module.error_fnindex(
+                                    item.ids[0].source_range(),
+                                    &format!(
+                                        "{}\nRequires `->` on function `{}`",
+                                        stack_trace(call_stack),
+                                        &call.fn_name
+                                    ),
+                                    call.index,
                                 ),
-                                call.index,
                             ));
                         }
                         let mut err = err.clone();
@@ -2545,6 +3333,23 @@ impl Runtime {
             }
             // Get the rest of the variables.
             for (i, prop) in item.ids[1..].iter().enumerate() {
+                // `i` skips first index.
+                let last = i + 2 == item_len;
+                match *unsafe { &*var } {
+                    Variable::F64Array(ref arr) if last => {
+                        farr = Some(farr_lookup(
+                            &self.module,
+                            arr.len(),
+                            stack,
+                            call_stack,
+                            prop,
+                            start_stack_len,
+                            &mut expr_j,
+                        )?);
+                        break;
+                    }
+                    _ => {}
+                }
                 var = item_lookup(
                     &self.module,
                     unsafe { &mut *var },
@@ -2554,8 +3359,7 @@ impl Runtime {
                     start_stack_len,
                     &mut expr_j,
                     insert,
-                    // `i` skips first index.
-                    i + 2 == item_len,
+                    last,
                 )?;
 
                 if item.try_ids.len() > try_id_ind && item.try_ids[try_id_ind] == i + 1 {
@@ -2564,13 +3368,17 @@ impl Runtime {
                         match Runtime::try_msg(&*var) {
                             Some(v) => v,
                             None => {
-                                return Err(self.module.error_fnindex(
-                                    prop.source_range(),
-                                    &format!(
-                                        "{}\nExpected `ok(_)`, `err(_)`, `bool`, `f64`",
-                                        stack_trace(call_stack)
+                                return Err(RuntimeError::new(
+                                    ErrorKind::Custom,
+                                    Some(prop.source_range()),
+                                    self.module.error_fnindex(
+                                        prop.source_range(),
+                                        &format!(
+                                            "{}\nExpected `ok(_)`, `err(_)`, `bool`, `f64`",
+                                            stack_trace(call_stack)
+                                        ),
+                                        call_stack.last().unwrap().index,
                                     ),
-                                    call_stack.last().unwrap().index,
                                 ));
                             }
                         }
@@ -2583,26 +3391,34 @@ impl Runtime {
                         Err(ref err) => {
                             let call = call_stack.last().unwrap();
                             if call.stack_len == 0 {
-                                return Err(self.module.error_fnindex(
-                                    prop.source_range(),
-                                    &format!(
-                                        "{}\nRequires `->` on function `{}`",
-                                        stack_trace(call_stack),
-                                        &call.fn_name
+                                return Err(RuntimeError::new(
+                                    ErrorKind::Custom,
+                                    Some(prop.source_range()),
+                                    self.module.error_fnindex(
+                                        prop.source_range(),
+                                        &format!(
+                                            "{}\nRequires `->` on function `{}`",
+                                            stack_trace(call_stack),
+                                            &call.fn_name
+                                        ),
+                                        call.index,
                                     ),
-                                    call.index,
                                 ));
                             }
                             if let Variable::Return = stack[call.stack_len - 1] {
                             } else {
-                                return Err(self.module.error_fnindex(
-                                    prop.source_range(),
-                                    &format!(
-                                        "{}\nRequires `->` on function `{}`",
-                                        stack_trace(call_stack),
-                                        &call.fn_name
+                                return Err(RuntimeError::new(
+                                    ErrorKind::Custom,
+                                    Some(prop.source_range()),
+                                    self.module.error_fnindex(
+                                        prop.source_range(),
+                                        &format!(
+                                            "{}\nRequires `->` on function `{}`",
+                                            stack_trace(call_stack),
+                                            &call.fn_name
+                                        ),
+                                        call.index,
                                     ),
-                                    call.index,
                                 ));
                             }
                             let mut err = err.clone();
@@ -2621,9 +3437,16 @@ impl Runtime {
                 }
             }
 
-            match side {
-                Side::Right => unsafe { &*var }.clone(),
-                Side::LeftInsert(_) => Variable::UnsafeRef(UnsafeRef(var)),
+            match (side, farr) {
+                (Side::Right, Some(i)) => match *unsafe { &*var } {
+                    Variable::F64Array(ref arr) => Variable::f64(arr[i]),
+                    _ => unreachable!(),
+                },
+                (Side::Right, None) => unsafe { &*var }.clone(),
+                (Side::LeftInsert(_), farr) => {
+                    *farr_index = farr;
+                    Variable::UnsafeRef(UnsafeRef(var))
+                }
             }
         };
         stack.truncate(start_stack_len);
@@ -2644,9 +3467,11 @@ impl Runtime {
         };
         let val = match *self.resolve(&cond) {
             Variable::Bool(val, _) => val,
-            _ => {
-                return self.err(
+            ref x => {
+                return self.type_err(
                     if_expr.cond.source_range(),
+                    "boolean",
+                    x,
                     "Expected bool from if condition",
                 )
             }
@@ -2671,7 +3496,14 @@ impl Runtime {
                 Variable::Bool(true, _) => {
                     return self.block(body);
                 }
-                _ => return self.err(cond.source_range(), "Expected bool from else if condition"),
+                ref x => {
+                    return self.type_err(
+                        cond.source_range(),
+                        "boolean",
+                        x,
+                        "Expected bool from else if condition",
+                    )
+                }
             }
         }
         if let Some(ref block) = if_expr.else_block {
@@ -2711,7 +3543,14 @@ impl Runtime {
             };
             let val = match val {
                 Variable::Bool(val, _) => val,
-                _ => return self.err(for_expr.cond.source_range(), "Expected bool"),
+                ref x => {
+                    return self.type_err(
+                        for_expr.cond.source_range(),
+                        "boolean",
+                        x,
+                        "Expected bool",
+                    )
+                }
             };
             if !val {
                 break;
@@ -2791,25 +3630,30 @@ impl Runtime {
         let w = self.stack.pop().expect(TINVOTS);
         let w = match self.resolve(&w) {
             &Variable::F64(val, _) => val,
-            x => return self.err(vec4.args[3].source_range(), &self.expected(x, "number")),
+            x => return self.err_from(vec4.args[3].source_range(), self.expected(x, "number")),
         };
         let z = self.stack.pop().expect(TINVOTS);
         let z = match self.resolve(&z) {
             &Variable::F64(val, _) => val,
-            x => return self.err(vec4.args[2].source_range(), &self.expected(x, "number")),
+            x => return self.err_from(vec4.args[2].source_range(), self.expected(x, "number")),
         };
         let y = self.stack.pop().expect(TINVOTS);
         let y = match self.resolve(&y) {
             &Variable::F64(val, _) => val,
-            x => return self.err(vec4.args[1].source_range(), &self.expected(x, "number")),
+            x => return self.err_from(vec4.args[1].source_range(), self.expected(x, "number")),
         };
         let x = self.stack.pop().expect(TINVOTS);
         let x = match self.resolve(&x) {
             &Variable::F64(val, _) => val,
-            x => return self.err(vec4.args[0].source_range(), &self.expected(x, "number")),
+            x => return self.err_from(vec4.args[0].source_range(), self.expected(x, "number")),
         };
         Ok((
-            Some(Variable::Vec4([x as f32, y as f32, z as f32, w as f32])),
+            Some(Variable::Vec4([
+                x as Scalar,
+                y as Scalar,
+                z as Scalar,
+                w as Scalar,
+            ])),
             Flow::Continue,
         ))
     }
@@ -2825,22 +3669,22 @@ impl Runtime {
         let w = self.stack.pop().expect(TINVOTS);
         let w = match self.resolve(&w) {
             &Variable::Vec4(val) => val,
-            x => return self.err(mat4.args[3].source_range(), &self.expected(x, "vec4")),
+            x => return self.err_from(mat4.args[3].source_range(), self.expected(x, "vec4")),
         };
         let z = self.stack.pop().expect(TINVOTS);
         let z = match self.resolve(&z) {
             &Variable::Vec4(val) => val,
-            x => return self.err(mat4.args[2].source_range(), &self.expected(x, "vec4")),
+            x => return self.err_from(mat4.args[2].source_range(), self.expected(x, "vec4")),
         };
         let y = self.stack.pop().expect(TINVOTS);
         let y = match self.resolve(&y) {
             &Variable::Vec4(val) => val,
-            x => return self.err(mat4.args[1].source_range(), &self.expected(x, "vec4")),
+            x => return self.err_from(mat4.args[1].source_range(), self.expected(x, "vec4")),
         };
         let x = self.stack.pop().expect(TINVOTS);
         let x = match self.resolve(&x) {
             &Variable::Vec4(val) => val,
-            x => return self.err(mat4.args[0].source_range(), &self.expected(x, "vec4")),
+            x => return self.err_from(mat4.args[0].source_range(), self.expected(x, "vec4")),
         };
         Ok((
             Some(Variable::Mat4(Box::new([
@@ -2854,20 +3698,72 @@ impl Runtime {
     }
 
     pub(crate) fn stack_trace(&self) -> String {
-        stack_trace(&self.call_stack)
+        let top = match self.intrinsic_range {
+            Some((n, range)) if n == self.call_stack.len() => Some(range),
+            _ => None,
+        };
+        stack_trace_at(&self.call_stack, top)
+    }
+}
+
+/// Sends function arguments to the in-types of a function.
+pub(crate) fn send_args(f: &ast::Function, msg: Vec<Variable>) {
+    use std::sync::atomic::Ordering;
+
+    let msg = Arc::new(msg);
+    // Uses smart swapping of channels to put the closed ones at the end.
+    let mut channels = f.senders.1.lock().unwrap();
+    let mut open = channels.len();
+    for i in (0..channels.len()).rev() {
+        match channels[i].send(Variable::Array(msg.clone())) {
+            Ok(_) => {}
+            Err(_) => {
+                open -= 1;
+                channels.swap(i, open);
+            }
+        }
+    }
+    channels.truncate(open);
+    if channels.is_empty() {
+        // Change of flag is guarded by the mutex.
+        f.senders.0.store(false, Ordering::Relaxed);
     }
 }
 
 fn stack_trace(call_stack: &[Call]) -> String {
+    stack_trace_at(call_stack, None)
+}
+
+/// Lists the functions on the call stack with file, line and column when known.
+///
+/// Each function is at the call to the next function, and the last one at `top`.
+fn stack_trace_at(call_stack: &[Call], top: Option<Range>) -> String {
     let mut s = String::new();
-    for call in call_stack.iter() {
+    for (i, call) in call_stack.iter().enumerate() {
         s.push_str(&call.fn_name);
         if let Some(ref file) = call.file {
             s.push_str(" (");
             s.push_str(file);
+            let range = call_stack.get(i + 1).map(|next| next.range).unwrap_or(top);
+            // Calls from Rust have an empty range.
+            if let Some(range) = range.filter(|r| r.length > 0) {
+                let (line, col) = line_col(&call.source, range.offset);
+                s.push_str(&format!(":{}:{}", line, col));
+            }
             s.push(')');
         }
         s.push('\n')
     }
     s
 }
+
+/// Returns the line and column of a character offset, starting at 1.
+fn line_col(source: &str, offset: usize) -> (usize, usize) {
+    source.chars().take(offset).fold((1, 1), |(line, col), c| {
+        if c == '\n' {
+            (line + 1, 1)
+        } else {
+            (line, col + 1)
+        }
+    })
+}
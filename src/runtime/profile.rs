@@ -0,0 +1,113 @@
+use std::time::{Duration, Instant};
+
+use super::*;
+
+/// Timing of a function or intrinsic.
+#[derive(Clone, Copy, Debug, Default)]
+struct Timing {
+    calls: u64,
+    /// Time from call to return, not counting recursive calls twice.
+    total: Duration,
+    /// Time spent in the function itself, without the functions it calls.
+    self_time: Duration,
+}
+
+struct Frame {
+    name: Arc<String>,
+    start: Instant,
+    /// Time spent in called functions.
+    children: Duration,
+}
+
+/// Records call counts and time per function.
+#[derive(Default)]
+pub(crate) struct Profiler {
+    timings: HashMap<Arc<String>, Timing>,
+    frames: Vec<Frame>,
+}
+
+impl Profiler {
+    pub(crate) fn enter(&mut self, name: &Arc<String>) {
+        self.frames.push(Frame {
+            name: name.clone(),
+            start: Instant::now(),
+            children: Duration::from_secs(0),
+        });
+    }
+
+    pub(crate) fn exit(&mut self) {
+        let frame = match self.frames.pop() {
+            Some(frame) => frame,
+            None => return,
+        };
+        let elapsed = frame.start.elapsed();
+        let recursive = self.frames.iter().any(|f| f.name == frame.name);
+        if let Some(parent) = self.frames.last_mut() {
+            parent.children += elapsed;
+        }
+        let timing = self.timings.entry(frame.name).or_default();
+        timing.calls += 1;
+        if !recursive {
+            timing.total += elapsed;
+        }
+        timing.self_time += elapsed.saturating_sub(frame.children);
+    }
+
+    /// Returns the number of functions being timed.
+    pub(crate) fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Stops timing functions that failed, down to a depth.
+    pub(crate) fn unwind(&mut self, depth: usize) {
+        while self.frames.len() > depth {
+            self.exit();
+        }
+    }
+
+    /// Returns the timings as an object,
+    /// with `calls`, `total` and `self` in seconds per function.
+    fn report(&self) -> Variable {
+        let mut obj = HashMap::new();
+        for (name, timing) in &self.timings {
+            let mut entry = HashMap::new();
+            entry.insert(Arc::new("calls".into()), Variable::f64(timing.calls as f64));
+            entry.insert(
+                Arc::new("total".into()),
+                Variable::f64(timing.total.as_secs_f64()),
+            );
+            entry.insert(
+                Arc::new("self".into()),
+                Variable::f64(timing.self_time.as_secs_f64()),
+            );
+            obj.insert(name.clone(), Variable::Object(Arc::new(entry)));
+        }
+        Variable::Object(Arc::new(obj))
+    }
+}
+
+impl Runtime {
+    /// Starts recording call counts and time per Dyon function and intrinsic.
+    ///
+    /// Previously recorded data is cleared.
+    pub fn enable_profiling(&mut self) {
+        self.profiler = Some(Profiler::default());
+    }
+
+    /// Stops recording and clears the data.
+    pub fn disable_profiling(&mut self) {
+        self.profiler = None;
+    }
+
+    /// Returns the recorded data as an object, with an entry per function.
+    ///
+    /// Each entry has `calls`, plus `total` and `self` time in seconds.
+    /// The `self` time leaves out the time spent in called functions.
+    /// Returns an empty object when profiling is disabled.
+    pub fn profile_report(&self) -> Variable {
+        match self.profiler {
+            Some(ref p) => p.report(),
+            None => Variable::Object(Arc::new(HashMap::new())),
+        }
+    }
+}
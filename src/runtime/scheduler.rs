@@ -0,0 +1,55 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::*;
+
+/// Lets the host decide what happens when a script waits,
+/// e.g. to run a script in step with the frames of a game loop.
+pub trait Scheduler: Send {
+    /// Called by `yield_frame`, when the script is done with the current frame.
+    ///
+    /// Returns when the script should continue.
+    fn yield_frame(&mut self) -> Result<(), String>;
+    /// Called by `sleep_until`, with a time in seconds since Unix Epoch.
+    ///
+    /// Returns when the script should continue.
+    fn sleep_until(&mut self, time: f64) -> Result<(), String>;
+}
+
+impl Runtime {
+    /// Sets the scheduler used by `yield_frame` and `sleep_until`.
+    ///
+    /// Without a scheduler, `yield_frame` yields the thread
+    /// and `sleep_until` puts the thread to sleep.
+    pub fn set_scheduler(&mut self, scheduler: Box<dyn Scheduler>) {
+        self.scheduler = Some(scheduler);
+    }
+
+    /// Removes the scheduler.
+    pub fn clear_scheduler(&mut self) -> Option<Box<dyn Scheduler>> {
+        self.scheduler.take()
+    }
+
+    pub(crate) fn yield_frame(&mut self) -> Result<(), String> {
+        match self.scheduler {
+            Some(ref mut s) => s.yield_frame(),
+            None => {
+                ::std::thread::yield_now();
+                Ok(())
+            }
+        }
+    }
+
+    pub(crate) fn sleep_until(&mut self, time: f64) -> Result<(), String> {
+        if let Some(ref mut s) = self.scheduler {
+            return s.sleep_until(time);
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs_f64())
+            .unwrap_or(0.0);
+        if time > now {
+            ::std::thread::sleep(Duration::from_secs_f64(time - now));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,208 @@
+//! Limits for running untrusted scripts.
+
+use std::time::Duration;
+#[cfg(not(target_family = "wasm"))]
+use std::time::Instant;
+
+use super::*;
+
+/// Settings of a runtime, used to limit the resources a program can use.
+///
+/// A limit of `None` means no limit, which is the default.
+/// When a limit is exceeded, the program stops with `ErrorKind::LimitExceeded`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RuntimeSettings {
+    /// The maximum number of nested function calls.
+    ///
+    /// Calls in tail position do not count.
+    pub max_call_depth: Option<usize>,
+    /// The maximum number of values on the stack.
+    pub max_stack_size: Option<usize>,
+    /// The maximum length of arrays created with `[x; n]`, `push`, `insert`,
+    /// `sift`, `split` and `chars`.
+    ///
+    /// Loading data with a longer array returns an error.
+    pub max_array_len: Option<usize>,
+    /// The maximum number of statements and loop iterations per call from the host,
+    /// e.g. `Runtime::run` or `Runtime::call_str`.
+    pub instruction_budget: Option<u64>,
+    /// The maximum wall-clock time per call from the host.
+    ///
+    /// The time is checked at every statement and loop iteration, and `sleep` stops at the deadline.
+    /// A call that blocks in other functions, e.g. reading a file, stops when the function returns.
+    pub time_budget: Option<Duration>,
+}
+
+/// A limit of `RuntimeSettings`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Limit {
+    /// `RuntimeSettings::max_call_depth`.
+    CallDepth,
+    /// `RuntimeSettings::max_stack_size`.
+    StackSize,
+    /// `RuntimeSettings::max_array_len`.
+    ArrayLen,
+    /// `RuntimeSettings::instruction_budget`.
+    InstructionBudget,
+    /// `RuntimeSettings::time_budget`, in milliseconds.
+    TimeBudget,
+}
+
+impl Limit {
+    fn name(self) -> &'static str {
+        match self {
+            Limit::CallDepth => "Call depth",
+            Limit::StackSize => "Stack size",
+            Limit::ArrayLen => "Array length",
+            Limit::InstructionBudget => "Instruction budget",
+            Limit::TimeBudget => "Time budget",
+        }
+    }
+
+    fn unit(self) -> &'static str {
+        match self {
+            Limit::TimeBudget => "ms",
+            _ => "",
+        }
+    }
+}
+
+impl Runtime {
+    /// Creates a new Runtime with settings.
+    pub fn with_settings(settings: RuntimeSettings) -> Runtime {
+        let mut rt = Runtime::new();
+        rt.set_settings(settings);
+        rt
+    }
+
+    /// Changes the settings.
+    pub fn set_settings(&mut self, settings: RuntimeSettings) {
+        self.settings = settings;
+        self.reset_budget();
+    }
+
+    /// Returns the settings.
+    pub fn settings(&self) -> &RuntimeSettings {
+        &self.settings
+    }
+
+    /// Restores the instruction budget and starts the time budget, at each call from the host.
+    pub(crate) fn reset_budget(&mut self) {
+        self.budget = self.settings.instruction_budget;
+        #[cfg(not(target_family = "wasm"))]
+        {
+            self.deadline = self
+                .settings
+                .time_budget
+                .and_then(|d| Instant::now().checked_add(d));
+        }
+    }
+
+    /// Returns the error for an exceeded time budget.
+    #[cfg(all(not(target_family = "wasm"), feature = "threading"))]
+    fn time_budget_err(&self) -> RuntimeError {
+        let max = self.settings.time_budget.unwrap_or_default().as_millis() as u64;
+        self.limit_err(Limit::TimeBudget, max)
+    }
+
+    /// Sleeps for a duration, stopping with an error at the deadline of the time budget.
+    #[cfg(all(not(target_family = "wasm"), feature = "threading"))]
+    pub(crate) fn sleep(&self, dur: Duration) -> Result<(), RuntimeError> {
+        use std::thread;
+
+        if let Some(deadline) = self.deadline {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if dur >= left {
+                thread::sleep(left);
+                return Err(self.time_budget_err());
+            }
+        }
+        thread::sleep(dur);
+        Ok(())
+    }
+
+    /// Returns the error for an exceeded limit.
+    pub(crate) fn limit_err(&self, limit: Limit, max: u64) -> RuntimeError {
+        RuntimeError::new(
+            ErrorKind::LimitExceeded { limit },
+            None,
+            format!(
+                "{} limit of `{}{}` exceeded",
+                limit.name(),
+                max,
+                limit.unit()
+            ),
+        )
+    }
+
+    /// Returns the error for an exceeded limit, reported at a range in the source.
+    fn limit_err_at(&self, range: Range, limit: Limit, max: u64) -> RuntimeError {
+        let err = self.limit_err(limit, max);
+        let msg = format!("{}\n{}", self.stack_trace(), err.message());
+        let msg = self.module.error(range, &msg, self);
+        err.at(range, msg)
+    }
+
+    /// Checks the call depth before calling a function.
+    pub(crate) fn check_call_depth(&self, range: Option<Range>) -> Result<(), RuntimeError> {
+        match self.settings.max_call_depth {
+            Some(max) if self.call_stack.len() >= max => Err(match range {
+                Some(range) => self.limit_err_at(range, Limit::CallDepth, max as u64),
+                None => {
+                    let err = self.limit_err(Limit::CallDepth, max as u64);
+                    let msg = format!("{}\n{}", self.stack_trace(), err.message());
+                    RuntimeError::new(
+                        ErrorKind::LimitExceeded {
+                            limit: Limit::CallDepth,
+                        },
+                        None,
+                        msg,
+                    )
+                }
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks the length of an array before creating it.
+    pub(crate) fn check_array_len(&self, len: usize) -> Result<(), RuntimeError> {
+        match self.settings.max_array_len {
+            Some(max) if len > max => Err(self.limit_err(Limit::ArrayLen, max as u64)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Counts an instruction and checks the stack size and cancellation.
+    pub(crate) fn step(&mut self, range: Range) -> Result<(), RuntimeError> {
+        self.check_cancel(range)?;
+        #[cfg(all(not(target_family = "wasm"), feature = "threading"))]
+        self.burn_fuel(range)?;
+        let exceeded = match self.budget {
+            Some(0) => Some((
+                Limit::InstructionBudget,
+                self.settings.instruction_budget.unwrap_or(0),
+            )),
+            Some(ref mut n) => {
+                *n -= 1;
+                None
+            }
+            None => None,
+        };
+        #[cfg(not(target_family = "wasm"))]
+        let exceeded = exceeded.or(match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => Some((
+                Limit::TimeBudget,
+                self.settings.time_budget.unwrap_or_default().as_millis() as u64,
+            )),
+            _ => None,
+        });
+        let exceeded = exceeded.or(match self.settings.max_stack_size {
+            Some(max) if self.stack.len() > max => Some((Limit::StackSize, max as u64)),
+            _ => None,
+        });
+        match exceeded {
+            Some((limit, max)) => Err(self.limit_err_at(range, limit, max)),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use super::*;
+
+/// A closure to call at a time, and optionally repeat.
+struct Timer {
+    id: u64,
+    at: f64,
+    interval: Option<f64>,
+    closure: Variable,
+}
+
+/// Timers started by `after` and `every`, driven by `Runtime::tick`.
+#[derive(Default)]
+pub(crate) struct Timers {
+    /// The time in seconds since the first tick.
+    time: f64,
+    next_id: u64,
+    list: Vec<Timer>,
+}
+
+impl Timers {
+    /// Starts a timer and returns its id.
+    pub(crate) fn start(&mut self, secs: f64, interval: Option<f64>, closure: Variable) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.list.push(Timer {
+            id,
+            at: self.time + secs,
+            interval,
+            closure,
+        });
+        id
+    }
+
+    /// Stops a timer, returning `false` if it is not running.
+    pub(crate) fn cancel(&mut self, id: u64) -> bool {
+        let n = self.list.len();
+        self.list.retain(|t| t.id != id);
+        self.list.len() != n
+    }
+
+    /// Takes the next timer due at or before a time.
+    ///
+    /// Timers due at the same time are taken in the order they were started.
+    /// Repeating timers are scheduled again.
+    fn next_due(&mut self, end: f64) -> Option<Variable> {
+        let i = self
+            .list
+            .iter()
+            .enumerate()
+            .filter(|&(_, t)| t.at <= end)
+            .min_by(|&(_, a), &(_, b)| {
+                a.at.partial_cmp(&b.at)
+                    .unwrap_or(::std::cmp::Ordering::Equal)
+                    .then(a.id.cmp(&b.id))
+            })
+            .map(|(i, _)| i)?;
+        self.time = self.list[i].at;
+        match self.list[i].interval {
+            Some(interval) => {
+                self.list[i].at += interval;
+                Some(self.list[i].closure.clone())
+            }
+            None => Some(self.list.swap_remove(i).closure),
+        }
+    }
+}
+
+impl Runtime {
+    /// Advances the timers by `dt` seconds,
+    /// calling the closures of timers that are due in order of time.
+    ///
+    /// While a closure runs, the timer clock is at the time the timer was due,
+    /// such that new timers are relative to it.
+    /// Timers do not advance between ticks, which lets the host pause them.
+    pub fn tick(&mut self, dt: f64) -> Result<(), String> {
+        if !dt.is_finite() || dt < 0.0 {
+            return Err(format!(
+                "Expected finite non-negative time step, found `{}`",
+                dt
+            ));
+        }
+        let end = self.timers.time + dt;
+        while let Some(closure) = self.timers.next_due(end) {
+            self.call_closure_opt(&closure, &[])?;
+        }
+        self.timers.time = end;
+        Ok(())
+    }
+
+    /// Returns the number of running timers.
+    pub fn timers(&self) -> usize {
+        self.timers.list.len()
+    }
+}
@@ -0,0 +1,244 @@
+//! Steps a scripted simulation at a fixed time step.
+//!
+//! A `Sim` owns the simulation state as a Dyon variable,
+//! and calls a `step(state, dt) -> state` function in the script
+//! as many times as needed to catch up with the elapsed time.
+//! Rendering can use `Sim::interpolate` to blend the last two states,
+//! and states can be saved with `Sim::snapshot` and restored with `Sim::rollback`.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+use Module;
+use Runtime;
+use Scalar;
+use Variable;
+
+/// A saved simulation state, see `Sim::snapshot`.
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    frame: u64,
+    state: Variable,
+    previous: Variable,
+    accumulator: f64,
+}
+
+impl Snapshot {
+    /// Returns the frame when the snapshot was taken.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Returns the saved state.
+    pub fn state(&self) -> &Variable {
+        &self.state
+    }
+}
+
+/// Runs a simulation at a fixed time step.
+pub struct Sim {
+    runtime: Runtime,
+    module: Arc<Module>,
+    step: String,
+    dt: f64,
+    max_steps: usize,
+    frame: u64,
+    state: Variable,
+    previous: Variable,
+    accumulator: f64,
+    history: VecDeque<Snapshot>,
+    history_len: usize,
+}
+
+impl Sim {
+    /// Creates a new simulation calling `step` with a fixed time step `dt` in seconds.
+    pub fn new(runtime: Runtime, module: Arc<Module>, state: Variable, dt: f64) -> Sim {
+        Sim {
+            runtime,
+            module,
+            step: "step".into(),
+            dt,
+            max_steps: 8,
+            frame: 0,
+            previous: state.clone(),
+            state,
+            accumulator: 0.0,
+            history: VecDeque::new(),
+            history_len: 0,
+        }
+    }
+
+    /// Sets the name of the step function, `step` by default.
+    pub fn set_step_fn(&mut self, name: &str) {
+        self.step = name.into();
+    }
+
+    /// Sets the maximum number of steps per call to `advance`, 8 by default.
+    ///
+    /// Time beyond this is dropped, such that a slow step does not make
+    /// the simulation fall further and further behind.
+    pub fn set_max_steps(&mut self, max_steps: usize) {
+        self.max_steps = max_steps;
+    }
+
+    /// Sets the number of snapshots to keep for `rollback_to`, 0 by default.
+    pub fn set_history(&mut self, len: usize) {
+        self.history_len = len;
+        while self.history.len() > len {
+            self.history.pop_front();
+        }
+    }
+
+    /// Returns the runtime used to call the step function.
+    pub fn runtime(&mut self) -> &mut Runtime {
+        &mut self.runtime
+    }
+
+    /// Returns the fixed time step in seconds.
+    pub fn dt(&self) -> f64 {
+        self.dt
+    }
+
+    /// Returns the number of steps taken.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Returns the simulated time in seconds.
+    pub fn time(&self) -> f64 {
+        self.frame as f64 * self.dt
+    }
+
+    /// Returns the current state.
+    pub fn state(&self) -> &Variable {
+        &self.state
+    }
+
+    /// Replaces the current state, e.g. to apply input from the host.
+    pub fn set_state(&mut self, state: Variable) {
+        self.state = state;
+    }
+
+    /// Takes a single step.
+    pub fn step(&mut self) -> Result<(), String> {
+        if self.history_len > 0 {
+            if self.history.len() == self.history_len {
+                self.history.pop_front();
+            }
+            let snapshot = self.snapshot();
+            self.history.push_back(snapshot);
+        }
+        let args = [self.state.clone(), Variable::f64(self.dt)];
+        let state = self.runtime.call_str_ret(&self.step, &args, &self.module)?;
+        self.previous = ::std::mem::replace(&mut self.state, state);
+        self.frame += 1;
+        Ok(())
+    }
+
+    /// Adds elapsed time in seconds and takes the steps that are due.
+    ///
+    /// Returns the number of steps taken.
+    pub fn advance(&mut self, elapsed: f64) -> Result<usize, String> {
+        if !elapsed.is_finite() || elapsed < 0.0 {
+            return Err(format!(
+                "Expected finite non-negative elapsed time, found `{}`",
+                elapsed
+            ));
+        }
+        if self.dt.is_nan() || self.dt <= 0.0 {
+            return Err(format!("Expected positive time step, found `{}`", self.dt));
+        }
+        self.accumulator += elapsed;
+        let mut steps = 0;
+        while self.accumulator >= self.dt {
+            if steps == self.max_steps {
+                self.accumulator = 0.0;
+                break;
+            }
+            self.step()?;
+            self.accumulator -= self.dt;
+            steps += 1;
+        }
+        Ok(steps)
+    }
+
+    /// Returns how far the time is between the last step and the next one,
+    /// from 0 to 1.
+    pub fn alpha(&self) -> f64 {
+        (self.accumulator / self.dt).min(1.0)
+    }
+
+    /// Blends the previous and current state by `alpha`, for rendering.
+    ///
+    /// Numbers and vec4 are interpolated linearly,
+    /// also inside arrays of equal length and objects.
+    /// Other values are taken from the current state.
+    pub fn interpolate(&self) -> Variable {
+        lerp(&self.previous, &self.state, self.alpha())
+    }
+
+    /// Saves the current state.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            frame: self.frame,
+            state: self.state.clone(),
+            previous: self.previous.clone(),
+            accumulator: self.accumulator,
+        }
+    }
+
+    /// Restores a saved state.
+    pub fn rollback(&mut self, snapshot: &Snapshot) {
+        self.frame = snapshot.frame;
+        self.state = snapshot.state.clone();
+        self.previous = snapshot.previous.clone();
+        self.accumulator = snapshot.accumulator;
+        self.history.retain(|s| s.frame < snapshot.frame);
+    }
+
+    /// Restores the state at a frame kept in the history, see `set_history`.
+    ///
+    /// Returns `false` if the frame is not in the history.
+    pub fn rollback_to(&mut self, frame: u64) -> bool {
+        match self.history.iter().find(|s| s.frame == frame).cloned() {
+            Some(snapshot) => {
+                self.rollback(&snapshot);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Blends two states, sharing the new arrays and objects by `Arc` like other variables.
+#[allow(clippy::arc_with_non_send_sync)]
+fn lerp(a: &Variable, b: &Variable, t: f64) -> Variable {
+    match (a, b) {
+        (&Variable::F64(a, _), &Variable::F64(b, ref sec)) => {
+            Variable::F64(a + (b - a) * t, sec.clone())
+        }
+        (&Variable::Vec4(a), &Variable::Vec4(b)) => {
+            let t = t as Scalar;
+            Variable::Vec4([
+                a[0] + (b[0] - a[0]) * t,
+                a[1] + (b[1] - a[1]) * t,
+                a[2] + (b[2] - a[2]) * t,
+                a[3] + (b[3] - a[3]) * t,
+            ])
+        }
+        (Variable::Array(a), Variable::Array(b)) if a.len() == b.len() => Variable::Array(
+            Arc::new(a.iter().zip(b.iter()).map(|(a, b)| lerp(a, b, t)).collect()),
+        ),
+        (Variable::Object(a), Variable::Object(b)) => {
+            let obj: HashMap<_, _> = b
+                .iter()
+                .map(|(k, b)| match a.get(k) {
+                    Some(a) => (k.clone(), lerp(a, b, t)),
+                    None => (k.clone(), b.clone()),
+                })
+                .collect();
+            Variable::Object(Arc::new(obj))
+        }
+        _ => b.clone(),
+    }
+}
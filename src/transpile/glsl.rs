@@ -0,0 +1,943 @@
+use range::Range;
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use ast;
+use FnIndex;
+use Module;
+use Scalar;
+use Type;
+use Variable;
+
+/// GLSL types that Dyon values can be mapped to.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Ty {
+    Void,
+    Bool,
+    Float,
+    Vec4,
+    Mat4,
+}
+
+impl Ty {
+    fn from_type(ty: &Type) -> Option<Ty> {
+        Some(match *ty {
+            Type::Void => Ty::Void,
+            Type::Bool => Ty::Bool,
+            Type::F64 => Ty::Float,
+            Type::Vec4 => Ty::Vec4,
+            Type::Mat4 => Ty::Mat4,
+            _ => return None,
+        })
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Ty::Void => "void",
+            Ty::Bool => "bool",
+            Ty::Float => "float",
+            Ty::Vec4 => "vec4",
+            Ty::Mat4 => "mat4",
+        }
+    }
+}
+
+/// Keeps track of local variables in the function being generated.
+struct Scope {
+    /// The index of the function.
+    f: usize,
+    /// The return type of the function.
+    ret: Ty,
+    /// Local variables and their types.
+    locals: Vec<(Arc<String>, Ty)>,
+}
+
+struct Generator<'a> {
+    module: &'a Module,
+    out: String,
+    visiting: Vec<usize>,
+    done: Vec<usize>,
+}
+
+pub(crate) fn generate(module: &Module, name: &str) -> Result<String, String> {
+    let name = Arc::new(name.into());
+    let index = match module.find_function(&name, 0) {
+        FnIndex::Loaded(i) => i as usize,
+        _ => return Err(format!("Could not find function `{}`", name)),
+    };
+    let mut g = Generator {
+        module,
+        out: String::new(),
+        visiting: vec![],
+        done: vec![],
+    };
+    g.function(index)?;
+    Ok(g.out)
+}
+
+fn write_tabs(out: &mut String, tabs: u32) {
+    for _ in 0..tabs {
+        out.push_str("    ");
+    }
+}
+
+/// Formats a number as a GLSL float literal.
+///
+/// GLSL uses single precision, so the shortest single precision representation is used.
+fn float(v: f64) -> String {
+    let s = format!("{:?}", v as f32);
+    if s.contains('.') || s.contains('e') {
+        s
+    } else {
+        format!("{}.0", s)
+    }
+}
+
+fn vec4(v: &[Scalar; 4]) -> String {
+    format!(
+        "vec4({}, {}, {}, {})",
+        float(f64::from(v[0])),
+        float(f64::from(v[1])),
+        float(f64::from(v[2])),
+        float(f64::from(v[3]))
+    )
+}
+
+/// Keywords of GLSL, including those reserved for future use.
+const KEYWORDS: &str = "\
+    active asm atomic_uint attribute bool break buffer bvec2 bvec3 bvec4 case cast \
+    centroid class coherent common const continue default discard dmat2 dmat3 dmat4 do \
+    double dvec2 dvec3 dvec4 else enum extern external false filter fixed flat float for \
+    fvec2 fvec3 fvec4 goto half highp hvec2 hvec3 hvec4 if in inline inout input int \
+    interface invariant isampler2D isampler3D ivec2 ivec3 ivec4 layout long lowp mat2 \
+    mat3 mat4 mediump namespace noinline noperspective out output partition patch \
+    precise precision public readonly resource restrict return sample sampler1D \
+    sampler2D sampler3D samplerCube shared short sizeof smooth static struct subroutine \
+    superp switch template this true typedef uint uniform union unsigned using \
+    usampler2D usampler3D uvec2 uvec3 uvec4 varying vec2 vec3 vec4 void volatile while \
+    writeonly";
+
+/// Built-in functions of GLSL, and `main` which is the entry point of a shader.
+const BUILTINS: &str = "\
+    abs acos acosh all any asin asinh atan atanh ceil clamp cos cosh cross dFdx dFdy \
+    degrees determinant distance dot equal exp exp2 faceforward floatBitsToInt \
+    floatBitsToUint floor fma fract frexp fwidth greaterThan greaterThanEqual \
+    intBitsToFloat inverse inversesqrt isinf isnan ldexp length lessThan lessThanEqual \
+    log log2 main matrixCompMult max min mix mod modf normalize not notEqual \
+    outerProduct pow radians reflect refract round roundEven sign sin sinh smoothstep \
+    sqrt step tan tanh texelFetch texture textureLod textureSize transpose trunc \
+    uintBitsToFloat";
+
+/// Returns the GLSL name of a function or variable.
+///
+/// Names that are reserved in GLSL get the prefix `dyon_`,
+/// and so do names that already start with `dyon_`, such that names stay unique.
+fn ident(name: &str) -> Cow<'_, str> {
+    let reserved = |words: &str| words.split_whitespace().any(|w| w == name);
+    if reserved(KEYWORDS)
+        || reserved(BUILTINS)
+        || name.starts_with("gl_")
+        || name.starts_with("dyon_")
+    {
+        Cow::Owned(format!("dyon_{}", name))
+    } else {
+        Cow::Borrowed(name)
+    }
+}
+
+/// Returns the GLSL name of a function,
+/// without mutability information in the name, e.g. `foo(mut,_)`.
+fn fn_name(name: &str) -> Cow<'_, str> {
+    match name.find('(') {
+        Some(i) => ident(&name[..i]),
+        None => ident(name),
+    }
+}
+
+fn swizzle_len(sw: &ast::Swizzle) -> usize {
+    2 + if sw.sw2.is_some() { 1 } else { 0 } + if sw.sw3.is_some() { 1 } else { 0 }
+}
+
+fn write_swizzle_components(out: &mut String, sw: &ast::Swizzle) {
+    const COMPS: [char; 4] = ['x', 'y', 'z', 'w'];
+    out.push(COMPS[sw.sw0]);
+    out.push(COMPS[sw.sw1]);
+    if let Some(sw2) = sw.sw2 {
+        out.push(COMPS[sw2]);
+    }
+    if let Some(sw3) = sw.sw3 {
+        out.push(COMPS[sw3]);
+    }
+}
+
+impl<'a> Generator<'a> {
+    fn err(&self, scope: &Scope, range: Range, msg: &str) -> String {
+        self.module.error_fnindex(range, msg, scope.f)
+    }
+
+    fn expected(&self, scope: &Scope, range: Range, expected: Ty, found: Ty) -> String {
+        self.err(
+            scope,
+            range,
+            &format!(
+                "GLSL: Expected `{}`, found `{}`",
+                expected.name(),
+                found.name()
+            ),
+        )
+    }
+
+    fn function(&mut self, index: usize) -> Result<(), String> {
+        if self.done.contains(&index) {
+            return Ok(());
+        }
+        let module = self.module;
+        let f = &module.functions[index];
+        let mut scope = Scope {
+            f: index,
+            ret: Ty::Void,
+            locals: vec![],
+        };
+        if self.visiting.contains(&index) {
+            return Err(self.err(
+                &scope,
+                f.source_range,
+                &format!("GLSL: Recursive function `{}` is not supported", f.name),
+            ));
+        }
+        if !f.currents.is_empty() {
+            return Err(self.err(
+                &scope,
+                f.source_range,
+                "GLSL: Current objects are not supported",
+            ));
+        }
+        scope.ret = match Ty::from_type(&f.ret) {
+            Some(ty) => ty,
+            None => {
+                return Err(self.err(
+                    &scope,
+                    f.source_range,
+                    "GLSL: Expected return type `f64`, `bool`, `vec4`, `mat4` or none",
+                ))
+            }
+        };
+
+        let mut head = format!("{} {}(", scope.ret.name(), fn_name(&f.name));
+        for (i, arg) in f.args.iter().enumerate() {
+            let ty = match Ty::from_type(&arg.ty) {
+                Some(ty) if ty != Ty::Void => ty,
+                _ => {
+                    return Err(self.err(
+                        &scope,
+                        arg.source_range,
+                        "GLSL: Expected argument type `f64`, `bool`, `vec4` or `mat4`",
+                    ))
+                }
+            };
+            if i > 0 {
+                head.push_str(", ");
+            }
+            if arg.mutable {
+                head.push_str("inout ");
+            }
+            head.push_str(&format!("{} {}", ty.name(), ident(&arg.name)));
+            scope.locals.push((arg.name.clone(), ty));
+        }
+        head.push_str(") ");
+
+        self.visiting.push(index);
+        let mut body = String::new();
+        let ret = scope.ret;
+        self.block(&mut scope, &f.block, ret, 0, &mut body)?;
+        self.visiting.pop();
+        self.done.push(index);
+
+        if !self.out.is_empty() {
+            self.out.push('\n');
+        }
+        self.out.push_str(&head);
+        self.out.push_str(&body);
+        self.out.push('\n');
+        Ok(())
+    }
+
+    /// Writes a block.
+    /// When `ret` is not `void`, the last expression is returned from the function.
+    fn block(
+        &mut self,
+        scope: &mut Scope,
+        block: &ast::Block,
+        ret: Ty,
+        tabs: u32,
+        out: &mut String,
+    ) -> Result<(), String> {
+        let n = scope.locals.len();
+        out.push_str("{\n");
+        for (i, expr) in block.expressions.iter().enumerate() {
+            write_tabs(out, tabs + 1);
+            if ret != Ty::Void && i + 1 == block.expressions.len() {
+                self.return_stmt(scope, expr, ret, tabs + 1, out)?;
+            } else {
+                self.stmt(scope, expr, tabs + 1, out)?;
+            }
+            out.push('\n');
+        }
+        scope.locals.truncate(n);
+        write_tabs(out, tabs);
+        out.push('}');
+        Ok(())
+    }
+
+    fn return_stmt(
+        &mut self,
+        scope: &mut Scope,
+        expr: &ast::Expression,
+        ret: Ty,
+        tabs: u32,
+        out: &mut String,
+    ) -> Result<(), String> {
+        use ast::Expression as E;
+
+        match *expr {
+            E::If(ref if_expr) => self.if_stmt(scope, if_expr, ret, tabs, out),
+            E::Block(ref block) => self.block(scope, block, ret, tabs, out),
+            E::Return(_) | E::ReturnVoid(_) => self.stmt(scope, expr, tabs, out),
+            _ => {
+                out.push_str("return ");
+                let ty = self.expr(scope, expr, out)?;
+                if ty != ret {
+                    return Err(self.expected(scope, expr.source_range(), ret, ty));
+                }
+                out.push(';');
+                Ok(())
+            }
+        }
+    }
+
+    fn stmt(
+        &mut self,
+        scope: &mut Scope,
+        expr: &ast::Expression,
+        tabs: u32,
+        out: &mut String,
+    ) -> Result<(), String> {
+        use ast::Expression as E;
+
+        match *expr {
+            E::Assign(ref assign) => {
+                self.assign(scope, assign, out)?;
+                out.push(';');
+            }
+            E::If(ref if_expr) => self.if_stmt(scope, if_expr, Ty::Void, tabs, out)?,
+            E::For(ref for_expr) => self.for_stmt(scope, for_expr, tabs, out)?,
+            E::ForN(ref for_n) => self.for_n_stmt(scope, for_n, tabs, out)?,
+            E::Block(ref block) => self.block(scope, block, Ty::Void, tabs, out)?,
+            E::Return(ref ret_expr) => {
+                out.push_str("return ");
+                let ty = self.expr(scope, ret_expr, out)?;
+                if ty != scope.ret {
+                    return Err(self.expected(scope, ret_expr.source_range(), scope.ret, ty));
+                }
+                out.push(';');
+            }
+            E::ReturnVoid(_) => out.push_str("return;"),
+            E::Break(ref br) => {
+                if br.label.is_some() {
+                    return Err(self.err(
+                        scope,
+                        br.source_range,
+                        "GLSL: Loop labels are not supported",
+                    ));
+                }
+                out.push_str("break;");
+            }
+            E::Continue(ref c) => {
+                if c.label.is_some() {
+                    return Err(self.err(
+                        scope,
+                        c.source_range,
+                        "GLSL: Loop labels are not supported",
+                    ));
+                }
+                out.push_str("continue;");
+            }
+            _ => {
+                self.expr(scope, expr, out)?;
+                out.push(';');
+            }
+        }
+        Ok(())
+    }
+
+    fn assign(
+        &mut self,
+        scope: &mut Scope,
+        assign: &ast::Assign,
+        out: &mut String,
+    ) -> Result<(), String> {
+        use ast::AssignOp;
+
+        let mut right = String::new();
+        let right_ty = self.expr(scope, &assign.right, &mut right)?;
+        if assign.op == AssignOp::Assign {
+            let name = match assign.left {
+                ast::Expression::Item(ref item) if item.ids.is_empty() => item.name.clone(),
+                _ => {
+                    return Err(self.err(
+                        scope,
+                        assign.left.source_range(),
+                        "GLSL: Expected local variable name",
+                    ))
+                }
+            };
+            if right_ty == Ty::Void {
+                return Err(self.err(
+                    scope,
+                    assign.right.source_range(),
+                    "GLSL: Expected something to assign",
+                ));
+            }
+            out.push_str(&format!("{} {} = {}", right_ty.name(), ident(&name), right));
+            scope.locals.push((name, right_ty));
+            return Ok(());
+        }
+
+        let mut left = String::new();
+        self.expr(scope, &assign.left, &mut left)?;
+        match assign.op {
+            AssignOp::Assign => unreachable!(),
+            AssignOp::Rem => out.push_str(&format!("{} = mod({}, {})", left, left, right)),
+            AssignOp::Pow => out.push_str(&format!("{} = pow({}, {})", left, left, right)),
+            op => out.push_str(&format!("{} {} {}", left, op.symbol(), right)),
+        }
+        Ok(())
+    }
+
+    fn if_stmt(
+        &mut self,
+        scope: &mut Scope,
+        if_expr: &ast::If,
+        ret: Ty,
+        tabs: u32,
+        out: &mut String,
+    ) -> Result<(), String> {
+        out.push_str("if (");
+        self.cond(scope, &if_expr.cond, out)?;
+        out.push_str(") ");
+        self.block(scope, &if_expr.true_block, ret, tabs, out)?;
+        for (cond, block) in if_expr
+            .else_if_conds
+            .iter()
+            .zip(if_expr.else_if_blocks.iter())
+        {
+            out.push_str(" else if (");
+            self.cond(scope, cond, out)?;
+            out.push_str(") ");
+            self.block(scope, block, ret, tabs, out)?;
+        }
+        if let Some(ref block) = if_expr.else_block {
+            out.push_str(" else ");
+            self.block(scope, block, ret, tabs, out)?;
+        }
+        Ok(())
+    }
+
+    fn cond(
+        &mut self,
+        scope: &mut Scope,
+        cond: &ast::Expression,
+        out: &mut String,
+    ) -> Result<(), String> {
+        let ty = self.expr(scope, cond, out)?;
+        if ty != Ty::Bool {
+            return Err(self.expected(scope, cond.source_range(), Ty::Bool, ty));
+        }
+        Ok(())
+    }
+
+    fn for_stmt(
+        &mut self,
+        scope: &mut Scope,
+        for_expr: &ast::For,
+        tabs: u32,
+        out: &mut String,
+    ) -> Result<(), String> {
+        use ast::Expression as E;
+
+        if for_expr.label.is_some() {
+            return Err(self.err(
+                scope,
+                for_expr.source_range,
+                "GLSL: Loop labels are not supported",
+            ));
+        }
+        let n = scope.locals.len();
+        out.push_str("for (");
+        match for_expr.init {
+            E::Block(ref b) if b.expressions.is_empty() => {}
+            E::Assign(ref assign) => self.assign(scope, assign, out)?,
+            ref expr => {
+                self.expr(scope, expr, out)?;
+            }
+        }
+        out.push(';');
+        match for_expr.cond {
+            E::Variable(ref range_var) if matches!(range_var.1, Variable::Bool(true, _)) => {}
+            ref cond => {
+                out.push(' ');
+                self.cond(scope, cond, out)?;
+            }
+        }
+        out.push(';');
+        match for_expr.step {
+            E::Block(ref b) if b.expressions.is_empty() => {}
+            E::Assign(ref assign) => {
+                out.push(' ');
+                self.assign(scope, assign, out)?;
+            }
+            ref expr => {
+                out.push(' ');
+                self.expr(scope, expr, out)?;
+            }
+        }
+        out.push_str(") ");
+        self.block(scope, &for_expr.block, Ty::Void, tabs, out)?;
+        scope.locals.truncate(n);
+        Ok(())
+    }
+
+    fn for_n_stmt(
+        &mut self,
+        scope: &mut Scope,
+        for_n: &ast::ForN,
+        tabs: u32,
+        out: &mut String,
+    ) -> Result<(), String> {
+        if for_n.label.is_some() {
+            return Err(self.err(
+                scope,
+                for_n.source_range,
+                "GLSL: Loop labels are not supported",
+            ));
+        }
+        let name = ident(&for_n.name);
+        out.push_str(&format!("for (float {} = ", name));
+        if let Some(ref start) = for_n.start {
+            self.float_expr(scope, start, out)?;
+        } else {
+            out.push_str("0.0");
+        }
+        out.push_str(&format!("; {} < ", name));
+        self.float_expr(scope, &for_n.end, out)?;
+        out.push_str(&format!("; {} += 1.0) ", name));
+        scope.locals.push((for_n.name.clone(), Ty::Float));
+        self.block(scope, &for_n.block, Ty::Void, tabs, out)?;
+        scope.locals.pop();
+        Ok(())
+    }
+
+    fn float_expr(
+        &mut self,
+        scope: &mut Scope,
+        expr: &ast::Expression,
+        out: &mut String,
+    ) -> Result<(), String> {
+        let ty = self.expr(scope, expr, out)?;
+        if ty != Ty::Float {
+            return Err(self.expected(scope, expr.source_range(), Ty::Float, ty));
+        }
+        Ok(())
+    }
+
+    /// Writes an expression and returns its type.
+    fn expr(
+        &mut self,
+        scope: &mut Scope,
+        expr: &ast::Expression,
+        out: &mut String,
+    ) -> Result<Ty, String> {
+        use ast::Expression as E;
+
+        match *expr {
+            E::Variable(ref range_var) => match range_var.1 {
+                Variable::F64(v, _) => {
+                    out.push_str(&float(v));
+                    Ok(Ty::Float)
+                }
+                Variable::Bool(v, _) => {
+                    out.push_str(if v { "true" } else { "false" });
+                    Ok(Ty::Bool)
+                }
+                Variable::Vec4(ref v) => {
+                    out.push_str(&vec4(v));
+                    Ok(Ty::Vec4)
+                }
+                Variable::Mat4(ref m) => {
+                    out.push_str(&format!(
+                        "mat4({}, {}, {}, {})",
+                        vec4(&m[0]),
+                        vec4(&m[1]),
+                        vec4(&m[2]),
+                        vec4(&m[3])
+                    ));
+                    Ok(Ty::Mat4)
+                }
+                _ => Err(self.err(
+                    scope,
+                    range_var.0,
+                    "GLSL: Expected `f64`, `bool`, `vec4` or `mat4`",
+                )),
+            },
+            E::Item(ref item) => self.item(scope, item, out),
+            E::Vec4(ref v) => self.vec4(scope, v, out),
+            E::Mat4(ref m) => {
+                // Dyon matrices are written as rows, GLSL constructs from columns.
+                out.push_str("transpose(mat4(");
+                for (i, arg) in m.args.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+                    let ty = self.expr(scope, arg, out)?;
+                    if ty != Ty::Vec4 {
+                        return Err(self.expected(scope, arg.source_range(), Ty::Vec4, ty));
+                    }
+                }
+                out.push_str("))");
+                Ok(Ty::Mat4)
+            }
+            E::If(ref if_expr) => self.if_expr(scope, if_expr, out),
+            E::Call(ref call) => self.intrinsic(
+                scope,
+                &call.info,
+                &call.args.iter().collect::<Vec<_>>(),
+                out,
+            ),
+            E::CallVoid(ref call) => self.intrinsic(
+                scope,
+                &call.info,
+                &call.args.iter().collect::<Vec<_>>(),
+                out,
+            ),
+            E::CallReturn(ref call) => self.intrinsic(
+                scope,
+                &call.info,
+                &call.args.iter().collect::<Vec<_>>(),
+                out,
+            ),
+            E::CallLazy(ref call) => self.intrinsic(
+                scope,
+                &call.info,
+                &call.args.iter().collect::<Vec<_>>(),
+                out,
+            ),
+            E::CallBinOp(ref call) => {
+                self.intrinsic(scope, &call.info, &[&call.left, &call.right], out)
+            }
+            E::CallUnOp(ref call) => self.intrinsic(scope, &call.info, &[&call.arg], out),
+            E::CallLoaded(ref call) => self.call_loaded(scope, call, out),
+            _ => Err(self.err(
+                scope,
+                expr.source_range(),
+                "GLSL: Expression is not supported",
+            )),
+        }
+    }
+
+    fn item(
+        &mut self,
+        scope: &mut Scope,
+        item: &ast::Item,
+        out: &mut String,
+    ) -> Result<Ty, String> {
+        if item.current || item.try || !item.try_ids.is_empty() {
+            return Err(self.err(scope, item.source_range, "GLSL: Expected local variable"));
+        }
+        let mut ty = match scope.locals.iter().rev().find(|l| l.0 == item.name) {
+            Some(l) => l.1,
+            None => {
+                return Err(self.err(
+                    scope,
+                    item.source_range,
+                    &format!("GLSL: Could not find local variable `{}`", item.name),
+                ))
+            }
+        };
+        out.push_str(&ident(&item.name));
+        for id in &item.ids {
+            ty = match ty {
+                Ty::Vec4 => Ty::Float,
+                Ty::Mat4 => Ty::Vec4,
+                _ => {
+                    return Err(self.err(
+                        scope,
+                        id.source_range(),
+                        "GLSL: Only `vec4` and `mat4` can be indexed",
+                    ))
+                }
+            };
+            match *id {
+                ast::Id::F64(_, ind) => out.push_str(&format!("[{}]", ind as usize)),
+                ast::Id::Expression(ref expr) => {
+                    out.push_str("[int(");
+                    self.float_expr(scope, expr, out)?;
+                    out.push_str(")]");
+                }
+                ast::Id::String(range, _) => {
+                    return Err(self.err(scope, range, "GLSL: Objects are not supported"))
+                }
+            }
+        }
+        Ok(ty)
+    }
+
+    fn vec4(&mut self, scope: &mut Scope, v: &ast::Vec4, out: &mut String) -> Result<Ty, String> {
+        out.push_str("vec4(");
+        let mut n = 0;
+        for arg in &v.args {
+            if n >= 4 {
+                break;
+            }
+            if n > 0 {
+                out.push_str(", ");
+            }
+            if let ast::Expression::Swizzle(ref sw) = *arg {
+                // GLSL vector constructors accept swizzled components directly.
+                let ty = self.expr(scope, &sw.expr, out)?;
+                if ty != Ty::Vec4 {
+                    return Err(self.expected(scope, sw.expr.source_range(), Ty::Vec4, ty));
+                }
+                out.push('.');
+                write_swizzle_components(out, sw);
+                n += swizzle_len(sw);
+            } else {
+                self.float_expr(scope, arg, out)?;
+                n += 1;
+            }
+        }
+        out.push(')');
+        Ok(Ty::Vec4)
+    }
+
+    fn if_expr(
+        &mut self,
+        scope: &mut Scope,
+        if_expr: &ast::If,
+        out: &mut String,
+    ) -> Result<Ty, String> {
+        // Translate to the ternary operator when every branch is a single expression.
+        let single = |block: &ast::Block| block.expressions.len() == 1;
+        let else_block = match if_expr.else_block {
+            Some(ref else_block)
+                if single(else_block)
+                    && single(&if_expr.true_block)
+                    && if_expr.else_if_blocks.iter().all(single) =>
+            {
+                else_block
+            }
+            _ => {
+                return Err(self.err(
+                    scope,
+                    if_expr.source_range,
+                    "GLSL: Expected `if` expression with single expression in every branch",
+                ))
+            }
+        };
+        let conds = Some(&if_expr.cond)
+            .into_iter()
+            .chain(if_expr.else_if_conds.iter());
+        let blocks = Some(&if_expr.true_block)
+            .into_iter()
+            .chain(if_expr.else_if_blocks.iter());
+        let mut ty = None;
+        let mut parens = 0;
+        for (cond, block) in conds.zip(blocks) {
+            out.push('(');
+            parens += 1;
+            self.cond(scope, cond, out)?;
+            out.push_str(" ? ");
+            let block_ty = self.expr(scope, &block.expressions[0], out)?;
+            if let Some(ty) = ty {
+                if ty != block_ty {
+                    return Err(self.expected(scope, block.source_range, ty, block_ty));
+                }
+            }
+            ty = Some(block_ty);
+            out.push_str(" : ");
+        }
+        let ty = ty.unwrap();
+        let else_ty = self.expr(scope, &else_block.expressions[0], out)?;
+        if else_ty != ty {
+            return Err(self.expected(scope, else_block.source_range, ty, else_ty));
+        }
+        for _ in 0..parens {
+            out.push(')');
+        }
+        Ok(ty)
+    }
+
+    fn call_loaded(
+        &mut self,
+        scope: &mut Scope,
+        call: &ast::CallLoaded,
+        out: &mut String,
+    ) -> Result<Ty, String> {
+        let index = (scope.f as isize + call.fun) as usize;
+        self.function(index)?;
+        let module = self.module;
+        let f = &module.functions[index];
+        out.push_str(&fn_name(&f.name));
+        out.push('(');
+        for (i, (arg, f_arg)) in call.args.iter().zip(f.args.iter()).enumerate() {
+            if i > 0 {
+                out.push_str(", ");
+            }
+            let ty = self.expr(scope, arg, out)?;
+            // Argument types were checked when generating the function.
+            let expected = Ty::from_type(&f_arg.ty).unwrap();
+            if ty != expected {
+                return Err(self.expected(scope, arg.source_range(), expected, ty));
+            }
+        }
+        out.push(')');
+        Ok(Ty::from_type(&f.ret).unwrap())
+    }
+
+    /// Writes calls to external functions.
+    fn intrinsic(
+        &mut self,
+        scope: &mut Scope,
+        info: &ast::CallInfo,
+        args: &[&ast::Expression],
+        out: &mut String,
+    ) -> Result<Ty, String> {
+        let mut a: Vec<(String, Ty)> = vec![];
+        for arg in args {
+            let mut s = String::new();
+            let ty = self.expr(scope, arg, &mut s)?;
+            a.push((s, ty));
+        }
+        let tys: Vec<Ty> = a.iter().map(|x| x.1).collect();
+        let name: &str = &info.name;
+
+        let (code, ty) = match (name, &tys[..]) {
+            ("add", &[x, y]) | ("sub", &[x, y]) | ("mul", &[x, y]) | ("div", &[x, y]) => {
+                let ty = match (x, y) {
+                    (Ty::Mat4, Ty::Vec4) if name == "mul" => Ty::Vec4,
+                    (Ty::Mat4, Ty::Mat4) => Ty::Mat4,
+                    (Ty::Vec4, Ty::Vec4) | (Ty::Vec4, Ty::Float) | (Ty::Float, Ty::Vec4) => {
+                        Ty::Vec4
+                    }
+                    (Ty::Float, Ty::Float) => Ty::Float,
+                    _ => return Err(self.unsupported(scope, info, &tys)),
+                };
+                let op = match name {
+                    "add" => "+",
+                    "sub" => "-",
+                    "mul" => "*",
+                    _ => "/",
+                };
+                (format!("({} {} {})", a[0].0, op, a[1].0), ty)
+            }
+            ("rem", &[x, Ty::Float]) if x == Ty::Float || x == Ty::Vec4 => {
+                (format!("mod({}, {})", a[0].0, a[1].0), x)
+            }
+            ("pow", &[Ty::Float, Ty::Float]) | ("pow", &[Ty::Vec4, Ty::Vec4]) => {
+                (format!("pow({}, {})", a[0].0, a[1].0), tys[0])
+            }
+            ("pow", &[Ty::Vec4, Ty::Float]) => {
+                (format!("pow({}, vec4({}))", a[0].0, a[1].0), Ty::Vec4)
+            }
+            ("less", &[Ty::Float, Ty::Float])
+            | ("less_or_equal", &[Ty::Float, Ty::Float])
+            | ("greater", &[Ty::Float, Ty::Float])
+            | ("greater_or_equal", &[Ty::Float, Ty::Float]) => {
+                let op = match name {
+                    "less" => "<",
+                    "less_or_equal" => "<=",
+                    "greater" => ">",
+                    _ => ">=",
+                };
+                (format!("({} {} {})", a[0].0, op, a[1].0), Ty::Bool)
+            }
+            ("equal", &[x, y]) | ("not_equal", &[x, y]) if x == y && x != Ty::Void => {
+                let op = if name == "equal" { "==" } else { "!=" };
+                (format!("({} {} {})", a[0].0, op, a[1].0), Ty::Bool)
+            }
+            ("and_also", &[Ty::Bool, Ty::Bool]) => {
+                (format!("({} && {})", a[0].0, a[1].0), Ty::Bool)
+            }
+            ("or_else", &[Ty::Bool, Ty::Bool]) => (format!("({} || {})", a[0].0, a[1].0), Ty::Bool),
+            ("dot", &[Ty::Vec4, Ty::Vec4]) => (format!("dot({}, {})", a[0].0, a[1].0), Ty::Float),
+            ("cross", &[Ty::Vec4, Ty::Vec4]) => (
+                format!("vec4(cross({}.xyz, {}.xyz), 0.0)", a[0].0, a[1].0),
+                Ty::Vec4,
+            ),
+            ("neg", &[x]) if x == Ty::Float || x == Ty::Vec4 || x == Ty::Mat4 => {
+                (format!("-{}", a[0].0), x)
+            }
+            // GLSL has value semantics, so cloning is not needed.
+            ("clone", &[x]) if x != Ty::Void => (a[0].0.clone(), x),
+            ("not", &[Ty::Bool]) => (format!("!{}", a[0].0), Ty::Bool),
+            ("norm", &[Ty::Vec4]) => (format!("length({})", a[0].0), Ty::Float),
+            ("sqrt", &[Ty::Float])
+            | ("sin", &[Ty::Float])
+            | ("asin", &[Ty::Float])
+            | ("cos", &[Ty::Float])
+            | ("acos", &[Ty::Float])
+            | ("tan", &[Ty::Float])
+            | ("atan", &[Ty::Float])
+            | ("exp", &[Ty::Float])
+            | ("log2", &[Ty::Float])
+            | ("round", &[Ty::Float])
+            | ("abs", &[Ty::Float])
+            | ("floor", &[Ty::Float])
+            | ("ceil", &[Ty::Float]) => (format!("{}({})", name, a[0].0), Ty::Float),
+            ("ln", &[Ty::Float]) => (format!("log({})", a[0].0), Ty::Float),
+            ("log10", &[Ty::Float]) => (format!("(log({}) / log(10.0))", a[0].0), Ty::Float),
+            ("atan2", &[Ty::Float, Ty::Float]) => {
+                (format!("atan({}, {})", a[0].0, a[1].0), Ty::Float)
+            }
+            ("is_nan", &[Ty::Float]) => (format!("isnan({})", a[0].0), Ty::Bool),
+            ("tau", &[]) => (float(::std::f64::consts::TAU), Ty::Float),
+            ("dir__angle", &[Ty::Float]) => (
+                format!("vec4(cos({}), sin({}), 0.0, 0.0)", a[0].0, a[0].0),
+                Ty::Vec4,
+            ),
+            ("x", &[Ty::Vec4]) | ("y", &[Ty::Vec4]) | ("z", &[Ty::Vec4]) | ("w", &[Ty::Vec4]) => {
+                (format!("{}.{}", a[0].0, name), Ty::Float)
+            }
+            ("s", &[Ty::Vec4, Ty::Float]) => (format!("{}[int({})]", a[0].0, a[1].0), Ty::Float),
+            ("det", &[Ty::Mat4]) => (format!("determinant({})", a[0].0), Ty::Float),
+            ("inv", &[Ty::Mat4]) => (format!("inverse({})", a[0].0), Ty::Mat4),
+            ("cx", &[Ty::Mat4]) => (format!("{}[0]", a[0].0), Ty::Vec4),
+            ("cy", &[Ty::Mat4]) => (format!("{}[1]", a[0].0), Ty::Vec4),
+            ("cz", &[Ty::Mat4]) => (format!("{}[2]", a[0].0), Ty::Vec4),
+            ("cw", &[Ty::Mat4]) => (format!("{}[3]", a[0].0), Ty::Vec4),
+            ("cv", &[Ty::Mat4, Ty::Float]) => (format!("{}[int({})]", a[0].0, a[1].0), Ty::Vec4),
+            ("rx", &[Ty::Mat4]) => (format!("transpose({})[0]", a[0].0), Ty::Vec4),
+            ("ry", &[Ty::Mat4]) => (format!("transpose({})[1]", a[0].0), Ty::Vec4),
+            ("rz", &[Ty::Mat4]) => (format!("transpose({})[2]", a[0].0), Ty::Vec4),
+            ("rw", &[Ty::Mat4]) => (format!("transpose({})[3]", a[0].0), Ty::Vec4),
+            ("rv", &[Ty::Mat4, Ty::Float]) => {
+                (format!("transpose({})[int({})]", a[0].0, a[1].0), Ty::Vec4)
+            }
+            _ => return Err(self.unsupported(scope, info, &tys)),
+        };
+        out.push_str(&code);
+        Ok(ty)
+    }
+
+    fn unsupported(&self, scope: &Scope, info: &ast::CallInfo, tys: &[Ty]) -> String {
+        let tys: Vec<&str> = tys.iter().map(|ty| ty.name()).collect();
+        self.err(
+            scope,
+            info.source_range,
+            &format!(
+                "GLSL: No equivalent for `{}({})`",
+                info.name,
+                tys.join(", ")
+            ),
+        )
+    }
+}
@@ -0,0 +1,816 @@
+use piston_meta::json;
+use range::Range;
+use std::sync::Arc;
+
+use ast;
+use Module;
+use Type;
+use Variable;
+
+/// Helper functions for Dyon semantics that JavaScript lacks,
+/// such as component-wise arithmetic on 4D vectors.
+const PRELUDE: &str = r#"const $dyon = {
+    op(a, b, f) {
+        if (Array.isArray(a)) {
+            return Array.isArray(b) ? a.map((x, i) => f(x, b[i])) : a.map((x) => f(x, b));
+        }
+        return Array.isArray(b) ? b.map((x) => f(a, x)) : f(a, b);
+    },
+    add(a, b) { return typeof a === "boolean" ? a || b : $dyon.op(a, b, (x, y) => x + y); },
+    sub(a, b) { return $dyon.op(a, b, (x, y) => x - y); },
+    mul(a, b) { return typeof a === "boolean" ? a && b : $dyon.op(a, b, (x, y) => x * y); },
+    div(a, b) { return $dyon.op(a, b, (x, y) => x / y); },
+    rem(a, b) { return $dyon.op(a, b, (x, y) => x % y); },
+    pow(a, b) { return $dyon.op(a, b, Math.pow); },
+    neg(a) { return Array.isArray(a) ? a.map((x) => -x) : -a; },
+    dot(a, b) {
+        const v = (x) => Array.isArray(x) ? x : [x, x, x, x];
+        return v(a).reduce((s, x, i) => s + x * v(b)[i], 0);
+    },
+    cross(a, b) {
+        return [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0], 0];
+    },
+    norm(a) { return Math.hypot(...a); },
+    eq(a, b) {
+        if (a === b) { return true; }
+        if (typeof a !== "object" || typeof b !== "object") { return false; }
+        const ka = Object.keys(a);
+        return ka.length === Object.keys(b).length && ka.every((k) => $dyon.eq(a[k], b[k]));
+    },
+    clone(a) {
+        if (Array.isArray(a)) { return a.map($dyon.clone); }
+        if (typeof a === "object") {
+            const o = {};
+            for (const k in a) { o[k] = $dyon.clone(a[k]); }
+            return o;
+        }
+        return a;
+    },
+    fill(a, n) { return Array.from({length: n}, () => $dyon.clone(a)); },
+    vec4(...a) { return a.concat([0, 0, 0, 0]).slice(0, 4); },
+    swizzle(v, ids) { return ids.map((i) => v[i]); },
+    swap(a, i, j) { [a[i], a[j]] = [a[j], a[i]]; },
+    has(a, key) { return Object.prototype.hasOwnProperty.call(a, key); },
+    dir_angle(a) { return [Math.cos(a), Math.sin(a), 0, 0]; },
+    typeof(a) {
+        if (Array.isArray(a)) { return "array"; }
+        return {number: "number", boolean: "boolean", string: "string", function: "closure"}[typeof a] || "object";
+    },
+    json(a) {
+        if (Array.isArray(a)) { return "[" + a.map($dyon.json).join(", ") + "]"; }
+        if (typeof a === "object") {
+            return "{" + Object.keys(a).map((k) => JSON.stringify(k) + ": " + $dyon.json(a[k])).join(", ") + "}";
+        }
+        return JSON.stringify(a);
+    },
+    str(a) { return typeof a === "string" ? a : $dyon.json(a); },
+    line: "",
+    print(a) { $dyon.line += $dyon.str(a); },
+    println(a) { console.log($dyon.line + $dyon.str(a)); $dyon.line = ""; },
+};
+"#;
+
+const RESERVED: &[&str] = &[
+    "arguments",
+    "await",
+    "case",
+    "catch",
+    "class",
+    "const",
+    "debugger",
+    "default",
+    "delete",
+    "do",
+    "enum",
+    "eval",
+    "export",
+    "extends",
+    "finally",
+    "function",
+    "import",
+    "instanceof",
+    "let",
+    "new",
+    "null",
+    "super",
+    "switch",
+    "this",
+    "throw",
+    "typeof",
+    "undefined",
+    "var",
+    "void",
+    "with",
+    "yield",
+];
+
+/// Converts a Dyon name to a valid JavaScript identifier.
+fn ident(name: &str) -> String {
+    let name = match name.find('(') {
+        // Remove mutability information, e.g. `foo(mut,_)`.
+        Some(i) => &name[..i],
+        None => name,
+    };
+    if RESERVED.contains(&name) {
+        format!("{}$", name)
+    } else {
+        name.into()
+    }
+}
+
+fn string(text: &str) -> String {
+    let mut w: Vec<u8> = vec![];
+    json::write_string(&mut w, text).unwrap();
+    String::from_utf8(w).unwrap()
+}
+
+fn number(v: f64) -> String {
+    if v.is_nan() {
+        "NaN".into()
+    } else if v.is_infinite() {
+        if v > 0.0 {
+            "Infinity".into()
+        } else {
+            "-Infinity".into()
+        }
+    } else {
+        format!("{}", v)
+    }
+}
+
+fn write_tabs(out: &mut String, tabs: u32) {
+    for _ in 0..tabs {
+        out.push_str("    ");
+    }
+}
+
+/// Where the value of a statement goes.
+enum Target {
+    /// The value is ignored.
+    Discard,
+    /// The value is returned.
+    Return,
+    /// The value is put between a prefix and a suffix.
+    Apply(String, String),
+}
+
+struct Generator<'a> {
+    module: &'a Module,
+    /// The index of current function.
+    f: usize,
+    /// Used to generate unique names for temporary variables.
+    tmp: usize,
+    /// The number of expressions wrapped in functions that are being generated.
+    wrapped: usize,
+    /// Declared local variables in each block.
+    scopes: Vec<Vec<Arc<String>>>,
+}
+
+pub(crate) fn generate(module: &Module) -> Result<String, String> {
+    let mut out = String::from(PRELUDE);
+    let mut g = Generator {
+        module,
+        f: 0,
+        tmp: 0,
+        wrapped: 0,
+        scopes: vec![],
+    };
+    for i in 0..module.functions.len() {
+        out.push('\n');
+        g.function(i, &mut out)?;
+    }
+    Ok(out)
+}
+
+impl<'a> Generator<'a> {
+    fn err(&self, range: Range, msg: &str) -> String {
+        self.module
+            .error_fnindex(range, &format!("JS: {}", msg), self.f)
+    }
+
+    fn tmp(&mut self, name: &str) -> String {
+        self.tmp += 1;
+        format!("${}{}", name, self.tmp)
+    }
+
+    fn function(&mut self, index: usize, out: &mut String) -> Result<(), String> {
+        let module = self.module;
+        let f = &module.functions[index];
+        self.f = index;
+        self.tmp = 0;
+        if !f.currents.is_empty() {
+            return Err(self.err(f.source_range, "Current objects are not supported"));
+        }
+        out.push_str(&format!("function {}(", ident(&f.name)));
+        let mut scope = vec![];
+        for (i, arg) in f.args.iter().enumerate() {
+            if i > 0 {
+                out.push_str(", ");
+            }
+            out.push_str(&ident(&arg.name));
+            scope.push(arg.name.clone());
+        }
+        out.push_str(") ");
+        let target = if f.ret == Type::Void {
+            Target::Discard
+        } else {
+            Target::Return
+        };
+        self.scopes.push(scope);
+        self.block_body(&f.block, &target, 0, out)?;
+        self.scopes.pop();
+        out.push('\n');
+        Ok(())
+    }
+
+    fn block(
+        &mut self,
+        block: &ast::Block,
+        target: &Target,
+        tabs: u32,
+        out: &mut String,
+    ) -> Result<(), String> {
+        self.scopes.push(vec![]);
+        let res = self.block_body(block, target, tabs, out);
+        self.scopes.pop();
+        res
+    }
+
+    /// Writes a block without creating a new scope.
+    fn block_body(
+        &mut self,
+        block: &ast::Block,
+        target: &Target,
+        tabs: u32,
+        out: &mut String,
+    ) -> Result<(), String> {
+        out.push_str("{\n");
+        for (i, expr) in block.expressions.iter().enumerate() {
+            write_tabs(out, tabs + 1);
+            if i + 1 == block.expressions.len() {
+                self.stmt(expr, target, tabs + 1, out)?;
+            } else {
+                self.stmt(expr, &Target::Discard, tabs + 1, out)?;
+            }
+            out.push('\n');
+        }
+        write_tabs(out, tabs);
+        out.push('}');
+        Ok(())
+    }
+
+    fn apply(&self, target: &Target, value: &str, out: &mut String) {
+        match *target {
+            Target::Discard => out.push_str(&format!("{};", value)),
+            Target::Return => out.push_str(&format!("return {};", value)),
+            Target::Apply(ref prefix, ref suffix) => {
+                out.push_str(&format!("{}{}{}", prefix, value, suffix))
+            }
+        }
+    }
+
+    fn label(&self, label: &Option<Arc<String>>, out: &mut String) {
+        if let Some(ref label) = *label {
+            out.push_str(&format!("${}: ", label));
+        }
+    }
+
+    fn stmt(
+        &mut self,
+        expr: &ast::Expression,
+        target: &Target,
+        tabs: u32,
+        out: &mut String,
+    ) -> Result<(), String> {
+        use ast::Expression as E;
+
+        match *expr {
+            E::Assign(ref assign) => {
+                let code = self.assign(assign)?;
+                out.push_str(&code);
+                out.push(';');
+            }
+            E::If(ref if_expr) => {
+                out.push_str("if (");
+                out.push_str(&self.expr(&if_expr.cond)?);
+                out.push_str(") ");
+                self.block(&if_expr.true_block, target, tabs, out)?;
+                for (cond, block) in if_expr
+                    .else_if_conds
+                    .iter()
+                    .zip(if_expr.else_if_blocks.iter())
+                {
+                    out.push_str(" else if (");
+                    out.push_str(&self.expr(cond)?);
+                    out.push_str(") ");
+                    self.block(block, target, tabs, out)?;
+                }
+                if let Some(ref block) = if_expr.else_block {
+                    out.push_str(" else ");
+                    self.block(block, target, tabs, out)?;
+                }
+            }
+            E::Block(ref block) => self.block(block, target, tabs, out)?,
+            E::For(ref for_expr) => self.for_stmt(for_expr, tabs, out)?,
+            E::ForN(ref for_n) => {
+                self.label(&for_n.label, out);
+                self.for_n_head(for_n, out)?;
+                self.for_n_block(for_n, &Target::Discard, tabs, out)?;
+            }
+            E::Return(ref ret) => {
+                if self.wrapped > 0 {
+                    return Err(self.err(
+                        expr.source_range(),
+                        "Return inside an expression is not supported",
+                    ));
+                }
+                let value = self.expr(ret)?;
+                out.push_str(&format!("return {};", value));
+            }
+            E::ReturnVoid(ref range) => {
+                if self.wrapped > 0 {
+                    return Err(self.err(**range, "Return inside an expression is not supported"));
+                }
+                out.push_str("return;");
+            }
+            E::Break(ref br) => match br.label {
+                Some(ref label) => out.push_str(&format!("break ${};", label)),
+                None => out.push_str("break;"),
+            },
+            E::Continue(ref c) => match c.label {
+                Some(ref label) => out.push_str(&format!("continue ${};", label)),
+                None => out.push_str("continue;"),
+            },
+            _ => {
+                let value = self.expr(expr)?;
+                self.apply(target, &value, out);
+            }
+        }
+        Ok(())
+    }
+
+    fn assign(&mut self, assign: &ast::Assign) -> Result<String, String> {
+        use ast::AssignOp;
+
+        let mut right = self.expr(&assign.right)?;
+        match (&assign.op, &assign.right) {
+            (&AssignOp::Assign, &ast::Expression::Item(_))
+            | (&AssignOp::Set, &ast::Expression::Item(_)) => {
+                // Dyon has value semantics for arrays and objects.
+                right = format!("$dyon.clone({})", right);
+            }
+            _ => {}
+        }
+        if assign.op == AssignOp::Assign {
+            let name = match assign.left {
+                ast::Expression::Item(ref item) if item.ids.is_empty() => item.name.clone(),
+                _ => {
+                    return Err(self.err(assign.left.source_range(), "Expected local variable name"))
+                }
+            };
+            let scope = self.scopes.last_mut().unwrap();
+            return Ok(if scope.contains(&name) {
+                format!("{} = {}", ident(&name), right)
+            } else {
+                scope.push(name.clone());
+                format!("let {} = {}", ident(&name), right)
+            });
+        }
+        let left = self.expr(&assign.left)?;
+        Ok(match assign.op {
+            AssignOp::Assign => unreachable!(),
+            AssignOp::Set => format!("{} = {}", left, right),
+            AssignOp::Add => format!("{} = $dyon.add({}, {})", left, left, right),
+            AssignOp::Sub => format!("{} = $dyon.sub({}, {})", left, left, right),
+            AssignOp::Mul => format!("{} = $dyon.mul({}, {})", left, left, right),
+            AssignOp::Div => format!("{} = $dyon.div({}, {})", left, left, right),
+            AssignOp::Rem => format!("{} = $dyon.rem({}, {})", left, left, right),
+            AssignOp::Pow => format!("{} = $dyon.pow({}, {})", left, left, right),
+        })
+    }
+
+    fn for_stmt(&mut self, for_expr: &ast::For, tabs: u32, out: &mut String) -> Result<(), String> {
+        use ast::Expression as E;
+
+        self.label(&for_expr.label, out);
+        self.scopes.push(vec![]);
+        out.push_str("for (");
+        match for_expr.init {
+            E::Block(ref b) if b.expressions.is_empty() => {}
+            E::Assign(ref assign) => {
+                let code = self.assign(assign)?;
+                out.push_str(&code);
+            }
+            ref expr => out.push_str(&self.expr(expr)?),
+        }
+        out.push(';');
+        match for_expr.cond {
+            E::Variable(ref range_var) if matches!(range_var.1, Variable::Bool(true, _)) => {}
+            ref cond => {
+                out.push(' ');
+                out.push_str(&self.expr(cond)?);
+            }
+        }
+        out.push(';');
+        match for_expr.step {
+            E::Block(ref b) if b.expressions.is_empty() => {}
+            E::Assign(ref assign) => {
+                out.push(' ');
+                let code = self.assign(assign)?;
+                out.push_str(&code);
+            }
+            ref expr => {
+                out.push(' ');
+                out.push_str(&self.expr(expr)?);
+            }
+        }
+        out.push_str(") ");
+        self.block(&for_expr.block, &Target::Discard, tabs, out)?;
+        self.scopes.pop();
+        Ok(())
+    }
+
+    fn for_n_head(&mut self, for_n: &ast::ForN, out: &mut String) -> Result<(), String> {
+        let start = match for_n.start {
+            Some(ref start) => self.expr(start)?,
+            None => "0".into(),
+        };
+        // The end is evaluated once, like in Dyon.
+        let end_name = self.tmp("end");
+        let end = self.expr(&for_n.end)?;
+        let name = ident(&for_n.name);
+        out.push_str(&format!(
+            "for (let {} = {}, {} = {}; {} < {}; {}++) ",
+            name, start, end_name, end, name, end_name, name
+        ));
+        Ok(())
+    }
+
+    fn for_n_block(
+        &mut self,
+        for_n: &ast::ForN,
+        target: &Target,
+        tabs: u32,
+        out: &mut String,
+    ) -> Result<(), String> {
+        self.scopes.push(vec![for_n.name.clone()]);
+        let res = self.block(&for_n.block, target, tabs, out);
+        self.scopes.pop();
+        res
+    }
+
+    /// Wraps statements in a function that is called immediately,
+    /// such that they can be used as an expression.
+    ///
+    /// The statements are written with one level of indention.
+    fn wrap<F>(&mut self, f: F) -> Result<String, String>
+    where
+        F: FnOnce(&mut Self, &mut String) -> Result<(), String>,
+    {
+        let mut out = String::from("(() => {\n");
+        self.wrapped += 1;
+        self.scopes.push(vec![]);
+        let res = f(self, &mut out);
+        self.scopes.pop();
+        self.wrapped -= 1;
+        res?;
+        out.push_str("\n})()");
+        Ok(out)
+    }
+
+    /// Writes a loop that computes a value, e.g. `sum i { ... }`.
+    ///
+    /// The `{}` in the update and the result is replaced by a temporary variable.
+    fn compute_loop(
+        &mut self,
+        for_n: &ast::ForN,
+        init: Option<&str>,
+        update: (&str, &str),
+        done: &str,
+    ) -> Result<String, String> {
+        let res = self.tmp("res");
+        let target = Target::Apply(update.0.replace("{}", &res), update.1.replace("{}", &res));
+        let init = init.map(|init| format!("    let {} = {};\n", res, init));
+        let done = done.replace("{}", &res);
+        self.wrap(|g, out| {
+            if let Some(ref init) = init {
+                out.push_str(init);
+            }
+            write_tabs(out, 1);
+            g.label(&for_n.label, out);
+            g.for_n_head(for_n, out)?;
+            g.for_n_block(for_n, &target, 1, out)?;
+            out.push_str(&format!("\n    return {};", done));
+            Ok(())
+        })
+    }
+
+    fn expr(&mut self, expr: &ast::Expression) -> Result<String, String> {
+        use ast::Expression as E;
+
+        Ok(match *expr {
+            E::Variable(ref range_var) => match range_var.1 {
+                Variable::F64(v, _) => number(v),
+                Variable::Bool(v, _) => format!("{}", v),
+                Variable::Str(ref text) => string(text),
+                Variable::Vec4(v) => format!(
+                    "[{}, {}, {}, {}]",
+                    number(f64::from(v[0])),
+                    number(f64::from(v[1])),
+                    number(f64::from(v[2])),
+                    number(f64::from(v[3]))
+                ),
+                _ => return Err(self.err(range_var.0, "Value is not supported")),
+            },
+            E::Item(ref item) => self.item(item)?,
+            E::Object(ref obj) => {
+                let mut s = String::from("{");
+                for (i, key_value) in obj.key_values.iter().enumerate() {
+                    if i > 0 {
+                        s.push_str(", ");
+                    }
+                    s.push_str(&string(&key_value.0));
+                    s.push_str(": ");
+                    s.push_str(&self.expr(&key_value.1)?);
+                }
+                s.push('}');
+                s
+            }
+            E::Array(ref arr) => format!("[{}]", self.args(&arr.items)?),
+            E::ArrayFill(ref arr_fill) => format!(
+                "$dyon.fill({}, {})",
+                self.expr(&arr_fill.fill)?,
+                self.expr(&arr_fill.n)?
+            ),
+            E::Vec4(ref vec4) => {
+                let has_swizzle = vec4.args.iter().any(|arg| matches!(*arg, E::Swizzle(_)));
+                if has_swizzle {
+                    format!("$dyon.vec4({})", self.args(&vec4.args)?)
+                } else {
+                    format!("[{}]", self.args(&vec4.args)?)
+                }
+            }
+            E::If(ref if_expr) => self.if_expr(if_expr)?,
+            E::Block(ref block) => {
+                let mut out = String::from("(() => ");
+                self.wrapped += 1;
+                self.scopes.push(vec![]);
+                let res = self.block_body(block, &Target::Return, 0, &mut out);
+                self.scopes.pop();
+                self.wrapped -= 1;
+                res?;
+                out.push_str(")()");
+                out
+            }
+            E::For(_) | E::ForN(_) | E::Assign(_) => self.wrap(|g, out| {
+                write_tabs(out, 1);
+                g.stmt(expr, &Target::Discard, 1, out)
+            })?,
+            E::Sum(ref for_n) => {
+                self.compute_loop(for_n, Some("0"), ("{} = $dyon.add({}, ", ");"), "{}")?
+            }
+            E::Prod(ref for_n) => {
+                self.compute_loop(for_n, Some("1"), ("{} = $dyon.mul({}, ", ");"), "{}")?
+            }
+            E::Min(ref for_n) => {
+                self.compute_loop(for_n, Some("Infinity"), ("{} = Math.min({}, ", ");"), "{}")?
+            }
+            E::Max(ref for_n) => {
+                self.compute_loop(for_n, Some("-Infinity"), ("{} = Math.max({}, ", ");"), "{}")?
+            }
+            E::Any(ref for_n) => {
+                self.compute_loop(for_n, None, ("if (", ") { return true; }"), "false")?
+            }
+            E::All(ref for_n) => {
+                self.compute_loop(for_n, None, ("if (!", ") { return false; }"), "true")?
+            }
+            E::Sift(ref for_n) => self.compute_loop(for_n, Some("[]"), ("{}.push(", ");"), "{}")?,
+            E::Call(ref call) => {
+                self.intrinsic(&call.info, &call.args.iter().collect::<Vec<_>>())?
+            }
+            E::CallVoid(ref call) => {
+                self.intrinsic(&call.info, &call.args.iter().collect::<Vec<_>>())?
+            }
+            E::CallReturn(ref call) => {
+                self.intrinsic(&call.info, &call.args.iter().collect::<Vec<_>>())?
+            }
+            E::CallLazy(ref call) => {
+                self.intrinsic(&call.info, &call.args.iter().collect::<Vec<_>>())?
+            }
+            E::CallBinOp(ref call) => self.intrinsic(&call.info, &[&call.left, &call.right])?,
+            E::CallUnOp(ref call) => self.intrinsic(&call.info, &[&call.arg])?,
+            E::CallLoaded(ref call) => {
+                format!("{}({})", ident(&call.info.name), self.args(&call.args)?)
+            }
+            E::Closure(ref closure) => self.closure(closure)?,
+            E::CallClosure(ref call) => {
+                format!("{}({})", self.item(&call.item)?, self.args(&call.args)?)
+            }
+            E::Grab(ref grab) => self.expr(&grab.expr)?,
+            E::Swizzle(ref swizzle) => {
+                return Err(self.err(
+                    swizzle.source_range,
+                    "Swizzle is only supported as argument",
+                ))
+            }
+            _ => return Err(self.err(expr.source_range(), "Expression is not supported")),
+        })
+    }
+
+    /// Writes arguments separated by comma, spreading swizzled components.
+    fn args(&mut self, args: &[ast::Expression]) -> Result<String, String> {
+        let mut s = String::new();
+        for (i, arg) in args.iter().enumerate() {
+            if i > 0 {
+                s.push_str(", ");
+            }
+            if let ast::Expression::Swizzle(ref sw) = *arg {
+                let mut ids = vec![sw.sw0, sw.sw1];
+                ids.extend(sw.sw2);
+                ids.extend(sw.sw3);
+                s.push_str(&format!(
+                    "...$dyon.swizzle({}, {:?})",
+                    self.expr(&sw.expr)?,
+                    ids
+                ));
+            } else {
+                s.push_str(&self.expr(arg)?);
+            }
+        }
+        Ok(s)
+    }
+
+    fn item(&mut self, item: &ast::Item) -> Result<String, String> {
+        if item.current {
+            return Err(self.err(item.source_range, "Current objects are not supported"));
+        }
+        if item.try || !item.try_ids.is_empty() {
+            return Err(self.err(item.source_range, "`?` is not supported"));
+        }
+        let mut s = ident(&item.name);
+        for id in &item.ids {
+            match *id {
+                ast::Id::String(_, ref prop) => s.push_str(&format!("[{}]", string(prop))),
+                ast::Id::F64(_, ind) => s.push_str(&format!("[{}]", ind)),
+                ast::Id::Expression(ref expr) => s.push_str(&format!("[{}]", self.expr(expr)?)),
+            }
+        }
+        Ok(s)
+    }
+
+    fn if_expr(&mut self, if_expr: &ast::If) -> Result<String, String> {
+        let single = |block: &ast::Block| block.expressions.len() == 1;
+        match if_expr.else_block {
+            Some(ref else_block)
+                if single(else_block)
+                    && single(&if_expr.true_block)
+                    && if_expr.else_if_blocks.iter().all(single) =>
+            {
+                let mut s = format!(
+                    "({} ? {} : ",
+                    self.expr(&if_expr.cond)?,
+                    self.expr(&if_expr.true_block.expressions[0])?
+                );
+                for (cond, block) in if_expr
+                    .else_if_conds
+                    .iter()
+                    .zip(if_expr.else_if_blocks.iter())
+                {
+                    s.push_str(&format!(
+                        "{} ? {} : ",
+                        self.expr(cond)?,
+                        self.expr(&block.expressions[0])?
+                    ));
+                }
+                s.push_str(&self.expr(&else_block.expressions[0])?);
+                s.push(')');
+                Ok(s)
+            }
+            _ => self.wrap(|g, out| {
+                write_tabs(out, 1);
+                g.stmt(
+                    &ast::Expression::If(Box::new(if_expr.clone())),
+                    &Target::Return,
+                    1,
+                    out,
+                )
+            }),
+        }
+    }
+
+    fn closure(&mut self, closure: &ast::Closure) -> Result<String, String> {
+        if !closure.currents.is_empty() {
+            return Err(self.err(closure.source_range, "Current objects are not supported"));
+        }
+        let args: Vec<String> = closure.args.iter().map(|arg| ident(&arg.name)).collect();
+        self.scopes
+            .push(closure.args.iter().map(|arg| arg.name.clone()).collect());
+        // A closure is its own function, so `return` is allowed.
+        let wrapped = self.wrapped;
+        self.wrapped = 0;
+        let body = match closure.expr {
+            ast::Expression::Block(ref block) => {
+                let mut out = String::new();
+                self.block_body(block, &Target::Return, 0, &mut out)
+                    .map(|_| out)
+            }
+            ref expr => self.expr(expr).map(|s| format!("({})", s)),
+        };
+        self.wrapped = wrapped;
+        self.scopes.pop();
+        Ok(format!("(({}) => {})", args.join(", "), body?))
+    }
+
+    /// Writes calls to external functions.
+    fn intrinsic(
+        &mut self,
+        info: &ast::CallInfo,
+        args: &[&ast::Expression],
+    ) -> Result<String, String> {
+        let mut a: Vec<String> = vec![];
+        for arg in args {
+            if let ast::Expression::Swizzle(ref sw) = **arg {
+                return Err(self.err(sw.source_range, "Swizzle is only supported as argument"));
+            }
+            a.push(self.expr(arg)?);
+        }
+        let name = ident(&info.name);
+        let binop = |op: &str| format!("({} {} {})", a[0], op, a[1]);
+        Ok(match (&*name, a.len()) {
+            ("add", 2)
+            | ("sub", 2)
+            | ("mul", 2)
+            | ("div", 2)
+            | ("rem", 2)
+            | ("pow", 2)
+            | ("dot", 2)
+            | ("cross", 2) => format!("$dyon.{}({}, {})", name, a[0], a[1]),
+            ("less", 2) => binop("<"),
+            ("less_or_equal", 2) => binop("<="),
+            ("greater", 2) => binop(">"),
+            ("greater_or_equal", 2) => binop(">="),
+            ("equal", 2) => format!("$dyon.eq({}, {})", a[0], a[1]),
+            ("not_equal", 2) => format!("!$dyon.eq({}, {})", a[0], a[1]),
+            ("and_also", 2) => binop("&&"),
+            ("or_else", 2) => binop("||"),
+            ("neg", 1)
+            | ("norm", 1)
+            | ("clone", 1)
+            | ("str", 1)
+            | ("print", 1)
+            | ("println", 1) => format!("$dyon.{}({})", name, a[0]),
+            ("typeof", 1) => format!("$dyon.typeof({})", a[0]),
+            ("not", 1) => format!("!{}", a[0]),
+            ("sqrt", 1)
+            | ("sin", 1)
+            | ("asin", 1)
+            | ("cos", 1)
+            | ("acos", 1)
+            | ("tan", 1)
+            | ("atan", 1)
+            | ("exp", 1)
+            | ("log2", 1)
+            | ("log10", 1)
+            | ("round", 1)
+            | ("abs", 1)
+            | ("floor", 1)
+            | ("ceil", 1) => format!("Math.{}({})", name, a[0]),
+            ("ln", 1) => format!("Math.log({})", a[0]),
+            ("atan2", 2) => format!("Math.atan2({}, {})", a[0], a[1]),
+            ("min", 1) | ("max", 1) => format!("Math.{}(...{})", name, a[0]),
+            ("random", 0) => "Math.random()".into(),
+            ("tau", 0) => "(2 * Math.PI)".into(),
+            ("now", 0) => "(Date.now() / 1000)".into(),
+            ("is_nan", 1) => format!("isNaN({})", a[0]),
+            ("x", 1) => format!("{}[0]", a[0]),
+            ("y", 1) => format!("{}[1]", a[0]),
+            ("z", 1) => format!("{}[2]", a[0]),
+            ("w", 1) => format!("{}[3]", a[0]),
+            ("s", 2) => format!("{}[{}]", a[0], a[1]),
+            ("dir__angle", 1) => format!("$dyon.dir_angle({})", a[0]),
+            ("json_string", 1) => format!("JSON.stringify({})", a[0]),
+            ("len", 1) => format!("{}.length", a[0]),
+            ("push", 2) | ("push_ref", 2) => format!("{}.push({})", a[0], a[1]),
+            ("pop", 1) => format!("{}.pop()", a[0]),
+            ("insert", 3) | ("insert_ref", 3) => {
+                format!("{}.splice({}, 0, {})", a[0], a[1], a[2])
+            }
+            ("remove", 2) => format!("{}.splice({}, 1)[0]", a[0], a[1]),
+            ("reverse", 1) => format!("{}.reverse()", a[0]),
+            ("clear", 1) => format!("{}.splice(0)", a[0]),
+            ("swap", 3) => format!("$dyon.swap({}, {}, {})", a[0], a[1], a[2]),
+            ("has", 2) => format!("$dyon.has({}, {})", a[0], a[1]),
+            ("keys", 1) => format!("Object.keys({})", a[0]),
+            ("chars", 1) => format!("Array.from({})", a[0]),
+            ("trim", 1) => format!("{}.trim()", a[0]),
+            ("trim_left", 1) => format!("{}.trimStart()", a[0]),
+            ("trim_right", 1) => format!("{}.trimEnd()", a[0]),
+            _ => {
+                return Err(self.err(
+                    info.source_range,
+                    &format!("No equivalent for `{}`", info.name),
+                ))
+            }
+        })
+    }
+}
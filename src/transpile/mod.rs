@@ -0,0 +1,50 @@
+//! Transpilers from Dyon to other languages.
+//!
+//! These support only a subset of Dyon and report an error
+//! when encountering something that has no equivalent in the target language.
+
+use Module;
+use Variable;
+
+mod glsl;
+mod js;
+mod rust_struct;
+
+/// Generates GLSL source for a loaded function.
+///
+/// Loaded functions called by the function are emitted first,
+/// such that the output can be pasted into a shader as it is.
+///
+/// Supports `f64`, `bool`, `vec4` and `mat4` arithmetic,
+/// local variables, `if`, `for` loops and common math intrinsics.
+/// Names that are reserved in GLSL, e.g. `clamp`, get the prefix `dyon_`.
+pub fn to_glsl(module: &Module, name: &str) -> Result<String, String> {
+    glsl::generate(module, name)
+}
+
+/// Generates JavaScript source for all loaded functions in a module.
+///
+/// A small prelude object `$dyon` is emitted first to keep Dyon semantics,
+/// such as component-wise `vec4` arithmetic and value semantics of arrays.
+/// Vectors become arrays of 4 numbers and objects become JavaScript objects.
+///
+/// Current objects, threads, channels, `mat4` and `?` are not supported.
+pub fn to_js(module: &Module) -> Result<String, String> {
+    js::generate(module)
+}
+
+/// Generates Rust struct definitions with Serde derives from a data sample.
+///
+/// Nested objects become separate structs named after the parent and key.
+/// Keys missing in some elements of an array become `Option` fields.
+pub fn to_rust_struct(data: &Variable, name: &str) -> Result<String, String> {
+    rust_struct::generate(data, name)
+}
+
+/// Checks that data matches a Rust struct definition.
+///
+/// The source is a list of structs, as generated by `to_rust_struct`,
+/// and `name` is the struct to check against.
+pub fn check_rust_struct(data: &Variable, source: &str, name: &str) -> Result<(), String> {
+    rust_struct::check_data(data, source, name)
+}
@@ -0,0 +1,530 @@
+use std::sync::Arc;
+
+use Variable;
+
+/// The Rust type of `vec4` and `mat4` components, matching `Scalar`.
+#[cfg(not(feature = "f64_vec4"))]
+const SCALAR: &str = "f32";
+/// The Rust type of `vec4` and `mat4` components, matching `Scalar`.
+#[cfg(feature = "f64_vec4")]
+const SCALAR: &str = "f64";
+
+/// The inferred shape of a data sample.
+#[derive(Clone, PartialEq)]
+enum Shape {
+    /// No information, e.g. elements of an empty array.
+    Unknown,
+    F64,
+    Bool,
+    Str,
+    Vec4,
+    Mat4,
+    Array(Box<Shape>),
+    Option(Box<Shape>),
+    /// Fields sorted by name, with flag for whether the field is always present.
+    Object(Vec<(Arc<String>, Shape, bool)>),
+}
+
+fn shape_of(v: &Variable, path: &str) -> Result<Shape, String> {
+    Ok(match *v {
+        Variable::F64(_, _) => Shape::F64,
+        Variable::Bool(_, _) => Shape::Bool,
+        Variable::Str(_) => Shape::Str,
+        Variable::Vec4(_) => Shape::Vec4,
+        Variable::Mat4(_) => Shape::Mat4,
+        Variable::Array(ref arr) => {
+            let mut item = Shape::Unknown;
+            for (i, it) in arr.iter().enumerate() {
+                let path = format!("{}[{}]", path, i);
+                let shape = shape_of(it, &path)?;
+                item = unify(item, shape, &path)?;
+            }
+            Shape::Array(Box::new(item))
+        }
+        Variable::Option(None) => Shape::Option(Box::new(Shape::Unknown)),
+        Variable::Option(Some(ref v)) => Shape::Option(Box::new(shape_of(v, path)?)),
+        Variable::Object(ref obj) => {
+            let mut fields = vec![];
+            for (key, val) in obj.iter() {
+                let shape = shape_of(val, &format!("{}.{}", path, key))?;
+                fields.push((key.clone(), shape, true));
+            }
+            fields.sort_by(|a, b| a.0.cmp(&b.0));
+            Shape::Object(fields)
+        }
+        ref x => {
+            return Err(format!(
+                "`{}`: Expected data, found `{}`",
+                path,
+                x.typeof_var()
+            ))
+        }
+    })
+}
+
+/// Finds a shape that fits both samples.
+fn unify(a: Shape, b: Shape, path: &str) -> Result<Shape, String> {
+    Ok(match (a, b) {
+        (Shape::Unknown, x) | (x, Shape::Unknown) => x,
+        (Shape::Array(a), Shape::Array(b)) => Shape::Array(Box::new(unify(*a, *b, path)?)),
+        (Shape::Option(a), Shape::Option(b)) => Shape::Option(Box::new(unify(*a, *b, path)?)),
+        (Shape::Option(a), b) | (b, Shape::Option(a)) => {
+            Shape::Option(Box::new(unify(*a, b, path)?))
+        }
+        (Shape::Object(a), Shape::Object(b)) => {
+            let mut fields = vec![];
+            for (key, shape, always) in a {
+                match b.iter().find(|f| f.0 == key) {
+                    Some(&(_, ref b_shape, b_always)) => {
+                        let shape = unify(shape, b_shape.clone(), &format!("{}.{}", path, key))?;
+                        fields.push((key, shape, always && b_always));
+                    }
+                    None => fields.push((key, shape, false)),
+                }
+            }
+            for field in b {
+                if !fields.iter().any(|f| f.0 == field.0) {
+                    fields.push((field.0, field.1, false));
+                }
+            }
+            fields.sort_by(|a, b| a.0.cmp(&b.0));
+            Shape::Object(fields)
+        }
+        (a, b) => {
+            if a == b {
+                a
+            } else {
+                return Err(format!(
+                    "`{}`: Could not find a common type for `{}` and `{}`",
+                    path,
+                    a.name(),
+                    b.name()
+                ));
+            }
+        }
+    })
+}
+
+impl Shape {
+    fn name(&self) -> &'static str {
+        match *self {
+            Shape::Unknown => "any",
+            Shape::F64 => "f64",
+            Shape::Bool => "bool",
+            Shape::Str => "str",
+            Shape::Vec4 => "vec4",
+            Shape::Mat4 => "mat4",
+            Shape::Array(_) => "[]",
+            Shape::Option(_) => "opt",
+            Shape::Object(_) => "{}",
+        }
+    }
+}
+
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "box", "break", "const", "continue", "crate", "dyn", "else", "enum",
+    "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move",
+    "mut", "pub", "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true",
+    "try", "type", "unsafe", "use", "where", "while", "yield",
+];
+
+/// Converts a key to a snake case Rust identifier.
+fn field_name(key: &str) -> String {
+    let mut name = String::new();
+    let mut prev_lower = false;
+    for c in key.chars() {
+        if c.is_uppercase() {
+            if prev_lower {
+                name.push('_');
+            }
+            name.extend(c.to_lowercase());
+            prev_lower = false;
+        } else if c.is_alphanumeric() || c == '_' {
+            name.push(c);
+            prev_lower = c.is_lowercase() || c.is_numeric();
+        } else {
+            name.push('_');
+            prev_lower = false;
+        }
+    }
+    if name.is_empty() || name.starts_with(|c: char| c.is_numeric()) {
+        name.insert(0, '_');
+    }
+    if KEYWORDS.contains(&&*name) {
+        name.push('_');
+    }
+    name
+}
+
+/// Converts a key to a camel case Rust type name.
+fn type_name(key: &str) -> String {
+    let mut name = String::new();
+    let mut upper = true;
+    for c in key.chars() {
+        if c.is_alphanumeric() {
+            if upper {
+                name.extend(c.to_uppercase());
+            } else {
+                name.push(c);
+            }
+            upper = false;
+        } else {
+            upper = true;
+        }
+    }
+    name
+}
+
+struct Generator {
+    structs: Vec<String>,
+}
+
+impl Generator {
+    fn ty(&mut self, shape: &Shape, name: &str) -> String {
+        match *shape {
+            Shape::Unknown => "()".into(),
+            Shape::F64 => "f64".into(),
+            Shape::Bool => "bool".into(),
+            Shape::Str => "String".into(),
+            Shape::Vec4 => format!("[{}; 4]", SCALAR),
+            Shape::Mat4 => format!("[[{}; 4]; 4]", SCALAR),
+            Shape::Array(ref item) => format!("Vec<{}>", self.ty(item, name)),
+            Shape::Option(ref v) => format!("Option<{}>", self.ty(v, name)),
+            Shape::Object(ref fields) => {
+                self.object(fields, name);
+                name.into()
+            }
+        }
+    }
+
+    fn object(&mut self, fields: &[(Arc<String>, Shape, bool)], name: &str) {
+        // Reserve the position to put structs before the structs they contain.
+        let index = self.structs.len();
+        self.structs.push(String::new());
+        let mut s = String::new();
+        s.push_str("#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]\n");
+        s.push_str(&format!("pub struct {} {{\n", name));
+        for &(ref key, ref shape, always) in fields {
+            let field = field_name(key);
+            if field != **key {
+                s.push_str(&format!("    #[serde(rename = {:?})]\n", key));
+            }
+            let mut ty = self.ty(shape, &format!("{}{}", name, type_name(key)));
+            if !always {
+                if let Shape::Option(_) = *shape {
+                } else {
+                    ty = format!("Option<{}>", ty);
+                }
+            }
+            s.push_str(&format!("    pub {}: {},\n", field, ty));
+        }
+        s.push_str("}\n");
+        self.structs[index] = s;
+    }
+}
+
+/// Generates Rust struct definitions from a data sample.
+pub(crate) fn generate(data: &Variable, name: &str) -> Result<String, String> {
+    let shape = shape_of(data, name)?;
+    let fields = match shape {
+        Shape::Object(fields) => fields,
+        x => return Err(format!("`{}`: Expected `{{}}`, found `{}`", name, x.name())),
+    };
+    let mut g = Generator { structs: vec![] };
+    g.object(&fields, &type_name(name));
+    Ok(g.structs.join("\n"))
+}
+
+/// Rust types that data can be checked against.
+#[derive(Debug)]
+enum RustType {
+    Unit,
+    Float,
+    Int { signed: bool },
+    Bool,
+    Str,
+    Array(Box<RustType>, usize),
+    Vec(Box<RustType>),
+    Option(Box<RustType>),
+    Struct(String),
+}
+
+struct Field {
+    name: String,
+    key: String,
+    ty: RustType,
+}
+
+struct Struct {
+    name: String,
+    fields: Vec<Field>,
+}
+
+/// A minimal parser of Rust struct definitions.
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_whitespace(&mut self) {
+        loop {
+            let rest = &self.src[self.pos..];
+            let trimmed = rest.trim_start();
+            self.pos += rest.len() - trimmed.len();
+            if trimmed.starts_with("//") {
+                self.pos += trimmed.find('\n').unwrap_or(trimmed.len());
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn eof(&mut self) -> bool {
+        self.skip_whitespace();
+        self.pos >= self.src.len()
+    }
+
+    fn tag(&mut self, tag: &str) -> bool {
+        self.skip_whitespace();
+        if self.src[self.pos..].starts_with(tag) {
+            self.pos += tag.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, tag: &str) -> Result<(), String> {
+        if self.tag(tag) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("Expected `{}`", tag)))
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, String> {
+        self.skip_whitespace();
+        let rest = &self.src[self.pos..];
+        let n = rest
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        if n == 0 {
+            return Err(self.error("Expected identifier"));
+        }
+        self.pos += n;
+        Ok(rest[..n].into())
+    }
+
+    fn error(&self, msg: &str) -> String {
+        let line = self.src[..self.pos].matches('\n').count() + 1;
+        format!("Rust struct, line {}: {}", line, msg)
+    }
+
+    /// Reads an attribute and returns serde rename, if any.
+    fn attribute(&mut self) -> Result<Option<String>, String> {
+        let start = self.pos;
+        let mut depth = 0;
+        for (i, c) in self.src[start..].char_indices() {
+            match c {
+                '[' => depth += 1,
+                ']' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.pos = start + i + 1;
+                        let attr = &self.src[start..self.pos];
+                        return Ok(attr
+                            .find("rename = \"")
+                            .map(|i| &attr[i + 10..])
+                            .and_then(|s| s.find('"').map(|j| s[..j].into())));
+                    }
+                }
+                _ => {}
+            }
+        }
+        Err(self.error("Expected `]`"))
+    }
+
+    fn ty(&mut self) -> Result<RustType, String> {
+        if self.tag("(") {
+            self.expect(")")?;
+            return Ok(RustType::Unit);
+        }
+        if self.tag("[") {
+            let item = self.ty()?;
+            self.expect(";")?;
+            self.skip_whitespace();
+            let rest = &self.src[self.pos..];
+            let n = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            let len = rest[..n]
+                .parse()
+                .map_err(|_| self.error("Expected array length"))?;
+            self.pos += n;
+            self.expect("]")?;
+            return Ok(RustType::Array(Box::new(item), len));
+        }
+        let name = self.ident()?;
+        Ok(match &*name {
+            "f32" | "f64" => RustType::Float,
+            "i8" | "i16" | "i32" | "i64" | "isize" => RustType::Int { signed: true },
+            "u8" | "u16" | "u32" | "u64" | "usize" => RustType::Int { signed: false },
+            "bool" => RustType::Bool,
+            "String" => RustType::Str,
+            "Vec" | "Option" => {
+                self.expect("<")?;
+                let item = Box::new(self.ty()?);
+                self.expect(">")?;
+                if name == "Vec" {
+                    RustType::Vec(item)
+                } else {
+                    RustType::Option(item)
+                }
+            }
+            _ => RustType::Struct(name),
+        })
+    }
+
+    fn structs(&mut self) -> Result<Vec<Struct>, String> {
+        let mut structs = vec![];
+        while !self.eof() {
+            if self.tag("#") {
+                self.attribute()?;
+                continue;
+            }
+            self.tag("pub");
+            self.expect("struct")?;
+            let name = self.ident()?;
+            self.expect("{")?;
+            let mut fields = vec![];
+            let mut rename = None;
+            while !self.tag("}") {
+                if self.eof() {
+                    return Err(self.error("Expected `}`"));
+                }
+                if self.tag("#") {
+                    rename = self.attribute()?;
+                    continue;
+                }
+                self.tag("pub");
+                let field = self.ident()?;
+                self.expect(":")?;
+                let ty = self.ty()?;
+                if !self.tag(",") {
+                    self.skip_whitespace();
+                    if !self.src[self.pos..].starts_with('}') {
+                        return Err(self.error("Expected `,`"));
+                    }
+                }
+                fields.push(Field {
+                    key: rename.take().unwrap_or_else(|| field.clone()),
+                    name: field,
+                    ty,
+                });
+            }
+            structs.push(Struct { name, fields });
+        }
+        Ok(structs)
+    }
+}
+
+fn check(data: &Variable, ty: &RustType, structs: &[Struct], path: &str) -> Result<(), String> {
+    let expected = |ty: &str| {
+        Err(format!(
+            "`{}`: Expected `{}`, found `{}`",
+            path,
+            ty,
+            data.typeof_var()
+        ))
+    };
+    match (ty, data) {
+        (RustType::Float, Variable::F64(_, _)) => Ok(()),
+        (&RustType::Int { signed }, &Variable::F64(v, _)) => {
+            if v.fract() != 0.0 || (!signed && v < 0.0) {
+                Err(format!(
+                    "`{}`: Expected {}integer, found `{}`",
+                    path,
+                    if signed { "" } else { "non-negative " },
+                    v
+                ))
+            } else {
+                Ok(())
+            }
+        }
+        (RustType::Bool, Variable::Bool(_, _)) => Ok(()),
+        (RustType::Str, Variable::Str(_)) => Ok(()),
+        (RustType::Array(item, 4), Variable::Vec4(_)) => match **item {
+            RustType::Float => Ok(()),
+            _ => expected(&format!("[{}; 4]", SCALAR)),
+        },
+        (RustType::Array(item, 4), Variable::Mat4(_)) => match **item {
+            RustType::Array(ref item, 4) => match **item {
+                RustType::Float => Ok(()),
+                _ => expected(&format!("[[{}; 4]; 4]", SCALAR)),
+            },
+            _ => expected(&format!("[[{}; 4]; 4]", SCALAR)),
+        },
+        (RustType::Array(item, n), Variable::Array(arr)) => {
+            if arr.len() != *n {
+                return Err(format!(
+                    "`{}`: Expected array of length `{}`, found `{}`",
+                    path,
+                    n,
+                    arr.len()
+                ));
+            }
+            for (i, it) in arr.iter().enumerate() {
+                check(it, item, structs, &format!("{}[{}]", path, i))?;
+            }
+            Ok(())
+        }
+        (RustType::Vec(item), Variable::Array(arr)) => {
+            for (i, it) in arr.iter().enumerate() {
+                check(it, item, structs, &format!("{}[{}]", path, i))?;
+            }
+            Ok(())
+        }
+        (RustType::Option(_), Variable::Option(None)) => Ok(()),
+        (RustType::Option(ty), Variable::Option(Some(v))) => check(v, ty, structs, path),
+        (RustType::Struct(name), Variable::Object(obj)) => {
+            let s = match structs.iter().find(|s| &s.name == name) {
+                Some(s) => s,
+                None => return Err(format!("Could not find struct `{}`", name)),
+            };
+            for field in &s.fields {
+                let path = format!("{}.{}", path, field.name);
+                match obj.get(&Arc::new(field.key.clone())) {
+                    Some(v) => check(v, &field.ty, structs, &path)?,
+                    None => {
+                        if let RustType::Option(_) = field.ty {
+                        } else {
+                            return Err(format!("`{}`: Missing key `{}`", path, field.key));
+                        }
+                    }
+                }
+            }
+            Ok(())
+        }
+        (RustType::Unit, _) => expected("()"),
+        (RustType::Float, _) => expected("f64"),
+        (RustType::Int { .. }, _) => expected("integer"),
+        (RustType::Bool, _) => expected("bool"),
+        (RustType::Str, _) => expected("String"),
+        (RustType::Array(_, n), _) => expected(&format!("[_; {}]", n)),
+        (RustType::Vec(_), _) => expected("Vec"),
+        // Serde reads present values of optional fields without `some(_)`.
+        (RustType::Option(ty), _) => check(data, ty, structs, path),
+        (RustType::Struct(name), _) => expected(name),
+    }
+}
+
+/// Checks that data matches a Rust struct definition.
+pub(crate) fn check_data(data: &Variable, source: &str, name: &str) -> Result<(), String> {
+    let structs = Parser {
+        src: source,
+        pos: 0,
+    }
+    .structs()?;
+    check(data, &RustType::Struct(name.into()), &structs, name)
+}
@@ -25,6 +25,10 @@ pub enum Type {
     Str,
     /// Link type.
     Link,
+    /// Binary data type.
+    Bytes,
+    /// Packed array of numbers.
+    F64Array,
     /// Array type.
     Array(Box<Type>),
     /// Object type.
@@ -40,6 +44,8 @@ pub enum Type {
     Thread(Box<Type>),
     /// In-type.
     In(Box<Type>),
+    /// Out-type.
+    Out(Box<Type>),
     /// Ad-hoc type.
     AdHoc(Arc<String>, Box<Type>),
     /// Closure type.
@@ -77,6 +83,8 @@ impl Type {
             Mat4 => "mat4".into(),
             Str => "str".into(),
             Link => "link".into(),
+            Bytes => "bytes".into(),
+            F64Array => "farr".into(),
             Array(ref ty) => {
                 if let Any = **ty {
                     "[]".into()
@@ -134,6 +142,16 @@ impl Type {
                     res
                 }
             }
+            Out(ref ty) => {
+                if let Any = **ty {
+                    "out".into()
+                } else {
+                    let mut res = String::from("out[");
+                    res.push_str(&ty.description());
+                    res.push(']');
+                    res
+                }
+            }
             AdHoc(ref ad, ref ty) => (&**ad).clone() + " " + &ty.description(),
             Closure(ref closure) => {
                 let mut s = String::new();
@@ -182,6 +200,11 @@ impl Type {
         Type::In(Box::new(Type::Any))
     }
 
+    /// Returns an out-type with an `any` as inner type.
+    pub fn out_ty() -> Type {
+        Type::Out(Box::new(Type::Any))
+    }
+
     /// Binds refinement type variables.
     ///
     /// Returns the type argument to compare to.
@@ -291,12 +314,15 @@ impl Type {
             #[cfg(all(not(target_family = "wasm"), feature = "threading"))]
             (&Thread(ref x), &Thread(ref y)) if x.ambiguous(y) => true,
             (&In(ref x), &In(ref y)) if x.ambiguous(y) => true,
+            (&Out(ref x), &Out(ref y)) if x.ambiguous(y) => true,
             (&Bool, &Any) => true,
             (&F64, &Any) => true,
             (&Str, &Any) => true,
             (&Vec4, &Any) => true,
             (&Mat4, &Any) => true,
             (&Link, &Any) => true,
+            (&Bytes, &Any) => true,
+            (&F64Array, &Any) => true,
             (&Array(_), &Any) => true,
             (&Option(_), &Any) => true,
             (&Result(_), &Any) => true,
@@ -304,6 +330,7 @@ impl Type {
             (&Thread(_), &Any) => true,
             (&Secret(_), &Any) => true,
             (&In(_), &Any) => true,
+            (&Out(_), &Any) => true,
             _ => false,
         }
     }
@@ -399,6 +426,13 @@ impl Type {
                     false
                 }
             }
+            &Out(ref out_ty) => {
+                if let Out(ref other_ty) = *other {
+                    out_ty.goes_with(other_ty)
+                } else {
+                    matches!(*other, Any)
+                }
+            }
             &Closure(ref cl) => {
                 if let Closure(ref other_cl) = *other {
                     if cl.tys.len() != other_cl.tys.len() {
@@ -500,6 +534,12 @@ impl Type {
             } else if let Ok((range, _)) = convert.meta_bool("link") {
                 convert.update(range);
                 ty = Some(Type::Link);
+            } else if let Ok((range, _)) = convert.meta_bool("bytes") {
+                convert.update(range);
+                ty = Some(Type::Bytes);
+            } else if let Ok((range, _)) = convert.meta_bool("farr") {
+                convert.update(range);
+                ty = Some(Type::F64Array);
             } else if let Ok((range, _)) = convert.meta_bool("opt_any") {
                 convert.update(range);
                 ty = Some(Type::Option(Box::new(Type::Any)));
@@ -515,6 +555,9 @@ impl Type {
             } else if let Ok((range, _)) = convert.meta_bool("in_any") {
                 convert.update(range);
                 ty = Some(Type::In(Box::new(Type::Any)));
+            } else if let Ok((range, _)) = convert.meta_bool("out_any") {
+                convert.update(range);
+                ty = Some(Type::Out(Box::new(Type::Any)));
             } else if let Ok((range, val)) = Type::from_meta_data("opt", convert, ignored) {
                 convert.update(range);
                 ty = Some(Type::Option(Box::new(val)));
@@ -527,6 +570,9 @@ impl Type {
             } else if let Ok((range, val)) = Type::from_meta_data("in", convert, ignored) {
                 convert.update(range);
                 ty = Some(Type::In(Box::new(val)));
+            } else if let Ok((range, val)) = Type::from_meta_data("out", convert, ignored) {
+                convert.update(range);
+                ty = Some(Type::Out(Box::new(val)));
             } else if let Ok((range, val)) = convert.meta_string("ad_hoc") {
                 convert.update(range);
                 let inner_ty =
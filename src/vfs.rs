@@ -0,0 +1,610 @@
+//! Virtual file systems, controlling what scripts can access.
+//!
+//! All file functions in the standard library go through the `Vfs` of the runtime.
+//! By default, this is `OsFs`, which passes paths through to the operating system.
+//!
+//! - `MemoryFs` keeps files in memory, which is useful for tests and sandboxes
+//! - `RestrictedFs` gives access to a single directory on disk, like `chroot`
+//!
+//! ```
+//! use std::sync::Arc;
+//! use dyon::Runtime;
+//! use dyon::vfs::MemoryFs;
+//!
+//! let fs = MemoryFs::new();
+//! fs.insert("data/hello.txt", "Hello world!");
+//! let mut rt = Runtime::new();
+//! rt.set_vfs(Arc::new(fs));
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Cursor, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Information about a file or directory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Metadata {
+    /// Whether the path is a directory.
+    pub is_dir: bool,
+    /// The length of the file in bytes.
+    pub len: u64,
+}
+
+/// Implemented by file systems that scripts can access.
+pub trait Vfs: Send + Sync {
+    /// Opens a file for reading.
+    fn open(&self, path: &str) -> io::Result<Box<dyn Read>>;
+    /// Creates or truncates a file for writing.
+    fn create(&self, path: &str) -> io::Result<Box<dyn Write>>;
+    /// Creates a file for writing, failing if it already exists.
+    fn create_new(&self, path: &str) -> io::Result<Box<dyn Write>> {
+        if self.metadata(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "File already exists",
+            ));
+        }
+        self.create(path)
+    }
+    /// Writes a file, such that it contains either the old or the new data.
+    fn write_atomic(&self, path: &str, data: &[u8]) -> io::Result<()> {
+        let mut f = self.create(path)?;
+        f.write_all(data)?;
+        f.flush()
+    }
+    /// Returns the sorted names of the entries in a directory.
+    fn read_dir(&self, path: &str) -> io::Result<Vec<String>>;
+    /// Creates a directory and its missing parents.
+    fn create_dir(&self, path: &str) -> io::Result<()>;
+    /// Removes a file.
+    fn remove_file(&self, path: &str) -> io::Result<()>;
+    /// Removes a directory with its contents.
+    fn remove_dir(&self, path: &str) -> io::Result<()>;
+    /// Returns information about a file or directory.
+    fn metadata(&self, path: &str) -> io::Result<Metadata>;
+    /// Returns the directory for temporary files.
+    fn temp_dir(&self) -> io::Result<String> {
+        Err(unsupported("Temporary files are not supported"))
+    }
+    /// Returns the path on disk, for functions that need an operating system file,
+    /// e.g. memory mapping and file locks.
+    fn os_path(&self, _path: &str) -> io::Result<PathBuf> {
+        Err(unsupported("File is not stored on disk"))
+    }
+    /// Returns `true` if files on disk can be memory mapped.
+    ///
+    /// A mapped file that is truncated by another process crashes the program when read,
+    /// so by default, `mmap` reads from the file instead.
+    fn allow_mmap(&self) -> bool {
+        false
+    }
+    /// Reads a file to a string.
+    fn read_to_string(&self, path: &str) -> io::Result<String> {
+        let mut s = String::new();
+        self.open(path)?.read_to_string(&mut s)?;
+        Ok(s)
+    }
+}
+
+fn unsupported(msg: &str) -> io::Error {
+    io::Error::other(msg)
+}
+
+/// Passes paths through to the file system of the operating system.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OsFs;
+
+impl Vfs for OsFs {
+    fn open(&self, path: &str) -> io::Result<Box<dyn Read>> {
+        Ok(Box::new(File::open(path)?))
+    }
+
+    fn create(&self, path: &str) -> io::Result<Box<dyn Write>> {
+        Ok(Box::new(BufWriter::new(File::create(path)?)))
+    }
+
+    fn create_new(&self, path: &str) -> io::Result<Box<dyn Write>> {
+        let f = OpenOptions::new().write(true).create_new(true).open(path)?;
+        Ok(Box::new(BufWriter::new(f)))
+    }
+
+    #[cfg(all(not(target_family = "wasm"), feature = "file"))]
+    fn write_atomic(&self, path: &str, data: &[u8]) -> io::Result<()> {
+        ::dyon_std::io::write_atomic(path, |f| f.write_all(data))
+    }
+
+    fn read_dir(&self, path: &str) -> io::Result<Vec<String>> {
+        let mut names = vec![];
+        for entry in fs::read_dir(path)? {
+            names.push(entry?.file_name().to_string_lossy().into_owned());
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    fn create_dir(&self, path: &str) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn remove_file(&self, path: &str) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn remove_dir(&self, path: &str) -> io::Result<()> {
+        fs::remove_dir_all(path)
+    }
+
+    fn metadata(&self, path: &str) -> io::Result<Metadata> {
+        let m = fs::metadata(path)?;
+        Ok(Metadata {
+            is_dir: m.is_dir(),
+            len: m.len(),
+        })
+    }
+
+    fn temp_dir(&self) -> io::Result<String> {
+        Ok(::std::env::temp_dir().to_string_lossy().into_owned())
+    }
+
+    fn os_path(&self, path: &str) -> io::Result<PathBuf> {
+        Ok(PathBuf::from(path))
+    }
+
+    fn allow_mmap(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Default)]
+struct MemoryState {
+    files: BTreeMap<String, Vec<u8>>,
+    dirs: BTreeSet<String>,
+}
+
+impl MemoryState {
+    fn is_dir(&self, path: &str) -> bool {
+        path.is_empty() || self.dirs.contains(path)
+    }
+
+    fn parent_is_dir(&self, path: &str) -> bool {
+        match path.rfind('/') {
+            Some(i) => self.is_dir(&path[..i]),
+            None => true,
+        }
+    }
+}
+
+/// Stores files in memory.
+///
+/// Paths are relative to the root, e.g. `/a/b.txt` is the same file as `a/b.txt`.
+/// Clones share the same files, so the host can inspect what a script wrote.
+/// Temporary files are not supported unless a directory is set with `with_temp_dir`.
+#[derive(Clone, Default)]
+pub struct MemoryFs {
+    state: Arc<Mutex<MemoryState>>,
+    temp_dir: Option<String>,
+}
+
+impl MemoryFs {
+    /// Creates a new empty file system.
+    pub fn new() -> MemoryFs {
+        MemoryFs::default()
+    }
+
+    /// Adds a file, creating its parent directories.
+    pub fn insert<T: Into<Vec<u8>>>(&self, path: &str, data: T) {
+        let path = normalize(path);
+        let mut state = self.state();
+        let mut i = 0;
+        while let Some(j) = path[i..].find('/') {
+            state.dirs.insert(path[..i + j].into());
+            i += j + 1;
+        }
+        state.files.insert(path, data.into());
+    }
+
+    /// Sets the directory for temporary files, which is created when needed.
+    pub fn with_temp_dir(mut self, path: &str) -> MemoryFs {
+        self.temp_dir = Some(normalize(path));
+        self
+    }
+
+    /// Returns the data of a file.
+    pub fn get(&self, path: &str) -> Option<Vec<u8>> {
+        self.state().files.get(&normalize(path)).cloned()
+    }
+
+    fn state(&self) -> ::std::sync::MutexGuard<'_, MemoryState> {
+        // The state is valid even if a thread panicked while holding the lock.
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn create_file(&self, path: &str, new: bool) -> io::Result<Box<dyn Write>> {
+        let path = normalize(path);
+        let mut state = self.state();
+        if state.is_dir(&path) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Path is a directory",
+            ));
+        }
+        if !state.parent_is_dir(&path) {
+            return Err(not_found());
+        }
+        if new && state.files.contains_key(&path) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "File already exists",
+            ));
+        }
+        state.files.insert(path.clone(), vec![]);
+        Ok(Box::new(MemoryWriter {
+            fs: self.clone(),
+            path,
+            data: vec![],
+        }))
+    }
+}
+
+impl Vfs for MemoryFs {
+    fn open(&self, path: &str) -> io::Result<Box<dyn Read>> {
+        match self.state().files.get(&normalize(path)) {
+            Some(data) => Ok(Box::new(Cursor::new(data.clone()))),
+            None => Err(not_found()),
+        }
+    }
+
+    fn create(&self, path: &str) -> io::Result<Box<dyn Write>> {
+        self.create_file(path, false)
+    }
+
+    fn create_new(&self, path: &str) -> io::Result<Box<dyn Write>> {
+        self.create_file(path, true)
+    }
+
+    fn write_atomic(&self, path: &str, data: &[u8]) -> io::Result<()> {
+        // Files are only visible when the writer is flushed, so this is atomic.
+        let mut f = self.create(path)?;
+        f.write_all(data)?;
+        f.flush()
+    }
+
+    fn read_dir(&self, path: &str) -> io::Result<Vec<String>> {
+        let path = normalize(path);
+        let state = self.state();
+        if !state.is_dir(&path) {
+            return Err(not_found());
+        }
+        let prefix = if path.is_empty() {
+            path
+        } else {
+            format!("{}/", path)
+        };
+        let children = state
+            .dirs
+            .iter()
+            .chain(state.files.keys())
+            .filter_map(|p| p.strip_prefix(&*prefix))
+            .filter(|name| !name.contains('/'));
+        let mut names: Vec<String> = children.map(|name| name.to_string()).collect();
+        names.sort();
+        Ok(names)
+    }
+
+    fn create_dir(&self, path: &str) -> io::Result<()> {
+        let path = normalize(path);
+        let mut state = self.state();
+        let mut end = 0;
+        while end < path.len() {
+            end = path[end..].find('/').map(|i| end + i).unwrap_or(path.len());
+            if state.files.contains_key(&path[..end]) {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "Path is a file",
+                ));
+            }
+            state.dirs.insert(path[..end].into());
+            end += 1;
+        }
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &str) -> io::Result<()> {
+        match self.state().files.remove(&normalize(path)) {
+            Some(_) => Ok(()),
+            None => Err(not_found()),
+        }
+    }
+
+    fn remove_dir(&self, path: &str) -> io::Result<()> {
+        let path = normalize(path);
+        let mut state = self.state();
+        if path.is_empty() || !state.dirs.remove(&path) {
+            return Err(not_found());
+        }
+        let prefix = format!("{}/", path);
+        state.dirs.retain(|p| !p.starts_with(&prefix));
+        state.files.retain(|p, _| !p.starts_with(&prefix));
+        Ok(())
+    }
+
+    fn metadata(&self, path: &str) -> io::Result<Metadata> {
+        let path = normalize(path);
+        let state = self.state();
+        if let Some(data) = state.files.get(&path) {
+            Ok(Metadata {
+                is_dir: false,
+                len: data.len() as u64,
+            })
+        } else if state.is_dir(&path) {
+            Ok(Metadata {
+                is_dir: true,
+                len: 0,
+            })
+        } else {
+            Err(not_found())
+        }
+    }
+
+    fn temp_dir(&self) -> io::Result<String> {
+        self.temp_dir
+            .clone()
+            .ok_or_else(|| unsupported("Temporary files are not supported"))
+    }
+}
+
+/// Writes to a file in memory, storing the data when flushed or dropped.
+struct MemoryWriter {
+    fs: MemoryFs,
+    path: String,
+    data: Vec<u8>,
+}
+
+impl Write for MemoryWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.data.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut state = self.fs.state();
+        // Do not recreate the file if it was removed.
+        if let Some(file) = state.files.get_mut(&self.path) {
+            file.clone_from(&self.data);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for MemoryWriter {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+fn not_found() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "No such file or directory")
+}
+
+/// Normalizes a path in memory, e.g. `/a/./b/../c` becomes `a/c`.
+fn normalize(path: &str) -> String {
+    let mut parts: Vec<&str> = vec![];
+    for part in path.split(['/', '\\']) {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+/// Gives access to a single directory on disk, like `chroot`.
+///
+/// Paths are relative to the root directory, e.g. `/data.txt` is `data.txt` in the root.
+/// Paths that escape the root by `..` are rejected, and so are paths with symbolic links.
+/// On Unix, files are opened relative to the root without following symbolic links,
+/// such that a link created after checking the path can not escape the root.
+/// Other operations, e.g. `read_dir` and `remove_file`, check the path before using it.
+///
+/// Temporary files are not supported unless a directory is set with `with_temp_dir`.
+#[derive(Clone, Debug)]
+pub struct RestrictedFs {
+    root: PathBuf,
+    temp_dir: Option<String>,
+}
+
+impl RestrictedFs {
+    /// Creates a new file system with a root directory.
+    pub fn new<P: Into<PathBuf>>(root: P) -> RestrictedFs {
+        RestrictedFs {
+            root: root.into(),
+            temp_dir: None,
+        }
+    }
+
+    /// Sets the directory for temporary files, relative to the root.
+    pub fn with_temp_dir(mut self, path: &str) -> RestrictedFs {
+        self.temp_dir = Some(path.into());
+        self
+    }
+
+    /// Returns the root directory.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns the path relative to the root, without `.` and `..`.
+    fn relative(&self, path: &str) -> io::Result<PathBuf> {
+        let mut rel = PathBuf::new();
+        for c in Path::new(path).components() {
+            match c {
+                Component::Normal(c) => rel.push(c),
+                Component::RootDir | Component::CurDir => {}
+                Component::ParentDir => {
+                    if !rel.pop() {
+                        return Err(outside_root());
+                    }
+                }
+                Component::Prefix(_) => return Err(outside_root()),
+            }
+        }
+        Ok(rel)
+    }
+
+    fn resolve(&self, path: &str) -> io::Result<PathBuf> {
+        let rel = self.relative(path)?;
+        // Symbolic links might point outside the root, even when dangling,
+        // so reject every existing component that is a link.
+        let mut full = self.root.clone();
+        for c in rel.components() {
+            full.push(c);
+            match fs::symlink_metadata(&full) {
+                Ok(meta) if meta.file_type().is_symlink() => return Err(symlink_denied()),
+                Ok(_) => {}
+                // The rest of the path does not exist.
+                Err(ref err) if err.kind() == io::ErrorKind::NotFound => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(self.root.join(rel))
+    }
+
+    /// Opens a file by walking from the root one directory at a time,
+    /// failing on symbolic links instead of following them.
+    #[cfg(all(unix, feature = "file"))]
+    fn open_beneath(&self, path: &str, flags: ::libc::c_int) -> io::Result<File> {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::io::{AsRawFd, FromRawFd};
+
+        let rel = self.relative(path)?;
+        let mut components = rel.components().peekable();
+        if components.peek().is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Path is a directory",
+            ));
+        }
+        let nofollow = ::libc::O_NOFOLLOW | ::libc::O_CLOEXEC;
+        let mut file = File::open(&self.root)?;
+        while let Some(c) = components.next() {
+            let name = CString::new(c.as_os_str().as_bytes())
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Invalid nul in path"))?;
+            let flags = if components.peek().is_none() {
+                flags | nofollow
+            } else {
+                ::libc::O_RDONLY | ::libc::O_DIRECTORY | nofollow
+            };
+            let fd = unsafe { ::libc::openat(file.as_raw_fd(), name.as_ptr(), flags, 0o666) };
+            if fd < 0 {
+                let err = io::Error::last_os_error();
+                return Err(match err.raw_os_error() {
+                    Some(::libc::ELOOP) => symlink_denied(),
+                    _ => err,
+                });
+            }
+            file = unsafe { File::from_raw_fd(fd) };
+        }
+        Ok(file)
+    }
+}
+
+fn symlink_denied() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        "Path contains a symbolic link",
+    )
+}
+
+fn outside_root() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        "Path is outside of the root directory",
+    )
+}
+
+/// Converts a resolved path to a string for `OsFs`.
+fn os_str(path: &Path) -> io::Result<&str> {
+    path.to_str()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid unicode in path"))
+}
+
+impl Vfs for RestrictedFs {
+    #[cfg(all(unix, feature = "file"))]
+    fn open(&self, path: &str) -> io::Result<Box<dyn Read>> {
+        Ok(Box::new(self.open_beneath(path, ::libc::O_RDONLY)?))
+    }
+
+    #[cfg(not(all(unix, feature = "file")))]
+    fn open(&self, path: &str) -> io::Result<Box<dyn Read>> {
+        OsFs.open(os_str(&self.resolve(path)?)?)
+    }
+
+    #[cfg(all(unix, feature = "file"))]
+    fn create(&self, path: &str) -> io::Result<Box<dyn Write>> {
+        let flags = ::libc::O_WRONLY | ::libc::O_CREAT | ::libc::O_TRUNC;
+        Ok(Box::new(BufWriter::new(self.open_beneath(path, flags)?)))
+    }
+
+    #[cfg(not(all(unix, feature = "file")))]
+    fn create(&self, path: &str) -> io::Result<Box<dyn Write>> {
+        OsFs.create(os_str(&self.resolve(path)?)?)
+    }
+
+    #[cfg(all(unix, feature = "file"))]
+    fn create_new(&self, path: &str) -> io::Result<Box<dyn Write>> {
+        let flags = ::libc::O_WRONLY | ::libc::O_CREAT | ::libc::O_EXCL;
+        Ok(Box::new(BufWriter::new(self.open_beneath(path, flags)?)))
+    }
+
+    #[cfg(not(all(unix, feature = "file")))]
+    fn create_new(&self, path: &str) -> io::Result<Box<dyn Write>> {
+        OsFs.create_new(os_str(&self.resolve(path)?)?)
+    }
+
+    fn write_atomic(&self, path: &str, data: &[u8]) -> io::Result<()> {
+        OsFs.write_atomic(os_str(&self.resolve(path)?)?, data)
+    }
+
+    fn read_dir(&self, path: &str) -> io::Result<Vec<String>> {
+        OsFs.read_dir(os_str(&self.resolve(path)?)?)
+    }
+
+    fn create_dir(&self, path: &str) -> io::Result<()> {
+        OsFs.create_dir(os_str(&self.resolve(path)?)?)
+    }
+
+    fn remove_file(&self, path: &str) -> io::Result<()> {
+        OsFs.remove_file(os_str(&self.resolve(path)?)?)
+    }
+
+    fn remove_dir(&self, path: &str) -> io::Result<()> {
+        let full = self.resolve(path)?;
+        if full == self.root {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Can not remove the root directory",
+            ));
+        }
+        OsFs.remove_dir(os_str(&full)?)
+    }
+
+    fn metadata(&self, path: &str) -> io::Result<Metadata> {
+        OsFs.metadata(os_str(&self.resolve(path)?)?)
+    }
+
+    fn temp_dir(&self) -> io::Result<String> {
+        self.temp_dir
+            .clone()
+            .ok_or_else(|| unsupported("Temporary files are not supported"))
+    }
+
+    fn os_path(&self, path: &str) -> io::Result<PathBuf> {
+        self.resolve(path)
+    }
+}
@@ -1,4 +1,5 @@
 use ast;
+use dyon_std::bigint::BigInt;
 use piston_meta::json;
 use std::io;
 use Runtime;
@@ -12,6 +13,19 @@ pub(crate) enum EscapeString {
     None,
 }
 
+/// Keeps track of nesting to detect cycles and limit depth.
+struct Visit {
+    /// Stack indices of references being written.
+    refs: Vec<usize>,
+    depth: usize,
+    max_depth: Option<usize>,
+    /// Whether a cycle is an error instead of being written as `<cycle>`.
+    cycle_err: bool,
+}
+
+/// Writes a variable.
+///
+/// A reference to a value that is being written is written as `<cycle>`.
 pub(crate) fn write_variable<W>(
     w: &mut W,
     rt: &Runtime,
@@ -19,6 +33,116 @@ pub(crate) fn write_variable<W>(
     escape_string: EscapeString,
     tabs: u32,
 ) -> Result<(), io::Error>
+where
+    W: io::Write,
+{
+    let mut visit = Visit {
+        refs: vec![],
+        depth: 0,
+        max_depth: None,
+        cycle_err: false,
+    };
+    write_var(w, rt, v, escape_string, tabs, &mut visit)
+}
+
+/// Writes a variable, replacing values nested deeper than `max_depth` with `...`.
+///
+/// A reference to a value that is being written is written as `<cycle>`.
+pub(crate) fn write_variable_max_depth<W>(
+    w: &mut W,
+    rt: &Runtime,
+    v: &Variable,
+    escape_string: EscapeString,
+    tabs: u32,
+    max_depth: usize,
+) -> Result<(), io::Error>
+where
+    W: io::Write,
+{
+    let mut visit = Visit {
+        refs: vec![],
+        depth: 0,
+        max_depth: Some(max_depth),
+        cycle_err: false,
+    };
+    write_var(w, rt, v, escape_string, tabs, &mut visit)
+}
+
+/// Writes a variable as data that can be loaded again.
+///
+/// Returns an error when the variable contains a cycle.
+pub(crate) fn write_data<W>(
+    w: &mut W,
+    rt: &Runtime,
+    v: &Variable,
+    tabs: u32,
+) -> Result<(), io::Error>
+where
+    W: io::Write,
+{
+    let mut visit = Visit {
+        refs: vec![],
+        depth: 0,
+        max_depth: None,
+        cycle_err: true,
+    };
+    write_var(w, rt, v, EscapeString::Json, tabs, &mut visit)
+}
+
+fn write_var<W>(
+    w: &mut W,
+    rt: &Runtime,
+    v: &Variable,
+    escape_string: EscapeString,
+    tabs: u32,
+    visit: &mut Visit,
+) -> Result<(), io::Error>
+where
+    W: io::Write,
+{
+    match *v {
+        Variable::Link(_)
+        | Variable::Object(_)
+        | Variable::Array(_)
+        | Variable::Option(Some(_))
+        | Variable::Result(_) => {
+            if let Some(max_depth) = visit.max_depth {
+                if visit.depth >= max_depth {
+                    return write!(w, "...");
+                }
+            }
+            visit.depth += 1;
+            let res = write_nested(w, rt, v, escape_string, tabs, visit);
+            visit.depth -= 1;
+            res
+        }
+        Variable::Ref(ind) => {
+            if visit.refs.contains(&ind) {
+                if visit.cycle_err {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Can not write a value that contains itself",
+                    ));
+                }
+                return write!(w, "<cycle>");
+            }
+            visit.refs.push(ind);
+            let res = write_var(w, rt, &rt.stack[ind], escape_string, tabs, visit);
+            visit.refs.pop();
+            res
+        }
+        _ => write_nested(w, rt, v, escape_string, tabs, visit),
+    }
+}
+
+fn write_nested<W>(
+    w: &mut W,
+    rt: &Runtime,
+    v: &Variable,
+    escape_string: EscapeString,
+    tabs: u32,
+    visit: &mut Visit,
+) -> Result<(), io::Error>
 where
     W: io::Write,
 {
@@ -70,9 +194,7 @@ where
         Variable::Bool(x, _) => {
             write!(w, "{}", x)?;
         }
-        Variable::Ref(ind) => {
-            write_variable(w, rt, &rt.stack[ind], escape_string, tabs)?;
-        }
+        Variable::Ref(_) => unreachable!(),
         Variable::Link(ref link) => {
             match escape_string {
                 EscapeString::Json => {
@@ -81,7 +203,7 @@ where
                     for slice in &link.slices {
                         for i in slice.start..slice.end {
                             let v = slice.block.var(i);
-                            write_variable(w, rt, &v, EscapeString::Json, tabs)?;
+                            write_var(w, rt, &v, EscapeString::Json, tabs, visit)?;
                             write!(w, " ")?;
                         }
                     }
@@ -91,7 +213,7 @@ where
                     for slice in &link.slices {
                         for i in slice.start..slice.end {
                             let v = slice.block.var(i);
-                            write_variable(w, rt, &v, EscapeString::None, tabs)?;
+                            write_var(w, rt, &v, EscapeString::None, tabs, visit)?;
                         }
                     }
                 }
@@ -107,7 +229,7 @@ where
                     json::write_string(w, k)?;
                     write!(w, ": ")?;
                 }
-                write_variable(w, rt, v, EscapeString::Json, tabs)?;
+                write_var(w, rt, v, EscapeString::Json, tabs, visit)?;
                 if i + 1 < n {
                     write!(w, ", ")?;
                 }
@@ -118,7 +240,7 @@ where
             write!(w, "[")?;
             let n = arr.len();
             for (i, v) in arr.iter().enumerate() {
-                write_variable(w, rt, v, EscapeString::Json, tabs)?;
+                write_var(w, rt, v, EscapeString::Json, tabs, visit)?;
                 if i + 1 < n {
                     write!(w, ", ")?;
                 }
@@ -129,19 +251,19 @@ where
             None => write!(w, "none()")?,
             Some(ref v) => {
                 write!(w, "some(")?;
-                write_variable(w, rt, v, EscapeString::Json, tabs)?;
+                write_var(w, rt, v, EscapeString::Json, tabs, visit)?;
                 write!(w, ")")?;
             }
         },
         Variable::Result(ref res) => match *res {
             Err(ref err) => {
                 write!(w, "err(")?;
-                write_variable(w, rt, &err.message, EscapeString::Json, tabs)?;
+                write_var(w, rt, &err.message, EscapeString::Json, tabs, visit)?;
                 write!(w, ")")?;
             }
             Ok(ref ok) => {
                 write!(w, "ok(")?;
-                write_variable(w, rt, ok, EscapeString::Json, tabs)?;
+                write_var(w, rt, ok, EscapeString::Json, tabs, visit)?;
                 write!(w, ")")?;
             }
         },
@@ -149,9 +271,30 @@ where
         Variable::Thread(_) => write!(w, "_thread")?,
         Variable::Return => write!(w, "_return")?,
         Variable::UnsafeRef(_) => write!(w, "_unsafe_ref")?,
-        Variable::RustObject(_) => write!(w, "_rust_object")?,
+        Variable::RustObject(ref obj) => match obj.lock().unwrap().downcast_ref::<BigInt>() {
+            Some(x) => write!(w, "{}", x)?,
+            None => write!(w, "_rust_object")?,
+        },
         Variable::Closure(ref closure, _) => write_closure(w, rt, closure, tabs)?,
+        Variable::Bytes(ref bytes) => {
+            write!(w, "bytes(\"")?;
+            for b in bytes.iter() {
+                write!(w, "{:02x}", b)?;
+            }
+            write!(w, "\")")?;
+        }
+        Variable::F64Array(ref arr) => {
+            write!(w, "farr([")?;
+            for (i, x) in arr.iter().enumerate() {
+                if i > 0 {
+                    write!(w, ", ")?;
+                }
+                write!(w, "{}", x)?;
+            }
+            write!(w, "])")?;
+        }
         Variable::In(_) => write!(w, "_in")?,
+        Variable::Out(_) => write!(w, "_out")?,
         // ref x => panic!("Could not print out `{:?}`", x)
     }
     Ok(())
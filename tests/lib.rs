@@ -1,13 +1,16 @@
 extern crate dyon;
 extern crate piston_meta;
+#[cfg(feature = "python")]
+extern crate pyo3;
 
 use dyon::*;
 
-pub fn test_src(source: &str) {
+pub fn test_src(source: &str) -> Module {
     let mut module = Module::new();
     load(source, &mut module).unwrap_or_else(|err| {
         panic!("In `{}`:\n{}", source, err);
     });
+    module
 }
 
 pub fn test_fail_src(source: &str) {
@@ -144,6 +147,10 @@ fn test_syntax() {
     test_src("source/syntax/lazy_pass_6.dyon");
     test_src("source/syntax/lazy_pass_7.dyon");
     test_src("source/syntax/lazy_pass_8.dyon");
+    test_src("source/syntax/version.dyon");
+    test_fail_src("source/syntax/version_fail_1.dyon");
+    test_fail_src("source/syntax/version_fail_2.dyon");
+    test_src("source/syntax/cfg.dyon");
 }
 
 #[test]
@@ -279,6 +286,102 @@ fn test_typechk() {
 #[test]
 fn test_functions() {
     test_src("source/functions/functions.dyon");
+    test_src("source/functions/str_max_depth.dyon");
+    test_src("source/functions/weak.dyon");
+    test_src("source/functions/persistent.dyon");
+    test_src("source/functions/string_builder.dyon");
+    test_src("source/functions/text_buffer.dyon");
+    test_src("source/functions/sort.dyon");
+    test_src("source/functions/map_filter_fold.dyon");
+    test_src("source/functions/object.dyon");
+    test_src("source/functions/string.dyon");
+    test_src("source/functions/regex.dyon");
+    test_src("source/functions/random.dyon");
+}
+
+#[test]
+fn test_transpile() {
+    test_src("source/transpile/glsl.dyon");
+    test_src("source/transpile/rust_struct.dyon");
+    test_src("source/transpile/js.dyon");
+
+    let mut module = Module::new();
+    load("source/transpile/js.dyon", &mut module).unwrap();
+    let js = transpile::to_js(&module).unwrap();
+    assert!(js.contains("function damage(hp, hits) {"));
+    let module = test_src("source/transpile/glsl.dyon");
+    let glsl = transpile::to_glsl(&module, "shade").unwrap();
+    // The user function `clamp` is renamed to not collide with the GLSL built-in.
+    assert_eq!(
+        glsl,
+        "\
+float dyon_clamp(float x) {
+    return ((x < 0.0) ? 0.0 : ((x > 1.0) ? 1.0 : x));
+}
+
+float brightness(vec4 color) {
+    return dyon_clamp(dot(color, vec4(0.299, 0.587, 0.114, 0.0)));
+}
+
+vec4 shade(vec4 pos, vec4 light, float t) {
+    float d = length((light - pos));
+    float fade = (1.0 / (1.0 + (d * d)));
+    float wave = ((sin(((pos.x * 10.0) + t)) * 0.5) + 0.5);
+    vec4 color = vec4(fade, (fade * wave), 0.2, 1.0);
+    for (float i = 0.0; i < 3.0; i += 1.0) {
+        color *= 0.9;
+    }
+    if ((brightness(color) < 0.1)) {
+        return vec4(0.0, 0.0, 0.0, 1.0);
+    }
+    return color;
+}
+"
+    );
+    let glsl = transpile::to_glsl(&module, "half").unwrap();
+    assert!(glsl.contains("float dyon_half(float dyon_dyon_x) {"));
+    assert!(glsl.contains("float dyon_sample = (dyon_dyon_x * 0.5);"));
+    assert!(transpile::to_glsl(&module, "greet").is_err());
+
+    let module = std::sync::Arc::new(test_src("source/transpile/rust_struct.dyon"));
+    let data = Runtime::new().call_str_ret("player", &[], &module).unwrap();
+    let src = transpile::to_rust_struct(&data, "player").unwrap();
+    // Vectors use the same precision as `Scalar`.
+    let scalar = if cfg!(feature = "f64_vec4") {
+        "f64"
+    } else {
+        "f32"
+    };
+    assert_eq!(
+        src,
+        format!(
+            "\
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Player {{
+    pub items: Vec<PlayerItems>,
+    pub name: String,
+    pub pos: [{}; 4],
+    pub stats: PlayerStats,
+}}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PlayerItems {{
+    pub count: Option<f64>,
+    pub id: f64,
+    pub tag: Option<String>,
+}}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PlayerStats {{
+    pub hp: f64,
+    #[serde(rename = \"max HP\")]
+    pub max_hp: f64,
+}}
+",
+            scalar
+        )
+    );
+    assert!(transpile::check_rust_struct(&data, &src, "Player").is_ok());
 }
 
 #[test]
@@ -291,3 +394,2731 @@ fn test_error() {
     test_src("source/error/unwrap_err.dyon");
     test_src("source/error/option.dyon");
 }
+
+#[cfg(feature = "capi")]
+#[test]
+fn test_capi_header() {
+    // Copy the generated header to `include/dyon.h` when the C API changes.
+    let generated = include_str!(concat!(env!("OUT_DIR"), "/dyon.h"));
+    let header = include_str!("../include/dyon.h");
+    assert!(
+        header == generated,
+        "`include/dyon.h` is out of date, copy it from `{}/dyon.h`",
+        env!("OUT_DIR")
+    );
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn test_python_convert() {
+    use dyon::python::{from_py, to_py};
+    use pyo3::prelude::*;
+    use pyo3::types::PyBytes;
+    use std::sync::Arc;
+
+    Python::initialize();
+    Python::attach(|py| {
+        let round_trip = |v: &Variable| from_py(to_py(py, v).unwrap().bind(py)).unwrap();
+
+        let bytes = Variable::Bytes(Arc::new(vec![0, 1, 255]));
+        assert!(to_py(py, &bytes)
+            .unwrap()
+            .bind(py)
+            .is_instance_of::<PyBytes>());
+        assert_eq!(round_trip(&bytes), bytes);
+        let obj = Variable::Object(Arc::new(
+            vec![(Arc::new("data".to_string()), bytes.clone())]
+                .into_iter()
+                .collect(),
+        ));
+        assert_eq!(round_trip(&obj), obj);
+
+        // Converting `farr` requires numpy, which is installed on CI.
+        let np = match py.import("numpy") {
+            Ok(np) => np,
+            Err(_) => return,
+        };
+        let farr = Variable::F64Array(Arc::new(vec![0.5, -2.0, 1e300]));
+        assert_eq!(round_trip(&farr), farr);
+        let matrix = np
+            .call_method1("array", (vec![vec![1.0, 2.0], vec![3.0, 4.0]],))
+            .unwrap();
+        assert_eq!(
+            from_py(&matrix).unwrap(),
+            Variable::Array(Arc::new(vec![
+                Variable::F64Array(Arc::new(vec![1.0, 2.0])),
+                Variable::F64Array(Arc::new(vec![3.0, 4.0])),
+            ]))
+        );
+    });
+}
+
+#[cfg(feature = "capi")]
+#[test]
+fn test_capi_call() {
+    use dyon::capi::*;
+    use std::ffi::{CStr, CString};
+    use std::ptr;
+
+    let c = |s: &str| CString::new(s).unwrap();
+    unsafe {
+        let module = dyon_module_new();
+        let name = c("test.dyon");
+        let source = c("fn plus(a: f64, b: f64) -> f64 { return a + b }");
+        assert_eq!(dyon_load_str(module, name.as_ptr(), source.as_ptr()), 0);
+        let runtime = dyon_runtime_new();
+        let a = dyon_var_f64(2.0);
+        let b = dyon_var_f64(3.0);
+        let args = [a as *const _, b as *const _];
+        let plus = c("plus");
+        let res = dyon_runtime_call_ret(runtime, module, plus.as_ptr(), args.as_ptr(), 2);
+        assert!(!res.is_null());
+        let mut val = 0.0;
+        assert_eq!(dyon_var_as_f64(res, &mut val), 0);
+        assert_eq!(val, 5.0);
+        assert_eq!(dyon_var_as_bool(res, &mut false), -1);
+        assert!(!dyon_last_error().is_null());
+
+        let missing = c("missing");
+        assert!(dyon_runtime_call_ret(runtime, module, missing.as_ptr(), ptr::null(), 0).is_null());
+
+        let bad = c("fn main() { x := }");
+        dyon_clear_error();
+        assert_eq!(dyon_load_str(module, name.as_ptr(), bad.as_ptr()), -1);
+        let err = CStr::from_ptr(dyon_last_error()).to_str().unwrap();
+        assert!(err.contains("test.dyon"));
+
+        for var in &[a, b, res] {
+            dyon_var_free(*var);
+        }
+        dyon_runtime_free(runtime);
+        dyon_module_free(module);
+    }
+}
+
+#[test]
+fn test_kernel() {
+    use dyon::kernel::{Display, Kernel};
+
+    let mut kernel = Kernel::new();
+    assert!(kernel.eval("a := 2").unwrap().value.is_none());
+    kernel
+        .eval("fn double(x: f64) -> f64 { return 2 * x }")
+        .unwrap();
+    kernel.eval("a += 1\nb := double(a)").unwrap();
+    let out = kernel.eval("display_html(\"<b>hi</b>\")\na + b").unwrap();
+    assert_eq!(out.value, Some(Variable::f64(9.0)));
+    assert_eq!(out.display, vec![Display::Html("<b>hi</b>".into())]);
+    let out = kernel
+        .eval("display_png([137, 80])\ndisplay_png(bytes_from_str(\"PNG\"))")
+        .unwrap();
+    assert_eq!(
+        out.display,
+        vec![Display::Png(vec![137, 80]), Display::Png(b"PNG".to_vec())]
+    );
+    assert!(kernel.eval("display_png(\"PNG\")").is_err());
+    assert!(kernel.eval("println(b)").unwrap().value.is_none());
+    assert!(kernel.eval("c := d").is_err());
+    assert_eq!(kernel.scope().len(), 2);
+
+    let interrupt = kernel.interrupt_handle();
+    let handle = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        interrupt.interrupt();
+    });
+    let err = kernel.eval("loop {}").unwrap_err();
+    assert!(err.contains("Interrupted"));
+    handle.join().unwrap();
+    assert_eq!(kernel.eval("a").unwrap().value, Some(Variable::f64(3.0)));
+}
+
+#[cfg(feature = "file")]
+#[test]
+fn test_literate() {
+    let doc = literate::render("source/literate/tutorial.md").unwrap();
+    assert!(doc.contains("distance(speed, time)\n```\n\n```text\n6\n```\n"));
+    assert!(doc.contains("\n<p>Done!</p>\n"));
+
+    let mut kernel = kernel::Kernel::new();
+    let err = literate::render_str("test.md", "```dyon\nx := y\n```\n", &mut kernel).unwrap_err();
+    assert!(err.starts_with("In `test.md`, block at line 1:"));
+}
+
+#[test]
+fn test_str_cycle() {
+    use std::sync::Arc;
+
+    let module = Arc::new(Module::new());
+    let mut rt = Runtime::new();
+    // An array containing a reference to itself.
+    rt.stack.push(Variable::Array(Arc::new(vec![
+        Variable::f64(1.0),
+        Variable::Ref(0),
+    ])));
+    let s = rt
+        .call_str_ret("str", &[Variable::Ref(0)], &module)
+        .unwrap();
+    assert_eq!(rt.var::<String>(&s).unwrap(), "[1, <cycle>]");
+}
+
+#[test]
+fn test_gc() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/gc/graph.dyon"));
+    let mut rt = Runtime::with_gc();
+    let freed = rt.call_str_ret("drop_pair", &[], &module).unwrap();
+    assert_eq!(freed, Variable::f64(2.0));
+    let sum = rt.call_str_ret("keep_nodes", &[], &module).unwrap();
+    assert_eq!(sum, Variable::f64(1247500.0));
+    let name = rt.call_str_ret("closure_keeps", &[], &module).unwrap();
+    assert_eq!(name, Variable::Str(Arc::new("kept".into())));
+    assert!(Runtime::new().run(&module).is_err());
+}
+
+#[test]
+fn test_bytecode() {
+    use std::sync::Arc;
+
+    let mut module = Module::new();
+    load("source/bytecode/numeric.dyon", &mut module).unwrap();
+    let interpreted = Arc::new(module.clone());
+    assert_eq!(module.compile(), 6);
+    let compiled = Arc::new(module);
+    let calls = vec![
+        ("fib", vec![Variable::f64(15.0)]),
+        ("collatz", vec![Variable::f64(27.0)]),
+        ("primes", vec![Variable::f64(500.0)]),
+        ("lazy", vec![Variable::bool(true), Variable::f64(3.0)]),
+        ("lazy", vec![Variable::bool(false), Variable::f64(-1.0)]),
+        ("lazy", vec![Variable::bool(false), Variable::f64(1.0)]),
+        ("roots", vec![Variable::f64(10.0)]),
+        ("fact", vec![Variable::f64(10.0)]),
+        (
+            "mixed",
+            vec![Variable::Array(Arc::new(vec![Variable::f64(1.0)]))],
+        ),
+        ("calls_untyped", vec![Variable::f64(1.0)]),
+    ];
+    let mut rt = Runtime::new();
+    for (name, args) in calls {
+        let a = rt.call_str_ret(name, &args, &interpreted).unwrap();
+        let b = rt.call_str_ret(name, &args, &compiled).unwrap();
+        assert_eq!(a, b, "{}", name);
+    }
+}
+
+#[test]
+fn test_persistent() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/persistent.dyon"));
+    let mut rt = Runtime::new();
+    let ok = rt
+        .call_str_ret("check", &[Variable::f64(2000.0)], &module)
+        .unwrap();
+    assert_eq!(ok, Variable::bool(true));
+}
+
+#[test]
+fn test_text_buffer() {
+    use std::sync::Arc;
+
+    // Edits spanning many chunks.
+    let module = Arc::new(test_src("source/functions/text_buffer.dyon"));
+    let mut rt = Runtime::new();
+    let ok = rt
+        .call_str_ret("check", &[Variable::f64(5000.0)], &module)
+        .unwrap();
+    assert_eq!(ok, Variable::bool(true));
+}
+
+#[test]
+#[cfg(feature = "file")]
+fn test_data_writer() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/data_writer.dyon"));
+    let file = std::env::temp_dir().join("dyon_test_data_writer.dyon");
+    let file = Variable::Str(Arc::new(file.to_str().unwrap().into()));
+    let mut rt = Runtime::new();
+    let data = rt
+        .call_str_ret("write", &[file, Variable::f64(1000.0)], &module)
+        .unwrap();
+    match rt.resolve(&data) {
+        &Variable::Array(ref arr) => {
+            assert_eq!(arr.len(), 1001);
+            assert_eq!(arr[1000], Variable::Array(Arc::new(vec![])));
+        }
+        x => panic!("Expected array, found {:?}", x),
+    }
+}
+
+#[test]
+#[cfg(all(feature = "file", feature = "crypto"))]
+fn test_atomic_save() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/atomic_save.dyon"));
+    let file = std::env::temp_dir().join("dyon_test_atomic_save.txt");
+    let file = Variable::Str(Arc::new(file.to_str().unwrap().into()));
+    let mut rt = Runtime::new();
+    let res = rt.call_str_ret("save", &[file], &module).unwrap();
+    match rt.resolve(&res) {
+        &Variable::Array(ref arr) => {
+            assert_eq!(
+                arr[0],
+                Variable::Str(Arc::new(
+                    "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".into()
+                ))
+            );
+            assert_eq!(arr[1], Variable::bool(true));
+            assert_eq!(arr[2], Variable::bool(false));
+            assert_eq!(arr[3], Variable::f64(1.0));
+        }
+        x => panic!("Expected array, found {:?}", x),
+    }
+}
+
+#[test]
+#[cfg(feature = "file")]
+fn test_lock_file() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/lock_file.dyon"));
+    let file = std::env::temp_dir().join("dyon_test_lock_file.lock");
+    let file = Variable::Str(Arc::new(file.to_str().unwrap().into()));
+    let mut rt = Runtime::new();
+    rt.call_str("exclusive", &[file.clone()], &module).unwrap();
+    rt.call_str("shared", &[file.clone()], &module).unwrap();
+    assert!(Runtime::new()
+        .call_str("unlock_twice", &[file], &module)
+        .is_err());
+}
+
+#[cfg(feature = "file")]
+#[test]
+fn test_temp_file() {
+    use std::path::Path;
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/temp_file.dyon"));
+    let mut rt = Runtime::new();
+    for name in &["file", "dir"] {
+        let path = match rt.call_str_ret(name, &[], &module).unwrap() {
+            Variable::Str(path) => path,
+            x => panic!("Expected path, found {:?}", x),
+        };
+        assert!(!path.is_empty());
+        assert!(!Path::new(&**path).exists());
+    }
+}
+
+#[test]
+fn test_std_dyon() {
+    use std::sync::Arc;
+
+    let mut module = Module::new();
+    module.add_std_dyon().unwrap();
+    load("source/functions/std_dyon.dyon", &mut module).unwrap();
+    let module = Arc::new(module);
+    let mut rt = Runtime::new();
+    assert_eq!(
+        rt.call_str_ret("check", &[], &module).unwrap(),
+        Variable::bool(true)
+    );
+}
+
+#[test]
+fn test_cfg() {
+    use std::sync::Arc;
+
+    for &(enabled, expected) in &[(true, "sandbox"), (false, "full")] {
+        let mut module = Module::new();
+        module.set_cfg("sandbox", enabled);
+        load("source/runtime/cfg.dyon", &mut module).unwrap();
+        let module = Arc::new(module);
+        let mut rt = Runtime::new();
+        assert_eq!(
+            rt.call_str_ret("mode", &[], &module).unwrap(),
+            Variable::Str(Arc::new(expected.into()))
+        );
+    }
+}
+
+#[test]
+fn test_flag() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/runtime/flag.dyon"));
+    let mut rt = Runtime::new();
+    assert_eq!(
+        rt.call_str_ret("sandbox", &[], &module).unwrap(),
+        Variable::bool(false)
+    );
+    rt.set_flag("sandbox", true);
+    assert_eq!(
+        rt.call_str_ret("sandbox", &[], &module).unwrap(),
+        Variable::bool(true)
+    );
+    assert_eq!(
+        rt.call_str_ret("file", &[], &module).unwrap(),
+        Variable::bool(true)
+    );
+}
+
+#[test]
+fn test_disabled_functions() {
+    let disabled = dyon::disabled_functions();
+    assert_eq!(disabled.contains(&"regex_match"), !cfg!(feature = "regex"));
+    assert_eq!(
+        disabled.contains(&"save__string_file"),
+        !cfg!(feature = "file")
+    );
+}
+
+#[cfg(feature = "regex")]
+#[test]
+fn test_regex() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/regex.dyon"));
+    let mut rt = Runtime::new();
+    assert_eq!(
+        rt.call_str_ret("check", &[], &module).unwrap(),
+        Variable::bool(true)
+    );
+    assert!(rt.call_str_ret("invalid", &[], &module).is_err());
+}
+
+#[test]
+fn test_random() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/random.dyon"));
+    let mut rt = Runtime::new();
+    let a = rt
+        .call_str_ret("sample", &[Variable::f64(7.0)], &module)
+        .unwrap();
+    let b = Runtime::new()
+        .call_str_ret("sample", &[Variable::f64(7.0)], &module)
+        .unwrap();
+    assert_eq!(a, b);
+    assert_eq!(
+        rt.call_str_ret("in_range", &[], &module).unwrap(),
+        Variable::bool(true)
+    );
+    assert!(rt.call_str_ret("huge", &[], &module).is_err());
+    assert!(rt.call_str_ret("not_a_number", &[], &module).is_err());
+    assert!(rt.call_str_ret("full_range", &[], &module).is_ok());
+}
+
+#[cfg(feature = "file")]
+#[test]
+fn test_dir() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/runtime/dir.dyon"));
+    let mut rt = Runtime::new();
+    assert_eq!(
+        rt.call_str_ret("check", &[], &module).unwrap(),
+        Variable::bool(true)
+    );
+}
+
+#[cfg(all(feature = "process", unix))]
+#[test]
+fn test_exec() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/runtime/exec.dyon"));
+    let mut rt = Runtime::new();
+    assert_eq!(
+        rt.call_str_ret("check", &[], &module).unwrap(),
+        Variable::bool(true)
+    );
+}
+
+#[test]
+fn test_debug_hook() {
+    use std::sync::{Arc, Mutex};
+
+    let module = Arc::new(test_src("source/runtime/debug.dyon"));
+    let events = Arc::new(Mutex::new(vec![]));
+    let mut rt = Runtime::new();
+    let log = events.clone();
+    rt.set_debug_hook(Box::new(move |e| {
+        let locals: Vec<String> = e
+            .locals()
+            .iter()
+            .map(|&(name, v)| format!("{}={:?}", name, v))
+            .collect();
+        log.lock().unwrap().push(format!(
+            "{:?} {} {}",
+            e.kind,
+            e.function(),
+            locals.join(",")
+        ));
+    }));
+    rt.run(&module).unwrap();
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![
+            "Call main ",
+            "Statement main ",
+            "Statement main x=F64(2.0, None)",
+            "Call sum a=F64(2.0, None),b=F64(3.0, None)",
+            "Statement sum a=F64(2.0, None),b=F64(3.0, None)",
+        ]
+    );
+    assert!(rt.clear_debug_hook().is_some());
+}
+
+#[test]
+fn test_telemetry_hook() {
+    use std::sync::{Arc, Mutex};
+
+    let module = Arc::new(test_src("source/runtime/telemetry.dyon"));
+    let calls = Arc::new(Mutex::new(vec![]));
+    let mut rt = Runtime::new();
+    let log = calls.clone();
+    rt.set_telemetry_hook(Box::new(move |name, _| {
+        log.lock().unwrap().push(name.to_string());
+    }));
+    rt.run(&module).unwrap();
+    assert_eq!(*calls.lock().unwrap(), vec!["sqrt", "sqrt", "str"]);
+    assert!(rt.clear_telemetry_hook().is_some());
+}
+
+#[test]
+fn test_profiling() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/runtime/profile.dyon"));
+    let mut rt = Runtime::new();
+    rt.enable_profiling();
+    rt.run(&module).unwrap();
+    let report = match rt.call_str_ret("report", &[], &module).unwrap() {
+        Variable::Object(obj) => obj,
+        x => panic!("Expected object, found {:?}", x),
+    };
+    let calls = |name: &str| match report[&Arc::new(name.to_string())] {
+        Variable::Object(ref entry) => entry[&Arc::new("calls".to_string())].clone(),
+        ref x => panic!("Expected object, found {:?}", x),
+    };
+    assert_eq!(calls("main"), Variable::f64(1.0));
+    assert_eq!(calls("fib"), Variable::f64(177.0));
+    assert_eq!(calls("str"), Variable::f64(1.0));
+
+    rt.disable_profiling();
+    assert_eq!(
+        rt.profile_report(),
+        Variable::Object(Arc::new(Default::default()))
+    );
+}
+
+#[test]
+fn test_run_manifest() {
+    use dyon::vfs::MemoryFs;
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/runtime/manifest.dyon"));
+    let mut rt = Runtime::new();
+    assert_eq!(
+        rt.call_str_ret("check", &[], &module).unwrap(),
+        Variable::bool(true)
+    );
+    assert_eq!(rt.seed(), 42);
+
+    let fs = MemoryFs::new();
+    rt.set_vfs(Arc::new(fs.clone()));
+    let file = rt.save_run_manifest(&module, "result.txt").unwrap();
+    assert_eq!(file, "result.txt.manifest");
+    let text = String::from_utf8(fs.get(&file).unwrap()).unwrap();
+    assert!(text.contains("\"source/runtime/manifest.dyon\": "));
+    assert!(text.contains("seed: 42"));
+}
+
+#[cfg(feature = "file")]
+#[test]
+fn test_vfs() {
+    use dyon::vfs::{MemoryFs, RestrictedFs, Vfs};
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/runtime/vfs.dyon"));
+
+    let fs = MemoryFs::new().with_temp_dir("tmp");
+    fs.insert("in/hello.txt", "hello");
+    let mut rt = Runtime::new();
+    rt.set_vfs(Arc::new(fs.clone()));
+    assert!(rt.call_str_ret("copy", &[], &module).is_ok());
+    assert_eq!(fs.get("out/hello.txt"), Some(b"hello!".to_vec()));
+    assert_eq!(
+        rt.call_str_ret("temp", &[], &module).unwrap(),
+        Variable::bool(true)
+    );
+    // The temporary file is removed when dropped.
+    assert_eq!(fs.read_dir("tmp").unwrap(), Vec::<String>::new());
+
+    let root = std::env::temp_dir().join(format!("dyon-vfs-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    let restricted = RestrictedFs::new(&root);
+    restricted.create_dir("in").unwrap();
+    restricted.write_atomic("in/hello.txt", b"hi").unwrap();
+    rt.set_vfs(Arc::new(restricted));
+    assert!(rt.call_str_ret("copy", &[], &module).is_ok());
+    assert_eq!(
+        std::fs::read_to_string(root.join("out/hello.txt")).unwrap(),
+        "hi!"
+    );
+    assert_eq!(
+        rt.call_str_ret("escape", &[], &module).unwrap(),
+        Variable::bool(true)
+    );
+    // No temporary directory is set.
+    assert!(rt.call_str_ret("temp", &[], &module).is_err());
+    #[cfg(unix)]
+    {
+        // Symbolic links are rejected, even when dangling.
+        let outside = root.with_extension("outside");
+        std::os::unix::fs::symlink(&outside, root.join("dangling")).unwrap();
+        std::os::unix::fs::symlink(&root.join("in"), root.join("linked")).unwrap();
+        let restricted = RestrictedFs::new(&root);
+        assert!(restricted.create("dangling").is_err());
+        assert!(restricted.metadata("dangling").is_err());
+        assert!(restricted.open("linked/hello.txt").is_err());
+        assert!(restricted.read_dir("linked").is_err());
+        assert!(!outside.exists());
+    }
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[cfg(feature = "threading")]
+#[test]
+fn test_runtime_pool() {
+    use dyon::RuntimePool;
+    use std::sync::Arc;
+
+    let pool = RuntimePool::new(Arc::new(test_src("source/runtime/pool.dyon")), 4);
+    let calls: Vec<_> = (0..100)
+        .map(|i| ("square", vec![Variable::f64(i as f64)]))
+        .collect();
+    for (i, res) in pool.call_all(calls).into_iter().enumerate() {
+        assert_eq!(res.unwrap(), Variable::f64((i * i) as f64));
+    }
+    assert!(pool.call("fail", vec![]).join().is_err());
+    assert!(pool.call("missing", vec![]).join().is_err());
+
+    let mut rt = pool.runtime();
+    let module = rt.module().clone();
+    assert_eq!(
+        rt.call_str_ret("square", &[Variable::f64(3.0)], &module)
+            .unwrap(),
+        Variable::f64(9.0)
+    );
+}
+
+#[test]
+fn test_sort() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/sort.dyon"));
+    let mut rt = Runtime::new();
+    let ok = rt
+        .call_str_ret("check", &[Variable::f64(1000.0)], &module)
+        .unwrap();
+    assert_eq!(ok, Variable::bool(true));
+    assert!(rt.call_str("mixed", &[], &module).is_err());
+}
+
+#[test]
+fn test_weak() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/weak.dyon"));
+    let mut rt = Runtime::new();
+    let res = match rt.call_str_ret("check", &[], &module).unwrap() {
+        Variable::Array(arr) => arr,
+        x => panic!("Expected array, found {:?}", x),
+    };
+    assert_eq!(res[0], Variable::bool(true));
+    assert_eq!(res[1], Variable::bool(false));
+    assert_eq!(res[2], Variable::bool(true));
+    assert!(matches!(res[3], Variable::Option(None)));
+    assert!(matches!(res[4], Variable::Option(None)));
+}
+
+#[test]
+fn test_object() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/object.dyon"));
+    let mut rt = Runtime::new();
+    let res = rt.call_str_ret("check", &[], &module).unwrap();
+    let arr = |items: Vec<Variable>| Variable::Array(Arc::new(items));
+    let str = |s: &str| Variable::Str(Arc::new(s.into()));
+    assert_eq!(
+        res,
+        arr(vec![
+            Variable::f64(5.0),
+            Variable::f64(2.0),
+            str("gone"),
+            Variable::bool(false),
+            arr(vec![
+                arr(vec![str("a"), Variable::f64(3.0)]),
+                arr(vec![str("c"), Variable::f64(4.0)]),
+            ]),
+            arr(vec![Variable::f64(3.0), Variable::f64(4.0)]),
+            arr(vec![
+                Variable::f64(1.0),
+                Variable::f64(2.0),
+                Variable::f64(3.0)
+            ]),
+        ])
+    );
+}
+
+#[test]
+fn test_string() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/string.dyon"));
+    let mut rt = Runtime::new();
+    let res = match rt.call_str_ret("check", &[], &module).unwrap() {
+        Variable::Array(arr) => arr,
+        x => panic!("Expected array, found {:?}", x),
+    };
+    let str = |s: &str| Variable::Str(Arc::new(s.into()));
+    let strs = |items: &[&str]| Variable::Array(Arc::new(items.iter().map(|s| str(s)).collect()));
+    let expected = vec![
+        strs(&["a", "b", "", "c"]),
+        str("a-b--c"),
+        str("one 2 2"),
+        Variable::bool(true),
+        Variable::bool(false),
+        Variable::bool(true),
+        strs(&[""]),
+        strs(&[]),
+        str(""),
+        str(""),
+        str("abc"),
+        Variable::bool(true),
+        Variable::bool(true),
+        Variable::bool(false),
+        Variable::bool(false),
+        strs(&["å", "ä", "ö"]),
+        strs(&["日本", "語"]),
+        str("grueße"),
+        Variable::bool(true),
+        Variable::bool(true),
+        Variable::bool(true),
+    ];
+    assert_eq!(res.len(), expected.len());
+    for (i, (a, b)) in res.iter().zip(expected.iter()).enumerate() {
+        assert_eq!(a, b, "item {}", i);
+    }
+    assert!(rt.call_str("out_of_range", &[], &module).is_err());
+}
+
+#[test]
+fn test_string_builder() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/string_builder.dyon"));
+    let mut rt = Runtime::new();
+    let text = r#"n=2.5 [1, "two"] "å\"""#;
+    assert_eq!(
+        rt.call_str_ret("check", &[], &module).unwrap(),
+        Variable::Array(Arc::new(vec![
+            Variable::Str(Arc::new(text.into())),
+            Variable::f64(text.chars().count() as f64),
+        ]))
+    );
+}
+
+#[test]
+fn test_str_max_depth() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/str_max_depth.dyon"));
+    let mut rt = Runtime::new();
+    let str = |s: &str| Variable::Str(Arc::new(s.into()));
+    assert_eq!(
+        rt.call_str_ret("check", &[], &module).unwrap(),
+        Variable::Array(Arc::new(vec![
+            str("[[...]]"),
+            str("..."),
+            str("[[[[1]]]]"),
+            str("{b: ...}"),
+            str("..."),
+        ]))
+    );
+    assert!(rt.call_str("negative", &[], &module).is_err());
+}
+
+#[test]
+fn test_map_filter_fold() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/map_filter_fold.dyon"));
+    let mut rt = Runtime::new();
+    let sum = rt
+        .call_str_ret("check", &[Variable::f64(100.0)], &module)
+        .unwrap();
+    assert_eq!(sum, Variable::f64(3366.0));
+    assert!(rt.call_str("bad_filter", &[], &module).is_err());
+}
+
+#[test]
+#[cfg(feature = "file")]
+fn test_mmap() {
+    use std::io::Write;
+    use std::sync::Arc;
+
+    let path = std::env::temp_dir().join("dyon_test_mmap.bin");
+    let mut f = std::fs::File::create(&path).unwrap();
+    for x in &[1.5f32, -2.0, 1000.25] {
+        f.write_all(&x.to_le_bytes()).unwrap();
+    }
+    f.write_all(&[7, 8, 9]).unwrap();
+    drop(f);
+
+    let module = Arc::new(test_src("source/functions/mmap.dyon"));
+    let file = Variable::Str(Arc::new(path.to_str().unwrap().into()));
+    let mut rt = Runtime::new();
+    let res = rt.call_str_ret("read", &[file.clone()], &module).unwrap();
+    let res: Vec<Variable> = match rt.resolve(&res) {
+        &Variable::Array(ref arr) => (**arr).clone(),
+        x => panic!("Expected array, found {:?}", x),
+    };
+    assert_eq!(res[0], Variable::f64(15.0));
+    assert_eq!(res[1], Variable::f64(-2.0));
+    assert_eq!(res[2], Variable::f64(1000.25));
+    assert_eq!(rt.var::<Vec<u32>>(&res[3]).unwrap(), vec![7, 8, 9]);
+    assert!(rt.call_str_ret("out_of_bounds", &[file], &module).is_err());
+
+    // Sandboxed file systems read from the file instead of mapping it.
+    let mut rt = Runtime::new();
+    rt.set_vfs(Arc::new(dyon::vfs::RestrictedFs::new(std::env::temp_dir())));
+    let file = Variable::Str(Arc::new("dyon_test_mmap.bin".into()));
+    let res = rt.call_str_ret("read", &[file.clone()], &module).unwrap();
+    let res: Vec<Variable> = match rt.resolve(&res) {
+        &Variable::Array(ref arr) => (**arr).clone(),
+        x => panic!("Expected array, found {:?}", x),
+    };
+    assert_eq!(res[0], Variable::f64(15.0));
+    assert_eq!(rt.var::<Vec<u32>>(&res[3]).unwrap(), vec![7, 8, 9]);
+    assert!(rt.call_str_ret("out_of_bounds", &[file], &module).is_err());
+}
+
+#[test]
+fn test_scheduler() {
+    use dyon::runtime::Scheduler;
+    use std::sync::{Arc, Mutex};
+
+    struct Frames(Arc<Mutex<(u32, Vec<f64>)>>);
+
+    impl Scheduler for Frames {
+        fn yield_frame(&mut self) -> Result<(), String> {
+            self.0.lock().unwrap().0 += 1;
+            Ok(())
+        }
+
+        fn sleep_until(&mut self, time: f64) -> Result<(), String> {
+            self.0.lock().unwrap().1.push(time);
+            Ok(())
+        }
+    }
+
+    let module = Arc::new(test_src("source/runtime/scheduler.dyon"));
+    let state = Arc::new(Mutex::new((0, vec![])));
+    let mut rt = Runtime::new();
+    rt.set_scheduler(Box::new(Frames(state.clone())));
+    rt.run(&module).unwrap();
+    assert_eq!(*state.lock().unwrap(), (3, vec![1e12]));
+
+    assert!(rt.clear_scheduler().is_some());
+    let module = Arc::new(test_src("source/runtime/no_scheduler.dyon"));
+    rt.run(&module).unwrap();
+}
+
+#[test]
+fn test_timers() {
+    use std::cell::RefCell;
+    use std::sync::Arc;
+
+    thread_local!(static FIRED: RefCell<Vec<f64>> = RefCell::new(vec![]));
+
+    fn fired(rt: &mut Runtime) -> Result<(), RuntimeError> {
+        let x: f64 = rt.pop()?;
+        FIRED.with(|f| f.borrow_mut().push(x));
+        Ok(())
+    }
+
+    fn take() -> Vec<f64> {
+        FIRED.with(|f| ::std::mem::replace(&mut *f.borrow_mut(), vec![]))
+    }
+
+    let mut module = Module::new();
+    module.add_str("fired", fired, Dfn::nl(vec![Type::F64], Type::Void));
+    load("source/runtime/timers.dyon", &mut module).unwrap();
+    let module = Arc::new(module);
+    let mut rt = Runtime::new();
+    rt.run(&module).unwrap();
+    assert_eq!(rt.timers(), 3);
+    rt.tick(0.25).unwrap();
+    assert_eq!(take(), Vec::<f64>::new());
+    rt.tick(0.75).unwrap();
+    assert_eq!(take(), vec![0.5, 1.0, 0.5]);
+    rt.tick(1.0).unwrap();
+    assert_eq!(take(), vec![0.5, 1.5, 7.0, 0.5]);
+    assert_eq!(
+        rt.call_str_ret("stop", &[], &module).unwrap(),
+        Variable::bool(true)
+    );
+    assert_eq!(rt.timers(), 0);
+    assert!(rt.tick(-1.0).is_err());
+}
+
+#[cfg(feature = "derive")]
+#[test]
+fn test_derive_dyon_object() {
+    use dyon::embed::PushVariable;
+    use std::sync::Arc;
+
+    #[derive(DyonObject, Debug, PartialEq)]
+    struct Player {
+        name: String,
+        #[dyon(rename = "hp")]
+        health: f64,
+        pos: [f64; 2],
+        #[dyon(skip)]
+        cache: Option<u32>,
+    }
+
+    fn heal(rt: &mut Runtime) -> Result<Variable, RuntimeError> {
+        let mut player: Player = rt.pop()?;
+        player.health += 10.0;
+        Ok(player.push_var())
+    }
+
+    let mut module = Module::new();
+    module.add_str("heal", heal, Dfn::nl(vec![Type::Object], Type::Object));
+    load("source/runtime/derive.dyon", &mut module).unwrap();
+    let module = Arc::new(module);
+    let mut rt = Runtime::new();
+    let res = rt.call_str_ret("test", &[], &module).unwrap();
+    let player: Player = rt.var(&res).unwrap();
+    assert_eq!(
+        player,
+        Player {
+            name: "ann".into(),
+            health: 15.0,
+            pos: [1.0, 2.0],
+            cache: None,
+        }
+    );
+    match res {
+        Variable::Object(ref obj) => assert!(!obj.contains_key(&Arc::new("cache".into()))),
+        ref x => panic!("Expected object, found {:?}", x),
+    }
+}
+
+#[cfg(feature = "threading")]
+#[test]
+fn test_channel() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/runtime/channel.dyon"));
+    let mut rt = Runtime::new();
+    let res = rt.call_str_ret("test", &[], &module).unwrap();
+    let res: Vec<f64> = rt.var(&res).unwrap();
+    assert_eq!(res, vec![0.0, 1.0, 4.0]);
+    assert_eq!(
+        rt.call_str_ret("dropped", &[], &module).unwrap(),
+        Variable::bool(false)
+    );
+}
+
+#[test]
+fn test_sim() {
+    use dyon::sim::Sim;
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/runtime/sim.dyon"));
+    let state = |pos: f64| {
+        let mut obj = std::collections::HashMap::new();
+        obj.insert(Arc::new("pos".to_string()), Variable::f64(pos));
+        obj.insert(Arc::new("vel".to_string()), Variable::f64(2.0));
+        Variable::Object(Arc::new(obj))
+    };
+    let pos = |v: &Variable| match *v {
+        Variable::Object(ref obj) => obj[&Arc::new("pos".to_string())].clone(),
+        ref x => panic!("Expected object, found {:?}", x),
+    };
+    let mut sim = Sim::new(Runtime::new(), module, state(0.0), 0.25);
+    sim.set_history(4);
+    assert_eq!(sim.advance(0.6).unwrap(), 2);
+    assert_eq!(sim.frame(), 2);
+    assert_eq!(pos(sim.state()), Variable::f64(1.0));
+    // 0.1 seconds into the next step of 0.25.
+    assert_eq!(pos(&sim.interpolate()), Variable::f64(0.5 + 0.5 * 0.4));
+
+    let snapshot = sim.snapshot();
+    assert_eq!(sim.advance(0.5).unwrap(), 2);
+    assert_eq!(pos(sim.state()), Variable::f64(2.0));
+    sim.rollback(&snapshot);
+    assert_eq!(sim.frame(), 2);
+    assert_eq!(pos(sim.state()), Variable::f64(1.0));
+    assert!(sim.rollback_to(1));
+    assert_eq!(pos(sim.state()), Variable::f64(0.5));
+    assert!(!sim.rollback_to(2));
+
+    sim.set_max_steps(3);
+    assert_eq!(sim.advance(10.0).unwrap(), 3);
+    assert_eq!(sim.frame(), 4);
+}
+
+#[test]
+fn test_metrics() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/runtime/metrics.dyon"));
+    let mut rt = Runtime::new();
+    rt.run(&module).unwrap();
+    let key = |s: &str| Arc::new(s.to_string());
+    let report = match rt.call_str_ret("report", &[], &module).unwrap() {
+        Variable::Object(obj) => obj,
+        x => panic!("Expected object, found {:?}", x),
+    };
+    match report[&key("counters")] {
+        Variable::Object(ref obj) => assert_eq!(obj[&key("requests")], Variable::f64(3.0)),
+        ref x => panic!("Expected object, found {:?}", x),
+    }
+    match report[&key("histograms")] {
+        Variable::Object(ref obj) => match obj[&key("latency seconds")] {
+            Variable::Object(ref h) => {
+                assert_eq!(h[&key("count")], Variable::f64(3.0));
+                assert_eq!(h[&key("max")], Variable::f64(20.0));
+            }
+            ref x => panic!("Expected object, found {:?}", x),
+        },
+        ref x => panic!("Expected object, found {:?}", x),
+    }
+    let text = rt.metrics().prometheus();
+    assert!(text.contains("# TYPE requests counter\nrequests 3\n"));
+    assert!(text.contains("latency_seconds_bucket{le=\"0.025\"} 1\n"));
+    assert!(text.contains("latency_seconds_bucket{le=\"10\"} 2\n"));
+    assert!(text.contains("latency_seconds_bucket{le=\"+Inf\"} 3\n"));
+    assert!(text.contains("latency_seconds_count 3\n"));
+    assert!(rt.call_str("dec", &[], &module).is_err());
+}
+
+#[cfg(feature = "threading")]
+#[test]
+fn test_shared() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/runtime/shared.dyon"));
+    let mut rt = Runtime::new();
+    assert_eq!(
+        rt.call_str_ret("test", &[], &module).unwrap(),
+        Variable::f64(402.0)
+    );
+}
+
+#[test]
+fn test_fuzzy() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/fuzzy.dyon"));
+    let mut rt = Runtime::new();
+    let res = rt.call_str_ret("distances", &[], &module).unwrap();
+    let res: Vec<f64> = rt.var(&res).unwrap();
+    assert_eq!(res, vec![3.0, 3.0, 1.0, 1.0 - 3.0 / 7.0, 1.0]);
+    let res = rt.call_str_ret("matches", &[], &module).unwrap();
+    let res: Vec<String> = rt.var(&res).unwrap();
+    assert_eq!(res, vec!["spawn", "set_player", "inspect"]);
+}
+
+#[test]
+fn test_time() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/time.dyon"));
+    let mut rt = Runtime::new();
+    let res = match rt.call_str_ret("test", &[], &module).unwrap() {
+        Variable::Array(arr) => arr,
+        x => panic!("Expected array, found {:?}", x),
+    };
+    assert_eq!(
+        res[0],
+        Variable::Str(Arc::new("2000-02-29 13:05:09.250 day 060 %".into()))
+    );
+    let nums: Vec<f64> = res[1..9].iter().map(|v| rt.var(v).unwrap()).collect();
+    assert_eq!(
+        nums,
+        vec![2000.0, 2.0, 29.0, 13.0, 5.0, 9.25, 0.0, -86400.0]
+    );
+    assert_eq!(res[9], Variable::bool(true));
+    assert_eq!(res[10], Variable::bool(true));
+    assert_eq!(res[11], Variable::bool(true));
+
+    for ts in [1e300, -1e300, f64::NAN, f64::INFINITY] {
+        let ts = [Variable::f64(ts)];
+        assert!(rt.call_str_ret("format_year", &ts, &module).is_err());
+        assert!(rt.call_str_ret("year", &ts, &module).is_err());
+    }
+    // The largest timestamp is one hundred billion days after Unix Epoch.
+    let ts = [Variable::f64(8.64e15)];
+    assert_eq!(
+        rt.call_str_ret("format_year", &ts, &module).unwrap(),
+        Variable::Str(Arc::new("273792670".into()))
+    );
+}
+
+#[cfg(feature = "file")]
+#[test]
+fn test_bytes() {
+    use dyon::vfs::MemoryFs;
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/bytes.dyon"));
+    let fs = MemoryFs::new();
+    fs.insert("in.bin", vec![0u8, b'a', b'b', b'c', 255]);
+    let mut rt = Runtime::new();
+    rt.set_vfs(Arc::new(fs.clone()));
+    let res = match rt.call_str_ret("test", &[], &module).unwrap() {
+        Variable::Array(arr) => arr,
+        x => panic!("Expected array, found {:?}", x),
+    };
+    let nums: Vec<f64> = res[0..3].iter().map(|v| rt.var(v).unwrap()).collect();
+    assert_eq!(nums, vec![5.0, 0.0, 255.0]);
+    assert_eq!(res[3], Variable::Str(Arc::new("abc".into())));
+    assert_eq!(res[4], Variable::bool(true));
+    assert_eq!(res[5], Variable::Str(Arc::new("bytes".into())));
+    assert_eq!(res[6], Variable::bool(true));
+    assert_eq!(fs.get("out.bin"), Some(b"abc".to_vec()));
+    assert!(rt.call_str_ret("out_of_bounds", &[], &module).is_err());
+    assert_eq!(res[7], Variable::Str(Arc::new("bytes(\"616263\")".into())));
+    assert_eq!(res[8], Variable::bool(true));
+    assert_eq!(res[9], Variable::bool(true));
+}
+
+#[test]
+fn test_formula() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/formula.dyon"));
+    let mut rt = Runtime::new();
+    let res = match rt.call_str_ret("test", &[], &module).unwrap() {
+        Variable::Array(arr) => arr,
+        x => panic!("Expected array, found {:?}", x),
+    };
+    let nums: Vec<f64> = res[0..4].iter().map(|v| rt.var(v).unwrap()).collect();
+    assert_eq!(nums, vec![72.0, -1.0, 1.0, 100.0]);
+    let errs: Vec<Arc<String>> = res[4..7].iter().map(|v| rt.var(v).unwrap()).collect();
+    assert_eq!(
+        errs,
+        vec![
+            Arc::new("Unexpected `*` at position 5".into()),
+            Arc::new("Expected `)` at position 3".into()),
+            Arc::new("Unknown variable `mana`".into()),
+        ]
+    );
+    assert_eq!(res[7], Variable::bool(true));
+}
+
+#[cfg(feature = "msgpack")]
+#[test]
+fn test_msgpack() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/msgpack.dyon"));
+    let mut rt = Runtime::new();
+    let res = match rt.call_str_ret("test", &[], &module).unwrap() {
+        Variable::Array(arr) => arr,
+        x => panic!("Expected array, found {:?}", x),
+    };
+    assert_eq!(res[0], Variable::bool(true));
+    assert_eq!(res[1], Variable::f64(4.0));
+    assert_eq!(res[2], Variable::Str(Arc::new("bytes(\"d402ff\")".into())));
+    assert_eq!(res[3], Variable::bool(true));
+    assert_eq!(res[4], Variable::bool(true));
+    assert_eq!(res[5], Variable::bool(true));
+}
+
+#[cfg(all(feature = "msgpack", feature = "f64_vec4"))]
+#[test]
+fn test_msgpack_f64_vec4() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/msgpack.dyon"));
+    let mut rt = Runtime::new();
+    match rt.call_str_ret("precise_vec4", &[], &module).unwrap() {
+        Variable::Vec4(v) => assert_eq!(v, [0.1, 16777217.0, 1.0 / 3.0, 1.0]),
+        x => panic!("Expected vec4, found {:?}", x),
+    }
+}
+
+#[cfg(feature = "toml")]
+#[test]
+fn test_toml() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/toml.dyon"));
+    let mut rt = Runtime::new();
+    let res = match rt.call_str_ret("test", &[], &module).unwrap() {
+        Variable::Array(arr) => arr,
+        x => panic!("Expected array, found {:?}", x),
+    };
+    assert_eq!(res[0], Variable::bool(true));
+    assert_eq!(
+        res[1],
+        Variable::Str(Arc::new(
+            "Error loading TOML from string:\n\
+             TOML parse error at line 2, column 1\n  |\n2 | a = 2\n  | ^\nduplicate key"
+                .into()
+        ))
+    );
+}
+
+#[cfg(feature = "yaml")]
+#[test]
+fn test_yaml() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/yaml.dyon"));
+    let mut rt = Runtime::new();
+    let res = match rt.call_str_ret("test", &[], &module).unwrap() {
+        Variable::Array(arr) => arr,
+        x => panic!("Expected array, found {:?}", x),
+    };
+    assert_eq!(res[0], Variable::bool(true));
+    assert_eq!(
+        res[1],
+        Variable::Str(Arc::new(
+            "Error loading YAML from string:\n\
+             mapping values are not allowed in this context at line 2 column 3"
+                .into()
+        ))
+    );
+    assert_eq!(
+        res[2],
+        Variable::Str(Arc::new(
+            "Error loading YAML from string:\nTag `!point` is not supported".into()
+        ))
+    );
+}
+
+#[test]
+fn test_cells() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/runtime/cells.dyon"));
+    let mut rt = Runtime::new();
+    let res = match rt.call_str_ret("test", &[], &module).unwrap() {
+        Variable::Array(arr) => arr,
+        x => panic!("Expected array, found {:?}", x),
+    };
+    assert_eq!(res[0], Variable::f64(60.0));
+    assert_eq!(res[1], Variable::f64(80.0));
+    assert_eq!(
+        res[2],
+        Variable::Str(Arc::new(
+            "Cycle between cells `total -> price -> total`".into()
+        ))
+    );
+    assert_eq!(res[3], Variable::f64(16.0));
+    assert_eq!(
+        res[4],
+        Variable::Str(Arc::new("Unknown cell `other`".into()))
+    );
+}
+
+#[test]
+fn test_solve() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/solve.dyon"));
+    let mut rt = Runtime::new();
+    let res = match rt.call_str_ret("linear", &[], &module).unwrap() {
+        Variable::Array(arr) => arr,
+        x => panic!("Expected array, found {:?}", x),
+    };
+    let nums: Vec<f64> = res[0..6].iter().map(|v| rt.var(v).unwrap()).collect();
+    let expected = [36.0, 2.0, 6.0, 2.8, 6.0, 8.0];
+    for (a, b) in nums.iter().zip(expected.iter()) {
+        assert!((a - b).abs() < 1e-9, "{:?} != {:?}", nums, expected);
+    }
+    assert_eq!(
+        res[6],
+        Variable::Str(Arc::new("No solution satisfies the constraints".into()))
+    );
+    assert_eq!(
+        res[7],
+        Variable::Str(Arc::new("The objective is unbounded".into()))
+    );
+
+    let res = match rt.call_str_ret("csp", &[], &module).unwrap() {
+        Variable::Array(arr) => arr,
+        x => panic!("Expected array, found {:?}", x),
+    };
+    let nums: Vec<f64> = res[0..3].iter().map(|v| rt.var(v).unwrap()).collect();
+    assert_eq!(nums, vec![1.0, 2.0, 3.0]);
+    assert_eq!(res[3], Variable::bool(true));
+}
+
+#[test]
+fn test_derivative() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/derivative.dyon"));
+    let mut rt = Runtime::new();
+    let res = match rt.call_str_ret("values", &[], &module).unwrap() {
+        Variable::Array(arr) => arr,
+        x => panic!("Expected array, found {:?}", x),
+    };
+    let nums: Vec<f64> = res.iter().map(|v| rt.var(v).unwrap()).collect();
+    let expected = [3.0 + 2.0 * 1f64.cos(), 3.0 + 4.0 / 3f64.ln(), -4.0, 6.0];
+    for (a, b) in nums.iter().zip(expected.iter()) {
+        assert!((a - b).abs() < 1e-6, "{:?} != {:?}", nums, expected);
+    }
+    assert_eq!(
+        rt.call_str_ret("text", &[], &module).unwrap(),
+        Variable::Str(Arc::new("\\(x: any, y: any) = 6 * x".into()))
+    );
+    assert!(rt.call_str("unknown", &[], &module).is_err());
+}
+
+#[test]
+fn test_integrate() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/integrate.dyon"));
+    let mut rt = Runtime::new();
+    let res = match rt.call_str_ret("decay", &[], &module).unwrap() {
+        Variable::Array(arr) => arr,
+        x => panic!("Expected array, found {:?}", x),
+    };
+    let nums: Vec<f64> = res.iter().map(|v| rt.var(v).unwrap()).collect();
+    let expected = [3.0, 0.5, 0.25, 11.0, (-1f64).exp(), 8.0, 4.0, 3.0];
+    for (a, b) in nums.iter().zip(expected.iter()) {
+        assert!((a - b).abs() < 1e-6, "{:?} != {:?}", nums, expected);
+    }
+    match rt.call_str_ret("oscillator", &[], &module).unwrap() {
+        Variable::Vec4(v) => {
+            assert!((v[0] + 1.0).abs() < 1e-5 && v[1].abs() < 1e-5, "{:?}", v)
+        }
+        x => panic!("Expected vec4, found {:?}", x),
+    }
+    assert!(rt.call_str("wrong_step", &[], &module).is_err());
+    assert!(rt.call_str("wrong_shape", &[], &module).is_err());
+}
+
+#[test]
+fn test_spline() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/spline.dyon"));
+    let mut rt = Runtime::new();
+    let res = match rt.call_str_ret("values", &[], &module).unwrap() {
+        Variable::Array(arr) => arr,
+        x => panic!("Expected array, found {:?}", x),
+    };
+    let vec4s = |vs: &[Variable]| -> Vec<[f32; 2]> {
+        vs.iter()
+            .map(|v| {
+                let v: [f32; 4] = rt.var_vec4(v).unwrap();
+                [v[0], v[1]]
+            })
+            .collect()
+    };
+    let points = [[0.0, 0.0], [1.0, 2.0], [3.0, 2.0]];
+    assert_eq!(
+        vec4s(&res[..4]),
+        [points[0], points[1], points[2], points[2]]
+    );
+    let length: f64 = rt.var(&res[4]).unwrap();
+    assert!((length - 2.0).abs() < 1e-9, "{}", length);
+    match res[5] {
+        Variable::Array(ref arr) => assert_eq!(vec4s(arr), points),
+        ref x => panic!("Expected array, found {:?}", x),
+    }
+    assert_eq!(res[6], Variable::f64(10.0));
+    assert!(rt.call_str("empty", &[], &module).is_err());
+}
+
+#[test]
+fn test_repl() {
+    let mut repl = Repl::new();
+    assert_eq!(repl.eval("x := 2").unwrap(), None);
+    assert_eq!(repl.eval("x + 1").unwrap(), Some(Variable::f64(3.0)));
+    assert_eq!(
+        repl.eval("fn sq(a: f64) -> f64 { return a * a }").unwrap(),
+        None
+    );
+    assert_eq!(
+        repl.eval("x += 1\nsq(x)").unwrap(),
+        Some(Variable::f64(9.0))
+    );
+    assert_eq!(
+        repl.eval("ys := []\nfor i 3 { push(mut ys, i) }").unwrap(),
+        None
+    );
+    assert_eq!(
+        repl.eval("sum i { ys[i] }").unwrap(),
+        Some(Variable::f64(3.0))
+    );
+    assert!(repl.eval("z := unknown").is_err());
+    assert!(repl.eval("x = \"3\"\nx + 1").is_err());
+    assert_eq!(
+        repl.eval("if x > 2 { \"big\" } else { \"small\" }")
+            .unwrap(),
+        Some(Variable::Str(std::sync::Arc::new("big".into())))
+    );
+    assert_eq!(repl.var("x"), Some(&Variable::f64(3.0)));
+    let names: Vec<String> = repl.var_names().iter().map(|n| n.to_string()).collect();
+    assert_eq!(names, ["x", "ys"]);
+}
+
+#[test]
+fn test_format_source() {
+    use std::sync::Arc;
+
+    let src = [
+        "",
+        "fn main() {",
+        "",
+        "  x := [",
+        "1,",
+        "  2 ]   ",
+        "\t// Comment {",
+        "    if x[0] > 0 {",
+        " println(\"{\\\"\")",
+        "      } else {",
+        "",
+        "",
+        "y := \\(a) = {",
+        " a + 1 }",
+        "}",
+        "",
+        "}",
+        "",
+        "sq(x) =",
+        "x * x",
+        "",
+        "",
+    ]
+    .join("\n");
+    let expected = [
+        "fn main() {",
+        "    x := [",
+        "        1,",
+        "        2 ]",
+        "    // Comment {",
+        "    if x[0] > 0 {",
+        "        println(\"{\\\"\")",
+        "    } else {",
+        "        y := \\(a) = {",
+        "            a + 1 }",
+        "    }",
+        "}",
+        "",
+        "sq(x) =",
+        "    x * x",
+        "",
+    ]
+    .join("\n");
+    let formatted = format_source(&src).unwrap();
+    assert_eq!(formatted, expected);
+    assert_eq!(format_source(&formatted).unwrap(), expected);
+    assert!(format_source("fn main() {").is_err());
+    // Only indentation changes, not spacing within lines.
+    assert_eq!(
+        format_source("fn main() {\nx :=  1+2\n}").unwrap(),
+        "fn main() {\n    x :=  1+2\n}\n"
+    );
+
+    let module = Arc::new(test_src("source/runtime/format.dyon"));
+    let mut rt = Runtime::new();
+    assert_eq!(
+        rt.call_str_ret("format", &[], &module).unwrap(),
+        Variable::Str(Arc::new("fn foo() {\n    return 1\n}\n".into()))
+    );
+    assert_eq!(
+        rt.call_str_ret("error", &[], &module).unwrap(),
+        Variable::bool(true)
+    );
+}
+
+#[test]
+fn test_optimize() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/runtime/optimize.dyon"));
+    let mut rt = Runtime::new();
+    let res = match rt.call_str_ret("values", &[], &module).unwrap() {
+        Variable::Array(arr) => arr,
+        x => panic!("Expected array, found {:?}", x),
+    };
+    let nums: Vec<f64> = res.iter().map(|v| rt.var(v).unwrap()).collect();
+    let expected = [
+        2.0,
+        1.0,
+        1.0,
+        1.0,
+        ::std::f64::consts::FRAC_PI_2,
+        2f64.cbrt(),
+    ];
+    for (a, b) in nums.iter().zip(expected.iter()) {
+        assert!((a - b).abs() < 1e-6, "{:?} != {:?}", nums, expected);
+    }
+    let res = match rt.call_str_ret("errors", &[], &module).unwrap() {
+        Variable::Array(arr) => arr,
+        x => panic!("Expected array, found {:?}", x),
+    };
+    let msgs: Vec<String> = res.iter().map(|v| rt.var(v).unwrap()).collect();
+    assert_eq!(
+        msgs,
+        [
+            "Expected values of opposite signs at end points, found 2 and 2",
+            "Derivative is 0 at 0",
+            "Did not converge after 10 iterations",
+        ]
+    );
+}
+
+#[test]
+fn test_runtime_error() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/runtime/runtime_error.dyon"));
+    let run = |function: &str| -> (String, RuntimeError) {
+        let mut rt = Runtime::new();
+        let err = rt.call_str(function, &[], &module).unwrap_err();
+        assert_eq!(err.to_string(), err.message());
+        (err.to_string(), err)
+    };
+
+    let (msg, err) = run("type_mismatch");
+    assert!(err.range().is_some());
+    match *err.kind() {
+        ErrorKind::TypeMismatch {
+            ref expected,
+            ref found,
+        } => {
+            assert_eq!(expected, "number");
+            assert_eq!(found, "string");
+        }
+        _ => panic!("{:?}", err),
+    }
+    assert!(msg.contains("Expected `number`, found `string`"));
+
+    let (_, err) = run("index_out_of_bounds");
+    assert!(err.range().is_some());
+    match *err.kind() {
+        ErrorKind::IndexOutOfBounds { index } => assert_eq!(index, 3.0),
+        _ => panic!("{:?}", err),
+    }
+
+    let (_, err) = run("user");
+    assert!(err.range().is_some());
+    match *err.kind() {
+        ErrorKind::User(ref value) => assert_eq!(value, &Variable::Str(Arc::new("oops".into()))),
+        _ => panic!("{:?}", err),
+    }
+
+    let (_, err) = run("custom");
+    assert!(err.range().is_some());
+    match *err.kind() {
+        ErrorKind::Custom => {}
+        _ => panic!("{:?}", err),
+    }
+
+    let type_mismatch = |function: &str, expected_ty: &str, found_ty: &str| {
+        let (_, err) = run(function);
+        assert!(err.range().is_some());
+        match *err.kind() {
+            ErrorKind::TypeMismatch {
+                ref expected,
+                ref found,
+            } => {
+                assert_eq!(expected, expected_ty);
+                assert_eq!(found, found_ty);
+            }
+            _ => panic!("{:?}", err),
+        }
+    };
+    type_mismatch("if_number", "boolean", "number");
+    type_mismatch("index_number", "object or array", "number");
+    type_mismatch("index_with_string", "number", "string");
+    type_mismatch("assign_string", "string", "number");
+    type_mismatch(
+        "add_string",
+        "`f64`, `vec4`, `mat4`, `bool`, `str` or `link`",
+        "(string, number)",
+    );
+
+    let (_, err) = run("missing_key");
+    assert!(err.range().is_some());
+    match *err.kind() {
+        ErrorKind::NotFound { ref name } => assert_eq!(name, "y"),
+        _ => panic!("{:?}", err),
+    }
+
+    for function in &[
+        "save_to_missing_dir",
+        "load_missing_file",
+        "load_missing_bytes",
+        "read_missing_dir",
+    ] {
+        let (_, err) = run(function);
+        assert!(err.range().is_some());
+        match *err.kind() {
+            ErrorKind::Io => {}
+            _ => panic!("{}: {:?}", function, err),
+        }
+    }
+    // Scripts still get `err(_)` values from failed file operations.
+    let mut rt = Runtime::new();
+    assert_eq!(
+        rt.call_str_ret("missing_file_is_err", &[], &module)
+            .unwrap(),
+        Variable::bool(true)
+    );
+
+    let mut rt = Runtime::new();
+    let err = rt
+        .call_str("foo", &[], &Arc::new(Module::new()))
+        .unwrap_err();
+    match *err.kind() {
+        ErrorKind::NotFound { ref name } => assert_eq!(name, "foo"),
+        _ => panic!("{:?}", err),
+    }
+
+    let err: RuntimeError = String::from("message").into();
+    assert_eq!(err.message(), "message");
+}
+
+#[test]
+fn test_stack_trace_location() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/runtime/stack_trace.dyon"));
+    let err = Runtime::new().run(&module).unwrap_err();
+    assert!(err.message().starts_with(
+        "main (source/runtime/stack_trace.dyon:2:10)\nfoo (source/runtime/stack_trace.dyon:6:16)\n"
+    ));
+}
+
+#[test]
+fn test_procgen() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/runtime/procgen.dyon"));
+    let mut rt = Runtime::new();
+    let grid = |rt: &mut Runtime, f: &str, args: &[Variable]| -> Vec<Vec<bool>> {
+        match rt.call_str_ret(f, args, &module).unwrap() {
+            Variable::Array(rows) => rows
+                .iter()
+                .map(|row| match *row {
+                    Variable::Array(ref cells) => {
+                        cells.iter().map(|c| rt.var(c).unwrap()).collect()
+                    }
+                    ref x => panic!("Expected array, found {:?}", x),
+                })
+                .collect(),
+            x => panic!("Expected array, found {:?}", x),
+        }
+    };
+    let (f, t) = (false, true);
+    assert_eq!(
+        grid(&mut rt, "blinker", &[]),
+        [[f, t, f], [f, t, f], [f, t, f]]
+    );
+    assert_eq!(grid(&mut rt, "walls", &[]), [[t]]);
+
+    let walk = grid(&mut rt, "walk", &[Variable::f64(1.0)]);
+    assert_eq!(walk.len(), 5);
+    assert!(walk.iter().all(|row| row.len() == 7));
+    assert!(walk[2][3]);
+    assert_eq!(walk, grid(&mut rt, "walk", &[Variable::f64(1.0)]));
+
+    let points: Vec<[f32; 4]> = match rt.call_str_ret("points", &[], &module).unwrap() {
+        Variable::Array(arr) => arr.iter().map(|p| rt.var_vec4(p).unwrap()).collect(),
+        x => panic!("Expected array, found {:?}", x),
+    };
+    assert!(points.len() > 10);
+    for (i, p) in points.iter().enumerate() {
+        assert!(p[0] >= 0.0 && p[0] < 10.0 && p[1] >= 0.0 && p[1] < 5.0);
+        for q in &points[..i] {
+            assert!((p[0] - q[0]).hypot(p[1] - q[1]) >= 0.999);
+        }
+    }
+}
+
+#[test]
+fn test_migrate() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/runtime/migrate.dyon"));
+    let mut rt = Runtime::new();
+    let mut upgrade = |text: &str| -> Result<(f64, f64), String> {
+        let arg = Variable::Str(Arc::new(text.into()));
+        let res = rt.call_str_ret("upgrade", &[arg], &module).unwrap();
+        let data = match res {
+            Variable::Result(Ok(data)) => data,
+            Variable::Result(Err(err)) => return Err(format!("{:?}", err.message)),
+            x => panic!("Expected res, found {:?}", x),
+        };
+        match *data {
+            Variable::Object(ref obj) => Ok((
+                rt.var(&obj[&Arc::new("version".into())]).unwrap(),
+                rt.var(&obj[&Arc::new("hp".into())]).unwrap(),
+            )),
+            ref x => panic!("Expected object, found {:?}", x),
+        }
+    };
+    assert_eq!(upgrade(r#"{name: "a"}"#), Ok((2.0, 20.0)));
+    assert_eq!(
+        upgrade(r#"{name: "a", hp: 7, version: 1}"#),
+        Ok((2.0, 14.0))
+    );
+    assert_eq!(upgrade(r#"{name: "a", hp: 7, version: 2}"#), Ok((2.0, 7.0)));
+    assert!(upgrade(r#"{name: "a", version: 3}"#)
+        .unwrap_err()
+        .contains("newer than the latest migration"));
+    assert!(Runtime::new()
+        .call_str("unordered", &[], &module)
+        .unwrap_err()
+        .message()
+        .contains("Expected increasing versions, found 1 after 2"));
+}
+
+#[test]
+fn test_try() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/runtime/try.dyon"));
+    let mut rt = Runtime::new();
+    rt.enable_profiling();
+    let res = match rt.call_str_ret("caught", &[], &module).unwrap() {
+        Variable::Array(arr) => arr,
+        x => panic!("Expected array, found {:?}", x),
+    };
+    let msg = |v: &Variable| -> String {
+        match *v {
+            Variable::Result(Err(ref err)) => format!("{:?}", err.message),
+            ref x => panic!("Expected err, found {:?}", x),
+        }
+    };
+    let ok = |v: &Variable| -> f64 {
+        match *v {
+            Variable::Result(Ok(ref x)) => rt.var(x).unwrap(),
+            ref x => panic!("Expected ok, found {:?}", x),
+        }
+    };
+    assert_eq!(ok(&res[0]), 1.0);
+    assert!(msg(&res[1]).contains("Out of bounds `0`"));
+    assert!(msg(&res[2]).contains("oops"));
+    assert_eq!(ok(&res[3]), 3.0);
+    assert!(msg(&res[4]).contains("none()"));
+    assert!(rt.stack.is_empty());
+    assert!(rt.call_stack.is_empty());
+
+    // The failed call is timed.
+    let report = match rt.profile_report() {
+        Variable::Object(obj) => obj,
+        x => panic!("Expected object, found {:?}", x),
+    };
+    match report.get(&Arc::new("fail".to_string())) {
+        Some(Variable::Object(fail)) => assert_eq!(
+            fail.get(&Arc::new("calls".to_string())),
+            Some(&Variable::f64(1.0))
+        ),
+        x => panic!("Expected object, found {:?}", x),
+    }
+
+    // Limits are not caught.
+    let mut rt = Runtime::with_settings(RuntimeSettings {
+        max_call_depth: Some(50),
+        ..Default::default()
+    });
+    for f in ["too_deep", "too_deep_expr"] {
+        let err = rt.call_str_ret(f, &[], &module).unwrap_err();
+        match *err.kind() {
+            ErrorKind::LimitExceeded { .. } => {}
+            _ => panic!("Expected limit exceeded, found {:?}", err),
+        }
+    }
+}
+
+#[cfg(feature = "rpc")]
+#[test]
+fn test_rpc() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/runtime/rpc.dyon"));
+    let mut rt = Runtime::new();
+    let res = match rt.call_str_ret("test", &[], &module).unwrap() {
+        Variable::Array(arr) => arr,
+        x => panic!("Expected array, found {:?}", x),
+    };
+    assert_eq!(res[0], Variable::f64(3.0));
+    assert_eq!(res[1], Variable::Str(Arc::new("dyon".into())));
+    assert_eq!(res[2], Variable::bool(true));
+    assert_eq!(res[3], Variable::bool(true));
+    assert_eq!(res[4], Variable::f64(7.0));
+}
+
+#[test]
+fn test_tail_call() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/runtime/tail_call.dyon"));
+    let mut rt = Runtime::new();
+    let res = rt.call_str_ret("calls", &[], &module).unwrap();
+    assert_eq!(
+        res,
+        Variable::Array(Arc::new(vec![
+            Variable::f64(5000050000.0),
+            Variable::bool(false),
+            Variable::f64(100000.0),
+            Variable::f64(100000.0),
+        ]))
+    );
+}
+
+#[test]
+fn test_runtime_settings() {
+    use dyon::runtime::Limit;
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/runtime/settings.dyon"));
+    let run = |function: &str, settings: RuntimeSettings| -> RuntimeError {
+        let mut rt = Runtime::with_settings(settings);
+        rt.call_str(function, &[], &module).unwrap_err()
+    };
+    let run_ok = |function: &str, settings: RuntimeSettings| {
+        let mut rt = Runtime::with_settings(settings);
+        rt.call_str(function, &[], &module).unwrap();
+    };
+    fn limit(err: RuntimeError) -> Limit {
+        assert!(err.range().is_some());
+        match *err.kind() {
+            ErrorKind::LimitExceeded { limit } => limit,
+            _ => panic!("{:?}", err),
+        }
+    }
+
+    let settings = RuntimeSettings {
+        max_call_depth: Some(100),
+        ..Default::default()
+    };
+    assert_eq!(limit(run("call_depth", settings)), Limit::CallDepth);
+
+    let settings = RuntimeSettings {
+        instruction_budget: Some(1000),
+        ..Default::default()
+    };
+    let err = run("forever", settings);
+    assert!(err
+        .message()
+        .contains("Instruction budget limit of `1000` exceeded"));
+    assert_eq!(limit(err), Limit::InstructionBudget);
+    assert_eq!(limit(run("count_many", settings)), Limit::InstructionBudget);
+
+    let settings = RuntimeSettings {
+        max_array_len: Some(10),
+        ..Default::default()
+    };
+    for function in &[
+        "fill",
+        "push_one",
+        "sift_n",
+        "sift_range",
+        "split_str",
+        "chars_str",
+    ] {
+        assert_eq!(limit(run(function, settings)), Limit::ArrayLen);
+    }
+    run_ok("sift_small", settings);
+    run_ok("load_data_str", settings);
+
+    let settings = RuntimeSettings {
+        max_stack_size: Some(50),
+        ..Default::default()
+    };
+    assert_eq!(limit(run("stack_size", settings)), Limit::StackSize);
+
+    // The budget is restored for each call from the host.
+    let mut rt = Runtime::with_settings(RuntimeSettings {
+        instruction_budget: Some(20),
+        ..Default::default()
+    });
+    for _ in 0..3 {
+        rt.call_str("small_loop", &[], &module).unwrap();
+    }
+}
+
+#[test]
+fn test_swizzle() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/swizzle.dyon"));
+    let mut rt = Runtime::new();
+    let res = rt.call_str_ret("checks", &[], &module).unwrap();
+    assert_eq!(
+        res,
+        Variable::Array(Arc::new(vec![Variable::bool(true); 4]))
+    );
+}
+
+#[test]
+fn test_quaternions() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/quaternions.dyon"));
+    let mut rt = Runtime::new();
+    let res = rt.call_str_ret("checks", &[], &module).unwrap();
+    assert_eq!(
+        res,
+        Variable::Array(Arc::new(vec![Variable::bool(true); 5]))
+    );
+}
+
+#[test]
+fn test_mat4_decompose() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/decompose.dyon"));
+    let mut rt = Runtime::new();
+    let res = rt.call_str_ret("checks", &[], &module).unwrap();
+    assert_eq!(
+        res,
+        Variable::Array(Arc::new(vec![Variable::bool(true); 6]))
+    );
+}
+
+#[test]
+fn test_transform2() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/transform2.dyon"));
+    let mut rt = Runtime::new();
+    let res = rt.call_str_ret("checks", &[], &module).unwrap();
+    assert_eq!(
+        res,
+        Variable::Array(Arc::new(vec![Variable::bool(true); 4]))
+    );
+}
+
+#[test]
+fn test_sync() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/runtime/sync.dyon"));
+    let mut rt = Runtime::new();
+    let res = match rt.call_str_ret("patches", &[], &module).unwrap() {
+        Variable::Array(arr) => arr,
+        x => panic!("Expected array, found {:?}", x),
+    };
+    let obj = |v: &Variable, key: &str| -> Variable {
+        match *v {
+            Variable::Object(ref obj) => obj[&Arc::new(key.into())].clone(),
+            ref x => panic!("Expected object, found {:?}", x),
+        }
+    };
+    let players = obj(&res[0], "players");
+    assert_eq!(obj(&obj(&players, "alice"), "y"), Variable::f64(3.0));
+    assert_eq!(obj(&obj(&players, "bob"), "x"), Variable::f64(5.0));
+    assert_eq!(obj(&res[0], "time"), Variable::f64(1.0));
+    assert_eq!(
+        obj(&res[0], "log"),
+        Variable::Array(Arc::new(vec![
+            Variable::Str(Arc::new("start".into())),
+            Variable::Str(Arc::new("move".into())),
+        ]))
+    );
+    match res[0] {
+        Variable::Object(ref obj) => assert!(!obj.contains_key(&Arc::new("old".into()))),
+        _ => unreachable!(),
+    }
+
+    // Only the changes of interest are applied.
+    let players = obj(&res[1], "players");
+    assert_eq!(obj(&obj(&players, "alice"), "y"), Variable::f64(3.0));
+    assert_eq!(obj(&obj(&players, "bob"), "x"), Variable::f64(3.0));
+    assert_eq!(obj(&res[1], "time"), Variable::f64(1.0));
+    assert_eq!(obj(&res[1], "old"), Variable::bool(true));
+    assert_eq!(
+        obj(&res[1], "log"),
+        Variable::Array(Arc::new(vec![Variable::Str(Arc::new("start".into()))]))
+    );
+
+    // Equal states give a patch with only the version.
+    assert_eq!(res[2], Variable::f64(1.0));
+    assert_eq!(
+        res[3],
+        Variable::Str(Arc::new("Expected object in state".into()))
+    );
+}
+
+#[test]
+fn test_cancel_handle() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/runtime/cancel.dyon"));
+    let mut rt = Runtime::new();
+    let cancel = rt.cancel_handle();
+    for name in &["spin", "spin_compiled"] {
+        let handle = {
+            let cancel = cancel.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                cancel.cancel();
+            })
+        };
+        let err = if *name == "spin" {
+            rt.call_str(name, &[], &module).unwrap_err()
+        } else {
+            rt.call_str_ret(name, &[], &module).unwrap_err()
+        };
+        handle.join().unwrap();
+        assert!(err.range().is_some());
+        assert!(err.message().contains("Interrupted"));
+        match *err.kind() {
+            ErrorKind::Cancelled => {}
+            _ => panic!("{:?}", err),
+        }
+        // Reset runtime state after error.
+        rt.stack.clear();
+        rt.call_stack.clear();
+        rt.local_stack.clear();
+        rt.current_stack.clear();
+    }
+    assert!(cancel.is_cancelled());
+    // The next call from the host clears the cancellation.
+    rt.run(&module).unwrap();
+    assert!(!cancel.is_cancelled());
+}
+
+#[test]
+fn test_topics() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/runtime/topics.dyon"));
+    let mut rt = Runtime::new();
+    let res = match rt.call_str_ret("messages", &[], &module).unwrap() {
+        Variable::Array(arr) => arr,
+        x => panic!("Expected array, found {:?}", x),
+    };
+    let opt = |v: &Variable| -> Option<f64> {
+        match *v {
+            Variable::Option(ref x) => x.as_ref().map(|x| rt.var(x).unwrap()),
+            ref x => panic!("Expected option, found {:?}", x),
+        }
+    };
+    assert_eq!(res[0], Variable::f64(2.0));
+    let res: Vec<_> = res[1..].iter().map(opt).collect();
+    assert_eq!(res, vec![Some(1.0), Some(1.0), None, None, Some(4.0), None]);
+}
+
+#[test]
+fn test_actor() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/runtime/actor.dyon"));
+    let mut rt = Runtime::new();
+    let res = rt.call_str_ret("results", &[], &module).unwrap();
+    assert_eq!(
+        res,
+        Variable::Array(Arc::new(vec![
+            Variable::f64(3.0),
+            Variable::bool(true),
+            Variable::f64(0.0),
+            Variable::f64(4.0),
+        ]))
+    );
+    assert_eq!(
+        rt.call_str_ret("bad_reply", &[], &module).unwrap(),
+        Variable::bool(true)
+    );
+}
+
+#[test]
+fn test_supervisor() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/runtime/supervisor.dyon"));
+    let mut rt = Runtime::new();
+    let res = rt.call_str_ret("results", &[], &module).unwrap();
+    assert_eq!(
+        res,
+        Variable::Array(Arc::new(vec![
+            Variable::Str(Arc::new("boom".into())),
+            Variable::Str(Arc::new("boom".into())),
+            Variable::f64(0.0),
+            Variable::bool(false),
+            Variable::f64(1.0),
+            Variable::f64(2.0),
+            Variable::bool(true),
+        ]))
+    );
+}
+
+#[test]
+fn test_parallel() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/runtime/parallel.dyon"));
+    let mut rt = Runtime::new();
+    let res = rt.call_str_ret("results", &[], &module).unwrap();
+    let squares = (0..10).map(|i| Variable::f64((i * i) as f64)).collect();
+    assert_eq!(
+        res,
+        Variable::Array(Arc::new(vec![
+            Variable::Array(Arc::new(squares)),
+            Variable::f64(5050.0),
+            Variable::f64(-1.0),
+            Variable::bool(true),
+        ]))
+    );
+}
+
+#[cfg(feature = "threading")]
+#[test]
+fn test_run_with_fuel() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/runtime/fuel.dyon"));
+    let mut out_of_fuel = 0;
+    let mut run = Runtime::new().run_with_fuel(&module, 100);
+    let rt = loop {
+        match run {
+            FuelRun::OutOfFuel(cont) => {
+                out_of_fuel += 1;
+                run = cont.resume(100);
+            }
+            FuelRun::Done(rt, res) => {
+                res.unwrap();
+                break rt;
+            }
+        }
+    };
+    assert!(out_of_fuel >= 10);
+
+    // The runtime can be reused, and a dropped continuation stops the program.
+    let module = Arc::new(test_src("source/runtime/fuel_forever.dyon"));
+    let mut run = rt.run_with_fuel(&module, 1000);
+    for _ in 0..3 {
+        run = match run {
+            FuelRun::OutOfFuel(cont) => cont.resume(1000),
+            FuelRun::Done(_, res) => panic!("Expected out of fuel, found {:?}", res),
+        };
+    }
+    match run {
+        FuelRun::OutOfFuel(cont) => drop(cont),
+        FuelRun::Done(_, res) => panic!("Expected out of fuel, found {:?}", res),
+    }
+}
+
+#[test]
+fn test_checked_channel() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/runtime/checked_channel.dyon"));
+    let mut rt = Runtime::new();
+    let res = match rt.call_str_ret("in_order", &[], &module).unwrap() {
+        Variable::Array(arr) => arr,
+        x => panic!("Expected array, found {:?}", x),
+    };
+    let received = res
+        .iter()
+        .filter(|v| match **v {
+            Variable::Option(ref x) => x.is_some(),
+            _ => false,
+        })
+        .count();
+    assert_eq!(received, 4);
+
+    let err = rt.call_str("out_of_order", &[], &module).unwrap_err();
+    assert!(err
+        .message()
+        .contains("Message `request` is not allowed in state `busy`"));
+    assert!(err.message().contains("`idle` -> `request` -> `busy`"));
+}
+
+#[cfg(feature = "gpu")]
+#[test]
+fn test_gpu() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/gpu.dyon"));
+    let mut rt = Runtime::new();
+    let res = rt.call_str_ret("results", &[], &module).unwrap();
+    let row =
+        |xs: &[f64]| Variable::Array(Arc::new(xs.iter().map(|&x| Variable::f64(x)).collect()));
+    assert_eq!(
+        res,
+        Variable::Array(Arc::new(vec![
+            row(&[2.0, 4.0, 6.0]),
+            Variable::Array(Arc::new(vec![row(&[17.0]), row(&[39.0])])),
+            Variable::bool(true),
+            Variable::bool(true),
+        ]))
+    );
+}
+
+#[test]
+fn test_noise() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/noise.dyon"));
+    let mut rt = Runtime::new();
+    assert_eq!(
+        rt.call_str_ret("check", &[], &module).unwrap(),
+        Variable::bool(true)
+    );
+}
+
+#[test]
+fn test_bulk_vec4() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/bulk_vec4.dyon"));
+    let mut rt = Runtime::new();
+    assert_eq!(
+        rt.call_str_ret("check", &[], &module).unwrap(),
+        Variable::bool(true)
+    );
+}
+
+#[test]
+fn test_bigint() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/bigint.dyon"));
+    let mut rt = Runtime::new();
+    let res = rt.call_str_ret("results", &[], &module).unwrap();
+    let s = |x: &str| Variable::Str(Arc::new(x.into()));
+    assert_eq!(
+        res,
+        Variable::Array(Arc::new(vec![
+            s("340282366920938463463374607431768211456"),
+            s("-123456788993898934827525016274"),
+            s("-123456789030792422974944119506"),
+            s("-6692605942"),
+            s("-14083847773837265618"),
+            s("-18446744073709551616"),
+            s("18446744073709551617"),
+            s("7"),
+            Variable::bool(true),
+            Variable::bool(true),
+            Variable::bool(true),
+            Variable::bool(true),
+        ]))
+    );
+}
+
+#[test]
+#[cfg(feature = "file")]
+fn test_model_infer() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/model_infer.dyon"));
+    let dir = std::env::temp_dir();
+    let dir = Variable::Str(Arc::new(dir.to_str().unwrap().into()));
+    let mut rt = Runtime::new();
+    let res = rt.call_str_ret("results", &[dir], &module).unwrap();
+    let nums =
+        |xs: &[f64]| Variable::Array(Arc::new(xs.iter().map(|&x| Variable::f64(x)).collect()));
+    assert_eq!(
+        res,
+        Variable::Array(Arc::new(vec![
+            nums(&[0.0, 1.0, 1.0, 0.0]),
+            nums(&[0.5, 0.5]),
+            Variable::bool(true),
+            Variable::bool(true),
+        ]))
+    );
+}
+
+#[test]
+fn test_bit_ops() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/bit_ops.dyon"));
+    let mut rt = Runtime::new();
+    let res = rt.call_str_ret("results", &[], &module).unwrap();
+    let nums = [2.0, 7.0, 5.0, -1.0, 4503599627370496.0, -4.0, 255.0];
+    let mut expected: Vec<Variable> = nums.iter().map(|&x| Variable::f64(x)).collect();
+    expected.extend(vec![Variable::bool(true); 4]);
+    assert_eq!(res, Variable::Array(Arc::new(expected)));
+}
+
+#[test]
+fn test_clustering() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/clustering.dyon"));
+    let mut rt = Runtime::new();
+    let res = rt.call_str_ret("results", &[], &module).unwrap();
+    let labels = [0.0, 0.0, 0.0, 1.0, 1.0, 1.0, -1.0];
+    assert_eq!(
+        res,
+        Variable::Array(Arc::new(vec![
+            Variable::bool(true),
+            Variable::f64(2.0),
+            Variable::f64(1.0),
+            Variable::Array(Arc::new(labels.iter().map(|&x| Variable::f64(x)).collect())),
+            Variable::bool(true),
+        ]))
+    );
+}
+
+#[test]
+fn test_frame() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/runtime/frame.dyon"));
+    let mut rt = Runtime::new();
+    let res = rt.call_str_ret("results", &[], &module).unwrap();
+    let s = |x: &str| Variable::Str(Arc::new(x.into()));
+    let scores = [5.0, 4.0, 3.0, 2.0, 1.0];
+    assert_eq!(
+        res,
+        Variable::Array(Arc::new(vec![
+            Variable::f64(5.0),
+            Variable::Array(Arc::new(scores.iter().map(|&x| Variable::f64(x)).collect())),
+            s("team,score_sum,score_max,score_count\na,7,4,2\nb,7,5,2\nc,1,1,1\n"),
+            Variable::f64(3.0),
+            Variable::Array(Arc::new(vec![s("a")])),
+            s("name,x,flag\n\"Smith, J\",1.5,true\nBob,2,false\n"),
+            Variable::bool(true),
+            Variable::bool(true),
+            Variable::bool(true),
+        ]))
+    );
+}
+
+#[cfg(feature = "crypto")]
+#[test]
+fn test_hashing() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/hashing.dyon"));
+    let mut rt = Runtime::new();
+    let str = |s: &str| Variable::Str(Arc::new(s.into()));
+    assert_eq!(
+        rt.call_str_ret("results", &[], &module).unwrap(),
+        Variable::Array(Arc::new(vec![
+            Variable::f64(3421780262.0),
+            Variable::f64(3421780262.0),
+            str("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+            str("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"),
+            Variable::bool(true),
+            Variable::bool(false),
+            Variable::bool(true),
+            Variable::bool(false),
+        ]))
+    );
+}
+
+#[test]
+fn test_slice() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/slice.dyon"));
+    let mut rt = Runtime::new();
+    let arr =
+        |xs: &[f64]| Variable::Array(Arc::new(xs.iter().map(|&x| Variable::f64(x)).collect()));
+    let a = Variable::Array(Arc::new(vec![
+        arr(&[1.0, 2.0]),
+        arr(&[3.0]),
+        arr(&[4.0, 5.0, 6.0]),
+    ]));
+    assert_eq!(
+        rt.call_str_ret("results", &[], &module).unwrap(),
+        Variable::Array(Arc::new(vec![
+            Variable::Array(Arc::new(vec![arr(&[3.0]), arr(&[7.0, 5.0, 6.0])])),
+            a.clone(),
+            arr(&[11.0, 12.0]),
+            a,
+            Variable::bool(true),
+        ]))
+    );
+}
+
+#[test]
+fn test_query() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/query.dyon"));
+    let mut rt = Runtime::new();
+    let obj = |kvs: &[(&str, Variable)]| {
+        Variable::Object(Arc::new(
+            kvs.iter()
+                .map(|(k, v)| (Arc::new(k.to_string()), v.clone()))
+                .collect(),
+        ))
+    };
+    let str = |s: &str| Variable::Str(Arc::new(s.into()));
+    assert_eq!(
+        rt.call_str_ret("results", &[], &module).unwrap(),
+        Variable::Array(Arc::new(vec![
+            Variable::Array(Arc::new(vec![
+                obj(&[("name", str("ann")), ("score_sum", Variable::f64(5.0))]),
+                obj(&[("name", str("bob")), ("score_sum", Variable::f64(5.0))]),
+            ])),
+            Variable::Array(Arc::new(vec![
+                obj(&[("who", str("cid"))]),
+                obj(&[("who", str("bob"))]),
+            ])),
+            Variable::Array(Arc::new(vec![obj(&[
+                ("count", Variable::f64(6.0)),
+                ("avg", Variable::f64(4.5)),
+                ("level_max", Variable::f64(5.0)),
+            ])])),
+            Variable::bool(true),
+        ]))
+    );
+}
+
+#[test]
+fn test_str_append() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/str_append.dyon"));
+    let mut rt = Runtime::new();
+    let str = |s: &str| Variable::Str(Arc::new(s.into()));
+    assert_eq!(
+        rt.call_str_ret("results", &[], &module).unwrap(),
+        Variable::Array(Arc::new(vec![
+            str("a012"),
+            str("a"),
+            str("a012!"),
+            str("012"),
+        ]))
+    );
+}
+
+#[cfg(feature = "crypto")]
+#[test]
+fn test_content_hash() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/content_hash.dyon"));
+    let mut rt = Runtime::new();
+    assert_eq!(
+        rt.call_str_ret("results", &[], &module).unwrap(),
+        Variable::Array(Arc::new(vec![
+            Variable::bool(true),
+            Variable::bool(true),
+            Variable::bool(true),
+            Variable::bool(true),
+            Variable::f64(64.0),
+        ]))
+    );
+}
+
+#[test]
+fn test_f64_array() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/f64_array.dyon"));
+    let mut rt = Runtime::new();
+    let str = |s: &str| Variable::Str(Arc::new(s.into()));
+    assert_eq!(
+        rt.call_str_ret("results", &[], &module).unwrap(),
+        Variable::Array(Arc::new(vec![
+            Variable::f64(4.0),
+            Variable::f64(10.0),
+            Variable::f64(24.0),
+            Variable::f64(1.0),
+            Variable::f64(4.0),
+            Variable::f64(10.0),
+            Variable::Array(Arc::new(vec![
+                Variable::f64(1.0),
+                Variable::f64(2.0),
+                Variable::f64(3.0),
+                Variable::f64(4.0),
+            ])),
+            Variable::bool(true),
+            Variable::f64(3.0),
+            Variable::f64(1.0),
+            str("farr([1.5, 2])"),
+            str("farr"),
+            Variable::bool(true),
+        ]))
+    );
+    for n in [1e300, -1.0, 1.5, f64::NAN, f64::INFINITY] {
+        assert!(rt
+            .call_str_ret("zeros", &[Variable::f64(n)], &module)
+            .is_err());
+    }
+    assert!(rt
+        .call_str_ret("zeros", &[Variable::f64(3.0)], &module)
+        .is_ok());
+    assert!(rt
+        .call_str_ret("get", &[Variable::f64(0.5)], &module)
+        .is_err());
+    assert_eq!(
+        rt.call_str_ret("get", &[Variable::f64(1.0)], &module)
+            .unwrap(),
+        Variable::f64(2.0)
+    );
+}
+
+#[test]
+fn test_check_fn_refs() {
+    let plugin = test_src("source/check_fn_refs/plugin.dyon");
+    let module = test_src("source/check_fn_refs/good.dyon");
+    assert!(module.check_fn_refs(&plugin).is_ok());
+
+    let module = test_src("source/check_fn_refs/bad.dyon");
+    let err = module.check_fn_refs(&plugin).unwrap_err();
+    assert!(err.contains("In `source/check_fn_refs/bad.dyon`"));
+    assert!(err.contains("Could not find function `int`"));
+    assert!(err.contains("Expected 1 arguments to `init`, found 0"));
+}
+
+#[test]
+fn test_find_fn() {
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/runtime/find_fn.dyon"));
+    let mut rt = Runtime::new();
+    let str = |s: &str| Variable::Str(Arc::new(s.into()));
+    assert_eq!(
+        rt.call_str_ret("results", &[], &module).unwrap(),
+        Variable::Array(Arc::new(vec![
+            Variable::f64(1.0),
+            str("loaded"),
+            str("source/runtime/find_fn.dyon"),
+            Variable::Array(Arc::new(vec![Variable::f64(3.0), Variable::f64(5.0)])),
+            str("Adds one.\nUsed for testing."),
+            str("Returns the length of array, either `[]` or `farr`."),
+            Variable::f64(0.0),
+        ]))
+    );
+}
+
+#[test]
+fn test_interned_strings() {
+    use std::sync::Arc;
+
+    let mut module = test_src("source/interned/a.dyon");
+    load("source/interned/b.dyon", &mut module).unwrap();
+    let module = Arc::new(module);
+    let mut rt = Runtime::new();
+    let a = rt.call_str_ret("a", &[], &module).unwrap();
+    let b = rt.call_str_ret("b", &[], &module).unwrap();
+    let (a_key, a_val) = match a {
+        Variable::Object(ref obj) => obj.iter().next().unwrap(),
+        _ => panic!("Expected object"),
+    };
+    let (b_val, b_key) = match b {
+        Variable::Array(ref arr) => match (&arr[0], &arr[1]) {
+            (&Variable::Str(ref s), &Variable::Object(ref obj)) => (s, obj.keys().next().unwrap()),
+            _ => panic!("Expected string and object"),
+        },
+        _ => panic!("Expected array"),
+    };
+    assert!(Arc::ptr_eq(a_key, b_key));
+    match *a_val {
+        Variable::Str(ref a_val) => assert!(Arc::ptr_eq(a_val, b_val)),
+        _ => panic!("Expected string"),
+    }
+}
+
+#[test]
+fn test_finish() {
+    use std::sync::Arc;
+
+    let mut module = test_src("source/runtime/finish.dyon");
+    let before = Arc::new(module.clone());
+    assert!(module.finish() > 0);
+    let after = Arc::new(module);
+    let mut rt = Runtime::new();
+    let args = [Variable::f64(1.0)];
+    let expected = rt.call_str_ret("results", &args, &before).unwrap();
+    assert_eq!(rt.call_str_ret("results", &args, &after).unwrap(), expected);
+    assert_eq!(
+        expected,
+        Variable::Str(Arc::new(
+            r#"[2, 7, 2, (1, 3, 3, -4), mat4 {1,2,3,4; 5,6,7,8; 0,0,1,0; 0,0,0,1}, "ab"]"#.into()
+        ))
+    );
+}
+
+#[test]
+fn test_sandbox() {
+    use dyon::vfs::MemoryFs;
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/runtime/host.dyon"));
+    let mut rt = Runtime::new();
+    let fs = MemoryFs::new();
+    fs.insert(
+        "good.dyon",
+        "fn hyp(a, b) -> { return sqrt(a * a + b * b) }",
+    );
+    fs.insert("bad.dyon", "fn main() { print(\"escaped\") }");
+    rt.set_vfs(Arc::new(fs));
+    let res = match rt.call_str_ret("results", &[], &module).unwrap() {
+        Variable::Array(arr) => arr,
+        _ => panic!("Expected array"),
+    };
+    assert_eq!(res[0], Variable::f64(5.0));
+    match res[1] {
+        Variable::Str(ref err) => assert!(err.contains("`print`"), "{}", err),
+        _ => panic!("Expected string"),
+    }
+    assert_eq!(res[2], Variable::bool(true));
+}
+
+#[test]
+fn test_mod_scheduler() {
+    use dyon::vfs::MemoryFs;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    fn crash(_: &mut Runtime) -> Result<(), RuntimeError> {
+        panic!("crash")
+    }
+
+    let fs = MemoryFs::new();
+    fs.insert(
+        "counter.dyon",
+        "fn update(dt) { if dt > 1 { x := unwrap(none()) } }\nfn name() -> str { return \"counter\" }",
+    );
+    fs.insert("busy.dyon", "fn update() { loop {} }");
+    fs.insert("crash.dyon", "fn update() { crash() }");
+    fs.insert(
+        "broken.dyon",
+        "fn update() { x := unwrap(err(\"broken\")) }",
+    );
+    let vfs = fs.clone();
+    let mut prelude = Module::new();
+    prelude.add_str("crash", crash, Dfn::nl(vec![], Type::Void));
+    let mut mods = ModScheduler::with_factory(prelude, move || {
+        let mut rt = Runtime::new();
+        rt.set_vfs(Arc::new(vfs.clone()));
+        rt
+    });
+    mods.load("counter", "counter.dyon", 0).unwrap();
+    mods.load("busy", "busy.dyon", 10).unwrap();
+    mods.load("broken", "broken.dyon", 5).unwrap();
+    assert!(mods.load("missing", "missing.dyon", 0).is_err());
+    assert_eq!(mods.names(), vec!["busy", "broken", "counter"]);
+    mods.set_step_budget("busy", Some(1000)).unwrap();
+
+    // Errors are isolated, in order of priority.
+    let errors = mods.tick(0.5);
+    let names: Vec<&str> = errors.iter().map(|e| &*e.0).collect();
+    assert_eq!(names, vec!["busy", "broken"]);
+    assert!(errors[0].1.contains("Instruction budget"));
+    assert!(mods.last_error("broken").unwrap().contains("broken"));
+    assert_eq!(
+        mods.call("counter", "name", &[]).unwrap(),
+        Variable::Str(Arc::new("counter".into()))
+    );
+
+    // `update` gets the time step.
+    let errors = mods.tick(2.0);
+    assert_eq!(errors.len(), 3);
+
+    mods.set_enabled("busy", false).unwrap();
+    assert!(!mods.is_enabled("busy"));
+    fs.insert("broken.dyon", "fn update() {}");
+    mods.reload("broken").unwrap();
+    assert_eq!(mods.last_error("broken"), None);
+    assert!(mods.tick(0.5).is_empty());
+
+    // A failed reload keeps the old code.
+    fs.insert("broken.dyon", "fn update( {}");
+    assert!(mods.reload("broken").is_err());
+    assert!(mods.tick(0.5).is_empty());
+    assert!(mods.remove("broken"));
+    assert_eq!(mods.names(), vec!["busy", "counter"]);
+
+    // A panic in one mod does not stop the others.
+    mods.load("crash", "crash.dyon", 5).unwrap();
+    let errors = mods.tick(0.5);
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].0, "crash");
+    assert!(errors[0].1.contains("panicked: crash"), "{}", errors[0].1);
+    assert!(mods.call("crash", "update", &[]).is_err());
+    assert_eq!(
+        mods.call("counter", "name", &[]).unwrap(),
+        Variable::Str(Arc::new("counter".into()))
+    );
+
+    // The time budget stops mods that sleep.
+    assert!(mods.remove("crash"));
+    fs.insert("sleepy.dyon", "fn update() { loop { sleep(0.01) } }");
+    fs.insert("sleeper.dyon", "fn update() { sleep(10) }");
+    mods.load("sleepy", "sleepy.dyon", 0).unwrap();
+    mods.load("sleeper", "sleeper.dyon", 0).unwrap();
+    for name in &["sleepy", "sleeper"] {
+        mods.set_time_budget(name, Some(Duration::from_millis(50)))
+            .unwrap();
+    }
+    let start = Instant::now();
+    let errors = mods.tick(0.5);
+    assert!(start.elapsed() < Duration::from_secs(5));
+    let names: Vec<&str> = errors.iter().map(|e| &*e.0).collect();
+    assert_eq!(names, vec!["sleepy", "sleeper"]);
+    for (_, err) in &errors {
+        assert!(
+            err.contains("Time budget limit of `50ms` exceeded"),
+            "{}",
+            err
+        );
+    }
+}
+
+#[test]
+fn test_save_deep() {
+    use dyon::vfs::MemoryFs;
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/save_deep.dyon"));
+    let mut rt = Runtime::new();
+    rt.set_vfs(Arc::new(MemoryFs::new()));
+    let ok = rt
+        .call_str_ret("check", &[Variable::f64(300.0)], &module)
+        .unwrap();
+    assert_eq!(ok, Variable::bool(true));
+
+    // Data containing itself can not be saved.
+    rt.stack
+        .push(Variable::Array(Arc::new(vec![Variable::Ref(0)])));
+    let res = rt
+        .call_str_ret(
+            "save__data_file",
+            &[
+                Variable::Ref(0),
+                Variable::Str(Arc::new("cycle.dyon".into())),
+            ],
+            &module,
+        )
+        .unwrap();
+    assert!(rt.var::<String>(&res).is_err());
+}
+
+#[test]
+fn test_farr_index() {
+    use dyon::vfs::MemoryFs;
+    use std::sync::Arc;
+
+    let module = Arc::new(test_src("source/functions/farr.dyon"));
+    let mut rt = Runtime::new();
+    rt.set_vfs(Arc::new(MemoryFs::new()));
+    let ok = rt.call_str_ret("check", &[], &module).unwrap();
+    assert_eq!(ok, Variable::bool(true));
+    let err = rt.call_str("out_of_bounds", &[], &module).unwrap_err();
+    assert!(err.message().contains("Out of bounds `1`"));
+}